@@ -0,0 +1,148 @@
+use criterion::{BatchSize, BenchmarkId, Criterion, Throughput, criterion_group, criterion_main};
+use std::hint::black_box;
+
+use crackle_kit::{
+    bam::{
+        process::{
+            BamLocusWorker, ParallelBamProcessor, ParallelLocusProcessorPileup, ProcessBamOptions,
+            RecordModifier,
+        },
+        query::LocusQueryEngine,
+        synthetic::SyntheticBam,
+    },
+    data::locus::GenomeCoordinate,
+    rust_htslib::bam::{self, pileup::Pileup},
+};
+
+/// Records of the synthetic bam, unless set by `BAM_PIPELINE_RECORDS`.
+const N_RECORDS: usize = 200_000;
+const N_LOCI: usize = 100_000;
+const WINDOW: usize = 10_000;
+
+fn n_records() -> usize {
+    std::env::var("BAM_PIPELINE_RECORDS")
+        .ok()
+        .and_then(|n| n.parse().ok())
+        .unwrap_or(N_RECORDS)
+}
+
+/// Keeps every record, so the bench measures the pipeline itself.
+struct KeepAll;
+
+impl RecordModifier for KeepAll {
+    type Error = anyhow::Error;
+
+    fn modify_record(&self, _record: &mut bam::Record) -> Result<Option<()>, Self::Error> {
+        Ok(Some(()))
+    }
+}
+
+struct DepthWorker;
+
+impl<'a> BamLocusWorker<'a> for DepthWorker {
+    type Input = GenomeCoordinate<'a>;
+    type Output = u32;
+    type Error = anyhow::Error;
+
+    fn work_for_locus(&self, plp: Pileup, _input: Self::Input) -> Result<u32, Self::Error> {
+        Ok(plp.depth())
+    }
+}
+
+fn bench_process_bam(c: &mut Criterion) {
+    let dir = tempfile::tempdir().unwrap();
+    let synthetic = SyntheticBam::new(n_records()).with_contigs(4);
+    let input = synthetic.build(dir.path()).unwrap();
+    let output = dir.path().join("out.bam");
+    let processor = ParallelBamProcessor::new(KeepAll);
+
+    let mut group = c.benchmark_group(format!("process_bam ({} records)", synthetic.n_records()));
+    group.sample_size(10);
+    group.throughput(Throughput::Elements(synthetic.n_records() as u64));
+
+    for n_workers in [1, 2, 4, 8] {
+        for batch_size in [256, 1024, 4096] {
+            let opts = ProcessBamOptions {
+                worker_threads: n_workers,
+                batch_size,
+                ..Default::default()
+            };
+            group.bench_with_input(
+                BenchmarkId::new(format!("{} workers", n_workers), batch_size),
+                &opts,
+                |b, opts| b.iter(|| processor.process_bam(&input, &output, opts).unwrap()),
+            );
+        }
+    }
+
+    group.finish();
+}
+
+fn bench_locus_processor(c: &mut Criterion) {
+    let dir = tempfile::tempdir().unwrap();
+    let synthetic = SyntheticBam::new(n_records()).with_contigs(4);
+    let bam_path = synthetic.build(dir.path()).unwrap();
+    let loci = synthetic.loci(N_LOCI);
+
+    let mut group = c.benchmark_group(format!("locus pileups ({} loci)", N_LOCI));
+    group.sample_size(10);
+    group.throughput(Throughput::Elements(N_LOCI as u64));
+
+    for n_threads in [1, 4] {
+        let processor = ParallelLocusProcessorPileup::new(DepthWorker, n_threads, bam_path.clone());
+        group.bench_function(format!("process_with_batch, {} threads", n_threads), |b| {
+            // the loci are cloned out of the measured section.
+            b.iter_batched(
+                || loci.clone(),
+                |loci| black_box(processor.process_with_batch(loci, WINDOW).unwrap()),
+                BatchSize::LargeInput,
+            )
+        });
+    }
+
+    group.finish();
+}
+
+/// Latency of queries of a few loci, by [`LocusQueryEngine`] and by a new
+/// [`ParallelLocusProcessorPileup`] each query, as a service without the
+/// engine would answer them.
+fn bench_locus_query(c: &mut Criterion) {
+    let dir = tempfile::tempdir().unwrap();
+    let synthetic = SyntheticBam::new(n_records()).with_contigs(4);
+    let bam_path = synthetic.build(dir.path()).unwrap();
+    let engine = LocusQueryEngine::new(ParallelLocusProcessorPileup::new(
+        DepthWorker,
+        4,
+        bam_path.clone(),
+    ))
+    .unwrap();
+
+    let mut group = c.benchmark_group("locus query latency");
+    for n_loci in [1, 5, 50] {
+        let loci = synthetic.loci(n_loci);
+        group.bench_with_input(BenchmarkId::new("engine", n_loci), &loci, |b, loci| {
+            b.iter(|| black_box(engine.query(loci).unwrap()))
+        });
+        group.bench_with_input(
+            BenchmarkId::new("process_with_batch", n_loci),
+            &loci,
+            |b, loci| {
+                b.iter(|| {
+                    let processor =
+                        ParallelLocusProcessorPileup::new(DepthWorker, 4, bam_path.clone());
+                    black_box(processor.process_with_batch(loci.clone(), WINDOW).unwrap())
+                })
+            },
+        );
+    }
+
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    bench_process_bam,
+    bench_locus_processor,
+    bench_locus_query
+);
+criterion_main!(benches);
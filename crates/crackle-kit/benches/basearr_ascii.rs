@@ -0,0 +1,101 @@
+use std::{fmt, hint::black_box, io::Write};
+
+use crackle_kit::data::bases::{Base, BaseArr};
+use criterion::{Criterion, Throughput, criterion_group, criterion_main};
+use rand::{Rng, SeedableRng};
+
+const SEQ_LEN: usize = 150;
+const N_SEQS: usize = 1000;
+
+fn generate_dna(len: usize, rng: &mut impl Rng) -> Vec<u8> {
+    (0..len).map(|_| b"ACGTN"[rng.random_range(0..5)]).collect()
+}
+
+/// Previous Display implementation: one `write!` per base.
+struct PerBaseDisplay<'a>(&'a BaseArr);
+
+impl fmt::Display for PerBaseDisplay<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for base in self.0.iter() {
+            let c = match base {
+                Base::A => 'A',
+                Base::T => 'T',
+                Base::C => 'C',
+                Base::G => 'G',
+                Base::N => 'N',
+            };
+            write!(f, "{}", c)?;
+        }
+        Ok(())
+    }
+}
+
+fn bench_basearr_ascii(c: &mut Criterion) {
+    let mut rng = rand::rngs::StdRng::seed_from_u64(1);
+    let arrs = (0..N_SEQS)
+        .map(|_| BaseArr::<u64>::from_bytes(&generate_dna(SEQ_LEN, &mut rng)).unwrap())
+        .collect::<Vec<_>>();
+
+    let mut group = c.benchmark_group("BaseArr to ASCII (150bp)");
+    group.throughput(Throughput::Bytes((SEQ_LEN * N_SEQS) as u64));
+
+    group.bench_function("to_string/per base (old)", |b| {
+        b.iter(|| {
+            for arr in arrs.iter() {
+                black_box(PerBaseDisplay(black_box(arr)).to_string());
+            }
+        })
+    });
+
+    group.bench_function("to_string/chunked", |b| {
+        b.iter(|| {
+            for arr in arrs.iter() {
+                black_box(black_box(arr).to_string());
+            }
+        })
+    });
+
+    group.bench_function("to_ascii_vec", |b| {
+        b.iter(|| {
+            for arr in arrs.iter() {
+                black_box(black_box(arr).to_ascii_vec());
+            }
+        })
+    });
+
+    group.bench_function("write/per base (old)", |b| {
+        let mut out = Vec::with_capacity(SEQ_LEN * N_SEQS * 2);
+        b.iter(|| {
+            out.clear();
+            for arr in arrs.iter() {
+                write!(out, "{}", PerBaseDisplay(black_box(arr))).unwrap();
+            }
+            black_box(&out);
+        })
+    });
+
+    group.bench_function("write/write_ascii", |b| {
+        let mut out = Vec::with_capacity(SEQ_LEN * N_SEQS * 2);
+        b.iter(|| {
+            out.clear();
+            for arr in arrs.iter() {
+                black_box(arr).write_ascii(&mut out).unwrap();
+            }
+            black_box(&out);
+        })
+    });
+
+    group.bench_function("fill_ascii", |b| {
+        let mut buf = [0_u8; 168];
+        b.iter(|| {
+            for arr in arrs.iter() {
+                black_box(black_box(arr).fill_ascii(&mut buf));
+            }
+        })
+    });
+
+    group.finish();
+}
+
+criterion_group!(benches, bench_basearr_ascii);
+criterion_main!(benches);
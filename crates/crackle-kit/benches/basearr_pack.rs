@@ -0,0 +1,91 @@
+use std::hint::black_box;
+
+use crackle_kit::data::bases::BaseArr;
+use criterion::{Criterion, Throughput, criterion_group, criterion_main};
+use rand::{Rng, SeedableRng};
+
+const N_SEQS: usize = 1000;
+
+fn generate_dna(len: usize, rng: &mut impl Rng) -> Vec<u8> {
+    (0..len)
+        .map(|_| b"ACGTNacgtn"[rng.random_range(0..10)])
+        .collect()
+}
+
+/// The lookup of `BaseArr::from_bytes`, `0xFF` for bytes which are not bases.
+const LOOKUP: [u8; 256] = {
+    let mut table = [0xFF; 256];
+    let mut i = 0;
+    while i < 5 {
+        let (base, code) = (b"ATCGN"[i], i as u8 + 1);
+        table[base as usize] = code;
+        table[base.to_ascii_lowercase() as usize] = code;
+        i += 1;
+    }
+    table
+};
+
+/// Previous packing of `BaseArr::from_bytes`: a checked lookup and a shift per
+/// base, into the chunks of a `BaseArr<u64>`.
+fn pack_per_base(s: &[u8]) -> Result<[u64; 8], String> {
+    let mut inner = [0; 8];
+    for (chunk_idx, chunk) in s.chunks(21).enumerate() {
+        let mut current = 0;
+        for (offset, &byte) in chunk.iter().enumerate() {
+            let code = LOOKUP[byte as usize];
+            if code == 0xFF {
+                return Err(format!(
+                    "Invalid base '{}' at position {}",
+                    byte as char,
+                    chunk_idx * 21 + offset
+                ));
+            }
+            current |= (code as u64) << (offset * 3);
+        }
+        inner[chunk_idx] = current;
+    }
+    Ok(inner)
+}
+
+fn bench_basearr_pack(c: &mut Criterion) {
+    let mut rng = rand::rngs::StdRng::seed_from_u64(1);
+
+    for len in [150, 21] {
+        let seqs = (0..N_SEQS)
+            .map(|_| generate_dna(len, &mut rng))
+            .collect::<Vec<_>>();
+
+        let mut group = c.benchmark_group(format!("BaseArr from bytes ({}bp)", len));
+        group.throughput(Throughput::Bytes((len * N_SEQS) as u64));
+
+        group.bench_function("per base (old)", |b| {
+            b.iter(|| {
+                for seq in seqs.iter() {
+                    black_box(pack_per_base(black_box(seq)).unwrap());
+                }
+            })
+        });
+
+        group.bench_function("from_bytes", |b| {
+            b.iter(|| {
+                for seq in seqs.iter() {
+                    black_box(BaseArr::<u64>::from_bytes(black_box(seq)).unwrap());
+                }
+            })
+        });
+
+        group.bench_function("from_bytes_unchecked", |b| {
+            b.iter(|| {
+                for seq in seqs.iter() {
+                    // SAFETY: the sequences are made of bases, of up to 168.
+                    black_box(unsafe { BaseArr::<u64>::from_bytes_unchecked(black_box(seq)) });
+                }
+            })
+        });
+
+        group.finish();
+    }
+}
+
+criterion_group!(benches, bench_basearr_pack);
+criterion_main!(benches);
@@ -0,0 +1,98 @@
+use criterion::{BatchSize, Criterion, Throughput, criterion_group, criterion_main};
+use std::{borrow::Cow, hint::black_box};
+
+use crackle_kit::{
+    bam::process::{BamLocusWorkInput, batch_indices_by_coordinate, batch_input_by_coordinate},
+    data::{chrom::Chrom, locus::GenomeCoordinate},
+};
+
+const N_INPUTS: usize = 5_000_000;
+const N_CONTIGS: usize = 24;
+const WINDOW: usize = 100_000;
+
+/// Previous implementation, which clones the contig of each new batch and grows
+/// every batch from an empty Vec.
+fn batch_input_by_coordinate_old<'a, I: BamLocusWorkInput<'a>>(
+    inputs: impl IntoIterator<Item = I>,
+    window_size: usize,
+) -> Vec<Vec<I>> {
+    let mut input_iter = inputs.into_iter();
+    let mut c_vec = vec![];
+    let (mut c_contig, mut c_start) = match input_iter.next() {
+        Some(inp) => {
+            let gc = inp.genome_coordinate();
+
+            let contig_clone = gc.contig.clone();
+            let start_val = gc.pos;
+            c_vec.push(inp);
+            (contig_clone, start_val)
+        }
+        None => return vec![],
+    };
+
+    let mut res: Vec<Vec<I>> = vec![];
+    for inp in input_iter {
+        let gc = inp.genome_coordinate();
+        if c_contig == gc.contig && gc.pos - c_start < window_size as i64 {
+            c_vec.push(inp);
+        } else {
+            res.push(c_vec);
+            c_contig = gc.contig.clone();
+            c_start = gc.pos;
+            c_vec = vec![inp];
+        }
+    }
+
+    if !c_vec.is_empty() {
+        res.push(c_vec);
+    }
+
+    res
+}
+
+/// Sorted coordinates spread evenly over non-standard (heap allocated) contigs.
+fn generate_coordinates() -> Vec<GenomeCoordinate<'static>> {
+    let per_contig = N_INPUTS / N_CONTIGS;
+    (0..N_CONTIGS)
+        .flat_map(|c| {
+            let contig = Chrom::Other(Cow::Owned(format!("contig_{}", c)));
+            (0..per_contig).map(move |i| GenomeCoordinate {
+                contig: contig.clone(),
+                pos: 1 + i as i64 * 37,
+            })
+        })
+        .collect()
+}
+
+fn bench_batch_by_coordinate(c: &mut Criterion) {
+    let inputs = generate_coordinates();
+
+    let mut group = c.benchmark_group("batch_input_by_coordinate (5M, 24 contigs)");
+    group.sample_size(10);
+    group.throughput(Throughput::Elements(inputs.len() as u64));
+
+    group.bench_function("old", |b| {
+        b.iter_batched(
+            || inputs.clone(),
+            |v| black_box(batch_input_by_coordinate_old(v, WINDOW)),
+            BatchSize::LargeInput,
+        )
+    });
+
+    group.bench_function("new", |b| {
+        b.iter_batched(
+            || inputs.clone(),
+            |v| black_box(batch_input_by_coordinate(v, WINDOW)),
+            BatchSize::LargeInput,
+        )
+    });
+
+    group.bench_function("indices", |b| {
+        b.iter(|| black_box(batch_indices_by_coordinate(&inputs, WINDOW)))
+    });
+
+    group.finish();
+}
+
+criterion_group!(benches, bench_batch_by_coordinate);
+criterion_main!(benches);
@@ -2,7 +2,10 @@ use criterion::{Criterion, Throughput, criterion_group, criterion_main};
 use std::hint::black_box;
 
 // Adjust this import path to match where you put the struct
-use crackle_kit::data::bases::comp::{CompMode, Complementor};
+use crackle_kit::data::bases::{
+    avx2_detected,
+    comp::{CompMode, Complementor},
+};
 use rand::{Rng, SeedableRng};
 
 fn generate_dna(len: usize) -> Vec<u8> {
@@ -45,7 +48,7 @@ fn bench_complement_only(c: &mut Criterion) {
     });
 
     // 2. SIMD (AVX2)
-    if is_x86_feature_detected!("avx2") {
+    if avx2_detected() {
         group.bench_function("150bp/SIMD (AVX2)", |b| {
             let mut runner = Complementor::new(CompMode::SIMD);
             runner.complement(&input_small);
@@ -70,7 +73,7 @@ fn bench_complement_only(c: &mut Criterion) {
         })
     });
 
-    if is_x86_feature_detected!("avx2") {
+    if avx2_detected() {
         group.bench_function("100KB/SIMD (AVX2)", |b| {
             let mut runner = Complementor::new(CompMode::SIMD);
             runner.complement(&input_large);
@@ -0,0 +1,162 @@
+use criterion::{Criterion, Throughput, criterion_group, criterion_main};
+use std::{
+    hint::black_box,
+    path::{Path, PathBuf},
+};
+
+use crackle_kit::{
+    bam::process::{BamLocusWorker, ParallelLocusProcessorPileup, batch_input_by_coordinate},
+    data::{
+        chrom::Chrom,
+        locus::{GenomeCoordinate, GenomeRegion},
+    },
+    rust_htslib::bam::{
+        self, Header, IndexedReader, Read as _, Writer,
+        header::HeaderRecord,
+        pileup::Pileup,
+        record::{Cigar, CigarString},
+    },
+};
+
+const N_LOCI: usize = 1_000_000;
+const LOCUS_STEP: i64 = 100;
+const WINDOW: usize = 10_000;
+const READ_LEN: usize = 100;
+/// One read every this many bp.
+const READ_STEP: i64 = 1_000;
+
+fn contigs() -> Vec<Chrom<'static>> {
+    Chrom::typical_chroms().to_vec()
+}
+
+fn contig_len() -> i64 {
+    (N_LOCI / contigs().len()) as i64 * LOCUS_STEP + READ_LEN as i64
+}
+
+/// Indexed bam of reads tiled sparsely along the 24 typical chromosomes.
+fn write_synthetic_bam(dir: &Path) -> PathBuf {
+    let path = dir.join("synthetic.bam");
+
+    let mut header = Header::new();
+    header.push_record(
+        HeaderRecord::new(b"HD")
+            .push_tag(b"VN", "1.6")
+            .push_tag(b"SO", "coordinate"),
+    );
+    for contig in contigs() {
+        header.push_record(
+            HeaderRecord::new(b"SQ")
+                .push_tag(b"SN", contig.as_str())
+                .push_tag(b"LN", contig_len()),
+        );
+    }
+
+    {
+        let mut writer = Writer::from_path(&path, &header, bam::Format::Bam).unwrap();
+        let cigar = CigarString(vec![Cigar::Match(READ_LEN as u32)]);
+        let seq = b"ACGT".repeat(READ_LEN / 4);
+        let qual = vec![30; READ_LEN];
+        let mut record = bam::Record::new();
+
+        for tid in 0..contigs().len() {
+            for (i, pos) in (0..contig_len() - READ_LEN as i64)
+                .step_by(READ_STEP as usize)
+                .enumerate()
+            {
+                record.set(
+                    format!("read{}_{}", tid, i).as_bytes(),
+                    Some(&cigar),
+                    &seq,
+                    &qual,
+                );
+                record.set_tid(tid as i32);
+                record.set_pos(pos);
+                record.set_mapq(60);
+                record.set_mtid(-1);
+                record.set_mpos(-1);
+                writer.write(&record).unwrap();
+            }
+        }
+    }
+    bam::index::build(&path, None, bam::index::Type::Bai, 1).unwrap();
+
+    path
+}
+
+/// Sorted loci spread evenly over the contigs.
+fn generate_loci() -> Vec<GenomeCoordinate<'static>> {
+    let per_contig = N_LOCI / contigs().len();
+    contigs()
+        .into_iter()
+        .flat_map(|contig| {
+            (0..per_contig).map(move |i| GenomeCoordinate {
+                contig: contig.clone(),
+                pos: 1 + i as i64 * LOCUS_STEP,
+            })
+        })
+        .collect()
+}
+
+struct DepthWorker;
+
+impl<'a> BamLocusWorker<'a> for DepthWorker {
+    type Input = GenomeCoordinate<'a>;
+    type Output = u32;
+    type Error = anyhow::Error;
+
+    fn work_for_locus(&self, plp: Pileup, _input: Self::Input) -> Result<u32, Self::Error> {
+        Ok(plp.depth())
+    }
+}
+
+fn bench_locus_tids(c: &mut Criterion) {
+    let dir = tempfile::tempdir().unwrap();
+    let bam_path = write_synthetic_bam(dir.path());
+    let loci = generate_loci();
+    let batches = batch_input_by_coordinate(loci.clone(), WINDOW);
+
+    let mut group = c.benchmark_group("locus fetches (1M loci, 24 contigs)");
+    group.sample_size(10);
+    group.throughput(Throughput::Elements(N_LOCI as u64));
+
+    let mut ir = IndexedReader::from_path(&bam_path).unwrap();
+    // the contig name of every batch is resolved in the header, as before.
+    group.bench_function("fetch by contig name (old)", |b| {
+        b.iter(|| {
+            for batch in batches.iter() {
+                let region = GenomeRegion {
+                    contig: batch[0].contig.clone(),
+                    start: batch[0].pos,
+                    end: batch[batch.len() - 1].pos,
+                };
+                region.fetch_in(&mut ir).unwrap();
+                black_box(&mut ir);
+            }
+        })
+    });
+
+    group.bench_function("fetch by tid (new)", |b| {
+        b.iter(|| {
+            let mut tids = vec![None; contigs().len()];
+            for batch in batches.iter() {
+                let contig = &batch[0].contig;
+                let code = contig.code().unwrap() as usize - 1;
+                let tid = *tids[code]
+                    .get_or_insert_with(|| ir.header().tid(contig.as_str().as_bytes()).unwrap());
+                ir.fetch((tid, batch[0].pos - 1, batch[batch.len() - 1].pos))
+                    .unwrap();
+                black_box(&mut ir);
+            }
+        })
+    });
+
+    let processor = ParallelLocusProcessorPileup::new(DepthWorker, 4, bam_path.clone());
+    group.bench_function("process_with_batch, 4 threads", |b| {
+        b.iter(|| black_box(processor.process_with_batch(loci.clone(), WINDOW).unwrap()))
+    });
+
+    group.finish();
+}
+
+criterion_group!(benches, bench_locus_tids);
+criterion_main!(benches);
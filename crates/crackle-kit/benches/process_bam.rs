@@ -0,0 +1,98 @@
+use criterion::{Criterion, Throughput, criterion_group, criterion_main};
+use std::path::{Path, PathBuf};
+
+use crackle_kit::{
+    bam::process::{ParallelBamProcessor, ProcessBamOptions, RecordModifier},
+    rust_htslib::bam::{
+        self, Header, Writer,
+        header::HeaderRecord,
+        record::{Cigar, CigarString},
+    },
+};
+
+const N_RECORDS: usize = 100_000;
+const READ_LEN: usize = 100;
+
+/// Keeps every record, so the bench measures the pipeline itself.
+struct KeepAll;
+
+impl RecordModifier for KeepAll {
+    type Error = anyhow::Error;
+
+    fn modify_record(&self, _record: &mut bam::Record) -> Result<Option<()>, Self::Error> {
+        Ok(Some(()))
+    }
+}
+
+/// Indexed bam of `N_RECORDS` reads tiled along one contig.
+fn write_synthetic_bam(dir: &Path) -> PathBuf {
+    let path = dir.join("synthetic.bam");
+
+    let mut header = Header::new();
+    header.push_record(
+        HeaderRecord::new(b"HD")
+            .push_tag(b"VN", "1.6")
+            .push_tag(b"SO", "coordinate"),
+    );
+    header.push_record(
+        HeaderRecord::new(b"SQ")
+            .push_tag(b"SN", "chr1")
+            .push_tag(b"LN", N_RECORDS * 10 + READ_LEN),
+    );
+
+    {
+        let mut writer = Writer::from_path(&path, &header, bam::Format::Bam).unwrap();
+        let cigar = CigarString(vec![Cigar::Match(READ_LEN as u32)]);
+        let seq = b"ACGT".repeat(READ_LEN / 4);
+        let qual = vec![30; READ_LEN];
+        let mut record = bam::Record::new();
+
+        for i in 0..N_RECORDS {
+            record.set(format!("read{}", i).as_bytes(), Some(&cigar), &seq, &qual);
+            record.set_tid(0);
+            record.set_pos(i as i64 * 10);
+            record.set_mapq(60);
+            record.set_mtid(-1);
+            record.set_mpos(-1);
+            writer.write(&record).unwrap();
+        }
+    }
+    bam::index::build(&path, None, bam::index::Type::Bai, 1).unwrap();
+
+    path
+}
+
+fn bench_process_bam(c: &mut Criterion) {
+    let dir = tempfile::tempdir().unwrap();
+    let input = write_synthetic_bam(dir.path());
+    let output = dir.path().join("out.bam");
+    let processor = ParallelBamProcessor::new(KeepAll);
+
+    let mut group = c.benchmark_group("process_bam (100k records)");
+    group.sample_size(10);
+    group.throughput(Throughput::Elements(N_RECORDS as u64));
+
+    let opts = ProcessBamOptions::default();
+    group.bench_function("sequential", |b| {
+        b.iter(|| {
+            processor
+                .process_bam_sequential(&input, &output, &opts)
+                .unwrap()
+        })
+    });
+
+    for n_workers in [1, 4, 16] {
+        let opts = ProcessBamOptions {
+            worker_threads: n_workers,
+            ..Default::default()
+        };
+        group.bench_function(format!("parallel, {} workers", n_workers), |b| {
+            b.iter(|| processor.process_bam(&input, &output, &opts).unwrap())
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, bench_process_bam);
+criterion_main!(benches);
@@ -0,0 +1,71 @@
+use criterion::{Criterion, Throughput, criterion_group, criterion_main};
+use std::hint::black_box;
+
+use crackle_kit::data::{
+    chrom::Chrom,
+    interval::RegionSet,
+    locus::{GenomeCoordinate, GenomeRegion},
+};
+use rand::{Rng, SeedableRng};
+
+const N_REGIONS: usize = 500_000;
+const N_QUERIES: usize = 1_000_000;
+const CONTIG_LEN: i64 = 100_000_000;
+
+/// Exome-like targets: short regions at random positions of chr1 to chr22.
+fn generate(rng: &mut impl Rng) -> (Vec<GenomeRegion<'static>>, Vec<GenomeCoordinate<'static>>) {
+    let contig = |rng: &mut dyn rand::RngCore| Chrom::from_code(rng.random_range(1..=22)).unwrap();
+
+    let regions = (0..N_REGIONS)
+        .map(|_| {
+            let start = rng.random_range(1..CONTIG_LEN);
+            GenomeRegion {
+                contig: contig(rng),
+                start,
+                end: start + rng.random_range(50..300),
+            }
+        })
+        .collect();
+    let queries = (0..N_QUERIES)
+        .map(|_| GenomeCoordinate {
+            contig: contig(rng),
+            pos: rng.random_range(1..CONTIG_LEN),
+        })
+        .collect();
+
+    (regions, queries)
+}
+
+fn bench_region_set(c: &mut Criterion) {
+    let mut rng = rand::rngs::StdRng::seed_from_u64(1);
+    let (regions, queries) = generate(&mut rng);
+
+    let mut group = c.benchmark_group("RegionSet (500k regions, 1M queries)");
+    group.sample_size(10);
+
+    group.bench_function("build", |b| {
+        b.iter(|| black_box(RegionSet::new(regions.iter().cloned(), false)))
+    });
+
+    let set = RegionSet::new(regions, false);
+    group.throughput(Throughput::Elements(N_QUERIES as u64));
+
+    group.bench_function("contains", |b| {
+        b.iter(|| queries.iter().filter(|q| set.contains(q)).count())
+    });
+
+    group.bench_function("nearest", |b| {
+        b.iter(|| {
+            queries
+                .iter()
+                .filter_map(|q| set.nearest(q))
+                .map(|(_, d)| d)
+                .sum::<i64>()
+        })
+    });
+
+    group.finish();
+}
+
+criterion_group!(benches, bench_region_set);
+criterion_main!(benches);
@@ -2,7 +2,7 @@ use std::hint::black_box;
 
 use criterion::{criterion_group, criterion_main, Criterion, Throughput};
 // Replace 'your_crate_name' with the actual name of your library crate
-use crackle_kit::data::bases::rev_comp::{RevCompMode, RevComplementor}; 
+use crackle_kit::data::bases::{avx2_detected, rev_comp::{RevCompMode, RevComplementor}};
 use rand::{Rng, SeedableRng};
 
 
@@ -56,7 +56,7 @@ fn bench_reverse_complement(c: &mut Criterion) {
     });
 
     // 3. SIMD (AVX2)
-    if is_x86_feature_detected!("avx2") {
+    if avx2_detected() {
         group.bench_function("150bp/SIMD (AVX2)", |b| {
             let mut runner = RevComplementor::with_mode(RevCompMode::SIMD);
             runner.reverse_complement(&input_small); 
@@ -67,7 +67,7 @@ fn bench_reverse_complement(c: &mut Criterion) {
         });
     }
 
-    if is_x86_feature_detected!("avx2") {
+    if avx2_detected() {
         group.bench_function("150bp/SIMD Unrolled (AVX2)", |b| {
             let mut runner = RevComplementor::with_mode(RevCompMode::SIMDUnrolled4x);
             runner.reverse_complement(&input_small); 
@@ -100,7 +100,7 @@ fn bench_reverse_complement(c: &mut Criterion) {
         })
     });
 
-    if is_x86_feature_detected!("avx2") {
+    if avx2_detected() {
         group.bench_function("100KB/SIMD (AVX2)", |b| {
             let mut runner = RevComplementor::with_mode(RevCompMode::SIMD);
             runner.reverse_complement(&input_large); 
@@ -110,7 +110,7 @@ fn bench_reverse_complement(c: &mut Criterion) {
         });
     }
 
-    if is_x86_feature_detected!("avx2") {
+    if avx2_detected() {
         group.bench_function("100KB/SIMD Unrolled (AVX2)", |b| {
             let mut runner = RevComplementor::with_mode(RevCompMode::SIMDUnrolled4x);
             runner.reverse_complement(&input_large); 
@@ -0,0 +1,72 @@
+use std::hint::black_box;
+
+use crackle_kit::utils::seq_stats::{
+    LowComplexityFilter, dust_score, gc_content, homopolymer_max_run, sliding_gc,
+};
+use criterion::{Criterion, Throughput, criterion_group, criterion_main};
+use rand::{Rng, SeedableRng};
+
+const SEQ_LEN: usize = 150;
+const N_SEQS: usize = 1000;
+
+fn generate_dna(len: usize, rng: &mut impl Rng) -> Vec<u8> {
+    (0..len).map(|_| b"ACGTN"[rng.random_range(0..5)]).collect()
+}
+
+fn bench_seq_stats(c: &mut Criterion) {
+    let mut rng = rand::rngs::StdRng::seed_from_u64(1);
+    let seqs = (0..N_SEQS)
+        .map(|_| generate_dna(SEQ_LEN, &mut rng))
+        .collect::<Vec<_>>();
+
+    let mut group = c.benchmark_group("Sequence stats (150bp)");
+    group.throughput(Throughput::Bytes((SEQ_LEN * N_SEQS) as u64));
+
+    group.bench_function("gc_content", |b| {
+        b.iter(|| {
+            for seq in seqs.iter() {
+                black_box(gc_content(black_box(seq)));
+            }
+        })
+    });
+
+    group.bench_function("sliding_gc/50bp by 10", |b| {
+        b.iter(|| {
+            for seq in seqs.iter() {
+                black_box(sliding_gc(black_box(seq), 50, 10));
+            }
+        })
+    });
+
+    group.bench_function("dust_score", |b| {
+        b.iter(|| {
+            for seq in seqs.iter() {
+                black_box(dust_score(black_box(seq)));
+            }
+        })
+    });
+
+    group.bench_function("homopolymer_max_run", |b| {
+        b.iter(|| {
+            for seq in seqs.iter() {
+                black_box(homopolymer_max_run(black_box(seq)));
+            }
+        })
+    });
+
+    let filter = LowComplexityFilter::new()
+        .with_max_homopolymer_run(10)
+        .with_gc_range(0.2, 0.8);
+    group.bench_function("LowComplexityFilter::keeps", |b| {
+        b.iter(|| {
+            for seq in seqs.iter() {
+                black_box(filter.keeps(black_box(seq)));
+            }
+        })
+    });
+
+    group.finish();
+}
+
+criterion_group!(benches, bench_seq_stats);
+criterion_main!(benches);
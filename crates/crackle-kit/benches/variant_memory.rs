@@ -0,0 +1,107 @@
+//! Building 1M variants with string alleles (the previous storage, as
+//! `StringVariant`) and packed ones.
+//!
+//! A `StringVariant` is 80 bytes, plus two heap allocations of the alleles,
+//! each of 1 or 2 bytes but taking 16 or more with the allocator's overhead;
+//! a packed `Variant` is 56 bytes and allocates nothing. The allocations and
+//! heap bytes of each are printed before the timings.
+
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    hint::black_box,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use crackle_kit::data::{
+    chrom::Chrom,
+    variant::{StringVariant, Variant},
+};
+use criterion::{Criterion, criterion_group, criterion_main};
+
+const N_VARIANTS: usize = 1_000_000;
+
+struct CountingAlloc;
+
+static N_ALLOCS: AtomicUsize = AtomicUsize::new(0);
+static N_BYTES: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        N_ALLOCS.fetch_add(1, Ordering::Relaxed);
+        N_BYTES.fetch_add(layout.size(), Ordering::Relaxed);
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(ptr, layout) }
+    }
+}
+
+#[global_allocator]
+static ALLOC: CountingAlloc = CountingAlloc;
+
+/// SNVs and short indels.
+const ALLELES: [(&str, &str); 4] = [("A", "G"), ("C", "T"), ("AT", "A"), ("G", "GCA")];
+
+fn chrom(i: usize) -> Chrom<'static> {
+    Chrom::from_code((i % 24 + 1) as u8).unwrap()
+}
+
+fn string_variants() -> Vec<StringVariant<'static>> {
+    (0..N_VARIANTS)
+        .map(|i| {
+            let (ref_b, alt_b) = ALLELES[i % ALLELES.len()];
+            StringVariant {
+                chrom: chrom(i),
+                pos: i as i64 * 10,
+                ref_b: ref_b.to_string(),
+                alt_b: alt_b.to_string(),
+            }
+        })
+        .collect()
+}
+
+fn packed_variants() -> Vec<Variant<'static>> {
+    (0..N_VARIANTS)
+        .map(|i| {
+            let (ref_b, alt_b) = ALLELES[i % ALLELES.len()];
+            Variant::new(chrom(i), i as i64 * 10, ref_b, alt_b)
+        })
+        .collect()
+}
+
+/// Allocations and bytes allocated by `f`, the vector itself included.
+fn report<T>(name: &str, f: impl FnOnce() -> Vec<T>) {
+    let (allocs, bytes) = (
+        N_ALLOCS.load(Ordering::Relaxed),
+        N_BYTES.load(Ordering::Relaxed),
+    );
+    let v = f();
+    println!(
+        "{}: {} allocations, {:.1} MB for {} variants of {} bytes",
+        name,
+        N_ALLOCS.load(Ordering::Relaxed) - allocs,
+        (N_BYTES.load(Ordering::Relaxed) - bytes) as f64 / 1e6,
+        v.len(),
+        size_of::<T>()
+    );
+}
+
+fn bench_variant_memory(c: &mut Criterion) {
+    report("String-backed (old)", string_variants);
+    report("packed", packed_variants);
+
+    let mut group = c.benchmark_group("Build 1M variants");
+    group.sample_size(10);
+
+    group.bench_function("String-backed (old)", |b| {
+        b.iter(|| black_box(string_variants()))
+    });
+
+    group.bench_function("packed", |b| b.iter(|| black_box(packed_variants())));
+
+    group.finish();
+}
+
+criterion_group!(benches, bench_variant_memory);
+criterion_main!(benches);
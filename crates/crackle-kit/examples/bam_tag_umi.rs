@@ -0,0 +1,40 @@
+//! Move UMIs from read names into the `RX` tag.
+//!
+//! ```text
+//! cargo run --features bam --example bam_tag_umi -- tests/data/sample.bam out.bam
+//! ```
+
+use std::path::Path;
+
+use anyhow::{Error, bail};
+use crackle_kit::bam::{
+    modifiers::UmiToRx,
+    process::{ParallelBamProcessor, ProcessBamOptions, ProcessStats},
+};
+
+/// Write `input` to `output` with UMIs tagged. The input must be indexed.
+pub fn run(
+    input: impl AsRef<Path>,
+    output: impl AsRef<Path>,
+    worker_threads: usize,
+) -> Result<ProcessStats, Error> {
+    let processor = ParallelBamProcessor::new(UmiToRx::new());
+    let opts = ProcessBamOptions {
+        worker_threads,
+        ..Default::default()
+    };
+
+    Ok(processor.process_bam(input, output, &opts)?)
+}
+
+fn main() -> Result<(), Error> {
+    let args = std::env::args().skip(1).collect::<Vec<_>>();
+    let [input, output] = args.as_slice() else {
+        bail!("usage: bam_tag_umi <IN_BAM> <OUT_BAM>");
+    };
+
+    let stats = run(input, output, 4)?;
+    eprintln!("{:?}", stats);
+
+    Ok(())
+}
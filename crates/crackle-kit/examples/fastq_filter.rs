@@ -0,0 +1,76 @@
+//! Drop read pairs with a low mean base quality.
+//!
+//! ```text
+//! cargo run --features fastq --example fastq_filter -- \
+//!     tests/data/sample_R1.fastq tests/data/sample_R2.fastq out_R1.fastq out_R2.fastq
+//! ```
+
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    path::Path,
+};
+
+use anyhow::{Error, bail};
+use crackle_kit::fastq::{FastqRecord, PairedFastqReaderConfig};
+
+const MIN_MEAN_QUALITY: f64 = 20.0;
+
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct FilterSummary {
+    pub n_pairs: u64,
+    pub n_kept: u64,
+}
+
+/// Copy pairs whose mates both have a mean quality of at least `min_mean_quality`.
+pub fn run(
+    r1: impl AsRef<Path>,
+    r2: impl AsRef<Path>,
+    out_r1: impl AsRef<Path>,
+    out_r2: impl AsRef<Path>,
+    min_mean_quality: f64,
+) -> Result<FilterSummary, Error> {
+    let mut reader = PairedFastqReaderConfig::new(r1, r2).run()?;
+    let mut w1 = BufWriter::new(File::create(out_r1)?);
+    let mut w2 = BufWriter::new(File::create(out_r2)?);
+
+    let mut rec1 = FastqRecord::new();
+    let mut rec2 = FastqRecord::new();
+    let mut summary = FilterSummary::default();
+    loop {
+        match reader.read(&mut rec1, &mut rec2) {
+            (Some(res1), Some(res2)) => {
+                res1?;
+                res2?;
+            }
+            (None, None) => break,
+            _ => bail!("R1 and R2 have a different number of reads"),
+        }
+        summary.n_pairs += 1;
+
+        let passes = |rec: &FastqRecord| rec.mean_quality().is_some_and(|q| q >= min_mean_quality);
+        if passes(&rec1) && passes(&rec2) {
+            rec1.write_to(&mut w1)?;
+            rec2.write_to(&mut w2)?;
+            summary.n_kept += 1;
+        }
+    }
+
+    w1.flush()?;
+    w2.flush()?;
+    reader.join()?;
+
+    Ok(summary)
+}
+
+fn main() -> Result<(), Error> {
+    let args = std::env::args().skip(1).collect::<Vec<_>>();
+    let [r1, r2, out_r1, out_r2] = args.as_slice() else {
+        bail!("usage: fastq_filter <R1> <R2> <OUT_R1> <OUT_R2>");
+    };
+
+    let summary = run(r1, r2, out_r1, out_r2, MIN_MEAN_QUALITY)?;
+    eprintln!("kept {} of {} pairs", summary.n_kept, summary.n_pairs);
+
+    Ok(())
+}
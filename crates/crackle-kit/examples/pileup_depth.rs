@@ -0,0 +1,122 @@
+//! Read depth at the positions of a BED file, written as TSV.
+//!
+//! ```text
+//! cargo run --features bam --example pileup_depth -- \
+//!     tests/data/sample.bam tests/data/positions.bed depth.tsv
+//! ```
+//!
+//! Every base of the BED intervals is reported, so keep them small. Intervals must
+//! be sorted by contig and position.
+
+use std::{
+    fs::{self, File},
+    io::BufWriter,
+    path::Path,
+};
+
+use anyhow::{Error, anyhow, bail};
+use crackle_kit::{
+    bam::{
+        pileup_ext::PileupExt,
+        process::{BamLocusWorker, ParallelLocusProcessorPileup},
+    },
+    data::{chrom::Chrom, locus::GenomeCoordinate},
+    rust_htslib::bam::pileup::Pileup,
+    table::TableWriter,
+};
+
+const BATCH_WINDOW: usize = 100_000;
+
+/// Counts reads with a base at the position, so deletions and skips are excluded.
+struct DepthWorker;
+
+impl<'a> BamLocusWorker<'a> for DepthWorker {
+    type Input = GenomeCoordinate<'a>;
+    type Output = (GenomeCoordinate<'a>, u32);
+    type Error = Error;
+
+    fn work_for_locus(&self, plp: Pileup, input: Self::Input) -> Result<Self::Output, Self::Error> {
+        let depth = plp
+            .typed_alignments()
+            .filter(|aln| aln.qpos.is_some())
+            .count();
+        Ok((input, depth as u32))
+    }
+}
+
+/// Positions (1-based) of every base of the BED intervals (0-based, half-open).
+fn read_bed_positions(path: impl AsRef<Path>) -> Result<Vec<GenomeCoordinate<'static>>, Error> {
+    let mut positions = vec![];
+    for (i, line) in fs::read_to_string(path)?.lines().enumerate() {
+        if line.is_empty() || line.starts_with('#') || line.starts_with("track") {
+            continue;
+        }
+
+        let fields = line.split('\t').collect::<Vec<_>>();
+        let [contig, start, end, ..] = fields.as_slice() else {
+            bail!("BED line {} has fewer than 3 fields", i + 1);
+        };
+        let parse = |s: &str| {
+            s.parse::<i64>()
+                .map_err(|_| anyhow!("BED line {}: invalid position {:?}", i + 1, s))
+        };
+
+        let contig = Chrom::from(contig.to_string());
+        for pos in parse(start)? + 1..=parse(end)? {
+            positions.push(GenomeCoordinate {
+                contig: contig.clone(),
+                pos,
+            });
+        }
+    }
+
+    Ok(positions)
+}
+
+/// Write `contig\tpos\tdepth` for every position of `bed`. Returns the number of rows.
+pub fn run(
+    bam: impl AsRef<Path>,
+    bed: impl AsRef<Path>,
+    out_tsv: impl AsRef<Path>,
+    n_threads: usize,
+) -> Result<usize, Error> {
+    let positions = read_bed_positions(bed)?;
+
+    let processor = ParallelLocusProcessorPileup::builder(bam)
+        .worker(DepthWorker)
+        .threads(n_threads)
+        .build()?;
+    // Positions without coverage are not reported by the processor; the results
+    // keep the input order, so walk both to fill them with zeros.
+    let mut depths = processor
+        .process_with_batch(positions.clone(), BATCH_WINDOW)?
+        .into_iter()
+        .peekable();
+
+    let mut tw = TableWriter::new(
+        BufWriter::new(File::create(out_tsv)?),
+        &["contig", "pos", "depth"],
+    )?;
+    for gc in positions.iter() {
+        let depth = match depths.next_if(|(covered, _)| covered == gc) {
+            Some((_, depth)) => depth,
+            None => 0,
+        };
+        tw.write_row([gc.contig.to_string(), gc.pos.to_string(), depth.to_string()])?;
+    }
+    tw.into_inner()?;
+
+    Ok(positions.len())
+}
+
+fn main() -> Result<(), Error> {
+    let args = std::env::args().skip(1).collect::<Vec<_>>();
+    let [bam, bed, out_tsv] = args.as_slice() else {
+        bail!("usage: pileup_depth <BAM> <BED> <OUT_TSV>");
+    };
+
+    let n = run(bam, bed, out_tsv, 4)?;
+    eprintln!("wrote depth of {} positions", n);
+
+    Ok(())
+}
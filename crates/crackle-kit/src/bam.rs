@@ -1,3 +1,32 @@
+#[cfg(feature = "async")]
+pub mod async_process;
+#[cfg(feature = "serde")]
+pub mod cache;
+pub mod checkpoint;
+pub mod cigar;
+pub mod consensus;
+pub mod context;
+pub mod decision_log;
+pub mod fan_out;
+pub mod filter;
+pub mod headerless;
+pub mod merge;
+pub mod modifiers;
+pub mod output;
+pub mod paired;
+pub mod pileup_ext;
+pub mod plan;
+pub mod pooled;
 pub mod process;
 pub mod process_task;
-
+pub mod qnames;
+pub mod query;
+pub mod reader;
+pub mod record_view;
+pub mod result_sink;
+pub mod stats;
+pub mod synthetic;
+pub mod tags;
+pub mod validate;
+pub mod watchdog;
+pub mod workers;
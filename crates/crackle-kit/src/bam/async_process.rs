@@ -0,0 +1,118 @@
+//! [`ParallelBamProcessor::process_bam`] for tokio tasks.
+
+use std::{
+    path::PathBuf,
+    sync::{Arc, atomic},
+    time::Duration,
+};
+
+use anyhow::Error;
+use tokio::sync::watch;
+
+use crate::bam::{
+    output::OutputTarget,
+    process::{
+        ParallelBamProcessor, PipelineControl, ProcessBamOptions, ProcessStats, RecordModifier,
+    },
+};
+
+/// Interval between the progress updates of [`ParallelBamProcessor::process_bam_async`].
+pub const PROGRESS_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Cancel the run when the future owning it is dropped.
+struct CancelOnDrop(Arc<PipelineControl>);
+
+impl Drop for CancelOnDrop {
+    fn drop(&mut self) {
+        self.0.cancelled.store(true, atomic::Ordering::Release);
+    }
+}
+
+impl<R: RecordModifier + 'static> ParallelBamProcessor<R> {
+    /// [`Self::process_bam`] on tokio's blocking pool.
+    ///
+    /// The record counts so far are sent to `progress` every [`PROGRESS_INTERVAL`],
+    /// and the final ones when the run ends. Dropping the future cancels the run:
+    /// the pipeline stops at its next batch, and no output is left.
+    ///
+    /// # Panics
+    /// Outside of a tokio runtime.
+    pub async fn process_bam_async(
+        self: Arc<Self>,
+        input_bam_path: impl Into<PathBuf>,
+        out_bam_path: impl Into<PathBuf>,
+        opts: ProcessBamOptions,
+        progress: Option<watch::Sender<ProcessStats>>,
+    ) -> Result<ProcessStats, crate::Error> {
+        let (input_bam_path, out_bam_path) = (input_bam_path.into(), out_bam_path.into());
+        let control = Arc::new(PipelineControl::default());
+        let _cancel = CancelOnDrop(Arc::clone(&control));
+
+        let mut handle = tokio::task::spawn_blocking({
+            let control = Arc::clone(&control);
+            let output = OutputTarget::File(out_bam_path);
+            move || self.process_bam_with_control(input_bam_path, &output, &opts, &control)
+        });
+
+        let mut interval = tokio::time::interval(PROGRESS_INTERVAL);
+        let res = loop {
+            tokio::select! {
+                res = &mut handle => break res.map_err(Error::from)?,
+                _ = interval.tick() => {
+                    if let Some(progress) = &progress {
+                        progress.send_replace(control.stats.snapshot());
+                    }
+                }
+            }
+        };
+
+        if let (Ok(stats), Some(progress)) = (&res, &progress) {
+            progress.send_replace(stats.clone());
+        }
+        Ok(res?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::TestBam;
+
+    struct KeepAll;
+
+    impl RecordModifier for KeepAll {
+        type Error = Error;
+
+        fn modify_record(
+            &self,
+            _record: &mut rust_htslib::bam::Record,
+        ) -> Result<Option<()>, Self::Error> {
+            Ok(Some(()))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_process_bam_async() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = tempfile::tempdir()?;
+        let bam_path = TestBam::new()
+            .add_reads("chr1", 0, 10, 3_000, 50)
+            .build(dir.path())?;
+
+        let (tx, rx) = watch::channel(ProcessStats::default());
+        let processor = Arc::new(ParallelBamProcessor::new(KeepAll));
+        let stats = processor
+            .process_bam_async(
+                bam_path,
+                dir.path().join("out.bam"),
+                ProcessBamOptions::default(),
+                Some(tx),
+            )
+            .await?;
+
+        assert_eq!(stats.records_read, 3_000);
+        assert_eq!(stats.records_written, 3_000);
+        assert_eq!(*rx.borrow(), stats);
+
+        Ok(())
+    }
+}
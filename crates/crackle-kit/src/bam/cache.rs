@@ -0,0 +1,424 @@
+//! Caches of the outputs of a worker at loci, for sessions querying the same
+//! loci again, e.g. while tuning thresholds applied to the outputs, see
+//! [`LocusQueryEngine::query_cached`].
+//!
+//! Outputs are kept by [`CacheKey`], a digest of the bam as found on the disk,
+//! of the locus, and of the options of the query, so that a bam rewritten or
+//! only touched, or other options, miss rather than give stale outputs.
+//!
+//! [`LocusQueryEngine::query_cached`]: crate::bam::query::LocusQueryEngine::query_cached
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt, fs,
+    io::{self, Write as _},
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use sha2::{Digest as _, Sha256};
+use tracing::{Level, event};
+
+use crate::{
+    data::locus::GenomeCoordinate,
+    errors::Error,
+    utils::{atomic_write::AtomicFile, fmt::to_hex},
+};
+
+/// Extension of the entries of a [`DiskCache`].
+const ENTRY_EXTENSION: &str = "bin";
+
+/// A cache of encoded outputs by [`CacheKey`].
+///
+/// Failing to read or write the cache is not an error of the query using it,
+/// so implementations log it and miss instead.
+pub trait LocusCache: Send + Sync {
+    /// The value put at `key`, if still cached.
+    fn get(&self, key: &CacheKey) -> Option<Vec<u8>>;
+
+    fn put(&self, key: &CacheKey, value: &[u8]);
+}
+
+/// The identity of a file as found on the disk: its canonical path, size and
+/// modification time.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileStamp {
+    pub path: PathBuf,
+    pub len: u64,
+    pub modified: SystemTime,
+}
+
+impl FileStamp {
+    pub fn of(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let path = path.as_ref();
+        let canonical = fs::canonicalize(path).map_err(|err| Error::io("resolve", path, err))?;
+        let meta = fs::metadata(&canonical).map_err(|err| Error::io("stat", path, err))?;
+        let modified = meta
+            .modified()
+            .map_err(|err| Error::io("stat", path, err))?;
+
+        Ok(Self {
+            path: canonical,
+            len: meta.len(),
+            modified,
+        })
+    }
+
+    /// The fields of the stamp, for [`digest_fields`].
+    pub(crate) fn fields(&self) -> [Vec<u8>; 3] {
+        let modified = self
+            .modified
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |since| since.as_nanos());
+        [
+            self.path.as_os_str().as_encoded_bytes().to_vec(),
+            self.len.to_le_bytes().to_vec(),
+            modified.to_le_bytes().to_vec(),
+        ]
+    }
+}
+
+/// SHA-256 of `fields`, each prefixed by its length so that no two lists of
+/// fields have the same encoding.
+pub(crate) fn digest_fields<F: AsRef<[u8]>>(fields: impl IntoIterator<Item = F>) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    for field in fields {
+        let field = field.as_ref();
+        hasher.update((field.len() as u64).to_le_bytes());
+        hasher.update(field);
+    }
+    hasher.finalize().into()
+}
+
+/// Key of the output at a locus of a bam, a digest of the [`FileStamp`] of
+/// the bam, of the coordinate, and of a digest of the options the output
+/// depends on. Shown as lowercase hex.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct CacheKey([u8; 32]);
+
+impl CacheKey {
+    /// Key of the output at `coord`, 1-based, of the bam of `bam`, computed with
+    /// the options of digest `options`.
+    pub fn new(bam: &FileStamp, coord: &GenomeCoordinate<'_>, options: &[u8; 32]) -> Self {
+        let [path, len, modified] = bam.fields();
+        Self(digest_fields([
+            path,
+            len,
+            modified,
+            coord.contig.as_str().as_bytes().to_vec(),
+            coord.pos.to_le_bytes().to_vec(),
+            options.to_vec(),
+        ]))
+    }
+
+    pub fn as_bytes(&self) -> &[u8; 32] {
+        &self.0
+    }
+
+    /// The key shown as `hex`, if it is one.
+    pub fn from_hex(hex: &str) -> Option<Self> {
+        if hex.len() != 64 {
+            return None;
+        }
+        let mut bytes = [0u8; 32];
+        for (byte, pair) in bytes.iter_mut().zip(hex.as_bytes().chunks(2)) {
+            *byte = u8::from_str_radix(std::str::from_utf8(pair).ok()?, 16).ok()?;
+        }
+        Some(Self(bytes))
+    }
+}
+
+impl fmt::Display for CacheKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&to_hex(&self.0))
+    }
+}
+
+/// A [`LocusCache`] of a file per entry in a directory, evicting the entries
+/// least recently used once they take more than a size cap.
+///
+/// Entries are written atomically, so a cache shared by processes never gives
+/// a partial entry, and the time of their last use is kept as their
+/// modification time, to carry it over sessions. The size is only counted of
+/// the entries seen by this instance, those in the directory when it was
+/// opened and those it put since.
+#[derive(Debug)]
+pub struct DiskCache {
+    dir: PathBuf,
+    max_bytes: u64,
+    lru: Mutex<Lru>,
+}
+
+/// The entries of a [`DiskCache`] by last use.
+#[derive(Debug, Default)]
+struct Lru {
+    /// Size and tick of the last use of each entry.
+    entries: HashMap<CacheKey, (u64, u64)>,
+    by_tick: BTreeMap<u64, CacheKey>,
+    n_bytes: u64,
+    next_tick: u64,
+}
+
+impl Lru {
+    /// Mark `key`, of `len` bytes, as the entry used last.
+    fn touch(&mut self, key: CacheKey, len: u64) {
+        self.remove(&key);
+        let tick = self.next_tick;
+        self.next_tick += 1;
+        self.entries.insert(key, (len, tick));
+        self.by_tick.insert(tick, key);
+        self.n_bytes += len;
+    }
+
+    fn remove(&mut self, key: &CacheKey) {
+        if let Some((len, tick)) = self.entries.remove(key) {
+            self.by_tick.remove(&tick);
+            self.n_bytes -= len;
+        }
+    }
+
+    /// Remove the entries used least recently until they take at most
+    /// `max_bytes`, returning their keys.
+    fn evict(&mut self, max_bytes: u64) -> Vec<CacheKey> {
+        let mut evicted = vec![];
+        while self.n_bytes > max_bytes
+            && let Some((_, key)) = self.by_tick.pop_first()
+        {
+            let (len, _) = self.entries.remove(&key).unwrap();
+            self.n_bytes -= len;
+            evicted.push(key);
+        }
+        evicted
+    }
+}
+
+impl DiskCache {
+    /// A cache in `dir`, created if missing, of at most `max_bytes` of entries.
+    /// Entries already in it are kept, evicting those used least recently if
+    /// they take more.
+    pub fn open(dir: impl AsRef<Path>, max_bytes: u64) -> Result<Self, Error> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(&dir).map_err(|err| Error::io("create", &dir, err))?;
+
+        let mut found = vec![];
+        for entry in fs::read_dir(&dir).map_err(|err| Error::io("read", &dir, err))? {
+            let entry = entry.map_err(|err| Error::io("read", &dir, err))?;
+            let path = entry.path();
+            // temp files of writes in progress, and other files, are not entries.
+            let key = path
+                .extension()
+                .filter(|ext| *ext == ENTRY_EXTENSION)
+                .and(path.file_stem())
+                .and_then(|stem| CacheKey::from_hex(&stem.to_string_lossy()));
+            let Some(key) = key else { continue };
+            let meta = entry
+                .metadata()
+                .map_err(|err| Error::io("stat", &path, err))?;
+            found.push((meta.modified().unwrap_or(UNIX_EPOCH), key, meta.len()));
+        }
+        found.sort();
+
+        let cache = Self {
+            dir,
+            max_bytes,
+            lru: Mutex::new(Lru::default()),
+        };
+        let mut lru = cache.lru.lock().unwrap();
+        for (_, key, len) in found {
+            lru.touch(key, len);
+        }
+        let evicted = lru.evict(max_bytes);
+        drop(lru);
+        cache.remove_entries(evicted);
+
+        Ok(cache)
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    pub fn max_bytes(&self) -> u64 {
+        self.max_bytes
+    }
+
+    /// Number of entries cached.
+    pub fn len(&self) -> usize {
+        self.lru.lock().unwrap().entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Bytes taken by the entries cached.
+    pub fn n_bytes(&self) -> u64 {
+        self.lru.lock().unwrap().n_bytes
+    }
+
+    fn entry_path(&self, key: &CacheKey) -> PathBuf {
+        self.dir.join(format!("{}.{}", key, ENTRY_EXTENSION))
+    }
+
+    fn write_entry(&self, key: &CacheKey, value: &[u8]) -> Result<(), Error> {
+        let mut file = AtomicFile::create(self.entry_path(key))?;
+        let tmp_path = file.tmp_path().to_path_buf();
+        file.write_all(value)
+            .map_err(|err| Error::io("write", tmp_path, err))?;
+        file.commit()
+    }
+
+    fn remove_entries(&self, keys: Vec<CacheKey>) {
+        for key in keys {
+            let path = self.entry_path(&key);
+            match fs::remove_file(&path) {
+                Ok(()) => {}
+                Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+                Err(err) => event!(
+                    Level::WARN,
+                    "Cannot evict {} from the cache: {}",
+                    path.display(),
+                    err
+                ),
+            }
+        }
+    }
+}
+
+impl LocusCache for DiskCache {
+    fn get(&self, key: &CacheKey) -> Option<Vec<u8>> {
+        let path = self.entry_path(key);
+        let value = match fs::read(&path) {
+            Ok(value) => value,
+            Err(err) => {
+                if err.kind() != io::ErrorKind::NotFound {
+                    event!(Level::WARN, "Cannot read {}: {}", path.display(), err);
+                }
+                self.lru.lock().unwrap().remove(key);
+                return None;
+            }
+        };
+
+        self.lru.lock().unwrap().touch(*key, value.len() as u64);
+        // the order of eviction of the next sessions.
+        if let Err(err) = fs::File::options()
+            .write(true)
+            .open(&path)
+            .and_then(|file| file.set_modified(SystemTime::now()))
+        {
+            event!(Level::DEBUG, "Cannot touch {}: {}", path.display(), err);
+        }
+        Some(value)
+    }
+
+    fn put(&self, key: &CacheKey, value: &[u8]) {
+        let len = value.len() as u64;
+        if len > self.max_bytes {
+            event!(
+                Level::DEBUG,
+                "Not caching {} bytes at {}, above the cap of {}",
+                len,
+                key,
+                self.max_bytes
+            );
+            return;
+        }
+
+        // writes are serialized, as the temp files of the entries are by process.
+        let mut lru = self.lru.lock().unwrap();
+        if let Err(err) = self.write_entry(key, value) {
+            event!(Level::WARN, "Cannot cache the output at {}: {:#}", key, err);
+            return;
+        }
+        lru.touch(*key, len);
+        let evicted = lru.evict(self.max_bytes);
+        drop(lru);
+        self.remove_entries(evicted);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::chrom::Chrom;
+
+    fn stamp() -> FileStamp {
+        FileStamp {
+            path: PathBuf::from("/data/sample.bam"),
+            len: 1_000,
+            modified: UNIX_EPOCH,
+        }
+    }
+
+    fn key(pos: i64) -> CacheKey {
+        let coord = GenomeCoordinate {
+            contig: Chrom::Chr1,
+            pos,
+        };
+        CacheKey::new(&stamp(), &coord, &[0; 32])
+    }
+
+    #[test]
+    fn test_cache_key() {
+        let bam = stamp();
+        let coord = GenomeCoordinate {
+            contig: Chrom::Chr1,
+            pos: 100,
+        };
+        let base = CacheKey::new(&bam, &coord, &[0; 32]);
+        assert_eq!(CacheKey::from_hex(&base.to_string()), Some(base));
+        assert_eq!(CacheKey::from_hex("00"), None);
+
+        let touched = FileStamp {
+            modified: UNIX_EPOCH + std::time::Duration::from_secs(1),
+            ..bam.clone()
+        };
+        let resized = FileStamp {
+            len: 1_001,
+            ..bam.clone()
+        };
+        let others = [
+            CacheKey::new(&touched, &coord, &[0; 32]),
+            CacheKey::new(&resized, &coord, &[0; 32]),
+            CacheKey::new(&bam, &coord, &[1; 32]),
+            key(101),
+        ];
+        assert!(others.iter().all(|other| *other != base));
+    }
+
+    #[test]
+    fn test_disk_cache_evicts_least_recently_used() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = tempfile::tempdir()?;
+        let cache = DiskCache::open(dir.path(), 250)?;
+        assert!(cache.is_empty());
+        assert_eq!(cache.get(&key(1)), None);
+
+        cache.put(&key(1), &[1; 100]);
+        cache.put(&key(2), &[2; 100]);
+        assert_eq!(cache.get(&key(1)), Some(vec![1; 100]));
+        // over the cap: 2 goes, as 1 was used since.
+        cache.put(&key(3), &[3; 100]);
+        assert_eq!((cache.len(), cache.n_bytes()), (2, 200));
+        assert_eq!(cache.get(&key(2)), None);
+        assert!(!cache.entry_path(&key(2)).exists());
+        assert_eq!(cache.get(&key(1)), Some(vec![1; 100]));
+        assert_eq!(cache.get(&key(3)), Some(vec![3; 100]));
+
+        // a value above the cap is not cached, and evicts nothing.
+        cache.put(&key(4), &[4; 251]);
+        assert_eq!(cache.get(&key(4)), None);
+        assert_eq!(cache.len(), 2);
+
+        // entries are kept over sessions, evicted down to a smaller cap.
+        drop(cache);
+        fs::write(dir.path().join("notes.txt"), "not an entry")?;
+        let cache = DiskCache::open(dir.path(), 300)?;
+        assert_eq!((cache.len(), cache.n_bytes()), (2, 200));
+        let cache = DiskCache::open(dir.path(), 150)?;
+        assert_eq!(cache.len(), 1);
+        assert_eq!(fs::read_dir(dir.path())?.count(), 2);
+
+        Ok(())
+    }
+}
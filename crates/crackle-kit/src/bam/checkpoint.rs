@@ -0,0 +1,526 @@
+//! Checkpoints of [`ParallelLocusProcessorPileup::process_with_batch_checkpointed`]:
+//! a state file, and a spool of the outputs of the batches done.
+//!
+//! The spool, at `{checkpoint}.spool`, has an entry per batch: its index and its
+//! number of outputs, then each output as its length and the bytes given by the
+//! encoder, all integers as little-endian `u64`s. Entries are appended as the
+//! batches finish, in any order.
+//!
+//! The state file lists the batches done, the length of the spool holding them,
+//! and a fingerprint of the inputs. It is replaced atomically once the spool is
+//! synced to the disk, so after a crash it only lists batches whose entries are
+//! complete; the spool is cut to that length on resume.
+//!
+//! [`ParallelLocusProcessorPileup::process_with_batch_checkpointed`]: crate::bam::process::ParallelLocusProcessorPileup::process_with_batch_checkpointed
+
+use std::{
+    collections::BTreeMap,
+    ffi::OsString,
+    fs::{self, File, OpenOptions},
+    io::{self, BufReader, Read, Write},
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use anyhow::{Context, Error, anyhow, ensure};
+
+use crate::{
+    bam::process::BamLocusWorkInput,
+    utils::{atomic_write::AtomicFile, stable_hash::Fnv1a},
+};
+
+const HEADER: &str = "crackle-kit locus checkpoint 1";
+
+/// FNV-1a of the coordinates of `batches` and of their sizes, the same across
+/// runs and builds.
+pub(crate) fn fingerprint<'a, I: BamLocusWorkInput<'a>>(batches: &[Vec<I>]) -> u64 {
+    let mut hasher = Fnv1a::new();
+    for batch in batches {
+        hasher.write(&(batch.len() as u64).to_le_bytes());
+        for input in batch {
+            let gc = input.genome_coordinate();
+            hasher.write(gc.contig.as_str().as_bytes());
+            hasher.write(&[0]);
+            hasher.write(&gc.pos.to_le_bytes());
+        }
+    }
+    hasher.finish()
+}
+
+/// Path of the spool of the checkpoint at `checkpoint_path`.
+pub(crate) fn spool_path(checkpoint_path: &Path) -> PathBuf {
+    let mut path = OsString::from(checkpoint_path.as_os_str());
+    path.push(".spool");
+    path.into()
+}
+
+/// Content of the state file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct CheckpointState {
+    fingerprint: u64,
+    n_batches: usize,
+    /// Bytes of the spool holding the batches done.
+    spool_len: u64,
+    /// Sorted.
+    done: Vec<usize>,
+}
+
+impl CheckpointState {
+    /// `None` if there is no state file at `path`.
+    fn read(path: &Path) -> Result<Option<Self>, crate::Error> {
+        let content = match fs::read_to_string(path) {
+            Ok(content) => content,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(crate::Error::io("read", path, err)),
+        };
+
+        let parse_err = |line: usize, source: Error| crate::Error::Parse {
+            what: "checkpoint".to_string(),
+            path: path.to_path_buf(),
+            line,
+            source,
+        };
+        let mut lines = content.lines().enumerate();
+        if lines.next().map(|(_, l)| l) != Some(HEADER) {
+            return Err(parse_err(1, anyhow!("Expected `{}`", HEADER)));
+        }
+
+        // `key value`, on the line after the previous one.
+        let mut field = |key: &str| {
+            let (i, line) = lines.next().unwrap_or((content.lines().count(), ""));
+            line.strip_prefix(key)
+                .and_then(|value| value.strip_prefix(' '))
+                .map(|value| (i + 1, value))
+                .ok_or_else(|| parse_err(i + 1, anyhow!("Expected `{} ...`", key)))
+        };
+
+        let (line, value) = field("fingerprint")?;
+        let fingerprint = u64::from_str_radix(value, 16).map_err(|e| parse_err(line, e.into()))?;
+        let (line, value) = field("batches")?;
+        let n_batches = value.parse().map_err(|e| parse_err(line, Error::from(e)))?;
+        let (line, value) = field("spool")?;
+        let spool_len = value.parse().map_err(|e| parse_err(line, Error::from(e)))?;
+        let (line, value) = field("done")?;
+        let done = value
+            .split_ascii_whitespace()
+            .map(str::parse)
+            .collect::<Result<Vec<usize>, _>>()
+            .map_err(|e| parse_err(line, e.into()))?;
+
+        Ok(Some(Self {
+            fingerprint,
+            n_batches,
+            spool_len,
+            done,
+        }))
+    }
+
+    /// Replace the state file at `path`, synced to the disk.
+    fn write(&self, path: &Path) -> Result<(), crate::Error> {
+        let mut file = AtomicFile::create(path)?.with_fsync(true);
+        let done = self
+            .done
+            .iter()
+            .map(|idx| idx.to_string())
+            .collect::<Vec<_>>()
+            .join(" ");
+        writeln!(
+            file.file(),
+            "{}\nfingerprint {:016x}\nbatches {}\nspool {}\ndone {}",
+            HEADER,
+            self.fingerprint,
+            self.n_batches,
+            self.spool_len,
+            done
+        )
+        .map_err(|e| crate::Error::io("write", file.tmp_path(), e))?;
+        file.commit()
+    }
+}
+
+/// The checkpoint of a run, shared by the threads finishing batches.
+#[derive(Debug)]
+pub(crate) struct LocusCheckpoint {
+    path: PathBuf,
+    spool_path: PathBuf,
+    every_n_batches: usize,
+    inner: Mutex<CheckpointInner>,
+}
+
+#[derive(Debug)]
+struct CheckpointInner {
+    state: CheckpointState,
+    spool: File,
+    /// Bytes of the spool, the entries not in the state yet included.
+    spool_len: u64,
+    /// Batches in the spool but not in the state yet.
+    pending: Vec<usize>,
+    /// Set once writing to the spool failed: the state is left as last written.
+    failed: bool,
+}
+
+impl LocusCheckpoint {
+    /// Resume the checkpoint at `path` of the same batches, or start one.
+    pub(crate) fn open(
+        path: &Path,
+        fingerprint: u64,
+        n_batches: usize,
+        every_n_batches: usize,
+    ) -> Result<Self, Error> {
+        ensure!(
+            every_n_batches > 0,
+            "Checkpoints must be written every 1 batch or more"
+        );
+
+        let state = match CheckpointState::read(path)? {
+            Some(state) => {
+                ensure!(
+                    state.fingerprint == fingerprint && state.n_batches == n_batches,
+                    "{} is the checkpoint of other inputs or batches",
+                    path.display()
+                );
+                state
+            }
+            None => CheckpointState {
+                fingerprint,
+                n_batches,
+                spool_len: 0,
+                done: vec![],
+            },
+        };
+
+        let spool_path = spool_path(path);
+        let spool = OpenOptions::new()
+            .append(true)
+            .create(true)
+            .open(&spool_path)
+            .map_err(|e| crate::Error::io("open", &spool_path, e))?;
+        // entries past the state may be partial.
+        spool
+            .set_len(state.spool_len)
+            .map_err(|e| crate::Error::io("truncate", &spool_path, e))?;
+
+        Ok(Self {
+            path: path.to_path_buf(),
+            spool_path,
+            every_n_batches,
+            inner: Mutex::new(CheckpointInner {
+                spool_len: state.spool_len,
+                state,
+                spool,
+                pending: vec![],
+                failed: false,
+            }),
+        })
+    }
+
+    /// Outputs of the batches done, by batch index, read back by `decode`.
+    pub(crate) fn load<T>(
+        &self,
+        decode: impl Fn(&[u8]) -> Result<T, Error>,
+    ) -> Result<BTreeMap<usize, Vec<T>>, Error> {
+        let inner = self.inner.lock().unwrap();
+        let file = File::open(&self.spool_path)
+            .map_err(|e| crate::Error::io("open", &self.spool_path, e))?;
+        let mut reader = BufReader::new(file).take(inner.state.spool_len);
+        let context = || format!("Failed to read the spool {}", self.spool_path.display());
+
+        let mut loaded = BTreeMap::new();
+        let mut buf = vec![];
+        while loaded.len() < inner.state.done.len() {
+            let idx = read_u64(&mut reader).with_context(context)? as usize;
+            let n_outputs = read_u64(&mut reader).with_context(context)? as usize;
+            let mut outputs = Vec::with_capacity(n_outputs);
+            for _ in 0..n_outputs {
+                buf.resize(read_u64(&mut reader).with_context(context)? as usize, 0);
+                reader.read_exact(&mut buf).with_context(context)?;
+                outputs.push(
+                    decode(&buf)
+                        .with_context(|| format!("Failed to decode an output of batch {}", idx))?,
+                );
+            }
+            loaded.insert(idx, outputs);
+        }
+        ensure!(
+            loaded.keys().eq(inner.state.done.iter()),
+            "The spool {} does not hold the batches of {}",
+            self.spool_path.display(),
+            self.path.display()
+        );
+
+        Ok(loaded)
+    }
+
+    /// Append the outputs of batch `idx` to the spool, encoded by `encode`, and
+    /// write the state every `every_n_batches` batches.
+    ///
+    /// Once this fails to write the spool or the state, the checkpoint saves
+    /// nothing more.
+    pub(crate) fn save<T>(
+        &self,
+        idx: usize,
+        outputs: &[T],
+        encode: &impl Fn(&T, &mut Vec<u8>) -> Result<(), Error>,
+    ) -> Result<(), Error> {
+        let mut entry = vec![];
+        entry.extend((idx as u64).to_le_bytes());
+        entry.extend((outputs.len() as u64).to_le_bytes());
+        let mut buf = vec![];
+        for output in outputs {
+            buf.clear();
+            encode(output, &mut buf)
+                .with_context(|| format!("Failed to encode an output of batch {}", idx))?;
+            entry.extend((buf.len() as u64).to_le_bytes());
+            entry.extend(&buf);
+        }
+
+        let mut inner = self.inner.lock().unwrap();
+        ensure!(
+            !inner.failed,
+            "The checkpoint {} failed to save an earlier batch",
+            self.path.display()
+        );
+        if let Err(err) = inner.spool.write_all(&entry) {
+            inner.failed = true;
+            // cut the partial entry; one left is cut on resume anyway.
+            let _ = inner.spool.set_len(inner.spool_len);
+            return Err(crate::Error::io("write", &self.spool_path, err).into());
+        }
+        inner.spool_len += entry.len() as u64;
+        inner.pending.push(idx);
+        if inner.pending.len() >= self.every_n_batches {
+            let res = inner.commit(&self.path, &self.spool_path);
+            inner.failed = res.is_err();
+            res?;
+        }
+        Ok(())
+    }
+
+    /// Remove the checkpoint of a run which succeeded, or write the state of
+    /// one which failed, to be resumed, unless saving failed.
+    pub(crate) fn finish(self, succeeded: bool) -> Result<(), Error> {
+        let mut inner = self.inner.into_inner().unwrap();
+        if !succeeded {
+            if inner.failed {
+                return Ok(());
+            }
+            return inner.commit(&self.path, &self.spool_path);
+        }
+
+        drop(inner);
+        for path in [&self.path, &self.spool_path] {
+            match fs::remove_file(path) {
+                Err(err) if err.kind() != io::ErrorKind::NotFound => {
+                    return Err(crate::Error::io("remove", path, err).into());
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+impl CheckpointInner {
+    /// Write the state of the batches in the spool at `spool_path` to `path`,
+    /// once the spool is synced.
+    fn commit(&mut self, path: &Path, spool_path: &Path) -> Result<(), Error> {
+        if self.pending.is_empty() {
+            return Ok(());
+        }
+        self.spool
+            .sync_data()
+            .map_err(|e| crate::Error::io("sync", spool_path, e))?;
+
+        let mut state = self.state.clone();
+        state.done.append(&mut self.pending);
+        state.done.sort_unstable();
+        state.spool_len = self.spool_len;
+        state.write(path)?;
+        self.state = state;
+        Ok(())
+    }
+}
+
+fn read_u64(reader: &mut impl Read) -> io::Result<u64> {
+    let mut bytes = [0; 8];
+    reader.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use rust_htslib::bam::pileup::Pileup;
+
+    use super::*;
+    use crate::{
+        bam::process::{BamLocusWorker, ParallelLocusProcessorPileup},
+        data::{chrom::Chrom, locus::GenomeCoordinate},
+        test_utils::TestBam,
+    };
+
+    /// Depth at each locus, failing from `fail_from` on, as if the run was
+    /// killed there.
+    struct DepthWorker {
+        fail_from: Option<i64>,
+        n_called: AtomicUsize,
+    }
+
+    impl<'a> BamLocusWorker<'a> for DepthWorker {
+        type Input = GenomeCoordinate<'a>;
+        type Output = (i64, u32);
+        type Error = Error;
+
+        fn work_for_locus(&self, plp: Pileup, input: Self::Input) -> Result<(i64, u32), Error> {
+            if self.fail_from.is_some_and(|pos| input.pos >= pos) {
+                return Err(crate::Error::Cancelled.into());
+            }
+            self.n_called.fetch_add(1, Ordering::Relaxed);
+            Ok((input.pos, plp.depth()))
+        }
+    }
+
+    fn processor(
+        bam_path: &Path,
+        fail_from: Option<i64>,
+    ) -> ParallelLocusProcessorPileup<DepthWorker> {
+        let worker = DepthWorker {
+            fail_from,
+            n_called: AtomicUsize::new(0),
+        };
+        ParallelLocusProcessorPileup::new(worker, 2, bam_path.to_path_buf())
+    }
+
+    fn encode(output: &(i64, u32), buf: &mut Vec<u8>) -> Result<(), Error> {
+        Ok(bincode::serialize_into(buf, output)?)
+    }
+
+    fn decode(bytes: &[u8]) -> Result<(i64, u32), Error> {
+        Ok(bincode::deserialize(bytes)?)
+    }
+
+    /// 1-based 1, 101, ..., 19_901: 20 batches of 10 loci with a 1 kb window.
+    fn inputs() -> Vec<GenomeCoordinate<'static>> {
+        (1..20_000)
+            .step_by(100)
+            .map(|pos| GenomeCoordinate {
+                contig: Chrom::Chr1,
+                pos,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_resume_after_failure() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = tempfile::tempdir()?;
+        let bam_path = TestBam::new()
+            .add_reads("chr1", 0, 7, 3_000, 50)
+            .build(dir.path())?;
+        let checkpoint_path = dir.path().join("scan.checkpoint");
+
+        let expected = processor(&bam_path, None).process_with_batch(inputs(), 1_000)?;
+        assert_eq!(expected.len(), 200);
+
+        // the second half of the batches fail.
+        let err = processor(&bam_path, Some(10_001))
+            .process_with_batch_checkpointed(inputs(), 1_000, &checkpoint_path, 3, encode, decode)
+            .unwrap_err();
+        assert!(
+            format!("{:#}", err).contains("locus chr1:10001"),
+            "{:#}",
+            err
+        );
+
+        let state = CheckpointState::read(&checkpoint_path)?.unwrap();
+        assert_eq!((state.n_batches, state.done), (20, (0..10).collect()));
+
+        // a partial entry written as the run was killed.
+        let spool = spool_path(&checkpoint_path);
+        fs::OpenOptions::new()
+            .append(true)
+            .open(&spool)?
+            .write_all(&[1, 2, 3])?;
+
+        let resumed = processor(&bam_path, None);
+        let outputs = resumed.process_with_batch_checkpointed(
+            inputs(),
+            1_000,
+            &checkpoint_path,
+            3,
+            encode,
+            decode,
+        )?;
+        assert_eq!(outputs, expected);
+        // only the loci of the failed batches.
+        assert_eq!(resumed.worker().n_called.load(Ordering::Relaxed), 100);
+        assert!(!checkpoint_path.exists() && !spool.exists());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_no_save_after_a_failed_write() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = tempfile::tempdir()?;
+        let checkpoint_path = dir.path().join("scan.checkpoint");
+        let checkpoint = LocusCheckpoint::open(&checkpoint_path, 7, 3, 1)?;
+        checkpoint.save(0, &[(1, 2)], &encode)?;
+
+        // writes to the spool fail from here.
+        let spool = spool_path(&checkpoint_path);
+        checkpoint.inner.lock().unwrap().spool = File::open(&spool)?;
+        let err = checkpoint.save(1, &[(3, 4)], &encode).unwrap_err();
+        assert!(
+            matches!(
+                err.downcast_ref::<crate::Error>(),
+                Some(crate::Error::Io { .. })
+            ),
+            "{:#}",
+            err
+        );
+        let err = checkpoint.save(2, &[(5, 6)], &encode).unwrap_err();
+        assert!(err.to_string().contains("failed to save"), "{:#}", err);
+        checkpoint.finish(false)?;
+
+        let state = CheckpointState::read(&checkpoint_path)?.unwrap();
+        assert_eq!(state.done, vec![0]);
+        assert_eq!(state.spool_len, fs::metadata(&spool)?.len());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_checkpoint_of_other_inputs() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = tempfile::tempdir()?;
+        let bam_path = TestBam::new()
+            .add_reads("chr1", 0, 7, 3_000, 50)
+            .build(dir.path())?;
+        let checkpoint_path = dir.path().join("scan.checkpoint");
+
+        processor(&bam_path, Some(10_001))
+            .process_with_batch_checkpointed(inputs(), 1_000, &checkpoint_path, 1, encode, decode)
+            .unwrap_err();
+
+        // the same loci in other batches.
+        let err = processor(&bam_path, None)
+            .process_with_batch_checkpointed(inputs(), 500, &checkpoint_path, 1, encode, decode)
+            .unwrap_err();
+        assert!(
+            err.to_string().contains("checkpoint of other inputs"),
+            "{:#}",
+            err
+        );
+
+        fs::write(&checkpoint_path, format!("{}\nfingerprint zz\n", HEADER))?;
+        let err = processor(&bam_path, None)
+            .process_with_batch_checkpointed(inputs(), 1_000, &checkpoint_path, 1, encode, decode)
+            .unwrap_err();
+        assert!(
+            matches!(err, crate::Error::Parse { line: 2, .. }),
+            "{:#}",
+            err
+        );
+
+        Ok(())
+    }
+}
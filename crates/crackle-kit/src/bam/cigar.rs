@@ -0,0 +1,274 @@
+//! CIGAR and split-read helpers on [`Record`], for clip and breakpoint evidence.
+//!
+//! The helpers read the raw CIGAR of the record, so they do not allocate a
+//! [`CigarStringView`](rust_htslib::bam::record::CigarStringView).
+
+use anyhow::{Context, Error, anyhow, bail};
+use rust_htslib::bam::{Record, record::Aux};
+
+use crate::data::chrom::Chrom;
+
+const CIGAR_MATCH: u32 = 0;
+const CIGAR_INS: u32 = 1;
+const CIGAR_DEL: u32 = 2;
+const CIGAR_REF_SKIP: u32 = 3;
+const CIGAR_SOFT_CLIP: u32 = 4;
+const CIGAR_HARD_CLIP: u32 = 5;
+const CIGAR_EQUAL: u32 = 7;
+const CIGAR_DIFF: u32 = 8;
+
+/// Strand of a [`SaEntry`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Strand {
+    Forward,
+    Reverse,
+}
+
+/// One alignment of the `SA` tag, `rname,pos,strand,CIGAR,mapQ,NM`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SaEntry {
+    pub chrom: Chrom<'static>,
+    /// 1-based, as in the tag.
+    pub pos: i64,
+    pub strand: Strand,
+    pub cigar: String,
+    pub mapq: u8,
+    pub nm: u32,
+}
+
+impl SaEntry {
+    fn parse(s: &str) -> Result<Self, Error> {
+        let fields = s.split(',').collect::<Vec<_>>();
+        let [chrom, pos, strand, cigar, mapq, nm] = fields[..] else {
+            bail!("Expected 6 fields in SA entry: {}", s);
+        };
+
+        let strand = match strand {
+            "+" => Strand::Forward,
+            "-" => Strand::Reverse,
+            _ => bail!("Invalid strand in SA entry: {}", s),
+        };
+
+        Ok(Self {
+            chrom: Chrom::from(chrom).into_owned(),
+            pos: pos
+                .parse()
+                .with_context(|| format!("Invalid pos in SA entry: {}", s))?,
+            strand,
+            cigar: cigar.to_string(),
+            mapq: mapq
+                .parse()
+                .with_context(|| format!("Invalid mapq in SA entry: {}", s))?,
+            nm: nm
+                .parse()
+                .with_context(|| format!("Invalid NM in SA entry: {}", s))?,
+        })
+    }
+}
+
+/// CIGAR and `SA` tag queries on a [`Record`].
+pub trait RecordCigarExt {
+    /// Length of the soft clip at the start of the alignment, after any hard clip.
+    fn left_softclip_len(&self) -> u32;
+
+    /// Length of the soft clip at the end of the alignment, before any hard clip.
+    fn right_softclip_len(&self) -> u32;
+
+    /// Whether the alignment starts with a soft or hard clip.
+    fn is_left_clipped(&self) -> bool;
+
+    /// Whether the alignment ends with a soft or hard clip.
+    fn is_right_clipped(&self) -> bool;
+
+    /// Reference bases covered by the alignment (M, D, N, =, X), skipped
+    /// introns included.
+    fn aligned_reference_span(&self) -> i64;
+
+    /// Query bases described by the CIGAR (M, I, S, =, X), equal to the sequence
+    /// length unless the sequence is missing.
+    fn query_consumed(&self) -> usize;
+
+    /// Bases of the left soft clip. The sequence is 4-bit packed in the record,
+    /// so they are decoded into a new vec.
+    fn leading_clip_seq(&self) -> Vec<u8>;
+
+    /// Whether the record has an `SA` tag.
+    fn has_supplementary_alignment(&self) -> bool;
+
+    /// The alignments of the `SA` tag, empty without one.
+    fn supplementary_alignments(&self) -> Result<Vec<SaEntry>, Error>;
+}
+
+fn cigar_ops(record: &Record) -> impl DoubleEndedIterator<Item = (u32, u32)> + '_ {
+    record.raw_cigar().iter().map(|&c| (c & 0xf, c >> 4))
+}
+
+/// Length of the soft clip of `ops`, seen from their start.
+fn softclip_len(mut ops: impl Iterator<Item = (u32, u32)>) -> u32 {
+    match ops.find(|&(op, _)| op != CIGAR_HARD_CLIP) {
+        Some((CIGAR_SOFT_CLIP, len)) => len,
+        _ => 0,
+    }
+}
+
+impl RecordCigarExt for Record {
+    fn left_softclip_len(&self) -> u32 {
+        softclip_len(cigar_ops(self))
+    }
+
+    fn right_softclip_len(&self) -> u32 {
+        softclip_len(cigar_ops(self).rev())
+    }
+
+    fn is_left_clipped(&self) -> bool {
+        matches!(
+            cigar_ops(self).next(),
+            Some((CIGAR_SOFT_CLIP | CIGAR_HARD_CLIP, _))
+        )
+    }
+
+    fn is_right_clipped(&self) -> bool {
+        matches!(
+            cigar_ops(self).next_back(),
+            Some((CIGAR_SOFT_CLIP | CIGAR_HARD_CLIP, _))
+        )
+    }
+
+    fn aligned_reference_span(&self) -> i64 {
+        cigar_ops(self)
+            .filter(|&(op, _)| {
+                matches!(
+                    op,
+                    CIGAR_MATCH | CIGAR_DEL | CIGAR_REF_SKIP | CIGAR_EQUAL | CIGAR_DIFF
+                )
+            })
+            .map(|(_, len)| len as i64)
+            .sum()
+    }
+
+    fn query_consumed(&self) -> usize {
+        cigar_ops(self)
+            .filter(|&(op, _)| {
+                matches!(
+                    op,
+                    CIGAR_MATCH | CIGAR_INS | CIGAR_SOFT_CLIP | CIGAR_EQUAL | CIGAR_DIFF
+                )
+            })
+            .map(|(_, len)| len as usize)
+            .sum()
+    }
+
+    fn leading_clip_seq(&self) -> Vec<u8> {
+        let seq = self.seq();
+        let len = (self.left_softclip_len() as usize).min(seq.len());
+        (0..len).map(|i| seq[i]).collect()
+    }
+
+    fn has_supplementary_alignment(&self) -> bool {
+        self.aux(b"SA").is_ok()
+    }
+
+    fn supplementary_alignments(&self) -> Result<Vec<SaEntry>, Error> {
+        let sa = match self.aux(b"SA") {
+            Ok(Aux::String(sa)) => sa,
+            Ok(aux) => return Err(anyhow!("SA tag is not a string: {:?}", aux)),
+            Err(_) => return Ok(vec![]),
+        };
+
+        sa.split(';')
+            .filter(|entry| !entry.is_empty())
+            .map(SaEntry::parse)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_htslib::bam::record::CigarString;
+
+    use super::*;
+
+    fn record(cigar: &str, seq: &[u8]) -> Record {
+        let cigar = CigarString::try_from(cigar).unwrap();
+        let qual = vec![30; seq.len()];
+        let mut record = Record::new();
+        record.set(b"r", Some(&cigar), seq, &qual);
+        record
+    }
+
+    #[test]
+    fn test_clips_and_spans() {
+        let seq = [b"ACGTACGTAC".as_slice(), &[b'T'; 90]].concat();
+
+        let r = record("10S90M", &seq);
+        assert_eq!(r.left_softclip_len(), 10);
+        assert_eq!(r.right_softclip_len(), 0);
+        assert!(r.is_left_clipped() && !r.is_right_clipped());
+        assert_eq!(r.aligned_reference_span(), 90);
+        assert_eq!(r.query_consumed(), 100);
+        assert_eq!(r.leading_clip_seq(), b"ACGTACGTAC");
+
+        let r = record("90M10S", &seq);
+        assert_eq!(r.left_softclip_len(), 0);
+        assert_eq!(r.right_softclip_len(), 10);
+        assert!(!r.is_left_clipped() && r.is_right_clipped());
+        assert_eq!(r.aligned_reference_span(), 90);
+        assert_eq!(r.query_consumed(), 100);
+        assert!(r.leading_clip_seq().is_empty());
+
+        let r = record("50M2000N50M", &seq);
+        assert_eq!(r.left_softclip_len() + r.right_softclip_len(), 0);
+        assert_eq!(r.aligned_reference_span(), 2100);
+        assert_eq!(r.query_consumed(), 100);
+
+        // hard clips are outside of the sequence.
+        let r = record("5H3S2I85M5D10S7H", &seq);
+        assert_eq!(r.left_softclip_len(), 3);
+        assert_eq!(r.right_softclip_len(), 10);
+        assert!(r.is_left_clipped() && r.is_right_clipped());
+        assert_eq!(r.aligned_reference_span(), 90);
+        assert_eq!(r.query_consumed(), 100);
+        assert_eq!(r.leading_clip_seq(), b"ACG");
+    }
+
+    #[test]
+    fn test_supplementary_alignments() -> Result<(), Error> {
+        let seq = [b'A'; 100];
+        let mut r = record("60M40S", &seq);
+        assert!(!r.has_supplementary_alignment());
+        assert!(r.supplementary_alignments()?.is_empty());
+
+        r.push_aux(
+            b"SA",
+            Aux::String("chr2,1001,-,60S40M,30,1;5,20,+,40H60M,0,0;"),
+        )?;
+        assert!(r.has_supplementary_alignment());
+        assert_eq!(
+            r.supplementary_alignments()?,
+            vec![
+                SaEntry {
+                    chrom: Chrom::Chr2,
+                    pos: 1001,
+                    strand: Strand::Reverse,
+                    cigar: "60S40M".to_string(),
+                    mapq: 30,
+                    nm: 1,
+                },
+                SaEntry {
+                    chrom: Chrom::Chr5,
+                    pos: 20,
+                    strand: Strand::Forward,
+                    cigar: "40H60M".to_string(),
+                    mapq: 0,
+                    nm: 0,
+                },
+            ]
+        );
+
+        r.remove_aux(b"SA")?;
+        r.push_aux(b"SA", Aux::String("chr2,1001,*,60S40M,30,1;"))?;
+        assert!(r.supplementary_alignments().is_err());
+
+        Ok(())
+    }
+}
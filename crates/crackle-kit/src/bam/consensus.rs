@@ -0,0 +1,359 @@
+//! Consensus sequence of a region, from the base counts of its pileup columns.
+
+use anyhow::{Error, bail};
+use rust_htslib::bam::pileup::Pileup;
+
+use crate::{
+    bam::pileup_ext::AlignmentContext,
+    data::{bases::Base, locus::GenomeRegion},
+    nuc_base_map::NucBaseMap,
+};
+
+/// Bases of [`NucBaseMap`], in the order of its inner array.
+const MAP_BASES: [Base; 5] = [Base::A, Base::T, Base::C, Base::G, Base::N];
+
+/// When a position gets a consensus base, see [`ConsensusBuilder::finish`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ConsensusRule {
+    /// Fewer bases and deletions than this is an `N`.
+    pub min_depth: u32,
+    /// The most frequent base, or deletion, must make at least this fraction of
+    /// the depth, and be the only one that frequent; otherwise `N`.
+    pub min_fraction: f64,
+}
+
+impl Default for ConsensusRule {
+    fn default() -> Self {
+        Self {
+            min_depth: 1,
+            min_fraction: 0.5,
+        }
+    }
+}
+
+/// Consensus of a position.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConsensusCall {
+    /// `N` if no base met the [`ConsensusRule`].
+    Base(Base),
+    /// Most reads have a deletion here; the position is left out of the
+    /// sequence.
+    Deletion,
+}
+
+/// Counts and consensus of a position of the region.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PositionSummary {
+    /// 1-based.
+    pub pos: i64,
+    /// Bases and deletions counted.
+    pub depth: u32,
+    pub call: ConsensusCall,
+    /// Fraction of the depth of the most frequent base or deletion, even when
+    /// the call is an `N`; 0 without depth.
+    pub fraction: f64,
+}
+
+/// Result of [`ConsensusBuilder::finish`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct Consensus {
+    /// Uppercase bases, without the deleted positions.
+    pub sequence: Vec<u8>,
+    /// 1-based positions called as [`ConsensusCall::Deletion`].
+    pub gaps: Vec<i64>,
+    /// Every position of the region, deleted ones included.
+    pub positions: Vec<PositionSummary>,
+}
+
+/// Base and deletion counts per position of a region, fed pileup columns.
+///
+/// Builders of the same region, e.g. one per batch of reads, are combined by
+/// [`Self::merge`].
+#[derive(Debug, Clone)]
+pub struct ConsensusBuilder {
+    region: GenomeRegion<'static>,
+    counts: Vec<NucBaseMap<u32>>,
+    deletions: Vec<u32>,
+    min_baseq: u8,
+    min_mapq: u8,
+    rule: ConsensusRule,
+}
+
+impl ConsensusBuilder {
+    /// Counts of `region`, 1-based and inclusive, counting every base.
+    pub fn new(region: &GenomeRegion<'_>) -> Self {
+        let len = (region.end - region.start + 1).max(0) as usize;
+        Self {
+            region: region.to_owned_region(),
+            counts: vec![NucBaseMap::default(); len],
+            deletions: vec![0; len],
+            min_baseq: 0,
+            min_mapq: 0,
+            rule: ConsensusRule::default(),
+        }
+    }
+
+    /// Skip the bases of a lower quality. Deletions have no quality and are
+    /// always counted.
+    pub fn with_min_baseq(mut self, min_baseq: u8) -> Self {
+        self.min_baseq = min_baseq;
+        self
+    }
+
+    /// Skip the bases and deletions of alignments of a lower mapping quality.
+    pub fn with_min_mapq(mut self, min_mapq: u8) -> Self {
+        self.min_mapq = min_mapq;
+        self
+    }
+
+    pub fn with_rule(mut self, rule: ConsensusRule) -> Self {
+        self.rule = rule;
+        self
+    }
+
+    pub fn region(&self) -> &GenomeRegion<'static> {
+        &self.region
+    }
+
+    /// Count the alignments of a pileup column of the contig of the region.
+    /// Columns outside of the region are ignored, and skips are not counted.
+    pub fn add_pileup(&mut self, plp: &Pileup) {
+        let Some(idx) = self.index_of(plp.pos() as i64 + 1) else {
+            return;
+        };
+
+        for aln in plp.alignments() {
+            let ctx = AlignmentContext::new(&aln);
+            if ctx.mapq < self.min_mapq {
+                continue;
+            }
+            if aln.is_del() {
+                self.deletions[idx] += 1;
+            } else if let (Some(base), Some(bq)) = (ctx.base, ctx.base_qual)
+                && bq >= self.min_baseq
+            {
+                *self.counts[idx]
+                    .get_or_insert_with(base.to_ascii(), || 0)
+                    .unwrap() += 1;
+            }
+        }
+    }
+
+    /// Index of 1-based `pos` in the counts.
+    fn index_of(&self, pos: i64) -> Option<usize> {
+        (self.region.start..=self.region.end)
+            .contains(&pos)
+            .then(|| (pos - self.region.start) as usize)
+    }
+
+    /// Add the counts of `other`, of the same region.
+    pub fn merge(&mut self, other: &Self) -> Result<(), Error> {
+        if self.region != other.region {
+            bail!(
+                "Cannot merge the consensus counts of {} into those of {}",
+                other.region,
+                self.region
+            );
+        }
+
+        for (counts, other) in self.counts.iter_mut().zip(&other.counts) {
+            for (count, other) in counts.get_mut_inner().iter_mut().zip(other.get_inner()) {
+                if let Some(other) = other {
+                    *count.get_or_insert(0) += other;
+                }
+            }
+        }
+        for (n, other) in self.deletions.iter_mut().zip(&other.deletions) {
+            *n += other;
+        }
+        Ok(())
+    }
+
+    /// The consensus of the counts so far, by the [`ConsensusRule`].
+    pub fn finish(&self) -> Consensus {
+        let mut consensus = Consensus {
+            sequence: Vec::with_capacity(self.counts.len()),
+            gaps: vec![],
+            positions: Vec::with_capacity(self.counts.len()),
+        };
+
+        for (i, (counts, &n_del)) in self.counts.iter().zip(&self.deletions).enumerate() {
+            let pos = self.region.start + i as i64;
+            let summary = self.call(pos, counts, n_del);
+            match summary.call {
+                ConsensusCall::Base(base) => consensus.sequence.push(base.to_ascii()),
+                ConsensusCall::Deletion => consensus.gaps.push(pos),
+            }
+            consensus.positions.push(summary);
+        }
+
+        consensus
+    }
+
+    fn call(&self, pos: i64, counts: &NucBaseMap<u32>, n_del: u32) -> PositionSummary {
+        let candidates = MAP_BASES
+            .iter()
+            .zip(counts.get_inner())
+            .map(|(&base, n)| (ConsensusCall::Base(base), n.unwrap_or(0)))
+            .chain([(ConsensusCall::Deletion, n_del)]);
+
+        let (mut best, mut best_n, mut tied, mut depth) =
+            (ConsensusCall::Base(Base::N), 0, false, 0);
+        for (call, n) in candidates {
+            depth += n;
+            if n > best_n {
+                (best, best_n, tied) = (call, n, false);
+            } else if n == best_n {
+                tied = true;
+            }
+        }
+
+        let fraction = match depth {
+            0 => 0.0,
+            _ => best_n as f64 / depth as f64,
+        };
+        let called =
+            depth >= self.rule.min_depth.max(1) && !tied && fraction >= self.rule.min_fraction;
+        PositionSummary {
+            pos,
+            depth,
+            call: if called {
+                best
+            } else {
+                ConsensusCall::Base(Base::N)
+            },
+            fraction,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_htslib::bam::{IndexedReader, Read as _};
+
+    use super::*;
+    use crate::{data::chrom::Chrom, test_utils::TestBam};
+
+    /// Counts of the reads of `bam_path` over `region`.
+    fn build(
+        bam_path: &std::path::Path,
+        region: &GenomeRegion,
+    ) -> Result<ConsensusBuilder, Box<dyn std::error::Error>> {
+        let mut builder = ConsensusBuilder::new(region)
+            .with_min_baseq(20)
+            .with_min_mapq(10)
+            .with_rule(ConsensusRule {
+                min_depth: 2,
+                min_fraction: 0.5,
+            });
+        let mut reader = IndexedReader::from_path(bam_path)?;
+        reader.fetch((region.contig.as_str(), region.start - 1, region.end))?;
+        for plp in reader.pileup() {
+            builder.add_pileup(&plp?);
+        }
+        Ok(builder)
+    }
+
+    #[test]
+    fn test_consensus() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = tempfile::tempdir()?;
+        // the reference is ACGTACGTAC at 0-based 100..=109.
+        let mut low_qual_4th = [30; 10];
+        low_qual_4th[3] = 10;
+        let mut test_bam = TestBam::new()
+            .add_read("chr1", 100, b"ACGTACGTAC", &[30; 10], 0)
+            .add_read("chr1", 100, b"ACGTACGTAC", &[30; 10], 0)
+            .add_read("chr1", 100, b"ACGTACGTAC", &[30; 10], 0)
+            // an A of low quality at the 4th base.
+            .add_read("chr1", 100, b"ACGAACGTAC", &low_qual_4th, 0)
+            // a mismatch of low mapping quality.
+            .add_read("chr1", 100, b"ACGTTCGTAC", &[30; 10], 0)
+            .with_mapq(5);
+        // 106..=107 deleted.
+        for _ in 0..6 {
+            test_bam = test_bam
+                .add_read("chr1", 100, b"ACGTACAC", &[30; 8], 0)
+                .with_cigar("6M2D2M");
+        }
+        // the only read at 110.
+        let bam_path = test_bam
+            .add_read("chr1", 105, b"CGTACG", &[30; 6], 0)
+            .build(dir.path())?;
+
+        // 1-based, 111 with a single read and 112 without.
+        let region = GenomeRegion::from((Chrom::Chr1, 101, 112));
+        let builder = build(&bam_path, &region)?;
+        let consensus = builder.finish();
+
+        assert_eq!(consensus.sequence, b"ACGTACACNN");
+        assert_eq!(consensus.gaps, vec![107, 108]);
+        assert_eq!(consensus.positions.len(), 12);
+
+        let at = |pos: i64| consensus.positions[(pos - 101) as usize];
+        assert_eq!(
+            at(101),
+            PositionSummary {
+                pos: 101,
+                depth: 10,
+                call: ConsensusCall::Base(Base::A),
+                fraction: 1.0,
+            }
+        );
+        // without the base of low quality.
+        assert_eq!(at(104).depth, 9);
+        assert_eq!(at(104).call, ConsensusCall::Base(Base::T));
+        // 5 G against 6 deletions.
+        assert_eq!(at(107).depth, 11);
+        assert_eq!(at(107).call, ConsensusCall::Deletion);
+        assert!((at(107).fraction - 6.0 / 11.0).abs() < 1e-9);
+        // low depth, no depth.
+        assert_eq!(
+            (at(111).depth, at(111).call, at(111).fraction),
+            (1, ConsensusCall::Base(Base::N), 1.0)
+        );
+        assert_eq!(
+            at(112),
+            PositionSummary {
+                pos: 112,
+                depth: 0,
+                call: ConsensusCall::Base(Base::N),
+                fraction: 0.0,
+            }
+        );
+
+        // a stricter rule turns the deletions into N.
+        let strict = builder.clone().with_rule(ConsensusRule {
+            min_depth: 2,
+            min_fraction: 0.6,
+        });
+        let consensus = strict.finish();
+        assert_eq!(consensus.sequence, b"ACGTACNNACNN");
+        assert!(consensus.gaps.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_merge() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = tempfile::tempdir()?;
+        let bam_path = TestBam::new()
+            .add_read("chr1", 100, b"ACGTACGTAC", &[30; 10], 0)
+            .add_read("chr1", 100, b"ACCTACGTAC", &[30; 10], 0)
+            .build(dir.path())?;
+        let region = GenomeRegion::from((Chrom::Chr1, 101, 110));
+
+        // a tie at 103, and a single read elsewhere per builder.
+        let mut merged = build(&bam_path, &region)?;
+        assert_eq!(merged.finish().sequence, b"ACNTACGTAC");
+        merged.merge(&build(&bam_path, &region)?)?;
+        let consensus = merged.finish();
+        assert_eq!(consensus.sequence, b"ACNTACGTAC");
+        assert_eq!(consensus.positions[0].depth, 4);
+        assert_eq!(consensus.positions[2].depth, 4);
+
+        let other = ConsensusBuilder::new(&GenomeRegion::from((Chrom::Chr1, 101, 111)));
+        assert!(merged.merge(&other).is_err());
+
+        Ok(())
+    }
+}
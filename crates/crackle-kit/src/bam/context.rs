@@ -0,0 +1,238 @@
+//! Input bam metadata handed to [`RecordModifier`](crate::bam::process::RecordModifier)s.
+
+use std::collections::HashMap;
+
+use rust_htslib::bam::{HeaderView, Record, record::Aux};
+
+use crate::data::chrom::Chrom;
+
+/// An `@RG` line of the header.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ReadGroup {
+    pub id: String,
+    /// `SM`, the sample.
+    pub sample: Option<String>,
+    /// `LB`, the library.
+    pub library: Option<String>,
+    /// `PU`, the platform unit, e.g. flowcell and lane.
+    pub platform_unit: Option<String>,
+}
+
+impl ReadGroup {
+    /// Parse a `@RG` line, `None` if it has no `ID`.
+    pub fn from_header_line(line: &str) -> Option<Self> {
+        let mut rg = ReadGroup::default();
+        let mut has_id = false;
+        for field in line.strip_prefix("@RG\t")?.split('\t') {
+            let Some((tag, value)) = field.split_once(':') else {
+                continue;
+            };
+            match tag {
+                "ID" => {
+                    rg.id = value.to_string();
+                    has_id = true;
+                }
+                "SM" => rg.sample = Some(value.to_string()),
+                "LB" => rg.library = Some(value.to_string()),
+                "PU" => rg.platform_unit = Some(value.to_string()),
+                _ => {}
+            }
+        }
+
+        has_id.then_some(rg)
+    }
+}
+
+/// Targets, read groups and programs of the input bam header.
+///
+/// Built once per run and shared by the worker threads behind an `Arc`, so
+/// modifiers can look up target metadata even when the record has no header.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ProcessContext {
+    target_names: Vec<String>,
+    chroms: Vec<Chrom<'static>>,
+    target_lens: Vec<u64>,
+    read_groups: Vec<ReadGroup>,
+    /// Index in `read_groups` by `ID`, to look up the `RG` tag of records.
+    read_group_idx: HashMap<String, usize>,
+    program_lines: Vec<String>,
+}
+
+impl ProcessContext {
+    pub fn from_header(header: &HeaderView) -> Self {
+        let target_names = header
+            .target_names()
+            .into_iter()
+            .map(|name| String::from_utf8_lossy(name).into_owned())
+            .collect::<Vec<_>>();
+        let chroms = target_names
+            .iter()
+            .map(|name| Chrom::from(name.as_str()).into_owned())
+            .collect();
+        let target_lens = (0..header.target_count())
+            .map(|tid| header.target_len(tid).unwrap_or(0))
+            .collect();
+
+        let text = String::from_utf8_lossy(header.as_bytes());
+        let mut read_groups = vec![];
+        let mut program_lines = vec![];
+        for line in text.lines() {
+            if let Some(rg) = ReadGroup::from_header_line(line) {
+                read_groups.push(rg);
+            } else if line.starts_with("@PG\t") {
+                program_lines.push(line.to_string());
+            }
+        }
+
+        let read_group_idx = read_groups
+            .iter()
+            .enumerate()
+            .map(|(i, rg)| (rg.id.clone(), i))
+            .collect();
+
+        Self {
+            target_names,
+            chroms,
+            target_lens,
+            read_groups,
+            read_group_idx,
+            program_lines,
+        }
+    }
+
+    pub fn n_targets(&self) -> usize {
+        self.target_names.len()
+    }
+
+    /// Name of target `tid` as written in the header. `None` for unmapped (-1)
+    /// or unknown tids.
+    pub fn target_name(&self, tid: i32) -> Option<&str> {
+        self.target_names
+            .get(usize::try_from(tid).ok()?)
+            .map(|s| s.as_str())
+    }
+
+    /// [`Chrom`] of target `tid`, normalized to the `chr` prefixed names.
+    pub fn chrom(&self, tid: i32) -> Option<&Chrom<'static>> {
+        self.chroms.get(usize::try_from(tid).ok()?)
+    }
+
+    /// Length of target `tid`.
+    pub fn target_len(&self, tid: i32) -> Option<u64> {
+        self.target_lens.get(usize::try_from(tid).ok()?).copied()
+    }
+
+    /// Tid of the target named `name` in the header.
+    pub fn tid(&self, name: &str) -> Option<i32> {
+        self.target_names
+            .iter()
+            .position(|n| n == name)
+            .map(|tid| tid as i32)
+    }
+
+    /// The `@RG` lines, in header order.
+    pub fn read_groups(&self) -> &[ReadGroup] {
+        &self.read_groups
+    }
+
+    /// Index in [`Self::read_groups`] of the `RG` tag of `record`, without
+    /// allocating. `None` if the record has no `RG` tag, or one not in the header.
+    pub fn read_group_idx(&self, record: &Record) -> Option<usize> {
+        match record.aux(b"RG") {
+            Ok(Aux::String(id)) => self.read_group_idx.get(id).copied(),
+            _ => None,
+        }
+    }
+
+    /// The `@PG` lines, without the newline.
+    pub fn program_lines(&self) -> &[String] {
+        &self.program_lines
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_htslib::bam::{Header, header::HeaderRecord};
+
+    use super::*;
+
+    #[test]
+    fn test_from_header() {
+        let mut header = Header::new();
+        header.push_record(
+            HeaderRecord::new(b"SQ")
+                .push_tag(b"SN", "1")
+                .push_tag(b"LN", 1000),
+        );
+        header.push_record(
+            HeaderRecord::new(b"SQ")
+                .push_tag(b"SN", "chrEBV")
+                .push_tag(b"LN", 171823),
+        );
+        header.push_record(
+            HeaderRecord::new(b"RG")
+                .push_tag(b"ID", "rg1")
+                .push_tag(b"SM", "sample"),
+        );
+        header.push_record(
+            HeaderRecord::new(b"RG")
+                .push_tag(b"ID", "rg2")
+                .push_tag(b"SM", "sample")
+                .push_tag(b"LB", "lib2")
+                .push_tag(b"PU", "FC1.2"),
+        );
+        header.push_record(
+            HeaderRecord::new(b"PG")
+                .push_tag(b"ID", "bwa")
+                .push_tag(b"PN", "bwa"),
+        );
+        let ctx = ProcessContext::from_header(&HeaderView::from_header(&header));
+
+        assert_eq!(ctx.n_targets(), 2);
+        assert_eq!(ctx.target_name(0), Some("1"));
+        assert_eq!(ctx.chrom(0), Some(&Chrom::Chr1));
+        assert_eq!(ctx.target_len(1), Some(171823));
+        assert_eq!(ctx.tid("chrEBV"), Some(1));
+        assert_eq!(ctx.target_name(-1), None);
+        assert_eq!(ctx.target_len(2), None);
+        assert_eq!(
+            ctx.read_groups(),
+            &[
+                ReadGroup {
+                    id: "rg1".to_string(),
+                    sample: Some("sample".to_string()),
+                    ..Default::default()
+                },
+                ReadGroup {
+                    id: "rg2".to_string(),
+                    sample: Some("sample".to_string()),
+                    library: Some("lib2".to_string()),
+                    platform_unit: Some("FC1.2".to_string()),
+                },
+            ]
+        );
+        assert_eq!(ctx.program_lines(), &["@PG\tID:bwa\tPN:bwa".to_string()]);
+
+        let mut record = Record::new();
+        assert_eq!(ctx.read_group_idx(&record), None);
+        record.push_aux(b"RG", Aux::String("rg2")).unwrap();
+        assert_eq!(ctx.read_group_idx(&record), Some(1));
+        record.remove_aux(b"RG").unwrap();
+        record.push_aux(b"RG", Aux::String("rg3")).unwrap();
+        assert_eq!(ctx.read_group_idx(&record), None);
+    }
+
+    #[test]
+    fn test_read_group_from_header_line() {
+        assert_eq!(
+            ReadGroup::from_header_line("@RG\tID:a\tSM:s1\tPL:ILLUMINA\tDS:x:y"),
+            Some(ReadGroup {
+                id: "a".to_string(),
+                sample: Some("s1".to_string()),
+                ..Default::default()
+            })
+        );
+        assert_eq!(ReadGroup::from_header_line("@RG\tSM:s1"), None);
+        assert_eq!(ReadGroup::from_header_line("@PG\tID:bwa"), None);
+    }
+}
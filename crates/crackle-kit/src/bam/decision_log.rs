@@ -0,0 +1,367 @@
+//! Decision logs of [`ParallelBamProcessor`], to tell after a run why a read
+//! was dropped or how it was modified.
+//!
+//! A log is a bgzf-compressed TSV with the header line
+//! `qname flag tid pos outcome reason`, then a row per record the
+//! [`RecordModifier`] did not plainly keep, in the order of the input, e.g.
+//! `read7 0 0 1006 dropped odd position`, tab-separated. `tid` and `pos` are
+//! those of the record, 0-based, `-1` when unmapped.
+
+use std::{
+    io::{BufRead, BufReader},
+    path::{Path, PathBuf},
+    str::FromStr,
+    thread::{self, JoinHandle},
+};
+
+use anyhow::{anyhow, bail};
+use crossbeam_channel::{Receiver, Sender, bounded};
+use rust_htslib::{bam::Record, bgzf};
+
+#[cfg(doc)]
+use crate::bam::process::{ParallelBamProcessor, RecordModifier};
+use crate::{errors::Error, table::TableWriter};
+
+const HEADER: [&str; 6] = ["qname", "flag", "tid", "pos", "outcome", "reason"];
+
+/// What happened to a logged record.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Outcome {
+    /// The modifier returned `Ok(None)`, or the read filter of the run dropped
+    /// the record before it reached the modifier.
+    Dropped,
+    /// The modifier failed on the record, which was not written. The reason is
+    /// the error.
+    Failed,
+    /// The modifier kept the record and gave a reason, see
+    /// [`RecordModifier::modify_record_with_reason`].
+    Modified,
+}
+
+impl Outcome {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Dropped => "dropped",
+            Self::Failed => "failed",
+            Self::Modified => "modified",
+        }
+    }
+}
+
+impl FromStr for Outcome {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "dropped" => Ok(Self::Dropped),
+            "failed" => Ok(Self::Failed),
+            "modified" => Ok(Self::Modified),
+            _ => bail!("Unknown outcome {:?}", s),
+        }
+    }
+}
+
+/// A row of a decision log.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Decision {
+    pub qname: String,
+    pub flag: u16,
+    /// `-1` for unmapped reads.
+    pub tid: i32,
+    /// 0-based, `-1` for unmapped reads.
+    pub pos: i64,
+    pub outcome: Outcome,
+    /// Empty if none was given.
+    pub reason: String,
+}
+
+impl Decision {
+    /// The decision of `outcome` on `record`. Tabs and line breaks of `reason`
+    /// become spaces.
+    pub(crate) fn new(record: &Record, outcome: Outcome, reason: &str) -> Self {
+        Self {
+            qname: String::from_utf8_lossy(record.qname()).into_owned(),
+            flag: record.flags(),
+            tid: record.tid(),
+            pos: record.pos(),
+            outcome,
+            reason: reason.replace(['\t', '\n', '\r'], " "),
+        }
+    }
+}
+
+/// The thread writing the decisions of a run to its log, so that the workers
+/// only send them. Decisions are sent a batch of records at a time, in order.
+pub(crate) struct DecisionLogThread {
+    tx: Sender<Vec<Decision>>,
+    handle: JoinHandle<Result<(), Error>>,
+}
+
+impl DecisionLogThread {
+    /// Start writing a log to `path`, buffering up to `capacity` batches.
+    pub(crate) fn spawn(path: &Path, capacity: usize) -> Self {
+        let (tx, rx) = bounded(capacity);
+        let path = path.to_path_buf();
+        let handle = thread::spawn(move || write_decision_log(&path, rx));
+        Self { tx, handle }
+    }
+
+    pub(crate) fn send(&self, decisions: Vec<Decision>) -> Result<(), anyhow::Error> {
+        // the thread only stops early on an error, returned by `finish`.
+        self.tx
+            .send(decisions)
+            .map_err(|_| anyhow!("The decision log stopped"))
+    }
+
+    /// Wait for the decisions sent to be written.
+    pub(crate) fn finish(self) -> Result<(), Error> {
+        drop(self.tx);
+        self.handle
+            .join()
+            .map_err(|_| anyhow!("The decision log thread panicked"))?
+    }
+}
+
+fn write_decision_log(path: &Path, rx: Receiver<Vec<Decision>>) -> Result<(), Error> {
+    let mut log = DecisionLogWriter::create(path)?;
+    for decisions in rx {
+        for decision in &decisions {
+            log.write(decision)?;
+        }
+    }
+    log.finish()
+}
+
+/// Writer of a decision log.
+pub(crate) struct DecisionLogWriter {
+    table: TableWriter<bgzf::Writer>,
+    path: PathBuf,
+}
+
+impl DecisionLogWriter {
+    pub(crate) fn create(path: &Path) -> Result<Self, Error> {
+        let writer = bgzf::Writer::from_path(path)?;
+        Ok(Self {
+            table: TableWriter::new(writer, &HEADER)?,
+            path: path.to_path_buf(),
+        })
+    }
+
+    pub(crate) fn write(&mut self, decision: &Decision) -> Result<(), Error> {
+        let Decision {
+            qname,
+            flag,
+            tid,
+            pos,
+            outcome,
+            reason,
+        } = decision;
+        self.table
+            .write_row([
+                qname.as_str(),
+                flag.to_string().as_str(),
+                tid.to_string().as_str(),
+                pos.to_string().as_str(),
+                outcome.as_str(),
+                reason.as_str(),
+            ])
+            .map_err(|err| err.context(format!("Failed to write {}", self.path.display())))?;
+        Ok(())
+    }
+
+    /// Flush the log, whose bgzf stream is ended when dropped.
+    pub(crate) fn finish(self) -> Result<(), Error> {
+        self.table
+            .into_inner()
+            .map_err(|err| err.context(format!("Failed to write {}", self.path.display())))?;
+        Ok(())
+    }
+}
+
+/// Rows of a decision log written by [`ParallelBamProcessor`].
+pub struct DecisionLogReader {
+    reader: BufReader<bgzf::Reader>,
+    path: PathBuf,
+    line: String,
+    line_no: usize,
+}
+
+impl DecisionLogReader {
+    /// Open the log at `path`, failing unless it starts with the header of a
+    /// decision log.
+    pub fn from_path(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let path = path.as_ref();
+        let mut reader = Self {
+            reader: BufReader::new(bgzf::Reader::from_path(path)?),
+            path: path.to_path_buf(),
+            line: String::new(),
+            line_no: 0,
+        };
+        let header = HEADER.join("\t");
+        if reader.next_line()? != Some(header.as_str()) {
+            return Err(reader.parse_error(anyhow!("Expected the header {:?}", header)));
+        }
+        Ok(reader)
+    }
+
+    fn next_line(&mut self) -> Result<Option<&str>, Error> {
+        self.line.clear();
+        let n = self
+            .reader
+            .read_line(&mut self.line)
+            .map_err(|err| Error::io("read", &self.path, err))?;
+        if n == 0 {
+            return Ok(None);
+        }
+        self.line_no += 1;
+        Ok(Some(self.line.trim_end_matches(['\n', '\r'])))
+    }
+
+    fn parse_error(&self, source: anyhow::Error) -> Error {
+        Error::Parse {
+            what: "decision log row".to_string(),
+            path: self.path.clone(),
+            line: self.line_no,
+            source,
+        }
+    }
+
+    fn parse(line: &str) -> Result<Decision, anyhow::Error> {
+        let fields = line.split('\t').collect::<Vec<_>>();
+        let [qname, flag, tid, pos, outcome, reason] = fields[..] else {
+            bail!("Expected {} fields, got {}", HEADER.len(), fields.len());
+        };
+        Ok(Decision {
+            qname: qname.to_string(),
+            flag: flag.parse()?,
+            tid: tid.parse()?,
+            pos: pos.parse()?,
+            outcome: outcome.parse()?,
+            reason: reason.to_string(),
+        })
+    }
+}
+
+impl Iterator for DecisionLogReader {
+    type Item = Result<Decision, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let line = match self.next_line() {
+            Ok(line) => line?,
+            Err(err) => return Some(Err(err)),
+        };
+        Some(Self::parse(line).map_err(|err| self.parse_error(err)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        bam::{
+            context::ProcessContext,
+            process::{ParallelBamProcessor, ProcessBamOptions, RecordModifier},
+        },
+        test_utils::TestBam,
+    };
+
+    /// Drops the reads at odd positions, fails at 1_004, and marks the others at
+    /// multiples of 10.
+    struct DropOdd;
+
+    impl RecordModifier for DropOdd {
+        type Error = anyhow::Error;
+
+        fn modify_record(&self, record: &mut Record) -> Result<Option<()>, Self::Error> {
+            Ok(self
+                .modify_record_with_reason(&ProcessContext::default(), record)?
+                .0)
+        }
+
+        fn modify_record_with_reason(
+            &self,
+            _ctx: &ProcessContext,
+            record: &mut Record,
+        ) -> Result<(Option<()>, Option<String>), Self::Error> {
+            match record.pos() {
+                1_004 => bail!("bad\tread"),
+                pos if pos % 2 == 1 => Ok((None, Some("odd position".to_string()))),
+                pos if pos % 10 == 0 => {
+                    record.set_mapq(0);
+                    Ok((Some(()), Some("mapq reset".to_string())))
+                }
+                _ => Ok((Some(()), None)),
+            }
+        }
+    }
+
+    #[test]
+    fn test_decision_log() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = tempfile::tempdir()?;
+        let bam_path = TestBam::new()
+            .add_reads("chr1", 1_000, 1, 40, 10)
+            .build(dir.path())?;
+        let out_path = dir.path().join("out.bam");
+        let opts = ProcessBamOptions {
+            batch_size: 8,
+            ..Default::default()
+        };
+
+        let mut logs = vec![];
+        for sequential in [false, true] {
+            let log_path = dir.path().join(format!("decisions{}.tsv.gz", sequential));
+            let processor = ParallelBamProcessor::new(DropOdd).with_decision_log(&log_path);
+            let stats = match sequential {
+                false => processor.process_bam(&bam_path, &out_path, &opts)?,
+                true => processor.process_bam_sequential(&bam_path, &out_path, &opts)?,
+            };
+            assert_eq!((stats.records_dropped, stats.records_failed), (20, 1));
+
+            let decisions =
+                DecisionLogReader::from_path(&log_path)?.collect::<Result<Vec<_>, _>>()?;
+            let n_dropped = decisions
+                .iter()
+                .filter(|d| d.outcome == Outcome::Dropped)
+                .count();
+            assert_eq!(n_dropped as u64, stats.records_dropped);
+            // 1_000, 1_010, 1_020 and 1_030 are modified.
+            assert_eq!(decisions.len(), 20 + 1 + 4);
+            logs.push(decisions);
+        }
+        assert_eq!(logs[0], logs[1]);
+
+        let decisions = &logs[0];
+        assert_eq!(
+            decisions[0],
+            Decision {
+                qname: "read0".to_string(),
+                flag: 0,
+                tid: 0,
+                pos: 1_000,
+                outcome: Outcome::Modified,
+                reason: "mapq reset".to_string(),
+            }
+        );
+        assert_eq!(
+            (decisions[1].qname.as_str(), decisions[1].pos),
+            ("read1", 1_001)
+        );
+        assert_eq!(decisions[1].reason, "odd position");
+        // the tab of the error is replaced.
+        let failed = &decisions[3];
+        assert_eq!((failed.pos, failed.outcome), (1_004, Outcome::Failed));
+        assert!(failed.reason.contains("bad read"), "{}", failed.reason);
+        assert!(decisions.is_sorted_by_key(|d| d.pos));
+
+        let not_a_log = dir.path().join("not_a_log.gz");
+        let mut writer = bgzf::Writer::from_path(&not_a_log)?;
+        std::io::Write::write_all(&mut writer, b"a\tb\n")?;
+        drop(writer);
+        assert!(matches!(
+            DecisionLogReader::from_path(&not_a_log),
+            Err(Error::Parse { line: 1, .. })
+        ));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,207 @@
+//! Run a task per contig or per region of an indexed bam, in parallel.
+
+use std::path::Path;
+
+use anyhow::Error;
+use rayon::{
+    ThreadPoolBuilder,
+    iter::{IntoParallelIterator, ParallelIterator},
+};
+use rust_htslib::bam::{IndexedReader, Read as _, Record};
+
+use crate::{
+    bam::{filter::ReadFilter, reader::plan_region_fetches},
+    data::locus::GenomeRegion,
+};
+
+/// Run `f` once per contig of the bam header, each call with its own reader
+/// fetched to the whole contig.
+///
+/// Returns `(contig name, output)` in header order. An error of `f` is returned
+/// as a [`WorkerFailed`](crate::Error::WorkerFailed) on the contig.
+pub fn par_map_contigs<T, F>(
+    bam_path: impl AsRef<Path>,
+    n_threads: usize,
+    f: F,
+) -> Result<Vec<(String, T)>, crate::Error>
+where
+    T: Send,
+    F: Fn(&str, &mut IndexedReader) -> Result<T, Error> + Sync,
+{
+    let bam_path = bam_path.as_ref();
+
+    let contigs = {
+        let reader = IndexedReader::from_path(bam_path)?;
+        let header = reader.header();
+        (0..header.target_count())
+            .map(|tid| String::from_utf8_lossy(header.tid2name(tid)).into_owned())
+            .collect::<Vec<_>>()
+    };
+
+    let outputs = par_map(n_threads, contigs.iter().collect(), |contig| {
+        let mut reader = IndexedReader::from_path(bam_path)?;
+        reader.fetch(contig.as_str())?;
+        f(contig, &mut reader).map_err(|err| worker_failed(format!("contig {contig}"), err))
+    })?;
+
+    Ok(contigs.into_iter().zip(outputs).collect())
+}
+
+/// Run `f` once per region, each call with its own reader fetched to the region.
+///
+/// Returns outputs in region order. Reads overlapping several regions are seen
+/// by each of them. Errors are as of [`par_map_contigs`], on the region.
+pub fn par_map_regions<'a, T, F>(
+    bam_path: impl AsRef<Path>,
+    regions: &[GenomeRegion<'a>],
+    n_threads: usize,
+    f: F,
+) -> Result<Vec<T>, crate::Error>
+where
+    T: Send,
+    F: Fn(&GenomeRegion<'a>, &mut IndexedReader) -> Result<T, Error> + Sync,
+{
+    let bam_path = bam_path.as_ref();
+
+    let outputs = par_map(n_threads, regions.iter().collect(), |region| {
+        let mut reader = IndexedReader::from_path(bam_path)?;
+        region.fetch_in(&mut reader)?;
+        f(region, &mut reader).map_err(|err| worker_failed(format!("region {region}"), err))
+    })?;
+
+    Ok(outputs)
+}
+
+/// [`records_in_regions`](crate::bam::reader::records_in_regions), with the
+/// merged regions fetched in parallel on `n_threads`, each by its own reader,
+/// and every record passed to `f` in no particular order.
+///
+/// A record overlapping several merged regions is still passed once, by the
+/// first of them. Errors are as of [`par_map_regions`], on the merged region.
+pub fn records_in_regions_par(
+    bam_path: impl AsRef<Path>,
+    regions: &[GenomeRegion<'_>],
+    filter: Option<&ReadFilter>,
+    n_threads: usize,
+    f: impl Fn(Record) + Send + Sync,
+) -> Result<(), crate::Error> {
+    let bam_path = bam_path.as_ref();
+    let fetches = {
+        let reader = IndexedReader::from_path(bam_path)?;
+        plan_region_fetches(reader.header(), regions)?
+    };
+
+    par_map(n_threads, fetches.iter().collect(), |fetch| {
+        let mut reader = IndexedReader::from_path(bam_path)?;
+        fetch.fetch_in(&mut reader)?;
+        for record in reader.records() {
+            let record = record
+                .map_err(|err| worker_failed(format!("region {}", fetch.region), err.into()))?;
+            if fetch.yields(filter, &record) {
+                f(record);
+            }
+        }
+        Ok(())
+    })?;
+
+    Ok(())
+}
+
+fn worker_failed(item: String, source: Error) -> Error {
+    crate::Error::WorkerFailed { item, source }.into()
+}
+
+fn par_map<I, T>(
+    n_threads: usize,
+    items: Vec<I>,
+    f: impl Fn(I) -> Result<T, Error> + Sync,
+) -> Result<Vec<T>, Error>
+where
+    I: Send,
+    T: Send,
+{
+    if items.is_empty() {
+        return Ok(vec![]);
+    }
+    let tp = ThreadPoolBuilder::new().num_threads(n_threads).build()?;
+
+    tp.install(|| items.into_par_iter().map(&f).collect::<Result<Vec<_>, Error>>())
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_htslib::bam::Read as _;
+
+    use super::*;
+    use crate::{bam::reader::records_in_regions, test_utils::TestBam};
+
+    #[test]
+    fn test_par_map_contigs_and_regions() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = tempfile::tempdir()?;
+        let bam_path = TestBam::new()
+            .add_reads("chr1", 0, 10, 30, 5)
+            .add_reads("chr2", 0, 10, 7, 5)
+            .contig("chr3", 1000)
+            .build(dir.path())?;
+
+        let counts = par_map_contigs(&bam_path, 2, |_, reader| Ok(reader.records().count()))?;
+        assert_eq!(
+            counts,
+            vec![
+                ("chr1".to_string(), 30),
+                ("chr2".to_string(), 7),
+                ("chr3".to_string(), 0)
+            ]
+        );
+
+        let regions = vec![
+            GenomeRegion::from(("chr1", 1, 100)),
+            GenomeRegion::from(("chr2", 21, 30)),
+        ];
+        let counts = par_map_regions(&bam_path, &regions, 2, |_, reader| {
+            Ok(reader.records().count())
+        })?;
+        // chr1 reads at 0..=90 overlap [0, 100); only the chr2 read at 20 overlaps [20, 30).
+        assert_eq!(counts, vec![10, 1]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_records_in_regions_par() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = tempfile::tempdir()?;
+        let bam_path = TestBam::new()
+            .add_reads("chr1", 0, 7, 1_000, 50)
+            .add_reads("chr2", 0, 20, 50, 50)
+            .build(dir.path())?;
+        // chr1 in pieces, with the gaps between them shorter than the reads.
+        let mut regions = (0..50)
+            .map(|i| GenomeRegion::from(("chr1", 1 + i * 130, 100 + i * 130)))
+            .collect::<Vec<_>>();
+        regions.push(GenomeRegion::from(("chr1", 50, 3_000)));
+        regions.push(GenomeRegion::from(("chr2", 1, 1_000)));
+        let filter = ReadFilter::default().with_min_mapq(10);
+
+        let qnames = std::sync::Mutex::new(vec![]);
+        records_in_regions_par(&bam_path, &regions, Some(&filter), 4, |record| {
+            qnames.lock().unwrap().push(record.qname().to_vec());
+        })?;
+        let mut qnames = qnames.into_inner().unwrap();
+        qnames.sort();
+
+        let mut expected = records_in_regions(&bam_path, &regions, Some(&filter))?
+            .map(|record| record.map(|r| r.qname().to_vec()))
+            .collect::<Result<Vec<_>, _>>()?;
+        expected.sort();
+        // no read is passed twice.
+        let n_expected = expected.len();
+        expected.dedup();
+        assert_eq!(expected.len(), n_expected);
+        assert_eq!(qnames, expected);
+        // the chr1 reads starting up to the end of the last piece, 6_470, and
+        // every read of chr2.
+        assert_eq!(qnames.len(), 925 + 50);
+
+        Ok(())
+    }
+}
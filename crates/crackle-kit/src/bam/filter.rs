@@ -0,0 +1,196 @@
+//! Read-level filters shared by the bam analyses.
+
+use std::{collections::HashSet, sync::Arc};
+
+use rust_htslib::bam::Record;
+
+use crate::{
+    bam::{context::ProcessContext, record_view::RecordView},
+    data::{chrom::Chrom, interval::RegionSet, locus::GenomeRegion},
+};
+
+pub const FLAG_UNMAPPED: u16 = 0x4;
+pub const FLAG_REVERSE: u16 = 0x10;
+pub const FLAG_SECONDARY: u16 = 0x100;
+pub const FLAG_QC_FAIL: u16 = 0x200;
+pub const FLAG_DUPLICATE: u16 = 0x400;
+pub const FLAG_SUPPLEMENTARY: u16 = 0x800;
+
+/// Standard read filter: excludes reads having any of `exclude_flags` or a mapping
+/// quality below `min_mapq`, and, if set, reads aligned outside of `regions` or of
+/// a read group not in `read_groups`.
+///
+/// The default drops unmapped, secondary, qc-failed, duplicate and supplementary
+/// reads, and keeps any mapping quality and position.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReadFilter {
+    pub exclude_flags: u16,
+    pub min_mapq: u8,
+    /// Keep only reads overlapping one of these. Shared, as filters are cloned
+    /// per thread.
+    pub regions: Option<Arc<RegionSet>>,
+    /// Keep only reads with one of these `RG` tags.
+    pub read_groups: Option<Arc<HashSet<String>>>,
+}
+
+impl Default for ReadFilter {
+    fn default() -> Self {
+        Self {
+            exclude_flags: FLAG_UNMAPPED
+                | FLAG_SECONDARY
+                | FLAG_QC_FAIL
+                | FLAG_DUPLICATE
+                | FLAG_SUPPLEMENTARY,
+            min_mapq: 0,
+            regions: None,
+            read_groups: None,
+        }
+    }
+}
+
+impl ReadFilter {
+    /// A filter which passes every read.
+    pub fn pass_all() -> Self {
+        Self {
+            exclude_flags: 0,
+            min_mapq: 0,
+            regions: None,
+            read_groups: None,
+        }
+    }
+
+    pub fn with_min_mapq(mut self, min_mapq: u8) -> Self {
+        self.min_mapq = min_mapq;
+        self
+    }
+
+    /// Keep only reads whose alignment overlaps one of `regions`.
+    pub fn within_regions(mut self, regions: RegionSet) -> Self {
+        self.regions = Some(Arc::new(regions));
+        self
+    }
+
+    /// Keep only reads whose `RG` tag is in `allow`. Reads without one are dropped.
+    pub fn read_groups(mut self, allow: HashSet<String>) -> Self {
+        self.read_groups = Some(Arc::new(allow));
+        self
+    }
+
+    /// Whether the record passes the flag, mapping quality and read group filters.
+    ///
+    /// `regions` are not looked at, as a record does not know the name of its
+    /// contig; see [`Self::passes_on`].
+    pub fn passes<R: RecordView + ?Sized>(&self, record: &R) -> bool {
+        record.flags() & self.exclude_flags == 0
+            && record.mapq() >= self.min_mapq
+            && self.passes_read_group(record)
+    }
+
+    fn passes_read_group<R: RecordView + ?Sized>(&self, record: &R) -> bool {
+        let Some(allow) = &self.read_groups else {
+            return true;
+        };
+        record.aux_str(b"RG").is_some_and(|id| allow.contains(id))
+    }
+
+    /// Whether the record, aligned on `contig`, passes all the filters.
+    pub fn passes_on<R: RecordView + ?Sized>(&self, contig: &str, record: &R) -> bool {
+        if !self.passes(record) {
+            return false;
+        }
+
+        let Some(regions) = &self.regions else {
+            return true;
+        };
+        let start = record.pos() + 1;
+        let aligned = GenomeRegion {
+            contig: Chrom::from(contig),
+            start,
+            end: start + (record.reference_span() - 1).max(0),
+        };
+        regions.overlapping(&aligned).next().is_some()
+    }
+
+    /// [`Self::passes_on`] the contig of the record in `ctx`. Unplaced records
+    /// fail if `regions` are set.
+    pub fn passes_ctx(&self, ctx: &ProcessContext, record: &Record) -> bool {
+        match ctx.target_name(record.tid()) {
+            Some(contig) => self.passes_on(contig, record),
+            None => self.regions.is_none() && self.passes(record),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_htslib::bam::record::{Aux, CigarString};
+
+    use super::*;
+
+    fn record(flags: u16, mapq: u8) -> Record {
+        let mut r = Record::new();
+        r.set_flags(flags);
+        r.set_mapq(mapq);
+        r
+    }
+
+    #[test]
+    fn test_default_filter() {
+        let f = ReadFilter::default();
+
+        assert!(f.passes(&record(0x1 | 0x2 | 0x40, 0)));
+        assert!(!f.passes(&record(FLAG_DUPLICATE, 60)));
+        assert!(!f.passes(&record(FLAG_SECONDARY, 60)));
+        assert!(!f.passes(&record(FLAG_SUPPLEMENTARY, 60)));
+        assert!(!f.passes(&record(FLAG_UNMAPPED, 60)));
+
+        let f = f.with_min_mapq(20);
+        assert!(!f.passes(&record(0, 19)));
+        assert!(f.passes(&record(0, 20)));
+
+        assert!(ReadFilter::pass_all().passes(&record(FLAG_DUPLICATE, 0)));
+    }
+
+    #[test]
+    fn test_within_regions() {
+        let f = ReadFilter::default().within_regions(RegionSet::new(
+            [GenomeRegion::from(("chr1", 101, 200))],
+            false,
+        ));
+        let at = |pos: i64, cigar: &str| {
+            let cigar = CigarString::try_from(cigar).unwrap();
+            let mut r = record(0, 60);
+            r.set(b"r", Some(&cigar), &[b'A'; 50], &[30; 50]);
+            r.set_pos(pos);
+            r
+        };
+
+        // 0-based 51..=100 ends on the first base of the region.
+        assert!(f.passes_on("chr1", &at(51, "50M")));
+        assert!(!f.passes_on("chr1", &at(50, "50M")));
+        // a deletion makes the alignment reach the region.
+        assert!(f.passes_on("chr1", &at(50, "25M1D25M")));
+        assert!(f.passes_on("1", &at(150, "50M")));
+        assert!(!f.passes_on("chr2", &at(150, "50M")));
+        assert!(!f.passes_on("chr1", &at(200, "50M")));
+        // regions are not looked at without the contig.
+        assert!(f.passes(&at(1_000, "50M")));
+    }
+
+    #[test]
+    fn test_read_groups() {
+        let f = ReadFilter::pass_all().read_groups(HashSet::from(["rg1".to_string()]));
+        let in_group = |rg: Option<&str>| {
+            let mut r = record(0, 60);
+            if let Some(rg) = rg {
+                r.push_aux(b"RG", Aux::String(rg)).unwrap();
+            }
+            r
+        };
+
+        assert!(f.passes(&in_group(Some("rg1"))));
+        assert!(!f.passes(&in_group(Some("rg2"))));
+        assert!(!f.passes(&in_group(None)));
+        assert!(ReadFilter::pass_all().passes(&in_group(None)));
+    }
+}
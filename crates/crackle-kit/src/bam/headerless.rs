@@ -0,0 +1,191 @@
+//! Records sent between threads without their header.
+//!
+//! A [`Record`] read from a file holds an `Rc` of the reader's [`HeaderView`],
+//! which must not be cloned or dropped on another thread. A [`HeaderlessRecord`]
+//! never holds one outside of a [`RecordGuard`], which each worker makes with its
+//! own header.
+
+use std::{
+    ops::{Deref, DerefMut},
+    rc::Rc,
+};
+
+use rust_htslib::{
+    bam::{self, HeaderView, Record},
+    errors::Error as HtslibError,
+};
+
+/// A [`Record`] without a header, safe to send to other threads.
+///
+/// Derefs to the record for read-only use; methods needing the header, e.g. the
+/// contig name of the record, are used through [`Self::with_header`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct HeaderlessRecord {
+    record: Record,
+}
+
+impl HeaderlessRecord {
+    /// An empty record.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Remove the header of `record`.
+    pub fn strip(mut record: Record) -> Self {
+        record.remove_header();
+        Self { record }
+    }
+
+    /// Read the next record of `reader` into this one, as [`bam::Read::read`],
+    /// without its header.
+    pub fn read_from(&mut self, reader: &mut impl bam::Read) -> Option<Result<(), HtslibError>> {
+        let res = reader.read(&mut self.record);
+        self.record.remove_header();
+        res
+    }
+
+    /// The record with `header` attached until the guard is dropped.
+    pub fn with_header(&mut self, header: Rc<HeaderView>) -> RecordGuard<'_> {
+        self.record.set_header(header);
+        RecordGuard {
+            record: &mut self.record,
+        }
+    }
+
+    pub fn into_inner(self) -> Record {
+        self.record
+    }
+}
+
+impl Deref for HeaderlessRecord {
+    type Target = Record;
+
+    fn deref(&self) -> &Record {
+        &self.record
+    }
+}
+
+/// A [`HeaderlessRecord`] with a header, removed again when dropped, even if
+/// the record was replaced or given another header.
+pub struct RecordGuard<'a> {
+    record: &'a mut Record,
+}
+
+impl Deref for RecordGuard<'_> {
+    type Target = Record;
+
+    fn deref(&self) -> &Record {
+        self.record
+    }
+}
+
+impl DerefMut for RecordGuard<'_> {
+    fn deref_mut(&mut self) -> &mut Record {
+        self.record
+    }
+}
+
+impl Drop for RecordGuard<'_> {
+    fn drop(&mut self) {
+        self.record.remove_header();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Error;
+    use rust_htslib::bam::{Read as _, Reader, record::Aux};
+
+    use super::*;
+    use crate::{
+        bam::process::{ParallelBamProcessor, ProcessBamOptions, RecordModifier},
+        test_utils::TestBam,
+    };
+
+    #[test]
+    fn test_record_guard() -> Result<(), Error> {
+        let dir = tempfile::tempdir()?;
+        let bam_path = TestBam::new()
+            .contig("chr1", 1_000)
+            .add_reads("chr1", 100, 10, 2, 50)
+            .build(dir.path())?;
+        let mut reader = Reader::from_path(&bam_path)?;
+        let header = Rc::new(reader.header().clone());
+
+        let mut record = HeaderlessRecord::new();
+        record.read_from(&mut reader).unwrap()?;
+        assert_eq!((record.qname(), record.pos()), (&b"read0"[..], 100));
+        {
+            let mut guard = record.with_header(Rc::clone(&header));
+            assert_eq!(Rc::strong_count(&header), 2);
+            guard.push_aux(b"XA", Aux::I32(7))?;
+        }
+        assert_eq!(Rc::strong_count(&header), 1);
+        assert_eq!(record.aux(b"XA")?, Aux::I32(7));
+
+        // a header set through the guard is removed too.
+        {
+            let mut guard = record.with_header(Rc::clone(&header));
+            *guard = Record::new();
+            guard.set_header(Rc::clone(&header));
+        }
+        assert_eq!(Rc::strong_count(&header), 1);
+        assert_eq!(record.into_inner(), Record::new());
+
+        Ok(())
+    }
+
+    /// Tags reads with their name and position.
+    struct NameTag;
+
+    impl RecordModifier for NameTag {
+        type Error = Error;
+
+        fn modify_record(&self, record: &mut Record) -> Result<Option<()>, Self::Error> {
+            let tag = format!(
+                "{}@{}",
+                String::from_utf8_lossy(record.qname()),
+                record.pos()
+            );
+            record.push_aux(b"XN", Aux::String(&tag))?;
+            Ok(Some(()))
+        }
+    }
+
+    #[test]
+    fn test_process_bam_accessors() -> Result<(), Error> {
+        let dir = tempfile::tempdir()?;
+        let bam_path = TestBam::new()
+            .contig("chr1", 10_000)
+            .contig("chr2", 10_000)
+            .add_reads("chr1", 0, 10, 500, 50)
+            .add_reads("chr2", 0, 10, 500, 50)
+            .build(dir.path())?;
+        let out_path = dir.path().join("out.bam");
+
+        ParallelBamProcessor::new(NameTag).process_bam(
+            &bam_path,
+            &out_path,
+            &ProcessBamOptions {
+                worker_threads: 4,
+                batch_size: 64,
+                ..Default::default()
+            },
+        )?;
+
+        let mut n_records = 0;
+        for record in Reader::from_path(&out_path)?.records() {
+            let record = record?;
+            let tag = format!(
+                "{}@{}",
+                String::from_utf8_lossy(record.qname()),
+                record.pos()
+            );
+            assert_eq!(record.aux(b"XN")?, Aux::String(&tag));
+            n_records += 1;
+        }
+        assert_eq!(n_records, 1_000);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,698 @@
+//! Several bams processed as one by
+//! [`ParallelBamProcessor::process_bams_merged`], concatenated or merged by
+//! coordinate.
+
+use std::{
+    cmp::Reverse,
+    collections::{BinaryHeap, HashMap, HashSet},
+    path::{Path, PathBuf},
+    sync::atomic,
+    thread, vec,
+};
+
+use anyhow::{Context, Error, anyhow, bail};
+use crossbeam_channel::{Receiver, Sender, bounded};
+use rust_htslib::bam::{self, HeaderView, Read as _, Record, record::Aux};
+use tracing::{Level, event, span};
+
+use crate::{
+    bam::{
+        context::{ProcessContext, ReadGroup},
+        headerless::HeaderlessRecord,
+        output::OutputTarget,
+        process::{
+            ParallelBamProcessor, PipelineControl, ProcessBamOptions, ProcessStats, RecordModifier,
+            budgeted_stage_threads,
+        },
+    },
+    data::seq_dict::SeqDict,
+    utils::instrument::{current_dispatch, enter_on_thread},
+};
+
+/// Batches read ahead by the thread of each input.
+const READ_AHEAD_BATCHES: usize = 4;
+
+/// Order of the records of [`ParallelBamProcessor::process_bams_merged`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum MergeOrder {
+    /// Every record of each input in turn, in the order of the inputs.
+    #[default]
+    Concatenate,
+    /// The records of inputs sorted by coordinate, merged by coordinate so that
+    /// the output is sorted too. Records at the same position are in the order
+    /// of their inputs.
+    Coordinate,
+}
+
+impl<R: RecordModifier> ParallelBamProcessor<R> {
+    /// Like [`ParallelBamProcessor::process_bam`], with the records of several
+    /// bams in the order set by `merge`, written as one.
+    ///
+    /// The inputs must have the same contigs, of the same lengths, and with
+    /// [`MergeOrder::Coordinate`] in the same order. Records are written with
+    /// the contigs of the first input.
+    ///
+    /// The `@RG` lines of all inputs are in the output header, those of the
+    /// same line once. A read group whose `ID` is taken by another line is
+    /// renamed `{ID}.{n}`, the first `n` free, and so is the `RG` tag of its
+    /// records. Other lines are those of the first input, with the `@PG` of
+    /// other `ID`s and the `@CO` lines of the others. Concatenated inputs are
+    /// written as unsorted.
+    ///
+    /// Each input is read ahead on a thread of its own, with a share of the
+    /// [`ProcessBamOptions::read_threads`]. No index is needed.
+    pub fn process_bams_merged(
+        &self,
+        inputs: &[PathBuf],
+        out_bam_path: impl AsRef<Path>,
+        opts: &ProcessBamOptions,
+        merge: MergeOrder,
+    ) -> Result<ProcessStats, crate::Error> {
+        self.process_bams_merged_to(inputs, &out_bam_path.as_ref().into(), opts, merge)
+    }
+
+    /// [`Self::process_bams_merged`], writing to `output`, e.g. a file per
+    /// contig. Only inputs merged by coordinate give files to index.
+    pub fn process_bams_merged_to(
+        &self,
+        inputs: &[PathBuf],
+        output: &OutputTarget,
+        opts: &ProcessBamOptions,
+        merge: MergeOrder,
+    ) -> Result<ProcessStats, crate::Error> {
+        Ok(self.process_bams_merged_with_control(
+            inputs,
+            output,
+            opts,
+            merge,
+            &PipelineControl::default(),
+        )?)
+    }
+
+    fn process_bams_merged_with_control(
+        &self,
+        inputs: &[PathBuf],
+        output: &OutputTarget,
+        opts: &ProcessBamOptions,
+        merge: MergeOrder,
+        control: &PipelineControl,
+    ) -> Result<ProcessStats, Error> {
+        if inputs.is_empty() {
+            bail!("No bam to merge");
+        }
+        if merge == MergeOrder::Concatenate
+            && inputs.len() > 1
+            && matches!(output, OutputTarget::PerContig { index: true, .. })
+        {
+            bail!("Concatenated bams can not be indexed, as they are not sorted");
+        }
+        let ([read_threads, worker_threads, write_threads], _guard) =
+            budgeted_stage_threads(opts, self.thread_budget.as_ref())?;
+        let threads_per_input = read_threads / inputs.len();
+
+        let mut readers = vec![];
+        for path in inputs {
+            let mut reader = opts
+                .retry_policy
+                .run(format_args!("Opening {}", path.display()), || {
+                    bam::Reader::from_path(path)
+                })
+                .with_context(|| format!("Failed to open {}", path.display()))?;
+            opts.check_dict(reader.header(), path)?;
+            if threads_per_input > 1 {
+                reader.set_threads(threads_per_input)?;
+            }
+            readers.push(reader);
+        }
+        let headers = readers
+            .iter()
+            .map(|reader| reader.header().clone())
+            .collect::<Vec<_>>();
+        let merged = merge_headers(&headers, inputs, merge)?;
+
+        let process_span = span!(
+            Level::INFO,
+            "process_bams_merged",
+            inputs = inputs.len(),
+            output = %output,
+            merge = ?merge,
+            read_threads = threads_per_input,
+            worker_threads = worker_threads,
+            write_threads = write_threads,
+        );
+        let _process_span = process_span.enter();
+        let dispatch = current_dispatch();
+
+        thread::scope(|s| {
+            let sources = readers
+                .into_iter()
+                .zip(inputs)
+                .zip(&merged.remaps)
+                .map(|((reader, path), remap)| {
+                    let (tx, rx) = bounded(READ_AHEAD_BATCHES);
+                    let (process_span, dispatch) = (&process_span, &dispatch);
+                    s.spawn(move || {
+                        let input = path.display();
+                        let _stage = enter_on_thread(
+                            dispatch,
+                            || span!(parent: process_span, Level::DEBUG, "input", path = %input),
+                        );
+                        if let Err(err) = read_ahead(reader, remap, opts.batch_size, &tx) {
+                            let _ = tx.send(Err(err.context(format!("Reading {}", input))));
+                        }
+                    });
+                    MergeInput::new(path, rx)
+                })
+                .collect();
+            // dropped, with the receivers stopping the input threads, before
+            // they are joined.
+            let mut records = MergedRecords::new(sources, merge)?;
+
+            let stats = &control.stats;
+            let read_record = |record: &mut HeaderlessRecord| {
+                let more = records.next_into(record)?;
+                if more {
+                    stats.records_read.fetch_add(1, atomic::Ordering::Relaxed);
+                }
+                Ok(more)
+            };
+
+            self.run_pipeline(
+                merged.text.as_bytes(),
+                read_record,
+                output,
+                opts,
+                [worker_threads, write_threads],
+                control,
+            )
+        })
+    }
+}
+
+/// The header of merged inputs, and how the records of each input are changed
+/// to match it.
+#[derive(Debug)]
+struct MergedHeader {
+    text: String,
+    /// By input.
+    remaps: Vec<InputRemap>,
+}
+
+/// Changes to the records of an input of a [`MergedHeader`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+struct InputRemap {
+    /// Tids of the merged header by tid of the input, `None` if the same.
+    tids: Option<Vec<i32>>,
+    /// New `ID`s of the read groups renamed, by `ID` in the input.
+    read_groups: HashMap<String, String>,
+}
+
+impl InputRemap {
+    fn apply(&self, record: &mut Record) -> Result<(), Error> {
+        if let Some(tids) = &self.tids {
+            let remap = |tid: i32| usize::try_from(tid).map_or(tid, |tid| tids[tid]);
+            record.set_tid(remap(record.tid()));
+            record.set_mtid(remap(record.mtid()));
+        }
+
+        if let Ok(Aux::String(id)) = record.aux(b"RG")
+            && let Some(new_id) = self.read_groups.get(id)
+        {
+            record.remove_aux(b"RG")?;
+            record.push_aux(b"RG", Aux::String(new_id))?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Merge the `headers` of the bams at `paths`, see
+/// [`ParallelBamProcessor::process_bams_merged`].
+fn merge_headers(
+    headers: &[HeaderView],
+    paths: &[PathBuf],
+    merge: MergeOrder,
+) -> Result<MergedHeader, Error> {
+    let dicts = headers
+        .iter()
+        .map(SeqDict::from_header)
+        .collect::<Result<Vec<_>, _>>()?;
+    let first_ctx = ProcessContext::from_header(&headers[0]);
+    let first_tids = (0..first_ctx.n_targets() as i32)
+        .filter_map(|tid| Some((first_ctx.chrom(tid)?.as_str().to_string(), tid)))
+        .collect::<HashMap<_, _>>();
+
+    let mut hd = None;
+    let mut sq_lines = vec![];
+    // `ID` and line, in order.
+    let mut rg_lines = Vec::<(String, String)>::new();
+    let mut other_lines = vec![];
+    let mut pg_ids = HashSet::new();
+    let mut remaps = vec![];
+
+    for (i, header) in headers.iter().enumerate() {
+        let mut remap = InputRemap::default();
+        if i > 0 {
+            match dicts[0].compatible_with(&dicts[i]) {
+                Ok(()) => {}
+                Err(mismatch) if mismatch.is_warning() && merge == MergeOrder::Concatenate => {}
+                Err(mismatch) => {
+                    return Err(Error::new(mismatch).context(format!(
+                        "The header of {} does not match that of {}",
+                        paths[i].display(),
+                        paths[0].display()
+                    )));
+                }
+            }
+
+            let ctx = ProcessContext::from_header(header);
+            let tids = (0..ctx.n_targets() as i32)
+                .map(|tid| {
+                    let chrom = ctx.chrom(tid).expect("a tid of the header");
+                    first_tids[chrom.as_str()]
+                })
+                .collect::<Vec<_>>();
+            if tids.iter().enumerate().any(|(tid, &to)| tid as i32 != to) {
+                remap.tids = Some(tids);
+            }
+        }
+
+        let text = String::from_utf8_lossy(header.as_bytes());
+        for line in text.lines().map(|line| line.trim_end_matches('\0')) {
+            match line.split('\t').next().unwrap_or_default() {
+                "" => {}
+                "@HD" if i == 0 => hd = Some(line.to_string()),
+                "@SQ" if i == 0 => sq_lines.push(line.to_string()),
+                "@HD" | "@SQ" => {}
+                "@RG" => {
+                    let Some(rg) = ReadGroup::from_header_line(line) else {
+                        bail!("@RG line without ID in {}: {}", paths[i].display(), line);
+                    };
+                    match rg_lines.iter().find(|(id, _)| *id == rg.id) {
+                        None => rg_lines.push((rg.id, line.to_string())),
+                        Some((_, taken)) if taken == line => {}
+                        Some(_) => {
+                            let new_id = (1..)
+                                .map(|n| format!("{}.{}", rg.id, n))
+                                .find(|new_id| rg_lines.iter().all(|(id, _)| id != new_id))
+                                .expect("a free ID");
+                            let line = line.replacen(
+                                &format!("\tID:{}", rg.id),
+                                &format!("\tID:{}", new_id),
+                                1,
+                            );
+                            rg_lines.push((new_id.clone(), line));
+                            remap.read_groups.insert(rg.id, new_id);
+                        }
+                    }
+                }
+                "@PG" => {
+                    let id = line.split('\t').find_map(|field| field.strip_prefix("ID:"));
+                    if pg_ids.insert(id.map(str::to_string)) || i == 0 {
+                        other_lines.push(line.to_string());
+                    }
+                }
+                _ if i == 0 || !other_lines.iter().any(|other| other == line) => {
+                    other_lines.push(line.to_string())
+                }
+                _ => {}
+            }
+        }
+        remaps.push(remap);
+    }
+
+    let sort_order = match merge {
+        MergeOrder::Coordinate => Some("coordinate"),
+        MergeOrder::Concatenate if headers.len() > 1 => Some("unsorted"),
+        MergeOrder::Concatenate => None,
+    };
+    let hd = match (hd, sort_order) {
+        (Some(hd), None) => hd,
+        (Some(hd), Some(sort_order)) => hd
+            .split('\t')
+            .filter(|field| !field.starts_with("SO:"))
+            .chain([format!("SO:{}", sort_order).as_str()])
+            .collect::<Vec<_>>()
+            .join("\t"),
+        (None, sort_order) => format!("@HD\tVN:1.6\tSO:{}", sort_order.unwrap_or("unknown")),
+    };
+
+    let mut text = String::new();
+    for line in [hd]
+        .into_iter()
+        .chain(sq_lines)
+        .chain(rg_lines.into_iter().map(|(_, line)| line))
+        .chain(other_lines)
+    {
+        text.push_str(&line);
+        text.push('\n');
+    }
+
+    Ok(MergedHeader { text, remaps })
+}
+
+/// Read `reader` into batches of `batch_size` records for `tx`, changed by
+/// `remap`, until the end of the input or until `tx` is dropped. Records which
+/// fail to read are skipped, as by `process_bam`.
+fn read_ahead(
+    mut reader: bam::Reader,
+    remap: &InputRemap,
+    batch_size: usize,
+    tx: &Sender<Result<Vec<HeaderlessRecord>, Error>>,
+) -> Result<(), Error> {
+    let mut batch = Vec::with_capacity(batch_size);
+    loop {
+        let mut record = Record::new();
+        match reader.read(&mut record) {
+            Some(Ok(())) => {}
+            Some(Err(e)) => {
+                event!(Level::WARN, "Error reading record: {:?}", e);
+                continue;
+            }
+            None => break,
+        }
+        remap.apply(&mut record)?;
+        batch.push(HeaderlessRecord::strip(record));
+
+        if batch.len() >= batch_size {
+            let full = std::mem::replace(&mut batch, Vec::with_capacity(batch_size));
+            if tx.send(Ok(full)).is_err() {
+                return Ok(());
+            }
+        }
+    }
+    if !batch.is_empty() {
+        let _ = tx.send(Ok(batch));
+    }
+
+    Ok(())
+}
+
+/// The records of an input, from the thread reading it ahead.
+struct MergeInput<'a> {
+    path: &'a Path,
+    rx: Receiver<Result<Vec<HeaderlessRecord>, Error>>,
+    batch: vec::IntoIter<HeaderlessRecord>,
+}
+
+impl<'a> MergeInput<'a> {
+    fn new(path: &'a Path, rx: Receiver<Result<Vec<HeaderlessRecord>, Error>>) -> Self {
+        Self {
+            path,
+            rx,
+            batch: vec![].into_iter(),
+        }
+    }
+
+    /// The next record, `None` at the end of the input.
+    fn next(&mut self) -> Result<Option<HeaderlessRecord>, Error> {
+        loop {
+            if let Some(record) = self.batch.next() {
+                return Ok(Some(record));
+            }
+            match self.rx.recv() {
+                Ok(batch) => self.batch = batch?.into_iter(),
+                Err(_) => return Ok(None),
+            }
+        }
+    }
+}
+
+/// Position of a record in a coordinate-sorted bam, the reads without a contig
+/// last.
+type SortKey = (u32, i64);
+
+fn sort_key(record: &Record) -> SortKey {
+    (record.tid() as u32, record.pos())
+}
+
+/// The records of [`MergeInput`]s in a [`MergeOrder`].
+struct MergedRecords<'a> {
+    merge: MergeOrder,
+    inputs: Vec<MergeInput<'a>>,
+    /// Of [`MergeOrder::Concatenate`], the input being read.
+    current: usize,
+    /// Of [`MergeOrder::Coordinate`], the next record of each input, and their
+    /// inputs by key, the smallest first.
+    heads: Vec<Option<HeaderlessRecord>>,
+    heap: BinaryHeap<Reverse<(SortKey, usize)>>,
+}
+
+impl<'a> MergedRecords<'a> {
+    fn new(inputs: Vec<MergeInput<'a>>, merge: MergeOrder) -> Result<Self, Error> {
+        let mut records = Self {
+            merge,
+            heads: (0..inputs.len()).map(|_| None).collect(),
+            inputs,
+            current: 0,
+            heap: BinaryHeap::new(),
+        };
+        if merge == MergeOrder::Coordinate {
+            for i in 0..records.inputs.len() {
+                records.refill(i, None)?;
+            }
+        }
+
+        Ok(records)
+    }
+
+    /// Move the next record into `record`. `false` once all are read.
+    fn next_into(&mut self, record: &mut HeaderlessRecord) -> Result<bool, Error> {
+        match self.merge {
+            MergeOrder::Concatenate => {
+                while let Some(input) = self.inputs.get_mut(self.current) {
+                    if let Some(next) = input.next()? {
+                        *record = next;
+                        return Ok(true);
+                    }
+                    self.current += 1;
+                }
+                Ok(false)
+            }
+            MergeOrder::Coordinate => {
+                let Some(Reverse((key, i))) = self.heap.pop() else {
+                    return Ok(false);
+                };
+                *record = self.heads[i]
+                    .take()
+                    .expect("an input in the heap has a record");
+                self.refill(i, Some(key))?;
+                Ok(true)
+            }
+        }
+    }
+
+    /// Take the next record of input `i` as its head, which must not come
+    /// before `last`, the key of the last one.
+    fn refill(&mut self, i: usize, last: Option<SortKey>) -> Result<(), Error> {
+        let Some(next) = self.inputs[i].next()? else {
+            return Ok(());
+        };
+        let key = sort_key(&next);
+        if let Some(last) = last
+            && key < last
+        {
+            return Err(anyhow!(
+                "{} is not sorted by coordinate: {} at {}:{} comes after {}:{}",
+                self.inputs[i].path.display(),
+                String::from_utf8_lossy(next.qname()),
+                key.0 as i32,
+                key.1,
+                last.0 as i32,
+                last.1
+            ));
+        }
+        self.heads[i] = Some(next);
+        self.heap.push(Reverse((key, i)));
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_htslib::bam::Reader;
+
+    use super::*;
+    use crate::test_utils::TestBam;
+
+    /// Keeps every record as it is.
+    struct KeepAll;
+
+    impl RecordModifier for KeepAll {
+        type Error = Error;
+
+        fn modify_record(&self, _record: &mut Record) -> Result<Option<()>, Self::Error> {
+            Ok(Some(()))
+        }
+    }
+
+    fn lane(id: &str, sample: &str, positions: &[i64]) -> TestBam {
+        let mut bam = TestBam::new()
+            .contig("chr1", 10_000)
+            .contig("chr2", 10_000)
+            .read_group(id, sample);
+        for (i, &pos) in positions.iter().enumerate() {
+            let contig = if i % 2 == 0 { "chr1" } else { "chr2" };
+            bam = bam
+                .add_read(contig, pos, b"ACGT", b"IIII", 0)
+                .with_qname(&format!("{}_{}", sample, i));
+        }
+        bam
+    }
+
+    /// Tid, position and read group of a record.
+    type Read = (i32, i64, String);
+
+    /// The header text and the reads of the bam at `path`.
+    fn read_back(path: &Path) -> Result<(String, Vec<Read>), Error> {
+        let mut reader = Reader::from_path(path)?;
+        let text = String::from_utf8_lossy(reader.header().as_bytes()).into_owned();
+        let records = reader
+            .records()
+            .map(|record| {
+                let record = record?;
+                let rg = match record.aux(b"RG")? {
+                    Aux::String(rg) => rg.to_string(),
+                    _ => bail!("RG is not a string"),
+                };
+                Ok((record.tid(), record.pos(), rg))
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+        Ok((text, records))
+    }
+
+    #[test]
+    fn test_merge_by_coordinate() -> Result<(), Error> {
+        let dirs = [tempfile::tempdir()?, tempfile::tempdir()?];
+        let inputs = [
+            lane("L1", "sample", &[100, 300, 500, 700, 900, 50]).build(dirs[0].path())?,
+            // another sample of the same ID.
+            lane("L1", "other", &[200, 300, 400, 600]).build(dirs[1].path())?,
+        ];
+        let out_path = dirs[0].path().join("merged.bam");
+
+        let stats = ParallelBamProcessor::new(KeepAll).process_bams_merged(
+            &inputs,
+            &out_path,
+            &ProcessBamOptions {
+                batch_size: 3,
+                ..Default::default()
+            },
+            MergeOrder::Coordinate,
+        )?;
+        assert_eq!((stats.records_read, stats.records_written), (10, 10));
+
+        let (text, records) = read_back(&out_path)?;
+        assert!(text.contains("@HD\tVN:1.6\tSO:coordinate\n"), "{}", text);
+        assert!(text.contains("@RG\tID:L1\tSM:sample\n"), "{}", text);
+        assert!(text.contains("@RG\tID:L1.1\tSM:other\n"), "{}", text);
+
+        let keys = records
+            .iter()
+            .map(|(tid, pos, _)| (*tid, *pos))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            keys,
+            [
+                (0, 100),
+                (0, 200),
+                (0, 400),
+                (0, 500),
+                (0, 900),
+                (1, 50),
+                (1, 300),
+                (1, 300),
+                (1, 600),
+                (1, 700),
+            ]
+        );
+        // at the same position, the first input first.
+        assert_eq!(records[6].2, "L1");
+        assert_eq!(records[7].2, "L1.1");
+        assert_eq!(records.iter().filter(|(.., rg)| rg == "L1.1").count(), 4);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_concatenate() -> Result<(), Error> {
+        let dirs = [tempfile::tempdir()?, tempfile::tempdir()?];
+        // contigs in another order, and the same read group.
+        let inputs = [
+            lane("L1", "sample", &[100, 300, 500]).build(dirs[0].path())?,
+            TestBam::new()
+                .contig("chr2", 10_000)
+                .contig("chr1", 10_000)
+                .read_group("L1", "sample")
+                .add_read("chr2", 20, b"ACGT", b"IIII", 0)
+                .add_read("chr1", 10, b"ACGT", b"IIII", 0)
+                .build(dirs[1].path())?,
+        ];
+        let out_path = dirs[0].path().join("merged.bam");
+
+        let processor = ParallelBamProcessor::new(KeepAll);
+        let opts = ProcessBamOptions::default();
+        let stats =
+            processor.process_bams_merged(&inputs, &out_path, &opts, MergeOrder::Concatenate)?;
+        assert_eq!((stats.records_read, stats.records_written), (5, 5));
+
+        let (text, records) = read_back(&out_path)?;
+        assert!(text.contains("SO:unsorted"), "{}", text);
+        assert_eq!(text.matches("@RG").count(), 1, "{}", text);
+        // tids of the first input, in input order.
+        assert_eq!(
+            records,
+            [
+                (0, 100, "L1".to_string()),
+                (0, 500, "L1".to_string()),
+                (1, 300, "L1".to_string()),
+                (1, 20, "L1".to_string()),
+                (0, 10, "L1".to_string()),
+            ]
+        );
+
+        // which are not sorted the same way.
+        let err = processor
+            .process_bams_merged(&inputs, &out_path, &opts, MergeOrder::Coordinate)
+            .unwrap_err();
+        assert!(
+            format!("{:#}", err).contains("contigs are in another order"),
+            "{:#}",
+            err
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_unsorted_input() -> Result<(), Error> {
+        let dir = tempfile::tempdir()?;
+        let sorted = lane("L1", "sample", &[100, 300]).build(dir.path())?;
+        let out_path = dir.path().join("concatenated.bam");
+        let processor = ParallelBamProcessor::new(KeepAll);
+        let opts = ProcessBamOptions::default();
+        processor.process_bams_merged(
+            &[sorted.clone(), sorted.clone()],
+            &out_path,
+            &opts,
+            MergeOrder::Concatenate,
+        )?;
+
+        let err = processor
+            .process_bams_merged(
+                &[out_path, sorted],
+                dir.path().join("merged.bam"),
+                &opts,
+                MergeOrder::Coordinate,
+            )
+            .unwrap_err();
+        assert!(
+            format!("{:#}", err).contains("is not sorted by coordinate"),
+            "{:#}",
+            err
+        );
+
+        Ok(())
+    }
+}
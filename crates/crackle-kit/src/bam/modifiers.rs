@@ -0,0 +1,937 @@
+//! Ready-made [`RecordModifier`]s.
+
+#[cfg(feature = "crypto")]
+use std::{collections::HashMap, io::Write, sync::Mutex};
+use std::{
+    ops::RangeInclusive,
+    path::Path,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use anyhow::{Error, anyhow, bail};
+#[cfg(feature = "crypto")]
+use hmac::{Hmac, Mac as _};
+#[cfg(feature = "crypto")]
+use rust_htslib::bam::{Header, HeaderView};
+use rust_htslib::bam::{Record, record::Aux};
+#[cfg(feature = "crypto")]
+use sha2::Sha256;
+use tracing::{Level, event};
+
+use crate::{
+    bam::{
+        process::{ParallelBamProcessor, ProcessBamOptions, ProcessStats, RecordModifier},
+        stats::estimate_mean_coverage,
+    },
+    utils::{
+        seq_stats::LowComplexityFilter,
+        stable_hash::{Fnv1a, fmix64},
+    },
+};
+#[cfg(feature = "crypto")]
+use crate::{table::TableWriter, utils::fmt::to_hex};
+
+/// Move the UMI from the read name into the `RX` tag.
+///
+/// The UMI is the last `:`-delimited token of the read name, as written by
+/// bcl-convert and most UMI extraction tools (e.g. `A01:1:FC:1:1101:1000:2000:ACGTACGT`).
+/// It is taken only if it consists of A, C, G, T, N and `+`; a `+` between dual
+/// UMIs is written as `-`, following the SAM spec. Reads without a UMI are kept
+/// unchanged.
+#[derive(Debug, Clone, Default)]
+pub struct UmiToRx {
+    /// Also remove the UMI (and its `:`) from the read name.
+    pub strip_from_qname: bool,
+}
+
+impl UmiToRx {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn strip_from_qname(mut self, yes: bool) -> Self {
+        self.strip_from_qname = yes;
+        self
+    }
+
+    /// UMI token of a read name, if any.
+    pub fn umi_of(qname: &[u8]) -> Option<&[u8]> {
+        let sep = qname.iter().rposition(|b| *b == b':')?;
+        let umi = &qname[sep + 1..];
+
+        let is_umi = !umi.is_empty()
+            && umi
+                .iter()
+                .all(|b| matches!(b, b'A' | b'C' | b'G' | b'T' | b'N' | b'+'));
+        is_umi.then_some(umi)
+    }
+}
+
+impl RecordModifier for UmiToRx {
+    type Error = Error;
+
+    fn modify_record(&self, record: &mut Record) -> Result<Option<()>, Self::Error> {
+        let qname = record.qname();
+        let umi = match Self::umi_of(qname) {
+            Some(umi) => umi,
+            None => return Ok(Some(())),
+        };
+
+        let rx = String::from_utf8(
+            umi.iter()
+                .map(|b| if *b == b'+' { b'-' } else { *b })
+                .collect(),
+        )?;
+        let stripped = self
+            .strip_from_qname
+            .then(|| qname[..qname.len() - umi.len() - 1].to_vec());
+
+        if record.aux(b"RX").is_ok() {
+            record.remove_aux(b"RX")?;
+        }
+        record.push_aux(b"RX", Aux::String(&rx))?;
+        if let Some(qname) = stripped {
+            record.set_qname(&qname);
+        }
+
+        Ok(Some(()))
+    }
+}
+
+/// Bin base qualities into a few values, which makes bams much smaller.
+///
+/// Qualities in the range of a bin are replaced by its value, others are kept.
+/// Values lie in their own range, so binning twice changes nothing more than
+/// binning once. Records without qualities (`*`) are left untouched.
+///
+/// The default bins are the 4 of Illumina RTA3 (NovaSeq).
+#[derive(Debug)]
+pub struct QualBinModifier {
+    table: [u8; 256],
+    n_changed: AtomicU64,
+}
+
+impl QualBinModifier {
+    /// Bins of `(qualities, value)`. Fails if two bins overlap, or if a value is
+    /// out of the range of its bin.
+    pub fn new(bins: impl IntoIterator<Item = (RangeInclusive<u8>, u8)>) -> Result<Self, Error> {
+        let mut table = std::array::from_fn(|q| q as u8);
+        let mut binned = [false; 256];
+
+        for (range, value) in bins {
+            if !range.contains(&value) {
+                bail!("Bin value {} is out of its range {:?}", value, range);
+            }
+            for q in range.clone() {
+                if std::mem::replace(&mut binned[q as usize], true) {
+                    bail!("Bin {:?} overlaps another one at quality {}", range, q);
+                }
+                table[q as usize] = value;
+            }
+        }
+
+        Ok(Self {
+            table,
+            n_changed: AtomicU64::new(0),
+        })
+    }
+
+    /// The bins of Illumina RTA3: 0-2 to 2, 3-14 to 12, 15-30 to 23 and 31+ to 37.
+    pub fn rta3() -> Self {
+        Self::new([(0..=2, 2), (3..=14, 12), (15..=30, 23), (31..=254, 37)]).expect("valid bins")
+    }
+
+    /// The 8 bins of the earlier Illumina binning (HiSeq): 2-9 to 6, 10-19 to 15,
+    /// 20-24 to 22, 25-29 to 27, 30-34 to 33, 35-39 to 37 and 40+ to 40. Qualities
+    /// below 2, of no-calls, are kept.
+    pub fn illumina_8_bins() -> Self {
+        Self::new([
+            (2..=9, 6),
+            (10..=19, 15),
+            (20..=24, 22),
+            (25..=29, 27),
+            (30..=34, 33),
+            (35..=39, 37),
+            (40..=254, 40),
+        ])
+        .expect("valid bins")
+    }
+
+    /// Bin `qual` in place and return the number of qualities changed.
+    pub fn bin(&self, qual: &mut [u8]) -> usize {
+        let mut n_changed = 0;
+        for q in qual.iter_mut() {
+            let binned = self.table[*q as usize];
+            n_changed += usize::from(binned != *q);
+            *q = binned;
+        }
+        n_changed
+    }
+
+    /// Qualities changed so far, over all records.
+    pub fn n_changed(&self) -> u64 {
+        self.n_changed.load(Ordering::Relaxed)
+    }
+}
+
+impl Default for QualBinModifier {
+    fn default() -> Self {
+        Self::rta3()
+    }
+}
+
+impl RecordModifier for QualBinModifier {
+    type Error = Error;
+
+    fn modify_record(&self, record: &mut Record) -> Result<Option<()>, Self::Error> {
+        // `*` is stored as 0xff for every base.
+        if record.qual().first().is_none_or(|q| *q == 0xff) {
+            return Ok(Some(()));
+        }
+
+        let mut qual = record.qual().to_vec();
+        let n_changed = self.bin(&mut qual);
+        if n_changed > 0 {
+            // qualities can only be set along with the other variable length
+            // fields; `set` keeps the tags.
+            let qname = record.qname().to_vec();
+            let cigar = record.cigar().take();
+            let seq = record.seq().as_bytes();
+            record.set(&qname, Some(&cigar), &seq, &qual);
+            self.n_changed
+                .fetch_add(n_changed as u64, Ordering::Relaxed);
+        }
+
+        Ok(Some(()))
+    }
+}
+
+/// Keep a fraction of the reads, chosen by a hash of the read name.
+///
+/// Both mates of a pair, and the secondary and supplementary alignments of a
+/// read, share the name, so they are kept or dropped together. Runs with the same
+/// seed keep the same reads.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DownsampleModifier {
+    fraction: f64,
+    seed: u64,
+}
+
+impl DownsampleModifier {
+    /// Keep about `fraction` of the reads. Fails if it is not in (0, 1].
+    pub fn new(fraction: f64, seed: u64) -> Result<Self, Error> {
+        if !(fraction > 0.0 && fraction <= 1.0) {
+            bail!(
+                "Fraction of reads to keep must be in (0, 1], got {}",
+                fraction
+            );
+        }
+
+        Ok(Self { fraction, seed })
+    }
+
+    pub fn fraction(&self) -> f64 {
+        self.fraction
+    }
+
+    /// Whether the reads named `qname` are kept.
+    pub fn keeps(&self, qname: &[u8]) -> bool {
+        // the top 53 bits as a uniform f64 in [0, 1).
+        let u = (qname_hash(qname, self.seed) >> 11) as f64 / (1u64 << 53) as f64;
+        u < self.fraction
+    }
+}
+
+impl RecordModifier for DownsampleModifier {
+    type Error = Error;
+
+    fn modify_record(&self, record: &mut Record) -> Result<Option<()>, Self::Error> {
+        Ok(self.keeps(record.qname()).then_some(()))
+    }
+}
+
+impl RecordModifier for LowComplexityFilter {
+    type Error = Error;
+
+    fn modify_record(&self, record: &mut Record) -> Result<Option<()>, Self::Error> {
+        Ok(self.keeps(&record.seq().as_bytes()).then_some(()))
+    }
+}
+
+/// FNV-1a, then the finalizer of MurmurHash3 to spread the bits.
+fn qname_hash(qname: &[u8], seed: u64) -> u64 {
+    let mut hasher = Fnv1a::with_seed(seed);
+    hasher.write(qname);
+    fmix64(hasher.finish())
+}
+
+/// Downsample an indexed bam to about `target_mean_coverage`, with a
+/// [`DownsampleModifier`] whose fraction comes from [`estimate_mean_coverage`].
+///
+/// If the bam is not deeper than the target, every read is kept.
+pub fn downsample_to_coverage(
+    input_bam_path: impl AsRef<Path>,
+    out_bam_path: impl AsRef<Path>,
+    target_mean_coverage: f64,
+    seed: u64,
+    opts: &ProcessBamOptions,
+) -> Result<ProcessStats, crate::Error> {
+    if target_mean_coverage.is_nan() || target_mean_coverage <= 0.0 {
+        return Err(anyhow!(
+            "Target coverage must be positive, got {}",
+            target_mean_coverage
+        )
+        .into());
+    }
+
+    let coverage = estimate_mean_coverage(&input_bam_path)?;
+    let fraction = target_mean_coverage / coverage;
+    event!(
+        Level::DEBUG,
+        "Mean coverage is {:.2}, keeping {:.4} of the reads",
+        coverage,
+        fraction.min(1.0)
+    );
+    if fraction >= 1.0 {
+        event!(
+            Level::WARN,
+            "Mean coverage {:.2} is not above the target {:.2}, keeping every read",
+            coverage,
+            target_mean_coverage
+        );
+    }
+
+    ParallelBamProcessor::new(DownsampleModifier::new(fraction.min(1.0), seed)?).process_bam(
+        input_bam_path,
+        out_bam_path,
+        opts,
+    )
+}
+
+/// How [`AnonymizeModifier`] names the reads.
+#[cfg(feature = "crypto")]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SurrogateNames {
+    /// The first 128 bits of the HMAC-SHA256 of the name with a secret key, in
+    /// hex. Reads of the same name get the same surrogate in any run with the
+    /// key, without keeping state, and the names cannot be recovered without it.
+    Hmac { key: Vec<u8> },
+    /// Numbers from 1 in the order the names are first seen. Every name is kept
+    /// in memory, and the numbers of a parallel run depend on the timing of the
+    /// workers.
+    Counter,
+}
+
+/// Replace the read names by surrogates and remove identifying tags, to share
+/// a bam.
+///
+/// Both mates, and the secondary and supplementary alignments of a read, get
+/// the same name. By default `OQ` and `BC` are removed; other tags are kept.
+/// The `RG` tags, and the `ID`s of the `@RG` lines of the header, can be
+/// renamed, and the `@CO` lines and the command lines of the `@PG` lines left
+/// out of the header.
+#[cfg(feature = "crypto")]
+#[derive(Debug)]
+pub struct AnonymizeModifier {
+    names: SurrogateNames,
+    prefix: String,
+    removed_tags: Vec<[u8; 2]>,
+    read_groups: HashMap<String, String>,
+    strip_header_comments: bool,
+    /// Surrogates by original name, for the counter or the mapping.
+    seen: Option<Mutex<HashMap<Vec<u8>, String>>>,
+}
+
+#[cfg(feature = "crypto")]
+impl AnonymizeModifier {
+    /// Tags removed unless set by [`Self::with_removed_tags`].
+    pub const DEFAULT_REMOVED_TAGS: [[u8; 2]; 2] = [*b"OQ", *b"BC"];
+
+    fn new(names: SurrogateNames) -> Self {
+        let seen = matches!(names, SurrogateNames::Counter).then(Mutex::default);
+        Self {
+            names,
+            prefix: "read".to_string(),
+            removed_tags: Self::DEFAULT_REMOVED_TAGS.to_vec(),
+            read_groups: HashMap::new(),
+            strip_header_comments: false,
+            seen,
+        }
+    }
+
+    /// Names of [`SurrogateNames::Hmac`] with `key`, which must not be empty.
+    pub fn hmac(key: impl Into<Vec<u8>>) -> Result<Self, Error> {
+        let key = key.into();
+        if key.is_empty() {
+            bail!("The key of the surrogate read names is empty");
+        }
+        Ok(Self::new(SurrogateNames::Hmac { key }))
+    }
+
+    /// Names of [`SurrogateNames::Counter`].
+    pub fn counter() -> Self {
+        Self::new(SurrogateNames::Counter)
+    }
+
+    /// Start of the surrogate names, `read` by default.
+    pub fn with_prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.prefix = prefix.into();
+        self
+    }
+
+    /// Remove these tags instead of [`Self::DEFAULT_REMOVED_TAGS`].
+    pub fn with_removed_tags(mut self, tags: impl IntoIterator<Item = [u8; 2]>) -> Self {
+        self.removed_tags = tags.into_iter().collect();
+        self
+    }
+
+    /// Rename read group `from` to `to`, in the `RG` tags and the header.
+    pub fn with_read_group(mut self, from: impl Into<String>, to: impl Into<String>) -> Self {
+        self.read_groups.insert(from.into(), to.into());
+        self
+    }
+
+    /// Leave the `@CO` lines, and the `CL` fields of the `@PG` lines, out of the
+    /// header.
+    pub fn with_header_comments_stripped(mut self, yes: bool) -> Self {
+        self.strip_header_comments = yes;
+        self
+    }
+
+    /// Keep the names seen, for [`Self::write_mapping_tsv`].
+    pub fn with_mapping(mut self) -> Self {
+        self.seen.get_or_insert_with(Mutex::default);
+        self
+    }
+
+    /// Surrogate of `qname`.
+    pub fn surrogate(&self, qname: &[u8]) -> String {
+        let Some(seen) = &self.seen else {
+            return self.hmac_surrogate(qname);
+        };
+
+        let mut seen = seen.lock().unwrap();
+        if let Some(surrogate) = seen.get(qname) {
+            return surrogate.clone();
+        }
+        let surrogate = match self.names {
+            SurrogateNames::Hmac { .. } => self.hmac_surrogate(qname),
+            SurrogateNames::Counter => format!("{}{}", self.prefix, seen.len() + 1),
+        };
+        seen.insert(qname.to_vec(), surrogate.clone());
+        surrogate
+    }
+
+    fn hmac_surrogate(&self, qname: &[u8]) -> String {
+        let SurrogateNames::Hmac { key } = &self.names else {
+            unreachable!("counter names are kept");
+        };
+        let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC takes keys of any length");
+        mac.update(qname);
+        let digest = mac.finalize().into_bytes();
+        format!("{}{}", self.prefix, to_hex(&digest[..16]))
+    }
+
+    /// Write the original and surrogate names seen so far as a TSV, sorted by
+    /// original name. Fails unless kept by [`Self::with_mapping`] or
+    /// [`SurrogateNames::Counter`].
+    ///
+    /// The names are written as they are; pass an encrypting writer to keep
+    /// them encrypted.
+    pub fn write_mapping_tsv(&self, w: impl Write) -> Result<(), Error> {
+        let Some(seen) = &self.seen else {
+            bail!("The read names were not kept for the mapping");
+        };
+        let seen = seen.lock().unwrap();
+        let mut mapping = seen.iter().collect::<Vec<_>>();
+        mapping.sort_unstable();
+
+        let mut tw = TableWriter::new(w, &["original", "surrogate"])?;
+        for (original, surrogate) in mapping {
+            tw.write_row([String::from_utf8_lossy(original).as_ref(), surrogate])?;
+        }
+        tw.into_inner()?;
+        Ok(())
+    }
+
+    /// A line of the header as it is written, if it is.
+    fn header_line(&self, line: &str) -> Option<String> {
+        let kind = line.split('\t').next().unwrap_or_default();
+        if self.strip_header_comments && kind == "@CO" {
+            return None;
+        }
+
+        let fields = line.split('\t').filter_map(|field| match (kind, field) {
+            ("@PG", f) if self.strip_header_comments && f.starts_with("CL:") => None,
+            ("@RG", f) if f.starts_with("ID:") => match self.read_groups.get(&f[3..]) {
+                Some(id) => Some(format!("ID:{}", id)),
+                None => Some(f.to_string()),
+            },
+            (_, f) => Some(f.to_string()),
+        });
+        Some(fields.collect::<Vec<_>>().join("\t"))
+    }
+}
+
+#[cfg(feature = "crypto")]
+impl RecordModifier for AnonymizeModifier {
+    type Error = Error;
+
+    fn modify_record(&self, record: &mut Record) -> Result<Option<()>, Self::Error> {
+        let qname = self.surrogate(record.qname());
+        record.set_qname(qname.as_bytes());
+
+        for tag in self.removed_tags.iter() {
+            if record.aux(tag).is_ok() {
+                record.remove_aux(tag)?;
+            }
+        }
+
+        let read_group = match record.aux(b"RG") {
+            Ok(Aux::String(rg)) => self.read_groups.get(rg),
+            _ => None,
+        };
+        if let Some(read_group) = read_group {
+            record.remove_aux(b"RG")?;
+            record.push_aux(b"RG", Aux::String(read_group))?;
+        }
+
+        Ok(Some(()))
+    }
+
+    fn output_header(&self, header: &HeaderView) -> Header {
+        let text = String::from_utf8_lossy(header.as_bytes());
+        let mut out = String::with_capacity(text.len());
+        for line in text.lines().filter_map(|line| self.header_line(line)) {
+            out.push_str(&line);
+            out.push('\n');
+        }
+        Header::from_template(&HeaderView::from_bytes(out.as_bytes()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_htslib::bam::record::{Cigar, CigarString};
+
+    use super::*;
+
+    fn record(qname: &[u8]) -> Record {
+        let mut record = Record::new();
+        let cigar = CigarString(vec![Cigar::Match(4)]);
+        record.set(qname, Some(&cigar), b"ACGT", &[30; 4]);
+        record
+    }
+
+    fn rx(record: &Record) -> Option<String> {
+        match record.aux(b"RX") {
+            Ok(Aux::String(s)) => Some(s.to_string()),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn test_umi_of() {
+        assert_eq!(
+            UmiToRx::umi_of(b"A01:1:FC:1:1101:1000:2000:ACGTN"),
+            Some(&b"ACGTN"[..])
+        );
+        assert_eq!(UmiToRx::umi_of(b"r1:AAC+GGT"), Some(&b"AAC+GGT"[..]));
+        // plain Illumina name, the last token is the y coordinate.
+        assert_eq!(UmiToRx::umi_of(b"A01:1:FC:1:1101:1000:2000"), None);
+        assert_eq!(UmiToRx::umi_of(b"r1:"), None);
+        assert_eq!(UmiToRx::umi_of(b"read1"), None);
+    }
+
+    #[test]
+    fn test_modify_record() -> Result<(), Error> {
+        let mut r = record(b"r1:AAC+GGT");
+        assert_eq!(UmiToRx::new().modify_record(&mut r)?, Some(()));
+        assert_eq!(rx(&r).as_deref(), Some("AAC-GGT"));
+        assert_eq!(r.qname(), b"r1:AAC+GGT");
+
+        // an existing tag is replaced.
+        let mut r = record(b"r2:TTTT");
+        r.push_aux(b"RX", Aux::String("NNNN"))?;
+        UmiToRx::new()
+            .strip_from_qname(true)
+            .modify_record(&mut r)?;
+        assert_eq!(rx(&r).as_deref(), Some("TTTT"));
+        assert_eq!(r.qname(), b"r2");
+
+        let mut r = record(b"r3");
+        assert_eq!(UmiToRx::new().modify_record(&mut r)?, Some(()));
+        assert_eq!(rx(&r), None);
+
+        Ok(())
+    }
+
+    /// A mapped read with tags, soft-clipped, with the given qualities.
+    fn full_record(qual: &[u8]) -> Record {
+        let mut record = Record::new();
+        let cigar = CigarString(vec![Cigar::SoftClip(2), Cigar::Match(6)]);
+        record.set(b"read1", Some(&cigar), b"ACGTNACG", qual);
+        record.set_tid(1);
+        record.set_pos(1_000);
+        record.set_mapq(60);
+        record.set_flags(0x1 | 0x2 | 0x40);
+        record.set_mtid(1);
+        record.set_mpos(1_200);
+        record.set_insert_size(250);
+        record.push_aux(b"RX", Aux::String("ACGT-TTGA")).unwrap();
+        record.push_aux(b"NM", Aux::I32(1)).unwrap();
+        record
+    }
+
+    #[test]
+    fn test_qual_bin_modifier() -> Result<(), Error> {
+        let modifier = QualBinModifier::illumina_8_bins();
+        let mut r = full_record(&[0, 2, 9, 10, 25, 35, 40, 60]);
+
+        assert_eq!(modifier.modify_record(&mut r)?, Some(()));
+        assert_eq!(r, full_record(&[0, 6, 6, 15, 27, 37, 40, 40]));
+        assert_eq!(modifier.n_changed(), 6);
+
+        // binned qualities stay as they are.
+        let binned = r.clone();
+        modifier.modify_record(&mut r)?;
+        assert_eq!(r, binned);
+        assert_eq!(modifier.n_changed(), 6);
+
+        let mut r = full_record(&[0xff; 8]);
+        modifier.modify_record(&mut r)?;
+        assert_eq!(r, full_record(&[0xff; 8]));
+
+        let mut qual = [1, 2, 14, 15, 30, 31, 41];
+        assert_eq!(QualBinModifier::default().bin(&mut qual), 6);
+        assert_eq!(qual, [2, 2, 12, 23, 23, 37, 37]);
+
+        assert!(QualBinModifier::new([(10..=19, 5)]).is_err());
+        assert!(QualBinModifier::new([(10..=19, 15), (19..=29, 25)]).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_qual_binning_shrinks_bam() -> Result<(), Error> {
+        use rand::{Rng, SeedableRng};
+
+        use crate::{
+            bam::process::{ParallelBamProcessor, ProcessBamOptions},
+            test_utils::TestBam,
+        };
+
+        let dir = tempfile::tempdir()?;
+        let mut rng = rand::rngs::StdRng::seed_from_u64(1);
+        let seq = b"ACGT".repeat(25);
+        let mut bam = TestBam::new();
+        for i in 0..2_000 {
+            let qual = (0..seq.len())
+                .map(|_| rng.random_range(2..42))
+                .collect::<Vec<u8>>();
+            bam = bam.add_read("chr1", i * 10, &seq, &qual, 0);
+        }
+        let input_bam_path = bam.build(dir.path())?;
+        let out_bam_path = dir.path().join("binned.bam");
+
+        let pbp = ParallelBamProcessor::new(QualBinModifier::default());
+        pbp.process_bam_sequential(
+            &input_bam_path,
+            &out_bam_path,
+            &ProcessBamOptions::default(),
+        )?;
+
+        let size = |path: &std::path::Path| std::fs::metadata(path).map(|m| m.len());
+        let (before, after) = (size(&input_bam_path)?, size(&out_bam_path)?);
+        assert!(after * 2 < before, "{} -> {} bytes", before, after);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_downsample_modifier() -> Result<(), Error> {
+        use rand::{Rng, SeedableRng};
+
+        let mut rng = rand::rngs::StdRng::seed_from_u64(1);
+        let modifier = DownsampleModifier::new(0.3, 7)?;
+        let n_kept = (0..100_000)
+            .filter(|_| {
+                let qname = format!("A01:1:FC:1:{}:{}", rng.random::<u32>(), rng.random::<u32>());
+                let mut r = record(qname.as_bytes());
+                modifier.modify_record(&mut r).unwrap().is_some()
+            })
+            .count();
+        // sd of the count is about 145.
+        assert!((29_250..=30_750).contains(&n_kept), "{} kept", n_kept);
+
+        // same name and seed, same decision.
+        let keeps = |seed| {
+            let modifier = DownsampleModifier::new(0.5, seed).unwrap();
+            (0..64)
+                .map(|i| modifier.keeps(format!("read{}", i).as_bytes()))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(keeps(1), keeps(1));
+        assert_ne!(keeps(1), keeps(2));
+
+        assert!(DownsampleModifier::new(1.0, 0)?.keeps(b"read1"));
+        for fraction in [0.0, -0.5, 1.5, f64::NAN] {
+            assert!(DownsampleModifier::new(fraction, 0).is_err());
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_low_complexity_filter() -> Result<(), Error> {
+        let filter = LowComplexityFilter::new().with_max_homopolymer_run(3);
+
+        let mut r = record(b"read1");
+        assert_eq!(filter.modify_record(&mut r)?, Some(()));
+
+        let cigar = CigarString(vec![Cigar::Match(8)]);
+        r.set(b"read2", Some(&cigar), b"ACGAAAAT", &[30; 8]);
+        assert_eq!(filter.modify_record(&mut r)?, None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_downsample_keeps_mates_together() -> Result<(), Error> {
+        use std::collections::HashMap;
+
+        use rust_htslib::bam::Read as _;
+
+        use crate::{
+            bam::process::{ParallelBamProcessor, ProcessBamOptions},
+            test_utils::TestBam,
+        };
+
+        let dir = tempfile::tempdir()?;
+        let mut bam = TestBam::new();
+        for i in 0..500 {
+            bam = bam.add_pair("chr1", i * 20, i * 20 + 200, 50, 0);
+        }
+        let input_bam_path = bam.build(dir.path())?;
+        let out_bam_path = dir.path().join("downsampled.bam");
+
+        let pbp = ParallelBamProcessor::new(DownsampleModifier::new(0.5, 42)?);
+        pbp.process_bam_sequential(
+            &input_bam_path,
+            &out_bam_path,
+            &ProcessBamOptions::default(),
+        )?;
+
+        let mut counts = HashMap::<Vec<u8>, usize>::new();
+        for record in rust_htslib::bam::Reader::from_path(&out_bam_path)?.records() {
+            *counts.entry(record?.qname().to_vec()).or_default() += 1;
+        }
+        assert!(
+            (200..=300).contains(&counts.len()),
+            "{} pairs",
+            counts.len()
+        );
+        assert!(counts.values().all(|&n| n == 2));
+
+        Ok(())
+    }
+
+    #[cfg(feature = "crypto")]
+    #[test]
+    fn test_anonymize_modifier() -> Result<(), Error> {
+        use std::collections::HashSet;
+
+        use rust_htslib::bam::Read as _;
+
+        use crate::{
+            bam::process::{ParallelBamProcessor, ProcessBamOptions},
+            test_utils::TestBam,
+        };
+
+        let dir = tempfile::tempdir()?;
+        let mut bam = TestBam::new().read_group("lane1", "patient7");
+        // tags on the second mates.
+        for i in 0..200 {
+            bam = bam
+                .add_pair("chr1", i * 20, i * 20 + 300, 50, 0)
+                .with_tag(b"OQ", "IIIII")
+                .with_tag(b"BC", "ACGTACGT")
+                .with_tag(b"RX", "AAC-GGT");
+        }
+        // supplementary alignments of two pairs, elsewhere.
+        for (name, pos) in [("pair0", 50_000), ("pair10", 60_000)] {
+            bam = bam
+                .add_read("chr1", pos, b"ACGTACGTAC", &[30; 10], 0x1 | 0x40 | 0x800)
+                .with_qname(name);
+        }
+        let input_bam_path = bam.build(dir.path())?;
+        let out_bam_path = dir.path().join("anonymized.bam");
+
+        let modifier = AnonymizeModifier::hmac("secret")?
+            .with_prefix("anon_")
+            .with_read_group("lane1", "rg1")
+            .with_mapping();
+        let pbp = ParallelBamProcessor::new(modifier);
+        pbp.process_bam(
+            &input_bam_path,
+            &out_bam_path,
+            &ProcessBamOptions::default(),
+        )?;
+
+        let mut input = rust_htslib::bam::Reader::from_path(&input_bam_path)?;
+        let mut output = rust_htslib::bam::Reader::from_path(&out_bam_path)?;
+        let mut surrogates = HashMap::<Vec<u8>, Vec<u8>>::new();
+        let (mut n_records, mut n_tagged) = (0, 0);
+        for (original, anonymized) in input.records().zip(output.records()) {
+            let (original, anonymized) = (original?, anonymized?);
+            assert_eq!(original.pos(), anonymized.pos());
+            assert_eq!(
+                anonymized.qname(),
+                pbp.record_modifier().surrogate(original.qname()).as_bytes()
+            );
+            assert!(anonymized.qname().starts_with(b"anon_"));
+            let surrogate = surrogates
+                .entry(original.qname().to_vec())
+                .or_insert_with(|| anonymized.qname().to_vec());
+            assert_eq!(surrogate, anonymized.qname());
+
+            n_tagged += usize::from(original.aux(b"OQ").is_ok());
+            assert!(anonymized.aux(b"OQ").is_err());
+            assert!(anonymized.aux(b"BC").is_err());
+            assert_eq!(anonymized.aux(b"RX").ok(), original.aux(b"RX").ok());
+            assert_eq!(anonymized.aux(b"RG")?, Aux::String("rg1"));
+            n_records += 1;
+        }
+        assert_eq!((n_records, n_tagged), (402, 200));
+        assert_eq!(surrogates.len(), 200);
+        assert_eq!(surrogates.values().collect::<HashSet<_>>().len(), 200);
+
+        let header = String::from_utf8(output.header().as_bytes().to_vec())?;
+        assert!(header.contains("@RG\tID:rg1\tSM:patient7"), "{}", header);
+        assert!(!header.contains("lane1"));
+
+        // the same key names reads the same in another run, without state.
+        let again = AnonymizeModifier::hmac("secret")?.with_prefix("anon_");
+        assert_eq!(
+            again.surrogate(b"pair0"),
+            pbp.record_modifier().surrogate(b"pair0")
+        );
+        assert_ne!(
+            AnonymizeModifier::hmac("other")?.surrogate(b"pair0"),
+            again.surrogate(b"pair0")
+        );
+        assert!(AnonymizeModifier::hmac("").is_err());
+        // the first half of HMAC-SHA256, RFC 4231 test case 2.
+        assert_eq!(
+            AnonymizeModifier::hmac("Jefe")?
+                .with_prefix("")
+                .surrogate(b"what do ya want for nothing?"),
+            "5bdcc146bf60754e6a042426089575c7"
+        );
+
+        let mut tsv = vec![];
+        pbp.record_modifier().write_mapping_tsv(&mut tsv)?;
+        let tsv = String::from_utf8(tsv)?;
+        assert_eq!(tsv.lines().count(), 201);
+        assert_eq!(tsv.lines().next(), Some("original\tsurrogate"));
+        assert_eq!(
+            tsv.lines().nth(1),
+            Some(format!("pair0\t{}", again.surrogate(b"pair0")).as_str())
+        );
+        assert!(again.write_mapping_tsv(&mut vec![]).is_err());
+
+        Ok(())
+    }
+
+    #[cfg(feature = "crypto")]
+    #[test]
+    fn test_anonymize_counter_and_header() -> Result<(), Error> {
+        let modifier = AnonymizeModifier::counter()
+            .with_removed_tags([*b"RX"])
+            .with_header_comments_stripped(true);
+
+        let mut r1 = record(b"A01:1:FC:1:1101:1000:2000");
+        r1.push_aux(b"RX", Aux::String("ACGT"))?;
+        r1.push_aux(b"OQ", Aux::String("IIII"))?;
+        let mut r2 = record(b"A01:1:FC:1:1101:1000:2000");
+        let mut other = record(b"A01:1:FC:1:1101:1200:2000");
+        for r in [&mut r1, &mut other, &mut r2] {
+            assert_eq!(modifier.modify_record(r)?, Some(()));
+        }
+        assert_eq!((r1.qname(), r2.qname()), (&b"read1"[..], &b"read1"[..]));
+        assert_eq!(other.qname(), b"read2");
+        assert!(r1.aux(b"RX").is_err());
+        assert_eq!(r1.aux(b"OQ")?, Aux::String("IIII"));
+
+        let header = HeaderView::from_bytes(
+            b"@HD\tVN:1.6\tSO:coordinate\n\
+              @SQ\tSN:chr1\tLN:1000\n\
+              @RG\tID:lane1\tSM:patient7\n\
+              @PG\tID:bwa\tPN:bwa\tCL:bwa mem /data/patient7_R1.fq.gz\n\
+              @CO\tsequenced for patient7\n",
+        );
+        let out = modifier.output_header(&header).to_bytes();
+        assert_eq!(
+            String::from_utf8(out)?,
+            "@HD\tVN:1.6\tSO:coordinate\n\
+             @SQ\tSN:chr1\tLN:1000\n\
+             @RG\tID:lane1\tSM:patient7\n\
+             @PG\tID:bwa\tPN:bwa"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_downsample_to_coverage() -> Result<(), Error> {
+        use rust_htslib::bam::Read as _;
+
+        use crate::{bam::process::ProcessBamOptions, test_utils::TestBam};
+
+        let dir = tempfile::tempdir()?;
+        // 10k reads of 100 bp over 100 kb: 10x.
+        let input_bam_path = TestBam::new()
+            .contig("chr1", 100_000)
+            .add_reads("chr1", 0, 10, 10_000, 100)
+            .build(dir.path())?;
+        let out_bam_path = dir.path().join("downsampled.bam");
+
+        downsample_to_coverage(
+            &input_bam_path,
+            &out_bam_path,
+            2.0,
+            1,
+            &ProcessBamOptions::default(),
+        )?;
+        let n_kept = rust_htslib::bam::Reader::from_path(&out_bam_path)?
+            .records()
+            .count();
+        assert!((1_850..=2_150).contains(&n_kept), "{} kept", n_kept);
+
+        // not deep enough: everything is kept.
+        downsample_to_coverage(
+            &input_bam_path,
+            &out_bam_path,
+            20.0,
+            1,
+            &ProcessBamOptions::default(),
+        )?;
+        let n_kept = rust_htslib::bam::Reader::from_path(&out_bam_path)?
+            .records()
+            .count();
+        assert_eq!(n_kept, 10_000);
+
+        assert!(
+            downsample_to_coverage(
+                &input_bam_path,
+                &out_bam_path,
+                0.0,
+                1,
+                &ProcessBamOptions::default()
+            )
+            .is_err()
+        );
+
+        Ok(())
+    }
+}
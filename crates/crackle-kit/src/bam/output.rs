@@ -0,0 +1,716 @@
+//! Outputs of [`ParallelBamProcessor::process_bam_to`], files or the stdin of a
+//! command, see [`OutputTarget`].
+//!
+//! [`ParallelBamProcessor::process_bam_to`]: crate::bam::process::ParallelBamProcessor::process_bam_to
+
+use std::{
+    collections::BTreeMap,
+    fs,
+    io::{self, PipeWriter, Read},
+    path::{Path, PathBuf},
+    process::{Child, Command, Stdio},
+    thread::{self, JoinHandle},
+};
+
+use anyhow::{Context, Error, anyhow, bail};
+use rust_htslib::{
+    bam::{self, Header, HeaderView, Record, Writer},
+    tpool::ThreadPool,
+};
+
+use crate::utils::atomic_write::AtomicFile;
+
+/// Placeholder of the contig name in [`OutputTarget::PerContig`] templates.
+pub const CONTIG_PLACEHOLDER: &str = "{contig}";
+
+/// Key of [`ProcessStats::records_per_contig`] for the reads without a contig.
+///
+/// [`ProcessStats::records_per_contig`]: crate::bam::process::ProcessStats::records_per_contig
+pub const UNMAPPED_CONTIG: &str = "*";
+
+/// Bytes of the stderr of an [`OutputTarget::Command`] kept for its error, the
+/// last ones.
+pub const COMMAND_STDERR_LIMIT: usize = 16 * 1024;
+
+/// Where [`ParallelBamProcessor::process_bam_to`] writes the records.
+///
+/// Every file is written next to its path and renamed to it once the run
+/// succeeds, see [`AtomicFile`]. A command is killed if the run fails.
+///
+/// [`ParallelBamProcessor::process_bam_to`]: crate::bam::process::ParallelBamProcessor::process_bam_to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OutputTarget {
+    /// A single file.
+    File(PathBuf),
+    /// A file per contig in `dir`, named by `template` with
+    /// [`CONTIG_PLACEHOLDER`] replaced by the contig name, e.g.
+    /// `sample.{contig}.bam`. Each file has the full header of the input, and
+    /// is only created once a record of its contig is written.
+    ///
+    /// The reads without a contig go to the file `unmapped` in `dir`. Reads
+    /// with one, unmapped or not, go to the file of their contig. If `index`
+    /// is set, a `.bai` index is built for each file once written; this needs
+    /// the BAM format and a coordinate-sorted input.
+    ///
+    /// A writer is kept open per contig, so inputs with thousands of contigs
+    /// may reach the limit of open files.
+    PerContig {
+        dir: PathBuf,
+        template: String,
+        unmapped: String,
+        index: bool,
+    },
+    /// The stdin of a command run with `argv`, e.g. `samtools sort -o out.bam -`,
+    /// spawned when the writer starts, without a shell. The bam is written in
+    /// the output format as to a file, and the command is waited for once the
+    /// records are written. A command failing, or exiting before all records
+    /// are written to it, fails the run with the end of its stderr, see
+    /// [`COMMAND_STDERR_LIMIT`]; its stdout is that of the process.
+    ///
+    /// Unix only, as the bam is written to the pipe by its `/dev/fd` path.
+    Command { argv: Vec<String> },
+}
+
+impl OutputTarget {
+    /// [`Self::PerContig`] without index, the reads without a contig going to
+    /// `template` named for the contig `unmapped`.
+    pub fn per_contig(dir: impl Into<PathBuf>, template: impl Into<String>) -> Self {
+        let template = template.into();
+        Self::PerContig {
+            dir: dir.into(),
+            unmapped: template.replace(CONTIG_PLACEHOLDER, "unmapped"),
+            template,
+            index: false,
+        }
+    }
+
+    /// Build a `.bai` index of each file of [`Self::PerContig`]. Ignored for
+    /// [`Self::File`].
+    pub fn with_index(mut self, index: bool) -> Self {
+        if let Self::PerContig { index: i, .. } = &mut self {
+            *i = index;
+        }
+        self
+    }
+
+    /// [`Self::Command`] running `argv`.
+    pub fn command<S: Into<String>>(argv: impl IntoIterator<Item = S>) -> Self {
+        Self::Command {
+            argv: argv.into_iter().map(Into::into).collect(),
+        }
+    }
+
+    /// Write the reads without a contig to `name` in the directory of
+    /// [`Self::PerContig`]. Ignored for [`Self::File`].
+    pub fn with_unmapped(mut self, name: impl Into<String>) -> Self {
+        if let Self::PerContig { unmapped, .. } = &mut self {
+            *unmapped = name.into();
+        }
+        self
+    }
+}
+
+impl From<PathBuf> for OutputTarget {
+    fn from(path: PathBuf) -> Self {
+        Self::File(path)
+    }
+}
+
+impl From<&Path> for OutputTarget {
+    fn from(path: &Path) -> Self {
+        Self::File(path.to_path_buf())
+    }
+}
+
+impl std::fmt::Display for OutputTarget {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::File(path) => write!(f, "{}", path.display()),
+            Self::PerContig { dir, template, .. } => {
+                write!(f, "{}", dir.join(template).display())
+            }
+            Self::Command { argv } => write!(f, "`{}`", argv.join(" ")),
+        }
+    }
+}
+
+/// An output being written, and its records.
+struct OpenOutput {
+    sink: Sink,
+    writer: Writer,
+    n_written: u64,
+}
+
+/// Where the writer of an [`OpenOutput`] writes.
+enum Sink {
+    File(AtomicFile),
+    Command(ChildCommand),
+}
+
+/// The command of an [`OutputTarget::Command`], killed when dropped before it
+/// is waited for.
+///
+/// The writer writes to a pipe of ours, forwarded to the stdin of the command
+/// by a thread, so that a command exiting before reading all the records is
+/// told apart from one which read them.
+struct ChildCommand {
+    child: Child,
+    /// The command line, for errors.
+    name: String,
+    forward: Option<JoinHandle<io::Result<u64>>>,
+    stderr: Option<JoinHandle<Vec<u8>>>,
+    exited: bool,
+}
+
+impl ChildCommand {
+    fn spawn(argv: &[String]) -> Result<(Self, PipeWriter), Error> {
+        let Some((program, args)) = argv.split_first() else {
+            bail!("No command to write the records to");
+        };
+        let name = format!("`{}`", argv.join(" "));
+        let mut child = Command::new(program)
+            .args(args)
+            .stdin(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .with_context(|| format!("Failed to run {}", name))?;
+
+        let (mut pipe, pipe_writer) = io::pipe()?;
+        let mut stdin = child.stdin.take().expect("stdin is piped");
+        // the pipe is closed on a failed write, failing the writes of the writer.
+        let forward = thread::spawn(move || io::copy(&mut pipe, &mut stdin));
+        let stderr = child
+            .stderr
+            .take()
+            .map(|stderr| thread::spawn(move || read_tail(stderr, COMMAND_STDERR_LIMIT)));
+        let command = Self {
+            child,
+            name,
+            forward: Some(forward),
+            stderr,
+            exited: false,
+        };
+        Ok((command, pipe_writer))
+    }
+
+    /// Wait for the command to exit, failing unless it read all the records
+    /// and succeeded. The writer must have been closed.
+    fn wait(&mut self) -> Result<(), Error> {
+        let forwarded = self
+            .forward
+            .take()
+            .map(|forward| {
+                forward
+                    .join()
+                    .map_err(|_| anyhow!("The thread writing to {} panicked", self.name))
+            })
+            .transpose()?;
+        let status = self
+            .child
+            .wait()
+            .with_context(|| format!("Failed to wait for {}", self.name))?;
+        self.exited = true;
+
+        if !status.success() {
+            let stderr = self
+                .stderr
+                .take()
+                .and_then(|stderr| stderr.join().ok())
+                .unwrap_or_default();
+            bail!(
+                "{} failed ({}): {}",
+                self.name,
+                status,
+                String::from_utf8_lossy(&stderr).trim()
+            );
+        }
+        if let Some(Err(err)) = forwarded {
+            return Err(
+                Error::new(err).context(format!("{} exited before reading all records", self.name))
+            );
+        }
+        Ok(())
+    }
+
+    /// The error of a failed write of the writer, mostly due to the command
+    /// having exited.
+    fn write_error(&mut self, err: Error) -> Error {
+        match self.wait() {
+            Err(command_err) => command_err,
+            Ok(()) => err.context(format!("Failed to write to {}", self.name)),
+        }
+    }
+}
+
+impl Drop for ChildCommand {
+    fn drop(&mut self) {
+        if !self.exited {
+            let _ = self.child.kill();
+            let _ = self.child.wait();
+        }
+    }
+}
+
+/// The last `limit` bytes of `reader`, read to its end.
+fn read_tail(mut reader: impl Read, limit: usize) -> Vec<u8> {
+    let mut tail = vec![];
+    let mut buf = [0; 4096];
+    while let Ok(n) = reader.read(&mut buf) {
+        if n == 0 {
+            break;
+        }
+        tail.extend_from_slice(&buf[..n]);
+        if tail.len() > 2 * limit {
+            tail.drain(..tail.len() - limit);
+        }
+    }
+    if tail.len() > limit {
+        tail.drain(..tail.len() - limit);
+    }
+    tail
+}
+
+/// htslib thread pool shared by the writers of [`OutputTarget::PerContig`].
+struct SharedPool(ThreadPool);
+
+// SAFETY: `ThreadPool` is an `Arc<RefCell>` of the htslib pool, which is itself
+// thread-safe. The pool and the writers holding clones of it only move together,
+// inside `OutputWriters`, and are used by one thread at a time; this is why
+// rust-htslib marks `Writer` as `Send` as well.
+unsafe impl Send for SharedPool {}
+
+/// Writers of an [`OutputTarget`], created as the first record of each file
+/// comes.
+pub(crate) struct OutputWriters<'a> {
+    target: &'a OutputTarget,
+    header: Header,
+    /// Contig names by tid, for the per-contig file names.
+    contigs: Vec<String>,
+    format: bam::Format,
+    write_threads: usize,
+    fsync: bool,
+    /// By tid + 1, the reads without a contig first; a single one for
+    /// [`OutputTarget::File`].
+    outputs: Vec<Option<OpenOutput>>,
+    // dropped after the writers.
+    pool: Option<SharedPool>,
+}
+
+impl<'a> OutputWriters<'a> {
+    pub(crate) fn new(
+        target: &'a OutputTarget,
+        header: &HeaderView,
+        format: bam::Format,
+        write_threads: usize,
+        fsync: bool,
+    ) -> Result<Self, Error> {
+        let contigs = header
+            .target_names()
+            .iter()
+            .map(|name| String::from_utf8_lossy(name).into_owned())
+            .collect::<Vec<_>>();
+        let n_outputs = match target {
+            OutputTarget::File(_) | OutputTarget::Command { .. } => 1,
+            OutputTarget::PerContig {
+                dir,
+                template,
+                index,
+                ..
+            } => {
+                if !template.contains(CONTIG_PLACEHOLDER) {
+                    bail!(
+                        "Template {} of the per-contig output has no {}",
+                        template,
+                        CONTIG_PLACEHOLDER
+                    );
+                }
+                if *index && format != bam::Format::Bam {
+                    bail!("Indexing the per-contig output needs the BAM format");
+                }
+                fs::create_dir_all(dir)
+                    .with_context(|| format!("Failed to create {}", dir.display()))?;
+                contigs.len() + 1
+            }
+        };
+
+        Ok(Self {
+            target,
+            header: Header::from_template(header),
+            contigs,
+            format,
+            write_threads,
+            fsync,
+            outputs: (0..n_outputs).map(|_| None).collect(),
+            pool: None,
+        })
+    }
+
+    /// Path of the output file at `slot`, see [`Self::outputs`].
+    fn path(&self, slot: usize) -> PathBuf {
+        match self.target {
+            OutputTarget::File(path) => path.clone(),
+            OutputTarget::PerContig { dir, unmapped, .. } if slot == 0 => dir.join(unmapped),
+            OutputTarget::PerContig { dir, template, .. } => {
+                dir.join(file_name(template, &self.contigs[slot - 1]))
+            }
+            OutputTarget::Command { .. } => unreachable!("commands have no path"),
+        }
+    }
+
+    fn open(&mut self, slot: usize) -> Result<&mut OpenOutput, Error> {
+        if self.outputs[slot].is_none() {
+            let (sink, mut writer) = match self.target {
+                OutputTarget::Command { argv } => self.spawn(argv)?,
+                _ => {
+                    let path = self.path(slot);
+                    let file = AtomicFile::create(&path)?.with_fsync(self.fsync);
+                    let writer = Writer::from_path(file.tmp_path(), &self.header, self.format)
+                        .with_context(|| format!("Failed to create {}", path.display()))?;
+                    (Sink::File(file), writer)
+                }
+            };
+
+            match self.target {
+                OutputTarget::File(_) | OutputTarget::Command { .. } if self.write_threads > 1 => {
+                    writer.set_threads(self.write_threads)?;
+                }
+                OutputTarget::PerContig { .. } if self.write_threads > 1 => {
+                    let pool = match &self.pool {
+                        Some(pool) => pool,
+                        None => self
+                            .pool
+                            .insert(SharedPool(ThreadPool::new(self.write_threads as u32)?)),
+                    };
+                    writer.set_thread_pool(&pool.0)?;
+                }
+                _ => {}
+            }
+
+            self.outputs[slot] = Some(OpenOutput {
+                sink,
+                writer,
+                n_written: 0,
+            });
+        }
+
+        Ok(self.outputs[slot].as_mut().unwrap())
+    }
+
+    /// Run the command of [`OutputTarget::Command`], with a writer to its stdin.
+    #[cfg(unix)]
+    fn spawn(&self, argv: &[String]) -> Result<(Sink, Writer), Error> {
+        use std::os::fd::AsRawFd;
+
+        let (command, pipe) = ChildCommand::spawn(argv)?;
+        // htslib opens the pipe anew by its path, so that the command gets the
+        // end of its input once the writer is closed, after `pipe`.
+        let path = format!("/dev/fd/{}", pipe.as_raw_fd());
+        let writer = Writer::from_path(&path, &self.header, self.format)
+            .with_context(|| format!("Failed to write to {}", command.name))?;
+        drop(pipe);
+        Ok((Sink::Command(command), writer))
+    }
+
+    #[cfg(not(unix))]
+    fn spawn(&self, _argv: &[String]) -> Result<(Sink, Writer), Error> {
+        bail!("Writing to a command is only supported on unix");
+    }
+
+    pub(crate) fn write(&mut self, record: &Record) -> Result<(), Error> {
+        let slot = match self.target {
+            OutputTarget::File(_) | OutputTarget::Command { .. } => 0,
+            OutputTarget::PerContig { .. } => (record.tid() + 1).max(0) as usize,
+        };
+        let output = self.open(slot)?;
+        if let Err(err) = output.writer.write(record) {
+            return Err(match &mut output.sink {
+                Sink::Command(command) => command.write_error(err.into()),
+                Sink::File(_) => err.into(),
+            });
+        }
+        output.n_written += 1;
+        Ok(())
+    }
+
+    /// Close the writers and move the files to their paths, indexing them if
+    /// asked. Returns the records written per contig of
+    /// [`OutputTarget::PerContig`], or nothing for [`OutputTarget::File`].
+    ///
+    /// A single file is written even without records, as is the input of a
+    /// command.
+    pub(crate) fn finish(mut self) -> Result<BTreeMap<String, u64>, Error> {
+        if let OutputTarget::File(_) | OutputTarget::Command { .. } = self.target {
+            self.open(0)?;
+        }
+
+        let mut written = vec![];
+        for (slot, output) in self.outputs.iter_mut().enumerate() {
+            let Some(OpenOutput {
+                sink,
+                writer,
+                n_written,
+            }) = output.take()
+            else {
+                continue;
+            };
+            // closing the writer writes the end of the bgzf stream.
+            drop(writer);
+            match sink {
+                Sink::File(file) => {
+                    let path = file.path().to_path_buf();
+                    file.commit()?;
+                    written.push((slot, path, n_written));
+                }
+                Sink::Command(mut command) => command.wait()?,
+            }
+        }
+
+        let mut counts = BTreeMap::new();
+        if let OutputTarget::PerContig { index, .. } = self.target {
+            for (slot, path, n_written) in written {
+                if *index {
+                    bam::index::build(&path, None, bam::index::Type::Bai, 1)
+                        .with_context(|| format!("Failed to index {}", path.display()))?;
+                }
+                let contig = match slot {
+                    0 => UNMAPPED_CONTIG.to_string(),
+                    _ => self.contigs[slot - 1].clone(),
+                };
+                counts.insert(contig, n_written);
+            }
+        }
+
+        Ok(counts)
+    }
+}
+
+fn file_name(template: &str, contig: &str) -> String {
+    template.replace(CONTIG_PLACEHOLDER, contig)
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_htslib::bam::{IndexedReader, Read as _, Reader};
+
+    use super::*;
+    use crate::{
+        bam::{
+            filter::ReadFilter,
+            process::{ParallelBamProcessor, ProcessBamOptions, RecordModifier},
+        },
+        test_utils::TestBam,
+    };
+
+    /// Drops the reads at positions multiple of 100.
+    struct DropSome;
+
+    impl RecordModifier for DropSome {
+        type Error = Error;
+
+        fn modify_record(&self, record: &mut Record) -> Result<Option<()>, Error> {
+            Ok((record.pos() % 100 != 0).then_some(()))
+        }
+    }
+
+    /// (tid, pos) of the records of `path`.
+    fn read_loci(path: impl AsRef<Path>) -> Result<Vec<(i32, i64)>, Error> {
+        let mut reader = Reader::from_path(path)?;
+        let mut loci = vec![];
+        for record in reader.records() {
+            let record = record?;
+            loci.push((record.tid(), record.pos()));
+        }
+        Ok(loci)
+    }
+
+    #[test]
+    fn test_per_contig_output() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = tempfile::tempdir()?;
+        let bam_path = TestBam::new()
+            .contig("chr1", 100_000)
+            .contig("chr2", 100_000)
+            .contig("chr3", 100_000)
+            .add_reads("chr1", 0, 10, 3_000, 50)
+            .add_reads("chr2", 5, 20, 1_000, 50)
+            .add_unplaced(b"ACGT", &[30; 4])
+            .add_unplaced(b"TTTT", &[30; 4])
+            .build(dir.path())?;
+
+        let out_dir = dir.path().join("split");
+        let output = OutputTarget::per_contig(&out_dir, "sample.{contig}.bam")
+            .with_unmapped("sample.unplaced.bam")
+            .with_index(true);
+        let opts = ProcessBamOptions {
+            batch_size: 64,
+            ..Default::default()
+        };
+        let stats =
+            ParallelBamProcessor::new(DropSome).process_bam_to(&bam_path, &output, &opts)?;
+
+        assert_eq!(stats.records_read, 4_002);
+        assert_eq!(
+            stats.records_per_contig,
+            BTreeMap::from([
+                ("chr1".to_string(), 2_700),
+                ("chr2".to_string(), 1_000),
+                (UNMAPPED_CONTIG.to_string(), 2),
+            ])
+        );
+        assert_eq!(
+            stats.records_per_contig.values().sum::<u64>(),
+            stats.records_written
+        );
+
+        // only the reads of its contig in each file, in input order.
+        let expected = read_loci(&bam_path)?
+            .into_iter()
+            .filter(|(_, pos)| pos % 100 != 0)
+            .collect::<Vec<_>>();
+        let mut loci = vec![];
+        for name in ["sample.chr1.bam", "sample.chr2.bam", "sample.unplaced.bam"] {
+            let file_loci = read_loci(out_dir.join(name))?;
+            assert!(file_loci.iter().all(|(tid, _)| *tid == file_loci[0].0));
+            loci.extend(file_loci);
+        }
+        assert_eq!(loci, expected);
+        // no read, no file.
+        assert!(!out_dir.join("sample.chr3.bam").exists());
+
+        // with the full header, and indexed.
+        let mut reader = IndexedReader::from_path(out_dir.join("sample.chr2.bam"))?;
+        assert_eq!(reader.header().target_count(), 3);
+        reader.fetch(("chr2", 0, 1_000))?;
+        assert_eq!(reader.records().count(), 50);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_per_contig_errors() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = tempfile::tempdir()?;
+        let bam_path = TestBam::new()
+            .add_reads("chr1", 0, 10, 100, 50)
+            .build(dir.path())?;
+        let processor = ParallelBamProcessor::new(DropSome);
+        let opts = ProcessBamOptions::default();
+
+        let output = OutputTarget::per_contig(dir.path(), "sample.bam");
+        let err = processor
+            .process_bam_to(&bam_path, &output, &opts)
+            .unwrap_err();
+        assert!(err.to_string().contains("has no {contig}"), "{:#}", err);
+
+        let output = OutputTarget::per_contig(dir.path(), "sample.{contig}.sam").with_index(true);
+        let opts = ProcessBamOptions {
+            output_format: bam::Format::Sam,
+            ..Default::default()
+        };
+        assert!(processor.process_bam_to(&bam_path, &output, &opts).is_err());
+
+        // a single file is written without records as well.
+        let out = dir.path().join("out.bam");
+        let stats = processor.process_bam_to(
+            &bam_path,
+            &OutputTarget::File(out.clone()),
+            &ProcessBamOptions {
+                read_filter: Some(ReadFilter::default().with_min_mapq(61)),
+                ..Default::default()
+            },
+        )?;
+        assert_eq!(stats.records_written, 0);
+        assert!(stats.records_per_contig.is_empty());
+        assert!(read_loci(&out)?.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_command_output() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = tempfile::tempdir()?;
+        let bam_path = TestBam::new()
+            .add_reads("chr1", 0, 10, 2_000, 50)
+            .build(dir.path())?;
+        let processor = ParallelBamProcessor::new(DropSome);
+        let opts = ProcessBamOptions {
+            batch_size: 64,
+            ..Default::default()
+        };
+
+        let out = dir.path().join("copy.bam");
+        let copy = format!("cat > {}", out.display());
+        let output = OutputTarget::command(["sh", "-c", copy.as_str()]);
+        let stats = processor.process_bam_to(&bam_path, &output, &opts)?;
+        assert_eq!(stats.records_written, 1_800);
+        assert_eq!(read_loci(&out)?.len(), 1_800);
+
+        let output = OutputTarget::command(["false"]);
+        let err = processor
+            .process_bam_to(&bam_path, &output, &opts)
+            .unwrap_err();
+        assert!(err.to_string().contains("`false` failed"), "{:#}", err);
+
+        let output = OutputTarget::command(["sh", "-c", "echo oops >&2; exit 3"]);
+        let err = processor
+            .process_bam_to(&bam_path, &output, &opts)
+            .unwrap_err();
+        assert!(err.to_string().contains("oops"), "{:#}", err);
+
+        // exiting before the end of the input is a failure even with status 0,
+        // the records being larger than the buffer of the pipe.
+        let output = OutputTarget::command(["sh", "-c", "head -c 100 > /dev/null"]);
+        let sam_opts = ProcessBamOptions {
+            output_format: bam::Format::Sam,
+            ..opts.clone()
+        };
+        let err = processor
+            .process_bam_to(&bam_path, &output, &sam_opts)
+            .unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("exited before reading all records"),
+            "{:#}",
+            err
+        );
+
+        let output = OutputTarget::command(Vec::<String>::new());
+        assert!(processor.process_bam_to(&bam_path, &output, &opts).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_command_output_samtools() -> Result<(), Box<dyn std::error::Error>> {
+        let has_samtools = Command::new("samtools")
+            .arg("--version")
+            .stdout(Stdio::null())
+            .status()
+            .is_ok_and(|status| status.success());
+        if !has_samtools {
+            eprintln!("samtools not found, skipped");
+            return Ok(());
+        }
+
+        let dir = tempfile::tempdir()?;
+        let bam_path = TestBam::new()
+            .add_reads("chr1", 0, 10, 500, 50)
+            .build(dir.path())?;
+        let count = dir.path().join("count.txt");
+        let script = format!("samtools view -c - > {}", count.display());
+        let output = OutputTarget::command(["sh", "-c", script.as_str()]);
+        ParallelBamProcessor::new(DropSome).process_bam_to(
+            &bam_path,
+            &output,
+            &ProcessBamOptions::default(),
+        )?;
+        assert_eq!(fs::read_to_string(&count)?.trim(), "450");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_tail() {
+        let input = (0..10_000u32).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+        assert_eq!(read_tail(&input[..], 100), &input[9_900..]);
+        assert_eq!(read_tail(&input[..10], 100), &input[..10]);
+    }
+}
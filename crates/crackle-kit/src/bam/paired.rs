@@ -0,0 +1,728 @@
+//! Processing both mates of a template at once, with
+//! [`ParallelBamProcessor::process_bam_paired`].
+
+use std::{
+    collections::{BTreeMap, HashMap, VecDeque},
+    mem,
+    path::Path,
+    rc::Rc,
+    sync::atomic,
+    thread,
+};
+
+use anyhow::{Context, Error, bail};
+use crossbeam_channel::bounded;
+use rust_htslib::bam::{self, Header, HeaderView, Read as _, Record, Writer};
+use tracing::{Level, event, span};
+
+#[cfg(doc)]
+use crate::bam::process::RecordModifier;
+use crate::{
+    bam::headerless::HeaderlessRecord,
+    bam::process::{
+        AtomicProcessStats, DeadLetterWriter, OnModifyError, ParallelBamProcessor, PipelineControl,
+        ProcessBamOptions, ProcessStats, budgeted_stage_threads, record_error,
+    },
+    data::data_with_index::DataWithIndex,
+    utils::{
+        atomic_write::AtomicFile,
+        instrument::{current_dispatch, enter_on_thread},
+        panic::catch_panic,
+        pipeline::{PipelineFailure, PipelineStage, joined},
+    },
+};
+
+/// Which mates of a pair [`ParallelBamProcessor::process_bam_paired`] writes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PairDecision {
+    KeepBoth,
+    DropBoth,
+    KeepFirst,
+    KeepSecond,
+}
+
+impl PairDecision {
+    /// Whether the first and the second mate are kept.
+    fn keeps(self) -> (bool, bool) {
+        match self {
+            PairDecision::KeepBoth => (true, true),
+            PairDecision::DropBoth => (false, false),
+            PairDecision::KeepFirst => (true, false),
+            PairDecision::KeepSecond => (false, true),
+        }
+    }
+}
+
+/// Modifies the two mates of a template together, e.g. to set their flags
+/// consistently or to copy tags from one to the other.
+pub trait PairedRecordModifier: Send + Sync {
+    type Error: Into<Error>;
+
+    /// Modify both mates. `r1` is the first read of the template (flag `0x40`), or
+    /// the first in the input if the flags do not tell.
+    fn modify_pair(&self, r1: &mut Record, r2: &mut Record) -> Result<PairDecision, Self::Error>;
+
+    /// Modify a record with no mate to pair with: unpaired reads, secondary and
+    /// supplementary alignments, and reads whose mate is not in the input.
+    /// `None` drops it, as in [`RecordModifier::modify_record`]. By default the
+    /// record is kept as it is.
+    fn modify_single(&self, record: &mut Record) -> Result<Option<()>, Self::Error> {
+        let _ = record;
+        Ok(Some(()))
+    }
+}
+
+/// How [`ParallelBamProcessor::process_bam_paired`] holds reads waiting for their mate.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PairingOptions {
+    /// Records read past the oldest read still waiting for its mate. The writer
+    /// keeps them in memory to write them in order, so this bounds the memory use.
+    pub max_in_flight: usize,
+    pub on_overflow: OnPairOverflow,
+}
+
+impl Default for PairingOptions {
+    fn default() -> Self {
+        Self {
+            max_in_flight: 1_000_000,
+            on_overflow: OnPairOverflow::default(),
+        }
+    }
+}
+
+/// What to do with a read which waits for its mate past
+/// [`PairingOptions::max_in_flight`], e.g. in a coordinate-sorted input whose
+/// mates are on different contigs.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OnPairOverflow {
+    /// Stop processing and return an error.
+    #[default]
+    Fail,
+    /// Process the read with [`PairedRecordModifier::modify_single`]. Its mate,
+    /// when it comes, is processed alone as well.
+    ProcessAsSingle,
+}
+
+/// Records processed together, tagged with their index in the input.
+enum Template {
+    Pair(
+        DataWithIndex<HeaderlessRecord>,
+        DataWithIndex<HeaderlessRecord>,
+    ),
+    Single(DataWithIndex<HeaderlessRecord>),
+}
+
+impl Template {
+    /// Pair two mates, the first read of the template first.
+    fn pair(a: DataWithIndex<HeaderlessRecord>, b: DataWithIndex<HeaderlessRecord>) -> Self {
+        if b.data().is_first_in_template() && !a.data().is_first_in_template() {
+            Template::Pair(b, a)
+        } else {
+            Template::Pair(a, b)
+        }
+    }
+
+    fn len(&self) -> usize {
+        match self {
+            Template::Pair(..) => 2,
+            Template::Single(_) => 1,
+        }
+    }
+
+    fn into_records(self) -> impl Iterator<Item = DataWithIndex<HeaderlessRecord>> {
+        let (a, b) = match self {
+            Template::Pair(a, b) => (a, Some(b)),
+            Template::Single(a) => (a, None),
+        };
+        std::iter::once(a).chain(b)
+    }
+}
+
+/// Whether `record` has a mate to wait for.
+fn is_pairable(record: &Record) -> bool {
+    record.is_paired() && !record.is_secondary() && !record.is_supplementary()
+}
+
+/// Reads waiting for their mate, by name.
+#[derive(Default)]
+struct MateBuffer {
+    waiting: HashMap<Vec<u8>, DataWithIndex<HeaderlessRecord>>,
+    /// Index and name of the waiting reads, oldest first. Entries of reads paired
+    /// since are removed when they reach the front.
+    order: VecDeque<(usize, Vec<u8>)>,
+}
+
+impl MateBuffer {
+    /// Pair `record` with its waiting mate, or keep it waiting.
+    fn push(&mut self, record: DataWithIndex<HeaderlessRecord>) -> Option<Template> {
+        match self.waiting.remove(record.data().qname()) {
+            Some(mate) => Some(Template::pair(mate, record)),
+            None => {
+                let qname = record.data().qname().to_vec();
+                self.order.push_back((record.idx, qname.clone()));
+                self.waiting.insert(qname, record);
+                None
+            }
+        }
+    }
+
+    /// Index of the oldest waiting read.
+    fn oldest(&mut self) -> Option<usize> {
+        while let Some((idx, qname)) = self.order.front() {
+            if self.waiting.get(qname).is_some_and(|r| r.idx == *idx) {
+                return Some(*idx);
+            }
+            self.order.pop_front();
+        }
+        None
+    }
+
+    fn pop_oldest(&mut self) -> Option<DataWithIndex<HeaderlessRecord>> {
+        self.oldest()?;
+        let (_, qname) = self.order.pop_front()?;
+        self.waiting.remove(&qname)
+    }
+
+    /// Reads whose mate never came.
+    fn into_waiting(self) -> impl Iterator<Item = DataWithIndex<HeaderlessRecord>> {
+        self.waiting.into_values()
+    }
+}
+
+impl<R: PairedRecordModifier> ParallelBamProcessor<R> {
+    /// Like [`ParallelBamProcessor::process_bam`], calling [`PairedRecordModifier::modify_pair`]
+    /// with both mates of each template.
+    ///
+    /// Reads wait in memory until their mate is read, so the input can be sorted
+    /// by name or by coordinate, and needs no index. Records are written in their
+    /// input order whichever mates are kept. Reads still waiting at the end of the
+    /// input go to [`PairedRecordModifier::modify_single`].
+    ///
+    /// An error of `modify_pair` fails both mates, which are handled together
+    /// according to [`ProcessBamOptions::on_modify_error`].
+    pub fn process_bam_paired(
+        &self,
+        input_bam_path: impl AsRef<Path>,
+        out_bam_path: impl AsRef<Path>,
+        opts: &ProcessBamOptions,
+    ) -> Result<ProcessStats, crate::Error> {
+        let input_bam_path = input_bam_path.as_ref();
+        let out_bam_path = out_bam_path.as_ref();
+        let ProcessBamOptions {
+            batch_size,
+            channel_capacity,
+            output_format,
+            ref on_modify_error,
+            fsync,
+            ref pairing,
+            ..
+        } = *opts;
+        let ([read_threads, worker_threads, write_threads], _guard) =
+            budgeted_stage_threads(opts, self.thread_budget.as_ref())?;
+
+        let mut reader = opts
+            .retry_policy
+            .run(format_args!("Opening {}", input_bam_path.display()), || {
+                bam::Reader::from_path(input_bam_path)
+            })
+            .with_context(|| format!("Failed to open {}", input_bam_path.display()))?;
+        opts.check_dict(reader.header(), input_bam_path)?;
+        if read_threads > 1 {
+            reader.set_threads(read_threads)?;
+        }
+        let header_bytes = reader.header().as_bytes().to_vec();
+        let dead_letter = DeadLetterWriter::new(on_modify_error, &header_bytes);
+
+        let (tx_read, rx_read) = bounded::<Vec<Template>>(channel_capacity);
+        let (tx_worker, rx_worker) = bounded::<Vec<Template>>(channel_capacity);
+
+        let control = PipelineControl::default();
+        let failure = PipelineFailure::new(&control.cancelled);
+
+        let process_span = span!(
+            Level::INFO,
+            "process_bam_paired",
+            input = %input_bam_path.display(),
+            output = %out_bam_path.display(),
+            worker_threads = worker_threads,
+        );
+        let _process_span = process_span.enter();
+        let dispatch = current_dispatch();
+
+        let (results, out_file) = thread::scope(|s| {
+            let (failure, stats) = (&failure, &control.stats);
+            let (header_bytes, dead_letter) = (&header_bytes, &dead_letter);
+            let (process_span, dispatch) = (&process_span, &dispatch);
+
+            let reader_handle = s.spawn(move || {
+                let _stage = enter_on_thread(
+                    dispatch,
+                    || span!(parent: process_span, Level::DEBUG, "reader"),
+                );
+                let res = catch_panic(|| {
+                    let mut mates = MateBuffer::default();
+                    let mut batch = Vec::with_capacity(batch_size);
+                    let mut n_batched = 0;
+                    let mut i = 0;
+
+                    loop {
+                        if failure.is_set() {
+                            event!(Level::DEBUG, "Reader stops: downstream failed.");
+                            return Ok(());
+                        }
+
+                        let mut record = HeaderlessRecord::new();
+                        match record.read_from(&mut reader) {
+                            Some(Ok(())) => {}
+                            Some(Err(e)) => {
+                                event!(Level::WARN, "Error reading record: {:?}", e);
+                                continue;
+                            }
+                            None => break,
+                        }
+                        let pairable = is_pairable(&record);
+                        let record = DataWithIndex::new(record, i);
+                        i += 1;
+
+                        let n_templates = batch.len();
+                        if pairable {
+                            batch.extend(mates.push(record));
+                        } else {
+                            batch.push(Template::Single(record));
+                        }
+
+                        while let Some(oldest) = mates.oldest() {
+                            if i - oldest <= pairing.max_in_flight {
+                                break;
+                            }
+                            let waiting = mates.pop_oldest().expect("a read is waiting");
+                            if pairing.on_overflow == OnPairOverflow::Fail {
+                                bail!(
+                                    "Read {} waited for its mate for more than {} records. \
+                                     Sort the input by name, or raise `max_in_flight`",
+                                    String::from_utf8_lossy(waiting.data().qname()),
+                                    pairing.max_in_flight
+                                );
+                            }
+                            batch.push(Template::Single(waiting));
+                        }
+
+                        n_batched += batch[n_templates..]
+                            .iter()
+                            .map(Template::len)
+                            .sum::<usize>();
+                        if n_batched >= batch_size {
+                            stats
+                                .records_read
+                                .store(i as u64, atomic::Ordering::Relaxed);
+                            if tx_read.send(mem::take(&mut batch)).is_err() {
+                                return Ok(());
+                            }
+                            n_batched = 0;
+                        }
+                    }
+
+                    stats
+                        .records_read
+                        .store(i as u64, atomic::Ordering::Relaxed);
+                    for waiting in mates.into_waiting() {
+                        batch.push(Template::Single(waiting));
+                        n_batched += 1;
+                        if n_batched >= batch_size {
+                            if tx_read.send(mem::take(&mut batch)).is_err() {
+                                return Ok(());
+                            }
+                            n_batched = 0;
+                        }
+                    }
+                    if !batch.is_empty() {
+                        let _ = tx_read.send(batch);
+                    }
+
+                    event!(Level::DEBUG, "Reader thread ended.");
+                    Ok::<(), Error>(())
+                })
+                .unwrap_or_else(|panicked| Err(panicked.into()));
+
+                failure.mark_if_err(PipelineStage::Reader, res)
+            });
+
+            let mut worker_handles = Vec::with_capacity(worker_threads);
+            for worker_i in 0..worker_threads {
+                let rx_read = rx_read.clone();
+                let tx_worker = tx_worker.clone();
+
+                worker_handles.push(s.spawn(move || {
+                    let _stage = enter_on_thread(
+                        dispatch,
+                        || span!(parent: process_span, Level::DEBUG, "worker", worker = worker_i),
+                    );
+                    let res = catch_panic(|| {
+                        let header_view = Rc::new(HeaderView::from_bytes(header_bytes));
+
+                        for mut batch in rx_read.iter() {
+                            if failure.is_set() {
+                                break;
+                            }
+                            for template in batch.iter_mut() {
+                                self.modify_template(
+                                    template,
+                                    &header_view,
+                                    stats,
+                                    dead_letter.as_ref(),
+                                    on_modify_error,
+                                )?;
+                            }
+                            if tx_worker.send(batch).is_err() {
+                                // the writer is gone, which only happens after a failure.
+                                break;
+                            }
+                        }
+
+                        Ok::<(), Error>(())
+                    })
+                    .unwrap_or_else(|panicked| Err(panicked.into()));
+
+                    failure.mark_if_err(PipelineStage::Worker(worker_i), res)
+                }));
+            }
+
+            drop(rx_read);
+            drop(tx_worker);
+
+            let writer_handle = s.spawn(move || {
+                let _stage = enter_on_thread(
+                    dispatch,
+                    || span!(parent: process_span, Level::DEBUG, "writer"),
+                );
+                let res = catch_panic(|| {
+                    let header = Header::from_template(&HeaderView::from_bytes(header_bytes));
+                    let out_file = AtomicFile::create(out_bam_path)?.with_fsync(fsync);
+                    let mut writer =
+                        Writer::from_path(out_file.tmp_path(), &header, output_format)?;
+                    if write_threads > 1 {
+                        writer.set_threads(write_threads)?;
+                    }
+
+                    // records come by template, in the order the workers finish them.
+                    let mut pending = BTreeMap::new();
+                    let mut next_idx = 0;
+                    let mut n_written = 0;
+
+                    for batch in rx_worker.iter() {
+                        if failure.is_set() {
+                            event!(Level::DEBUG, "Writer stops: another stage failed.");
+                            return Ok(out_file);
+                        }
+
+                        for record in batch.into_iter().flat_map(Template::into_records) {
+                            pending.insert(record.idx, record);
+                        }
+                        while let Some(record) = pending.remove(&next_idx) {
+                            if !record.skip {
+                                writer.write(record.data())?;
+                                n_written += 1;
+                            }
+                            next_idx += 1;
+                        }
+                        stats
+                            .records_written
+                            .store(n_written, atomic::Ordering::Relaxed);
+                    }
+
+                    debug_assert!(pending.is_empty() || failure.is_set());
+
+                    drop(writer);
+                    Ok::<AtomicFile, Error>(out_file)
+                })
+                .unwrap_or_else(|panicked| Err(panicked.into()));
+
+                failure.mark_if_err(PipelineStage::Writer, res)
+            });
+
+            let mut results = Vec::with_capacity(worker_threads + 2);
+            results.push((PipelineStage::Reader, joined(reader_handle.join())));
+            for (worker_i, handle) in worker_handles.into_iter().enumerate() {
+                results.push((PipelineStage::Worker(worker_i), joined(handle.join())));
+            }
+            let (writer_res, out_file) = match joined(writer_handle.join()) {
+                Ok(out_file) => (Ok(()), Some(out_file)),
+                Err(err) => (Err(err), None),
+            };
+            results.push((PipelineStage::Writer, writer_res));
+
+            (results, out_file)
+        });
+
+        failure.into_result(results)?;
+        out_file
+            .expect("the writer returns its output when it succeeds")
+            .commit()?;
+
+        Ok(control.stats.snapshot())
+    }
+
+    /// Call the modifier on `template`, flagging the records it drops.
+    fn modify_template(
+        &self,
+        template: &mut Template,
+        header_view: &Rc<HeaderView>,
+        stats: &AtomicProcessStats,
+        dead_letter: Option<&DeadLetterWriter>,
+        on_modify_error: &OnModifyError,
+    ) -> Result<(), Error> {
+        let mut records = match template {
+            Template::Pair(r1, r2) => vec![r1, r2],
+            Template::Single(r) => vec![r],
+        };
+        // the dead letter bam gets the records as they were read.
+        let originals =
+            dead_letter.map(|_| records.iter().map(|r| r.data().clone()).collect::<Vec<_>>());
+
+        // a panic of the modifier fails the template like an error.
+        let res = catch_panic(|| {
+            match records.as_mut_slice() {
+                [r1, r2] => self
+                    .record_modifier()
+                    .modify_pair(
+                        &mut r1.data_mut().with_header(Rc::clone(header_view)),
+                        &mut r2.data_mut().with_header(Rc::clone(header_view)),
+                    )
+                    .map(PairDecision::keeps),
+                [r] => self
+                    .record_modifier()
+                    .modify_single(&mut r.data_mut().with_header(Rc::clone(header_view)))
+                    .map(|kept| (kept.is_some(), false)),
+                _ => unreachable!("a template has one or two records"),
+            }
+            .map_err(Into::into)
+        })
+        .unwrap_or_else(|panicked| Err(panicked.into()));
+
+        match res {
+            Ok(keeps) => {
+                for (r, keep) in records.iter_mut().zip([keeps.0, keeps.1]) {
+                    r.skip = !keep;
+                    if !keep {
+                        stats
+                            .records_dropped
+                            .fetch_add(1, atomic::Ordering::Relaxed);
+                    }
+                }
+            }
+            Err(err) => {
+                let err = record_error(err, header_view, records[0].data());
+                stats
+                    .records_failed
+                    .fetch_add(records.len() as u64, atomic::Ordering::Relaxed);
+                if *on_modify_error == OnModifyError::Fail {
+                    return Err(err);
+                }
+                event!(Level::WARN, "{:#}. drop this template", err);
+
+                if let (Some(dead_letter), Some(originals)) = (dead_letter, &originals) {
+                    for original in originals {
+                        dead_letter.write(original)?;
+                    }
+                }
+                for r in records.iter_mut() {
+                    r.skip = true;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_htslib::bam::record::Aux;
+
+    use super::*;
+    use crate::test_utils::TestBam;
+
+    /// Tags each mate with the position of the other. Drops the pairs starting at
+    /// 200 and fails on those starting at 500.
+    struct TagMates;
+
+    impl PairedRecordModifier for TagMates {
+        type Error = Error;
+
+        fn modify_pair(&self, r1: &mut Record, r2: &mut Record) -> Result<PairDecision, Error> {
+            assert!(r1.is_first_in_template() && r2.is_last_in_template());
+            match r1.pos() {
+                200 => return Ok(PairDecision::DropBoth),
+                500 => bail!("bad pair"),
+                _ => {}
+            }
+            let (pos1, pos2) = (r1.pos(), r2.pos());
+            r1.push_aux(b"mp", Aux::I32(pos2 as i32))?;
+            r2.push_aux(b"mp", Aux::I32(pos1 as i32))?;
+            Ok(PairDecision::KeepBoth)
+        }
+
+        fn modify_single(&self, record: &mut Record) -> Result<Option<()>, Error> {
+            record.push_aux(b"sg", Aux::U8(1))?;
+            Ok(Some(()))
+        }
+    }
+
+    /// Name, position, `mp` tag and whether the `sg` tag is set.
+    type OutRecord = (String, i64, Option<i64>, bool);
+
+    fn read_output(path: &Path) -> Result<Vec<OutRecord>, Error> {
+        let mut reader = bam::Reader::from_path(path)?;
+        reader
+            .records()
+            .map(|r| {
+                let r = r?;
+                let mate_pos = match r.aux(b"mp") {
+                    Ok(Aux::I32(pos)) => Some(pos as i64),
+                    _ => None,
+                };
+                Ok((
+                    String::from_utf8(r.qname().to_vec())?,
+                    r.pos(),
+                    mate_pos,
+                    r.aux(b"sg").is_ok(),
+                ))
+            })
+            .collect()
+    }
+
+    fn fixture() -> TestBam {
+        let seq = b"ACGT".repeat(10);
+        TestBam::new()
+            .add_pair("chr1", 100, 300, 40, 0)
+            // the second read comes first by coordinate.
+            .add_pair("chr1", 150, 120, 40, 0)
+            .add_pair("chr1", 200, 400, 40, 0)
+            .add_read("chr1", 250, &seq, &[30; 40], 0)
+            // mate unmapped and not in the input.
+            .add_read("chr1", 260, &seq, &[30; 40], 0x1 | 0x8 | 0x40)
+            .add_pair("chr1", 500, 520, 40, 0)
+    }
+
+    #[test]
+    fn test_process_bam_paired() -> Result<(), Error> {
+        let dir = tempfile::tempdir()?;
+        let by_coordinate = fixture().build(dir.path())?;
+        let by_name = fixture().build_name_sorted(dir.path())?;
+        let out_bam_path = dir.path().join("out.bam");
+        let opts = ProcessBamOptions {
+            worker_threads: 3,
+            batch_size: 2,
+            channel_capacity: 2,
+            ..Default::default()
+        };
+        let pbp = ParallelBamProcessor::new(TagMates);
+
+        for input in [&by_coordinate, &by_name] {
+            let stats = pbp.process_bam_paired(input, &out_bam_path, &opts)?;
+            assert_eq!(
+                stats,
+                ProcessStats {
+                    records_read: 10,
+                    records_written: 6,
+                    records_dropped: 2,
+                    records_failed: 2,
+                    ..Default::default()
+                }
+            );
+
+            let mut expected = vec![
+                ("pair0", 100, Some(300), false),
+                ("pair0", 300, Some(100), false),
+                ("pair2", 150, Some(120), false),
+                ("pair2", 120, Some(150), false),
+                ("read6", 250, None, true),
+                ("read7", 260, None, true),
+            ];
+            if input == &by_coordinate {
+                expected.sort_by_key(|r| r.1);
+            }
+            let expected = expected
+                .into_iter()
+                .map(|(qname, pos, mp, sg)| (qname.to_string(), pos, mp, sg))
+                .collect::<Vec<_>>();
+            assert_eq!(read_output(&out_bam_path)?, expected);
+        }
+
+        let err = pbp
+            .process_bam_paired(
+                &by_name,
+                &out_bam_path,
+                &ProcessBamOptions {
+                    on_modify_error: OnModifyError::Fail,
+                    ..Default::default()
+                },
+            )
+            .unwrap_err();
+        let msg = format!("{:#}", err);
+        assert!(msg.contains("worker"), "{}", msg);
+        assert!(
+            msg.ends_with("Failed to process read pair8 at chr1:501: bad pair"),
+            "{}",
+            msg
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_process_bam_paired_header_only() -> Result<(), Error> {
+        let dir = tempfile::tempdir()?;
+        let input_bam_path = TestBam::new().contig("chr1", 1_000).build(dir.path())?;
+        let out_bam_path = dir.path().join("out.bam");
+
+        let stats = ParallelBamProcessor::new(TagMates).process_bam_paired(
+            &input_bam_path,
+            &out_bam_path,
+            &ProcessBamOptions::default(),
+        )?;
+        assert_eq!(stats, ProcessStats::default());
+        let reader = bam::Reader::from_path(&out_bam_path)?;
+        assert_eq!(reader.header().target_names(), [b"chr1"]);
+        assert!(read_output(&out_bam_path)?.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_pair_overflow() -> Result<(), Error> {
+        let dir = tempfile::tempdir()?;
+        // 20 reads between the mates.
+        let input_bam_path = TestBam::new()
+            .add_pair("chr1", 100, 1_000, 40, 0)
+            .add_reads("chr1", 200, 10, 20, 40)
+            .build(dir.path())?;
+        let out_bam_path = dir.path().join("out.bam");
+        let pbp = ParallelBamProcessor::new(TagMates);
+
+        let mut opts = ProcessBamOptions::default();
+        opts.pairing.max_in_flight = 30;
+        pbp.process_bam_paired(&input_bam_path, &out_bam_path, &opts)?;
+        let out = read_output(&out_bam_path)?;
+        assert_eq!(out[0], ("pair0".to_string(), 100, Some(1_000), false));
+
+        opts.pairing.max_in_flight = 10;
+        let err = pbp
+            .process_bam_paired(&input_bam_path, &out_bam_path, &opts)
+            .unwrap_err();
+        assert!(
+            format!("{:#}", err).contains("Read pair0 waited for its mate"),
+            "{:#}",
+            err
+        );
+
+        opts.pairing.on_overflow = OnPairOverflow::ProcessAsSingle;
+        let stats = pbp.process_bam_paired(&input_bam_path, &out_bam_path, &opts)?;
+        assert_eq!(stats.records_written, 22);
+        let out = read_output(&out_bam_path)?;
+        assert_eq!(out[0], ("pair0".to_string(), 100, None, true));
+        assert_eq!(out[21], ("pair0".to_string(), 1_000, None, true));
+
+        Ok(())
+    }
+}
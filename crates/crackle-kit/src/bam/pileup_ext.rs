@@ -0,0 +1,886 @@
+//! Typed view of the alignments of a [`Pileup`], so workers do not repeat the
+//! `qpos()` / `record()` / indel checks for each field they need.
+//!
+//! Note that the pileups of htslib leave out unmapped, secondary, QC-failed
+//! and duplicate reads by default.
+//!
+//! Indels are placed as htslib does: an insertion is reported at its anchor,
+//! the last aligned base before it, and a deletion both at its anchor, as
+//! [`IndelCall::Deletion`], and at each position it spans, as an alignment
+//! without a base. E.g. a read aligned `10M2I3D10M` from 0-based 100 has
+//! [`IndelCall::Insertion`] at 109, no insertion at 110, and a deletion
+//! spanning 110 to 112; see [`BaseCounts`].
+
+use std::{
+    collections::{BTreeMap, HashMap, hash_map::Entry},
+    hash::{DefaultHasher, Hash, Hasher},
+};
+
+use rust_htslib::bam::pileup::{Alignment, Indel, Pileup};
+
+use crate::{bam::cigar::Strand, data::bases::Base, nuc_base_map::NucBaseMap};
+
+/// Indel starting after the base of an alignment at the locus.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum IndelCall {
+    #[default]
+    None,
+    /// Length of the insertion.
+    Insertion(u32),
+    /// Length of the deletion.
+    Deletion(u32),
+}
+
+impl IndelCall {
+    pub fn is_indel(&self) -> bool {
+        *self != Self::None
+    }
+}
+
+impl From<Indel> for IndelCall {
+    fn from(value: Indel) -> Self {
+        match value {
+            Indel::Ins(len) => Self::Insertion(len),
+            Indel::Del(len) => Self::Deletion(len),
+            Indel::None => Self::None,
+        }
+    }
+}
+
+/// What a worker usually needs of an alignment at a locus, see
+/// [`PileupExt::typed_alignments`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AlignmentContext {
+    /// `None` if the read has a deletion or a skip at the locus, or a base
+    /// other than `ACGTN`.
+    pub base: Option<Base>,
+    /// `None` if the read has a deletion or a skip at the locus.
+    pub base_qual: Option<u8>,
+    pub mapq: u8,
+    pub strand: Strand,
+    pub is_duplicate: bool,
+    pub is_first_in_pair: bool,
+    pub indel: IndelCall,
+    /// Position of the base in the read, `None` at a deletion or a skip.
+    pub qpos: Option<usize>,
+    /// Whether the read skips the locus with an `N` of its CIGAR, e.g. a
+    /// spliced RNA read, rather than deleting it.
+    pub is_refskip: bool,
+    /// Hash of the read name, the same for both mates of a pair, e.g. to count
+    /// fragments instead of reads.
+    pub qname_hash: u64,
+}
+
+impl AlignmentContext {
+    pub fn new(alignment: &Alignment<'_>) -> Self {
+        let record = alignment.record();
+        let qpos = alignment.qpos();
+
+        let mut hasher = DefaultHasher::new();
+        record.qname().hash(&mut hasher);
+
+        Self {
+            base: qpos.and_then(|qpos| Base::try_from(record.seq()[qpos]).ok()),
+            base_qual: qpos.map(|qpos| record.qual()[qpos]),
+            mapq: record.mapq(),
+            strand: if record.is_reverse() {
+                Strand::Reverse
+            } else {
+                Strand::Forward
+            },
+            is_duplicate: record.is_duplicate(),
+            is_first_in_pair: record.is_first_in_template(),
+            indel: alignment.indel().into(),
+            qpos,
+            is_refskip: alignment.is_refskip(),
+            qname_hash: hasher.finish(),
+        }
+    }
+
+    /// Whether the read has a deletion spanning the locus, not a skip.
+    pub fn is_del(&self) -> bool {
+        self.qpos.is_none() && !self.is_refskip
+    }
+}
+
+/// Counts of the alignments at a locus, see [`PileupExt::locus_counts`].
+///
+/// The indels follow the convention of the [module](self): `insertions` are
+/// counted at the anchor base before them only, and `deletions` at each
+/// position they span but not at their anchor, which has a base.
+#[derive(Debug, Clone, Default)]
+pub struct BaseCounts {
+    /// As [`PileupExt::base_counts`].
+    pub bases: NucBaseMap<u32>,
+    /// Reads with a deletion spanning the locus.
+    pub deletions: u32,
+    /// Reads with an insertion right after the locus.
+    pub insertions: u32,
+    /// Number of the `insertions` by their length.
+    pub insertion_lengths: BTreeMap<u32, u32>,
+    /// Reads skipping the locus with an `N` of their CIGAR.
+    pub ref_skips: u32,
+}
+
+impl BaseCounts {
+    /// Reads counted, with a base or a gap at the locus.
+    pub fn depth(&self) -> u32 {
+        self.bases.get_inner().iter().flatten().sum::<u32>() + self.deletions + self.ref_skips
+    }
+}
+
+/// The alignments of a pileup column, capped at a number of them, see
+/// [`PileupExt::sample_alignments`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SampledPileup {
+    /// The alignments kept, in the order of the column.
+    pub alignments: Vec<AlignmentContext>,
+    /// Alignments of the whole column.
+    pub depth: usize,
+}
+
+impl SampledPileup {
+    /// Whether alignments of the column were left out.
+    pub fn is_sampled(&self) -> bool {
+        self.alignments.len() < self.depth
+    }
+
+    /// Alignments of the column per alignment kept, to scale the counts of
+    /// the sample up to the column; 1 if not sampled.
+    pub fn scale(&self) -> f64 {
+        match self.alignments.len() {
+            0 => 1.0,
+            n => self.depth as f64 / n as f64,
+        }
+    }
+}
+
+/// Highest base quality of the agreeing mates of
+/// [`OverlapPolicy::QualityConsensus`], as in htslib.
+pub const MAX_CONSENSUS_QUAL: u8 = 200;
+
+/// How the two mates of a fragment overlapping a locus are counted, see
+/// [`apply_overlap_policy`].
+///
+/// The `ignore_overlaps` option of htslib pileups, on by default in
+/// [`ParallelLocusProcessorPileup`](crate::bam::process::ParallelLocusProcessorPileup),
+/// already changes the base qualities of overlapping mates like
+/// [`Self::QualityConsensus`], setting that of the mate left out to 0 but
+/// keeping it in the pileup. The policies other than [`Self::Ignore`] expect
+/// pileups made without it, with the qualities as sequenced.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum OverlapPolicy {
+    /// Count both mates.
+    #[default]
+    Ignore,
+    /// Count the mate with the higher base quality, the first one seen on a
+    /// tie, or the one with a base if the other has a deletion.
+    CountOnce,
+    /// Count one observation of the mates, as htslib does: agreeing bases with
+    /// the sum of their qualities, up to [`MAX_CONSENSUS_QUAL`], and
+    /// disagreeing ones as the base of the higher quality, with 80% of it.
+    QualityConsensus,
+}
+
+/// `alignments` of a pileup column with the mates of each fragment, of the
+/// same [`AlignmentContext::qname_hash`], merged by `policy` into the context
+/// of the first one seen. The order of the fragments is kept.
+pub fn apply_overlap_policy(
+    alignments: impl IntoIterator<Item = AlignmentContext>,
+    policy: OverlapPolicy,
+) -> Vec<AlignmentContext> {
+    if policy == OverlapPolicy::Ignore {
+        return alignments.into_iter().collect();
+    }
+
+    let mut kept: Vec<AlignmentContext> = vec![];
+    let mut fragments = HashMap::new();
+    for aln in alignments {
+        match fragments.entry(aln.qname_hash) {
+            Entry::Vacant(entry) => {
+                entry.insert(kept.len());
+                kept.push(aln);
+            }
+            Entry::Occupied(entry) => merge_mates(&mut kept[*entry.get()], aln, policy),
+        }
+    }
+    kept
+}
+
+/// Merge `mate` into `kept`, its mate seen first, by `policy`.
+fn merge_mates(kept: &mut AlignmentContext, mate: AlignmentContext, policy: OverlapPolicy) {
+    let qual = |aln: &AlignmentContext| aln.base.and(aln.base_qual);
+    let (kept_qual, mate_qual) = match (qual(kept), qual(&mate)) {
+        (_, None) => return,
+        (None, Some(_)) => {
+            *kept = mate;
+            return;
+        }
+        (Some(kept_qual), Some(mate_qual)) => (kept_qual, mate_qual),
+    };
+
+    match policy {
+        OverlapPolicy::Ignore => unreachable!("mates are not merged"),
+        OverlapPolicy::CountOnce => {
+            if mate_qual > kept_qual {
+                *kept = mate;
+            }
+        }
+        OverlapPolicy::QualityConsensus if kept.base == mate.base => {
+            let sum = kept_qual.saturating_add(mate_qual);
+            kept.base_qual = Some(sum.min(MAX_CONSENSUS_QUAL));
+        }
+        OverlapPolicy::QualityConsensus => {
+            let higher = kept_qual.max(mate_qual);
+            if mate_qual > kept_qual {
+                *kept = mate;
+            }
+            kept.base_qual = Some((higher as u32 * 4 / 5) as u8);
+        }
+    }
+}
+
+/// Typed queries on the alignments of a [`Pileup`].
+pub trait PileupExt {
+    /// The alignments of the pileup, each read once into an [`AlignmentContext`].
+    fn typed_alignments(&self) -> impl Iterator<Item = AlignmentContext>;
+
+    /// [`Self::typed_alignments`] with the overlapping mates merged by `policy`.
+    fn typed_alignments_with(&self, policy: OverlapPolicy) -> Vec<AlignmentContext>;
+
+    /// Counts of the bases at the locus, of alignments with a base quality of
+    /// at least `min_baseq` and a mapping quality of at least `min_mapq`.
+    /// Bases without any alignment are `None`.
+    fn base_counts(&self, min_baseq: u8, min_mapq: u8) -> NucBaseMap<u32>;
+
+    /// [`Self::base_counts`] with the overlapping mates merged by `policy`
+    /// before their qualities are checked.
+    fn base_counts_with(
+        &self,
+        min_baseq: u8,
+        min_mapq: u8,
+        policy: OverlapPolicy,
+    ) -> NucBaseMap<u32>;
+
+    /// [`Self::base_counts`] with the indels and skips at the locus. The gaps
+    /// have no base quality, so only `min_mapq` applies to them, and an
+    /// insertion is counted whatever the quality of its anchor base.
+    fn locus_counts(&self, min_baseq: u8, min_mapq: u8) -> BaseCounts;
+
+    /// [`Self::locus_counts`] with the overlapping mates merged by `policy`.
+    fn locus_counts_with(&self, min_baseq: u8, min_mapq: u8, policy: OverlapPolicy) -> BaseCounts;
+
+    /// At most `max` of the alignments of the pileup, those of the lowest
+    /// hashes of their read name and alignment start, i.e. below a threshold
+    /// set by `max` and the depth. Reruns, and other workers on the same
+    /// column, keep the same alignments, though the mates of a fragment are
+    /// kept or left out apart.
+    fn sample_alignments(&self, max: usize) -> SampledPileup;
+}
+
+impl PileupExt for Pileup {
+    fn typed_alignments(&self) -> impl Iterator<Item = AlignmentContext> {
+        self.alignments().map(|aln| AlignmentContext::new(&aln))
+    }
+
+    fn typed_alignments_with(&self, policy: OverlapPolicy) -> Vec<AlignmentContext> {
+        apply_overlap_policy(self.typed_alignments(), policy)
+    }
+
+    fn base_counts(&self, min_baseq: u8, min_mapq: u8) -> NucBaseMap<u32> {
+        count_bases(self.typed_alignments(), min_baseq, min_mapq)
+    }
+
+    fn base_counts_with(
+        &self,
+        min_baseq: u8,
+        min_mapq: u8,
+        policy: OverlapPolicy,
+    ) -> NucBaseMap<u32> {
+        count_bases(self.typed_alignments_with(policy), min_baseq, min_mapq)
+    }
+
+    fn locus_counts(&self, min_baseq: u8, min_mapq: u8) -> BaseCounts {
+        count_locus(self.typed_alignments(), min_baseq, min_mapq)
+    }
+
+    fn locus_counts_with(&self, min_baseq: u8, min_mapq: u8, policy: OverlapPolicy) -> BaseCounts {
+        count_locus(self.typed_alignments_with(policy), min_baseq, min_mapq)
+    }
+
+    fn sample_alignments(&self, max: usize) -> SampledPileup {
+        let depth = self.depth() as usize;
+        if depth <= max {
+            return SampledPileup {
+                alignments: self.typed_alignments().collect(),
+                depth,
+            };
+        }
+
+        let keys = self
+            .alignments()
+            .map(|aln| sampling_key(&aln))
+            .collect::<Vec<_>>();
+        let threshold = match max {
+            0 => None,
+            _ => Some(*keys.clone().select_nth_unstable(max - 1).1),
+        };
+        // alignments of the same key as the threshold are kept in column order.
+        let alignments = self
+            .alignments()
+            .zip(keys)
+            .filter(|(_, key)| threshold.is_some_and(|threshold| *key <= threshold))
+            .take(max)
+            .map(|(aln, _)| AlignmentContext::new(&aln))
+            .collect();
+        SampledPileup { alignments, depth }
+    }
+}
+
+fn count_bases(
+    alignments: impl IntoIterator<Item = AlignmentContext>,
+    min_baseq: u8,
+    min_mapq: u8,
+) -> NucBaseMap<u32> {
+    let mut counts = NucBaseMap::default();
+    for aln in alignments {
+        if let (Some(base), Some(bq)) = (aln.base, aln.base_qual)
+            && bq >= min_baseq
+            && aln.mapq >= min_mapq
+        {
+            *counts.get_or_insert_with(base.to_ascii(), || 0).unwrap() += 1;
+        }
+    }
+    counts
+}
+
+/// Key of an alignment for [`PileupExt::sample_alignments`], the same on
+/// every run.
+fn sampling_key(alignment: &Alignment<'_>) -> u64 {
+    let record = alignment.record();
+    let mut hasher = DefaultHasher::new();
+    record.qname().hash(&mut hasher);
+    record.pos().hash(&mut hasher);
+    hasher.finish()
+}
+
+fn count_locus(
+    alignments: impl IntoIterator<Item = AlignmentContext>,
+    min_baseq: u8,
+    min_mapq: u8,
+) -> BaseCounts {
+    let alignments = alignments
+        .into_iter()
+        .filter(|aln| aln.mapq >= min_mapq)
+        .collect::<Vec<_>>();
+
+    let mut counts = BaseCounts::default();
+    for aln in &alignments {
+        if aln.is_refskip {
+            counts.ref_skips += 1;
+        } else if aln.is_del() {
+            counts.deletions += 1;
+        }
+        if let IndelCall::Insertion(len) = aln.indel {
+            counts.insertions += 1;
+            *counts.insertion_lengths.entry(len).or_default() += 1;
+        }
+    }
+    counts.bases = count_bases(alignments, min_baseq, 0);
+    counts
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Error;
+
+    use rust_htslib::bam::pileup::PileupOption;
+
+    use super::*;
+    use crate::{
+        bam::{
+            pooled::{CombinedWorker, PooledBamSource, PooledLocusProcessor},
+            process::{
+                BamLocusWorker, MultiBamLocusProcessor, MultiBamLocusWorker,
+                ParallelLocusProcessorPileup,
+            },
+        },
+        data::{chrom::Chrom, locus::GenomeCoordinate},
+        test_utils::TestBam,
+    };
+
+    struct ContextWorker;
+
+    impl<'a> BamLocusWorker<'a> for ContextWorker {
+        type Input = GenomeCoordinate<'a>;
+        type Output = (Vec<AlignmentContext>, NucBaseMap<u32>);
+        type Error = Error;
+
+        fn work_for_locus(&self, plp: Pileup, _input: Self::Input) -> Result<Self::Output, Error> {
+            Ok((plp.typed_alignments().collect(), plp.base_counts(20, 30)))
+        }
+    }
+
+    #[test]
+    fn test_typed_alignments() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = tempfile::tempdir()?;
+        let qual = (10..20).collect::<Vec<u8>>();
+        // all reads at 0-based 100, so 1-based 104 is the 4th base.
+        let bam_path = TestBam::new()
+            // a 2 bp deletion after the 4th base.
+            .add_read("chr1", 100, b"ACGTACGTAC", &qual, 0x1 | 0x40)
+            .with_cigar("4M2D6M")
+            // a 2 bp insertion after the 4th base.
+            .add_read("chr1", 100, b"ACGTTTACGT", &qual, 0x1 | 0x10 | 0x80)
+            .with_cigar("4M2I4M")
+            .with_mapq(20)
+            .add_read("chr1", 100, b"ACGAACGTAC", &[30; 10], 0x10)
+            .build(dir.path())?;
+
+        let plp = ParallelLocusProcessorPileup::new(ContextWorker, 1, bam_path);
+        let coord = |pos| GenomeCoordinate {
+            contig: Chrom::Chr1,
+            pos,
+        };
+        let mut res = plp.process_with_batch(vec![coord(104), coord(105)], 1_000)?;
+        assert_eq!(res.len(), 2);
+
+        let (at_105, counts_105) = res.pop().unwrap();
+        let (at_104, counts_104) = res.pop().unwrap();
+        assert_eq!(at_104.len(), 3);
+
+        // the read names differ.
+        let hashes = at_104.iter().map(|a| a.qname_hash).collect::<Vec<_>>();
+        assert!(hashes[0] != hashes[1] && hashes[1] != hashes[2]);
+
+        let expected = [
+            AlignmentContext {
+                base: Some(Base::T),
+                base_qual: Some(13),
+                mapq: 60,
+                strand: Strand::Forward,
+                is_duplicate: false,
+                is_first_in_pair: true,
+                indel: IndelCall::Deletion(2),
+                qpos: Some(3),
+                is_refskip: false,
+                qname_hash: hashes[0],
+            },
+            AlignmentContext {
+                base: Some(Base::T),
+                base_qual: Some(13),
+                mapq: 20,
+                strand: Strand::Reverse,
+                is_duplicate: false,
+                is_first_in_pair: false,
+                indel: IndelCall::Insertion(2),
+                qpos: Some(3),
+                is_refskip: false,
+                qname_hash: hashes[1],
+            },
+            AlignmentContext {
+                base: Some(Base::A),
+                base_qual: Some(30),
+                mapq: 60,
+                strand: Strand::Reverse,
+                is_duplicate: false,
+                is_first_in_pair: false,
+                indel: IndelCall::None,
+                qpos: Some(3),
+                is_refskip: false,
+                qname_hash: hashes[2],
+            },
+        ];
+        assert_eq!(at_104, expected);
+
+        // within the deletion of the first read, past the insertion of the second.
+        assert_eq!(at_105.len(), 3);
+        assert_eq!((at_105[0].base, at_105[0].base_qual), (None, None));
+        assert_eq!(at_105[0].qpos, None);
+        assert_eq!(at_105[0].qname_hash, hashes[0]);
+        assert_eq!((at_105[1].base, at_105[1].qpos), (Some(Base::A), Some(6)));
+        assert!(!at_105[1].indel.is_indel());
+        assert_eq!((at_105[2].base, at_105[2].qpos), (Some(Base::A), Some(4)));
+
+        // the second read is left out by its mapping quality.
+        assert_eq!(counts_104.get(b'T'), None);
+        assert_eq!(counts_104.get(b'A'), Some(&1));
+        assert_eq!(counts_105.get(b'A'), Some(&1));
+        assert_eq!(counts_105.get(b'C'), None);
+
+        Ok(())
+    }
+
+    struct CountsWorker;
+
+    impl<'a> BamLocusWorker<'a> for CountsWorker {
+        type Input = GenomeCoordinate<'a>;
+        type Output = BaseCounts;
+        type Error = Error;
+
+        fn work_for_locus(&self, plp: Pileup, _input: Self::Input) -> Result<Self::Output, Error> {
+            Ok(plp.locus_counts(0, 0))
+        }
+    }
+
+    #[test]
+    fn test_locus_counts() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = tempfile::tempdir()?;
+        let seq = |len| b"ACGT".repeat(6)[..len].to_vec();
+        // all reads at 0-based 100, so the 10th base is at 1-based 110.
+        let bam_path = TestBam::new()
+            // a 3 bp deletion over 1-based 111 to 113.
+            .add_read("chr1", 100, &seq(20), &[30; 20], 0)
+            .with_cigar("10M3D10M")
+            // 2 and 3 bp insertions between 110 and 111.
+            .add_read("chr1", 100, &seq(22), &[30; 22], 0)
+            .with_cigar("10M2I10M")
+            .add_read("chr1", 100, &seq(18), &[30; 18], 0)
+            .with_cigar("10M3I5M")
+            // skips 1-based 106 to 205.
+            .add_read("chr1", 100, &seq(10), &[30; 10], 0)
+            .with_cigar("5M100N5M")
+            .build(dir.path())?;
+
+        let plp = ParallelLocusProcessorPileup::new(CountsWorker, 1, bam_path);
+        let coords = [110, 111, 113, 114]
+            .map(|pos| GenomeCoordinate {
+                contig: Chrom::Chr1,
+                pos,
+            })
+            .to_vec();
+        let res = plp.process_with_batch(coords, 1_000)?;
+        let gaps = |c: &BaseCounts| (c.deletions, c.insertions, c.ref_skips);
+
+        // the anchor: the insertions, but not the deletion, which starts after.
+        let (anchor, first_del, last_del, after) = (&res[0], &res[1], &res[2], &res[3]);
+        assert_eq!(gaps(anchor), (0, 2, 1));
+        assert_eq!(anchor.insertion_lengths, BTreeMap::from([(2, 1), (3, 1)]));
+        assert_eq!(anchor.bases.get(b'C'), Some(&3));
+        assert_eq!(anchor.depth(), 4);
+
+        // the insertions are not counted again after their anchor.
+        assert_eq!(gaps(first_del), (1, 0, 1));
+        assert!(first_del.insertion_lengths.is_empty());
+        assert_eq!(first_del.depth(), 4);
+        assert_eq!(gaps(last_del), (1, 0, 1));
+        assert_eq!(last_del.depth(), 4);
+        assert_eq!(gaps(after), (0, 0, 1));
+        assert_eq!(after.depth(), 4);
+
+        Ok(())
+    }
+
+    /// The depth, the read names kept, the fraction of `T` and whether the
+    /// column was sampled.
+    struct SampleWorker;
+
+    type SampleSummary = (usize, Vec<u64>, f64, bool);
+
+    fn summarize(alignments: &[AlignmentContext], depth: usize, sampled: bool) -> SampleSummary {
+        let n_alt = alignments
+            .iter()
+            .filter(|a| a.base == Some(Base::T))
+            .count();
+        (
+            depth,
+            alignments.iter().map(|a| a.qname_hash).collect(),
+            n_alt as f64 / alignments.len() as f64,
+            sampled,
+        )
+    }
+
+    impl<'a> BamLocusWorker<'a> for SampleWorker {
+        type Input = GenomeCoordinate<'a>;
+        type Output = SampleSummary;
+        type Error = Error;
+
+        fn work_for_locus(&self, plp: Pileup, _input: Self::Input) -> Result<Self::Output, Error> {
+            let alignments = plp.typed_alignments().collect::<Vec<_>>();
+            Ok(summarize(&alignments, alignments.len(), false))
+        }
+
+        fn work_for_locus_sampled(
+            &self,
+            _plp: Pileup,
+            _input: Self::Input,
+            _ref_base: Option<Base>,
+            sample: SampledPileup,
+        ) -> Result<Self::Output, Error> {
+            Ok(summarize(
+                &sample.alignments,
+                sample.depth,
+                sample.is_sampled(),
+            ))
+        }
+    }
+
+    #[test]
+    fn test_sample_alignments() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = tempfile::tempdir()?;
+        // 20_000 reads over 1-based 105, 30% of them with a T there, and 50
+        // reads over 1_005.
+        let mut test_bam = TestBam::new();
+        for i in 0..20_000 {
+            let offset = i % 5;
+            let mut seq = *b"ACGAACGAAC";
+            seq[4 - offset] = if i % 10 < 3 { b'T' } else { b'A' };
+            test_bam = test_bam.add_read("chr1", 100 + offset as i64, &seq, &[30; 10], 0);
+        }
+        let bam_path = test_bam
+            .add_reads("chr1", 1_000, 0, 50, 10)
+            .build(dir.path())?;
+        let coords = [105, 1_005]
+            .map(|pos| GenomeCoordinate {
+                contig: Chrom::Chr1,
+                pos,
+            })
+            .to_vec();
+
+        let full = ParallelLocusProcessorPileup::new(SampleWorker, 1, bam_path.clone())
+            .process_with_batch(coords.clone(), 10_000)?;
+        assert_eq!((full[0].0, full[0].1.len()), (20_000, 20_000));
+        assert!((full[0].2 - 0.3).abs() < 1e-9);
+
+        let run = |n_threads, window| {
+            ParallelLocusProcessorPileup::builder(&bam_path)
+                .worker(SampleWorker)
+                .threads(n_threads)
+                .max_alignments_per_locus(2_000)
+                .build()?
+                .process_with_batch(coords.clone(), window)
+        };
+        let sampled = run(1, 10_000)?;
+        let (depth, kept, alt_fraction, is_sampled) = &sampled[0];
+        assert_eq!((*depth, kept.len(), *is_sampled), (20_000, 2_000, true));
+        assert!((alt_fraction - 0.3).abs() < 0.03, "{}", alt_fraction);
+        // a column under the cap is kept whole.
+        assert_eq!((sampled[1].0, sampled[1].1.len()), (50, 50));
+        assert!(!sampled[1].3);
+
+        // the same reads on another run, in other batches.
+        let rerun = run(2, 100)?;
+        assert_eq!(rerun[0].1, *kept);
+        assert_eq!(rerun[1].1, sampled[1].1);
+
+        Ok(())
+    }
+
+    struct OverlapWorker(OverlapPolicy);
+
+    impl<'a> BamLocusWorker<'a> for OverlapWorker {
+        type Input = GenomeCoordinate<'a>;
+        type Output = (Vec<(Option<Base>, Option<u8>)>, NucBaseMap<u32>);
+        type Error = Error;
+
+        fn work_for_locus(&self, plp: Pileup, _input: Self::Input) -> Result<Self::Output, Error> {
+            let alignments = plp.typed_alignments_with(self.0);
+            Ok((
+                alignments.iter().map(|a| (a.base, a.base_qual)).collect(),
+                plp.base_counts_with(0, 0, self.0),
+            ))
+        }
+    }
+
+    /// A bam of 3 pairs 1_000 bp apart, of mates over 0-based 100..110, and
+    /// the coordinates of their 5th base, at 1-based 105.
+    fn overlapping_pairs_bam(
+        dir: &std::path::Path,
+    ) -> Result<(std::path::PathBuf, Vec<GenomeCoordinate<'static>>), Error> {
+        let qual = |q5| {
+            let mut qual = vec![30; 10];
+            qual[4] = q5;
+            qual
+        };
+        let (first, second) = (0x1 | 0x40, 0x1 | 0x10 | 0x80);
+        let mut test_bam = TestBam::new();
+        // (mate bases at 105, their qualities), in 3 pairs 1_000 bp apart.
+        let pairs = [(b"AA", [30, 20]), (b"AC", [30, 20]), (b"AC", [20, 35])];
+        for (i, (bases, quals)) in pairs.iter().enumerate() {
+            let pos = 100 + 1_000 * i as i64;
+            for (j, flags) in [first, second].into_iter().enumerate() {
+                let mut seq = *b"ACGTACGTAC";
+                seq[4] = bases[j];
+                test_bam = test_bam
+                    .add_read("chr1", pos, &seq, &qual(quals[j]), flags)
+                    .with_qname(&format!("pair{}", i))
+                    .with_mate(pos, 10);
+            }
+        }
+        let coords = (0..3)
+            .map(|i| GenomeCoordinate {
+                contig: Chrom::Chr1,
+                pos: 105 + 1_000 * i,
+            })
+            .collect();
+        Ok((test_bam.build(dir)?, coords))
+    }
+
+    #[test]
+    fn test_overlap_policies() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = tempfile::tempdir()?;
+        let (bam_path, coords) = overlapping_pairs_bam(dir.path())?;
+
+        let run = |policy| {
+            ParallelLocusProcessorPileup::builder(&bam_path)
+                .worker(OverlapWorker(policy))
+                .threads(1)
+                .pileup_options(PileupOption {
+                    max_depth: i32::MAX,
+                    ignore_overlaps: false,
+                })
+                .build()?
+                .process_with_batch(coords.clone(), 10_000)
+        };
+        let counts =
+            |counts: &NucBaseMap<u32>| [b'A', b'C'].map(|b| counts.get(b).copied().unwrap_or(0));
+
+        let ignored = run(OverlapPolicy::Ignore)?;
+        assert_eq!(
+            ignored[0].0,
+            [(Some(Base::A), Some(30)), (Some(Base::A), Some(20))]
+        );
+        assert_eq!(counts(&ignored[0].1), [2, 0]);
+        assert_eq!(counts(&ignored[1].1), [1, 1]);
+
+        let once = run(OverlapPolicy::CountOnce)?;
+        assert_eq!(once[0].0, [(Some(Base::A), Some(30))]);
+        assert_eq!(once[1].0, [(Some(Base::A), Some(30))]);
+        assert_eq!(once[2].0, [(Some(Base::C), Some(35))]);
+        assert_eq!(counts(&once[1].1), [1, 0]);
+        assert_eq!(counts(&once[2].1), [0, 1]);
+
+        let consensus = run(OverlapPolicy::QualityConsensus)?;
+        // agreeing: summed; disagreeing: the higher one at 80%.
+        assert_eq!(consensus[0].0, [(Some(Base::A), Some(50))]);
+        assert_eq!(consensus[1].0, [(Some(Base::A), Some(24))]);
+        assert_eq!(consensus[2].0, [(Some(Base::C), Some(28))]);
+        assert_eq!(counts(&consensus[2].1), [0, 1]);
+
+        Ok(())
+    }
+
+    /// The mates of the column of each bam, kept as by [`OverlapWorker`].
+    struct MultiOverlapWorker(OverlapPolicy);
+
+    impl<'a> MultiBamLocusWorker<'a> for MultiOverlapWorker {
+        type Input = GenomeCoordinate<'a>;
+        type Output = Vec<Vec<(Option<Base>, Option<u8>)>>;
+        type Error = Error;
+
+        fn work_for_locus(
+            &self,
+            pileups: &[Option<Pileup>],
+            _input: Self::Input,
+        ) -> Result<Self::Output, Error> {
+            Ok(pileups
+                .iter()
+                .flatten()
+                .map(|plp| {
+                    let alignments = plp.typed_alignments_with(self.0);
+                    alignments.iter().map(|a| (a.base, a.base_qual)).collect()
+                })
+                .collect())
+        }
+    }
+
+    #[test]
+    fn test_overlap_policy_across_bams() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = tempfile::tempdir()?;
+        let (bam_path, coords) = overlapping_pairs_bam(dir.path())?;
+        let as_sequenced = PileupOption {
+            max_depth: i32::MAX,
+            ignore_overlaps: false,
+        };
+        // a depth of 1 leaves the second mate out, to tell the options are used.
+        let shallow = PileupOption {
+            max_depth: 1,
+            ignore_overlaps: false,
+        };
+        let multi = |options| {
+            MultiBamLocusProcessor::new(
+                MultiOverlapWorker(OverlapPolicy::QualityConsensus),
+                1,
+                vec![bam_path.clone(); 2],
+            )
+            .with_pileup_options(options)
+            .process_with_batch(coords.clone(), 10_000)
+        };
+        let pooled = |options| {
+            let worker = CombinedWorker::new(
+                OverlapWorker(OverlapPolicy::QualityConsensus),
+                |outputs: Vec<(Vec<(Option<Base>, Option<u8>)>, NucBaseMap<u32>)>| {
+                    outputs
+                        .into_iter()
+                        .map(|(mates, _)| mates)
+                        .collect::<Vec<_>>()
+                },
+            );
+            let source = PooledBamSource::new([&bam_path, &bam_path]).with_pileup_options(options);
+            PooledLocusProcessor::builder(source)
+                .worker(worker)
+                .threads(1)
+                .build()?
+                .process_with_batch(coords.clone(), 10_000)
+        };
+
+        // as of one bam: disagreeing mates at 80% of the higher quality, once.
+        let consensus = [
+            (Some(Base::A), Some(50)),
+            (Some(Base::A), Some(24)),
+            (Some(Base::C), Some(28)),
+        ]
+        .map(|mate| vec![vec![mate]; 2]);
+        assert_eq!(multi(as_sequenced)?, consensus);
+        assert_eq!(pooled(as_sequenced)?, consensus);
+
+        let first_mate = vec![vec![(Some(Base::A), Some(30))]; 2];
+        assert_eq!(multi(shallow)?[0], first_mate);
+        assert_eq!(pooled(shallow)?[0], first_mate);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_overlap_policy_with_deletion() {
+        let aln = |base, base_qual, qname_hash| AlignmentContext {
+            base,
+            base_qual,
+            mapq: 60,
+            strand: Strand::Forward,
+            is_duplicate: false,
+            is_first_in_pair: true,
+            indel: IndelCall::None,
+            qpos: base.map(|_| 4),
+            is_refskip: false,
+            qname_hash,
+        };
+        let alignments = [
+            aln(None, None, 1),
+            aln(Some(Base::G), Some(100), 2),
+            aln(Some(Base::T), Some(12), 1),
+            aln(Some(Base::G), Some(150), 2),
+        ];
+
+        for policy in [OverlapPolicy::CountOnce, OverlapPolicy::QualityConsensus] {
+            let merged = apply_overlap_policy(alignments.clone(), policy);
+            let merged = merged
+                .iter()
+                .map(|a| (a.base, a.base_qual))
+                .collect::<Vec<_>>();
+            // the mate with a base is kept as it is.
+            let expected_g = match policy {
+                OverlapPolicy::QualityConsensus => MAX_CONSENSUS_QUAL,
+                _ => 150,
+            };
+            assert_eq!(
+                merged,
+                [(Some(Base::T), Some(12)), (Some(Base::G), Some(expected_g))]
+            );
+        }
+        assert_eq!(
+            apply_overlap_policy(alignments.clone(), OverlapPolicy::Ignore).len(),
+            4
+        );
+    }
+}
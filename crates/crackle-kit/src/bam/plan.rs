@@ -0,0 +1,663 @@
+//! Plans of runs of the BAM processors, to see what a run would do and check
+//! it before it starts, without reading any record, see [`RunPlan`].
+//!
+//! Made by [`ParallelBamProcessor::plan`] and
+//! [`ParallelLocusProcessorPileupBuilder::plan`], and logged at DEBUG by the
+//! runs themselves.
+//!
+//! [`ParallelBamProcessor::plan`]: crate::bam::process::ParallelBamProcessor::plan
+//! [`ParallelLocusProcessorPileupBuilder::plan`]: crate::bam::process::ParallelLocusProcessorPileupBuilder::plan
+
+use std::{
+    fmt,
+    fs::{self, OpenOptions},
+    io,
+    path::{Path, PathBuf},
+    process,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use rust_htslib::bam::{self, HeaderView, IndexedReader, Read as _};
+use tracing::{Level, event};
+
+use crate::{
+    bam::{
+        context::ProcessContext,
+        reader::{BamOpener, RetryPolicy, find_bam_index, open_with_retry},
+        validate::{BamCheckReport, InputCheck, quick_check},
+    },
+    errors::{Error, PlanProblem},
+};
+
+/// What a run would do, and the problems which would fail it.
+///
+/// The checks of a plan read the header and the index of the input, and try
+/// creating a file where the outputs go, but no record. All of their problems
+/// are collected, rather than the first only.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RunPlan {
+    /// The method run, e.g. `process_bam`.
+    pub run: String,
+    pub input: PathBuf,
+    /// The index of `input`, if found.
+    pub index: Option<PathBuf>,
+    /// The header of `input`, if it could be read.
+    pub header: Option<HeaderSummary>,
+    /// The [`quick_check`] of `input`, unless skipped.
+    pub input_check: Option<BamCheckReport>,
+    /// Where the records go, as displayed, e.g. a path or a command. `None` for
+    /// runs returning their outputs.
+    pub output: Option<String>,
+    /// Threads of each stage, by name, once cut to fit the thread budget.
+    pub threads: Vec<(String, usize)>,
+    /// The total of the thread budget, if any.
+    pub thread_budget: Option<usize>,
+    pub batches: BatchPlan,
+    pub problems: Vec<PlanProblem>,
+}
+
+/// The header of the input of a [`RunPlan`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct HeaderSummary {
+    pub n_contigs: usize,
+    /// Sum of the lengths of the contigs, in bp.
+    pub total_length: u64,
+    pub n_read_groups: usize,
+    /// `SO` of the `@HD` line, e.g. `coordinate`.
+    pub sort_order: Option<String>,
+}
+
+/// The batches of a [`RunPlan`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct BatchPlan {
+    /// What is batched, `records` or `loci`.
+    pub unit: String,
+    /// Items to process, for records as counted by the index, if known.
+    pub n_items: Option<u64>,
+    /// Records per batch, or the window of locus batches in bp, if known.
+    pub batch_size: Option<u64>,
+    /// Batches to run, if known; estimated for records.
+    pub n_batches: Option<u64>,
+}
+
+impl RunPlan {
+    /// A plan of `run` on `input`, with the checks that it exists and has an
+    /// index, its [`quick_check`] under `input_check`, and the summary of its
+    /// header. The input is returned opened by `opener` if it could be, to
+    /// check the rest of the run against.
+    pub(crate) fn of_input(
+        run: &str,
+        input: &Path,
+        opener: &dyn BamOpener,
+        retry_policy: &RetryPolicy,
+        input_check: InputCheck,
+    ) -> (Self, Option<IndexedReader>) {
+        let mut plan = Self {
+            run: run.to_string(),
+            input: input.to_path_buf(),
+            index: None,
+            header: None,
+            input_check: None,
+            output: None,
+            threads: vec![],
+            thread_budget: None,
+            batches: BatchPlan::default(),
+            problems: vec![],
+        };
+        if !input.exists() {
+            plan.problems.push(PlanProblem::MissingInput {
+                path: input.to_path_buf(),
+            });
+            return (plan, None);
+        }
+        if input_check != InputCheck::Skip {
+            match quick_check(input) {
+                Ok(report) => {
+                    if input_check == InputCheck::Strict && report.has_errors() {
+                        let errors = report.errors().map(|e| e.message.clone());
+                        plan.problems.push(PlanProblem::CorruptInput {
+                            path: input.to_path_buf(),
+                            message: errors.collect::<Vec<_>>().join("; "),
+                        });
+                    }
+                    plan.input_check = Some(report);
+                }
+                Err(err) => plan.problems.push(PlanProblem::UnreadableInput {
+                    path: input.to_path_buf(),
+                    message: source_message(&err),
+                }),
+            }
+        }
+        match find_bam_index(input) {
+            Ok(index) => plan.index = Some(index),
+            Err(err) => {
+                plan.problems.push(PlanProblem::MissingIndex {
+                    path: input.to_path_buf(),
+                    message: source_message(&err),
+                });
+                // the header is still summarized, read without the index.
+                match bam::Reader::from_path(input) {
+                    Ok(reader) => plan.header = Some(HeaderSummary::of(reader.header())),
+                    Err(err) => plan.problems.push(PlanProblem::UnreadableInput {
+                        path: input.to_path_buf(),
+                        message: err.to_string(),
+                    }),
+                }
+                return (plan, None);
+            }
+        }
+
+        match open_with_retry(opener, retry_policy, input) {
+            Ok(reader) => {
+                plan.header = Some(HeaderSummary::of(reader.header()));
+                (plan, Some(reader))
+            }
+            Err(err) => {
+                plan.problems.push(PlanProblem::UnreadableInput {
+                    path: input.to_path_buf(),
+                    message: format!("{:#}", err),
+                });
+                (plan, None)
+            }
+        }
+    }
+
+    /// Whether the run would start, i.e. no problem was found.
+    pub fn is_valid(&self) -> bool {
+        self.problems.is_empty()
+    }
+
+    /// The plan if valid, else an [`InvalidPlan`](Error::InvalidPlan) error
+    /// with its problems.
+    pub fn into_result(self) -> Result<Self, Error> {
+        match self.is_valid() {
+            true => Ok(self),
+            false => Err(Error::InvalidPlan {
+                problems: self.problems,
+            }),
+        }
+    }
+
+    /// Fail with an [`InvalidPlan`](Error::InvalidPlan) error of the
+    /// [`CorruptInput`](PlanProblem::CorruptInput) problems, if any, so that a
+    /// run refuses to start on a corrupt input rather than fail hours in; else
+    /// log the findings of the input check as warnings.
+    pub(crate) fn check_input(&self) -> Result<(), Error> {
+        let corrupt = self
+            .problems
+            .iter()
+            .filter(|problem| matches!(problem, PlanProblem::CorruptInput { .. }))
+            .cloned()
+            .collect::<Vec<_>>();
+        if !corrupt.is_empty() {
+            return Err(Error::InvalidPlan { problems: corrupt });
+        }
+        for finding in self.input_check.iter().flat_map(|report| &report.findings) {
+            event!(Level::WARN, "{}: {}", self.input.display(), finding);
+        }
+        Ok(())
+    }
+
+    /// Add the problem of writing to `path` as by [`check_writable`], if any.
+    pub(crate) fn check_writable(&mut self, path: &Path, create_dirs: bool) {
+        if let Err(problem) = check_writable(path, create_dirs) {
+            self.problems.push(problem);
+        }
+    }
+}
+
+impl fmt::Display for RunPlan {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Plan of {}", self.run)?;
+        write!(f, "  input: {}", self.input.display())?;
+        if let Some(index) = &self.index {
+            write!(f, " (index {})", index.display())?;
+        }
+        writeln!(f)?;
+        if let Some(header) = &self.header {
+            writeln!(f, "  header: {}", header)?;
+        }
+        if let Some(report) = &self.input_check
+            && !report.findings.is_empty()
+        {
+            let findings = report.findings.iter().map(|f| f.to_string());
+            writeln!(
+                f,
+                "  input check: {}",
+                findings.collect::<Vec<_>>().join("; ")
+            )?;
+        }
+        if let Some(output) = &self.output {
+            writeln!(f, "  output: {}", output)?;
+        }
+
+        let threads = self
+            .threads
+            .iter()
+            .map(|(stage, n)| format!("{} {}", stage, n))
+            .collect::<Vec<_>>();
+        write!(f, "  threads: {}", threads.join(", "))?;
+        if let Some(total) = self.thread_budget {
+            write!(f, " (of a budget of {})", total)?;
+        }
+        writeln!(f)?;
+        writeln!(f, "  batches: {}", self.batches)?;
+
+        match self.problems.as_slice() {
+            [] => write!(f, "  problems: none"),
+            problems => {
+                write!(f, "  problems:")?;
+                for problem in problems {
+                    write!(f, "\n    - {}", problem)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl HeaderSummary {
+    pub(crate) fn of(header: &HeaderView) -> Self {
+        let total_length = (0..header.target_count())
+            .filter_map(|tid| header.target_len(tid))
+            .sum();
+        let text = String::from_utf8_lossy(header.as_bytes());
+        let sort_order = text
+            .lines()
+            .find(|line| line.starts_with("@HD\t"))
+            .and_then(|line| line.split('\t').find_map(|field| field.strip_prefix("SO:")))
+            .map(str::to_string);
+
+        Self {
+            n_contigs: header.target_count() as usize,
+            total_length,
+            n_read_groups: ProcessContext::from_header(header).read_groups().len(),
+            sort_order,
+        }
+    }
+}
+
+impl fmt::Display for HeaderSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} contigs of {} bp, {} read groups, sorted by {}",
+            self.n_contigs,
+            self.total_length,
+            self.n_read_groups,
+            self.sort_order.as_deref().unwrap_or("unknown")
+        )
+    }
+}
+
+impl fmt::Display for BatchPlan {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let unknown = |n: Option<u64>| n.map_or("?".to_string(), |n| n.to_string());
+        write!(
+            f,
+            "{} of {} {}",
+            unknown(self.n_batches),
+            unknown(self.n_items),
+            self.unit
+        )?;
+        if let Some(batch_size) = self.batch_size {
+            match self.unit.as_str() {
+                "loci" => write!(f, ", in windows of {} bp", batch_size)?,
+                unit => write!(f, ", {} {} each", batch_size, unit)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Check that a file can be created at `path`, by creating and removing one
+/// next to it. With `create_dirs`, `path` is a directory created by the run if
+/// missing, so the check is done in its closest existing ancestor instead.
+pub(crate) fn check_writable(path: &Path, create_dirs: bool) -> Result<(), PlanProblem> {
+    let unwritable = |err: io::Error| PlanProblem::UnwritableOutput {
+        path: path.to_path_buf(),
+        message: err.to_string(),
+    };
+    let dir = match create_dirs {
+        true => path
+            .ancestors()
+            .find(|dir| dir.exists())
+            .unwrap_or(Path::new(".")),
+        false => match path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => Path::new("."),
+        },
+    };
+    if dir.exists() && !dir.is_dir() {
+        return Err(unwritable(io::Error::new(
+            io::ErrorKind::NotADirectory,
+            format!("{} is not a directory", dir.display()),
+        )));
+    }
+
+    // unique in the process too, as plans of concurrent runs may share `dir`.
+    static N_PROBES: AtomicU64 = AtomicU64::new(0);
+    let probe = dir.join(format!(
+        ".plan-probe.{}.{}",
+        process::id(),
+        N_PROBES.fetch_add(1, Ordering::Relaxed)
+    ));
+    OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&probe)
+        .map_err(unwritable)?;
+    fs::remove_file(&probe).map_err(unwritable)
+}
+
+/// The message of the source of `err`, without what it failed to do, e.g. the
+/// index paths looked for of [`find_bam_index`].
+fn source_message(err: &Error) -> String {
+    match err {
+        Error::Io { source, .. } => source.to_string(),
+        err => format!("{:#}", err),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_htslib::bam::pileup::Pileup;
+
+    use super::*;
+    use crate::{
+        bam::{
+            output::OutputTarget,
+            process::{
+                BamLocusWorker, ParallelBamProcessor, ParallelLocusProcessorPileup,
+                ProcessBamOptions, RecordModifier,
+            },
+        },
+        data::{chrom::Chrom, locus::GenomeCoordinate},
+        test_utils::TestBam,
+        utils::thread_budget::ThreadBudget,
+    };
+
+    struct KeepAll;
+
+    impl RecordModifier for KeepAll {
+        type Error = anyhow::Error;
+
+        fn modify_record(
+            &self,
+            _record: &mut rust_htslib::bam::Record,
+        ) -> Result<Option<()>, Self::Error> {
+            Ok(Some(()))
+        }
+    }
+
+    struct Depth;
+
+    impl<'a> BamLocusWorker<'a> for Depth {
+        type Input = GenomeCoordinate<'a>;
+        type Output = u32;
+        type Error = anyhow::Error;
+
+        fn work_for_locus(&self, plp: Pileup, _input: Self::Input) -> Result<u32, Self::Error> {
+            Ok(plp.depth())
+        }
+    }
+
+    #[test]
+    fn test_dry_run_bam() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = tempfile::tempdir()?;
+        let bam_path = TestBam::new()
+            .read_group("rg1", "sample1")
+            .add_reads("chr1", 1_000, 1, 40, 10)
+            .add_reads("chr2", 0, 1, 2, 10)
+            .build(dir.path())?;
+        let out_path = dir.path().join("out.bam");
+        let opts = ProcessBamOptions {
+            batch_size: 8,
+            worker_threads: 6,
+            ..Default::default()
+        };
+        let processor =
+            ParallelBamProcessor::new(KeepAll).with_thread_budget(&ThreadBudget::new(8));
+
+        let plan = processor.dry_run(&bam_path, &out_path.as_path().into(), &opts)?;
+        assert_eq!(
+            plan.batches,
+            BatchPlan {
+                unit: "records".to_string(),
+                n_items: Some(42),
+                batch_size: Some(8),
+                n_batches: Some(6),
+            }
+        );
+        let header = plan.header.as_ref().unwrap();
+        assert_eq!((header.n_contigs, header.n_read_groups), (2, 1));
+        assert_eq!(header.sort_order.as_deref(), Some("coordinate"));
+        // 11 threads wanted, 8 had, taken of the stages with the most first.
+        let threads = plan.threads.iter().map(|(_, n)| *n).collect::<Vec<_>>();
+        assert_eq!(threads, [1, 4, 3]);
+        assert!(
+            plan.to_string()
+                .contains("batches: 6 of 42 records, 8 records each")
+        );
+        // the plan reads no record, nor leaves any file behind.
+        assert!(!out_path.exists());
+        assert_eq!(std::fs::read_dir(dir.path())?.count(), 2);
+
+        let stats = processor.process_bam(&bam_path, &out_path, &opts)?;
+        assert_eq!(stats.plan, Some(plan));
+        assert_eq!(stats.records_written, 42);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_dry_run_collects_problems() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = tempfile::tempdir()?;
+        let bam_path = TestBam::new()
+            .add_reads("chr1", 1_000, 1, 40, 10)
+            .build(dir.path())?;
+        std::fs::remove_file(dir.path().join("test.bam.bai"))?;
+        let not_a_dir = dir.path().join("not_a_dir");
+        std::fs::write(&not_a_dir, "")?;
+        let output = OutputTarget::File(not_a_dir.join("out.bam"));
+        let processor = ParallelBamProcessor::new(KeepAll);
+
+        let plan = processor.plan(&bam_path, &output, &ProcessBamOptions::default());
+        assert!(matches!(
+            plan.problems.as_slice(),
+            [
+                PlanProblem::MissingIndex { .. },
+                PlanProblem::UnwritableOutput { .. }
+            ]
+        ));
+        // the header is still read, without the index.
+        assert_eq!(plan.header.as_ref().map(|h| h.n_contigs), Some(1));
+        assert_eq!(plan.batches.n_batches, None);
+
+        let err = processor
+            .dry_run(&bam_path, &output, &ProcessBamOptions::default())
+            .unwrap_err();
+        let Error::InvalidPlan { problems } = &err else {
+            panic!("{:?}", err);
+        };
+        assert_eq!(problems, &plan.problems);
+        let msg = err.to_string();
+        assert!(
+            msg.starts_with("The run plan has 2 problems: Index of"),
+            "{}",
+            msg
+        );
+        assert!(msg.contains("not_a_dir is not a directory"), "{}", msg);
+
+        let missing = processor.plan(
+            dir.path().join("missing.bam"),
+            &OutputTarget::per_contig(dir.path().join("new/dir"), "{contig}.bam"),
+            &ProcessBamOptions::default(),
+        );
+        // the directories of per-contig outputs are created by the run.
+        assert!(matches!(
+            missing.problems.as_slice(),
+            [PlanProblem::MissingInput { .. }]
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_concurrent_writable_checks() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = tempfile::tempdir()?;
+        let out = dir.path().join("out.bam");
+        std::thread::scope(|s| {
+            let checks = (0..8)
+                .map(|_| s.spawn(|| (0..50).try_for_each(|_| check_writable(&out, false))))
+                .collect::<Vec<_>>();
+            checks
+                .into_iter()
+                .try_for_each(|check| check.join().unwrap())
+        })?;
+        assert_eq!(fs::read_dir(dir.path())?.count(), 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_corrupt_input() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = tempfile::tempdir()?;
+        let bam_path = TestBam::new()
+            .add_reads("chr1", 1_000, 1, 40, 10)
+            .build(dir.path())?;
+        // the bam without its EOF block, as of an interrupted copy.
+        let bytes = fs::read(&bam_path)?;
+        let truncated = dir.path().join("truncated.bam");
+        fs::write(&truncated, &bytes[..bytes.len() - 28])?;
+        fs::copy(
+            dir.path().join("test.bam.bai"),
+            dir.path().join("truncated.bam.bai"),
+        )?;
+        let out_path = dir.path().join("out.bam");
+        let processor = ParallelBamProcessor::new(KeepAll);
+
+        let opts = ProcessBamOptions::default();
+        let plan = processor.plan(&truncated, &out_path.as_path().into(), &opts);
+        assert!(matches!(
+            plan.problems.as_slice(),
+            [PlanProblem::CorruptInput { .. }]
+        ));
+        assert!(plan.to_string().contains("input check: error: the file"));
+        let err = processor
+            .process_bam(&truncated, &out_path, &opts)
+            .unwrap_err();
+        assert!(err.to_string().contains("looks corrupt"), "{}", err);
+        assert!(!out_path.exists());
+        let err = processor
+            .process_bam_sequential(&truncated, &out_path, &opts)
+            .unwrap_err();
+        assert!(err.to_string().contains("looks corrupt"), "{}", err);
+        assert!(!out_path.exists());
+
+        let opts = ProcessBamOptions {
+            input_check: InputCheck::Warn,
+            ..Default::default()
+        };
+        assert!(
+            processor
+                .plan(&truncated, &out_path.as_path().into(), &opts)
+                .is_valid()
+        );
+        let stats = processor.process_bam(&truncated, &out_path, &opts)?;
+        assert_eq!(stats.records_written, 40);
+        let sequential_path = dir.path().join("sequential.bam");
+        let stats = processor.process_bam_sequential(&truncated, &sequential_path, &opts)?;
+        assert_eq!(stats.records_written, 40);
+        assert_eq!(fs::read(&sequential_path)?, fs::read(&out_path)?);
+
+        let inputs = vec![GenomeCoordinate {
+            contig: Chrom::Chr1,
+            pos: 1_001,
+        }];
+        let processor = ParallelLocusProcessorPileup::builder(&truncated)
+            .worker(Depth)
+            .threads(1)
+            .build()?;
+        let err = processor
+            .process_with_batch(inputs.clone(), 100)
+            .unwrap_err();
+        assert!(err.to_string().contains("looks corrupt"), "{}", err);
+        let processor = processor.with_input_check(InputCheck::Skip);
+        assert_eq!(processor.process_with_batch(inputs, 100)?, [1]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_locus_plan() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = tempfile::tempdir()?;
+        let bam_path = TestBam::new()
+            .add_reads("chr1", 1_000, 1, 40, 10)
+            .build(dir.path())?;
+        let inputs = [
+            (Chrom::Chr1, 1_001),
+            (Chrom::Chr1, 1_005),
+            (Chrom::Chr1, 1_200),
+        ]
+        .into_iter()
+        .map(|(contig, pos)| GenomeCoordinate { contig, pos })
+        .collect::<Vec<_>>();
+
+        let builder = ParallelLocusProcessorPileup::builder(&bam_path)
+            .worker(Depth)
+            .threads(2);
+        let plan = builder.dry_run(&inputs, 100)?;
+        assert_eq!(plan.batches.n_batches, Some(2));
+        assert_eq!(plan.batches.n_items, Some(3));
+        assert_eq!(plan.threads, [("workers".to_string(), 2)]);
+        assert_eq!(builder.build()?.plan(&inputs, 100), plan);
+
+        let mut inputs = inputs;
+        inputs.push(GenomeCoordinate {
+            contig: Chrom::from("chrUn"),
+            pos: 1,
+        });
+        let err = ParallelLocusProcessorPileup::<Depth>::builder(&bam_path)
+            .dry_run(&inputs, 100)
+            .unwrap_err();
+        let Error::InvalidPlan { problems } = err else {
+            panic!("{:?}", err);
+        };
+        assert_eq!(
+            problems,
+            [
+                PlanProblem::UnknownContigs {
+                    contigs: vec!["chrUn".to_string()],
+                    n_contigs: 1,
+                },
+                PlanProblem::InvalidOption {
+                    message: "No worker set".to_string(),
+                },
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_plan_serde() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = tempfile::tempdir()?;
+        let bam_path = TestBam::new()
+            .add_reads("chr1", 1_000, 1, 40, 10)
+            .build(dir.path())?;
+        let plan = ParallelBamProcessor::new(KeepAll).plan(
+            &bam_path,
+            &OutputTarget::File(dir.path().join("out.bam")),
+            &ProcessBamOptions::default(),
+        );
+        let json = serde_json::to_string(&plan)?;
+        assert_eq!(serde_json::from_str::<RunPlan>(&json)?, plan);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,452 @@
+//! Pileups over the union of several bams of one sample, e.g. lane-level bams,
+//! without merging them first.
+
+use std::{
+    path::{Path, PathBuf},
+    thread,
+};
+
+use anyhow::{Error, anyhow};
+use rayon::{ThreadPool, ThreadPoolBuilder};
+use rust_htslib::bam::pileup::{Pileup, PileupOption};
+
+#[cfg(doc)]
+use crate::bam::reader::HtslibOpener;
+use crate::bam::{
+    process::{BamLocusWorkInput, BamLocusWorker, BamSet, CoordinateSystem},
+    reader::{BamOpener, RetryPolicy},
+};
+
+/// Bams pooled as one by [`PooledLocusProcessor`].
+///
+/// Every batch opens and fetches each bam, and the columns of the bams at a
+/// locus are passed to the worker together. Overlapping mates are counted once
+/// within a bam by default, see [`Self::with_pileup_options`], never across bams.
+pub struct PooledBamSource {
+    bams: BamSet,
+}
+
+impl PooledBamSource {
+    pub fn new(bam_paths: impl IntoIterator<Item = impl AsRef<Path>>) -> Self {
+        Self {
+            bams: BamSet::new(
+                bam_paths
+                    .into_iter()
+                    .map(|p| p.as_ref().to_path_buf())
+                    .collect(),
+            ),
+        }
+    }
+
+    /// Open the bams with `opener` instead of [`HtslibOpener`].
+    pub fn with_opener(mut self, opener: impl BamOpener + 'static) -> Self {
+        self.bams.opener = Box::new(opener);
+        self
+    }
+
+    /// Retries of opening and fetching the bams, 3 attempts by default.
+    pub fn with_retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.bams.retry_policy = retry_policy;
+        self
+    }
+
+    /// Options of the pileups of every bam, unlimited depth and overlapping
+    /// mates counted once by default, e.g. without `ignore_overlaps` for an
+    /// [`OverlapPolicy`](crate::bam::pileup_ext::OverlapPolicy) of the worker.
+    pub fn with_pileup_options(mut self, pileup_options: PileupOption) -> Self {
+        self.bams.pileup_options = pileup_options;
+        self
+    }
+
+    pub fn bam_paths(&self) -> &[PathBuf] {
+        &self.bams.bam_paths
+    }
+}
+
+/// [`BamLocusWorker`] counterpart for [`PooledLocusProcessor`].
+///
+/// rust_htslib's [`Pileup`] cannot be built from the alignments of several
+/// columns, so the worker gets the column of each bam instead.
+/// [`CombinedWorker`] runs a [`BamLocusWorker`] on each and combines the outputs.
+pub trait PooledLocusWorker<'a>: Send + Sync {
+    type Input: BamLocusWorkInput<'a>;
+    type Output: Send + Sync;
+    type Error: Into<Error>;
+
+    /// `pileups` has the columns at the locus of the bams covering it, in the
+    /// order of the [`PooledBamSource`], so it is never empty.
+    fn work_for_locus(
+        &self,
+        pileups: Vec<Pileup>,
+        input: Self::Input,
+    ) -> Result<Self::Output, Self::Error>;
+}
+
+/// A [`BamLocusWorker`] lifted to pooled bams: run on the column of each bam,
+/// with the outputs merged by `combine`, e.g. summed.
+pub struct CombinedWorker<W, F> {
+    worker: W,
+    combine: F,
+}
+
+impl<W, F> CombinedWorker<W, F> {
+    pub fn new(worker: W, combine: F) -> Self {
+        Self { worker, combine }
+    }
+}
+
+impl<'a, W, F, O> PooledLocusWorker<'a> for CombinedWorker<W, F>
+where
+    W: BamLocusWorker<'a>,
+    W::Input: Clone,
+    F: Fn(Vec<W::Output>) -> O + Send + Sync,
+    O: Send + Sync,
+{
+    type Input = W::Input;
+    type Output = O;
+    type Error = Error;
+
+    fn work_for_locus(&self, pileups: Vec<Pileup>, input: Self::Input) -> Result<O, Error> {
+        let outputs = pileups
+            .into_iter()
+            .map(|plp| {
+                self.worker
+                    .work_for_locus(plp, input.clone())
+                    .map_err(|err| err.into())
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+
+        Ok((self.combine)(outputs))
+    }
+}
+
+/// [`ParallelLocusProcessorPileup`](crate::bam::process::ParallelLocusProcessorPileup)
+/// over a [`PooledBamSource`]: inputs are batched by coordinate, and inputs not
+/// covered by any bam have no output.
+///
+/// Built by [`Self::builder`], e.g.
+/// `PooledLocusProcessor::builder(source).worker(w).threads(n).build()?`.
+pub struct PooledLocusProcessor<W: for<'a> PooledLocusWorker<'a>> {
+    worker: W,
+    n_threads: usize,
+    source: PooledBamSource,
+}
+
+/// Builder of [`PooledLocusProcessor`], see [`PooledLocusProcessor::builder`].
+pub struct PooledLocusProcessorBuilder<W> {
+    source: PooledBamSource,
+    worker: Option<W>,
+    n_threads: Option<usize>,
+}
+
+impl<W: for<'a> PooledLocusWorker<'a>> PooledLocusProcessorBuilder<W> {
+    /// The worker run at each locus. Required.
+    pub fn worker(mut self, worker: W) -> Self {
+        self.worker = Some(worker);
+        self
+    }
+
+    /// Threads of the pool of [`PooledLocusProcessor::process_with_batch`],
+    /// the available parallelism by default.
+    pub fn threads(mut self, n_threads: usize) -> Self {
+        self.n_threads = Some(n_threads);
+        self
+    }
+
+    /// How the positions of the inputs are read, 1-based by default.
+    pub fn coordinate_system(mut self, coordinate_system: CoordinateSystem) -> Self {
+        self.source.bams.coordinate_system = coordinate_system;
+        self
+    }
+
+    /// Fails if no worker is set, the thread count is 0, or the source has no bam.
+    pub fn build(self) -> Result<PooledLocusProcessor<W>, crate::Error> {
+        let Some(worker) = self.worker else {
+            return Err(anyhow!("No worker set for PooledLocusProcessor").into());
+        };
+        let n_threads = match self.n_threads {
+            Some(0) => return Err(anyhow!("Thread count must be at least 1").into()),
+            Some(n) => n,
+            None => thread::available_parallelism().map_or(1, |n| n.get()),
+        };
+        if self.source.bams.bam_paths.is_empty() {
+            return Err(anyhow!("No BAM was given to PooledBamSource").into());
+        }
+
+        Ok(PooledLocusProcessor {
+            worker,
+            n_threads,
+            source: self.source,
+        })
+    }
+}
+
+impl<W: for<'a> PooledLocusWorker<'a>> PooledLocusProcessor<W> {
+    /// Start building a processor of the bams of `source`.
+    pub fn builder(source: PooledBamSource) -> PooledLocusProcessorBuilder<W> {
+        PooledLocusProcessorBuilder {
+            source,
+            worker: None,
+            n_threads: None,
+        }
+    }
+
+    pub fn worker(&self) -> &W {
+        &self.worker
+    }
+
+    pub fn source(&self) -> &PooledBamSource {
+        &self.source
+    }
+
+    pub fn process_with_batch<'a>(
+        &self,
+        inputs: Vec<<W as PooledLocusWorker<'a>>::Input>,
+        batch_window_size: usize,
+    ) -> Result<Vec<<W as PooledLocusWorker<'a>>::Output>, crate::Error> {
+        if inputs.is_empty() {
+            // no pool for nothing, with the errors of the arguments all the same.
+            return self.process_with_batch_on(inputs, batch_window_size, None);
+        }
+        let tp = ThreadPoolBuilder::new()
+            .num_threads(self.n_threads)
+            .build()
+            .map_err(Error::from)?;
+        self.process_with_batch_on(inputs, batch_window_size, Some(&tp))
+    }
+
+    /// [`Self::process_with_batch`] on `pool`, or on the current rayon pool if
+    /// `None`. Outputs are in input order and the error is that of the first
+    /// failed batch.
+    pub fn process_with_batch_on<'a>(
+        &self,
+        inputs: Vec<<W as PooledLocusWorker<'a>>::Input>,
+        batch_window_size: usize,
+        pool: Option<&ThreadPool>,
+    ) -> Result<Vec<<W as PooledLocusWorker<'a>>::Output>, crate::Error> {
+        let res = self.source.bams.process_with_batch_on(
+            inputs,
+            batch_window_size,
+            pool,
+            |columns, inp| {
+                let pileups = columns.drain(..).flatten().collect::<Vec<_>>();
+                if pileups.is_empty() {
+                    return Ok(None);
+                }
+
+                let r = self
+                    .worker
+                    .work_for_locus(pileups, inp)
+                    .map_err(|err| err.into())?;
+                Ok(Some(r))
+            },
+        )?;
+        Ok(res)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        bam::process::ParallelLocusProcessorPileup,
+        data::{chrom::Chrom, locus::GenomeCoordinate},
+        test_utils::TestBam,
+    };
+
+    struct DepthWorker;
+
+    impl<'a> BamLocusWorker<'a> for DepthWorker {
+        type Input = GenomeCoordinate<'a>;
+        type Output = (i64, u32);
+        type Error = Error;
+
+        fn work_for_locus(&self, plp: Pileup, input: Self::Input) -> Result<(i64, u32), Error> {
+            Ok((input.pos, plp.depth()))
+        }
+    }
+
+    /// Depth summed over the bams, and the number of bams covering the locus.
+    struct PooledDepthWorker;
+
+    impl<'a> PooledLocusWorker<'a> for PooledDepthWorker {
+        type Input = GenomeCoordinate<'a>;
+        type Output = (i64, u32, usize);
+        type Error = Error;
+
+        fn work_for_locus(
+            &self,
+            pileups: Vec<Pileup>,
+            input: Self::Input,
+        ) -> Result<Self::Output, Error> {
+            let depth = pileups.iter().map(|plp| plp.depth()).sum();
+            Ok((input.pos, depth, pileups.len()))
+        }
+    }
+
+    fn inputs() -> Vec<GenomeCoordinate<'static>> {
+        (1..=3_000)
+            .step_by(7)
+            .map(|pos| GenomeCoordinate {
+                contig: Chrom::Chr1,
+                pos,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_pooled_depth_doubles() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = tempfile::tempdir()?;
+        // uneven depths over 1-based 1..=2_037, nothing after.
+        let bam_path = TestBam::new()
+            .add_reads("chr1", 0, 13, 150, 100)
+            .add_reads("chr1", 500, 3, 200, 60)
+            .build(dir.path())?;
+
+        let single = ParallelLocusProcessorPileup::new(DepthWorker, 2, bam_path.clone())
+            .process_with_batch(inputs(), 500)?;
+        assert!(single.len() > 200 && single.len() < inputs().len());
+
+        let source = || PooledBamSource::new([&bam_path, &bam_path]);
+        let pooled = PooledLocusProcessor::builder(source())
+            .worker(PooledDepthWorker)
+            .threads(2)
+            .build()?
+            .process_with_batch(inputs(), 500)?;
+        assert_eq!(
+            pooled,
+            single
+                .iter()
+                .map(|&(pos, depth)| (pos, depth * 2, 2))
+                .collect::<Vec<_>>()
+        );
+
+        let worker = CombinedWorker::new(DepthWorker, |outputs: Vec<(i64, u32)>| {
+            (outputs[0].0, outputs.iter().map(|(_, d)| d).sum::<u32>())
+        });
+        let combined = PooledLocusProcessor::builder(source())
+            .worker(worker)
+            .threads(2)
+            .build()?
+            .process_with_batch(inputs(), 500)?;
+        assert_eq!(
+            combined,
+            single
+                .iter()
+                .map(|&(pos, depth)| (pos, depth * 2))
+                .collect::<Vec<_>>()
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_pooled_lanes() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = tempfile::tempdir()?;
+        let (lane1, lane2) = (dir.path().join("lane1"), dir.path().join("lane2"));
+        std::fs::create_dir_all(&lane1)?;
+        std::fs::create_dir_all(&lane2)?;
+
+        // 2 reads over chr1:1-100 in lane 1, 3 over 51-150 in lane 2, which
+        // names its contigs without the `chr` prefix.
+        let lane1 = TestBam::new()
+            .add_reads("chr1", 0, 0, 2, 100)
+            .build(&lane1)?;
+        let lane2 = TestBam::new().add_reads("1", 50, 0, 3, 100).build(&lane2)?;
+
+        let coord = |pos| GenomeCoordinate {
+            contig: Chrom::Chr1,
+            pos,
+        };
+        let processor = |cs| {
+            PooledLocusProcessor::builder(PooledBamSource::new([&lane1, &lane2]))
+                .worker(PooledDepthWorker)
+                .threads(2)
+                .coordinate_system(cs)
+                .build()
+        };
+        let res = processor(CoordinateSystem::OneBased)?
+            .process_with_batch([10, 60, 140, 500].into_iter().map(coord).collect(), 1_000)?;
+        assert_eq!(res, vec![(10, 2, 1), (60, 5, 2), (140, 3, 1)]);
+        // 0-based: the first base of lane 2, and the bases just past lanes 1 and 2.
+        let res = processor(CoordinateSystem::ZeroBased)?
+            .process_with_batch([50, 100, 150].into_iter().map(coord).collect(), 1_000)?;
+        assert_eq!(res, vec![(50, 5, 2), (100, 3, 1)]);
+
+        let empty = PooledBamSource::new(Vec::<PathBuf>::new());
+        assert!(
+            PooledLocusProcessor::builder(empty)
+                .worker(PooledDepthWorker)
+                .build()
+                .is_err()
+        );
+        let no_worker =
+            PooledLocusProcessor::<PooledDepthWorker>::builder(PooledBamSource::new([&lane1]));
+        assert!(no_worker.build().is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_pooled_at_contig_ends() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = tempfile::tempdir()?;
+        let (lane1, lane2) = (dir.path().join("lane1"), dir.path().join("lane2"));
+        std::fs::create_dir_all(&lane1)?;
+        std::fs::create_dir_all(&lane2)?;
+
+        // chr1 covered to its end in both lanes, of 1_000 bp in lane 1 and of
+        // 2_000 bp in lane 2.
+        let lane1 = TestBam::new()
+            .contig("chr1", 1_000)
+            .add_reads("chr1", 0, 10, 100, 10)
+            .build(&lane1)?;
+        let lane2 = TestBam::new()
+            .contig("chr1", 2_000)
+            .add_reads("chr1", 0, 10, 200, 10)
+            .build(&lane2)?;
+        let run = |coordinate_system, positions: &[i64]| {
+            PooledLocusProcessor::builder(PooledBamSource::new([&lane1, &lane2]))
+                .worker(PooledDepthWorker)
+                .threads(2)
+                .coordinate_system(coordinate_system)
+                .build()?
+                .process_with_batch(
+                    positions
+                        .iter()
+                        .map(|&pos| GenomeCoordinate {
+                            contig: Chrom::Chr1,
+                            pos,
+                        })
+                        .collect(),
+                    100,
+                )
+        };
+
+        // the batch reaching before the start, or past the end, is clamped to
+        // the contig of each bam.
+        let res = run(CoordinateSystem::OneBased, &[0, 1, 1_000, 1_050])?;
+        assert_eq!(res, [(1, 2, 2), (1_000, 2, 2), (1_050, 1, 1)]);
+        let res = run(CoordinateSystem::ZeroBased, &[0, 999])?;
+        assert_eq!(res, [(0, 2, 2), (999, 2, 2)]);
+
+        // a batch wholly past the end of the contig of a bam fails.
+        for (coordinate_system, positions) in [
+            (CoordinateSystem::OneBased, [1_001, 1_050]),
+            (CoordinateSystem::ZeroBased, [1_000, 1_049]),
+        ] {
+            let err = run(coordinate_system, &positions).unwrap_err();
+            let crate::Error::OutOfContig {
+                contig,
+                len,
+                coordinates,
+            } = err
+            else {
+                panic!("{:#}", err);
+            };
+            assert_eq!((contig.as_str(), len), ("chr1", 1_000));
+            assert_eq!(coordinates, ["chr1:1001", "chr1:1050"]);
+        }
+
+        Ok(())
+    }
+}
@@ -3,57 +3,371 @@
 //! 2 Processes are supported:
 //! 1. Make a modified bam (with Producer and Consumer)
 //! 2. Do a given task per genomic positions (in parallel)
-//!     Examples)
-//!     a. Pileup each variant position and get reads.
+//!    Examples)
+//!    a. Pileup each variant position and get reads.
+//!    b. Compare the pileups of several BAMs at each position, see [`MultiBamLocusProcessor`].
 //!
+//! Per-position work is set up with [`ParallelLocusProcessorPileup::builder`],
+//! which checks the bam and its index before anything runs:
 //!
+//! ```no_run
+//! # use crackle_kit::{bam::process::*, data::{chrom::Chrom, locus::GenomeCoordinate}};
+//! # use crackle_kit::rust_htslib::bam::pileup::Pileup;
+//! struct DepthWorker;
 //!
+//! impl<'a> BamLocusWorker<'a> for DepthWorker {
+//!     type Input = GenomeCoordinate<'a>;
+//!     type Output = u32;
+//!     type Error = anyhow::Error;
+//!
+//!     fn work_for_locus(&self, plp: Pileup, _input: Self::Input) -> Result<u32, Self::Error> {
+//!         Ok(plp.depth())
+//!     }
+//! }
+//!
+//! # fn main() -> Result<(), anyhow::Error> {
+//! let processor = ParallelLocusProcessorPileup::builder("sample.bam")
+//!     .worker(DepthWorker)
+//!     .threads(4)
+//!     .build()?;
+//! let inputs = vec![GenomeCoordinate {
+//!     contig: Chrom::Chr1,
+//!     pos: 1_000,
+//! }];
+//! let depths = processor.process_with_batch(inputs, 10_000)?;
+//! # Ok(())
+//! # }
+//! ```
 
 use std::{
     cmp::Ordering,
-    collections::{HashMap, HashSet},
-    hash::RandomState,
+    collections::{BTreeMap, HashMap, HashSet},
+    hash::{Hash, RandomState},
     i32,
+    iter::Peekable,
+    ops::Range,
     path::{Path, PathBuf},
     rc::Rc,
     sync::{
-        Arc,
-        atomic::{self, AtomicUsize},
+        Mutex, OnceLock,
+        atomic::{self, AtomicBool, AtomicU64},
     },
-    thread::{self, sleep},
-    time::{Duration, Instant},
+    thread,
+    time::Duration,
 };
 
-use anyhow::Error;
-use crossbeam_channel::{bounded, RecvError, Sender, TryRecvError};
-use indicatif::ProgressBar;
+use anyhow::{Context, Error, anyhow};
+use crossbeam_channel::TryRecvError;
 use rayon::{
-    ThreadPoolBuilder,
-    iter::{IntoParallelIterator, IntoParallelRefIterator, ParallelIterator},
+    ThreadPool, ThreadPoolBuilder,
+    iter::{
+        IndexedParallelIterator, IntoParallelIterator, IntoParallelRefIterator, ParallelIterator,
+    },
 };
 use rust_htslib::bam::{
-    self, Header, HeaderView, IndexedReader, Read as _, Record, Writer,
-    pileup::{Pileup, PileupOption},
+    self, Header, HeaderView, Read as _, Record, Writer,
+    pileup::{Pileup, PileupOption, Pileups},
 };
-use tracing::{Level, event};
-
+use tracing::{Level, Span, event, span};
+
+use crate::bam::checkpoint::{self, LocusCheckpoint};
+use crate::bam::plan::{BatchPlan, RunPlan};
+use crate::bam::result_sink::LocusResultSink;
+#[cfg(feature = "liftover")]
+use crate::data::liftover::{ChainLiftover, LiftResult};
+#[cfg(feature = "mmap")]
+use crate::data::ref_mmap::MmapFasta;
+use crate::data::seq_dict::{DictCheck, SeqDict};
+use crate::errors::{PlanProblem, ThreadBudgetError};
+#[cfg(feature = "bio")]
+use crate::reference::RefGenome;
+use crate::utils::channel_metrics::ChannelStats;
+use crate::utils::columnar::{ColumnarSink, OrderedMerge};
+use crate::utils::fmt::SiCount;
+use crate::utils::panic::catch_panic;
+use crate::utils::pipeline::DEFAULT_METRICS_INTERVAL;
+use crate::utils::thread_budget::{BudgetGuard, ThreadBudget};
+use crate::utils::throughput::{DEFAULT_THROUGHPUT_INTERVAL, ThroughputLogger};
 use crate::{
+    bam::context::ProcessContext,
+    bam::decision_log::{Decision, DecisionLogThread, DecisionLogWriter, Outcome},
+    bam::filter::ReadFilter,
+    bam::output::{OutputTarget, OutputWriters},
+    bam::headerless::HeaderlessRecord,
+    utils::instrument::{BusyTime, current_dispatch, enter_on_thread},
+    bam::paired::PairingOptions,
+    bam::pileup_ext::{PileupExt, SampledPileup},
+    bam::reader::{
+        BamOpener, HtslibOpener, RetryPolicy, clamp_to_contig, contig_len, contig_tid, fetch_tid,
+        fetch_with_retry, find_bam_index, index_counts_no_records, open_with_retry,
+        resolve_contig_name,
+    },
+    bam::validate::InputCheck,
+    bam::watchdog::{BatchTimeoutPolicy, BatchWatchdog},
     data::{
-        data_with_index::DataWithIndex,
+        bases::Base,
+        chrom::Chrom,
         locus::{GenomeCoordinate, GenomeRegion},
+        variant::Variant,
     }, pbar::prepare_pbar, utils::{
-        batch_region::batch_region, batched_channel::BatchedChannel, batched_data::BatchedData,
+        batch_region::batch_region, batched_channel::BatchedChannel,
+        batch_tuning::{DEFAULT_MAX_WINDOW, describe_batching, suggest_window},
+        pipeline::{Batch, OrderedPipeline},
+        pool::PoolStats,
     }
 };
 
 const N_1M: usize = 10_usize.pow(6);
 
+/// A pool of `n_threads` threads, or of the total of `budget` if fewer, with
+/// the guard of its threads reserved of `budget`.
+fn budgeted_pool(
+    n_threads: usize,
+    budget: Option<&ThreadBudget>,
+) -> Result<(ThreadPool, Option<BudgetGuard>), Error> {
+    let guard = budget
+        .map(|budget| budget.reserve(n_threads.min(budget.total()).max(1)))
+        .transpose()?;
+    let tp = ThreadPoolBuilder::new()
+        .num_threads(guard.as_ref().map_or(n_threads, BudgetGuard::n_threads))
+        .build()?;
+    Ok((tp, guard))
+}
+
+/// Threads of the reader, the workers and the writer of `opts`, cut to fit the
+/// threads reserved of `budget`: those of the stage with the most first, down
+/// to one each, so a budget of fewer than 3 threads fails.
+pub(crate) fn budgeted_stage_threads(
+    opts: &ProcessBamOptions,
+    budget: Option<&ThreadBudget>,
+) -> Result<([usize; 3], Option<BudgetGuard>), Error> {
+    let threads = stage_threads(opts, budget.map(ThreadBudget::total));
+    let Some(budget) = budget else {
+        return Ok((threads, None));
+    };
+    let guard = budget.reserve(threads.iter().sum())?;
+    Ok((threads, Some(guard)))
+}
+
+/// Threads of the stages of [`budgeted_stage_threads`], cut to fit a budget of
+/// `total` threads if any, but to no fewer than 3.
+fn stage_threads(opts: &ProcessBamOptions, total: Option<usize>) -> [usize; 3] {
+    let mut threads = [opts.read_threads, opts.worker_threads, opts.write_threads];
+    let Some(total) = total else {
+        return threads;
+    };
+
+    threads.iter_mut().for_each(|n| *n = (*n).max(1));
+    let wanted = threads.iter().sum::<usize>();
+    for _ in wanted.min(total.max(3))..wanted {
+        let most = (0..3).max_by_key(|&i| threads[i]).unwrap();
+        threads[most] -= 1;
+    }
+    threads
+}
+
+/// The plan of a run of the worker of a [`ParallelLocusProcessorPileup`] on
+/// the bam at `bam_path` with `n_threads` threads, of `inputs` in `batches`.
+#[allow(clippy::too_many_arguments)]
+fn locus_plan<'a, 'b, I: BamLocusWorkInput<'a> + 'b>(
+    bam_path: &Path,
+    opener: &dyn BamOpener,
+    retry_policy: &RetryPolicy,
+    input_check: InputCheck,
+    n_threads: usize,
+    budget: Option<&ThreadBudget>,
+    inputs: impl IntoIterator<Item = &'b I>,
+    batches: BatchPlan,
+) -> RunPlan {
+    let (mut plan, reader) = RunPlan::of_input(
+        "process_with_batch",
+        bam_path,
+        opener,
+        retry_policy,
+        input_check,
+    );
+
+    let mut contigs = vec![];
+    let mut seen = HashSet::new();
+    for input in inputs {
+        let contig = &input.genome_coordinate().contig;
+        if seen.insert(contig) {
+            contigs.push(contig);
+        }
+    }
+    if let Some(reader) = &reader {
+        let header = reader.header();
+        let unknown = contigs
+            .into_iter()
+            .filter(|contig| contig_tid(header, contig).is_none())
+            .map(|contig| contig.to_string())
+            .collect::<Vec<_>>();
+        if !unknown.is_empty() {
+            plan.problems.push(PlanProblem::UnknownContigs {
+                contigs: unknown,
+                n_contigs: header.target_count() as usize,
+            });
+        }
+    }
+
+    let n_threads = match budget {
+        Some(budget) => n_threads.min(budget.total()).max(1),
+        None => n_threads,
+    };
+    plan.threads = vec![("workers".to_string(), n_threads)];
+    plan.thread_budget = budget.map(ThreadBudget::total);
+    plan.batches = batches;
+    plan
+}
+
+/// The [`BatchPlan`] of `n_inputs` loci in `n_batches` batches made with
+/// `batch_window_size`.
+fn locus_batch_plan(n_inputs: usize, n_batches: usize, batch_window_size: usize) -> BatchPlan {
+    BatchPlan {
+        unit: "loci".to_string(),
+        n_items: Some(n_inputs as u64),
+        batch_size: Some(batch_window_size as u64),
+        n_batches: Some(n_batches as u64),
+    }
+}
+
 pub trait BamLocusWorker<'a>: Send + Sync {
     type Input: BamLocusWorkInput<'a>;
     type Output: Send + Sync;
     type Error: Into<Error>;
 
     fn work_for_locus(&self, plp: Pileup, input: Self::Input) -> Result<Self::Output, Self::Error>;
+
+    /// Called by the processor instead of [`Self::work_for_locus`].
+    ///
+    /// `ref_base` is the reference base at the locus when the processor has a
+    /// reference (see `ParallelLocusProcessorPileup::with_reference` and
+    /// `with_mmap_reference`) and the base is one of A, C, G, T, N. By default it
+    /// is ignored.
+    fn work_for_locus_with_ref(
+        &self,
+        plp: Pileup,
+        input: Self::Input,
+        ref_base: Option<Base>,
+    ) -> Result<Self::Output, Self::Error> {
+        let _ = ref_base;
+        self.work_for_locus(plp, input)
+    }
+
+    /// Called by the processor instead of [`Self::work_for_locus_with_ref`]
+    /// when it caps the alignments per locus, see
+    /// `ParallelLocusProcessorPileup::with_max_alignments_per_locus`. `sample`
+    /// has the alignments kept of the column, and its depth to scale counts
+    /// with. By default it is ignored, so workers whose cost grows with the
+    /// depth should use it rather than `plp`.
+    fn work_for_locus_sampled(
+        &self,
+        plp: Pileup,
+        input: Self::Input,
+        ref_base: Option<Base>,
+        sample: SampledPileup,
+    ) -> Result<Self::Output, Self::Error> {
+        let _ = sample;
+        self.work_for_locus_with_ref(plp, input, ref_base)
+    }
+}
+
+/// A [`BamLocusWorker`] with a state of its own on each thread of the pool,
+/// e.g. a model session or a large lookup table, made once per thread rather
+/// than shared behind a lock, see
+/// [`ParallelLocusProcessorPileup::process_with_thread_state`].
+///
+/// Workers without state run as they are by the other methods of the
+/// processor, or as one of no state wrapped in [`Stateless`].
+pub trait LocusWorkerInit<'a>: BamLocusWorker<'a> {
+    type ThreadState: Send;
+
+    /// The state of a thread, made once on each thread of the pool before any
+    /// batch runs. An error fails the run.
+    fn init_thread(&self) -> Result<Self::ThreadState, Error>;
+
+    /// [`BamLocusWorker::work_for_locus`] with the state of the thread it runs
+    /// on, which no other call uses meanwhile.
+    fn work_for_locus_with_state(
+        &self,
+        state: &mut Self::ThreadState,
+        plp: Pileup,
+        input: Self::Input,
+    ) -> Result<Self::Output, Self::Error>;
+}
+
+/// Adaptor of a worker without state into a [`LocusWorkerInit`] of no state,
+/// for code generic over the latter.
+#[derive(Debug, Clone, Default)]
+pub struct Stateless<W>(pub W);
+
+impl<'a, W: BamLocusWorker<'a>> BamLocusWorker<'a> for Stateless<W> {
+    type Input = W::Input;
+    type Output = W::Output;
+    type Error = W::Error;
+
+    fn work_for_locus(&self, plp: Pileup, input: Self::Input) -> Result<Self::Output, Self::Error> {
+        self.0.work_for_locus(plp, input)
+    }
+
+    fn work_for_locus_with_ref(
+        &self,
+        plp: Pileup,
+        input: Self::Input,
+        ref_base: Option<Base>,
+    ) -> Result<Self::Output, Self::Error> {
+        self.0.work_for_locus_with_ref(plp, input, ref_base)
+    }
+
+    fn work_for_locus_sampled(
+        &self,
+        plp: Pileup,
+        input: Self::Input,
+        ref_base: Option<Base>,
+        sample: SampledPileup,
+    ) -> Result<Self::Output, Self::Error> {
+        self.0.work_for_locus_sampled(plp, input, ref_base, sample)
+    }
+}
+
+impl<'a, W: BamLocusWorker<'a>> LocusWorkerInit<'a> for Stateless<W> {
+    type ThreadState = ();
+
+    fn init_thread(&self) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn work_for_locus_with_state(
+        &self,
+        _state: &mut (),
+        plp: Pileup,
+        input: Self::Input,
+    ) -> Result<Self::Output, Self::Error> {
+        self.0.work_for_locus(plp, input)
+    }
+}
+
+/// The worker of [`ParallelLocusProcessorPileup::process_with_thread_state`]:
+/// `W` given the state of the pool thread it runs on, by its index.
+struct ThreadStateWorker<'w, W, S> {
+    worker: &'w W,
+    states: Vec<Mutex<S>>,
+}
+
+impl<'a, W: LocusWorkerInit<'a>> BamLocusWorker<'a> for ThreadStateWorker<'_, W, W::ThreadState> {
+    type Input = W::Input;
+    type Output = W::Output;
+    type Error = Error;
+
+    fn work_for_locus(&self, plp: Pileup, input: Self::Input) -> Result<Self::Output, Error> {
+        let state = rayon::current_thread_index()
+            .and_then(|idx| self.states.get(idx))
+            .ok_or_else(|| anyhow!("Not run on a thread of the pool"))?;
+        let mut state = state.lock().unwrap();
+        self.worker
+            .work_for_locus_with_state(&mut state, plp, input)
+            .map_err(Into::into)
+    }
 }
 
 pub trait BamLocusWorkInput<'a>: Send + Sync {
@@ -66,800 +380,5591 @@ impl<'a> BamLocusWorkInput<'a> for GenomeCoordinate<'a> {
     }
 }
 
-fn batch_input_by_coordinate<'a, I: BamLocusWorkInput<'a>>(
+impl<'a> BamLocusWorkInput<'a> for Variant<'a> {
+    fn genome_coordinate(&self) -> &GenomeCoordinate<'a> {
+        self.coordinate()
+    }
+}
+
+/// Split `inputs`, sorted by coordinate, into batches of the same contig whose
+/// positions are less than `window_size` away from the first one of the batch.
+///
+/// A position lower than the previous one also starts a new batch, so that
+/// every batch is sorted even if `inputs` are not, only in smaller batches.
+///
+/// The inputs are moved into exactly sized batches.
+pub fn batch_input_by_coordinate<'a, I: BamLocusWorkInput<'a>>(
     inputs: impl IntoIterator<Item = I>,
     window_size: usize,
 ) -> Vec<Vec<I>> {
+    let inputs = inputs.into_iter().collect::<Vec<_>>();
+    let ranges = batch_indices_by_coordinate(&inputs, window_size);
+
     let mut input_iter = inputs.into_iter();
-    let mut c_vec = vec![];
-    let (mut c_contig, mut c_start) = match input_iter.next() {
-        Some(inp) => {
-            let gc = inp.genome_coordinate();
+    ranges
+        .into_iter()
+        .map(|range| input_iter.by_ref().take(range.len()).collect())
+        .collect()
+}
 
-            let contig_clone = gc.contig.clone();
-            let start_val = gc.pos;
-            c_vec.push(inp);
-            (contig_clone, start_val)
-        }
-        None => return vec![],
-    };
+/// Index-based [`batch_input_by_coordinate`]: returns the ranges of `inputs`
+/// forming each batch, without moving or cloning anything.
+pub fn batch_indices_by_coordinate<'a, I: BamLocusWorkInput<'a>>(
+    inputs: &[I],
+    window_size: usize,
+) -> Vec<Range<usize>> {
+    let mut res = vec![];
+    if inputs.is_empty() {
+        return res;
+    }
 
-    let mut res: Vec<Vec<I>> = vec![];
-    for inp in input_iter {
+    let mut batch_start = 0;
+    let mut first = inputs[0].genome_coordinate();
+    let mut prev_pos = first.pos;
+    for (i, inp) in inputs.iter().enumerate().skip(1) {
         let gc = inp.genome_coordinate();
         // Condition to start a new batch:
         // 1. The contig changes.
-        // 2. The span from the batch's start to the current region's end exceeds window_size.
-        if c_contig == gc.contig && gc.pos - c_start < window_size as i64 {
-            c_vec.push(inp);
-        } else {
-            res.push(c_vec); // Push the completed batch
-            c_contig = gc.contig.clone(); // Start a new batch: reset contig and start
-            c_start = gc.pos;
-            c_vec = vec![inp]; // Start new batch with current region
+        // 2. The span from the batch's start to the current position reaches window_size.
+        // 3. The position goes back. Batches are fetched from their first to their
+        //    last position and swept in order, so such an input would be missed.
+        if first.contig != gc.contig
+            || gc.pos - first.pos >= window_size as i64
+            || gc.pos < prev_pos
+        {
+            res.push(batch_start..i);
+            batch_start = i;
+            first = gc;
         }
+        prev_pos = gc.pos;
     }
-
-    // Push any remaining regions in the last batch
-    if !c_vec.is_empty() {
-        res.push(c_vec);
-    }
+    res.push(batch_start..inputs.len());
 
     res
 }
 
+/// Positions of the inputs are read as set by [`CoordinateSystem`], 1-based
+/// by default, as in [`GenomeCoordinate`].
 ///
 /// # Example
-/// ```
-/// struct MeanBPWorker {
-/// }
-///
-/// impl BamLocusWorker for MeanBPWorker {
-///     fn work_for_locus() {
-///         // your code here
-///     }
-/// }
+/// ```no_run
+/// use crackle_kit::{
+///     bam::process::{BamLocusWorker, ParallelLocusProcessorPileup},
+///     data::locus::GenomeCoordinate,
+/// };
+/// use rust_htslib::bam::pileup::Pileup;
 ///
+/// /// Mean base quality at each locus.
+/// struct MeanBPWorker;
 ///
+/// impl<'a> BamLocusWorker<'a> for MeanBPWorker {
+///     type Input = GenomeCoordinate<'a>;
+///     type Output = f64;
+///     type Error = anyhow::Error;
 ///
+///     fn work_for_locus(&self, plp: Pileup, _input: Self::Input) -> Result<f64, Self::Error> {
+///         let quals = plp
+///             .alignments()
+///             .filter_map(|aln| aln.qpos().map(|i| aln.record().qual()[i] as f64))
+///             .collect::<Vec<_>>();
+///         Ok(quals.iter().sum::<f64>() / quals.len().max(1) as f64)
+///     }
+/// }
 ///
+/// let processor = ParallelLocusProcessorPileup::new(MeanBPWorker, 4, "sample.bam".into());
+/// let inputs = vec![GenomeCoordinate::new("chr1", 10_001)?];
+/// let means = processor.process_with_batch(inputs, 1_000)?;
+/// # Ok::<_, Box<dyn std::error::Error>>(())
 /// ```
 pub struct ParallelLocusProcessorPileup<W: for<'a> BamLocusWorker<'a>> {
     bam_locus_worker: W,
     n_threads: usize,
     bam_path: PathBuf,
+    pub(crate) opener: Box<dyn BamOpener>,
+    pub(crate) retry_policy: RetryPolicy,
+    pub(crate) pileup_options: PileupOption,
+    pub(crate) max_alignments_per_locus: Option<usize>,
+    pub(crate) coordinate_system: CoordinateSystem,
+    batch_timeout: Option<(Duration, BatchTimeoutPolicy)>,
+    thread_budget: Option<ThreadBudget>,
+    dict_check: Option<DictCheck>,
+    input_check: InputCheck,
+    #[cfg(feature = "bio")]
+    reference: Option<RefGenome>,
+    #[cfg(feature = "mmap")]
+    mmap_reference: Option<std::sync::Arc<MmapFasta>>,
 }
 
-impl<W: for<'a> BamLocusWorker<'a>> ParallelLocusProcessorPileup<W> {
-    pub fn new(bam_locus_worker: W, n_threads: usize, bam_path: PathBuf) -> Self {
-        Self {
-            bam_locus_worker,
-            n_threads,
-            bam_path,
+/// How the positions of [`BamLocusWorkInput`]s are read.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum CoordinateSystem {
+    /// The first base of a contig is 1, as in VCF and [`GenomeCoordinate`].
+    #[default]
+    OneBased,
+    /// The first base of a contig is 0, as in BED starts and htslib.
+    ZeroBased,
+}
+
+impl CoordinateSystem {
+    pub(crate) fn to_0based(self, pos: i64) -> i64 {
+        match self {
+            Self::OneBased => pos - 1,
+            Self::ZeroBased => pos,
         }
     }
+}
 
-    pub fn process_with_batch<'a>(
-        &self,
-        inputs: Vec<<W as BamLocusWorker<'a>>::Input>,
-        batch_window_size: usize,
-    ) -> Result<Vec<<W as BamLocusWorker<'a>>::Output>, Error> {
-        // make batch
-        let batched_regions = batch_input_by_coordinate(inputs.into_iter(), batch_window_size);
+/// Maximum depth unlimited, overlapping mates counted once.
+const DEFAULT_PILEUP_OPTIONS: PileupOption = PileupOption {
+    max_depth: i32::MAX,
+    ignore_overlaps: true,
+};
 
-        event!(
-            Level::DEBUG,
-            "batched_regions len={}",
-            batched_regions.len()
-        );
+/// Builder of [`ParallelLocusProcessorPileup`], see
+/// [`ParallelLocusProcessorPileup::builder`].
+pub struct ParallelLocusProcessorPileupBuilder<W> {
+    bam_path: PathBuf,
+    worker: Option<W>,
+    n_threads: Option<usize>,
+    pileup_options: PileupOption,
+    max_alignments_per_locus: Option<usize>,
+    coordinate_system: CoordinateSystem,
+    batch_timeout: Option<(Duration, BatchTimeoutPolicy)>,
+    thread_budget: Option<ThreadBudget>,
+    input_check: InputCheck,
+}
 
-        // open threadpool and distribute the jobs.
-        let tp = ThreadPoolBuilder::new()
-            .num_threads(self.n_threads)
-            .build()?;
+impl<W: for<'a> BamLocusWorker<'a>> ParallelLocusProcessorPileupBuilder<W> {
+    /// The worker run at each locus. Required.
+    pub fn worker(mut self, worker: W) -> Self {
+        self.worker = Some(worker);
+        self
+    }
 
-        let batch_res = tp.scope(|_scope| {
-            event!(Level::DEBUG, "Parallel Processing...");
+    /// Threads of the pool of [`ParallelLocusProcessorPileup::process_with_batch`],
+    /// the available parallelism by default.
+    pub fn threads(mut self, n_threads: usize) -> Self {
+        self.n_threads = Some(n_threads);
+        self
+    }
 
-            let r = batched_regions
-                .into_par_iter()
-                .map(|batch| {
-                    if batch.is_empty() {
-                        return Ok(vec![]);
-                    }
+    /// Options of the pileups, unlimited depth and overlapping mates counted
+    /// once by default.
+    pub fn pileup_options(mut self, pileup_options: PileupOption) -> Self {
+        self.pileup_options = pileup_options;
+        self
+    }
 
-                    let mut ir = IndexedReader::from_path(&self.bam_path)?;
+    /// See [`ParallelLocusProcessorPileup::with_max_alignments_per_locus`].
+    pub fn max_alignments_per_locus(mut self, max: usize) -> Self {
+        self.max_alignments_per_locus = Some(max);
+        self
+    }
 
-                    let first_elem = batch.first().unwrap();
-                    let last_elem = batch.last().unwrap();
+    pub fn coordinate_system(mut self, coordinate_system: CoordinateSystem) -> Self {
+        self.coordinate_system = coordinate_system;
+        self
+    }
+
+    /// See [`ParallelLocusProcessorPileup::with_batch_timeout`].
+    pub fn batch_timeout(mut self, timeout: Duration, policy: BatchTimeoutPolicy) -> Self {
+        self.batch_timeout = Some((timeout, policy));
+        self
+    }
 
-                    let batch_contig = first_elem.genome_coordinate().contig.as_str();
-                    let batch_pileup_start = first_elem.genome_coordinate().pos - 1; // batch is not empty, by the if condition of function start point.
-                    let batch_pileup_end = last_elem.genome_coordinate().pos;
+    /// See [`ParallelLocusProcessorPileup::with_thread_budget`].
+    pub fn thread_budget(mut self, budget: &ThreadBudget) -> Self {
+        self.thread_budget = Some(budget.clone());
+        self
+    }
 
-                    ir.fetch((batch_contig, batch_pileup_start, batch_pileup_end))?;
-                    let mut pileups = ir
-                        .pileup_with_option(PileupOption {
-                            max_depth: i32::MAX,
-                            ignore_overlaps: true,
-                        })
-                        .peekable();
+    /// See [`ParallelLocusProcessorPileup::with_input_check`].
+    pub fn input_check(mut self, check: InputCheck) -> Self {
+        self.input_check = check;
+        self
+    }
 
-                    // Create peekable iterators for both the pileups and the batch of inputs.
-                    let mut res = Vec::with_capacity(batch.len());
+    /// What [`Self::build`], then [`ParallelLocusProcessorPileup::process_with_batch`]
+    /// of `inputs` with `batch_window_size`, would do, checked without reading
+    /// any record: that a worker is set, that the bam exists and is indexed,
+    /// and that the contigs of the inputs are in its header. See [`RunPlan`].
+    pub fn plan<'a>(
+        &self,
+        inputs: &[<W as BamLocusWorker<'a>>::Input],
+        batch_window_size: usize,
+    ) -> RunPlan {
+        let n_threads = self
+            .n_threads
+            .unwrap_or_else(|| thread::available_parallelism().map_or(1, |n| n.get()));
+        let n_batches = batch_indices_by_coordinate(inputs, batch_window_size).len();
+        let mut plan = locus_plan(
+            &self.bam_path,
+            &HtslibOpener,
+            &RetryPolicy::default(),
+            self.input_check,
+            n_threads,
+            self.thread_budget.as_ref(),
+            inputs,
+            locus_batch_plan(inputs.len(), n_batches, batch_window_size),
+        );
+        if self.worker.is_none() {
+            plan.problems.push(PlanProblem::InvalidOption {
+                message: "No worker set".to_string(),
+            });
+        }
+        if n_threads == 0 {
+            plan.problems.push(PlanProblem::Threads {
+                message: "Thread count must be at least 1".to_string(),
+            });
+        }
+        plan
+    }
 
-                    let mut batch_peekable = batch.into_iter().peekable();
+    /// [`Self::plan`], failing with an [`InvalidPlan`](crate::Error::InvalidPlan)
+    /// error of all its problems if it has any.
+    pub fn dry_run<'a>(
+        &self,
+        inputs: &[<W as BamLocusWorker<'a>>::Input],
+        batch_window_size: usize,
+    ) -> Result<RunPlan, crate::Error> {
+        self.plan(inputs, batch_window_size).into_result()
+    }
 
-                    // This is the efficient "merge/zip" sweep-line algorithm
-                    while let (Some(Ok(pileup_col)), Some(input)) =
-                        (pileups.peek(), batch_peekable.peek())
-                    {
-                        let pileup_pos = pileup_col.pos() as i64;
-                        // Assuming you've updated the trait to use GenomeCoordinate
-                        let target_pos = input.genome_coordinate().pos - 1;
+    /// Fails if no worker is set, the thread count is 0, or the bam or its index
+    /// does not exist, as an [`Io`](crate::Error::Io) error.
+    pub fn build(self) -> Result<ParallelLocusProcessorPileup<W>, crate::Error> {
+        let Some(worker) = self.worker else {
+            return Err(anyhow!("No worker set for {}", self.bam_path.display()).into());
+        };
+        let n_threads = match self.n_threads {
+            Some(0) => return Err(anyhow!("Thread count must be at least 1").into()),
+            Some(n) => n,
+            None => thread::available_parallelism().map_or(1, |n| n.get()),
+        };
+        find_bam_index(&self.bam_path)?;
+
+        let mut processor = ParallelLocusProcessorPileup::new(worker, n_threads, self.bam_path);
+        processor.pileup_options = self.pileup_options;
+        processor.max_alignments_per_locus = self.max_alignments_per_locus;
+        processor.coordinate_system = self.coordinate_system;
+        processor.batch_timeout = self.batch_timeout;
+        processor.thread_budget = self.thread_budget;
+        processor.input_check = self.input_check;
+        Ok(processor)
+    }
+}
 
-                        match pileup_pos.cmp(&target_pos) {
-                            Ordering::Less => {
-                                // Case 1: Pileup is before our target site.
-                                // Discard the pileup and advance the pileup iterator.
-                                pileups.next();
-                            }
-                            Ordering::Greater => {
-                                // Case 2: We've passed our target site, but there was no pileup (zero coverage).
-                                // Discard the target and advance the site iterator.
-                                batch_peekable.next();
-                            }
-                            Ordering::Equal => {
-                                // Case 3: Match found! Process it.
-                                // We must consume both items from the iterators to advance.
-                                if let (Some(Ok(plp)), Some(inp)) =
-                                    (pileups.next(), batch_peekable.next())
-                                {
-                                    let r = self
-                                        .bam_locus_worker
-                                        .work_for_locus(plp, inp)
-                                        .map_err(|err| err.into())?;
-                                    res.push(r);
-                                }
-                            }
-                        }
-                    }
+impl<W: for<'a> BamLocusWorker<'a>> ParallelLocusProcessorPileup<W> {
+    /// Start building a processor of the bam at `bam_path`.
+    pub fn builder(bam_path: impl AsRef<Path>) -> ParallelLocusProcessorPileupBuilder<W> {
+        ParallelLocusProcessorPileupBuilder {
+            bam_path: bam_path.as_ref().to_path_buf(),
+            worker: None,
+            n_threads: None,
+            pileup_options: DEFAULT_PILEUP_OPTIONS,
+            max_alignments_per_locus: None,
+            coordinate_system: CoordinateSystem::default(),
+            batch_timeout: None,
+            thread_budget: None,
+            input_check: InputCheck::default(),
+        }
+    }
 
-                    Ok::<_, Error>(res)
-                })
-                .collect::<Result<Vec<_>, Error>>()?;
+    /// Unlike [`Self::builder`], does not check the bam until it is processed.
+    pub fn new(bam_locus_worker: W, n_threads: usize, bam_path: PathBuf) -> Self {
+        Self {
+            bam_locus_worker,
+            n_threads,
+            bam_path,
+            opener: Box::new(HtslibOpener),
+            retry_policy: RetryPolicy::default(),
+            pileup_options: DEFAULT_PILEUP_OPTIONS,
+            max_alignments_per_locus: None,
+            coordinate_system: CoordinateSystem::default(),
+            batch_timeout: None,
+            thread_budget: None,
+            dict_check: None,
+            input_check: InputCheck::default(),
+            #[cfg(feature = "bio")]
+            reference: None,
+            #[cfg(feature = "mmap")]
+            mmap_reference: None,
+        }
+    }
 
-            event!(Level::DEBUG, "Flatten Batched Results...");
+    pub fn worker(&self) -> &W {
+        &self.bam_locus_worker
+    }
 
-            let r2 = r.into_iter().flatten().collect::<Vec<_>>();
+    pub fn bam_path(&self) -> &Path {
+        &self.bam_path
+    }
 
-            event!(Level::DEBUG, "Done.");
+    /// The processor with `worker` instead of its worker, and its options, e.g.
+    /// to wrap the worker in an adaptor of [`workers`](crate::bam::workers).
+    pub fn with_worker<V: for<'a> BamLocusWorker<'a>>(
+        self,
+        worker: V,
+    ) -> ParallelLocusProcessorPileup<V> {
+        self.map_worker(|_| worker)
+    }
 
-            Ok::<_, Error>(r2)
-        })?;
+    /// The processor with its worker wrapped by `wrap`.
+    pub(crate) fn map_worker<V: for<'a> BamLocusWorker<'a>>(
+        self,
+        wrap: impl FnOnce(W) -> V,
+    ) -> ParallelLocusProcessorPileup<V> {
+        ParallelLocusProcessorPileup {
+            bam_locus_worker: wrap(self.bam_locus_worker),
+            n_threads: self.n_threads,
+            bam_path: self.bam_path,
+            opener: self.opener,
+            retry_policy: self.retry_policy,
+            pileup_options: self.pileup_options,
+            max_alignments_per_locus: self.max_alignments_per_locus,
+            coordinate_system: self.coordinate_system,
+            batch_timeout: self.batch_timeout,
+            thread_budget: self.thread_budget,
+            dict_check: self.dict_check,
+            input_check: self.input_check,
+            #[cfg(feature = "bio")]
+            reference: self.reference,
+            #[cfg(feature = "mmap")]
+            mmap_reference: self.mmap_reference,
+        }
+    }
 
-        Ok(batch_res)
+    /// Open the bam with `opener` instead of [`HtslibOpener`].
+    pub fn with_opener(mut self, opener: impl BamOpener + 'static) -> Self {
+        self.opener = Box::new(opener);
+        self
     }
-}
 
-// pub trait RecordModifierInput {
+    /// Retries of opening and fetching the bam, 3 attempts by default.
+    pub fn with_retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.retry_policy = retry_policy;
+        self
+    }
 
-// }
+    /// Give the worker at most `max` alignments of each pileup column, sampled
+    /// by [`PileupExt::sample_alignments`], through
+    /// [`BamLocusWorker::work_for_locus_sampled`], to cap its cost at
+    /// ultra-deep loci. Off by default.
+    pub fn with_max_alignments_per_locus(mut self, max: usize) -> Self {
+        self.max_alignments_per_locus = Some(max);
+        self
+    }
 
+    /// Watch for batches running for longer than `timeout`, logging a WARN with
+    /// their region each time they run for another `timeout`, and aborting them
+    /// under [`BatchTimeoutPolicy::Abort`]. Off by default.
+    pub fn with_batch_timeout(mut self, timeout: Duration, policy: BatchTimeoutPolicy) -> Self {
+        self.batch_timeout = Some((timeout, policy));
+        self
+    }
 
+    /// Reserve the threads of the pools made by [`Self::process_with_batch`]
+    /// and [`Self::process_and_fold`] of `budget`, up to its total, waiting for
+    /// them or failing as set by its
+    /// [`ExhaustedPolicy`](crate::utils::thread_budget::ExhaustedPolicy).
+    /// Pools given to the `_on` methods are not counted.
+    pub fn with_thread_budget(mut self, budget: &ThreadBudget) -> Self {
+        self.thread_budget = Some(budget.clone());
+        self
+    }
 
-pub trait RecordModifier: Send + Sync {
-    // type Input: RecordModifierInput;
-    // type Output: Send + Sync;
-    type Error: Into<Error>;
+    /// Before processing, check that the inputs are within the contigs of the
+    /// bam header, and that the header matches the reference of
+    /// [`Self::with_reference`], if set. Under [`DictCheck::Strict`], a mismatch
+    /// fails the run without processing any batch. Off by default.
+    pub fn with_dict_check(mut self, check: DictCheck) -> Self {
+        self.dict_check = Some(check);
+        self
+    }
 
-    /// modify record and return `Option<()>`,   
-    /// `None` means this record should not be written to the output bamfile.
-    fn modify_record(&self, record: &mut bam::Record) -> Result<Option<()>, Self::Error>;
-}
+    /// What the [`quick_check`](crate::bam::validate::quick_check) of the bam,
+    /// run with the plan before processing, does with its findings. Under the
+    /// default [`InputCheck::Strict`], a bam e.g. missing its EOF block fails
+    /// the run without processing any batch.
+    pub fn with_input_check(mut self, check: InputCheck) -> Self {
+        self.input_check = check;
+        self
+    }
 
-/// Read a bam file, modify reads and write bam.
-///
-/// Use Producer Consumer Method.
-pub struct ParallelBamProcessor<R: RecordModifier> {
-    record_modifier: R,
-    // bam_path: PathBuf,
-    // n_threads: usize,
-}
+    /// What [`Self::process_with_batch`] of `inputs` with `batch_window_size`
+    /// would do, checked without reading any record, as by
+    /// [`ParallelLocusProcessorPileupBuilder::plan`].
+    pub fn plan<'a>(
+        &self,
+        inputs: &[<W as BamLocusWorker<'a>>::Input],
+        batch_window_size: usize,
+    ) -> RunPlan {
+        let n_batches = batch_indices_by_coordinate(inputs, batch_window_size).len();
+        locus_plan(
+            &self.bam_path,
+            self.opener.as_ref(),
+            &self.retry_policy,
+            self.input_check,
+            self.n_threads,
+            self.thread_budget.as_ref(),
+            inputs,
+            locus_batch_plan(inputs.len(), n_batches, batch_window_size),
+        )
+    }
 
-impl<R: RecordModifier> ParallelBamProcessor<R> {
-    fn process_bam(
+    /// The checks of [`Self::with_dict_check`], if set.
+    fn check_dicts<'a>(
         &self,
-        input_bam_path: impl AsRef<Path>,
-        read_thread: usize,
-        worker_thread: usize,
-        write_thread: usize,
-        out_bam_path: impl AsRef<Path>,
-        batch_size: usize,
-        channel_capacity: usize,
+        batched_regions: &[Vec<<W as BamLocusWorker<'a>>::Input>],
     ) -> Result<(), Error> {
-        let input_bam_path = input_bam_path.as_ref();
-        let out_bam_path = out_bam_path.as_ref();
+        let Some(check) = self.dict_check else {
+            return Ok(());
+        };
+        let reader = open_with_retry(self.opener.as_ref(), &self.retry_policy, &self.bam_path)?;
+        let bam_dict = SeqDict::from_header(reader.header())?;
 
-        // check bam path exists
-        if !input_bam_path.exists() {
-            Err(std::io::Error::new(
-                std::io::ErrorKind::NotFound,
-                input_bam_path.to_string_lossy(),
-            ))?
+        #[cfg(feature = "bio")]
+        if let Some(reference) = &self.reference {
+            let what = format!("The header of {}", self.bam_path.display());
+            reference.seq_dict().check(&bam_dict, &what, check)?;
+        }
+        #[cfg(feature = "mmap")]
+        if let Some(reference) = &self.mmap_reference {
+            let what = format!("The header of {}", self.bam_path.display());
+            reference.seq_dict().check(&bam_dict, &what, check)?;
         }
 
-        // prepare channels
-        let (tx_read, rx_read) = bounded::<BatchedData<DataWithIndex<Record>>>(channel_capacity);
-        let (tx_worker, rx_worker) =
-            bounded::<BatchedData<DataWithIndex<Record>>>(channel_capacity);
-        // let (tx_write, rx_write) = bounded::<BatchedData<DataWithIndex<Record>>>(channel_capacity);
-        let (tx_buf, rx_buf) = bounded::<BatchedData<DataWithIndex<Record>>>(channel_capacity);
-
-        let batch_init =
-            || BatchedData::new(|| DataWithIndex::new(Record::default(), 0), batch_size);
-        for _ in 0..channel_capacity {
-            tx_buf.send(batch_init())?;
+        let inputs = batched_regions.iter().flatten();
+        if let Err(err) = bam_dict.validate_inputs(inputs, self.coordinate_system) {
+            if check == DictCheck::Strict {
+                return Err(err.into());
+            }
+            event!(Level::WARN, "{:#}", err);
         }
 
-        // read header first
-        let reader = IndexedReader::from_path(&input_bam_path)?;
-        let header_view_bytes = Arc::new(reader.header().as_bytes().to_vec());
+        Ok(())
+    }
 
-        let bam_path_clone = input_bam_path.to_path_buf();
-        let rx_buf_clone = rx_buf.clone();
-        // reader thread
-        thread::scope(|s| {
-            let reader_handle = s.spawn(move || {
-                let mut reader = IndexedReader::from_path(bam_path_clone)?;
+    /// Pass reference bases to [`BamLocusWorker::work_for_locus_with_ref`],
+    /// read from an indexed FASTA.
+    #[cfg(feature = "bio")]
+    pub fn with_reference(mut self, fasta_path: impl AsRef<Path>) -> Result<Self, Error> {
+        self.reference = Some(RefGenome::from_path(fasta_path)?);
+        Ok(self)
+    }
 
-                if read_thread > 1 {
-                    reader.set_threads(read_thread)?; // Use shared pool for internal I/O [1]
-                }
+    /// Pass reference bases to [`BamLocusWorker::work_for_locus_with_ref`],
+    /// read from a memory-mapped FASTA, which the threads of the processor, and
+    /// other processors, share without locks. Takes the place of
+    /// [`Self::with_reference`] if both are set.
+    #[cfg(feature = "mmap")]
+    pub fn with_mmap_reference(mut self, fasta: std::sync::Arc<MmapFasta>) -> Self {
+        self.mmap_reference = Some(fasta);
+        self
+    }
 
-                reader.fetch(".")?; // Read all records from the file
+    /// Path of the reference bases are read from, if set.
+    #[cfg(feature = "serde")]
+    pub(crate) fn reference_path(&self) -> Option<&Path> {
+        #[cfg(feature = "mmap")]
+        if let Some(reference) = self.mmap_reference.as_ref() {
+            return Some(reference.path());
+        }
+        #[cfg(feature = "bio")]
+        if let Some(reference) = self.reference.as_ref() {
+            return Some(reference.path());
+        }
 
-                let mut i = 0;
+        None
+    }
 
-                'batched_process_loop: loop {
-                    let mut record_batch = match rx_buf_clone.recv() {
-                        Ok(v) => v,
-                        Err(RecvError) => {
-                            break;
-                        }
-                        // Err(TryRecvError::Empty) => {
-                        //     sleep(Duration::from_millis(10));
-                        //     continue;
-                        // }
-                        // Err(TryRecvError::Disconnected) => {
-                        //     break;
-                        // }
-                    };
+    /// Reference sequence of a batch span (1-based inclusive), uppercase, if a
+    /// reference is set.
+    pub(crate) fn batch_reference(
+        &self,
+        contig: &Chrom<'_>,
+        start: i64,
+        end: i64,
+    ) -> Result<Option<Vec<u8>>, Error> {
+        let region = GenomeRegion {
+            contig: contig.clone(),
+            start,
+            end,
+        };
+        #[cfg(feature = "mmap")]
+        if let Some(reference) = self.mmap_reference.as_ref() {
+            let mut seq = reference.seq(&region)?.into_owned();
+            seq.make_ascii_uppercase();
+            return Ok(Some(seq));
+        }
+        #[cfg(feature = "bio")]
+        if let Some(reference) = self.reference.as_ref() {
+            return Ok(Some(reference.fetch_seq(&region)?));
+        }
 
-                    while let Some(record_with_idx) = record_batch.next_mut() {
-                        let record = record_with_idx.data_mut();
+        let _ = region;
+        Ok(None)
+    }
 
-                        if let Some(res) = reader.read(record) {
-                            match res {
-                                Ok(_) => {
-                                    record.remove_header();
-                                    record_with_idx.idx = i;
-                                    i += 1;
-                                }
-                                Err(e) => {
-                                    event!(Level::WARN, "Error reading record: {:?}", e);
-                                    // Decide how to handle: propagate error, skip read, etc.
-                                    // For this example, we'll just continue.
-                                }
+    /// Run the worker on `inputs`, sorted by coordinate, in batches spanning up to
+    /// `batch_window_size` bp, on a new pool of `n_threads` threads.
+    ///
+    /// See [`Self::process_with_batch_on`].
+    pub fn process_with_batch<'a>(
+        &self,
+        inputs: Vec<<W as BamLocusWorker<'a>>::Input>,
+        batch_window_size: usize,
+    ) -> Result<Vec<<W as BamLocusWorker<'a>>::Output>, crate::Error> {
+        if inputs.is_empty() {
+            return Ok(vec![]);
+        }
+        let (tp, _guard) = budgeted_pool(self.n_threads, self.thread_budget.as_ref())?;
+        self.process_with_batch_on(inputs, batch_window_size, Some(&tp))
+    }
+
+    /// [`Self::process_with_batch`] with a window suggested by
+    /// [`suggest_window`] for about `target_per_batch` inputs per batch, up to
+    /// [`DEFAULT_MAX_WINDOW`] bp.
+    pub fn process_with_auto_batch<'a>(
+        &self,
+        inputs: Vec<<W as BamLocusWorker<'a>>::Input>,
+        target_per_batch: usize,
+    ) -> Result<Vec<<W as BamLocusWorker<'a>>::Output>, crate::Error> {
+        let window = suggest_window(&inputs, target_per_batch, DEFAULT_MAX_WINDOW);
+        event!(
+            Level::DEBUG,
+            "batch_window_size={} {:?}",
+            window,
+            describe_batching(&inputs, window)
+        );
+
+        self.process_with_batch(inputs, window)
+    }
+
+    /// [`Self::process_with_batch`] on `pool`, or on the current rayon pool (the
+    /// global one, unless called from within another pool) if `None`.
+    ///
+    /// Outputs are in input order whatever the scheduling; inputs without
+    /// coverage have no output. If batches fail, the error of the first one in
+    /// input order is returned, a [`WorkerFailed`](crate::Error::WorkerFailed)
+    /// at the locus if the worker failed.
+    pub fn process_with_batch_on<'a>(
+        &self,
+        inputs: Vec<<W as BamLocusWorker<'a>>::Input>,
+        batch_window_size: usize,
+        pool: Option<&ThreadPool>,
+    ) -> Result<Vec<<W as BamLocusWorker<'a>>::Output>, crate::Error> {
+        Ok(collect_in_order(self.process_batches_on(
+            inputs,
+            batch_window_size,
+            pool,
+        ))?)
+    }
+
+    /// [`Self::process_with_batch_on`], keeping the outputs of the batches which
+    /// succeeded. The errors of the others are returned along, in input order,
+    /// e.g. [`BatchTimedOut`](crate::bam::watchdog::BatchTimedOut)s.
+    pub fn process_with_batch_partial_on<'a>(
+        &self,
+        inputs: Vec<<W as BamLocusWorker<'a>>::Input>,
+        batch_window_size: usize,
+        pool: Option<&ThreadPool>,
+    ) -> (Vec<<W as BamLocusWorker<'a>>::Output>, Vec<Error>) {
+        let (mut outputs, mut errors) = (vec![], vec![]);
+        for batch in self.process_batches_on(inputs, batch_window_size, pool) {
+            match batch {
+                Ok(batch) => outputs.extend(batch),
+                Err(err) => errors.push(err),
+            }
+        }
+
+        (outputs, errors)
+    }
+
+    /// [`Self::process_with_batch`], saving the outputs of the batches done so
+    /// that a run which fails can be resumed.
+    ///
+    /// Outputs are encoded by `encode` into a spool next to `checkpoint_path`,
+    /// and the batches in it are written to `checkpoint_path` every
+    /// `every_n_batches` batches and when the run fails (see
+    /// [`checkpoint`](crate::bam::checkpoint)). Run again with the same inputs
+    /// and window, the batches saved are not processed again: their outputs are
+    /// read back by `decode`, so the result is that of a run which did not fail.
+    /// A checkpoint of other inputs is an error. Both files are removed once the
+    /// run succeeds.
+    pub fn process_with_batch_checkpointed<'a, E, D>(
+        &self,
+        inputs: Vec<<W as BamLocusWorker<'a>>::Input>,
+        batch_window_size: usize,
+        checkpoint_path: impl AsRef<Path>,
+        every_n_batches: usize,
+        encode: E,
+        decode: D,
+    ) -> Result<Vec<<W as BamLocusWorker<'a>>::Output>, crate::Error>
+    where
+        E: Fn(&<W as BamLocusWorker<'a>>::Output, &mut Vec<u8>) -> Result<(), Error> + Sync,
+        D: Fn(&[u8]) -> Result<<W as BamLocusWorker<'a>>::Output, Error>,
+    {
+        if inputs.is_empty() {
+            return Ok(vec![]);
+        }
+        let mut batches = batch_input_by_coordinate(inputs, batch_window_size);
+        let checkpoint = LocusCheckpoint::open(
+            checkpoint_path.as_ref(),
+            checkpoint::fingerprint(&batches),
+            batches.len(),
+            every_n_batches,
+        )?;
+        let mut saved = checkpoint.load(decode)?;
+        for batch_idx in saved.keys() {
+            batches[*batch_idx].clear();
+        }
+        event!(
+            Level::INFO,
+            "Resuming {} of {} batches from {}",
+            batches.len() - saved.len(),
+            batches.len(),
+            checkpoint_path.as_ref().display()
+        );
+
+        let (tp, _guard) = budgeted_pool(self.n_threads, self.thread_budget.as_ref())?;
+        let batch_res = self.fold_batched_on(
+            &self.bam_locus_worker,
+            batches,
+            batch_window_size,
+            Some(&tp),
+            Vec::with_capacity,
+            |mut res, r| {
+                res.push(r);
+                res
+            },
+            |batch_idx, outputs: Vec<_>| {
+                checkpoint.save(batch_idx, &outputs, &encode)?;
+                Ok(outputs)
+            },
+        );
+        let res = collect_in_order(
+            batch_res
+                .into_iter()
+                .enumerate()
+                .map(|(batch_idx, res)| saved.remove(&batch_idx).map_or(res, Ok))
+                .collect(),
+        );
+
+        let finished = checkpoint.finish(res.is_ok());
+        match res {
+            Ok(res) => {
+                finished?;
+                Ok(res)
+            }
+            Err(err) => {
+                if let Err(finish_err) = finished {
+                    event!(
+                        Level::WARN,
+                        "Failed to write the checkpoint: {:#}",
+                        finish_err
+                    );
+                }
+                Err(err.into())
+            }
+        }
+    }
+
+    /// [`Self::process_with_batch`], giving the outputs of each batch to `sink`
+    /// once it is done rather than collecting them, e.g. to write them to a file
+    /// per contig by [`PerContigTsvSink`](crate::bam::result_sink::PerContigTsvSink).
+    ///
+    /// The batches are given from the threads which ran them, in any order, see
+    /// [`LocusResultSink`]. If batches fail, the error of the first one in input
+    /// order is returned, unless the sink fails to finish.
+    pub fn process_with_batch_streaming<'a, S>(
+        &self,
+        inputs: Vec<<W as BamLocusWorker<'a>>::Input>,
+        batch_window_size: usize,
+        mut sink: S,
+    ) -> Result<S::Summary, crate::Error>
+    where
+        S: LocusResultSink<<W as BamLocusWorker<'a>>::Output>,
+    {
+        let batches = batch_input_by_coordinate(inputs, batch_window_size);
+        let batch_contigs = batches
+            .iter()
+            .map(|batch| batch[0].genome_coordinate().contig.clone())
+            .collect::<Vec<_>>();
+        sink.start(&batch_contigs)?;
+
+        let (tp, _guard) = budgeted_pool(self.n_threads, self.thread_budget.as_ref())?;
+        let batch_res = self.fold_batched_on(
+            &self.bam_locus_worker,
+            batches,
+            batch_window_size,
+            Some(&tp),
+            Vec::with_capacity,
+            |mut res, r| {
+                res.push(r);
+                res
+            },
+            |batch_idx, outputs| {
+                sink.write_batch(batch_idx, outputs)?;
+                Ok(vec![])
+            },
+        );
+        let res = collect_in_order(batch_res);
+
+        // a sink failing to write fails the batches after it too.
+        let summary = sink.finish(res.is_ok())?;
+        res?;
+        Ok(summary)
+    }
+
+    /// Aggregate of the outputs of `inputs`, without collecting them, on a new
+    /// pool of `n_threads` threads. Batches are made as by
+    /// [`Self::process_with_batch`].
+    ///
+    /// The outputs of each batch are folded by `fold` into a new `init()` in
+    /// input order, then the accumulators of the batches are merged by `merge`
+    /// in input order too, so the result does not depend on scheduling. It does
+    /// not depend on the batching, i.e. `batch_window_size`, either if `merge` is
+    /// associative, `init()` is its identity and `fold` agrees with it, e.g. sums
+    /// of integers; a sum of floats may differ in its last bits.
+    ///
+    /// If batches fail, the error of the first one in input order is returned.
+    pub fn process_and_fold<'a, A>(
+        &self,
+        inputs: Vec<<W as BamLocusWorker<'a>>::Input>,
+        batch_window_size: usize,
+        init: impl Fn() -> A + Sync,
+        fold: impl Fn(A, <W as BamLocusWorker<'a>>::Output) -> A + Sync,
+        merge: impl Fn(A, A) -> A + Sync,
+    ) -> Result<A, crate::Error>
+    where
+        A: Send,
+    {
+        if inputs.is_empty() {
+            return Ok(init());
+        }
+        let (tp, _guard) = budgeted_pool(self.n_threads, self.thread_budget.as_ref())?;
+        let batches = self.fold_batches_on(inputs, batch_window_size, Some(&tp), |_| init(), fold);
+
+        batches
+            .into_iter()
+            .try_fold(init(), |acc, batch| Ok(merge(acc, batch?)))
+    }
+
+    /// [`Self::process_with_batch`], appending the outputs to `sink` instead of
+    /// collecting them in a `Vec`, e.g. to a sink of a `Vec` per field of the
+    /// output made by [`columnar_sink!`](crate::columnar_sink), without the
+    /// padding of each output.
+    ///
+    /// Each batch fills a sink of its own, reserved for its inputs, merged into
+    /// `sink` by [`OrderedMerge`] as soon as the batches before it are, so that
+    /// the outputs are not held twice. If batches fail, the error of the first
+    /// one in input order is returned, and `sink` has the outputs of the
+    /// batches before it.
+    pub fn process_into<'a, S>(
+        &self,
+        inputs: Vec<<W as BamLocusWorker<'a>>::Input>,
+        batch_window_size: usize,
+        sink: &mut S,
+    ) -> Result<(), crate::Error>
+    where
+        S: ColumnarSink<<W as BamLocusWorker<'a>>::Output> + Default + Send,
+    {
+        if inputs.is_empty() {
+            return Ok(());
+        }
+        let (tp, _guard) = budgeted_pool(self.n_threads, self.thread_budget.as_ref())?;
+        let merge = Mutex::new(OrderedMerge::new(sink));
+        let batches = batch_input_by_coordinate(inputs, batch_window_size);
+        let batch_res = self.fold_batched_on(
+            &self.bam_locus_worker,
+            batches,
+            batch_window_size,
+            Some(&tp),
+            |n| {
+                let mut batch = S::default();
+                batch.reserve(n);
+                batch
+            },
+            |mut batch, output| {
+                batch.push(output);
+                batch
+            },
+            |batch_idx, batch| {
+                merge.lock().unwrap().add(batch_idx, batch);
+                Ok(S::default())
+            },
+        );
+
+        for res in batch_res {
+            res?;
+        }
+        merge.into_inner().unwrap().finish();
+        Ok(())
+    }
+
+    /// Number of outputs per key of `bucket_fn`, e.g. a depth bin, by
+    /// [`Self::process_and_fold`].
+    pub fn process_and_histogram<'a, K>(
+        &self,
+        inputs: Vec<<W as BamLocusWorker<'a>>::Input>,
+        batch_window_size: usize,
+        bucket_fn: impl Fn(&<W as BamLocusWorker<'a>>::Output) -> K + Sync,
+    ) -> Result<HashMap<K, u64>, crate::Error>
+    where
+        K: Eq + Hash + Send,
+    {
+        self.process_and_fold(
+            inputs,
+            batch_window_size,
+            HashMap::new,
+            |mut hist, output| {
+                *hist.entry(bucket_fn(&output)).or_insert(0) += 1;
+                hist
+            },
+            |mut hist, other| {
+                for (key, n) in other {
+                    *hist.entry(key).or_insert(0) += n;
+                }
+                hist
+            },
+        )
+    }
+
+    /// Outputs of each batch, in input order.
+    fn process_batches_on<'a>(
+        &self,
+        inputs: Vec<<W as BamLocusWorker<'a>>::Input>,
+        batch_window_size: usize,
+        pool: Option<&ThreadPool>,
+    ) -> Vec<Result<Vec<<W as BamLocusWorker<'a>>::Output>, Error>> {
+        self.fold_batches_on(
+            inputs,
+            batch_window_size,
+            pool,
+            Vec::with_capacity,
+            |mut res, r| {
+                res.push(r);
+                res
+            },
+        )
+    }
+
+    /// Outputs of each batch folded by `fold`, in input order, from `init` of
+    /// the number of inputs of the batch.
+    fn fold_batches_on<'a, A, I, F>(
+        &self,
+        inputs: Vec<<W as BamLocusWorker<'a>>::Input>,
+        batch_window_size: usize,
+        pool: Option<&ThreadPool>,
+        init: I,
+        fold: F,
+    ) -> Vec<Result<A, Error>>
+    where
+        A: Send,
+        I: Fn(usize) -> A + Sync,
+        F: Fn(A, <W as BamLocusWorker<'a>>::Output) -> A + Sync,
+    {
+        let batched_regions = batch_input_by_coordinate(inputs, batch_window_size);
+        self.fold_batched_on(
+            &self.bam_locus_worker,
+            batched_regions,
+            batch_window_size,
+            pool,
+            init,
+            fold,
+            |_, acc| Ok(acc),
+        )
+    }
+
+    /// Run `worker` at the column `plp`, on a sample of its alignments if
+    /// [`Self::with_max_alignments_per_locus`] is set.
+    pub(crate) fn work_at_column<'a, V: BamLocusWorker<'a>>(
+        &self,
+        worker: &V,
+        plp: Pileup,
+        input: V::Input,
+        ref_base: Option<Base>,
+    ) -> Result<V::Output, V::Error> {
+        match self.max_alignments_per_locus {
+            Some(max) => {
+                let sample = plp.sample_alignments(max);
+                worker.work_for_locus_sampled(plp, input, ref_base, sample)
+            }
+            None => worker.work_for_locus_with_ref(plp, input, ref_base),
+        }
+    }
+
+    /// [`Self::fold_batches_on`] of inputs already batched with
+    /// `batch_window_size`, run by `worker`, passing the index
+    /// and the accumulator of each batch to `on_batch` once it is done, which
+    /// gives back the accumulator returned. Empty batches give `init(0)` without
+    /// calling it.
+    #[allow(clippy::too_many_arguments)]
+    fn fold_batched_on<'a, V, A, I, F, B>(
+        &self,
+        worker: &V,
+        batched_regions: Vec<Vec<<W as BamLocusWorker<'a>>::Input>>,
+        batch_window_size: usize,
+        pool: Option<&ThreadPool>,
+        init: I,
+        fold: F,
+        on_batch: B,
+    ) -> Vec<Result<A, Error>>
+    where
+        V: BamLocusWorker<
+                'a,
+                Input = <W as BamLocusWorker<'a>>::Input,
+                Output = <W as BamLocusWorker<'a>>::Output,
+            >,
+        A: Send,
+        I: Fn(usize) -> A + Sync,
+        F: Fn(A, <W as BamLocusWorker<'a>>::Output) -> A + Sync,
+        B: Fn(usize, A) -> Result<A, Error> + Sync,
+    {
+        if batched_regions.is_empty() {
+            return vec![];
+        }
+        if let Err(err) = self.check_dicts(&batched_regions) {
+            return vec![Err(err)];
+        }
+        let n_inputs = batched_regions.iter().map(Vec::len).sum();
+        let plan = locus_plan(
+            &self.bam_path,
+            self.opener.as_ref(),
+            &self.retry_policy,
+            self.input_check,
+            pool_threads(pool),
+            self.thread_budget.as_ref(),
+            batched_regions.iter().flatten(),
+            locus_batch_plan(n_inputs, batched_regions.len(), batch_window_size),
+        );
+        event!(Level::DEBUG, "{}", plan);
+        if let Err(err) = plan.check_input() {
+            return vec![Err(err.into())];
+        }
+        event!(
+            Level::DEBUG,
+            "batched_regions len={}",
+            batched_regions.len()
+        );
+
+        // tids are looked up once per contig, by the first batch on it, rather
+        // than from the contig name on every fetch.
+        let (batch_contigs, contigs) = batch_contig_indices(&batched_regions);
+        let contig_tids = contigs
+            .iter()
+            .map(|_| OnceLock::new())
+            .collect::<Vec<OnceLock<Option<u32>>>>();
+
+        let process_span = span!(
+            Level::INFO,
+            "process_with_batch",
+            bam = %self.bam_path.display(),
+            n_threads = pool_threads(pool),
+            n_batches = batched_regions.len(),
+        );
+        let _process_span = process_span.enter();
+        let dispatch = current_dispatch();
+        let busy = BusyTime::default();
+        let watchdog = self
+            .batch_timeout
+            .map(|(timeout, policy)| BatchWatchdog::new(timeout, policy));
+
+        let run_batches = || {
+            event!(Level::DEBUG, "Parallel Processing...");
+
+            batched_regions
+                .into_par_iter()
+                .zip(batch_contigs)
+                .enumerate()
+                .map(|(batch_idx, (batch, contig_idx))| {
+                    if batch.is_empty() {
+                        return Ok(init(0));
+                    }
+
+                    let _batch_span = enter_on_thread(&dispatch, || {
+                        batch_span(&process_span, batch[0].genome_coordinate(), batch.len())
+                    });
+                    let _busy = busy.start();
+
+                    let first_elem = batch.first().unwrap();
+                    let last_elem = batch.last().unwrap();
+
+                    let batch_contig = &contigs[contig_idx];
+                    // batch is not empty, by the if condition of function start point.
+                    let batch_pileup_start = self
+                        .coordinate_system
+                        .to_0based(first_elem.genome_coordinate().pos);
+                    let batch_pileup_end = self
+                        .coordinate_system
+                        .to_0based(last_elem.genome_coordinate().pos)
+                        + 1;
+                    let watched = watchdog.as_ref().map(|watchdog| {
+                        let region = GenomeRegion {
+                            contig: batch_contig.clone().into_owned(),
+                            start: batch_pileup_start + 1,
+                            end: batch_pileup_end,
+                        };
+                        watchdog.watch(batch_idx, region)
+                    });
+
+                    let mut ir =
+                        open_with_retry(self.opener.as_ref(), &self.retry_policy, &self.bam_path)?;
+
+                    let tid = *contig_tids[contig_idx]
+                        .get_or_init(|| contig_tid(ir.header(), batch_contig));
+                    let (batch_pileup_start, batch_pileup_end) = match tid {
+                        Some(tid) => clamp_batch(
+                            ir.header(),
+                            tid,
+                            batch_contig,
+                            &batch,
+                            self.coordinate_system,
+                            (batch_pileup_start, batch_pileup_end),
+                        )?,
+                        // failed by the fetch, with the contigs of the header.
+                        None => (batch_pileup_start, batch_pileup_end),
+                    };
+                    // fetches the reads of the batch from the 0-based `start` on.
+                    let fetch_from = |ir: &mut bam::IndexedReader, start: i64| {
+                        self.retry_policy
+                            .run(
+                                format_args!(
+                                    "Fetching {}:{}-{} from {}",
+                                    batch_contig,
+                                    start + 1,
+                                    batch_pileup_end,
+                                    self.bam_path.display()
+                                ),
+                                || fetch_tid(ir, batch_contig, tid, start + 1, batch_pileup_end),
+                            )
+                            .with_context(|| format!("Reading {}", self.bam_path.display()))
+                    };
+                    fetch_from(&mut ir, batch_pileup_start)?;
+                    let batch_ref_seq = self.batch_reference(
+                        batch_contig,
+                        batch_pileup_start + 1,
+                        batch_pileup_end,
+                    )?;
+                    let mut pileups = ir.pileup_with_option(self.pileup_options).peekable();
+
+                    // Create peekable iterators for both the pileups and the batch of inputs.
+                    let batch_len = batch.len();
+                    let mut acc = init(batch_len);
+                    // inputs without a pileup column, i.e. without coverage.
+                    let mut n_unmatched = 0;
+
+                    let mut batch_peekable = batch.into_iter().peekable();
+
+                    // This is the efficient "merge/zip" sweep-line algorithm
+                    while let Some(input) = batch_peekable.peek() {
+                        if let Some(timed_out) = watched.as_ref().and_then(|w| w.timed_out()) {
+                            return Err(timed_out.into());
+                        }
+
+                        let pileup_pos = match pileups.peek() {
+                            Some(Ok(pileup_col)) => pileup_col.pos() as i64,
+                            Some(Err(_)) => {
+                                return Err(pileups.next().unwrap().unwrap_err().into());
+                            }
+                            None => {
+                                // No pileup left: the remaining targets have no coverage.
+                                n_unmatched += batch_peekable.by_ref().count();
+                                break;
+                            }
+                        };
+                        // Assuming you've updated the trait to use GenomeCoordinate
+                        let input_pos = input.genome_coordinate().pos;
+                        let target_pos = self.coordinate_system.to_0based(input_pos);
+
+                        match pileup_pos.cmp(&target_pos) {
+                            Ordering::Less => {
+                                // Case 1: Pileup is before our target site.
+                                // Discard the pileup and advance the pileup iterator.
+                                pileups.next();
+                            }
+                            Ordering::Greater => {
+                                // Case 2: We've passed our target site, but there was no pileup (zero coverage).
+                                // Discard the target and advance the site iterator.
+                                batch_peekable.next();
+                                n_unmatched += 1;
                             }
+                            Ordering::Equal => {
+                                // Case 3: Match found! Process it.
+                                // We must consume both items from the iterators to advance.
+                                if let (Some(Ok(plp)), Some(inp)) =
+                                    (pileups.next(), batch_peekable.next())
+                                {
+                                    let ref_base = batch_ref_seq
+                                        .as_ref()
+                                        .and_then(|seq| {
+                                            seq.get((target_pos - batch_pileup_start) as usize)
+                                        })
+                                        .and_then(|b| Base::try_from(*b).ok());
+
+                                    let r = catch_panic(|| {
+                                        self.work_at_column(worker, plp, inp, ref_base)
+                                            .map_err(Into::into)
+                                    })
+                                    .unwrap_or_else(|panicked| Err(panicked.into()))
+                                    .map_err(|err| crate::Error::WorkerFailed {
+                                        item: format!("locus {}:{}", batch_contig, input_pos),
+                                        source: err,
+                                    })?;
+                                    acc = fold(acc, r);
+                                }
 
-                            // if i % const { 10_usize.pow(6) } == 0 {
-                            //     // break 'batched_process_loop;
-                            //     // event!(
-                            //     //     Level::DEBUG,
-                            //     //     "Reading speed: {:.1}/s",
-                            //     //     i as f64 / timer.elapsed().as_secs_f64()
-                            //     // );
-                            // }
-                        } else {
-                            if !record_batch.is_empty() {
-                                tx_read.send(record_batch)?;
+                                // The column is consumed by the worker, so the next input at
+                                // the same position, e.g. another allele of a multi-allelic
+                                // site, gets it by fetching again from the position.
+                                let same_pos = batch_peekable.peek().is_some_and(|next| {
+                                    self.coordinate_system.to_0based(next.genome_coordinate().pos)
+                                        == target_pos
+                                });
+                                if same_pos {
+                                    drop(pileups);
+                                    fetch_from(&mut ir, target_pos)?;
+                                    pileups = ir.pileup_with_option(self.pileup_options).peekable();
+                                }
                             }
-                            drop(tx_read);
-                            break 'batched_process_loop;
                         }
                     }
 
-                    tx_read.send(record_batch)?;
-                }
+                    if n_unmatched > 0 {
+                        event!(
+                            Level::WARN,
+                            "{} of {} inputs of the batch at {}:{} have no pileup column and no output",
+                            n_unmatched,
+                            batch_len,
+                            batch_contig,
+                            batch_pileup_start + 1,
+                        );
+                    }
+
+                    on_batch(batch_idx, acc)
+                })
+                .collect::<Vec<_>>()
+        };
+
+        let batch_res = match watchdog.as_ref() {
+            Some(watchdog) => thread::scope(|s| {
+                let watchdog_thread = s.spawn(|| {
+                    let _span = enter_on_thread(
+                        &dispatch,
+                        || span!(parent: &process_span, Level::TRACE, "watchdog"),
+                    );
+                    watchdog.run();
+                });
+                let batch_res = install_on(pool, run_batches);
+                watchdog.stop();
+                watchdog_thread.thread().unpark();
+                batch_res
+            }),
+            None => install_on(pool, run_batches),
+        };
+
+        event!(
+            Level::DEBUG,
+            n_batches = batch_res.len(),
+            n_failed = batch_res.iter().filter(|r| r.is_err()).count(),
+            busy_ms = busy.millis(),
+            "process_with_batch finished"
+        );
+
+        batch_res
+    }
+}
+
+impl<W: for<'a> LocusWorkerInit<'a>> ParallelLocusProcessorPileup<W> {
+    /// [`Self::process_with_batch`] with a state per thread of the pool, made by
+    /// [`LocusWorkerInit::init_thread`] on each of them before any batch runs,
+    /// and given to every call of
+    /// [`LocusWorkerInit::work_for_locus_with_state`] on that thread. The run
+    /// fails before any batch if a state fails to be made.
+    ///
+    /// The reference bases and the sampling of
+    /// [`Self::with_max_alignments_per_locus`] are not given to the worker.
+    /// It must not run jobs on the pool itself, which could run another call
+    /// of the same thread meanwhile.
+    pub fn process_with_thread_state<'a>(
+        &self,
+        inputs: Vec<<W as BamLocusWorker<'a>>::Input>,
+        batch_window_size: usize,
+    ) -> Result<Vec<<W as BamLocusWorker<'a>>::Output>, crate::Error> {
+        if inputs.is_empty() {
+            return Ok(vec![]);
+        }
+        let (tp, _guard) = budgeted_pool(self.n_threads, self.thread_budget.as_ref())?;
+        let states = tp
+            .broadcast(|_| self.bam_locus_worker.init_thread())
+            .into_iter()
+            .collect::<Result<Vec<_>, _>>()
+            .context("Failed to initialize a thread of the worker")?;
+        event!(Level::DEBUG, "Initialized {} worker threads", states.len());
+        let worker = ThreadStateWorker {
+            worker: &self.bam_locus_worker,
+            states: states.into_iter().map(Mutex::new).collect(),
+        };
+
+        let batch_res = self.fold_batched_on(
+            &worker,
+            batch_input_by_coordinate(inputs, batch_window_size),
+            batch_window_size,
+            Some(&tp),
+            Vec::with_capacity,
+            |mut res, r| {
+                res.push(r);
+                res
+            },
+            |_, acc| Ok(acc),
+        );
+        Ok(collect_in_order(batch_res)?)
+    }
+}
+
+/// Input of a [`PreLifted`] worker: an input of the wrapped worker, at the
+/// locus its coordinate was lifted to.
+#[cfg(feature = "liftover")]
+pub struct LiftedInput<'a, I> {
+    /// Index in the inputs of
+    /// [`ParallelLocusProcessorPileup::process_lifted_with_batch`].
+    index: usize,
+    lifted: GenomeCoordinate<'a>,
+    input: I,
+}
+
+#[cfg(feature = "liftover")]
+impl<'a, I: Send + Sync> BamLocusWorkInput<'a> for LiftedInput<'a, I> {
+    fn genome_coordinate(&self) -> &GenomeCoordinate<'a> {
+        &self.lifted
+    }
+}
+
+/// A worker run at the loci its inputs are lifted to by a chain file, see
+/// [`ParallelLocusProcessorPileup::process_lifted_with_batch`].
+///
+/// The wrapped worker is given its inputs as they are, in the coordinates of
+/// the source assembly, with the pileup at the lifted locus.
+#[cfg(feature = "liftover")]
+pub struct PreLifted<W>(pub W);
+
+#[cfg(feature = "liftover")]
+impl<'a, W: BamLocusWorker<'a>> BamLocusWorker<'a> for PreLifted<W> {
+    type Input = LiftedInput<'a, W::Input>;
+    type Output = (usize, W::Output);
+    type Error = W::Error;
+
+    fn work_for_locus(&self, plp: Pileup, input: Self::Input) -> Result<Self::Output, Self::Error> {
+        let output = self.0.work_for_locus(plp, input.input)?;
+        Ok((input.index, output))
+    }
+
+    fn work_for_locus_with_ref(
+        &self,
+        plp: Pileup,
+        input: Self::Input,
+        ref_base: Option<Base>,
+    ) -> Result<Self::Output, Self::Error> {
+        let output = self.0.work_for_locus_with_ref(plp, input.input, ref_base)?;
+        Ok((input.index, output))
+    }
+
+    fn work_for_locus_sampled(
+        &self,
+        plp: Pileup,
+        input: Self::Input,
+        ref_base: Option<Base>,
+        sample: SampledPileup,
+    ) -> Result<Self::Output, Self::Error> {
+        let output = self
+            .0
+            .work_for_locus_sampled(plp, input.input, ref_base, sample)?;
+        Ok((input.index, output))
+    }
+}
 
-                event!(Level::DEBUG, "Reader thread ended.");
+/// Output of [`ParallelLocusProcessorPileup::process_lifted_with_batch`] for an
+/// input.
+#[cfg(feature = "liftover")]
+#[derive(Debug)]
+pub struct LiftedOutput<O> {
+    /// Where the coordinate of the input was lifted to, 1-based whatever the
+    /// [`CoordinateSystem`].
+    pub lift: LiftResult<GenomeCoordinate<'static>>,
+    /// Output of the worker at the lifted locus, `None` if the input did not
+    /// lift to one locus or the locus has no coverage.
+    pub output: Option<O>,
+}
 
-                Ok::<_, Error>(())
+#[cfg(feature = "liftover")]
+impl<W: for<'a> BamLocusWorker<'a>> ParallelLocusProcessorPileup<PreLifted<W>> {
+    /// [`Self::process_with_batch`] at the loci `inputs` are lifted to by
+    /// `liftover`, e.g. of hg19 inputs on an hg38 bam.
+    ///
+    /// Returns one output per input, in input order, with the result of its
+    /// lift. Inputs which do not lift to one locus are not processed.
+    pub fn process_lifted_with_batch<'a>(
+        &self,
+        inputs: Vec<<W as BamLocusWorker<'a>>::Input>,
+        liftover: &ChainLiftover,
+        batch_window_size: usize,
+    ) -> Result<Vec<LiftedOutput<<W as BamLocusWorker<'a>>::Output>>, crate::Error> {
+        let mut outputs = Vec::with_capacity(inputs.len());
+        let mut lifted_inputs = vec![];
+        for (index, input) in inputs.into_iter().enumerate() {
+            let coord = input.genome_coordinate();
+            let one_based = self.coordinate_system.to_0based(coord.pos) + 1;
+            let lift = liftover.lift_coordinate(&GenomeCoordinate {
+                contig: coord.contig.clone(),
+                pos: one_based,
             });
 
-            // worker threads
-            let mut worker_handles = Vec::with_capacity(worker_thread);
-            let n_processed = Arc::new(AtomicUsize::new(0));
-            // let work_timer = Instant::now();
-            for i in 0..worker_thread {
-                // let rx_reads_clone = rx_reads.clone();
-                // let tx_modified_reads_clone = tx_modified_reads.clone();
-                // let input_bam_path_clone = input_bam_path.clone();
-                let header_view = header_view_bytes.clone();
-                let n_processed = n_processed.clone();
-                let rx_read_clone = rx_read.clone();
-                let tx_worker_clone = tx_worker.clone();
-
-                worker_handles.push(s.spawn(move || {
-                    // event!(
-                    //     Level::INFO,
-                    //     "Process worker {:?} start. linux thread id={}",
-                    //     thread::current().id(),
-                    //     unsafe { libc::syscall(libc::SYS_gettid) },
-                    // );
-
-                    let header_view = Rc::new(HeaderView::from_bytes(&header_view));
-
-                    loop {
-                        let mut record_batch = match rx_read_clone.recv() {
-                            Ok(v) => v,
-                            Err(RecvError) => {
-                                event!(
-                                    Level::DEBUG,
-                                    "Checked end of channel from reader, thread {:?}",
-                                    thread::current().id()
-                                );
-                                break;
-                            }
-                            // Err(TryRecvError::Empty) => {
-                            //     sleep(Duration::from_millis(10));
-                            //     continue;
-                            // }
-                            // Err(TryRecvError::Disconnected) => {
-                            //     event!(
-                            //         Level::DEBUG,
-                            //         "Checked end of channel from reader, thread {:?}",
-                            //         thread::current().id()
-                            //     );
-                            //     break;
-                            // }
-                        };
+            if let LiftResult::Mapped(lifted) = &lift {
+                lifted_inputs.push(LiftedInput {
+                    index,
+                    lifted: GenomeCoordinate {
+                        contig: lifted.locus.contig.clone(),
+                        pos: lifted.locus.pos + coord.pos - one_based,
+                    },
+                    input,
+                });
+            }
+            outputs.push(LiftedOutput { lift, output: None });
+        }
+
+        // in the order of the destination, for batches as large as unlifted.
+        lifted_inputs.sort_by(|a, b| {
+            (&a.lifted.contig, a.lifted.pos).cmp(&(&b.lifted.contig, b.lifted.pos))
+        });
+        event!(
+            Level::DEBUG,
+            n_inputs = outputs.len(),
+            n_lifted = lifted_inputs.len(),
+            "lifted inputs"
+        );
+
+        for (index, output) in self.process_with_batch(lifted_inputs, batch_window_size)? {
+            outputs[index].output = Some(output);
+        }
+
+        Ok(outputs)
+    }
+}
+
+/// [`BamLocusWorker`] counterpart for [`MultiBamLocusProcessor`], called with the
+/// pileup columns of every BAM at the locus.
+pub trait MultiBamLocusWorker<'a>: Send + Sync {
+    type Input: BamLocusWorkInput<'a>;
+    type Output: Send + Sync;
+    type Error: Into<Error>;
+
+    /// `pileups` has one column per BAM, in the order given to the processor.
+    /// It is `None` where the BAM has no coverage at the locus or does not have
+    /// the contig at all.
+    fn work_for_locus(
+        &self,
+        pileups: &[Option<Pileup>],
+        input: Self::Input,
+    ) -> Result<Self::Output, Self::Error>;
+}
+
+/// Evaluate each locus across several BAMs at once, e.g. tumor/normal pairs.
+///
+/// Like [`ParallelLocusProcessorPileup`], inputs are batched by coordinate and
+/// every BAM is read once per batch. Unlike it, the worker is called for every
+/// input, so the output has one item per input locus, in input order.
+///
+/// Contig names are looked up in each BAM header separately, with or without the
+/// `chr` prefix (and `MT` for chrM), so BAMs with different naming can be mixed.
+pub struct MultiBamLocusProcessor<W: for<'a> MultiBamLocusWorker<'a>> {
+    worker: W,
+    n_threads: usize,
+    bams: BamSet,
+}
+
+impl<W: for<'a> MultiBamLocusWorker<'a>> MultiBamLocusProcessor<W> {
+    pub fn new(worker: W, n_threads: usize, bam_paths: Vec<PathBuf>) -> Self {
+        Self {
+            worker,
+            n_threads,
+            bams: BamSet::new(bam_paths),
+        }
+    }
+
+    /// Open the bams with `opener` instead of [`HtslibOpener`].
+    pub fn with_opener(mut self, opener: impl BamOpener + 'static) -> Self {
+        self.bams.opener = Box::new(opener);
+        self
+    }
+
+    /// Retries of opening and fetching the bams, 3 attempts by default.
+    pub fn with_retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.bams.retry_policy = retry_policy;
+        self
+    }
+
+    /// Options of the pileups of every bam, unlimited depth and overlapping
+    /// mates counted once by default, see
+    /// [`ParallelLocusProcessorPileupBuilder::pileup_options`].
+    pub fn with_pileup_options(mut self, pileup_options: PileupOption) -> Self {
+        self.bams.pileup_options = pileup_options;
+        self
+    }
+
+    /// How the positions of the inputs are read, 1-based by default.
+    pub fn with_coordinate_system(mut self, coordinate_system: CoordinateSystem) -> Self {
+        self.bams.coordinate_system = coordinate_system;
+        self
+    }
 
-                        for record_with_idx in record_batch.filled_mut() {
-                            let record = record_with_idx.data_mut();
+    pub fn bam_paths(&self) -> &[PathBuf] {
+        &self.bams.bam_paths
+    }
+
+    pub fn process_with_batch<'a>(
+        &self,
+        inputs: Vec<<W as MultiBamLocusWorker<'a>>::Input>,
+        batch_window_size: usize,
+    ) -> Result<Vec<<W as MultiBamLocusWorker<'a>>::Output>, crate::Error> {
+        if inputs.is_empty() {
+            // no pool for nothing, with the errors of the arguments all the same.
+            return self.process_with_batch_on(inputs, batch_window_size, None);
+        }
+        let tp = ThreadPoolBuilder::new()
+            .num_threads(self.n_threads)
+            .build()
+            .map_err(Error::from)?;
+        self.process_with_batch_on(inputs, batch_window_size, Some(&tp))
+    }
 
-                            record.set_header(Rc::clone(&header_view));
-                            // `iter()` blocks until a message is available or channel is disconnected [2]
+    /// [`Self::process_with_batch`] on `pool`, or on the current rayon pool if
+    /// `None`. Like [`ParallelLocusProcessorPileup::process_with_batch_on`],
+    /// outputs are in input order and the error is that of the first failed batch.
+    pub fn process_with_batch_on<'a>(
+        &self,
+        inputs: Vec<<W as MultiBamLocusWorker<'a>>::Input>,
+        batch_window_size: usize,
+        pool: Option<&ThreadPool>,
+    ) -> Result<Vec<<W as MultiBamLocusWorker<'a>>::Output>, crate::Error> {
+        if self.bams.bam_paths.is_empty() {
+            return Err(anyhow!("No BAM was given to MultiBamLocusProcessor").into());
+        }
 
-                            // how to remove the record from the batch? the problem is,
-                            // writer thread use the idx as order, so the writer will wait for this missing index forever
+        self.bams
+            .process_with_batch_on(inputs, batch_window_size, pool, |columns, inp| {
+                let r = self
+                    .worker
+                    .work_for_locus(columns, inp)
+                    .map_err(|err| err.into())?;
+                Ok(Some(r))
+            })
+            .map_err(crate::Error::from)
+    }
+}
+
+/// Bams read together, batch by batch, by [`MultiBamLocusProcessor`] and
+/// [`PooledLocusProcessor`](crate::bam::pooled::PooledLocusProcessor).
+pub(crate) struct BamSet {
+    pub(crate) bam_paths: Vec<PathBuf>,
+    pub(crate) opener: Box<dyn BamOpener>,
+    pub(crate) retry_policy: RetryPolicy,
+    pub(crate) pileup_options: PileupOption,
+    pub(crate) coordinate_system: CoordinateSystem,
+}
+
+impl BamSet {
+    pub(crate) fn new(bam_paths: Vec<PathBuf>) -> Self {
+        Self {
+            bam_paths,
+            opener: Box::new(HtslibOpener),
+            retry_policy: RetryPolicy::default(),
+            pileup_options: DEFAULT_PILEUP_OPTIONS,
+            coordinate_system: CoordinateSystem::default(),
+        }
+    }
+
+    /// Call `work` with the pileup columns of every bam at each input, batched
+    /// by coordinate. Outputs of `Some` are returned in input order; an error of
+    /// `work` is returned as a [`WorkerFailed`](crate::Error::WorkerFailed) at
+    /// the locus.
+    pub(crate) fn process_with_batch_on<'a, I, T>(
+        &self,
+        inputs: Vec<I>,
+        batch_window_size: usize,
+        pool: Option<&ThreadPool>,
+        work: impl Fn(&mut Vec<Option<Pileup>>, I) -> Result<Option<T>, Error> + Sync,
+    ) -> Result<Vec<T>, Error>
+    where
+        I: BamLocusWorkInput<'a>,
+        T: Send,
+    {
+        let batched_regions = batch_input_by_coordinate(inputs, batch_window_size);
+        if batched_regions.is_empty() {
+            return Ok(vec![]);
+        }
+
+        event!(
+            Level::DEBUG,
+            "batched_regions len={}",
+            batched_regions.len()
+        );
+
+        let process_span = span!(
+            Level::INFO,
+            "process_with_batch",
+            bams = ?self.bam_paths,
+            n_threads = pool_threads(pool),
+            n_batches = batched_regions.len(),
+        );
+        let _process_span = process_span.enter();
+        let dispatch = current_dispatch();
+        let busy = BusyTime::default();
+
+        let res = install_on(pool, || {
+            let batch_res = batched_regions
+                .into_par_iter()
+                .map(|batch| {
+                    let _batch_span = batch.first().map(|first| {
+                        enter_on_thread(&dispatch, || {
+                            batch_span(&process_span, first.genome_coordinate(), batch.len())
+                        })
+                    });
+                    let _busy = busy.start();
+                    self.process_batch(batch, &work)
+                })
+                .collect::<Vec<_>>();
+            collect_in_order(batch_res)
+        })?;
+
+        event!(
+            Level::DEBUG,
+            n_outputs = res.len(),
+            busy_ms = busy.millis(),
+            "process_with_batch finished"
+        );
+
+        Ok(res)
+    }
+
+    fn process_batch<'a, I: BamLocusWorkInput<'a>, T>(
+        &self,
+        batch: Vec<I>,
+        work: &impl Fn(&mut Vec<Option<Pileup>>, I) -> Result<Option<T>, Error>,
+    ) -> Result<Vec<T>, Error> {
+        let (Some(first_elem), Some(last_elem)) = (batch.first(), batch.last()) else {
+            return Ok(vec![]);
+        };
+
+        let batch_contig = &first_elem.genome_coordinate().contig;
+        let batch_contig_name = batch_contig.to_string();
+        let batch_pileup_start = self
+            .coordinate_system
+            .to_0based(first_elem.genome_coordinate().pos);
+        let batch_pileup_end = self
+            .coordinate_system
+            .to_0based(last_elem.genome_coordinate().pos)
+            + 1;
+
+        // Readers of BAMs without the contig are left out; their columns stay `None`.
+        // The others read the batch range clamped to the contig in their header.
+        let mut readers = Vec::with_capacity(self.bam_paths.len());
+        for bam_path in self.bam_paths.iter() {
+            let ir = open_with_retry(self.opener.as_ref(), &self.retry_policy, bam_path)?;
+            let reader = match resolve_contig_name(ir.header(), batch_contig)
+                .and_then(|name| Some((ir.header().tid(name.as_bytes())?, name)))
+            {
+                Some((tid, name)) => {
+                    let range = clamp_batch(
+                        ir.header(),
+                        tid,
+                        batch_contig,
+                        &batch,
+                        self.coordinate_system,
+                        (batch_pileup_start, batch_pileup_end),
+                    )?;
+                    Some((ir, name, range))
+                }
+                None => None,
+            };
+            readers.push(reader);
+        }
+        // fetches the reads of the batch from the 0-based `start` on.
+        let fetch_from = |readers: &mut [BamSetReader], start: i64| {
+            for (reader, bam_path) in readers.iter_mut().zip(self.bam_paths.iter()) {
+                if let Some((ir, name, (batch_start, batch_end))) = reader {
+                    fetch_with_retry(
+                        ir,
+                        &self.retry_policy,
+                        bam_path,
+                        name,
+                        Some((start.max(*batch_start), *batch_end)),
+                    )?;
+                }
+            }
+            Ok::<_, Error>(())
+        };
+        fetch_from(&mut readers, batch_pileup_start)?;
+        let mut pileups = reader_pileups(&mut readers, self.pileup_options);
+
+        let mut res = Vec::with_capacity(batch.len());
+        let mut columns = Vec::with_capacity(pileups.len());
+        let mut prev_target_pos = None;
+        for inp in batch {
+            let input_pos = inp.genome_coordinate().pos;
+            let target_pos = self.coordinate_system.to_0based(input_pos);
+
+            // The columns of the previous input, at the same position, may have
+            // been taken by `work`, so they are read again by fetching from it.
+            if prev_target_pos == Some(target_pos) {
+                drop(pileups);
+                fetch_from(&mut readers, target_pos)?;
+                pileups = reader_pileups(&mut readers, self.pileup_options);
+            }
+            prev_target_pos = Some(target_pos);
+
+            // The columns stay valid until their iterator is advanced, which only
+            // happens for the next input.
+            columns.clear();
+            for sample_pileups in pileups.iter_mut() {
+                let col = match sample_pileups {
+                    Some(sample_pileups) => pileup_at(sample_pileups, target_pos)?,
+                    None => None,
+                };
+                columns.push(col);
+            }
+
+            let r = catch_panic(|| work(&mut columns, inp))
+                .unwrap_or_else(|panicked| Err(panicked.into()))
+                .map_err(|err| crate::Error::WorkerFailed {
+                    item: format!("locus {}:{}", batch_contig_name, input_pos),
+                    source: err,
+                })?;
+            if let Some(r) = r {
+                res.push(r);
+            }
+        }
+
+        Ok(res)
+    }
+}
+
+/// Run `op` on `pool`, or on the current rayon pool if `None`.
+fn install_on<R: Send>(pool: Option<&ThreadPool>, op: impl FnOnce() -> R + Send) -> R {
+    match pool {
+        Some(pool) => pool.install(op),
+        None => op(),
+    }
+}
+
+fn pool_threads(pool: Option<&ThreadPool>) -> usize {
+    pool.map_or_else(rayon::current_num_threads, |pool| {
+        pool.current_num_threads()
+    })
+}
+
+/// Flatten batch results in input order. All batches are run even if some fail,
+/// so that the returned error, of the first failed batch, does not depend on
+/// the scheduling.
+fn collect_in_order<T>(batch_res: Vec<Result<Vec<T>, Error>>) -> Result<Vec<T>, Error> {
+    let mut res = vec![];
+    for batch in batch_res {
+        res.extend(batch?);
+    }
+    Ok(res)
+}
+
+/// Index of the contig of each batch among the distinct contigs of `batches`,
+/// and these contigs.
+fn batch_contig_indices<'a, I: BamLocusWorkInput<'a>>(
+    batches: &[Vec<I>],
+) -> (Vec<usize>, Vec<Chrom<'a>>) {
+    let mut indices = HashMap::new();
+    let mut contigs = vec![];
+    let batch_contigs = batches
+        .iter()
+        .map(|batch| match batch.first() {
+            Some(first) => {
+                let contig = &first.genome_coordinate().contig;
+                *indices.entry(contig).or_insert_with(|| {
+                    contigs.push(contig.clone());
+                    contigs.len() - 1
+                })
+            }
+            // empty batches fetch nothing.
+            None => 0,
+        })
+        .collect();
+
+    (batch_contigs, contigs)
+}
+
+/// Span of a locus batch, starting at `first`.
+fn batch_span(parent: &Span, first: &GenomeCoordinate<'_>, size: usize) -> Span {
+    span!(
+        parent: parent,
+        Level::TRACE,
+        "batch",
+        contig = %first.contig,
+        start = first.pos,
+        size = size,
+    )
+}
+
+/// Reader of a bam of a [`BamSet`] batch, with the name of the contig of the
+/// batch in its header and the range of the batch clamped to the contig; `None`
+/// where the bam does not have the contig.
+type BamSetReader = Option<(bam::IndexedReader, String, (i64, i64))>;
+
+/// Pileups of the fetched readers of a [`BamSet`] batch, `None` where the bam
+/// does not have the contig.
+fn reader_pileups(
+    readers: &mut [BamSetReader],
+    options: PileupOption,
+) -> Vec<Option<Peekable<Pileups<'_, bam::IndexedReader>>>> {
+    readers
+        .iter_mut()
+        .map(|reader| {
+            reader
+                .as_mut()
+                .map(|(ir, _, _)| ir.pileup_with_option(options).peekable())
+        })
+        .collect()
+}
+
+/// `range` of `batch`, 0-based half-open, clamped to the contig `tid` by
+/// [`clamp_to_contig`]. Fails with an [`OutOfContig`](crate::Error::OutOfContig)
+/// error if it starts at or past the end of the contig.
+fn clamp_batch<'a, I: BamLocusWorkInput<'a>>(
+    header: &HeaderView,
+    tid: u32,
+    contig: &Chrom<'_>,
+    batch: &[I],
+    coordinate_system: CoordinateSystem,
+    (start, end): (i64, i64),
+) -> Result<(i64, i64), Error> {
+    if let Some(len) = contig_len(header, tid)
+        && start >= len
+    {
+        let coordinates = batch
+            .iter()
+            .map(|input| {
+                let pos = input.genome_coordinate().pos;
+                format!("{}:{}", contig, coordinate_system.to_0based(pos) + 1)
+            })
+            .collect();
+        return Err(crate::Error::OutOfContig {
+            contig: contig.to_string(),
+            len: len as u64,
+            coordinates,
+        }
+        .into());
+    }
+    Ok(clamp_to_contig(header, tid, contig.as_str(), start, end))
+}
+
+/// Advance `pileups`, sorted by position, to the column at 0-based `target_pos`.
+/// Columns before it are discarded, columns after it are kept for later targets.
+fn pileup_at<R: bam::Read>(
+    pileups: &mut Peekable<Pileups<'_, R>>,
+    target_pos: i64,
+) -> Result<Option<Pileup>, Error> {
+    while let Some(col) = pileups.next_if(|col| {
+        col.as_ref()
+            .map_or(true, |col| col.pos() as i64 <= target_pos)
+    }) {
+        let col = col?;
+        if col.pos() as i64 == target_pos {
+            return Ok(Some(col));
+        }
+    }
+
+    Ok(None)
+}
+
+// pub trait RecordModifierInput {
+
+// }
+
+
+
+pub trait RecordModifier: Send + Sync {
+    // type Input: RecordModifierInput;
+    // type Output: Send + Sync;
+    type Error: Into<Error>;
+
+    /// modify record and return `Option<()>`,   
+    /// `None` means this record should not be written to the output bamfile.
+    fn modify_record(&self, record: &mut bam::Record) -> Result<Option<()>, Self::Error>;
+
+    /// Like [`Self::modify_record`], with the context of the input bam. This is
+    /// what [`ParallelBamProcessor`] calls; the default ignores `ctx`.
+    fn modify_record_ctx(
+        &self,
+        ctx: &ProcessContext,
+        record: &mut bam::Record,
+    ) -> Result<Option<()>, Self::Error> {
+        let _ = ctx;
+        self.modify_record(record)
+    }
+
+    /// Like [`Self::modify_record_ctx`], with the reason of the outcome for the
+    /// decision log of [`ParallelBamProcessor::with_decision_log`], e.g. why
+    /// the record is dropped. Kept records are logged only with a reason. This
+    /// is what [`ParallelBamProcessor`] calls; the default gives no reason.
+    fn modify_record_with_reason(
+        &self,
+        ctx: &ProcessContext,
+        record: &mut bam::Record,
+    ) -> Result<(Option<()>, Option<String>), Self::Error> {
+        Ok((self.modify_record_ctx(ctx, record)?, None))
+    }
+
+    /// Header of the output bam, from that of the input. The default copies it;
+    /// the contigs must be kept as they are.
+    fn output_header(&self, header: &HeaderView) -> Header {
+        Header::from_template(header)
+    }
+}
+
+/// Read a bam file, modify reads and write bam.
+///
+/// Use Producer Consumer Method.
+pub struct ParallelBamProcessor<R> {
+    record_modifier: R,
+    opener: Box<dyn BamOpener>,
+    pub(crate) thread_budget: Option<ThreadBudget>,
+    decision_log: Option<PathBuf>,
+    // bam_path: PathBuf,
+    // n_threads: usize,
+}
+
+/// Options for [`ParallelBamProcessor::process_bam`] and
+/// [`ParallelBamProcessor::process_bam_sequential`].
+///
+/// The thread and batch options are ignored by the sequential path.
+#[derive(Debug, Clone)]
+pub struct ProcessBamOptions {
+    /// htslib decompression threads of the reader.
+    pub read_threads: usize,
+    /// Threads calling [`RecordModifier::modify_record`].
+    pub worker_threads: usize,
+    /// htslib compression threads of the writer.
+    pub write_threads: usize,
+    /// Records per batch sent between threads.
+    pub batch_size: usize,
+    /// Batches each channel can hold.
+    pub channel_capacity: usize,
+    pub output_format: bam::Format,
+    /// Retries of opening and fetching the input bam.
+    pub retry_policy: RetryPolicy,
+    /// What to do with records the modifier fails on.
+    pub on_modify_error: OnModifyError,
+    /// Sync the output and its directory to the disk before it is renamed to its
+    /// final path. See [`AtomicFile`](crate::utils::atomic_write::AtomicFile).
+    pub fsync: bool,
+    /// Buffering of mates by [`ParallelBamProcessor::process_bam_paired`].
+    pub pairing: PairingOptions,
+    /// Drop the records failing this filter before they reach the modifier,
+    /// counted in [`ProcessStats::records_dropped`]. Ignored by
+    /// [`ParallelBamProcessor::process_bam_paired`].
+    pub read_filter: Option<ReadFilter>,
+    /// How often the channels of [`ParallelBamProcessor::process_bam`] are
+    /// reported at DEBUG while it runs, see [`ProcessStats::read_channel`].
+    /// They are only sampled, 20 times per report, while DEBUG events are
+    /// enabled or with the `metrics` feature.
+    pub metrics_interval: Duration,
+    /// Check the header of the input bam against this dictionary, e.g. of the
+    /// reference the output is meant for, before reading any record.
+    pub reference_dict: Option<SeqDict>,
+    /// What a mismatch with `reference_dict` does.
+    pub dict_check: DictCheck,
+    /// What the [`quick_check`](crate::bam::validate::quick_check) of the
+    /// input bam, run with the plan, does with its findings.
+    pub input_check: InputCheck,
+    /// How often [`ParallelBamProcessor::process_bam`] logs the records written
+    /// and their rate at INFO, see [`ThroughputLogger`].
+    pub throughput_interval: Duration,
+}
+
+impl Default for ProcessBamOptions {
+    fn default() -> Self {
+        Self {
+            read_threads: 1,
+            worker_threads: 4,
+            write_threads: 4,
+            batch_size: 1024,
+            channel_capacity: 128,
+            output_format: bam::Format::Bam,
+            retry_policy: RetryPolicy::default(),
+            on_modify_error: OnModifyError::default(),
+            fsync: false,
+            pairing: PairingOptions::default(),
+            read_filter: None,
+            metrics_interval: DEFAULT_METRICS_INTERVAL,
+            reference_dict: None,
+            dict_check: DictCheck::default(),
+            input_check: InputCheck::default(),
+            throughput_interval: DEFAULT_THROUGHPUT_INTERVAL,
+        }
+    }
+}
+
+impl ProcessBamOptions {
+    /// The check of [`Self::reference_dict`], if set, of the header of the bam
+    /// at `input_bam_path`.
+    pub(crate) fn check_dict(
+        &self,
+        header: &HeaderView,
+        input_bam_path: &Path,
+    ) -> Result<(), crate::Error> {
+        match &self.reference_dict {
+            Some(reference_dict) => {
+                let what = format!("The header of {}", input_bam_path.display());
+                let header_dict = SeqDict::from_header(header)?;
+                reference_dict.check(&header_dict, &what, self.dict_check)
+            }
+            None => Ok(()),
+        }
+    }
+}
+
+/// What [`ParallelBamProcessor`] does with a record for which
+/// [`RecordModifier::modify_record`] returned an error.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum OnModifyError {
+    /// Drop the record, logged at WARN and counted in [`ProcessStats::records_failed`].
+    #[default]
+    Skip,
+    /// Stop processing and return the error, naming the read and its position.
+    Fail,
+    /// Drop the record like `Skip`, and write it as it was read to this bam, with
+    /// the header of the input. The file is created at the first failure only.
+    DeadLetter(PathBuf),
+}
+
+/// The lazily created bam of [`OnModifyError::DeadLetter`], shared by the workers.
+pub(crate) struct DeadLetterWriter<'a> {
+    path: &'a Path,
+    header_bytes: &'a [u8],
+    writer: Mutex<Option<Writer>>,
+}
+
+impl<'a> DeadLetterWriter<'a> {
+    pub(crate) fn new(policy: &'a OnModifyError, header_bytes: &'a [u8]) -> Option<Self> {
+        match policy {
+            OnModifyError::DeadLetter(path) => Some(Self {
+                path,
+                header_bytes,
+                writer: Mutex::new(None),
+            }),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn write(&self, record: &Record) -> Result<(), Error> {
+        let mut writer = self.writer.lock().unwrap();
+        let writer = match &mut *writer {
+            Some(writer) => writer,
+            None => {
+                let header = Header::from_template(&HeaderView::from_bytes(self.header_bytes));
+                let new_writer = Writer::from_path(self.path, &header, bam::Format::Bam)
+                    .with_context(|| format!("Failed to create {}", self.path.display()))?;
+                writer.insert(new_writer)
+            }
+        };
+        writer.write(record)?;
+        Ok(())
+    }
+}
+
+/// Copy the data of `src` into `dst`, reusing the buffer of `dst`, which only
+/// grows for records longer than any copied before.
+///
+/// Unlike [`Record::clone`], which allocates a new record and its data each
+/// time, this keeps the records as read for the [`DeadLetterWriter`] without
+/// any allocation per record, which matters for megabase-long reads. The
+/// header and the cached CIGAR of `dst` are left as they are.
+pub(crate) fn copy_record_into(dst: &mut Record, src: &Record) {
+    // `bam_copy1` grows the data with `realloc`, and `Record` frees it with
+    // `free`, both of the C allocator.
+    let copied = unsafe { rust_htslib::htslib::bam_copy1(dst.inner_mut(), src.inner()) };
+    assert!(
+        !copied.is_null(),
+        "Failed to allocate {} bytes to copy a record",
+        src.inner().l_data
+    );
+}
+
+/// [`RecordModifier::modify_record_with_reason`] on `record`, a panic of which
+/// fails the record like an error it returns.
+pub(crate) fn modify_caught<M: RecordModifier>(
+    modifier: &M,
+    ctx: &ProcessContext,
+    record: &mut bam::Record,
+) -> Result<(Option<()>, Option<String>), Error> {
+    catch_panic(|| {
+        modifier
+            .modify_record_with_reason(ctx, record)
+            .map_err(Into::into)
+    })
+    .unwrap_or_else(|panicked| Err(panicked.into()))
+}
+
+/// `err` with the read name and 1-based position of `record`, for errors of
+/// [`RecordModifier::modify_record`].
+pub(crate) fn record_error(err: Error, header: &HeaderView, record: &Record) -> Error {
+    let qname = String::from_utf8_lossy(record.qname());
+    let locus = match record.tid() {
+        tid if tid < 0 => "*".to_string(),
+        tid => format!(
+            "{}:{}",
+            String::from_utf8_lossy(header.tid2name(tid as u32)),
+            record.pos() + 1
+        ),
+    };
+    crate::Error::WorkerFailed {
+        item: format!("read {} at {}", qname, locus),
+        source: err,
+    }
+    .into()
+}
+
+/// Record counts of a finished [`ParallelBamProcessor`] run.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ProcessStats {
+    pub records_read: u64,
+    pub records_written: u64,
+    /// Records for which the modifier returned `Ok(None)`.
+    pub records_dropped: u64,
+    /// Records for which the modifier returned an error. They are not written.
+    pub records_failed: u64,
+    /// Reuse of the record batches by [`ParallelBamProcessor::process_bam`].
+    pub batch_pool: PoolStats,
+    /// Batches from the reader to the workers of
+    /// [`ParallelBamProcessor::process_bam`]; mostly full if the workers are
+    /// the bottleneck, mostly empty if reading is.
+    pub read_channel: ChannelStats,
+    /// Batches from the workers to the writer of
+    /// [`ParallelBamProcessor::process_bam`]; mostly full if writing is the
+    /// bottleneck.
+    pub write_channel: ChannelStats,
+    /// Counts per read group `ID` of the header, and under [`UNKNOWN_READ_GROUP`]
+    /// for records without one. Not counted by
+    /// [`ParallelBamProcessor::process_bam_paired`].
+    pub read_groups: BTreeMap<String, ReadGroupStats>,
+    /// Records written per file of [`OutputTarget::PerContig`], by contig, and
+    /// under [`UNMAPPED_CONTIG`] for the reads without one. Empty for other
+    /// outputs.
+    ///
+    /// [`UNMAPPED_CONTIG`]: crate::bam::output::UNMAPPED_CONTIG
+    pub records_per_contig: BTreeMap<String, u64>,
+    /// The plan of the run, logged at DEBUG as it starts. Only made by
+    /// [`ParallelBamProcessor::process_bam`] and
+    /// [`ParallelBamProcessor::process_bam_to`].
+    pub plan: Option<RunPlan>,
+}
+
+impl std::fmt::Display for ProcessStats {
+    /// The record counts, e.g. `1.2 M records read, 1.2 M written, 3 dropped,
+    /// 0 failed`, see [`SiCount`].
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} records read, {} written, {} dropped, {} failed",
+            SiCount(self.records_read),
+            SiCount(self.records_written),
+            SiCount(self.records_dropped),
+            SiCount(self.records_failed)
+        )
+    }
+}
+
+/// Key of [`ProcessStats::read_groups`] for records without an `RG` tag, or with
+/// one not in the header. Only present if there are such records.
+pub const UNKNOWN_READ_GROUP: &str = "unknown";
+
+/// Record counts of a read group, by the `RG` tag of the records as read.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ReadGroupStats {
+    pub records_read: u64,
+    pub records_written: u64,
+    /// Records not written: filtered out, dropped by the modifier or failed.
+    pub records_dropped: u64,
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct AtomicProcessStats {
+    pub(crate) records_read: AtomicU64,
+    pub(crate) records_written: AtomicU64,
+    pub(crate) records_dropped: AtomicU64,
+    pub(crate) records_failed: AtomicU64,
+    /// `ID`s of the read groups, and the counts of each then of the unknown one.
+    read_groups: OnceLock<(Vec<String>, Vec<AtomicReadGroupStats>)>,
+}
+
+#[derive(Debug, Default)]
+struct AtomicReadGroupStats {
+    records_read: AtomicU64,
+    records_written: AtomicU64,
+    records_dropped: AtomicU64,
+}
+
+impl AtomicProcessStats {
+    /// Count records per read group of `ctx` from now on.
+    pub(crate) fn init_read_groups(&self, ctx: &ProcessContext) {
+        let ids = ctx
+            .read_groups()
+            .iter()
+            .map(|rg| rg.id.clone())
+            .collect::<Vec<_>>();
+        let counts = (0..=ids.len()).map(|_| Default::default()).collect();
+        let _ = self.read_groups.set((ids, counts));
+    }
+
+    /// Count a record of read group `rg`, an index of
+    /// [`ProcessContext::read_groups`], as written or dropped.
+    pub(crate) fn count_read_group(&self, rg: Option<usize>, written: bool) {
+        let Some((ids, counts)) = self.read_groups.get() else {
+            return;
+        };
+        let counts = &counts[rg.unwrap_or(ids.len())];
+        counts.records_read.fetch_add(1, atomic::Ordering::Relaxed);
+        match written {
+            true => &counts.records_written,
+            false => &counts.records_dropped,
+        }
+        .fetch_add(1, atomic::Ordering::Relaxed);
+    }
+
+    pub(crate) fn snapshot(&self) -> ProcessStats {
+        let read_groups = match self.read_groups.get() {
+            Some((ids, counts)) => ids
+                .iter()
+                .map(|id| id.as_str())
+                .chain([UNKNOWN_READ_GROUP])
+                .zip(counts)
+                .map(|(id, counts)| {
+                    let stats = ReadGroupStats {
+                        records_read: counts.records_read.load(atomic::Ordering::Relaxed),
+                        records_written: counts.records_written.load(atomic::Ordering::Relaxed),
+                        records_dropped: counts.records_dropped.load(atomic::Ordering::Relaxed),
+                    };
+                    (id.to_string(), stats)
+                })
+                .enumerate()
+                .filter(|(i, (_, stats))| *i < ids.len() || stats.records_read > 0)
+                .map(|(_, entry)| entry)
+                .collect(),
+            None => BTreeMap::new(),
+        };
+
+        ProcessStats {
+            records_read: self.records_read.load(atomic::Ordering::Relaxed),
+            records_written: self.records_written.load(atomic::Ordering::Relaxed),
+            records_dropped: self.records_dropped.load(atomic::Ordering::Relaxed),
+            records_failed: self.records_failed.load(atomic::Ordering::Relaxed),
+            batch_pool: PoolStats::default(),
+            read_channel: ChannelStats::default(),
+            write_channel: ChannelStats::default(),
+            read_groups,
+            records_per_contig: BTreeMap::new(),
+            plan: None,
+        }
+    }
+}
+
+/// The `records_{read,written,dropped,failed}_total` counters of the
+/// [`global`](crate::utils::metrics::global) metrics registry, fed the counts
+/// of a run as they grow.
+///
+/// Published per batch by the writer rather than per record, so that the
+/// workers do not contend on more atomics.
+#[cfg(feature = "metrics")]
+struct RecordCounters {
+    counters: [crate::utils::metrics::Counter; 4],
+    /// Counts of the run already added to the counters.
+    published: [u64; 4],
+}
+
+#[cfg(feature = "metrics")]
+impl RecordCounters {
+    /// Fails if a counter is registered as a gauge already.
+    fn new() -> Result<Self, crate::Error> {
+        let registry = crate::utils::metrics::global();
+        let [read, written, dropped, failed] = ["read", "written", "dropped", "failed"]
+            .map(|what| registry.counter(&format!("records_{}_total", what), &[]));
+        Ok(Self {
+            counters: [read?, written?, dropped?, failed?],
+            published: [0; 4],
+        })
+    }
+
+    fn publish(&mut self, stats: &AtomicProcessStats) {
+        let counts = [
+            &stats.records_read,
+            &stats.records_written,
+            &stats.records_dropped,
+            &stats.records_failed,
+        ]
+        .map(|count| count.load(atomic::Ordering::Relaxed));
+        let published = self.counters.iter().zip(&mut self.published);
+        for ((counter, published), count) in published.zip(counts) {
+            counter.add(count.saturating_sub(*published));
+            *published = (*published).max(count);
+        }
+    }
+}
+
+/// Live state of a [`ParallelBamProcessor`] run, shared with the caller.
+///
+/// The counts of `stats` are updated at each batch while the run goes on.
+/// Setting `cancelled` stops the stages at their next loop iteration, the same
+/// way a failure does.
+#[derive(Debug, Default)]
+pub(crate) struct PipelineControl {
+    pub(crate) cancelled: AtomicBool,
+    pub(crate) stats: AtomicProcessStats,
+}
+
+impl<R> ParallelBamProcessor<R> {
+    pub fn new(record_modifier: R) -> Self {
+        Self {
+            record_modifier,
+            opener: Box::new(HtslibOpener),
+            thread_budget: None,
+            decision_log: None,
+        }
+    }
+
+    /// Open the input bam with `opener` instead of [`HtslibOpener`].
+    pub fn with_opener(mut self, opener: impl BamOpener + 'static) -> Self {
+        self.opener = Box::new(opener);
+        self
+    }
+
+    /// Reserve the reader, worker and writer threads of each run of `budget`.
+    /// Over its total, the stages with the most threads get fewer, down to one
+    /// each. Waits for the threads or fails as set by its
+    /// [`ExhaustedPolicy`](crate::utils::thread_budget::ExhaustedPolicy).
+    pub fn with_thread_budget(mut self, budget: &ThreadBudget) -> Self {
+        self.thread_budget = Some(budget.clone());
+        self
+    }
+
+    /// Log the records the modifier does not plainly keep to a bgzf-compressed
+    /// TSV at `path`, with why, in the order of the input. It is written by a
+    /// thread of its own as the run goes. Not written by
+    /// [`Self::process_bam_paired`]. See [`decision_log`](crate::bam::decision_log).
+    pub fn with_decision_log(mut self, path: impl AsRef<Path>) -> Self {
+        self.decision_log = Some(path.as_ref().to_path_buf());
+        self
+    }
+
+    pub fn record_modifier(&self) -> &R {
+        &self.record_modifier
+    }
+
+    /// What [`Self::process_bam_to`] would do, checked without reading any
+    /// record: that the input exists and is indexed, that its header matches
+    /// [`ProcessBamOptions::reference_dict`], that the outputs, the decision log
+    /// and the dead letter bam can be written, and that the threads fit the
+    /// thread budget. Batches are estimated from the records counted by the
+    /// index. See [`RunPlan`].
+    pub fn plan(
+        &self,
+        input_bam_path: impl AsRef<Path>,
+        output: &OutputTarget,
+        opts: &ProcessBamOptions,
+    ) -> RunPlan {
+        let input_bam_path = input_bam_path.as_ref();
+        let (mut plan, reader) = RunPlan::of_input(
+            "process_bam",
+            input_bam_path,
+            self.opener.as_ref(),
+            &opts.retry_policy,
+            opts.input_check,
+        );
+        let mut n_records = None;
+        if let Some(mut reader) = reader {
+            if let Err(err) = opts.check_dict(reader.header(), input_bam_path) {
+                plan.problems.push(PlanProblem::DictMismatch {
+                    message: format!("{:#}", err),
+                });
+            }
+            n_records = reader.index_stats().ok().map(|stats| {
+                stats
+                    .iter()
+                    .map(|&(_, _, mapped, unmapped)| mapped + unmapped)
+                    .sum::<u64>()
+            });
+        }
+        if opts.batch_size == 0 {
+            plan.problems.push(PlanProblem::InvalidOption {
+                message: "Batch size must be at least 1".to_string(),
+            });
+        }
+        plan.batches = BatchPlan {
+            unit: "records".to_string(),
+            n_items: n_records,
+            batch_size: Some(opts.batch_size as u64),
+            n_batches: n_records.map(|n| n.div_ceil(opts.batch_size.max(1) as u64)),
+        };
+
+        plan.output = Some(output.to_string());
+        match output {
+            OutputTarget::File(path) => plan.check_writable(path, false),
+            OutputTarget::PerContig { dir, .. } => plan.check_writable(dir, true),
+            OutputTarget::Command { argv } if argv.is_empty() => {
+                plan.problems.push(PlanProblem::InvalidOption {
+                    message: "The output command is empty".to_string(),
+                })
+            }
+            OutputTarget::Command { .. } => {}
+        }
+        if let Some(path) = &self.decision_log {
+            plan.check_writable(path, false);
+        }
+        if let OnModifyError::DeadLetter(path) = &opts.on_modify_error {
+            plan.check_writable(path, false);
+        }
+
+        let total = self.thread_budget.as_ref().map(ThreadBudget::total);
+        let [read_threads, worker_threads, write_threads] = stage_threads(opts, total);
+        plan.threads = vec![
+            ("reader".to_string(), read_threads),
+            ("workers".to_string(), worker_threads),
+            ("writer".to_string(), write_threads),
+        ];
+        plan.thread_budget = total;
+        if let Some(total) = total
+            && total < 3
+        {
+            let err = ThreadBudgetError::OverTotal {
+                requested: 3,
+                total,
+            };
+            plan.problems.push(PlanProblem::Threads {
+                message: err.to_string(),
+            });
+        }
+        plan
+    }
+
+    /// [`Self::plan`], failing with an [`InvalidPlan`](crate::Error::InvalidPlan)
+    /// error of all its problems if it has any.
+    pub fn dry_run(
+        &self,
+        input_bam_path: impl AsRef<Path>,
+        output: &OutputTarget,
+        opts: &ProcessBamOptions,
+    ) -> Result<RunPlan, crate::Error> {
+        self.plan(input_bam_path, output, opts).into_result()
+    }
+}
+
+impl<R: RecordModifier> ParallelBamProcessor<R> {
+    /// Run the reader -> workers -> writer pipeline. The input bam must be indexed.
+    ///
+    /// If any stage fails, the others stop at their next loop iteration and the
+    /// error of the stage which failed first is returned.
+    ///
+    /// The output is written next to `out_bam_path` and renamed to it once
+    /// complete (see [`AtomicFile`](crate::utils::atomic_write::AtomicFile)), so
+    /// a failed run leaves no output behind.
+    pub fn process_bam(
+        &self,
+        input_bam_path: impl AsRef<Path>,
+        out_bam_path: impl AsRef<Path>,
+        opts: &ProcessBamOptions,
+    ) -> Result<ProcessStats, crate::Error> {
+        self.process_bam_to(input_bam_path, &out_bam_path.as_ref().into(), opts)
+    }
+
+    /// [`Self::process_bam`], writing to `output`, e.g. a file per contig.
+    ///
+    /// Records keep the input order within each file.
+    pub fn process_bam_to(
+        &self,
+        input_bam_path: impl AsRef<Path>,
+        output: &OutputTarget,
+        opts: &ProcessBamOptions,
+    ) -> Result<ProcessStats, crate::Error> {
+        Ok(self.process_bam_with_control(
+            input_bam_path,
+            output,
+            opts,
+            &PipelineControl::default(),
+        )?)
+    }
+
+    /// [`Self::process_bam_to`], reporting to and stopped by `control`.
+    pub(crate) fn process_bam_with_control(
+        &self,
+        input_bam_path: impl AsRef<Path>,
+        output: &OutputTarget,
+        opts: &ProcessBamOptions,
+        control: &PipelineControl,
+    ) -> Result<ProcessStats, Error> {
+        let input_bam_path = input_bam_path.as_ref();
+        // the problems of the plan fail the run as they are met below.
+        let plan = self.plan(input_bam_path, output, opts);
+        event!(Level::DEBUG, "{}", plan);
+        plan.check_input()?;
+        let retry_policy = opts.retry_policy;
+        let ([read_thread, worker_thread, write_thread], _guard) =
+            budgeted_stage_threads(opts, self.thread_budget.as_ref())?;
+
+        // check bam path exists
+        if !input_bam_path.exists() {
+            Err(std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                input_bam_path.to_string_lossy(),
+            ))?
+        }
+
+        let mut reader = open_with_retry(self.opener.as_ref(), &retry_policy, input_bam_path)?;
+        opts.check_dict(reader.header(), input_bam_path)?;
+        if read_thread > 1 {
+            reader.set_threads(read_thread)?; // Use shared pool for internal I/O [1]
+        }
+        // Read all records from the file. htslib fails to fetch them from a file
+        // without any, which is then left unfetched, reading no record.
+        if let Err(err) = fetch_with_retry(&mut reader, &retry_policy, input_bam_path, ".", None) {
+            if !index_counts_no_records(&mut reader) {
+                return Err(err.into());
+            }
+            event!(Level::WARN, "{} has no records", input_bam_path.display());
+        }
+
+        let header_view_bytes = reader.header().as_bytes().to_vec();
+
+        let process_span = span!(
+            Level::INFO,
+            "process_bam",
+            input = %input_bam_path.display(),
+            output = %output,
+            read_threads = read_thread,
+            worker_threads = worker_thread,
+            write_threads = write_thread,
+        );
+        let _process_span = process_span.enter();
+
+        let stats = &control.stats;
+        let read_record = |record: &mut HeaderlessRecord| loop {
+            match record.read_from(&mut reader) {
+                Some(Ok(_)) => {
+                    stats.records_read.fetch_add(1, atomic::Ordering::Relaxed);
+                    return Ok(true);
+                }
+                // skip the read and reuse its slot.
+                Some(Err(e)) => event!(Level::WARN, "Error reading record: {:?}", e),
+                None => return Ok(false),
+            }
+        };
+
+        let stats = self.run_pipeline(
+            &header_view_bytes,
+            read_record,
+            output,
+            opts,
+            [worker_thread, write_thread],
+            control,
+        )?;
+        Ok(ProcessStats {
+            plan: Some(plan),
+            ..stats
+        })
+    }
+
+    /// The workers and the writer of [`Self::process_bam_with_control`], for the
+    /// records of `read_record`, of the header `header_view_bytes`.
+    pub(crate) fn run_pipeline(
+        &self,
+        header_view_bytes: &[u8],
+        read_record: impl FnMut(&mut HeaderlessRecord) -> Result<bool, Error> + Send,
+        output: &OutputTarget,
+        opts: &ProcessBamOptions,
+        [worker_thread, write_thread]: [usize; 2],
+        control: &PipelineControl,
+    ) -> Result<ProcessStats, Error> {
+        let ProcessBamOptions {
+            batch_size,
+            channel_capacity,
+            output_format,
+            ref on_modify_error,
+            fsync,
+            ref read_filter,
+            metrics_interval,
+            throughput_interval,
+            ..
+        } = *opts;
+        let dead_letter = DeadLetterWriter::new(on_modify_error, header_view_bytes);
+        let ctx = ProcessContext::from_header(&HeaderView::from_bytes(header_view_bytes));
+        let stats = &control.stats;
+        stats.init_read_groups(&ctx);
+
+        let decision_log = self
+            .decision_log
+            .as_deref()
+            .map(|path| DecisionLogThread::spawn(path, channel_capacity));
+        let logging = decision_log.is_some();
+
+        let make_worker = |_| {
+            let header_view = Rc::new(HeaderView::from_bytes(header_view_bytes));
+            let (ctx, dead_letter) = (&ctx, &dead_letter);
+            // the dead letter bam gets the record as it was read, copied into
+            // the same buffer for every record of the worker.
+            let mut original = dead_letter.as_ref().map(|_| Record::new());
+
+            // With a decision log, the records not plainly kept are passed to
+            // the writer with their decision, which it logs in order; only the
+            // modified ones are written.
+            Ok(move |record: &mut HeaderlessRecord| {
+                let rg = ctx.read_group_idx(record);
+                if let Some(filter) = read_filter
+                    && !filter.passes_ctx(ctx, record)
+                {
+                    stats
+                        .records_dropped
+                        .fetch_add(1, atomic::Ordering::Relaxed);
+                    stats.count_read_group(rg, false);
+                    return Ok(logging
+                        .then(|| Some(Decision::new(record, Outcome::Dropped, "read filter"))));
+                }
+
+                if let Some(original) = &mut original {
+                    copy_record_into(original, record);
+                }
+
+                // Dropped records keep their slot, as the writer orders batches
+                // by idx; they are only flagged for the writer to skip.
+                let res = modify_caught(
+                    &self.record_modifier,
+                    ctx,
+                    &mut record.with_header(Rc::clone(&header_view)),
+                );
+                stats.count_read_group(rg, matches!(res, Ok((Some(_), _))));
+                match res {
+                    Ok((Some(_), reason)) => {
+                        let reason = reason.filter(|_| logging);
+                        Ok(Some(reason.map(|reason| {
+                            Decision::new(record, Outcome::Modified, &reason)
+                        })))
+                    }
+                    Ok((None, reason)) => {
+                        stats
+                            .records_dropped
+                            .fetch_add(1, atomic::Ordering::Relaxed);
+                        let reason = reason.unwrap_or_default();
+                        Ok(logging.then(|| Some(Decision::new(record, Outcome::Dropped, &reason))))
+                    }
+                    Err(err) => {
+                        let reason = format!("{:#}", err);
+                        let decision =
+                            logging.then(|| Some(Decision::new(record, Outcome::Failed, &reason)));
+                        let err = record_error(err, &header_view, record);
+                        stats.records_failed.fetch_add(1, atomic::Ordering::Relaxed);
+                        if *on_modify_error == OnModifyError::Fail {
+                            return Err(err);
+                        }
+                        event!(Level::WARN, "{:#}. drop this read", err);
+
+                        if let (Some(dead_letter), Some(original)) = (dead_letter, &original) {
+                            dead_letter.write(original)?;
+                        }
+                        Ok(decision)
+                    }
+                }
+            })
+        };
+
+        // files are created by the writer thread as records come, so that their
+        // errors are reported as the writer's.
+        let mut writers = OutputWriters::new(
+            output,
+            &HeaderView::from_header(
+                &self
+                    .record_modifier
+                    .output_header(&HeaderView::from_bytes(header_view_bytes)),
+            ),
+            output_format,
+            write_thread,
+            fsync,
+        )?;
+        let pbar = prepare_pbar(0);
+        let mut n_consumed = 0;
+        // the pbar is hidden without a terminal, leaving these lines in the logs.
+        let throughput = ThroughputLogger::new("records written", Level::INFO)
+            .with_interval(throughput_interval);
+        #[cfg(feature = "metrics")]
+        let mut record_counters = RecordCounters::new()?;
+
+        let write_batch = |batch: &mut Batch<HeaderlessRecord, Option<Decision>>| {
+            let mut decisions = vec![];
+            let mut n_written = 0u64;
+            for (record, decision) in batch.kept() {
+                if let Some(decision) = decision {
+                    decisions.push(decision.clone());
+                    if decision.outcome != Outcome::Modified {
+                        continue;
+                    }
+                }
+                writers.write(record)?;
+                stats
+                    .records_written
+                    .fetch_add(1, atomic::Ordering::Relaxed);
+                n_written += 1;
+            }
+
+            let n_before = n_consumed;
+            n_consumed += batch.len();
+            if n_consumed / N_1M > n_before / N_1M {
+                pbar.inc((n_consumed / N_1M - n_before / N_1M) as u64 * N_1M as u64);
+            }
+            throughput.add(n_written);
+            #[cfg(feature = "metrics")]
+            record_counters.publish(stats);
+
+            if let Some(decision_log) = &decision_log
+                && !decisions.is_empty()
+            {
+                decision_log.send(decisions)?;
+            }
+            Ok(())
+        };
+
+        let report = OrderedPipeline::with_worker_init(read_record, make_worker, write_batch)
+            .with_worker_threads(worker_thread)
+            .with_batch_size(batch_size)
+            .with_channel_capacity(channel_capacity)
+            .with_cancel_flag(&control.cancelled)
+            .with_metrics_interval(metrics_interval)
+            .run();
+        // an error of the log stops the writer, so it comes first.
+        if let Some(decision_log) = decision_log {
+            decision_log.finish()?;
+        }
+        #[cfg(feature = "metrics")]
+        record_counters.publish(stats);
+        let report = report?;
+
+        pbar.inc(n_consumed as u64 - pbar.position());
+        pbar.tick();
+        pbar.finish();
+        throughput.finish();
+
+        // On errors, the writers are dropped above and the partial output removed.
+        let records_per_contig = writers.finish()?;
+
+        let stats = ProcessStats {
+            batch_pool: report.batch_pool,
+            read_channel: report.read_channel,
+            write_channel: report.write_channel,
+            records_per_contig,
+            ..stats.snapshot()
+        };
+        event!(
+            Level::DEBUG,
+            records_read = stats.records_read,
+            records_written = stats.records_written,
+            records_dropped = stats.records_dropped,
+            records_failed = stats.records_failed,
+            reader_busy_ms = report.reader_busy.as_millis() as u64,
+            worker_busy_ms = report.worker_busy.as_millis() as u64,
+            writer_busy_ms = report.writer_busy.as_millis() as u64,
+            batches_created = report.batch_pool.n_created,
+            "process_bam finished: {}",
+            stats
+        );
+
+        Ok(stats)
+    }
+
+    /// Single-threaded counterpart of [`Self::process_bam`].
+    ///
+    /// Records are read, modified and written one by one, with the same drop
+    /// semantics for `Ok(None)` and errors, so the output is identical. The input
+    /// is planned, checked and opened as by [`Self::process_bam`], and must be
+    /// indexed as well. Useful for debugging a [`RecordModifier`] and for tiny
+    /// inputs.
+    pub fn process_bam_sequential(
+        &self,
+        input_bam_path: impl AsRef<Path>,
+        out_bam_path: impl AsRef<Path>,
+        opts: &ProcessBamOptions,
+    ) -> Result<ProcessStats, crate::Error> {
+        self.process_bam_sequential_to(input_bam_path, &out_bam_path.as_ref().into(), opts)
+    }
+
+    /// [`Self::process_bam_sequential`], writing to `output` as
+    /// [`Self::process_bam_to`] does.
+    pub fn process_bam_sequential_to(
+        &self,
+        input_bam_path: impl AsRef<Path>,
+        output: &OutputTarget,
+        opts: &ProcessBamOptions,
+    ) -> Result<ProcessStats, crate::Error> {
+        let input_bam_path = input_bam_path.as_ref();
+        let mut plan = self.plan(input_bam_path, output, opts);
+        // a single thread reads, modifies and writes, which fits any budget.
+        plan.run = "process_bam_sequential".to_string();
+        plan.threads = vec![("sequential".to_string(), 1)];
+        plan.problems
+            .retain(|problem| !matches!(problem, PlanProblem::Threads { .. }));
+        event!(Level::DEBUG, "{}", plan);
+        plan.check_input()?;
+        let _guard = self
+            .thread_budget
+            .as_ref()
+            .map(|budget| budget.reserve(1))
+            .transpose()
+            .map_err(Error::from)?;
+
+        let mut reader =
+            open_with_retry(self.opener.as_ref(), &opts.retry_policy, input_bam_path)?;
+        opts.check_dict(reader.header(), input_bam_path)?;
+        // as by `process_bam`, a file without records is left unfetched.
+        if let Err(err) =
+            fetch_with_retry(&mut reader, &opts.retry_policy, input_bam_path, ".", None)
+        {
+            if !index_counts_no_records(&mut reader) {
+                return Err(err);
+            }
+            event!(Level::WARN, "{} has no records", input_bam_path.display());
+        }
+        let header = self.record_modifier.output_header(reader.header());
+        let mut writers = OutputWriters::new(
+            output,
+            &HeaderView::from_header(&header),
+            opts.output_format,
+            1,
+            opts.fsync,
+        )?;
+        let header_view = reader.header().clone();
+        let dead_letter = DeadLetterWriter::new(&opts.on_modify_error, header_view.as_bytes());
+        let ctx = ProcessContext::from_header(&header_view);
+        let mut decision_log = match &self.decision_log {
+            Some(path) => Some(DecisionLogWriter::create(path)?),
+            None => None,
+        };
+
+        let mut stats = ProcessStats::default();
+        // read groups are counted as by the parallel run.
+        let rg_stats = AtomicProcessStats::default();
+        rg_stats.init_read_groups(&ctx);
+        let mut record = Record::new();
+        let mut original = dead_letter.as_ref().map(|_| Record::new());
+
+        while let Some(res) = reader.read(&mut record) {
+            if let Err(e) = res {
+                event!(Level::WARN, "Error reading record: {:?}", e);
+                continue;
+            }
+            stats.records_read += 1;
+
+            let rg = ctx.read_group_idx(&record);
+            if let Some(filter) = &opts.read_filter
+                && !filter.passes_ctx(&ctx, &record)
+            {
+                stats.records_dropped += 1;
+                rg_stats.count_read_group(rg, false);
+                if let Some(decision_log) = &mut decision_log {
+                    decision_log.write(&Decision::new(&record, Outcome::Dropped, "read filter"))?;
+                }
+                continue;
+            }
+
+            if let Some(original) = &mut original {
+                copy_record_into(original, &record);
+            }
+            let res = modify_caught(&self.record_modifier, &ctx, &mut record);
+            rg_stats.count_read_group(rg, matches!(res, Ok((Some(_), _))));
+            match res {
+                Ok((Some(_), reason)) => {
+                    if let (Some(decision_log), Some(reason)) = (&mut decision_log, reason) {
+                        decision_log.write(&Decision::new(&record, Outcome::Modified, &reason))?;
+                    }
+                    writers.write(&record)?;
+                    stats.records_written += 1;
+                }
+                Ok((None, reason)) => {
+                    stats.records_dropped += 1;
+                    if let Some(decision_log) = &mut decision_log {
+                        let reason = reason.unwrap_or_default();
+                        decision_log.write(&Decision::new(&record, Outcome::Dropped, &reason))?;
+                    }
+                }
+                Err(err) => {
+                    if let Some(decision_log) = &mut decision_log {
+                        let reason = format!("{:#}", err);
+                        decision_log.write(&Decision::new(&record, Outcome::Failed, &reason))?;
+                    }
+                    let err = record_error(err, &header_view, &record);
+                    stats.records_failed += 1;
+                    if opts.on_modify_error == OnModifyError::Fail {
+                        return Err(err.into());
+                    }
+                    event!(Level::WARN, "{:#}. drop this read", err);
+
+                    if let (Some(dead_letter), Some(original)) = (&dead_letter, &original) {
+                        dead_letter.write(original)?;
+                    }
+                }
+            }
+        }
+
+        stats.records_per_contig = writers.finish()?;
+        if let Some(decision_log) = decision_log {
+            decision_log.finish()?;
+        }
+
+        stats.read_groups = rg_stats.snapshot().read_groups;
+        stats.plan = Some(plan);
+        Ok(stats)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        borrow::Cow,
+        sync::{Arc, atomic::AtomicUsize},
+        time::{Duration, Instant},
+    };
+
+    use anyhow::bail;
+    use rust_htslib::bam::{
+        IndexedReader,
+        record::{Cigar, CigarString},
+    };
+    use tracing::field;
+
+    use super::*;
+    use crate::{
+        bam::{pileup_ext::PileupExt, process::BamLocusWorker},
+        data::{bases::BaseArr, chrom::Chrom, seq_dict::SeqEntry},
+        test_utils::TestBam,
+        utils::{
+            panic::Panicked,
+            pipeline_test_kit::{PipelineKit, Script, assert_failed_in, with_debug_tracing},
+        },
+    };
+
+    struct MeanBPWorker;
+
+    impl<'a> BamLocusWorker<'a> for MeanBPWorker {
+        type Output = f64;
+        type Input = GenomeCoordinate<'a>;
+        type Error = Error;
+
+        fn work_for_locus(
+            &self,
+            plp: Pileup,
+            inp: Self::Input,
+        ) -> Result<Self::Output, Self::Error> {
+            let len = plp.alignments().len();
+            let bq_sum = plp
+                .typed_alignments()
+                .filter_map(|aln| aln.base_qual)
+                .map(|bq| bq as u64)
+                .sum::<u64>();
+
+            Ok(bq_sum as f64 / len as f64)
+        }
+    }
+
+    #[test]
+    fn parallel_locus_processor1() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = tempfile::tempdir()?;
+        // reads cover 1-based 59_991..=160_015, with base quality 30.
+        let bam_path = TestBam::new()
+            .add_reads("chr20", 59_990, 25, 4_000, 50)
+            .build(dir.path())?;
+        let plp = ParallelLocusProcessorPileup::new(MeanBPWorker, 4, bam_path);
+
+        // positions after 160_000 have no coverage.
+        let regions = (60000..(60000 + 110_000))
+            .step_by(1000)
+            .map(|p| GenomeCoordinate {
+                contig: Chrom::Chr20,
+                pos: p,
+            })
+            .collect::<Vec<_>>();
+
+        let r = plp.process_with_batch(regions, 10_000)?;
+
+        assert_eq!(r.len(), 101);
+        assert!(r.iter().all(|bq| *bq == 30.0));
+
+        Ok(())
+    }
+
+    /// Position and depth of the locus, failing at positions in `fail_at`.
+    struct PosDepthWorker {
+        fail_at: HashSet<i64>,
+    }
+
+    impl<'a> BamLocusWorker<'a> for PosDepthWorker {
+        type Output = (i64, usize);
+        type Input = GenomeCoordinate<'a>;
+        type Error = Error;
+
+        fn work_for_locus(
+            &self,
+            plp: Pileup,
+            inp: Self::Input,
+        ) -> Result<Self::Output, Self::Error> {
+            if self.fail_at.contains(&inp.pos) {
+                bail!("failed at {}", inp.pos);
+            }
+            Ok((inp.pos, plp.alignments().len()))
+        }
+    }
+
+    #[test]
+    fn test_process_with_batch_is_reproducible() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = tempfile::tempdir()?;
+        // uneven depths, 1-based 1..=100_046 covered.
+        let bam_path = TestBam::new()
+            .add_reads("chr1", 0, 7, 14_286, 50)
+            .build(dir.path())?;
+        let plp = ParallelLocusProcessorPileup::new(
+            PosDepthWorker {
+                fail_at: HashSet::new(),
+            },
+            8,
+            bam_path,
+        );
+        let inputs = || {
+            (1..=100_000)
+                .map(|pos| coord("chr1", pos))
+                .collect::<Vec<_>>()
+        };
+        let serialize = |res: Vec<(i64, usize)>| {
+            res.into_iter()
+                .flat_map(|(pos, depth)| [pos.to_le_bytes(), (depth as u64).to_le_bytes()])
+                .flatten()
+                .collect::<Vec<u8>>()
+        };
+
+        let pool = ThreadPoolBuilder::new().num_threads(8).build()?;
+        let first = serialize(plp.process_with_batch_on(inputs(), 500, Some(&pool))?);
+        let second = serialize(plp.process_with_batch_on(inputs(), 500, Some(&pool))?);
+        // on the current pool.
+        let third = serialize(pool.install(|| plp.process_with_batch_on(inputs(), 500, None))?);
+
+        assert_eq!(first.len(), 100_000 * 16);
+        assert!(first == second && first == third);
+        assert_eq!(first[..8], 1_i64.to_le_bytes());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_process_with_auto_batch() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = tempfile::tempdir()?;
+        let bam_path = TestBam::new()
+            .add_reads("chr1", 0, 7, 2_000, 50)
+            .build(dir.path())?;
+        let plp = ParallelLocusProcessorPileup::new(
+            PosDepthWorker {
+                fail_at: HashSet::new(),
+            },
+            4,
+            bam_path,
+        );
+        let inputs = || {
+            (1..=14_000)
+                .step_by(10)
+                .map(|pos| coord("chr1", pos))
+                .collect::<Vec<_>>()
+        };
+
+        let auto = plp.process_with_auto_batch(inputs(), 32)?;
+        assert_eq!(auto.len(), 1_400);
+        assert_eq!(auto, plp.process_with_batch(inputs(), 320)?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_empty_inputs() -> Result<(), Box<dyn std::error::Error>> {
+        use crate::utils::thread_budget::ExhaustedPolicy;
+
+        let dir = tempfile::tempdir()?;
+        let bam_path = TestBam::new()
+            .add_reads("chr1", 0, 1, 10, 50)
+            .build(dir.path())?;
+        // all of the budget taken, so a pool built would fail the run.
+        let budget = ThreadBudget::new(2).with_policy(ExhaustedPolicy::Error);
+        let _taken = budget.reserve(2)?;
+        let plp = ParallelLocusProcessorPileup::new(
+            PosDepthWorker {
+                fail_at: HashSet::new(),
+            },
+            2,
+            bam_path.clone(),
+        )
+        .with_thread_budget(&budget);
+
+        assert_eq!(plp.process_with_batch(vec![], 100)?, vec![]);
+        assert_eq!(
+            plp.process_and_fold(vec![], 100, || 7, |n, _| n + 1, |a, b| a + b)?,
+            7
+        );
+        let checkpoint_path = dir.path().join("run.ckpt");
+        let res = plp.process_with_batch_checkpointed(
+            vec![],
+            100,
+            &checkpoint_path,
+            1,
+            |_, _| Ok(()),
+            |_| bail!("nothing to decode"),
+        )?;
+        assert!(res.is_empty() && !checkpoint_path.exists());
+        let pool = ThreadPoolBuilder::new().num_threads(1).build()?;
+        let res = plp.process_with_batch_on(vec![], 100, Some(&pool))?;
+        assert!(res.is_empty());
+
+        let processor = MultiBamLocusProcessor::new(SampleDepthWorker, 2, vec![bam_path]);
+        assert!(processor.process_with_batch(vec![], 100)?.is_empty());
+        // the arguments are checked all the same.
+        let processor = MultiBamLocusProcessor::new(SampleDepthWorker, 2, vec![]);
+        assert!(processor.process_with_batch(vec![], 100).is_err());
+
+        Ok(())
+    }
+
+    #[cfg(feature = "liftover")]
+    #[test]
+    fn test_process_lifted_with_batch() -> Result<(), Box<dyn std::error::Error>> {
+        use crate::data::liftover::Lifted;
+
+        let dir = tempfile::tempdir()?;
+        // chr1:101-200 to chr1:1-110 with a gap after 50 bp, and chr2:1-100 to
+        // chr5:601-700, reversed.
+        let chain_path = dir.path().join("test.chain");
+        std::fs::write(
+            &chain_path,
+            "chain 1000 chr1 1000 + 100 200 chr1 1000 + 0 110 1\n50 10 20\n40\n\n\
+             chain 900 chr2 500 + 0 100 chr5 800 - 100 200 2\n100\n",
+        )?;
+        let liftover = ChainLiftover::from_path(&chain_path)?;
+        let bam_path = TestBam::new()
+            .add_read("chr1", 0, &[b'A'; 100], &[30; 100], 0)
+            .add_read("chr5", 600, &[b'A'; 100], &[30; 100], 0)
+            .add_read("chr5", 650, &[b'A'; 50], &[30; 50], 0)
+            .build(dir.path())?;
+        let plp = ParallelLocusProcessorPileup::new(
+            PreLifted(PosDepthWorker {
+                fail_at: HashSet::new(),
+            }),
+            2,
+            bam_path,
+        );
+
+        let inputs = [
+            ("chr1", 101),
+            ("chr2", 1),
+            ("chr1", 155),
+            ("chr2", 100),
+            ("chr1", 161),
+        ]
+        .into_iter()
+        .map(|(contig, pos)| coord(contig, pos))
+        .collect();
+        let outputs = plp.process_lifted_with_batch(inputs, &liftover, 100)?;
+
+        let lifted = |contig: &str, pos, strand_flipped| {
+            LiftResult::Mapped(Lifted {
+                locus: GenomeCoordinate::new(contig.to_string(), pos).unwrap(),
+                strand_flipped,
+            })
+        };
+        assert_eq!(
+            outputs.iter().map(|o| o.lift.clone()).collect::<Vec<_>>(),
+            [
+                lifted("chr1", 1, false),
+                lifted("chr5", 700, true),
+                LiftResult::Unmapped,
+                lifted("chr5", 601, true),
+                lifted("chr1", 71, false),
+            ]
+        );
+        // outputs of the worker, at the source positions.
+        assert_eq!(
+            outputs.iter().map(|o| o.output).collect::<Vec<_>>(),
+            [
+                Some((101, 1)),
+                Some((1, 2)),
+                None,
+                Some((100, 1)),
+                Some((161, 1))
+            ]
+        );
+
+        Ok(())
+    }
+
+    /// Sum of the base qualities and depth of the locus.
+    struct BqSumWorker;
+
+    impl<'a> BamLocusWorker<'a> for BqSumWorker {
+        type Output = (u64, u64);
+        type Input = GenomeCoordinate<'a>;
+        type Error = Error;
+
+        fn work_for_locus(
+            &self,
+            plp: Pileup,
+            _inp: Self::Input,
+        ) -> Result<Self::Output, Self::Error> {
+            Ok(plp.typed_alignments().fold((0, 0), |(bq_sum, depth), aln| {
+                (bq_sum + aln.base_qual.unwrap_or(0) as u64, depth + 1)
+            }))
+        }
+    }
+
+    #[test]
+    fn test_process_and_fold() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = tempfile::tempdir()?;
+        // reads of varying base qualities, 1-based 1..=100_046 covered.
+        let mut test_bam = TestBam::new();
+        for i in 0..14_286 {
+            let qual = (0..50).map(|j| ((i + j) % 41) as u8).collect::<Vec<_>>();
+            test_bam = test_bam.add_read("chr1", i as i64 * 7, &[b'A'; 50], &qual, 0);
+        }
+        let bam_path = test_bam.build(dir.path())?;
+        let plp = ParallelLocusProcessorPileup::new(BqSumWorker, 8, bam_path);
+        let inputs = || {
+            (1..=100_000)
+                .map(|pos| coord("chr1", pos))
+                .collect::<Vec<_>>()
+        };
+
+        let outputs = plp.process_with_batch(inputs(), 1_000)?;
+        let expected = outputs
+            .iter()
+            .fold((0, 0), |(bq, depth), out| (bq + out.0, depth + out.1));
+        let add = |a: (u64, u64), b: (u64, u64)| (a.0 + b.0, a.1 + b.1);
+        for window in [1_000, 7_777, 200_000] {
+            let folded = plp.process_and_fold(inputs(), window, || (0, 0), add, add)?;
+            assert_eq!(folded, expected);
+        }
+        let mean_bq = expected.0 as f64 / expected.1 as f64;
+        assert!(mean_bq > 15.0 && mean_bq < 25.0, "{mean_bq}");
+
+        // the same in every run for a given window, even for floats.
+        let mean_of_means = || {
+            plp.process_and_fold(
+                inputs(),
+                1_000,
+                || 0.0,
+                |acc, (bq, depth)| acc + bq as f64 / depth as f64,
+                |a, b| a + b,
+            )
+        };
+        assert_eq!(mean_of_means()?.to_bits(), mean_of_means()?.to_bits());
+
+        let hist = plp.process_and_histogram(inputs(), 1_000, |(_, depth)| *depth)?;
+        let mut expected_hist = HashMap::new();
+        for (_, depth) in &outputs {
+            *expected_hist.entry(*depth).or_insert(0) += 1;
+        }
+        assert_eq!(hist, expected_hist);
+        assert_eq!(hist.values().sum::<u64>(), 100_000);
+
+        Ok(())
+    }
+
+    #[derive(Debug, Clone, PartialEq)]
+    struct LocusStats {
+        pos: i64,
+        depth: u32,
+        mean_bq: f32,
+    }
+
+    crate::columnar_sink! {
+        struct StatsColumns for LocusStats {
+            pos: i64,
+            depth: u32,
+            mean_bq: f32,
+        }
+    }
+
+    /// [`LocusStats`] of [`BqSumWorker`].
+    struct StatsWorker;
+
+    impl<'a> BamLocusWorker<'a> for StatsWorker {
+        type Output = LocusStats;
+        type Input = GenomeCoordinate<'a>;
+        type Error = Error;
+
+        fn work_for_locus(&self, plp: Pileup, inp: Self::Input) -> Result<LocusStats, Error> {
+            let (bq_sum, depth) = BqSumWorker.work_for_locus(plp, inp.clone())?;
+            Ok(LocusStats {
+                pos: inp.pos,
+                depth: depth as u32,
+                mean_bq: bq_sum as f32 / depth as f32,
+            })
+        }
+    }
+
+    #[test]
+    fn test_process_into() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = tempfile::tempdir()?;
+        let bam_path = TestBam::new()
+            .add_reads("chr1", 0, 10, 2_000, 50)
+            .add_reads("chr2", 0, 10, 500, 50)
+            .build(dir.path())?;
+        let plp = ParallelLocusProcessorPileup::new(StatsWorker, 4, bam_path);
+        // with loci without coverage, past the reads of chr2.
+        let inputs = || {
+            let chr1 = (1..=20_000).map(|pos| coord("chr1", pos));
+            let chr2 = (1..=6_000).step_by(3).map(|pos| coord("chr2", pos));
+            chr1.chain(chr2).collect::<Vec<_>>()
+        };
+
+        let outputs = plp.process_with_batch(inputs(), 1_000)?;
+        assert_eq!(outputs.len(), 20_000 + 1_680);
+        let mut columns = StatsColumns::default();
+        plp.process_into(inputs(), 1_000, &mut columns)?;
+        assert_eq!(columns.len(), outputs.len());
+        assert_eq!(
+            columns.pos(),
+            outputs.iter().map(|o| o.pos).collect::<Vec<_>>()
+        );
+        assert_eq!(
+            columns.depth(),
+            outputs.iter().map(|o| o.depth).collect::<Vec<_>>()
+        );
+        assert_eq!(
+            columns.mean_bq(),
+            outputs.iter().map(|o| o.mean_bq).collect::<Vec<_>>()
+        );
+
+        // appended after the outputs already there.
+        plp.process_into(inputs()[..10].to_vec(), 1_000, &mut columns)?;
+        assert_eq!(columns.len(), outputs.len() + 10);
+        assert_eq!(
+            columns.pos()[outputs.len()..],
+            outputs[..10].iter().map(|o| o.pos).collect::<Vec<_>>()
+        );
+
+        let mut vec = vec![];
+        plp.process_into(inputs(), 7_777, &mut vec)?;
+        assert_eq!(vec, outputs);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_process_with_batch_returns_first_error() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = tempfile::tempdir()?;
+        let bam_path = TestBam::new()
+            .add_reads("chr1", 0, 10, 2_000, 50)
+            .build(dir.path())?;
+        let plp = ParallelLocusProcessorPileup::new(
+            PosDepthWorker {
+                fail_at: (1..20).map(|i| i * 1_000 + 500).collect(),
+            },
+            8,
+            bam_path,
+        );
+
+        for _ in 0..5 {
+            let inputs = (1..=20_000).map(|pos| coord("chr1", pos)).collect();
+            let err = plp.process_with_batch(inputs, 100).unwrap_err();
+            assert_eq!(
+                format!("{:#}", err),
+                "Failed to process locus chr1:1500: failed at 1500"
+            );
+            assert!(
+                matches!(&err, crate::Error::WorkerFailed { item, .. } if item == "locus chr1:1500"),
+                "{:?}",
+                err
+            );
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_process_with_batch_disordered_inputs() -> Result<(), Box<dyn std::error::Error>> {
+        use tracing_subscriber::layer::SubscriberExt;
+
+        let dir = tempfile::tempdir()?;
+        // 1-based 1..=1_040 covered.
+        let bam_path = TestBam::new()
+            .add_reads("chr1", 0, 10, 100, 50)
+            .build(dir.path())?;
+        let plp = ParallelLocusProcessorPileup::new(
+            PosDepthWorker {
+                fail_at: HashSet::new(),
+            },
+            4,
+            bam_path,
+        );
+
+        let positions = [500, 100, 2_000, 300, 301, 50, 1_040, 5];
+        let inputs = positions.iter().map(|&pos| coord("chr1", pos)).collect();
+        let res = plp.process_with_batch(inputs, 1_000)?;
+
+        // every covered input is processed, in input order; 2_000 has no coverage.
+        assert_eq!(
+            res.iter().map(|(pos, _)| *pos).collect::<Vec<_>>(),
+            vec![500, 100, 300, 301, 50, 1_040, 5]
+        );
+        assert!(res.iter().all(|(_, depth)| *depth > 0));
+
+        // sorted, with duplicates: each of them is processed, and only the
+        // inputs at 2_000 are warned of.
+        let positions = [100, 100, 300, 300, 300, 1_040, 1_040, 2_000, 2_000];
+        let inputs = positions.iter().map(|&pos| coord("chr1", pos)).collect();
+        let collector = SpanCollector::default();
+        let subscriber = tracing_subscriber::registry().with(collector.clone());
+        let res = tracing::subscriber::with_default(subscriber, || {
+            plp.process_with_batch(inputs, 10_000)
+        })?;
+        assert_eq!(
+            res.iter().map(|(pos, _)| *pos).collect::<Vec<_>>(),
+            vec![100, 100, 300, 300, 300, 1_040, 1_040]
+        );
+        assert_eq!(res[0], res[1]);
+        assert_eq!(res[2], res[4]);
+        let warns = collector.spans("warn");
+        assert_eq!(warns.len(), 1, "{warns:?}");
+        assert!(warns[0].contains("2 of 9 inputs"), "{warns:?}");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_contig_tids() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = tempfile::tempdir()?;
+        // contigs named without the `chr` prefix; depth 1 at 1-based 1..=50.
+        let bam_path = TestBam::new()
+            .contig("1", 10_000)
+            .contig("2", 10_000)
+            .add_reads("1", 0, 0, 1, 50)
+            .add_reads("2", 0, 0, 2, 50)
+            .build(dir.path())?;
+
+        let inputs = vec![
+            coord("chr1", 10),
+            coord("chr1", 40),
+            coord("chr2", 10),
+            coord("chr1", 20),
+        ];
+        let batches = batch_input_by_coordinate(inputs.clone(), 20);
+        let (batch_contigs, contigs) = batch_contig_indices(&batches);
+        assert_eq!(batch_contigs, vec![0, 0, 1, 0]);
+        assert_eq!(
+            contigs.iter().map(|c| c.as_str()).collect::<Vec<_>>(),
+            ["chr1", "chr2"]
+        );
+
+        let worker = PosDepthWorker {
+            fail_at: HashSet::new(),
+        };
+        let plp = ParallelLocusProcessorPileup::new(worker, 2, bam_path);
+        assert_eq!(
+            plp.process_with_batch(inputs, 20)?,
+            vec![(10, 1), (40, 1), (10, 2), (20, 1)]
+        );
+
+        let err = plp
+            .process_with_batch(vec![coord("chr1", 10), coord("chr9", 10)], 20)
+            .unwrap_err();
+        assert!(
+            format!("{:#}", err).contains("Contig chr9 is not in the bam header"),
+            "{:#}",
+            err
+        );
+
+        Ok(())
+    }
+
+    struct AltDepthWorker;
+
+    impl<'a> BamLocusWorker<'a> for AltDepthWorker {
+        type Input = Variant<'a>;
+        type Output = (String, u32);
+        type Error = Error;
+
+        fn work_for_locus(&self, plp: Pileup, input: Self::Input) -> Result<Self::Output, Error> {
+            Ok((input.alt_bases().to_string(), plp.depth()))
+        }
+    }
+
+    #[test]
+    fn test_variant_inputs() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = tempfile::tempdir()?;
+        let bam_path = TestBam::new()
+            .add_reads("chr1", 0, 10, 100, 50)
+            .build(dir.path())?;
+
+        let variants = vec![
+            Variant::new(Chrom::Chr1, 10, "A", "C"),
+            Variant::new(Chrom::Chr1, 11, "AT", "A"),
+            // another allele of the site at 11.
+            Variant::new(Chrom::Chr1, 11, "A", "G"),
+            Variant::new(Chrom::Chr1, 1_041, "G", "T"),
+        ];
+        let res = ParallelLocusProcessorPileup::new(AltDepthWorker, 2, bam_path)
+            .process_with_batch(variants, 100)?;
+        assert_eq!(
+            res,
+            vec![
+                ("C".to_string(), 1),
+                ("A".to_string(), 2),
+                ("G".to_string(), 2)
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_locus_processor_builder() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = tempfile::tempdir()?;
+        // 1-based 1..=1_040 covered, depth 1 at 1..=10.
+        let bam_path = TestBam::new()
+            .add_reads("chr1", 0, 10, 100, 50)
+            .build(dir.path())?;
+        let worker = || PosDepthWorker {
+            fail_at: HashSet::new(),
+        };
+
+        let plp = ParallelLocusProcessorPileup::builder(&bam_path)
+            .worker(worker())
+            .threads(2)
+            .build()?;
+        assert_eq!(plp.bam_path(), bam_path);
+        assert!(plp.worker().fail_at.is_empty());
+        let one_based = plp.process_with_batch(vec![coord("chr1", 10), coord("chr1", 11)], 100)?;
+        assert_eq!(one_based, vec![(10, 1), (11, 2)]);
+
+        let plp = ParallelLocusProcessorPileup::builder(&bam_path)
+            .worker(worker())
+            .coordinate_system(CoordinateSystem::ZeroBased)
+            .pileup_options(PileupOption {
+                max_depth: 1_000,
+                ignore_overlaps: false,
+            })
+            .build()?;
+        let zero_based = plp.process_with_batch(vec![coord("chr1", 9), coord("chr1", 10)], 100)?;
+        assert_eq!(zero_based, vec![(9, 1), (10, 2)]);
+
+        let no_worker = ParallelLocusProcessorPileup::<PosDepthWorker>::builder(&bam_path).build();
+        assert!(no_worker.is_err());
+        let no_thread = ParallelLocusProcessorPileup::builder(&bam_path)
+            .worker(worker())
+            .threads(0)
+            .build();
+        assert!(no_thread.is_err());
+
+        let err = ParallelLocusProcessorPileup::builder(dir.path().join("missing.bam"))
+            .worker(worker())
+            .build()
+            .err()
+            .unwrap();
+        assert!(matches!(err, crate::Error::Io { .. }), "{:?}", err);
+        assert!(format!("{:#}", err).ends_with("missing.bam does not exist"));
+
+        std::fs::remove_file(dir.path().join("test.bam.bai"))?;
+        let err = ParallelLocusProcessorPileup::builder(&bam_path)
+            .worker(worker())
+            .build()
+            .err()
+            .unwrap();
+        assert!(matches!(err, crate::Error::Io { .. }), "{:?}", err);
+        let err = format!("{:#}", err);
+        let bam = bam_path.display();
+        assert!(err.contains(&format!("{bam}.bai, {bam}.csi")), "{err}");
+
+        Ok(())
+    }
+
+    // Helper function to create coordinates for tests
+    fn coord<'a>(contig: &'a str, pos: i64) -> GenomeCoordinate<'a> {
+        GenomeCoordinate {
+            contig: Chrom::Other(Cow::Borrowed(contig)),
+            pos,
+        }
+    }
+
+    #[test]
+    fn test_standard_batching() {
+        let inputs = vec![
+            coord("chr1", 100),
+            coord("chr1", 200),
+            coord("chr1", 10000), // This should start a new batch
+            coord("chr1", 10100),
+        ];
+        let window_size = 1000;
+        let batches = batch_input_by_coordinate(inputs, window_size);
+
+        assert_eq!(batches.len(), 2);
+        assert_eq!(batches[0].len(), 2);
+        assert_eq!(batches[1].len(), 2);
+        assert_eq!(batches[0][0].pos, 100);
+        assert_eq!(batches[1][0].pos, 10000);
+    }
+
+    #[test]
+    fn test_contig_change() {
+        let inputs = vec![
+            coord("chr1", 100),
+            coord("chr1", 200),
+            coord("chr2", 300), // This must start a new batch
+            coord("chr2", 400),
+        ];
+        let window_size = 1000;
+        let batches = batch_input_by_coordinate(inputs, window_size);
+
+        assert_eq!(batches.len(), 2);
+        assert_eq!(batches[0].len(), 2);
+        assert_eq!(batches[1].len(), 2);
+        assert_eq!(batches[0][0].contig, Chrom::Other(Cow::Borrowed("chr1")));
+        assert_eq!(batches[1][0].contig, Chrom::Other(Cow::Borrowed("chr2")));
+    }
+
+    #[test]
+    fn test_window_size_boundary() {
+        let window_size = 1000;
+        let inputs = vec![
+            coord("chr1", 100),
+            coord("chr1", 1099), // gc.pos (1099) - c_start (100) = 999. This is < 1000, so it fits.
+            coord("chr1", 1100), // gc.pos (1100) - c_start (100) = 1000. This is NOT < 1000, new batch.
+        ];
+
+        let batches = batch_input_by_coordinate(inputs, window_size);
+
+        assert_eq!(batches.len(), 2);
+        assert_eq!(batches[0].len(), 2); // The first two items should be in the first batch
+        assert_eq!(batches[1].len(), 1); // The third item starts a new batch
+        assert_eq!(batches[1][0].pos, 1100);
+    }
+
+    #[test]
+    fn test_empty_input() {
+        let inputs: Vec<GenomeCoordinate> = vec![];
+        let window_size = 1000;
+        let batches = batch_input_by_coordinate(inputs, window_size);
+        assert!(batches.is_empty());
+    }
+
+    #[test]
+    fn test_single_input() {
+        let inputs = vec![coord("chr1", 100)];
+        let window_size = 1000;
+        let batches = batch_input_by_coordinate(inputs, window_size);
+        assert_eq!(batches.len(), 1);
+        assert_eq!(batches[0].len(), 1);
+        assert_eq!(batches[0][0].pos, 100);
+    }
+
+    #[test]
+    fn test_batch_indices() {
+        let inputs = vec![
+            coord("chr1", 100),
+            coord("chr1", 200),
+            coord("chr2", 300),
+            coord("chr2", 5000),
+            coord("chr2", 5100),
+        ];
+        assert_eq!(
+            batch_indices_by_coordinate(&inputs, 1000),
+            vec![0..2, 2..3, 3..5]
+        );
+        assert!(batch_indices_by_coordinate::<GenomeCoordinate>(&[], 1000).is_empty());
+    }
+
+    #[test]
+    fn test_disordered_batching() {
+        let inputs = vec![
+            coord("chr1", 100),
+            coord("chr1", 200),
+            coord("chr1", 150), // goes back, new batch
+            coord("chr1", 50),  // goes back again, new batch
+            coord("chr1", 300),
+            coord("chr1", 300),
+        ];
+        assert_eq!(
+            batch_indices_by_coordinate(&inputs, 1000),
+            vec![0..2, 2..3, 3..6]
+        );
+
+        let batches = batch_input_by_coordinate(inputs, 1000);
+        assert_eq!(batches.iter().map(|b| b.len()).sum::<usize>(), 6);
+        assert!(
+            batches
+                .iter()
+                .all(|b| b.windows(2).all(|w| w[0].pos <= w[1].pos))
+        );
+    }
+
+    #[test]
+    fn test_all_in_one_batch() {
+        let inputs = vec![coord("chr1", 100), coord("chr1", 200), coord("chr1", 300)];
+        let window_size = 1000;
+        let batches = batch_input_by_coordinate(inputs, window_size);
+        assert_eq!(batches.len(), 1);
+        assert_eq!(batches[0].len(), 3);
+    }
+
+    struct OnlyOddPosRecord {}
+
+    impl RecordModifier for OnlyOddPosRecord {
+        type Error = Error;
+
+        fn modify_record(&self, record: &mut bam::Record) -> Result<Option<()>, Self::Error> {
+            let r = if (record.pos()+1) % 2 == 0 {
+                None
+            } else {
+                Some(())
+            };
+
+            Ok(r)
+        }
+    }
+
+    #[test]
+    fn test_parallel_bam_processor() -> Result<(), Box<dyn std::error::Error>> {
+        let pbp = ParallelBamProcessor::new(OnlyOddPosRecord {});
+
+        let dir = tempfile::tempdir()?;
+        // 0-based positions 0, 3, 6, ..., so every other read is at an odd 1-based position.
+        let input_bam_path = TestBam::new()
+            .add_reads("chr20", 0, 3, 5_000, 50)
+            .build(dir.path())?;
+        let out_bam_path = dir.path().join("oddposonly.bam");
+        let opts = ProcessBamOptions {
+            read_threads: 1,
+            worker_threads: 2,
+            write_threads: 4,
+            batch_size: 1024,
+            channel_capacity: 128,
+            ..Default::default()
+        };
+
+        let stats = pbp.process_bam(&input_bam_path, &out_bam_path, &opts)?;
+        assert_eq!(stats.records_read, 5_000);
+        assert_eq!(stats.records_written, 2_500);
+        assert_eq!(stats.records_dropped, 2_500);
+        assert_eq!(
+            stats.to_string(),
+            "5.0 k records read, 2.5 k written, 2.5 k dropped, 0 failed"
+        );
+
+        let written = read_qnames_and_pos(&out_bam_path)?;
+        assert_eq!(written.len(), 2_500);
+        assert!(written.iter().all(|(_, pos)| (pos + 1) % 2 == 1));
+
+        Ok(())
+    }
+
+    #[cfg(feature = "metrics")]
+    #[test]
+    fn test_process_bam_metrics() -> Result<(), Box<dyn std::error::Error>> {
+        use crate::utils::metrics::global;
+
+        let dir = tempfile::tempdir()?;
+        let input_bam_path = TestBam::new()
+            .add_reads("chr20", 0, 3, 5_000, 50)
+            .build(dir.path())?;
+        // other tests may run processors at the same time, counting into the
+        // same counters.
+        let counters = ["read", "written", "dropped"].map(|what| {
+            global()
+                .counter(&format!("records_{}_total", what), &[])
+                .unwrap()
+        });
+        let before = counters.clone().map(|c| c.get());
+
+        ParallelBamProcessor::new(OnlyOddPosRecord {}).process_bam(
+            &input_bam_path,
+            dir.path().join("out.bam"),
+            &ProcessBamOptions::default(),
+        )?;
+
+        let added = [0, 1, 2].map(|i| counters[i].get() - before[i]);
+        assert!(added[0] >= 5_000, "{:?}", added);
+        assert!(added[1] >= 2_500 && added[2] >= 2_500, "{:?}", added);
+        let text = global().render();
+        assert!(
+            text.contains("# TYPE records_read_total counter\n"),
+            "{}",
+            text
+        );
+        assert!(
+            text.contains("channel_queue_depth{channel=\"reader->workers\"}"),
+            "{}",
+            text
+        );
+
+        Ok(())
+    }
+
+    /// Sleeps on some records, so that batches finish out of order.
+    struct UnevenRecord;
+
+    impl RecordModifier for UnevenRecord {
+        type Error = Error;
+
+        fn modify_record(&self, record: &mut bam::Record) -> Result<Option<()>, Self::Error> {
+            if record.pos() % 97 == 0 {
+                thread::sleep(Duration::from_micros(300));
+            }
+            Ok(Some(()))
+        }
+    }
+
+    #[test]
+    fn test_process_bam_reuses_batches() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = tempfile::tempdir()?;
+        let input_bam_path = TestBam::new()
+            .add_reads("chr1", 0, 1, 20_000, 50)
+            .build(dir.path())?;
+        let opts = ProcessBamOptions {
+            worker_threads: 8,
+            batch_size: 16,
+            channel_capacity: 4,
+            ..Default::default()
+        };
+
+        let stats = ParallelBamProcessor::new(UnevenRecord).process_bam(
+            &input_bam_path,
+            dir.path().join("out.bam"),
+            &opts,
+        )?;
+        assert_eq!(stats.records_written, 20_000);
+        // 1_250 batches, all of them in the ones allocated up front.
+        assert_eq!(stats.batch_pool.n_created, 4);
+
+        Ok(())
+    }
+
+    /// Replaces every record with a default one.
+    struct ResetRecord;
+
+    impl RecordModifier for ResetRecord {
+        type Error = Error;
+
+        fn modify_record(&self, record: &mut bam::Record) -> Result<Option<()>, Self::Error> {
+            *record = bam::Record::default();
+            Ok(Some(()))
+        }
+    }
+
+    #[test]
+    fn test_parallel_bam_processor_writes_default_like_records() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = tempfile::tempdir()?;
+        let input_bam_path = TestBam::new()
+            .add_reads("chr1", 0, 3, 100, 50)
+            .build(dir.path())?;
+        let out_bam_path = dir.path().join("out.bam");
+
+        let stats = ParallelBamProcessor::new(ResetRecord).process_bam(
+            &input_bam_path,
+            &out_bam_path,
+            &ProcessBamOptions::default(),
+        )?;
+        assert_eq!(stats.records_written, 100);
+        assert_eq!(stats.records_dropped, 0);
+
+        let mut reader = bam::Reader::from_path(&out_bam_path)?;
+        let mut n = 0;
+        for record in reader.records() {
+            assert_eq!(record?, bam::Record::default());
+            n += 1;
+        }
+        assert_eq!(n, 100);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_header_only_bam() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = tempfile::tempdir()?;
+        let input_bam_path = TestBam::new()
+            .contig("chr1", 1_000)
+            .contig("chr2", 500)
+            .build(dir.path())?;
+        let pbp = ParallelBamProcessor::new(ResetRecord);
+        let opts = ProcessBamOptions {
+            worker_threads: 2,
+            ..Default::default()
+        };
+
+        let parallel = dir.path().join("parallel.bam");
+        let stats = pbp.process_bam(&input_bam_path, &parallel, &opts)?;
+        assert_eq!((stats.records_read, stats.records_written), (0, 0));
+        assert_eq!(stats.read_channel.n_sent, 0);
+        let sequential = dir.path().join("sequential.bam");
+        let stats = pbp.process_bam_sequential(&input_bam_path, &sequential, &opts)?;
+        assert_eq!((stats.records_read, stats.records_written), (0, 0));
+
+        for out_bam_path in [&parallel, &sequential] {
+            let mut reader = bam::Reader::from_path(out_bam_path)?;
+            let header = reader.header();
+            assert_eq!(header.target_names(), [b"chr1".as_slice(), b"chr2"]);
+            assert_eq!(
+                (header.target_len(0), header.target_len(1)),
+                (Some(1_000), Some(500))
+            );
+            assert_eq!(reader.records().count(), 0);
+        }
+
+        Ok(())
+    }
+
+    /// Counts its calls on each thread, in a state reporting the count once
+    /// dropped; fails to initialize if `fail_init`.
+    struct CountingWorker {
+        fail_init: bool,
+        n_inits: AtomicU64,
+        counts: Arc<Mutex<Vec<u64>>>,
+    }
+
+    struct CallCount {
+        n_calls: u64,
+        counts: Arc<Mutex<Vec<u64>>>,
+    }
+
+    impl Drop for CallCount {
+        fn drop(&mut self) {
+            self.counts.lock().unwrap().push(self.n_calls);
+        }
+    }
+
+    impl<'a> BamLocusWorker<'a> for CountingWorker {
+        type Input = GenomeCoordinate<'a>;
+        type Output = i64;
+        type Error = Error;
+
+        fn work_for_locus(&self, _plp: Pileup, _inp: Self::Input) -> Result<i64, Error> {
+            bail!("only run with a state")
+        }
+    }
+
+    impl<'a> LocusWorkerInit<'a> for CountingWorker {
+        type ThreadState = CallCount;
+
+        fn init_thread(&self) -> Result<CallCount, Error> {
+            self.n_inits.fetch_add(1, atomic::Ordering::Relaxed);
+            if self.fail_init {
+                bail!("no model");
+            }
+            Ok(CallCount {
+                n_calls: 0,
+                counts: self.counts.clone(),
+            })
+        }
+
+        fn work_for_locus_with_state(
+            &self,
+            state: &mut CallCount,
+            _plp: Pileup,
+            inp: Self::Input,
+        ) -> Result<i64, Error> {
+            state.n_calls += 1;
+            Ok(inp.pos)
+        }
+    }
+
+    #[test]
+    fn test_process_with_thread_state() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = tempfile::tempdir()?;
+        let bam_path = TestBam::new()
+            .add_reads("chr1", 0, 10, 1_000, 50)
+            .build(dir.path())?;
+        let inputs = || {
+            (1..=2_000)
+                .map(|pos| coord("chr1", pos))
+                .collect::<Vec<_>>()
+        };
+        let new_worker = |fail_init| CountingWorker {
+            fail_init,
+            n_inits: AtomicU64::new(0),
+            counts: Arc::default(),
+        };
+
+        let plp = ParallelLocusProcessorPileup::new(new_worker(false), 4, bam_path.clone());
+        let res = plp.process_with_thread_state(inputs(), 100)?;
+        assert_eq!(res, (1..=2_000).collect::<Vec<_>>());
+        let worker = plp.worker();
+        // a state per thread, each used by the calls on its thread only.
+        assert_eq!(worker.n_inits.load(atomic::Ordering::Relaxed), 4);
+        let counts = worker.counts.lock().unwrap().clone();
+        assert_eq!(counts.len(), 4);
+        assert_eq!(counts.iter().sum::<u64>(), 2_000);
+
+        // no batch runs once a thread fails to initialize.
+        let plp = ParallelLocusProcessorPileup::new(new_worker(true), 4, bam_path);
+        let err = plp.process_with_thread_state(inputs(), 100).unwrap_err();
+        assert!(format!("{:#}", err).contains("no model"), "{:#}", err);
+        assert_eq!(plp.worker().n_inits.load(atomic::Ordering::Relaxed), 4);
+        assert!(plp.worker().counts.lock().unwrap().is_empty());
+
+        // a worker without state, as one of no state.
+        let plp = ParallelLocusProcessorPileup::new(
+            Stateless(PosDepthWorker {
+                fail_at: HashSet::new(),
+            }),
+            2,
+            plp.bam_path().to_path_buf(),
+        );
+        let res = plp.process_with_thread_state(vec![coord("chr1", 5)], 100)?;
+        assert_eq!(res, [(5, 1)]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_batches_at_contig_ends() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = tempfile::tempdir()?;
+        // chr1 covered to its end, 1-based 1..=1_000.
+        let bam_path = TestBam::new()
+            .contig("chr1", 1_000)
+            .add_reads("chr1", 0, 10, 100, 10)
+            .build(dir.path())?;
+        let run = |coordinate_system, positions: &[i64]| {
+            ParallelLocusProcessorPileup::builder(&bam_path)
+                .worker(PosDepthWorker {
+                    fail_at: HashSet::new(),
+                })
+                .threads(2)
+                .coordinate_system(coordinate_system)
+                .build()?
+                .process_with_batch(
+                    positions.iter().map(|&pos| coord("chr1", pos)).collect(),
+                    100,
+                )
+        };
+
+        // the batch reaching before the start, or past the end, is clamped.
+        let res = run(CoordinateSystem::OneBased, &[0, 1, 1_000, 1_050])?;
+        assert_eq!(res, [(1, 1), (1_000, 1)]);
+        let res = run(CoordinateSystem::OneBased, &[990, 1_000, 1_050])?;
+        assert_eq!(res, [(990, 1), (1_000, 1)]);
+        let res = run(CoordinateSystem::ZeroBased, &[0, 999])?;
+        assert_eq!(res, [(0, 1), (999, 1)]);
+
+        // a batch wholly past the end fails, naming its inputs.
+        for (coordinate_system, positions) in [
+            (CoordinateSystem::OneBased, [1_001, 1_050]),
+            (CoordinateSystem::ZeroBased, [1_000, 1_049]),
+        ] {
+            let err = run(coordinate_system, &positions).unwrap_err();
+            assert_eq!(
+                err.to_string(),
+                "Inputs chr1:1001, chr1:1050 lie past the end of chr1, of 1000 bp"
+            );
+            let crate::Error::OutOfContig {
+                contig,
+                len,
+                coordinates,
+            } = err
+            else {
+                panic!("{:#}", err);
+            };
+            assert_eq!((contig.as_str(), len), ("chr1", 1_000));
+            assert_eq!(coordinates, ["chr1:1001", "chr1:1050"]);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_dict_check() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = tempfile::tempdir()?;
+        let bam_path = TestBam::new()
+            .contig("chr1", 1_000)
+            .add_reads("chr1", 0, 1, 10, 50)
+            .build(dir.path())?;
+        let worker = || PosDepthWorker {
+            fail_at: HashSet::new(),
+        };
+        let inputs = || vec![coord("chr1", 5), coord("chr1", 1_001)];
+
+        // unchecked by default, the input past the end of chr1 failing its batch.
+        let plp = ParallelLocusProcessorPileup::new(worker(), 2, bam_path.clone());
+        let err = plp.process_with_batch(inputs(), 100).unwrap_err();
+        assert!(matches!(err, crate::Error::OutOfContig { .. }), "{:#}", err);
+        let plp = plp.with_dict_check(DictCheck::Warn);
+        let err = plp.process_with_batch(inputs(), 100).unwrap_err();
+        assert!(matches!(err, crate::Error::OutOfContig { .. }), "{:#}", err);
+        let plp = plp.with_dict_check(DictCheck::Strict);
+        let err = plp.process_with_batch(inputs(), 100).unwrap_err();
+        assert_eq!(
+            format!("{:#}", err),
+            "Input 1 at chr1:1001 is out of its contig, of 1000 bp"
+        );
+        let res = plp.process_with_batch(vec![coord("chr1", 5)], 100)?;
+        assert_eq!(res.len(), 1);
+
+        // the header is checked against the dictionary of the options.
+        let pbp = ParallelBamProcessor::new(ResetRecord);
+        let out_bam_path = dir.path().join("out.bam");
+        let reference_dict = SeqDict::new([SeqEntry {
+            contig: Chrom::Chr1,
+            len: 2_000,
+            md5: None,
+        }]);
+        let opts = ProcessBamOptions {
+            reference_dict: Some(reference_dict),
+            ..Default::default()
+        };
+        let err = pbp
+            .process_bam(&bam_path, &out_bam_path, &opts)
+            .unwrap_err();
+        assert!(
+            format!("{:#}", err).contains("lengths of chr1 (2000 vs 1000)"),
+            "{:#}",
+            err
+        );
+        let err = pbp
+            .process_bam_sequential(&bam_path, &out_bam_path, &opts)
+            .unwrap_err();
+        assert!(format!("{:#}", err).contains("does not match"), "{:#}", err);
+        let opts = ProcessBamOptions {
+            dict_check: DictCheck::Warn,
+            ..opts
+        };
+        let stats = pbp.process_bam(&bam_path, &out_bam_path, &opts)?;
+        assert_eq!(stats.records_written, 10);
+
+        Ok(())
+    }
+
+    /// Zeroes the mapq, then fails on the given reads.
+    struct FailOnQnames(HashSet<&'static [u8]>);
+
+    impl RecordModifier for FailOnQnames {
+        type Error = Error;
+
+        fn modify_record(&self, record: &mut bam::Record) -> Result<Option<()>, Self::Error> {
+            record.set_mapq(0);
+            if self.0.contains(record.qname()) {
+                bail!("bad read");
+            }
+            Ok(Some(()))
+        }
+    }
+
+    fn fail_on_read3_and_read7() -> ParallelBamProcessor<FailOnQnames> {
+        ParallelBamProcessor::new(FailOnQnames(HashSet::from([
+            b"read3".as_slice(),
+            b"read7".as_slice(),
+        ])))
+    }
+
+    #[test]
+    fn test_on_modify_error_skip() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = tempfile::tempdir()?;
+        let input_bam_path = TestBam::new()
+            .add_reads("chr1", 0, 3, 100, 50)
+            .build(dir.path())?;
+        let out_bam_path = dir.path().join("out.bam");
+
+        let stats = fail_on_read3_and_read7().process_bam(
+            &input_bam_path,
+            &out_bam_path,
+            &ProcessBamOptions::default(),
+        )?;
+        assert_eq!(stats.records_read, 100);
+        assert_eq!(stats.records_written, 98);
+        assert_eq!(stats.records_failed, 2);
+
+        let written = read_qnames_and_pos(&out_bam_path)?;
+        assert!(
+            !written
+                .iter()
+                .any(|(qname, _)| qname == b"read3" || qname == b"read7")
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_on_modify_error_fail() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = tempfile::tempdir()?;
+        let input_bam_path = TestBam::new()
+            .add_reads("chr1", 0, 3, 100, 50)
+            .build(dir.path())?;
+        let out_bam_path = dir.path().join("out.bam");
+        let opts = ProcessBamOptions {
+            on_modify_error: OnModifyError::Fail,
+            ..Default::default()
+        };
+
+        let pbp = fail_on_read3_and_read7();
+        let err = pbp
+            .process_bam(&input_bam_path, &out_bam_path, &opts)
+            .unwrap_err();
+        let msg = format!("{:#}", err);
+        assert!(msg.contains("worker"), "{}", msg);
+        assert!(
+            msg.contains("Failed to process read read3 at chr1:10: bad read")
+                || msg.contains("Failed to process read read7 at chr1:22: bad read"),
+            "{}",
+            msg
+        );
+
+        let err = pbp
+            .process_bam_sequential(&input_bam_path, &out_bam_path, &opts)
+            .unwrap_err();
+        assert_eq!(
+            format!("{:#}", err),
+            "Failed to process read read3 at chr1:10: bad read"
+        );
+
+        Ok(())
+    }
+
+    /// Panics on `read7`.
+    struct PanicOnRead7;
+
+    impl RecordModifier for PanicOnRead7 {
+        type Error = Error;
+
+        fn modify_record(&self, record: &mut bam::Record) -> Result<Option<()>, Self::Error> {
+            if record.qname() == b"read7" {
+                panic!("unexpected {}", String::from_utf8_lossy(record.qname()));
+            }
+            Ok(Some(()))
+        }
+    }
+
+    #[test]
+    fn test_modifier_panic() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = tempfile::tempdir()?;
+        let input_bam_path = TestBam::new()
+            .add_reads("chr1", 0, 3, 100, 50)
+            .build(dir.path())?;
+        let out_bam_path = dir.path().join("out.bam");
+        let pbp = ParallelBamProcessor::new(PanicOnRead7);
+
+        let stats = pbp.process_bam(
+            &input_bam_path,
+            &out_bam_path,
+            &ProcessBamOptions::default(),
+        )?;
+        assert_eq!((stats.records_written, stats.records_failed), (99, 1));
+
+        let opts = ProcessBamOptions {
+            on_modify_error: OnModifyError::Fail,
+            ..Default::default()
+        };
+        let err = pbp
+            .process_bam_sequential(&input_bam_path, &out_bam_path, &opts)
+            .unwrap_err();
+        assert_eq!(
+            format!("{:#}", err),
+            "Failed to process read read7 at chr1:22: panicked: unexpected read7"
+        );
+        let crate::Error::WorkerFailed { source, .. } = &err else {
+            panic!("{:?}", err);
+        };
+        assert!(source.is::<Panicked>());
+
+        let err = pbp
+            .process_bam(&input_bam_path, &out_bam_path, &opts)
+            .unwrap_err();
+        let msg = format!("{:#}", err);
+        assert!(
+            msg.contains("read read7 at chr1:22: panicked: unexpected read7"),
+            "{}",
+            msg
+        );
+
+        Ok(())
+    }
+
+    /// Panics at the given 1-based position.
+    struct PanicAt(i64);
+
+    impl<'a> BamLocusWorker<'a> for PanicAt {
+        type Output = i64;
+        type Input = GenomeCoordinate<'a>;
+        type Error = Error;
+
+        fn work_for_locus(&self, _plp: Pileup, inp: Self::Input) -> Result<i64, Self::Error> {
+            assert_ne!(inp.pos, self.0, "bad locus");
+            Ok(inp.pos)
+        }
+    }
+
+    #[test]
+    fn test_locus_worker_panic() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = tempfile::tempdir()?;
+        let bam_path = TestBam::new()
+            .add_reads("chr1", 0, 10, 200, 50)
+            .build(dir.path())?;
+        let plp = ParallelLocusProcessorPileup::new(PanicAt(1_500), 4, bam_path);
+
+        let inputs = (1..=2_000).map(|pos| coord("chr1", pos)).collect();
+        let err = plp.process_with_batch(inputs, 100).unwrap_err();
+        let msg = format!("{:#}", err);
+        assert!(
+            msg.starts_with("Failed to process locus chr1:1500: panicked: ")
+                && msg.contains("bad locus"),
+            "{}",
+            msg
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_failed_run_leaves_no_output() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = tempfile::tempdir()?;
+        let input_bam_path = TestBam::new()
+            .add_reads("chr1", 0, 3, 3_000, 50)
+            .build(dir.path())?;
+        let out_bam_path = dir.path().join("out.bam");
+        let opts = ProcessBamOptions {
+            on_modify_error: OnModifyError::Fail,
+            batch_size: 16,
+            fsync: true,
+            ..Default::default()
+        };
+        let files = || -> std::io::Result<Vec<_>> {
+            let mut names = std::fs::read_dir(dir.path())?
+                .map(|e| e.map(|e| e.file_name().to_string_lossy().into_owned()))
+                .collect::<Result<Vec<_>, _>>()?;
+            names.sort();
+            Ok(names)
+        };
+
+        let pbp = fail_on_read3_and_read7();
+        for sequential in [false, true] {
+            let res = match sequential {
+                false => pbp.process_bam(&input_bam_path, &out_bam_path, &opts),
+                true => pbp.process_bam_sequential(&input_bam_path, &out_bam_path, &opts),
+            };
+            assert!(res.is_err());
+            assert_eq!(files()?, ["test.bam", "test.bam.bai"]);
+        }
+
+        // a complete output of an earlier run is kept as it is.
+        ParallelBamProcessor::new(FailOnQnames(HashSet::new())).process_bam(
+            &input_bam_path,
+            &out_bam_path,
+            &opts,
+        )?;
+        assert!(
+            pbp.process_bam(&input_bam_path, &out_bam_path, &opts)
+                .is_err()
+        );
+        assert_eq!(files()?, ["out.bam", "test.bam", "test.bam.bai"]);
+        assert_eq!(read_qnames_and_pos(&out_bam_path)?.len(), 3_000);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_on_modify_error_dead_letter() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = tempfile::tempdir()?;
+        let input_bam_path = TestBam::new()
+            .add_reads("chr1", 0, 3, 100, 50)
+            .build(dir.path())?;
+        let out_bam_path = dir.path().join("out.bam");
+
+        for (i, sequential) in [false, true].into_iter().enumerate() {
+            let dead_letter_path = dir.path().join(format!("dead_letter{}.bam", i));
+            let opts = ProcessBamOptions {
+                on_modify_error: OnModifyError::DeadLetter(dead_letter_path.clone()),
+                ..Default::default()
+            };
+
+            let pbp = fail_on_read3_and_read7();
+            let stats = if sequential {
+                pbp.process_bam_sequential(&input_bam_path, &out_bam_path, &opts)?
+            } else {
+                pbp.process_bam(&input_bam_path, &out_bam_path, &opts)?
+            };
+            assert_eq!(stats.records_written, 98);
+            assert_eq!(stats.records_failed, 2);
+
+            // the records as read, before the modifier zeroed the mapq.
+            let mut reader = bam::Reader::from_path(&dead_letter_path)?;
+            assert_eq!(reader.header().target_names(), vec![b"chr1".as_slice()]);
+            let mut dead = vec![];
+            for record in reader.records() {
+                let record = record?;
+                assert_eq!(record.mapq(), 60);
+                dead.push((record.qname().to_vec(), record.pos()));
+            }
+            dead.sort();
+            assert_eq!(dead, vec![(b"read3".to_vec(), 9), (b"read7".to_vec(), 21)]);
+        }
+
+        // no failure, no file.
+        let dead_letter_path = dir.path().join("unused.bam");
+        let opts = ProcessBamOptions {
+            on_modify_error: OnModifyError::DeadLetter(dead_letter_path.clone()),
+            ..Default::default()
+        };
+        ParallelBamProcessor::new(FailOnQnames(HashSet::new())).process_bam(
+            &input_bam_path,
+            &out_bam_path,
+            &opts,
+        )?;
+        assert!(!dead_letter_path.exists());
+
+        Ok(())
+    }
+
+    /// Drops reads running past the end of their contig.
+    struct RejectPastContigEnd;
+
+    impl RecordModifier for RejectPastContigEnd {
+        type Error = Error;
+
+        fn modify_record(&self, _record: &mut bam::Record) -> Result<Option<()>, Self::Error> {
+            bail!("needs the context")
+        }
+
+        fn modify_record_ctx(
+            &self,
+            ctx: &ProcessContext,
+            record: &mut bam::Record,
+        ) -> Result<Option<()>, Self::Error> {
+            let contig_len = ctx
+                .target_len(record.tid())
+                .with_context(|| format!("Unknown tid {}", record.tid()))?;
+            if record.cigar().end_pos() as u64 > contig_len {
+                return Ok(None);
+            }
+            Ok(Some(()))
+        }
+    }
+
+    #[test]
+    fn test_modifier_uses_context() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = tempfile::tempdir()?;
+        // reads at 0, 10, .., 90 of 20 bases; the ones at 80 and 90 end past 95.
+        let input_bam_path = TestBam::new()
+            .contig("chr1", 95)
+            .add_reads("chr1", 0, 10, 10, 20)
+            .build(dir.path())?;
+        let out_bam_path = dir.path().join("out.bam");
+
+        let pbp = ParallelBamProcessor::new(RejectPastContigEnd);
+        let opts = ProcessBamOptions::default();
+        let par_stats = pbp.process_bam(&input_bam_path, &out_bam_path, &opts)?;
+        let seq_stats = pbp.process_bam_sequential(&input_bam_path, &out_bam_path, &opts)?;
+
+        for stats in [par_stats, seq_stats] {
+            assert_eq!(stats.records_written, 8);
+            assert_eq!(stats.records_dropped, 2);
+            assert_eq!(stats.records_failed, 0);
+        }
+
+        Ok(())
+    }
+
+    struct CountingModifier {
+        n_called: AtomicUsize,
+    }
+
+    impl RecordModifier for CountingModifier {
+        type Error = Error;
+
+        fn modify_record(&self, _record: &mut bam::Record) -> Result<Option<()>, Self::Error> {
+            self.n_called.fetch_add(1, atomic::Ordering::Relaxed);
+            Ok(Some(()))
+        }
+    }
+
+    #[test]
+    fn test_parallel_bam_processor_stops_on_writer_failure() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = tempfile::tempdir()?;
+        let n_reads = 10_000;
+        let input_bam_path = TestBam::new()
+            .add_reads("chr1", 0, 10, n_reads, 50)
+            .build(dir.path())?;
+        // the writer can not create a file in a directory which does not exist.
+        let out_bam_path = dir.path().join("no_such_dir").join("out.bam");
+
+        let pbp = ParallelBamProcessor::new(CountingModifier {
+            n_called: AtomicUsize::new(0),
+        });
+        let opts = ProcessBamOptions {
+            worker_threads: 2,
+            write_threads: 1,
+            batch_size: 16,
+            channel_capacity: 4,
+            ..Default::default()
+        };
+
+        let timer = Instant::now();
+        let err = pbp
+            .process_bam(&input_bam_path, &out_bam_path, &opts)
+            .unwrap_err();
+
+        assert!(timer.elapsed() < Duration::from_secs(30));
+        assert!(format!("{:#}", err).contains("writer thread failed"), "{:#}", err);
+        assert!(pbp.record_modifier.n_called.load(atomic::Ordering::Relaxed) < n_reads);
+
+        Ok(())
+    }
+
+    /// Steps its script at the index of each record, `i` of `read{i}`.
+    struct ScriptedModifier(Script);
+
+    impl RecordModifier for ScriptedModifier {
+        type Error = Error;
+
+        fn modify_record(&self, record: &mut bam::Record) -> Result<Option<()>, Self::Error> {
+            let i = std::str::from_utf8(&record.qname()[4..])?.parse()?;
+            self.0.step("modifier", i)?;
+            Ok(Some(()))
+        }
+    }
+
+    #[test]
+    fn test_run_pipeline_scripted_failures() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = tempfile::tempdir()?;
+        let header = b"@HD\tVN:1.6\tSO:coordinate\n@SQ\tSN:chr1\tLN:1000000\n";
+        let cigar = bam::record::CigarString::try_from("50M")?;
+        let records = (0..5_000)
+            .map(|i| {
+                let mut record = bam::Record::new();
+                let qname = format!("read{}", i);
+                record.set(qname.as_bytes(), Some(&cigar), &[b'A'; 50], &[30; 50]);
+                record.set_tid(0);
+                record.set_pos(i * 10);
+                HeaderlessRecord::strip(record)
+            })
+            .collect::<Vec<_>>();
+        let out_bam_path = dir.path().join("out.bam");
+        let output = OutputTarget::File(out_bam_path.clone());
+        let opts = ProcessBamOptions {
+            on_modify_error: OnModifyError::Fail,
+            batch_size: 32,
+            channel_capacity: 4,
+            ..Default::default()
+        };
+        let run = |kit: &PipelineKit, reader: Script, modifier: Script| {
+            ParallelBamProcessor::new(ScriptedModifier(modifier)).run_pipeline(
+                header,
+                kit.items_from(records.clone(), reader),
+                &output,
+                &opts,
+                [3, 1],
+                &PipelineControl::default(),
+            )
+        };
+
+        // the reader fails mid-input.
+        let kit = PipelineKit::new();
+        let err = run(&kit, Script::new().fail_at(3_000), Script::new()).unwrap_err();
+        assert_failed_in(&err, "reader", "reader failed at 3000");
+        kit.assert_shut_down();
+        assert!(!out_bam_path.exists());
+
+        // a worker fails on a record after a hang, while the others run ahead.
+        let kit = PipelineKit::new();
+        let modifier = Script::new()
+            .hang_at(1_000, Duration::from_millis(50))
+            .fail_at(1_000);
+        let err = run(&kit, Script::new(), modifier).unwrap_err();
+        let msg = format!("{:#}", err);
+        assert!(
+            msg.starts_with("worker ") && msg.ends_with("modifier failed at 1000"),
+            "{}",
+            msg
+        );
+        kit.assert_shut_down();
+        assert!(!out_bam_path.exists());
+
+        // hangs of the reader and a worker, and all records written in order.
+        let kit = PipelineKit::new();
+        let hang = |i| Script::new().hang_at(i, Duration::from_millis(20));
+        let stats = run(&kit, hang(100), hang(2_500))?;
+        kit.assert_shut_down();
+        assert_eq!(stats.records_written, 5_000);
+        assert_eq!(stats.batch_pool.n_created, 4);
+        let written = read_qnames_and_pos(&out_bam_path)?;
+        assert_eq!(written.len(), 5_000);
+        assert!(
+            written
+                .iter()
+                .enumerate()
+                .all(|(i, (_, pos))| *pos == i as i64 * 10)
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_parallel_bam_processor_small_file_is_fast() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = tempfile::tempdir()?;
+        let input_bam_path = TestBam::new()
+            .add_reads("chr1", 0, 10, 1_000, 50)
+            .build(dir.path())?;
+
+        let pbp = ParallelBamProcessor::new(CountingModifier {
+            n_called: AtomicUsize::new(0),
+        });
+        let timer = Instant::now();
+        let stats = pbp.process_bam(
+            &input_bam_path,
+            dir.path().join("out.bam"),
+            &ProcessBamOptions::default(),
+        )?;
+
+        assert_eq!(stats.records_written, 1_000);
+        // the stages block on their channels, with no fixed wait.
+        assert!(
+            timer.elapsed() < Duration::from_millis(500),
+            "{:?}",
+            timer.elapsed()
+        );
+
+        Ok(())
+    }
+
+    /// Checks the threads of its pool and those left of the budget.
+    struct BudgetProbeWorker(ThreadBudget);
+
+    impl<'a> BamLocusWorker<'a> for BudgetProbeWorker {
+        type Input = GenomeCoordinate<'a>;
+        type Output = ();
+        type Error = Error;
+
+        fn work_for_locus(&self, _plp: Pileup, _input: Self::Input) -> Result<(), Error> {
+            if rayon::current_num_threads() != 2 || self.0.available() > 2 {
+                bail!("Running over the threads reserved");
+            }
+            Ok(())
+        }
+    }
+
+    struct BudgetProbeModifier(ThreadBudget);
+
+    impl RecordModifier for BudgetProbeModifier {
+        type Error = Error;
+
+        fn modify_record(&self, _record: &mut bam::Record) -> Result<Option<()>, Self::Error> {
+            if self.0.available() > 1 {
+                bail!("Running over the threads reserved");
+            }
+            Ok(Some(()))
+        }
+    }
+
+    #[test]
+    fn test_thread_budget() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = tempfile::tempdir()?;
+        let bam_path = TestBam::new()
+            .add_reads("chr1", 0, 10, 2_000, 50)
+            .build(dir.path())?;
+        let out_bam_path = dir.path().join("out.bam");
+        let budget = ThreadBudget::new(4);
+
+        // 2 threads for the loci and 3 for the records, so one waits for the other.
+        let loci = ParallelLocusProcessorPileup::new(
+            BudgetProbeWorker(budget.clone()),
+            2,
+            bam_path.clone(),
+        )
+        .with_thread_budget(&budget);
+        let records = ParallelBamProcessor::new(BudgetProbeModifier(budget.clone()))
+            .with_thread_budget(&budget);
+        let opts = ProcessBamOptions {
+            read_threads: 1,
+            worker_threads: 1,
+            write_threads: 1,
+            ..Default::default()
+        };
+        let inputs = (1..20_000)
+            .step_by(100)
+            .map(|pos| GenomeCoordinate {
+                contig: Chrom::Chr1,
+                pos,
+            })
+            .collect::<Vec<_>>();
+
+        let (outputs, stats) = thread::scope(|s| {
+            let outputs = s.spawn(|| loci.process_with_batch(inputs, 1_000));
+            let stats = records.process_bam(&bam_path, &out_bam_path, &opts);
+            (outputs.join().unwrap(), stats)
+        });
+        assert_eq!(outputs?.len(), 200);
+        assert_eq!(stats?.records_written, 2_000);
+        assert_eq!(budget.available(), 4);
+
+        // stages cut down to the budget, the one with the most threads first.
+        let opts = ProcessBamOptions {
+            read_threads: 1,
+            worker_threads: 4,
+            write_threads: 4,
+            ..Default::default()
+        };
+        let (threads, guard) = budgeted_stage_threads(&opts, Some(&budget))?;
+        assert_eq!((threads, guard.unwrap().n_threads()), ([1, 2, 1], 4));
+        assert!(budgeted_stage_threads(&opts, Some(&ThreadBudget::new(2))).is_err());
+
+        let (tp, guard) = budgeted_pool(8, Some(&budget))?;
+        assert_eq!((tp.current_num_threads(), budget.available()), (4, 0));
+        drop(guard);
+        assert_eq!(budget.available(), 4);
+
+        Ok(())
+    }
+
+    struct SlowModifier;
+
+    impl RecordModifier for SlowModifier {
+        type Error = Error;
+
+        fn modify_record(&self, _record: &mut bam::Record) -> Result<Option<()>, Self::Error> {
+            thread::sleep(Duration::from_micros(200));
+            Ok(Some(()))
+        }
+    }
+
+    #[test]
+    fn test_channel_stats() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = tempfile::tempdir()?;
+        let bam_path = TestBam::new()
+            .add_reads("chr1", 0, 10, 2_000, 50)
+            .build(dir.path())?;
+        let opts = ProcessBamOptions {
+            worker_threads: 1,
+            batch_size: 20,
+            channel_capacity: 4,
+            metrics_interval: Duration::from_millis(50),
+            ..Default::default()
+        };
+
+        // the worker is the bottleneck, holding the reader back.
+        let stats = with_debug_tracing(|| {
+            ParallelBamProcessor::new(SlowModifier).process_bam(
+                &bam_path,
+                dir.path().join("out.bam"),
+                &opts,
+            )
+        })?;
+        assert_eq!(stats.records_written, 2_000);
+        let (read, write) = (stats.read_channel, stats.write_channel);
+        assert_eq!((read.n_sent, write.n_sent), (100, 100));
+        assert!(read.full_fraction() > 0.5, "{:?}", read);
+        assert!(write.full_fraction() < 0.2, "{:?}", write);
+
+        Ok(())
+    }
+
+    fn read_qnames_and_pos(bam_path: &Path) -> Result<Vec<(Vec<u8>, i64)>, Error> {
+        let mut reader = bam::Reader::from_path(bam_path)?;
+        let mut res = vec![];
+        for r in reader.records() {
+            let r = r?;
+            res.push((r.qname().to_vec(), r.pos()));
+        }
+        Ok(res)
+    }
+
+    #[test]
+    fn test_sequential_matches_parallel() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = tempfile::tempdir()?;
+        let input_bam_path = TestBam::new()
+            .contig("chr1", 10_000)
+            .add_reads("chr1", 0, 3, 2_000, 50)
+            .add_reads("chr2", 100, 7, 500, 50)
+            .build(dir.path())?;
+
+        let pbp = ParallelBamProcessor::new(OnlyOddPosRecord {});
+        let opts = ProcessBamOptions {
+            worker_threads: 3,
+            batch_size: 64,
+            channel_capacity: 8,
+            ..Default::default()
+        };
+
+        let par_out = dir.path().join("par.bam");
+        let seq_out = dir.path().join("seq.bam");
+        let par_stats = pbp.process_bam(&input_bam_path, &par_out, &opts)?;
+        let seq_stats = pbp.process_bam_sequential(&input_bam_path, &seq_out, &opts)?;
+
+        // batches are only pooled, and channels only metered, by the parallel
+        // run; the plans differ by their threads.
+        assert_eq!(
+            ProcessStats {
+                batch_pool: PoolStats::default(),
+                read_channel: ChannelStats::default(),
+                write_channel: ChannelStats::default(),
+                plan: None,
+                ..par_stats
+            },
+            ProcessStats {
+                plan: None,
+                ..seq_stats.clone()
+            }
+        );
+        assert_eq!(seq_stats.records_read, 2_500);
+        assert_eq!(
+            seq_stats.records_written + seq_stats.records_dropped,
+            seq_stats.records_read
+        );
+
+        let par_records = read_qnames_and_pos(&par_out)?;
+        assert_eq!(par_records.len() as u64, par_stats.records_written);
+        assert_eq!(par_records, read_qnames_and_pos(&seq_out)?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_process_bam_read_groups() -> Result<(), Error> {
+        let dir = tempfile::tempdir()?;
+        // reads added before the first read group have none.
+        let input_bam_path = TestBam::new()
+            .add_reads("chr1", 0, 2, 10, 50)
+            .read_group("rg1", "sample1")
+            .add_reads("chr1", 1, 1, 100, 50)
+            .read_group("rg2", "sample2")
+            .add_reads("chr2", 0, 1, 40, 50)
+            .build(dir.path())?;
+
+        let pbp = ParallelBamProcessor::new(OnlyOddPosRecord {});
+        let opts = ProcessBamOptions {
+            worker_threads: 3,
+            batch_size: 16,
+            read_filter: Some(
+                ReadFilter::pass_all().read_groups(HashSet::from(["rg1".to_string()])),
+            ),
+            ..Default::default()
+        };
+        let out_path = dir.path().join("out.bam");
+        let stats = pbp.process_bam(&input_bam_path, &out_path, &opts)?;
+
+        let rg_stats = |read, written| ReadGroupStats {
+            records_read: read,
+            records_written: written,
+            records_dropped: read - written,
+        };
+        assert_eq!(
+            stats.read_groups,
+            BTreeMap::from([
+                ("rg1".to_string(), rg_stats(100, 50)),
+                ("rg2".to_string(), rg_stats(40, 0)),
+                (UNKNOWN_READ_GROUP.to_string(), rg_stats(10, 0)),
+            ])
+        );
+        assert_eq!(stats.records_read, 150);
+        assert_eq!(stats.records_written, 50);
+        assert_eq!(stats.records_dropped, 100);
+
+        let mut n_written = 0;
+        for record in bam::Reader::from_path(&out_path)?.records() {
+            let record = record?;
+            assert_eq!(record.aux(b"RG")?, bam::record::Aux::String("rg1"));
+            n_written += 1;
+        }
+        assert_eq!(n_written, 50);
+
+        let seq_stats =
+            pbp.process_bam_sequential(&input_bam_path, dir.path().join("seq.bam"), &opts)?;
+        assert_eq!(
+            seq_stats.plan.as_ref().map(|plan| plan.run.as_str()),
+            Some("process_bam_sequential")
+        );
+        assert_eq!(
+            ProcessStats {
+                plan: None,
+                ..seq_stats
+            },
+            ProcessStats {
+                batch_pool: PoolStats::default(),
+                read_channel: ChannelStats::default(),
+                write_channel: ChannelStats::default(),
+                plan: None,
+                ..stats
+            }
+        );
 
-                            match self.record_modifier.modify_record(record) {
-                                Ok(Some(_)) => {
-                                    record.remove_header();
-                                }
-                                Ok(None) => {
-                                    *record = Record::default(); // re-assign empty record for writer not to write this record.
-                                }
-                                Err(err) => {
-                                    event!(
-                                        Level::WARN,
-                                        "Error: {}. drop this read:{}",
-                                        err.into(),
-                                        str::from_utf8(record.qname())?
-                                    );
-                                    *record = Record::default();
-                                    continue;
-                                }
-                            };
-
-                            n_processed.fetch_add(1, atomic::Ordering::Relaxed);
-
-                            // let n_proc = n_processed.load(atomic::Ordering::Relaxed);
-                            // if n_proc % N_1M == 0 {
-                            //     event!(
-                            //         Level::DEBUG,
-                            //         "Processing speed: {:.1}/s",
-                            //         n_proc as f64 / work_timer.elapsed().as_secs_f64()
-                            //     );
-                            // }
-                        }
+        Ok(())
+    }
 
-                        match tx_worker_clone.send(record_batch) {
-                            Ok(_) => {}
-                            Err(err) => {
-                                event!(Level::ERROR, "Unexpected send failure.");
-                                sleep(Duration::from_secs(2));
-                                Err(err)?
-                            }
-                        };
-                    }
+    #[cfg(any(feature = "bio", feature = "mmap"))]
+    struct RefBaseWorker;
 
-                    event!(
-                        Level::DEBUG,
-                        "Processing thread {:?} ended.",
-                        thread::current().id()
-                    );
+    #[cfg(any(feature = "bio", feature = "mmap"))]
+    impl<'a> BamLocusWorker<'a> for RefBaseWorker {
+        type Output = (i64, Option<Base>);
+        type Input = GenomeCoordinate<'a>;
+        type Error = Error;
 
-                    Ok::<(), anyhow::Error>(()) // Return Result from the thread
-                }));
-            }
+        fn work_for_locus(&self, _plp: Pileup, inp: Self::Input) -> Result<Self::Output, Self::Error> {
+            Ok((inp.pos, None))
+        }
 
-            // Spawn the Consumer (Writer) Thread
-            // let input_bam_path_clone = input_bam_path.clone();
-            let header_view = header_view_bytes.clone();
-            let writer_handle = s.spawn(move || {
-                // event!(
-                //     Level::INFO,
-                //     "Writer worker start. linux thread id ={}",
-                //     unsafe { libc::syscall(libc::SYS_gettid) }
-                // );
-                let pbar = prepare_pbar(0);
-                let mut i = 0;
+        fn work_for_locus_with_ref(
+            &self,
+            _plp: Pileup,
+            inp: Self::Input,
+            ref_base: Option<Base>,
+        ) -> Result<Self::Output, Self::Error> {
+            Ok((inp.pos, ref_base))
+        }
+    }
 
-                let header_view = Rc::new(HeaderView::from_bytes(&header_view));
+    #[cfg(feature = "bio")]
+    #[test]
+    fn test_locus_processor_with_reference() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = tempfile::tempdir()?;
+        let bam_path = TestBam::new()
+            .contig("chr1", 100)
+            .add_reads("chr1", 0, 10, 5, 50)
+            .build(dir.path())?;
+        let fasta_path = crate::test_utils::write_test_fasta(
+            dir.path(),
+            &[("chr1", b"ACGTacgtNR".repeat(10).as_slice())],
+        )?;
 
-                let header = Header::from_template(&header_view);
+        let plp = ParallelLocusProcessorPileup::new(RefBaseWorker, 2, bam_path)
+            .with_reference(&fasta_path)?;
+
+        let inputs = [1, 6, 9, 10].into_iter().map(|p| coord("chr1", p)).collect();
+        let r = plp.process_with_batch(inputs, 100)?;
+
+        // R is not a Base, so it is passed as None.
+        assert_eq!(
+            r,
+            vec![
+                (1, Some(Base::A)),
+                (6, Some(Base::C)),
+                (9, Some(Base::N)),
+                (10, None)
+            ]
+        );
 
-                let mut writer =
-                    Writer::from_path(&out_bam_path, &header, rust_htslib::bam::Format::Bam)?;
+        Ok(())
+    }
 
-                if write_thread > 1 {
-                    writer.set_threads(write_thread)?; // Use shared pool for internal I/O
-                }
+    #[cfg(feature = "mmap")]
+    #[test]
+    fn test_locus_processor_with_mmap_reference() -> Result<(), Box<dyn std::error::Error>> {
+        use crate::data::ref_mmap::MmapFasta;
+
+        let dir = tempfile::tempdir()?;
+        let bam_path = TestBam::new()
+            .contig("chr1", 100)
+            .add_reads("chr1", 0, 10, 5, 50)
+            .build(dir.path())?;
+        // batches across line breaks are copied.
+        let fasta_path = crate::test_utils::write_test_fasta_with_width(
+            dir.path(),
+            &[("chr1", b"ACGTacgtNR".repeat(10).as_slice())],
+            7,
+        )?;
+        let fasta = std::sync::Arc::new(MmapFasta::from_path(&fasta_path)?);
+
+        let plp = ParallelLocusProcessorPileup::new(RefBaseWorker, 2, bam_path)
+            .with_mmap_reference(fasta)
+            .with_dict_check(DictCheck::Strict);
+
+        let inputs = [1, 6, 9, 10, 16, 50]
+            .into_iter()
+            .map(|p| coord("chr1", p))
+            .collect();
+        let r = plp.process_with_batch(inputs, 100)?;
+
+        // soft-masked bases are uppercased, and R is not a Base.
+        assert_eq!(
+            r,
+            vec![
+                (1, Some(Base::A)),
+                (6, Some(Base::C)),
+                (9, Some(Base::N)),
+                (10, None),
+                (16, Some(Base::C)),
+                (50, None)
+            ]
+        );
 
-                let default_record = Record::default();
-
-                let mut ordered_buf_map: HashMap<usize, BatchedData<DataWithIndex<Record>>> =
-                    HashMap::with_capacity(1024 * 16);
-
-                #[inline]
-                fn write_and_send_batch(
-                    mut next_batch_to_write: BatchedData<DataWithIndex<Record>>,
-                    writer: &mut Writer,
-                    tx_buffer: &Sender<BatchedData<DataWithIndex<Record>>>,
-                    i: &mut usize,
-                    // work_timer: &Instant,
-                    pbar: &ProgressBar,
-                    default_record: &Record,
-                    send_empty_batch: bool,
-                ) -> Result<(), Error> {
-                    for record_with_idx in next_batch_to_write.filled_mut() {
-                        // record.set_header(Rc::clone(&header_view));
-                        let record = record_with_idx.data_mut();
-
-                        if record != default_record {
-                            writer.write(&record)?;
-                        }
+        Ok(())
+    }
 
-                        *i += 1;
-                        if *i % N_1M == 0 {
-                            // event!(
-                            //     Level::DEBUG,
-                            //     "Writing speed: {:.1}/s",
-                            //     *i as f64 / work_timer.elapsed().as_secs_f64()
-                            // );
-                            pbar.inc(N_1M as u64);
-                        }
-                    }
+    #[test]
+    fn test_process_bam_sequential_from_temp_store() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = tempfile::tempdir()?;
+        let input_bam_path = TestBam::new()
+            .add_reads("chr1", 0, 3, 100, 50)
+            .build(dir.path())?;
+        // on disk, over the limit, as the run needs the index next to the input,
+        // which a memfd path cannot have.
+        let input =
+            crate::temp_store::TempStore::from_path_with_limit(&input_bam_path, "test_input_bam", 0)?;
+        assert!(!input.is_memory());
+        let mut index_path = input.path().as_os_str().to_owned();
+        index_path.push(".bai");
+        bam::index::build(input.path(), None, bam::index::Type::Bai, 1)?;
+
+        let pbp = ParallelBamProcessor::new(OnlyOddPosRecord {});
+        let stats = pbp.process_bam_sequential(
+            &input,
+            dir.path().join("out.bam"),
+            &ProcessBamOptions::default(),
+        );
+        std::fs::remove_file(index_path)?;
 
-                    next_batch_to_write.reset_index();
-                    if send_empty_batch {
-                        tx_buffer.send(next_batch_to_write)?;
-                    }
-                    Ok(())
-                }
+        assert_eq!(stats?.records_read, 100);
 
-                loop {
-                    let mut record_batch_from_chan = match rx_worker.recv() {
-                        Ok(v) => v,
-                        Err(RecvError) => {
-                            event!(Level::DEBUG, "rx processed closed.");
-                            break;
-                        }
-                        // Err(TryRecvError::Empty) => {
-                        //     sleep(Duration::from_millis(10));
-                        //     continue;
-                        // }
-                        // Err(TryRecvError::Disconnected) => {
-                        //     event!(Level::DEBUG, "rx processed closed.");
-                        //     sleep(Duration::from_secs(2));
-                        //     break;
-                        // }
-                    };
+        Ok(())
+    }
 
-                    let maximum_batch_gen = 1024;
-                    let mut n_batch_gen = 0;
+    /// Depth of each sample, `None` where the sample has no column.
+    struct SampleDepthWorker;
 
-                    let start_idx_from_channel = match record_batch_from_chan.filled().iter().next()
-                    {
-                        Some(v) => v.idx,
-                        None => panic!("Code failed: Reader sent empty batch!"),
-                    };
+    impl<'a> MultiBamLocusWorker<'a> for SampleDepthWorker {
+        type Input = GenomeCoordinate<'a>;
+        type Output = (GenomeCoordinate<'a>, Vec<Option<u32>>);
+        type Error = Error;
 
-                    let mut next_batch_to_write = if start_idx_from_channel == i {
-                        record_batch_from_chan
-                    } else {
-                        ordered_buf_map.insert(start_idx_from_channel, record_batch_from_chan);
+        fn work_for_locus(
+            &self,
+            pileups: &[Option<Pileup>],
+            inp: Self::Input,
+        ) -> Result<Self::Output, Self::Error> {
+            let depths = pileups
+                .iter()
+                .map(|plp| plp.as_ref().map(|plp| plp.depth()))
+                .collect();
+            Ok((inp, depths))
+        }
+    }
 
-                        match ordered_buf_map.remove(&i) {
-                            Some(b) => b,
-                            None => {
-                                if n_batch_gen < maximum_batch_gen {
-                                    tx_buf.send(batch_init())?; // make new empty batch for compensating keeping a batch.
-                                    n_batch_gen += 1;
-                                }
+    #[test]
+    fn test_multi_bam_locus_processor() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = tempfile::tempdir()?;
+        let (tumor_dir, normal_dir) = (dir.path().join("tumor"), dir.path().join("normal"));
+        std::fs::create_dir_all(&tumor_dir)?;
+        std::fs::create_dir_all(&normal_dir)?;
+
+        // 3 reads over chr1:101-110 in the tumor, 1 read over 1:106-115 in the
+        // normal, which names its contigs without the `chr` prefix.
+        let tumor = TestBam::new()
+            .add_reads("chr1", 100, 0, 3, 10)
+            .add_reads("chr1", 300, 0, 2, 10)
+            .build(&tumor_dir)?;
+        let normal = TestBam::new()
+            .add_reads("1", 105, 0, 1, 10)
+            .add_reads("2", 0, 0, 4, 10)
+            .build(&normal_dir)?;
 
-                                continue;
-                            }
-                        }
-                    };
+        let inputs = vec![
+            coord("chr1", 101),
+            coord("chr1", 106),
+            coord("chr1", 200),
+            coord("chr1", 301),
+            coord("chr2", 5),
+        ];
+        let processor = MultiBamLocusProcessor::new(SampleDepthWorker, 2, vec![tumor, normal]);
+        // a small window puts the loci in separate batches.
+        let res = processor.process_with_batch(inputs.clone(), 50)?;
 
-                    write_and_send_batch(
-                        next_batch_to_write,
-                        &mut writer,
-                        &tx_buf,
-                        &mut i,
-                        // &work_timer,
-                        &pbar,
-                        &default_record,
-                        true,
-                    )?;
-                }
+        assert_eq!(
+            res.iter().map(|(gc, _)| gc).collect::<Vec<_>>(),
+            inputs.iter().collect::<Vec<_>>()
+        );
+        assert_eq!(
+            res.into_iter()
+                .map(|(_, depths)| depths)
+                .collect::<Vec<_>>(),
+            vec![
+                vec![Some(3), None],
+                vec![Some(3), Some(1)],
+                vec![None, None],
+                vec![Some(2), None],
+                // the tumor has no chr2 at all.
+                vec![None, Some(4)],
+            ]
+        );
 
-                // write remained records in ordered_buf_map.
-                event!(
-                    Level::DEBUG,
-                    "Writing remaining records ({}) in ordered_buffer...",
-                    ordered_buf_map.len()
-                );
+        // adjacent duplicates each get the columns of the locus.
+        let inputs = vec![
+            coord("chr1", 106),
+            coord("chr1", 106),
+            coord("chr1", 107),
+            coord("chr1", 107),
+        ];
+        let res = processor.process_with_batch(inputs, 50)?;
+        assert_eq!(
+            res.into_iter()
+                .map(|(_, depths)| depths)
+                .collect::<Vec<_>>(),
+            vec![vec![Some(3), Some(1)]; 4]
+        );
 
-                loop {
-                    if let Some(next_batch_to_write) = ordered_buf_map.remove(&i) {
-                        write_and_send_batch(
-                            next_batch_to_write,
-                            &mut writer,
-                            &tx_buf,
-                            &mut i,
-                            // &work_timer,
-                            &pbar,
-                            &default_record,
-                            false,
-                        )?;
-                    } else {
-                        break;
-                    }
-                }
+        Ok(())
+    }
 
-                debug_assert!(ordered_buf_map.is_empty());
+    #[test]
+    fn test_multi_bam_at_contig_ends() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = tempfile::tempdir()?;
+        let (tumor_dir, normal_dir) = (dir.path().join("tumor"), dir.path().join("normal"));
+        std::fs::create_dir_all(&tumor_dir)?;
+        std::fs::create_dir_all(&normal_dir)?;
+
+        // chr1 covered to its end in both, of 1_000 bp in the tumor and of
+        // 2_000 bp in the normal.
+        let tumor = TestBam::new()
+            .contig("chr1", 1_000)
+            .add_reads("chr1", 0, 10, 100, 10)
+            .build(&tumor_dir)?;
+        let normal = TestBam::new()
+            .contig("1", 2_000)
+            .add_reads("1", 0, 10, 200, 10)
+            .build(&normal_dir)?;
+        let run = |coordinate_system, positions: &[i64]| {
+            MultiBamLocusProcessor::new(SampleDepthWorker, 2, vec![tumor.clone(), normal.clone()])
+                .with_coordinate_system(coordinate_system)
+                .process_with_batch(
+                    positions.iter().map(|&pos| coord("chr1", pos)).collect(),
+                    100,
+                )
+                .map(|res| {
+                    res.into_iter()
+                        .map(|(_, depths)| depths)
+                        .collect::<Vec<_>>()
+                })
+        };
 
-                pbar.inc(i as u64 - pbar.position());
-                pbar.tick();
-                pbar.finish();
+        // the batch reaching before the start, or past the end, is clamped to
+        // the contig of each bam.
+        let res = run(CoordinateSystem::OneBased, &[0, 1, 1_000, 1_050])?;
+        assert_eq!(
+            res,
+            [
+                vec![None, None],
+                vec![Some(1), Some(1)],
+                vec![Some(1), Some(1)],
+                vec![None, Some(1)],
+            ]
+        );
+        let res = run(CoordinateSystem::ZeroBased, &[0, 999])?;
+        assert_eq!(res, [vec![Some(1), Some(1)], vec![Some(1), Some(1)]]);
+
+        // a batch wholly past the end of the contig of a bam fails.
+        for (coordinate_system, positions) in [
+            (CoordinateSystem::OneBased, [1_001, 1_050]),
+            (CoordinateSystem::ZeroBased, [1_000, 1_049]),
+        ] {
+            let err = run(coordinate_system, &positions).unwrap_err();
+            let crate::Error::OutOfContig {
+                contig,
+                len,
+                coordinates,
+            } = err
+            else {
+                panic!("{:#}", err);
+            };
+            assert_eq!((contig.as_str(), len), ("chr1", 1_000));
+            assert_eq!(coordinates, ["chr1:1001", "chr1:1050"]);
+        }
 
-                event!(Level::DEBUG, "writer thread ended.");
-                sleep(Duration::from_secs(2));
+        Ok(())
+    }
 
-                Ok::<(), anyhow::Error>(()) // Return Result from the thread
-            });
+    #[test]
+    fn test_resolve_contig_name() {
+        let header = Header::from_template(&HeaderView::from_bytes(
+            b"@SQ\tSN:1\tLN:100\n@SQ\tSN:chrX\tLN:100\n@SQ\tSN:MT\tLN:100\n",
+        ));
+        let header = HeaderView::from_header(&header);
+
+        assert_eq!(
+            resolve_contig_name(&header, &Chrom::Chr1).as_deref(),
+            Some("1")
+        );
+        assert_eq!(
+            resolve_contig_name(&header, &Chrom::ChrX).as_deref(),
+            Some("chrX")
+        );
+        assert_eq!(
+            resolve_contig_name(&header, &Chrom::ChrM).as_deref(),
+            Some("MT")
+        );
+        assert_eq!(resolve_contig_name(&header, &Chrom::Chr2), None);
+    }
 
-            // 5. Wait for all threads to complete
-            reader_handle.join().expect("Reader thread panicked")?;
+    /// Fails the first `n_failures` opens, then opens with htslib.
+    struct FlakyOpener {
+        n_failures: usize,
+        n_calls: Arc<AtomicUsize>,
+    }
 
-            for handle in worker_handles {
-                handle.join().expect("Processor thread panicked")?;
+    impl BamOpener for FlakyOpener {
+        fn open(&self, path: &Path) -> Result<IndexedReader, Error> {
+            let n = self.n_calls.fetch_add(1, atomic::Ordering::SeqCst);
+            if n < self.n_failures {
+                bail!("Simulated I/O error");
             }
+            HtslibOpener.open(path)
+        }
+    }
 
-            drop(tx_worker);
+    #[test]
+    fn test_locus_processor_retries_open() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = tempfile::tempdir()?;
+        let bam_path = TestBam::new()
+            .add_reads("chr1", 100, 0, 2, 10)
+            .build(dir.path())?;
+        let policy = RetryPolicy::new(3, Duration::from_millis(1));
+
+        let run = |n_failures: usize| {
+            let n_calls = Arc::new(AtomicUsize::new(0));
+            let processor = ParallelLocusProcessorPileup::new(MeanBPWorker, 1, bam_path.clone())
+                .with_opener(FlakyOpener {
+                    n_failures,
+                    n_calls: n_calls.clone(),
+                })
+                .with_retry_policy(policy);
+            let res = processor.process_with_batch(vec![coord("chr1", 101)], 100);
+            (res, n_calls.load(atomic::Ordering::SeqCst))
+        };
 
-            writer_handle.join().expect("Writer thread panicked")?;
+        // the plan opens the bam through the opener too, with the same retries:
+        // 2 failures and an open, then the open of the batch.
+        let (res, n_calls) = run(2);
+        assert_eq!(res?, vec![30.0]);
+        assert_eq!(n_calls, 4);
 
-            Ok::<_, Error>(())
-        })?;
+        // the plan only notes that it could not open the bam; the batch fails.
+        let (res, n_calls) = run(6);
+        let err = format!("{:#}", res.unwrap_err());
+        assert!(err.contains("Failed to open"), "{}", err);
+        assert!(err.contains(&bam_path.display().to_string()), "{}", err);
+        assert!(err.contains("Simulated I/O error"), "{}", err);
+        assert_eq!(n_calls, 6);
 
         Ok(())
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use std::borrow::Cow;
+    #[test]
+    fn test_fetch_error_names_region() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = tempfile::tempdir()?;
+        let bam_path = TestBam::new()
+            .add_reads("chr1", 100, 0, 2, 10)
+            .build(dir.path())?;
+
+        let mut ir = IndexedReader::from_path(&bam_path)?;
+        let err = fetch_with_retry(
+            &mut ir,
+            &RetryPolicy::no_retry(),
+            &bam_path,
+            "chr9",
+            Some((9, 20)),
+        )
+        .unwrap_err();
+        assert!(matches!(err, crate::Error::Fetch { .. }), "{:?}", err);
+        assert!(err.to_string().contains("chr9:10-20"), "{}", err);
+        assert!(
+            err.to_string().contains(&bam_path.display().to_string()),
+            "{}",
+            err
+        );
 
-    use tracing::level_filters::LevelFilter;
+        Ok(())
+    }
 
-    use super::*;
-    use crate::{
-        bam::process::BamLocusWorker, data::chrom::Chrom, tracing_kit::{setup_logging_stderr_only, setup_logging_stderr_only_debug},
-    };
+    /// Records the name and fields of every new span, and the fields of every
+    /// WARN and INFO event as `warn` and `info` spans.
+    #[derive(Clone, Default)]
+    struct SpanCollector(Arc<std::sync::Mutex<Vec<(String, String)>>>);
 
-    struct MeanBPWorker;
+    struct Fields(String);
 
-    impl<'a> BamLocusWorker<'a> for MeanBPWorker {
-        type Output = f64;
-        type Input = GenomeCoordinate<'a>;
-        type Error = Error;
+    impl tracing::field::Visit for Fields {
+        fn record_debug(&mut self, field: &field::Field, value: &dyn std::fmt::Debug) {
+            self.0 += &format!("{}={:?} ", field.name(), value);
+        }
+    }
 
-        fn work_for_locus(
+    impl<S: tracing::Subscriber> tracing_subscriber::Layer<S> for SpanCollector {
+        fn on_new_span(
             &self,
-            plp: Pileup,
-            inp: Self::Input,
-        ) -> Result<Self::Output, Self::Error> {
-            let mut bq_sum: u64 = 0;
-            let alignments = plp.alignments();
-            let len = alignments.len();
-            for alignment in alignments {
-                let qpos = match alignment.qpos() {
-                    Some(qpos) => qpos,
-                    None => continue,
-                };
+            attrs: &tracing::span::Attributes<'_>,
+            _id: &tracing::span::Id,
+            _ctx: tracing_subscriber::layer::Context<'_, S>,
+        ) {
+            let mut fields = Fields(String::new());
+            attrs.record(&mut fields);
+            self.0
+                .lock()
+                .unwrap()
+                .push((attrs.metadata().name().to_string(), fields.0));
+        }
 
-                let record = alignment.record();
+        fn on_event(
+            &self,
+            event: &tracing::Event<'_>,
+            _ctx: tracing_subscriber::layer::Context<'_, S>,
+        ) {
+            let name = match *event.metadata().level() {
+                Level::WARN => "warn",
+                Level::INFO => "info",
+                _ => return,
+            };
 
-                let bq = record.qual().get(qpos).unwrap();
-                bq_sum += *bq as u64;
-            }
+            let mut fields = Fields(String::new());
+            event.record(&mut fields);
+            self.0.lock().unwrap().push((name.to_string(), fields.0));
+        }
+    }
 
-            Ok(bq_sum as f64 / len as f64)
+    impl SpanCollector {
+        fn spans(&self, name: &str) -> Vec<String> {
+            self.0
+                .lock()
+                .unwrap()
+                .iter()
+                .filter(|(n, _)| n == name)
+                .map(|(_, fields)| fields.clone())
+                .collect()
         }
     }
 
     #[test]
-    fn parallel_locus_processor1() -> Result<(), Box<dyn std::error::Error>> {
-        setup_logging_stderr_only_debug(LevelFilter::DEBUG)?;
+    fn test_pipeline_spans() -> Result<(), Box<dyn std::error::Error>> {
+        use tracing_subscriber::layer::SubscriberExt;
+
+        let dir = tempfile::tempdir()?;
+        let input_bam_path = TestBam::new()
+            .add_reads("chr1", 0, 3, 100, 50)
+            .build(dir.path())?;
+
+        let collector = SpanCollector::default();
+        let subscriber = tracing_subscriber::registry().with(collector.clone());
+        let stats = tracing::subscriber::with_default(subscriber, || {
+            let opts = ProcessBamOptions {
+                worker_threads: 2,
+                batch_size: 32,
+                ..Default::default()
+            };
+            let stats = ParallelBamProcessor::new(OnlyOddPosRecord {}).process_bam(
+                &input_bam_path,
+                dir.path().join("out.bam"),
+                &opts,
+            )?;
 
-        let bam_path = "/home/eck/workspace/common_resources/NA12878.chrom20.ILLUMINA.bwa.CEU.low_coverage.20121211.bam";
-        let plp = ParallelLocusProcessorPileup {
-            bam_locus_worker: MeanBPWorker,
-            n_threads: 4,
-            bam_path: bam_path.into(),
-        };
+            ParallelLocusProcessorPileup::new(MeanBPWorker, 2, input_bam_path.clone())
+                .process_with_batch(vec![coord("chr1", 10), coord("chr1", 200)], 100)?;
 
-        let regions = (60000..(60000 + 1_000_000))
-            .step_by(1000)
-            .map(|p| GenomeCoordinate {
-                contig: Chrom::Other("20".into()),
-                pos: p,
-            })
-            .collect::<Vec<_>>();
+            Ok::<_, Error>(stats)
+        })?;
 
-        let r = plp.process_with_batch(regions, 100_000)?;
+        let process_bam = collector.spans("process_bam");
+        assert_eq!(process_bam.len(), 1);
+        assert!(process_bam[0].contains(&format!("input={}", input_bam_path.display())));
+        assert!(
+            process_bam[0].contains("worker_threads=2"),
+            "{}",
+            process_bam[0]
+        );
 
-        eprintln!("{} {:?}", r.len(), &r[..10]);
+        assert_eq!(collector.spans("reader").len(), 1);
+        assert_eq!(collector.spans("writer").len(), 1);
+        let mut workers = collector.spans("worker");
+        workers.sort();
+        assert_eq!(workers, vec!["worker=0 ", "worker=1 "]);
+
+        // 4 batches of 32 records, each seen by the 3 stages, and 2 locus batches.
+        let batches = collector.spans("batch");
+        assert_eq!(
+            batches
+                .iter()
+                .filter(|f| f.contains("first_record="))
+                .count(),
+            4 * 3
+        );
+        assert_eq!(
+            batches.iter().filter(|f| f.contains("contig=chr1")).count(),
+            2
+        );
+        assert_eq!(collector.spans("process_with_batch").len(), 1);
+
+        // the throughput is of the records written, not those read.
+        assert!(stats.records_written < stats.records_read);
+        let throughput = collector
+            .spans("info")
+            .into_iter()
+            .filter(|f| f.contains("name=\"records written\""))
+            .collect::<Vec<_>>();
+        let total = format!("total={} ", stats.records_written);
+        assert!(
+            throughput.last().unwrap().contains(&total),
+            "{:?}",
+            throughput
+        );
 
         Ok(())
     }
 
-    // Helper function to create coordinates for tests
-    fn coord<'a>(contig: &'a str, pos: i64) -> GenomeCoordinate<'a> {
-        GenomeCoordinate {
-            contig: Chrom::Other(Cow::Borrowed(contig)),
-            pos,
-        }
+    /// Position of the locus, sleeping at `sleep_at`.
+    struct SleepyWorker {
+        sleep_at: i64,
+        sleep: Duration,
     }
 
-    #[test]
-    fn test_standard_batching() {
-        let inputs = vec![
-            coord("chr1", 100),
-            coord("chr1", 200),
-            coord("chr1", 10000), // This should start a new batch
-            coord("chr1", 10100),
-        ];
-        let window_size = 1000;
-        let batches = batch_input_by_coordinate(inputs, window_size);
+    impl<'a> BamLocusWorker<'a> for SleepyWorker {
+        type Output = i64;
+        type Input = GenomeCoordinate<'a>;
+        type Error = Error;
 
-        assert_eq!(batches.len(), 2);
-        assert_eq!(batches[0].len(), 2);
-        assert_eq!(batches[1].len(), 2);
-        assert_eq!(batches[0][0].pos, 100);
-        assert_eq!(batches[1][0].pos, 10000);
+        fn work_for_locus(&self, _plp: Pileup, inp: Self::Input) -> Result<i64, Error> {
+            if inp.pos == self.sleep_at {
+                thread::sleep(self.sleep);
+            }
+            Ok(inp.pos)
+        }
     }
 
     #[test]
-    fn test_contig_change() {
-        let inputs = vec![
-            coord("chr1", 100),
-            coord("chr1", 200),
-            coord("chr2", 300), // This must start a new batch
-            coord("chr2", 400),
-        ];
-        let window_size = 1000;
-        let batches = batch_input_by_coordinate(inputs, window_size);
+    fn test_batch_timeout() -> Result<(), Box<dyn std::error::Error>> {
+        use tracing_subscriber::layer::SubscriberExt;
+
+        use crate::bam::watchdog::BatchTimedOut;
+
+        let dir = tempfile::tempdir()?;
+        let bam_path = TestBam::new()
+            .add_reads("chr1", 0, 10, 300, 50)
+            .build(dir.path())?;
+        let worker = || SleepyWorker {
+            sleep_at: 10,
+            sleep: Duration::from_millis(300),
+        };
+        // 2 batches, the first stuck on 10.
+        let inputs = || vec![coord("chr1", 10), coord("chr1", 20), coord("chr1", 2_000)];
+
+        let collector = SpanCollector::default();
+        let subscriber = tracing_subscriber::registry().with(collector.clone());
+        let (warned, (aborted, errors)) = tracing::subscriber::with_default(subscriber, || {
+            let warned = ParallelLocusProcessorPileup::new(worker(), 2, bam_path.clone())
+                .with_batch_timeout(Duration::from_millis(50), BatchTimeoutPolicy::Warn)
+                .process_with_batch(inputs(), 100)?;
+
+            let pool = ThreadPoolBuilder::new().num_threads(2).build()?;
+            let aborted = ParallelLocusProcessorPileup::new(worker(), 2, bam_path.clone())
+                .with_batch_timeout(Duration::from_millis(50), BatchTimeoutPolicy::Abort)
+                .process_with_batch_partial_on(inputs(), 100, Some(&pool));
+
+            Ok::<_, Error>((warned, aborted))
+        })?;
 
-        assert_eq!(batches.len(), 2);
-        assert_eq!(batches[0].len(), 2);
-        assert_eq!(batches[1].len(), 2);
-        assert_eq!(batches[0][0].contig, Chrom::Other(Cow::Borrowed("chr1")));
-        assert_eq!(batches[1][0].contig, Chrom::Other(Cow::Borrowed("chr2")));
+        assert_eq!(warned, vec![10, 20, 2_000]);
+        let warns = collector.spans("warn");
+        assert!(warns.len() >= 2, "{warns:?}");
+        assert!(
+            warns.iter().all(|w| w.contains("region=chr1:10-20")),
+            "{warns:?}"
+        );
+        assert!(warns.iter().any(|w| w.contains("aborting")), "{warns:?}");
+
+        // the stuck batch is aborted, the other one is kept.
+        assert_eq!(aborted, vec![2_000]);
+        assert_eq!(errors.len(), 1);
+        let timed_out = errors[0].downcast_ref::<BatchTimedOut>().unwrap();
+        assert_eq!(timed_out.region.to_string(), "chr1:10-20");
+        assert!(timed_out.elapsed >= Duration::from_millis(50));
+
+        // no timeout, no warning.
+        let res = ParallelLocusProcessorPileup::new(worker(), 2, bam_path)
+            .with_batch_timeout(Duration::from_secs(60), BatchTimeoutPolicy::Abort)
+            .process_with_batch(inputs(), 100)?;
+        assert_eq!(res, vec![10, 20, 2_000]);
+
+        Ok(())
     }
 
-    #[test]
-    fn test_window_size_boundary() {
-        let window_size = 1000;
-        let inputs = vec![
-            coord("chr1", 100),
-            coord("chr1", 1099), // gc.pos (1099) - c_start (100) = 999. This is < 1000, so it fits.
-            coord("chr1", 1100), // gc.pos (1100) - c_start (100) = 1000. This is NOT < 1000, new batch.
-        ];
+    /// Keeps every record as it is.
+    struct KeepAll;
 
-        let batches = batch_input_by_coordinate(inputs, window_size);
+    impl RecordModifier for KeepAll {
+        type Error = Error;
 
-        assert_eq!(batches.len(), 2);
-        assert_eq!(batches[0].len(), 2); // The first two items should be in the first batch
-        assert_eq!(batches[1].len(), 1); // The third item starts a new batch
-        assert_eq!(batches[1][0].pos, 1100);
+        fn modify_record(&self, _record: &mut bam::Record) -> Result<Option<()>, Self::Error> {
+            Ok(Some(()))
+        }
     }
 
-    #[test]
-    fn test_empty_input() {
-        let inputs: Vec<GenomeCoordinate> = vec![];
-        let window_size = 1000;
-        let batches = batch_input_by_coordinate(inputs, window_size);
-        assert!(batches.is_empty());
+    fn read_records(bam_path: &Path) -> Result<Vec<bam::Record>, Error> {
+        Ok(bam::Reader::from_path(bam_path)?
+            .records()
+            .collect::<Result<Vec<_>, _>>()?)
+    }
+
+    /// Reads of long-read sequencers: megabase-scale records, CIGARs of more
+    /// ops than the 16 bits of the bam field, which are stored in a `CG` tag,
+    /// and reads split into many supplementary alignments.
+    fn long_read_bam(dir: &Path) -> Result<PathBuf, Error> {
+        let seq = |len: usize| b"ACGT".repeat(len.div_ceil(4))[..len].to_vec();
+        let qual = |len: usize| (0..len).map(|i| (i % 41) as u8).collect::<Vec<_>>();
+
+        // 80_001 ops, over 120_000 bases and 160_000 bp.
+        let many_ops = format!("{}40000M", "2M1D".repeat(40_000));
+        let sa = (1..=8)
+            .map(|i| {
+                format!(
+                    "chr1,{},+,{}H10000M,60,0;",
+                    500_000 + i * 20_000,
+                    i * 10_000
+                )
+            })
+            .collect::<String>();
+        let mut test_bam = TestBam::new()
+            .contig("chr1", 2_000_000)
+            .add_reads("chr1", 0, 400, 10, 150)
+            .add_read("chr1", 1_000, &seq(150_000), &qual(150_000), 0)
+            .add_read("chr1", 5_000, &seq(120_000), &qual(120_000), 0x10)
+            .with_cigar(&many_ops)
+            .add_read("chr1", 400_000, &seq(100_000), &qual(100_000), 0)
+            .with_cigar("10000M90000S")
+            .with_qname("split")
+            .with_tag(b"SA", &sa);
+        for i in 1..=8 {
+            test_bam = test_bam
+                .add_read(
+                    "chr1",
+                    500_000 + i * 20_000 - 1,
+                    &seq(10_000),
+                    &qual(10_000),
+                    0x800,
+                )
+                .with_cigar(&format!("{}H10000M{}H", i * 10_000, 90_000 - i * 10_000))
+                .with_qname("split");
+        }
+        test_bam
+            .add_reads("chr1", 1_000_000, 400, 10, 150)
+            .build(dir)
     }
 
     #[test]
-    fn test_single_input() {
-        let inputs = vec![coord("chr1", 100)];
-        let window_size = 1000;
-        let batches = batch_input_by_coordinate(inputs, window_size);
-        assert_eq!(batches.len(), 1);
-        assert_eq!(batches[0].len(), 1);
-        assert_eq!(batches[0][0].pos, 100);
+    fn test_long_reads_round_trip() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = tempfile::tempdir()?;
+        let input_bam_path = long_read_bam(dir.path())?;
+        let input = read_records(&input_bam_path)?;
+        assert_eq!(input.len(), 31);
+        assert_eq!(input.iter().map(|r| r.cigar_len()).max(), Some(80_001));
+        assert_eq!(input.iter().map(|r| r.seq_len()).max(), Some(150_000));
+
+        // with a dead letter bam, every record is copied before it is modified.
+        let opts = ProcessBamOptions {
+            batch_size: 4,
+            on_modify_error: OnModifyError::DeadLetter(dir.path().join("dead.bam")),
+            ..Default::default()
+        };
+        for sequential in [false, true] {
+            let out_bam_path = dir.path().join("out.bam");
+            let processor = ParallelBamProcessor::new(KeepAll);
+            let stats = match sequential {
+                false => processor.process_bam(&input_bam_path, &out_bam_path, &opts)?,
+                true => processor.process_bam_sequential(&input_bam_path, &out_bam_path, &opts)?,
+            };
+            assert_eq!(stats.records_written, 31);
+
+            let output = read_records(&out_bam_path)?;
+            assert_eq!(output.len(), input.len());
+            for (out, inp) in output.iter().zip(&input) {
+                assert!(
+                    out == inp,
+                    "{:?} differs",
+                    String::from_utf8_lossy(inp.qname())
+                );
+                assert_eq!(out.raw_cigar(), inp.raw_cigar());
+            }
+            assert!(!dir.path().join("dead.bam").exists());
+        }
+
+        // packing the sequence of a long read fails rather than panics.
+        let longest = input.iter().max_by_key(|r| r.seq_len()).unwrap();
+        let err = BaseArr::<u64>::from_bytes(&longest.seq().as_bytes()).unwrap_err();
+        assert!(
+            err.to_string().contains("Input slice is too long"),
+            "{}",
+            err
+        );
+
+        Ok(())
     }
 
     #[test]
-    fn test_all_in_one_batch() {
-        let inputs = vec![coord("chr1", 100), coord("chr1", 200), coord("chr1", 300)];
-        let window_size = 1000;
-        let batches = batch_input_by_coordinate(inputs, window_size);
-        assert_eq!(batches.len(), 1);
-        assert_eq!(batches[0].len(), 3);
+    fn test_copy_record_into_reuses_buffer() {
+        let record = |len: usize| {
+            let cigar = CigarString(vec![Cigar::Match(len as u32)]);
+            let mut record = bam::Record::new();
+            record.set(b"long", Some(&cigar), &vec![b'A'; len], &vec![30; len]);
+            record
+        };
+        let (long, short) = (record(200_000), record(150));
+
+        let mut copy = bam::Record::new();
+        copy_record_into(&mut copy, &long);
+        assert_eq!(copy, long);
+        let (data, m_data) = (copy.inner().data, copy.inner().m_data);
+        assert!(m_data as usize >= long.inner().l_data as usize);
+
+        // no allocation past the first copy, where a clone makes a record and
+        // its data of every record.
+        for src in [&short, &long, &short] {
+            copy_record_into(&mut copy, src);
+            assert_eq!(&copy, src);
+            assert_eq!((copy.inner().data, copy.inner().m_data), (data, m_data));
+        }
+        assert_ne!(long.clone().inner().data, data);
     }
 
-    struct OnlyOddPosRecord {}
+    /// Depth and base of the locus.
+    struct DepthBaseWorker;
 
-    impl RecordModifier for OnlyOddPosRecord {
+    impl<'a> BamLocusWorker<'a> for DepthBaseWorker {
+        type Output = (usize, Option<Base>);
+        type Input = GenomeCoordinate<'a>;
         type Error = Error;
 
-        fn modify_record(&self, record: &mut bam::Record) -> Result<Option<()>, Self::Error> {
-            let r = if (record.pos()+1) % 2 == 0 {
-                None
-            } else {
-                Some(())
-            };
-
-            Ok(r)
+        fn work_for_locus(&self, plp: Pileup, _inp: Self::Input) -> Result<Self::Output, Error> {
+            let bases = plp
+                .typed_alignments()
+                .map(|aln| aln.base)
+                .collect::<Vec<_>>();
+            Ok((bases.len(), bases.first().copied().flatten()))
         }
     }
 
     #[test]
-    fn test_parallel_bam_processor() -> Result<(), Box<dyn std::error::Error>> {
-        setup_logging_stderr_only(LevelFilter::DEBUG)?;
-
-        let pbp = ParallelBamProcessor {
-            record_modifier: OnlyOddPosRecord {},
-        };
-
-        let input_bam_path = "/home/eck/workspace/common_resources/NA12878.chrom20.ILLUMINA.bwa.CEU.low_coverage.20121211.bam";
-        let read_thread = 1;
-        let worker_thread = 2;
-        let write_thread = 4;
-        let out_bam_path = "NA12878.chrom20.ILLUMINA.bwa.CEU.low_coverage.20121211.oddposonly.bam";
-        let batch_size = 1024;
-        let channel_capacity = 128;
-
-        pbp.process_bam(
-            input_bam_path,
-            read_thread,
-            worker_thread,
-            write_thread,
-            out_bam_path,
-            batch_size,
-            channel_capacity,
-        )?;
-
+    fn test_pileup_of_long_read() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = tempfile::tempdir()?;
+        // a single read over 1-based 1..=100_000, then a short one.
+        let bam_path = TestBam::new()
+            .contig("chr1", 200_000)
+            .add_reads("chr1", 0, 1, 1, 100_000)
+            .add_reads("chr1", 99_990, 1, 1, 50)
+            .build(dir.path())?;
+        let plp = ParallelLocusProcessorPileup::new(DepthBaseWorker, 4, bam_path);
+
+        let inputs = (1..=100_040).map(|pos| coord("chr1", pos)).collect();
+        let outputs = plp.process_with_batch(inputs, 1_000)?;
+        assert_eq!(outputs.len(), 100_040);
+        for (i, &(depth, base)) in outputs.iter().enumerate() {
+            let expected = match i {
+                ..99_990 => 1,
+                99_990..100_000 => 2,
+                _ => 1,
+            };
+            assert_eq!(depth, expected, "depth at {}", i + 1);
+            // that of the long read first, then of the short one.
+            let offset = if i < 100_000 { i } else { i - 99_990 };
+            let expected = [Base::A, Base::C, Base::G, Base::T][offset % 4];
+            assert_eq!(base, Some(expected), "base at {}", i + 1);
+        }
 
         Ok(())
     }
@@ -0,0 +1,132 @@
+//! Names of the reads aligned to regions, e.g. to extract their pairs from the
+//! fastq files with [`extract_by_qname`](crate::fastq::extract::extract_by_qname).
+
+use std::{collections::HashSet, path::Path};
+
+use rust_htslib::bam::{IndexedReader, Read as _, Record};
+
+use crate::{
+    bam::reader::{HtslibOpener, RetryPolicy, contig_tid, fetch_with_retry, open_with_retry},
+    data::locus::GenomeRegion,
+    errors::Error,
+};
+
+/// Names of the reads of the bam at `bam_path` overlapping any of `regions`.
+/// See [`for_each_qname`].
+pub fn collect_qnames(
+    bam_path: impl AsRef<Path>,
+    regions: &[GenomeRegion<'_>],
+    include_mates: bool,
+) -> Result<HashSet<Vec<u8>>, Error> {
+    let mut qnames = HashSet::new();
+    for_each_qname(bam_path, regions, include_mates, |qname| {
+        if !qnames.contains(qname) {
+            qnames.insert(qname.to_vec());
+        }
+    })?;
+
+    Ok(qnames)
+}
+
+/// Call `f` with the name of each read of the bam at `bam_path` overlapping any
+/// of `regions`, once per read and region, so possibly more than once per name.
+/// Sets other than a `HashSet` are filled with it, e.g. a
+/// [`QnameSet::Bloom`](crate::fastq::extract::QnameSet::Bloom) of names too
+/// many to hold.
+///
+/// With `include_mates`, the reads whose mate starts in a region are also
+/// named, though they are elsewhere, for bams missing the mates in the region,
+/// e.g. filtered. They are found by reading the whole bam.
+pub fn for_each_qname(
+    bam_path: impl AsRef<Path>,
+    regions: &[GenomeRegion<'_>],
+    include_mates: bool,
+    mut f: impl FnMut(&[u8]),
+) -> Result<(), Error> {
+    let bam_path = bam_path.as_ref();
+    let policy = RetryPolicy::default();
+    let mut reader = open_with_retry(&HtslibOpener, &policy, bam_path)?;
+    let mut record = Record::new();
+
+    for region in regions {
+        region.fetch_in(&mut reader)?;
+        read_each(&mut reader, &mut record, |record| f(record.qname()))?;
+    }
+
+    if include_mates {
+        let mate_regions = regions
+            .iter()
+            .filter_map(|region| {
+                let tid = contig_tid(reader.header(), &region.contig)?;
+                Some((tid as i32, region.start - 1, region.end))
+            })
+            .collect::<Vec<_>>();
+        fetch_with_retry(&mut reader, &policy, bam_path, ".", None)?;
+        read_each(&mut reader, &mut record, |record| {
+            let mate_in_region = record.is_paired()
+                && !record.is_mate_unmapped()
+                && mate_regions.iter().any(|&(tid, start, end)| {
+                    record.mtid() == tid && (start..end).contains(&record.mpos())
+                });
+            if mate_in_region {
+                f(record.qname());
+            }
+        })?;
+    }
+
+    Ok(())
+}
+
+fn read_each(
+    reader: &mut IndexedReader,
+    record: &mut Record,
+    mut f: impl FnMut(&Record),
+) -> Result<(), Error> {
+    while let Some(res) = reader.read(record) {
+        res?;
+        f(record);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::TestBam;
+
+    #[test]
+    fn test_collect_qnames() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = tempfile::tempdir()?;
+        let bam_path = TestBam::new()
+            .add_pair("chr1", 1_000, 1_200, 50, 0)
+            .add_pair("chr1", 5_000, 5_200, 50, 0)
+            // a read whose mate is not in the bam.
+            .add_read("chr1", 20_000, b"ACGT", b"IIII", 0x1 | 0x40)
+            .with_qname("lonely")
+            .with_mate(1_100, 0)
+            .build(dir.path())?;
+
+        let regions = [
+            GenomeRegion::from(("chr1", 1_101, 1_300)),
+            GenomeRegion::from(("chr1", 5_190, 5_300)),
+        ];
+        let qnames = collect_qnames(&bam_path, &regions, false)?;
+        assert_eq!(
+            qnames,
+            HashSet::from([b"pair0".to_vec(), b"pair2".to_vec()])
+        );
+
+        let mut n_reads = 0;
+        for_each_qname(&bam_path, &regions, false, |_| n_reads += 1)?;
+        // only the second mates are in the regions.
+        assert_eq!(n_reads, 2);
+
+        let qnames = collect_qnames(&bam_path, &regions[..1], true)?;
+        assert_eq!(
+            qnames,
+            HashSet::from([b"pair0".to_vec(), b"lonely".to_vec()])
+        );
+
+        Ok(())
+    }
+}
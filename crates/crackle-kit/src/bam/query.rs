@@ -0,0 +1,706 @@
+//! Pileups at a few loci at a time with low latency, e.g. for a service
+//! answering queries of a bam, which [`ParallelLocusProcessorPileup`] is not
+//! made for, building a pool and opening the bam for every call.
+//!
+//! ```no_run
+//! use std::sync::Arc;
+//!
+//! use crackle_kit::{
+//!     bam::{
+//!         process::{BamLocusWorker, ParallelLocusProcessorPileup},
+//!         query::LocusQueryEngine,
+//!     },
+//!     data::{chrom::Chrom, locus::GenomeCoordinate},
+//!     rust_htslib::bam::pileup::Pileup,
+//! };
+//!
+//! struct Depth;
+//!
+//! impl<'a> BamLocusWorker<'a> for Depth {
+//!     type Input = GenomeCoordinate<'a>;
+//!     type Output = u32;
+//!     type Error = anyhow::Error;
+//!
+//!     fn work_for_locus(&self, plp: Pileup, _input: Self::Input) -> anyhow::Result<u32> {
+//!         Ok(plp.depth())
+//!     }
+//! }
+//!
+//! let processor = ParallelLocusProcessorPileup::builder("sample.bam")
+//!     .worker(Depth)
+//!     .build()?;
+//! let engine = Arc::new(LocusQueryEngine::new(processor)?);
+//! let depths = engine.query(&[GenomeCoordinate {
+//!     contig: Chrom::Chr1,
+//!     pos: 1_000,
+//! }])?;
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+
+use std::{collections::HashMap, sync::Mutex};
+
+use anyhow::Context;
+use rust_htslib::bam::{IndexedReader, Read as _, pileup::Pileup};
+#[cfg(feature = "serde")]
+use serde::{Serialize, de::DeserializeOwned};
+#[cfg(feature = "serde")]
+use tracing::{Level, event};
+
+#[cfg(feature = "serde")]
+use crate::bam::cache::{CacheKey, FileStamp, LocusCache, digest_fields};
+use crate::{
+    bam::{
+        pileup_ext::SampledPileup,
+        process::{BamLocusWorkInput, BamLocusWorker, ParallelLocusProcessorPileup},
+        reader::{fetch_tid, open_with_retry},
+    },
+    data::{bases::Base, chrom::Chrom, locus::GenomeCoordinate},
+    errors::Error,
+    utils::panic::catch_panic,
+};
+
+/// Queries of at most this many coordinates are answered on the calling thread.
+pub const DEFAULT_MAX_SMALL_QUERY: usize = 16;
+
+/// Readers kept open between queries by default.
+pub const DEFAULT_MAX_READERS: usize = 4;
+
+/// Span of the batches of queries above the small query size.
+pub const DEFAULT_BATCH_WINDOW: usize = 10_000;
+
+/// Answers queries of the pileups at a few coordinates of a bam from readers
+/// kept open between queries. Shared behind an `Arc`, it answers queries of
+/// several threads at once, each with a reader of its own.
+///
+/// Queries of up to [`Self::with_max_small_query`] coordinates are answered on
+/// the calling thread, fetching each coordinate alone. Larger ones are given to
+/// [`ParallelLocusProcessorPileup::process_with_batch`].
+pub struct LocusQueryEngine<W>
+where
+    W: for<'a> BamLocusWorker<'a, Input = GenomeCoordinate<'a>>,
+{
+    processor: ParallelLocusProcessorPileup<QueryWorker<W>>,
+    readers: Mutex<Vec<IndexedReader>>,
+    max_readers: usize,
+    tids: HashMap<String, u32>,
+    max_small_query: usize,
+    batch_window: usize,
+    cache_tag: String,
+}
+
+impl<W> LocusQueryEngine<W>
+where
+    W: for<'a> BamLocusWorker<'a, Input = GenomeCoordinate<'a>>,
+{
+    /// An engine querying the bam of `processor` with its worker and options.
+    /// Opens the bam once, to read the contigs of its header.
+    pub fn new(processor: ParallelLocusProcessorPileup<W>) -> Result<Self, Error> {
+        let reader = open_with_retry(
+            processor.opener.as_ref(),
+            &processor.retry_policy,
+            processor.bam_path(),
+        )?;
+        let tids = reader
+            .header()
+            .target_names()
+            .into_iter()
+            .enumerate()
+            .map(|(tid, name)| {
+                let chrom = Chrom::from(String::from_utf8_lossy(name).into_owned());
+                (chrom.as_str().to_string(), tid as u32)
+            })
+            .collect();
+
+        Ok(Self {
+            processor: processor.map_worker(QueryWorker),
+            readers: Mutex::new(vec![reader]),
+            max_readers: DEFAULT_MAX_READERS,
+            tids,
+            max_small_query: DEFAULT_MAX_SMALL_QUERY,
+            batch_window: DEFAULT_BATCH_WINDOW,
+            cache_tag: String::new(),
+        })
+    }
+
+    /// Answer queries of up to `max_small_query` coordinates on the calling
+    /// thread, [`DEFAULT_MAX_SMALL_QUERY`] by default.
+    pub fn with_max_small_query(mut self, max_small_query: usize) -> Self {
+        self.max_small_query = max_small_query;
+        self
+    }
+
+    /// Keep up to `max_readers` readers open between queries,
+    /// [`DEFAULT_MAX_READERS`] by default. Queries running at once beyond them
+    /// open readers of their own.
+    pub fn with_max_readers(mut self, max_readers: usize) -> Self {
+        self.max_readers = max_readers;
+        self
+    }
+
+    /// Batch window of the queries above the small query size,
+    /// [`DEFAULT_BATCH_WINDOW`] by default.
+    pub fn with_batch_window(mut self, batch_window: usize) -> Self {
+        self.batch_window = batch_window;
+        self
+    }
+
+    /// Tag of the options of the worker itself, e.g. its thresholds, for the
+    /// keys of [`Self::query_cached`], which cannot see them. A change of the
+    /// options of the worker then misses rather than give the outputs cached
+    /// with the former ones. Empty by default.
+    pub fn with_cache_tag(mut self, cache_tag: impl Into<String>) -> Self {
+        self.cache_tag = cache_tag.into();
+        self
+    }
+
+    pub fn worker(&self) -> &W {
+        &self.processor.worker().0
+    }
+
+    /// The output of the worker at each of `coords`, in their order, `None` for
+    /// those without coverage.
+    ///
+    /// Fails on a contig not in the bam, like
+    /// [`ParallelLocusProcessorPileup::process_with_batch`].
+    pub fn query<'a>(
+        &self,
+        coords: &[GenomeCoordinate<'a>],
+    ) -> Result<Vec<Option<<W as BamLocusWorker<'a>>::Output>>, Error> {
+        if coords.len() > self.max_small_query {
+            return self.query_batched(coords);
+        }
+
+        self.with_reader(|reader| {
+            coords
+                .iter()
+                .map(|coord| self.query_one(reader, coord))
+                .collect()
+        })
+    }
+
+    fn query_one<'a>(
+        &self,
+        reader: &mut IndexedReader,
+        coord: &GenomeCoordinate<'a>,
+    ) -> Result<Option<<W as BamLocusWorker<'a>>::Output>, Error> {
+        let processor = &self.processor;
+        let pos0 = processor.coordinate_system.to_0based(coord.pos);
+        let tid = self.tids.get(coord.contig.as_str()).copied();
+        processor
+            .retry_policy
+            .run(
+                format_args!(
+                    "Fetching {}:{} from {}",
+                    coord.contig,
+                    pos0 + 1,
+                    processor.bam_path().display()
+                ),
+                || fetch_tid(reader, &coord.contig, tid, pos0 + 1, pos0 + 1),
+            )
+            .with_context(|| format!("Reading {}", processor.bam_path().display()))?;
+
+        // the pileups start at the first read overlapping the locus.
+        for plp in reader.pileup_with_option(processor.pileup_options) {
+            let plp = plp.map_err(anyhow::Error::from)?;
+            let plp_pos = plp.pos() as i64;
+            if plp_pos < pos0 {
+                continue;
+            }
+            if plp_pos > pos0 {
+                break;
+            }
+
+            let ref_base = processor
+                .batch_reference(&coord.contig, pos0 + 1, pos0 + 1)?
+                .and_then(|seq| seq.first().and_then(|b| Base::try_from(*b).ok()));
+            let output = catch_panic(|| {
+                processor
+                    .work_at_column(self.worker(), plp, coord.clone(), ref_base)
+                    .map_err(Into::into)
+            })
+            .unwrap_or_else(|panicked| Err(panicked.into()));
+            return output.map(Some).map_err(|err| Error::WorkerFailed {
+                item: format!("locus {}:{}", coord.contig, coord.pos),
+                source: err,
+            });
+        }
+
+        Ok(None)
+    }
+
+    fn query_batched<'a>(
+        &self,
+        coords: &[GenomeCoordinate<'a>],
+    ) -> Result<Vec<Option<<W as BamLocusWorker<'a>>::Output>>, Error> {
+        let inputs = coords
+            .iter()
+            .enumerate()
+            .map(|(index, coord)| QueryInput {
+                index,
+                coord: coord.clone(),
+            })
+            .collect::<Vec<_>>();
+
+        let mut outputs = (0..coords.len()).map(|_| None).collect::<Vec<_>>();
+        for (index, output) in self
+            .processor
+            .process_with_batch(inputs, self.batch_window)?
+        {
+            outputs[index] = Some(output);
+        }
+        Ok(outputs)
+    }
+
+    /// Run `f` with a pooled reader, or a new one if all are in use. The reader
+    /// goes back to the pool unless `f` fails.
+    fn with_reader<T>(
+        &self,
+        f: impl FnOnce(&mut IndexedReader) -> Result<T, Error>,
+    ) -> Result<T, Error> {
+        let pooled = self.readers.lock().unwrap().pop();
+        let mut reader = match pooled {
+            Some(reader) => reader,
+            None => open_with_retry(
+                self.processor.opener.as_ref(),
+                &self.processor.retry_policy,
+                self.processor.bam_path(),
+            )?,
+        };
+
+        let res = f(&mut reader);
+        if res.is_ok() {
+            let mut readers = self.readers.lock().unwrap();
+            if readers.len() < self.max_readers {
+                readers.push(reader);
+            }
+        }
+        res
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<W> LocusQueryEngine<W>
+where
+    W: for<'a> BamLocusWorker<'a, Input = GenomeCoordinate<'a>>,
+{
+    /// [`Self::query`] through `cache`: the outputs cached at `coords` are
+    /// decoded by bincode, and only the other coordinates are queried, their
+    /// outputs put in `cache`. Coordinates without coverage are cached as such.
+    ///
+    /// Outputs are keyed by [`CacheKey`], of the bam as found on the disk when
+    /// the query starts, and of the type of the worker, its
+    /// [`Self::with_cache_tag`], the pileup options and the reference of the
+    /// processor. Outputs are not put if the bam changes during the query.
+    pub fn query_cached<'a>(
+        &self,
+        coords: &[GenomeCoordinate<'a>],
+        cache: &dyn LocusCache,
+    ) -> Result<Vec<Option<<W as BamLocusWorker<'a>>::Output>>, Error>
+    where
+        <W as BamLocusWorker<'a>>::Output: Serialize + DeserializeOwned,
+    {
+        let bam = FileStamp::of(self.processor.bam_path())?;
+        let options = self.cache_options()?;
+        let keys = coords
+            .iter()
+            .map(|coord| {
+                let pos = self.processor.coordinate_system.to_0based(coord.pos) + 1;
+                let locus = GenomeCoordinate {
+                    contig: coord.contig.clone(),
+                    pos,
+                };
+                CacheKey::new(&bam, &locus, &options)
+            })
+            .collect::<Vec<_>>();
+
+        let mut outputs = Vec::with_capacity(coords.len());
+        let mut missed = vec![];
+        for (i, key) in keys.iter().enumerate() {
+            let cached = cache.get(key).and_then(|value| {
+                bincode::deserialize(&value)
+                    .inspect_err(|err| {
+                        event!(Level::DEBUG, "Cannot decode the output at {}: {}", key, err)
+                    })
+                    .ok()
+            });
+            if cached.is_none() {
+                missed.push(i);
+            }
+            outputs.push(cached);
+        }
+        event!(
+            Level::DEBUG,
+            n_coords = coords.len(),
+            n_missed = missed.len(),
+            "cached query"
+        );
+        if missed.is_empty() {
+            return Ok(outputs.into_iter().map(Option::flatten).collect());
+        }
+
+        let missed_coords = missed
+            .iter()
+            .map(|&i| coords[i].clone())
+            .collect::<Vec<_>>();
+        let queried = self.query(&missed_coords)?;
+
+        let unchanged = FileStamp::of(self.processor.bam_path()).is_ok_and(|now| now == bam);
+        if !unchanged {
+            event!(
+                Level::WARN,
+                "{} changed during the query, its outputs are not cached",
+                bam.path.display()
+            );
+        }
+        for (i, output) in missed.into_iter().zip(queried) {
+            if unchanged {
+                match bincode::serialize(&output) {
+                    Ok(value) => cache.put(&keys[i], &value),
+                    Err(err) => event!(
+                        Level::WARN,
+                        "Cannot encode the output at {}: {}",
+                        keys[i],
+                        err
+                    ),
+                }
+            }
+            outputs[i] = Some(output);
+        }
+
+        Ok(outputs.into_iter().map(Option::flatten).collect())
+    }
+
+    /// Digest of what the outputs depend on besides the bam and the locus.
+    fn cache_options(&self) -> Result<[u8; 32], Error> {
+        let processor = &self.processor;
+        let pileup = processor.pileup_options;
+        let max_alignments = processor.max_alignments_per_locus.map_or(0, |max| max + 1);
+        let mut fields = vec![
+            std::any::type_name::<W>().as_bytes().to_vec(),
+            self.cache_tag.as_bytes().to_vec(),
+            pileup.max_depth.to_le_bytes().to_vec(),
+            vec![pileup.ignore_overlaps as u8],
+            max_alignments.to_le_bytes().to_vec(),
+        ];
+        if let Some(reference) = processor.reference_path() {
+            fields.extend(FileStamp::of(reference)?.fields());
+        }
+
+        Ok(digest_fields(fields))
+    }
+}
+
+/// A coordinate of a query with its index in the query.
+struct QueryInput<'a> {
+    index: usize,
+    coord: GenomeCoordinate<'a>,
+}
+
+impl<'a> BamLocusWorkInput<'a> for QueryInput<'a> {
+    fn genome_coordinate(&self) -> &GenomeCoordinate<'a> {
+        &self.coord
+    }
+}
+
+/// Runs the worker of an engine on [`QueryInput`]s, keeping their index.
+struct QueryWorker<W>(W);
+
+impl<'a, W> BamLocusWorker<'a> for QueryWorker<W>
+where
+    W: BamLocusWorker<'a, Input = GenomeCoordinate<'a>>,
+{
+    type Input = QueryInput<'a>;
+    type Output = (usize, W::Output);
+    type Error = W::Error;
+
+    fn work_for_locus(&self, plp: Pileup, input: Self::Input) -> Result<Self::Output, Self::Error> {
+        let output = self.0.work_for_locus(plp, input.coord)?;
+        Ok((input.index, output))
+    }
+
+    fn work_for_locus_with_ref(
+        &self,
+        plp: Pileup,
+        input: Self::Input,
+        ref_base: Option<Base>,
+    ) -> Result<Self::Output, Self::Error> {
+        let output = self.0.work_for_locus_with_ref(plp, input.coord, ref_base)?;
+        Ok((input.index, output))
+    }
+
+    fn work_for_locus_sampled(
+        &self,
+        plp: Pileup,
+        input: Self::Input,
+        ref_base: Option<Base>,
+        sample: SampledPileup,
+    ) -> Result<Self::Output, Self::Error> {
+        let output = self
+            .0
+            .work_for_locus_sampled(plp, input.coord, ref_base, sample)?;
+        Ok((input.index, output))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Arc, thread};
+
+    use super::*;
+    use crate::test_utils::TestBam;
+
+    struct DepthWorker;
+
+    impl<'a> BamLocusWorker<'a> for DepthWorker {
+        type Input = GenomeCoordinate<'a>;
+        type Output = (i64, u32);
+        type Error = anyhow::Error;
+
+        fn work_for_locus(
+            &self,
+            plp: Pileup,
+            input: Self::Input,
+        ) -> Result<(i64, u32), Self::Error> {
+            Ok((input.pos, plp.depth()))
+        }
+    }
+
+    fn assert_send_sync<T: Send + Sync>() {}
+
+    #[test]
+    fn test_query_matches_batches() -> Result<(), Box<dyn std::error::Error>> {
+        assert_send_sync::<LocusQueryEngine<DepthWorker>>();
+
+        let dir = tempfile::tempdir()?;
+        let bam_path = TestBam::new()
+            .contig("chr1", 10_000)
+            .contig("chr2", 10_000)
+            .add_reads("chr1", 100, 0, 3, 50)
+            .add_reads("chr1", 1_000, 0, 2, 50)
+            .add_reads("chr2", 500, 0, 1, 50)
+            .build(dir.path())?;
+
+        // unsorted, with loci without coverage and a repeated one.
+        let coords = [
+            ("chr1", 1_020),
+            ("chr1", 120),
+            ("chr2", 90),
+            ("chr2", 549),
+            ("chr1", 5_000),
+            ("chr1", 120),
+            ("chr2", 501),
+        ]
+        .map(|(contig, pos)| GenomeCoordinate {
+            contig: Chrom::from(contig),
+            pos,
+        });
+
+        let processor = ParallelLocusProcessorPileup::new(DepthWorker, 2, bam_path.clone());
+        let expected = processor.process_with_batch(coords.to_vec(), 1_000)?;
+        assert_eq!(
+            expected,
+            [(1_020, 2), (120, 3), (549, 1), (120, 3), (501, 1)]
+        );
+
+        for max_small_query in [coords.len(), 0] {
+            let engine = LocusQueryEngine::new(processor_for(&bam_path))?
+                .with_max_small_query(max_small_query);
+            let outputs = engine.query(&coords)?;
+            assert_eq!(outputs.len(), coords.len());
+            assert_eq!(outputs[2], None);
+            assert_eq!(outputs[4], None);
+            assert_eq!(outputs.into_iter().flatten().collect::<Vec<_>>(), expected);
+        }
+
+        let engine = Arc::new(LocusQueryEngine::new(processor_for(&bam_path))?.with_max_readers(2));
+        let (coords, expected) = (&coords, &expected);
+        thread::scope(|s| {
+            for _ in 0..4 {
+                let engine = Arc::clone(&engine);
+                s.spawn(move || {
+                    for _ in 0..10 {
+                        let outputs = engine.query(coords).unwrap();
+                        assert_eq!(outputs.into_iter().flatten().collect::<Vec<_>>(), *expected);
+                    }
+                });
+            }
+        });
+        assert!(engine.readers.lock().unwrap().len() <= 2);
+
+        let missing = engine.query(&[GenomeCoordinate {
+            contig: Chrom::Chr3,
+            pos: 100,
+        }]);
+        assert!(missing.is_err());
+
+        Ok(())
+    }
+
+    fn processor_for(bam_path: &std::path::Path) -> ParallelLocusProcessorPileup<DepthWorker> {
+        ParallelLocusProcessorPileup::new(DepthWorker, 2, bam_path.to_path_buf())
+    }
+
+    /// The depth of a column and the read names of the alignments it is given.
+    struct KeptWorker;
+
+    impl<'a> BamLocusWorker<'a> for KeptWorker {
+        type Input = GenomeCoordinate<'a>;
+        type Output = (usize, Vec<u64>);
+        type Error = anyhow::Error;
+
+        fn work_for_locus(
+            &self,
+            plp: Pileup,
+            _input: Self::Input,
+        ) -> Result<Self::Output, Self::Error> {
+            use crate::bam::pileup_ext::PileupExt;
+
+            let kept = plp
+                .typed_alignments()
+                .map(|a| a.qname_hash)
+                .collect::<Vec<_>>();
+            Ok((kept.len(), kept))
+        }
+
+        fn work_for_locus_sampled(
+            &self,
+            _plp: Pileup,
+            _input: Self::Input,
+            _ref_base: Option<Base>,
+            sample: SampledPileup,
+        ) -> Result<Self::Output, Self::Error> {
+            let kept = sample.alignments.iter().map(|a| a.qname_hash).collect();
+            Ok((sample.depth, kept))
+        }
+    }
+
+    #[test]
+    fn test_query_caps_alignments() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = tempfile::tempdir()?;
+        let bam_path = TestBam::new()
+            .contig("chr1", 10_000)
+            .add_reads("chr1", 100, 0, 40, 50)
+            .add_reads("chr1", 1_000, 0, 5, 50)
+            .build(dir.path())?;
+        let coords = [120, 1_020].map(|pos| GenomeCoordinate {
+            contig: Chrom::Chr1,
+            pos,
+        });
+
+        let capped = || {
+            ParallelLocusProcessorPileup::builder(&bam_path)
+                .worker(KeptWorker)
+                .threads(2)
+                .max_alignments_per_locus(10)
+                .build()
+        };
+        let expected = capped()?.process_with_batch(coords.to_vec(), 1_000)?;
+        assert_eq!((expected[0].0, expected[0].1.len()), (40, 10));
+        // a column under the cap is kept whole.
+        assert_eq!((expected[1].0, expected[1].1.len()), (5, 5));
+
+        // the same sample on both sides of the small query size.
+        for max_small_query in [coords.len(), 0] {
+            let engine = LocusQueryEngine::new(capped()?)?.with_max_small_query(max_small_query);
+            let outputs = engine.query(&coords)?;
+            assert_eq!(outputs.into_iter().flatten().collect::<Vec<_>>(), expected);
+        }
+
+        Ok(())
+    }
+
+    /// [`DepthWorker`] counting its calls.
+    #[cfg(feature = "serde")]
+    #[derive(Default)]
+    struct CountedDepth(std::sync::atomic::AtomicUsize);
+
+    #[cfg(feature = "serde")]
+    impl<'a> BamLocusWorker<'a> for CountedDepth {
+        type Input = GenomeCoordinate<'a>;
+        type Output = (i64, u32);
+        type Error = anyhow::Error;
+
+        fn work_for_locus(
+            &self,
+            plp: Pileup,
+            input: Self::Input,
+        ) -> Result<(i64, u32), Self::Error> {
+            self.0.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+            DepthWorker.work_for_locus(plp, input)
+        }
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_query_cached() -> Result<(), Box<dyn std::error::Error>> {
+        use std::{
+            fs::File,
+            sync::atomic::Ordering,
+            time::{Duration, SystemTime},
+        };
+
+        use crate::bam::cache::DiskCache;
+
+        let dir = tempfile::tempdir()?;
+        let bam_path = TestBam::new()
+            .add_reads("chr1", 100, 0, 3, 50)
+            .add_reads("chr1", 1_000, 0, 2, 50)
+            .build(dir.path())?;
+        let cache = DiskCache::open(dir.path().join("cache"), 1 << 20)?;
+        let coords = [("chr1", 120), ("chr1", 5_000), ("chr1", 1_020)].map(|(contig, pos)| {
+            GenomeCoordinate {
+                contig: Chrom::from(contig),
+                pos,
+            }
+        });
+        let engine = |tag: &str| -> Result<_, Error> {
+            let processor =
+                ParallelLocusProcessorPileup::new(CountedDepth::default(), 2, bam_path.clone());
+            Ok(LocusQueryEngine::new(processor)?.with_cache_tag(tag))
+        };
+        let n_called =
+            |engine: &LocusQueryEngine<CountedDepth>| engine.worker().0.load(Ordering::Relaxed);
+
+        let fresh = engine("")?.query(&coords)?;
+        assert_eq!(fresh, [Some((120, 3)), None, Some((1_020, 2))]);
+
+        let first = engine("")?;
+        assert_eq!(first.query_cached(&coords, &cache)?, fresh);
+        assert_eq!(n_called(&first), 2);
+        // the locus without coverage is cached too.
+        assert_eq!(cache.len(), 3);
+
+        // a hit of every coordinate, by another engine, does not run the worker.
+        let second = engine("")?;
+        assert_eq!(second.query_cached(&coords, &cache)?, fresh);
+        assert_eq!(n_called(&second), 0);
+        let more = [
+            coords[0].clone(),
+            GenomeCoordinate {
+                contig: Chrom::Chr1,
+                pos: 101,
+            },
+        ];
+        assert_eq!(
+            second.query_cached(&more, &cache)?,
+            [Some((120, 3)), Some((101, 3))]
+        );
+        assert_eq!(n_called(&second), 1);
+
+        // other worker options miss.
+        let tagged = engine("min_baseq=20")?;
+        assert_eq!(tagged.query_cached(&coords, &cache)?, fresh);
+        assert_eq!(n_called(&tagged), 2);
+
+        // touching the bam misses.
+        File::options()
+            .write(true)
+            .open(&bam_path)?
+            .set_modified(SystemTime::now() + Duration::from_secs(10))?;
+        let touched = engine("")?;
+        assert_eq!(touched.query_cached(&coords, &cache)?, fresh);
+        assert_eq!(n_called(&touched), 2);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,577 @@
+//! Opening and fetching indexed bams with retries, fetching of
+//! [`GenomeRegion`]s, and the records of a set of regions without pileups, see
+//! [`records_in_regions`].
+
+use std::{
+    ffi::OsString,
+    io,
+    path::{Path, PathBuf},
+};
+
+use rust_htslib::bam::{FetchDefinition, HeaderView, IndexedReader, Read as _, Record};
+use tracing::{Level, event};
+
+pub use crate::utils::retry::RetryPolicy;
+use crate::{
+    bam::filter::ReadFilter,
+    data::{
+        chrom::Chrom,
+        interval::RegionSet,
+        locus::{GenomeCoordinate, GenomeRegion},
+    },
+    err_opt_ext::edit_distance,
+    errors::Error,
+};
+
+/// Contigs listed in the error for a contig missing from a header.
+const N_CONTIG_SUGGESTIONS: usize = 5;
+
+/// Opens indexed bams for the processors.
+///
+/// [`HtslibOpener`] is the default; other implementations can e.g. inject
+/// failures in tests.
+pub trait BamOpener: Send + Sync {
+    fn open(&self, path: &Path) -> Result<IndexedReader, anyhow::Error>;
+}
+
+/// [`IndexedReader::from_path`].
+#[derive(Debug, Clone, Copy, Default)]
+pub struct HtslibOpener;
+
+impl BamOpener for HtslibOpener {
+    fn open(&self, path: &Path) -> Result<IndexedReader, anyhow::Error> {
+        Ok(IndexedReader::from_path(path)?)
+    }
+}
+
+/// Open `path` with `opener`, retrying with `policy`. The final error names the
+/// file, and is an [`Error::Io`] if it does not exist.
+pub fn open_with_retry(
+    opener: &dyn BamOpener,
+    policy: &RetryPolicy,
+    path: &Path,
+) -> Result<IndexedReader, Error> {
+    policy
+        .run(format_args!("Opening {}", path.display()), || {
+            opener.open(path)
+        })
+        .map_err(|err| {
+            if path.exists() {
+                Error::Other(err.context(format!("Failed to open {}", path.display())))
+            } else {
+                Error::io("open", path, io::ErrorKind::NotFound.into())
+            }
+        })
+}
+
+/// Fetch `contig`, or only its 0-based half-open `range`, retrying with `policy`.
+/// `"."` fetches every read. The final error is an [`Error::Fetch`].
+///
+/// A range out of the contig is clamped to it, see [`clamp_to_contig`].
+pub fn fetch_with_retry(
+    reader: &mut IndexedReader,
+    policy: &RetryPolicy,
+    path: &Path,
+    contig: &str,
+    range: Option<(i64, i64)>,
+) -> Result<(), Error> {
+    let range = range.map(
+        |(start, end)| match reader.header().tid(contig.as_bytes()) {
+            Some(tid) => clamp_to_contig(reader.header(), tid, contig, start, end),
+            None => (start, end),
+        },
+    );
+    // 1-based inclusive, like the regions of samtools.
+    let region = match range {
+        Some((start, end)) => format!("{}:{}-{}", contig, start + 1, end),
+        None => contig.to_string(),
+    };
+
+    policy
+        .run(
+            format_args!("Fetching {} from {}", region, path.display()),
+            || match range {
+                Some((start, end)) => reader.fetch((contig, start, end)),
+                None => reader.fetch(contig),
+            },
+        )
+        .map_err(|err| Error::Fetch {
+            region,
+            path: Some(path.to_path_buf()),
+            source: err.into(),
+        })
+}
+
+/// The region as is, with no contig name resolution: the name must be the one of
+/// the bam header.
+impl<'a> From<&'a GenomeRegion<'_>> for FetchDefinition<'a> {
+    fn from(region: &'a GenomeRegion<'_>) -> Self {
+        // 1-based inclusive to 0-based half-open.
+        FetchDefinition::RegionString(
+            region.contig.as_str().as_bytes(),
+            region.start - 1,
+            region.end,
+        )
+    }
+}
+
+impl GenomeRegion<'_> {
+    /// Fetch the region in `reader`.
+    ///
+    /// The contig is looked up in the header first, under its usual names
+    /// (`chr1` or `1`, `chrM` or `MT`). If it is not there, the error is an
+    /// [`Error::ContigNotFound`] with the closest contigs of the header, instead
+    /// of the generic error of htslib.
+    pub fn fetch_in(&self, reader: &mut IndexedReader) -> Result<(), Error> {
+        let tid = contig_tid(reader.header(), &self.contig);
+        fetch_tid(reader, &self.contig, tid, self.start, self.end)
+    }
+}
+
+/// Fetch `start..=end` (1-based) of `contig`, whose tid was looked up beforehand
+/// by [`contig_tid`]. Errors as [`GenomeRegion::fetch_in`].
+pub(crate) fn fetch_tid(
+    reader: &mut IndexedReader,
+    contig: &Chrom<'_>,
+    tid: Option<u32>,
+    start: i64,
+    end: i64,
+) -> Result<(), Error> {
+    let tid = tid.ok_or_else(|| missing_contig_error(reader.header(), contig))?;
+    let (start0, end) = clamp_to_contig(reader.header(), tid, contig.as_str(), start - 1, end);
+    reader
+        .fetch((tid, start0, end))
+        .map_err(|err| Error::Fetch {
+            region: format!("{}:{}-{}", contig, start, end),
+            path: None,
+            source: err.into(),
+        })
+}
+
+/// `start..end`, 0-based half-open, clamped to `0..len` of the contig `tid`,
+/// with a DEBUG event if it reaches out of it. A range wholly past the end
+/// becomes empty, at the end.
+///
+/// htslib fetches the reads hanging over the end of a contig for a region past
+/// it with some indexes, and rejects the region with others, so such a region
+/// would not fetch nothing as it should.
+pub(crate) fn clamp_to_contig(
+    header: &HeaderView,
+    tid: u32,
+    contig: &str,
+    start: i64,
+    end: i64,
+) -> (i64, i64) {
+    let len = contig_len(header, tid).unwrap_or(i64::MAX);
+    let clamped = (start.clamp(0, len), end.clamp(0, len));
+    if clamped != (start, end) {
+        event!(
+            Level::DEBUG,
+            "Region {}:{}-{} reaches out of the contig, of {} bp. Clamped to {}-{}",
+            contig,
+            start + 1,
+            end,
+            len,
+            clamped.0 + 1,
+            clamped.1
+        );
+    }
+    clamped
+}
+
+/// Length of the contig `tid` in `header`.
+pub(crate) fn contig_len(header: &HeaderView, tid: u32) -> Option<i64> {
+    header
+        .target_len(tid)
+        .and_then(|len| i64::try_from(len).ok())
+}
+
+impl GenomeCoordinate<'_> {
+    /// Fetch the base at the coordinate, as [`GenomeRegion::fetch_in`] does.
+    pub fn fetch_1bp_in(&self, reader: &mut IndexedReader) -> Result<(), Error> {
+        GenomeRegion::from(self).fetch_in(reader)
+    }
+}
+
+/// Index of the bam at `path`, looked for where htslib does: `{path}.bai`,
+/// `{path}.csi`, then the path with `.bai` in place of `.bam`.
+///
+/// The error is an [`Error::Io`] naming the bam, or every index path looked for.
+pub fn find_bam_index(path: impl AsRef<Path>) -> Result<PathBuf, Error> {
+    let path = path.as_ref();
+    let not_found = |msg: String| {
+        Error::io(
+            "find the index of",
+            path,
+            io::Error::new(io::ErrorKind::NotFound, msg),
+        )
+    };
+    if !path.is_file() {
+        return Err(not_found(format!("BAM {} does not exist", path.display())));
+    }
+
+    let with_suffix = |suffix: &str| {
+        let mut name = OsString::from(path.as_os_str());
+        name.push(suffix);
+        PathBuf::from(name)
+    };
+    let mut candidates = vec![with_suffix(".bai"), with_suffix(".csi")];
+    if path.extension().is_some_and(|ext| ext == "bam") {
+        candidates.push(path.with_extension("bai"));
+    }
+
+    match candidates.iter().find(|c| c.is_file()) {
+        Some(index) => Ok(index.clone()),
+        None => Err(not_found(format!(
+            "Index of {} not found, looked for {}. Create it with `samtools index`",
+            path.display(),
+            candidates
+                .iter()
+                .map(|c| c.display().to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        ))),
+    }
+}
+
+/// Whether the index of `reader` counts no record, on a contig or not, e.g. of
+/// a header-only bam.
+pub(crate) fn index_counts_no_records(reader: &mut IndexedReader) -> bool {
+    reader.index_stats().is_ok_and(|stats| {
+        stats
+            .iter()
+            .all(|&(_, _, mapped, unmapped)| mapped == 0 && unmapped == 0)
+    })
+}
+
+/// Name of `contig` in `header`: as is, then with and without the `chr` prefix.
+pub(crate) fn resolve_contig_name(header: &HeaderView, contig: &Chrom<'_>) -> Option<String> {
+    let mut candidates = vec![
+        contig.as_str().to_string(),
+        contig.to_prefixed().into_owned(),
+        contig.to_unprefixed().to_string(),
+    ];
+    if *contig == Chrom::ChrM {
+        candidates.push("MT".to_string());
+    }
+
+    candidates
+        .into_iter()
+        .find(|name| header.tid(name.as_bytes()).is_some())
+}
+
+/// Tid of `contig` in `header`, under any of the names of [`resolve_contig_name`].
+pub(crate) fn contig_tid(header: &HeaderView, contig: &Chrom<'_>) -> Option<u32> {
+    resolve_contig_name(header, contig).and_then(|name| header.tid(name.as_bytes()))
+}
+
+fn missing_contig_error(header: &HeaderView, contig: &Chrom<'_>) -> Error {
+    let query = contig.to_unprefixed().to_ascii_lowercase();
+    let mut names = header
+        .target_names()
+        .into_iter()
+        .map(|name| String::from_utf8_lossy(name).into_owned())
+        .collect::<Vec<_>>();
+    let n_contigs = names.len();
+
+    // stable, so contigs at the same distance keep the header order.
+    names.sort_by_cached_key(|name| {
+        let name = Chrom::from(name.as_str())
+            .to_unprefixed()
+            .to_ascii_lowercase();
+        edit_distance(name.as_bytes(), query.as_bytes())
+    });
+    names.truncate(N_CONTIG_SUGGESTIONS);
+
+    Error::ContigNotFound {
+        contig: contig.to_string(),
+        n_contigs,
+        suggestions: names,
+    }
+}
+
+/// The records overlapping `regions` in the bam at `bam_path`, each once, in
+/// the order of the bam, and passing `filter` if any.
+///
+/// The regions are merged first, so that overlapping ones are fetched once,
+/// then fetched in turn from a single reader. A record overlapping several
+/// merged regions is yielded by the first only. A contig not in the header is
+/// an [`Error::ContigNotFound`] before any record is read.
+///
+/// See [`records_in_regions_par`](crate::bam::fan_out::records_in_regions_par)
+/// to go through the regions in parallel.
+pub fn records_in_regions(
+    bam_path: impl AsRef<Path>,
+    regions: &[GenomeRegion<'_>],
+    filter: Option<&ReadFilter>,
+) -> Result<RegionRecords, Error> {
+    let reader = open_with_retry(&HtslibOpener, &RetryPolicy::default(), bam_path.as_ref())?;
+    let fetches = plan_region_fetches(reader.header(), regions)?;
+    Ok(RegionRecords {
+        reader,
+        fetches: fetches.into_iter(),
+        current: None,
+        filter: filter.cloned(),
+    })
+}
+
+/// Records of [`records_in_regions`].
+///
+/// A failed fetch or read is yielded as an error, then the records of the next
+/// region are read.
+pub struct RegionRecords {
+    reader: IndexedReader,
+    fetches: std::vec::IntoIter<RegionFetch>,
+    current: Option<RegionFetch>,
+    filter: Option<ReadFilter>,
+}
+
+impl Iterator for RegionRecords {
+    type Item = Result<Record, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let Some(fetch) = &self.current else {
+                let fetch = self.fetches.next()?;
+                if let Err(err) = fetch.fetch_in(&mut self.reader) {
+                    return Some(Err(err));
+                }
+                self.current = Some(fetch);
+                continue;
+            };
+
+            let mut record = Record::new();
+            match self.reader.read(&mut record) {
+                None => self.current = None,
+                Some(Err(err)) => {
+                    self.current = None;
+                    return Some(Err(err.into()));
+                }
+                Some(Ok(())) => {
+                    if fetch.yields(self.filter.as_ref(), &record) {
+                        return Some(Ok(record));
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// A merged region of [`records_in_regions`], resolved in the header.
+#[derive(Debug, Clone)]
+pub(crate) struct RegionFetch {
+    pub(crate) region: GenomeRegion<'static>,
+    /// Name of the contig in the header.
+    name: String,
+    tid: u32,
+    /// 0-based position before which records overlapping the region were
+    /// already yielded by the previous region of the contig: they overlap its
+    /// last base, as they reach this one.
+    seen_before: i64,
+}
+
+impl RegionFetch {
+    pub(crate) fn fetch_in(&self, reader: &mut IndexedReader) -> Result<(), Error> {
+        let GenomeRegion { contig, start, end } = &self.region;
+        fetch_tid(reader, contig, Some(self.tid), *start, *end)
+    }
+
+    /// Whether `record`, fetched with the region, is new and passes `filter`.
+    pub(crate) fn yields(&self, filter: Option<&ReadFilter>, record: &Record) -> bool {
+        record.pos() >= self.seen_before
+            && filter.is_none_or(|filter| filter.passes_on(&self.name, record))
+    }
+}
+
+/// `regions` merged, resolved in `header` and sorted in its order.
+pub(crate) fn plan_region_fetches(
+    header: &HeaderView,
+    regions: &[GenomeRegion<'_>],
+) -> Result<Vec<RegionFetch>, Error> {
+    let mut fetches = vec![];
+    for region in RegionSet::new(regions.iter().cloned(), true).merged() {
+        let tid = contig_tid(header, &region.contig)
+            .ok_or_else(|| missing_contig_error(header, &region.contig))?;
+        fetches.push(RegionFetch {
+            name: String::from_utf8_lossy(header.tid2name(tid)).into_owned(),
+            tid,
+            seen_before: i64::MIN,
+            region,
+        });
+    }
+    fetches.sort_by_key(|f| (f.tid, f.region.start));
+
+    for i in 1..fetches.len() {
+        if fetches[i - 1].tid == fetches[i].tid {
+            // 1-based inclusive end, so 0-based exclusive.
+            fetches[i].seen_before = fetches[i - 1].region.end;
+        }
+    }
+    Ok(fetches)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{bam::filter::FLAG_DUPLICATE, test_utils::TestBam};
+
+    #[test]
+    fn test_fetch_in() -> Result<(), anyhow::Error> {
+        let dir = tempfile::tempdir()?;
+        let bam_path = TestBam::new()
+            .contig("1", 10_000)
+            .contig("2", 10_000)
+            .contig("11", 10_000)
+            .contig("MT", 16_569)
+            .add_reads("1", 0, 10, 100, 50)
+            .add_reads("MT", 0, 10, 5, 50)
+            .build(dir.path())?;
+        let mut reader = IndexedReader::from_path(&bam_path)?;
+
+        // reads starting at 0-based 60 to 190.
+        GenomeRegion::from(("chr1", 101, 200)).fetch_in(&mut reader)?;
+        assert_eq!(reader.records().count(), 14);
+
+        let coord = GenomeCoordinate {
+            contig: Chrom::ChrM,
+            pos: 1,
+        };
+        coord.fetch_1bp_in(&mut reader)?;
+        assert_eq!(reader.records().count(), 1);
+
+        let err = GenomeRegion::from(("chr12", 1, 100))
+            .fetch_in(&mut reader)
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Contig chr12 is not in the bam header. Closest of its 4 contigs: 1, 2, 11, MT"
+        );
+        let Error::ContigNotFound {
+            contig,
+            n_contigs,
+            suggestions,
+        } = err
+        else {
+            panic!("expected ContigNotFound, got {:?}", err);
+        };
+        assert_eq!((contig.as_str(), n_contigs), ("chr12", 4));
+        assert_eq!(suggestions, ["1", "2", "11", "MT"]);
+
+        let region = GenomeRegion::from(("chr1", 101, 200));
+        let def = FetchDefinition::from(&region);
+        assert!(matches!(
+            def,
+            FetchDefinition::RegionString(b"chr1", 100, 200)
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_records_in_regions() -> Result<(), anyhow::Error> {
+        let dir = tempfile::tempdir()?;
+        let bam_path = TestBam::new()
+            .add_reads("chr1", 0, 7, 300, 50)
+            .add_read("chr1", 150, &[b'A'; 50], &[30; 50], FLAG_DUPLICATE)
+            .add_read("chr1", 160, &[b'A'; 50], &[30; 50], 0)
+            .with_mapq(5)
+            .add_reads("chr2", 0, 20, 50, 50)
+            .build(dir.path())?;
+
+        // overlapping regions, others a few bases apart, reached by the same
+        // reads, and chr2 under another name.
+        let regions = [
+            GenomeRegion::from(("chr1", 101, 200)),
+            GenomeRegion::from(("chr1", 150, 300)),
+            GenomeRegion::from(("chr1", 120, 130)),
+            GenomeRegion::from(("chr1", 320, 400)),
+            GenomeRegion::from(("chr1", 1_000, 1_010)),
+            GenomeRegion::from(("2", 500, 520)),
+            GenomeRegion::from(("chr2", 510, 700)),
+        ];
+
+        // every record of the bam overlapping a region.
+        let brute_force = |filter: Option<&ReadFilter>| -> Result<Vec<String>, anyhow::Error> {
+            let mut reader = IndexedReader::from_path(&bam_path)?;
+            reader.fetch(FetchDefinition::All)?;
+            let header = reader.header().clone();
+            let mut qnames = vec![];
+            for record in reader.records() {
+                let record = record?;
+                let contig = String::from_utf8_lossy(header.tid2name(record.tid() as u32));
+                let contig = Chrom::from(contig.as_ref());
+                let (start, end) = (record.pos() + 1, record.pos() + 50);
+                let overlaps = regions.iter().any(|r| {
+                    Chrom::from(r.contig.as_str()) == contig && r.start <= end && r.end >= start
+                });
+                if overlaps && filter.is_none_or(|f| f.passes(&record)) {
+                    qnames.push(String::from_utf8(record.qname().to_vec())?);
+                }
+            }
+            Ok(qnames)
+        };
+        let qnames = |filter: Option<&ReadFilter>| -> Result<Vec<String>, anyhow::Error> {
+            records_in_regions(&bam_path, &regions, filter)?
+                .map(|record| Ok(String::from_utf8(record?.qname().to_vec())?))
+                .collect()
+        };
+
+        for filter in [None, Some(ReadFilter::default().with_min_mapq(10))] {
+            let yielded = qnames(filter.as_ref())?;
+            let unique = yielded.iter().collect::<std::collections::HashSet<_>>();
+            assert_eq!(unique.len(), yielded.len(), "{:?}", yielded);
+            assert_eq!(yielded, brute_force(filter.as_ref())?);
+        }
+        // reads of chr1 at 56 to 399 and 945 to 1009, 0-based, and of chr2 at
+        // 460 to 699.
+        assert_eq!(qnames(None)?.len(), 50 + 2 + 9 + 12);
+        assert_eq!(
+            qnames(Some(&ReadFilter::default().with_min_mapq(10)))?.len(),
+            50 + 9 + 12
+        );
+
+        let missing = [GenomeRegion::from(("chr3", 1, 10))];
+        let err = records_in_regions(&bam_path, &missing, None).err().unwrap();
+        assert!(matches!(err, Error::ContigNotFound { .. }), "{:?}", err);
+        assert_eq!(records_in_regions(&bam_path, &[], None)?.count(), 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_fetch_past_contig_end() -> Result<(), anyhow::Error> {
+        let dir = tempfile::tempdir()?;
+        // the last read hangs over the end of the contig.
+        let bam_path = TestBam::new()
+            .contig("chr1", 1_000)
+            .add_read("chr1", 900, &[b'A'; 50], &[30; 50], 0)
+            .add_read("chr1", 980, &[b'A'; 50], &[30; 50], 0)
+            .build(dir.path())?;
+        let mut reader = IndexedReader::from_path(&bam_path)?;
+
+        GenomeRegion::from(("chr1", 901, 2_000)).fetch_in(&mut reader)?;
+        assert_eq!(reader.records().count(), 2);
+        // wholly past the end.
+        GenomeRegion::from(("chr1", 1_001, 1_100)).fetch_in(&mut reader)?;
+        assert_eq!(reader.records().count(), 0);
+        fetch_with_retry(
+            &mut reader,
+            &RetryPolicy::default(),
+            &bam_path,
+            "chr1",
+            Some((1_000, 1_100)),
+        )?;
+        assert_eq!(reader.records().count(), 0);
+        // before the start.
+        fetch_with_retry(
+            &mut reader,
+            &RetryPolicy::default(),
+            &bam_path,
+            "chr1",
+            Some((-100, 901)),
+        )?;
+        assert_eq!(reader.records().count(), 1);
+
+        Ok(())
+    }
+}
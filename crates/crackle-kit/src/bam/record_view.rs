@@ -0,0 +1,314 @@
+//! The accessors of an aligned record which [`ReadFilter`] and the tag helpers
+//! need, see [`RecordView`], so that they can be used on records of other
+//! libraries than rust_htslib.
+//!
+//! [`ReadFilter`]: crate::bam::filter::ReadFilter
+
+use rust_htslib::bam::{Record, record::Aux};
+
+use crate::bam::{cigar::RecordCigarExt, filter::FLAG_REVERSE};
+
+/// An aligned record, as read by [`ReadFilter`](crate::bam::filter::ReadFilter)
+/// and [`RecordUmiExt`](crate::bam::tags::RecordUmiExt).
+///
+/// Implemented for [`Record`], and for the `RecordBuf`s of noodles with the
+/// `noodles` feature; records of another library implement it to go through the
+/// same filters. The processors themselves only read [`Record`]s.
+pub trait RecordView {
+    /// 0-based leftmost position of the alignment, -1 if unplaced.
+    fn pos(&self) -> i64;
+
+    fn mapq(&self) -> u8;
+
+    /// SAM flags.
+    fn flags(&self) -> u16;
+
+    fn qname(&self) -> &[u8];
+
+    /// Value of the string (`Z`) tag `tag`, if the record has one.
+    fn aux_str(&self, tag: &[u8; 2]) -> Option<&str>;
+
+    /// Reference bases covered by the alignment, 0 without a CIGAR.
+    fn reference_span(&self) -> i64;
+
+    /// Length of the sequence, 0 if missing.
+    fn seq_len(&self) -> usize;
+
+    /// Base `i` of the sequence as stored, i.e. reverse complemented for
+    /// reverse strand records, as an uppercase ASCII byte.
+    fn seq_base(&self, i: usize) -> u8;
+
+    fn is_reverse(&self) -> bool {
+        self.flags() & FLAG_REVERSE != 0
+    }
+}
+
+impl RecordView for Record {
+    fn pos(&self) -> i64 {
+        Record::pos(self)
+    }
+
+    fn mapq(&self) -> u8 {
+        Record::mapq(self)
+    }
+
+    fn flags(&self) -> u16 {
+        Record::flags(self)
+    }
+
+    fn qname(&self) -> &[u8] {
+        Record::qname(self)
+    }
+
+    fn aux_str(&self, tag: &[u8; 2]) -> Option<&str> {
+        match self.aux(tag) {
+            Ok(Aux::String(value)) => Some(value),
+            _ => None,
+        }
+    }
+
+    fn reference_span(&self) -> i64 {
+        self.aligned_reference_span()
+    }
+
+    fn seq_len(&self) -> usize {
+        Record::seq_len(self)
+    }
+
+    fn seq_base(&self, i: usize) -> u8 {
+        self.seq()[i]
+    }
+
+    fn is_reverse(&self) -> bool {
+        Record::is_reverse(self)
+    }
+}
+
+/// `noodles_sam::alignment::Record` is the trait of lazily decoded records,
+/// whose fields cannot be borrowed; they go through the filters as their
+/// `RecordBuf`, e.g. of `RecordBuf::try_from_alignment_record`.
+#[cfg(feature = "noodles")]
+impl RecordView for noodles_sam::alignment::RecordBuf {
+    fn pos(&self) -> i64 {
+        self.alignment_start()
+            .map_or(-1, |pos| usize::from(pos) as i64 - 1)
+    }
+
+    fn mapq(&self) -> u8 {
+        // noodles has no mapping quality for the missing 255.
+        self.mapping_quality().map_or(255, |mapq| mapq.get())
+    }
+
+    fn flags(&self) -> u16 {
+        self.flags().bits()
+    }
+
+    fn qname(&self) -> &[u8] {
+        self.name().map_or(&[], |name| name.as_ref())
+    }
+
+    fn aux_str(&self, tag: &[u8; 2]) -> Option<&str> {
+        use noodles_sam::alignment::{record::data::field::Tag, record_buf::data::field::Value};
+
+        match self.data().get(&Tag::from(*tag)) {
+            Some(Value::String(value)) => std::str::from_utf8(value).ok(),
+            _ => None,
+        }
+    }
+
+    fn reference_span(&self) -> i64 {
+        self.cigar().alignment_span() as i64
+    }
+
+    fn seq_len(&self) -> usize {
+        self.sequence().len()
+    }
+
+    fn seq_base(&self, i: usize) -> u8 {
+        self.sequence().as_ref()[i].to_ascii_uppercase()
+    }
+
+    fn is_reverse(&self) -> bool {
+        self.flags().is_reverse_complemented()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use rust_htslib::bam::record::CigarString;
+
+    use super::*;
+    use crate::{
+        bam::{filter::ReadFilter, tags::RecordUmiExt},
+        data::{interval::RegionSet, locus::GenomeRegion},
+        utils::umi::UmiScheme,
+    };
+
+    /// A record of another library, as decoded fields.
+    struct PlainRecord {
+        qname: Vec<u8>,
+        flags: u16,
+        pos: i64,
+        mapq: u8,
+        span: i64,
+        seq: Vec<u8>,
+        read_group: Option<String>,
+    }
+
+    impl RecordView for PlainRecord {
+        fn pos(&self) -> i64 {
+            self.pos
+        }
+
+        fn mapq(&self) -> u8 {
+            self.mapq
+        }
+
+        fn flags(&self) -> u16 {
+            self.flags
+        }
+
+        fn qname(&self) -> &[u8] {
+            &self.qname
+        }
+
+        fn aux_str(&self, tag: &[u8; 2]) -> Option<&str> {
+            (tag == b"RG")
+                .then_some(self.read_group.as_deref())
+                .flatten()
+        }
+
+        fn reference_span(&self) -> i64 {
+            self.span
+        }
+
+        fn seq_len(&self) -> usize {
+            self.seq.len()
+        }
+
+        fn seq_base(&self, i: usize) -> u8 {
+            self.seq[i]
+        }
+    }
+
+    impl PlainRecord {
+        fn to_htslib(&self) -> Record {
+            let cigar = CigarString::try_from(format!("{}M", self.span).as_str()).unwrap();
+            let mut record = Record::new();
+            record.set(
+                &self.qname,
+                Some(&cigar),
+                &self.seq,
+                &vec![30; self.seq.len()],
+            );
+            record.set_flags(self.flags);
+            record.set_pos(self.pos);
+            record.set_mapq(self.mapq);
+            if let Some(rg) = &self.read_group {
+                record.push_aux(b"RG", Aux::String(rg)).unwrap();
+            }
+            record
+        }
+
+        #[cfg(feature = "noodles")]
+        fn to_noodles(&self) -> noodles_sam::alignment::RecordBuf {
+            use noodles_core::Position;
+            use noodles_sam::alignment::{
+                RecordBuf,
+                record::{
+                    Flags, MappingQuality,
+                    cigar::{Op, op::Kind},
+                },
+                record_buf::data::field::Value,
+            };
+
+            let mut builder = RecordBuf::builder()
+                .set_name(self.qname.clone())
+                .set_flags(Flags::from_bits_retain(self.flags))
+                .set_alignment_start(Position::try_from(self.pos as usize + 1).unwrap())
+                .set_mapping_quality(MappingQuality::new(self.mapq).unwrap())
+                .set_cigar(
+                    [Op::new(Kind::Match, self.span as usize)]
+                        .into_iter()
+                        .collect(),
+                )
+                .set_sequence(self.seq.clone().into());
+            if let Some(rg) = &self.read_group {
+                builder = builder.set_data(
+                    [(*b"RG", Value::from(rg.as_str()))]
+                        .into_iter()
+                        .map(|(tag, value)| (tag.into(), value))
+                        .collect(),
+                );
+            }
+            builder.build()
+        }
+    }
+
+    #[test]
+    fn test_filter_through_both_views() {
+        let record = |flags: u16, pos: i64, mapq: u8, read_group: Option<&str>| PlainRecord {
+            qname: b"read1_ACGT".to_vec(),
+            flags,
+            pos,
+            mapq,
+            span: 6,
+            seq: b"AACCGT".to_vec(),
+            read_group: read_group.map(str::to_string),
+        };
+        let records = [
+            record(0, 100, 60, Some("rg1")),
+            record(FLAG_REVERSE, 195, 60, Some("rg1")),
+            record(0, 100, 10, Some("rg1")),
+            record(0x400, 100, 60, Some("rg1")),
+            record(0, 100, 60, Some("rg2")),
+            record(0, 100, 60, None),
+            record(0, 200, 60, Some("rg1")),
+            record(0, 94, 60, Some("rg1")),
+        ];
+        let filter = ReadFilter::default()
+            .with_min_mapq(20)
+            .read_groups(HashSet::from(["rg1".to_string()]))
+            .within_regions(RegionSet::new(
+                [GenomeRegion::from(("chr1", 101, 200))],
+                false,
+            ));
+
+        let plain = records
+            .iter()
+            .map(|r| filter.passes_on("chr1", r))
+            .collect::<Vec<_>>();
+        let htslib = records
+            .iter()
+            .map(|r| filter.passes_on("chr1", &r.to_htslib()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            plain,
+            [true, true, false, false, false, false, false, false]
+        );
+        assert_eq!(plain, htslib);
+        #[cfg(feature = "noodles")]
+        {
+            let noodles = records
+                .iter()
+                .map(|r| filter.passes_on("chr1", &r.to_noodles()))
+                .collect::<Vec<_>>();
+            assert_eq!(plain, noodles);
+        }
+
+        for scheme in [UmiScheme::ReadIdSuffix, UmiScheme::SequencePrefix(4)] {
+            for r in &records[..2] {
+                let htslib = r.to_htslib();
+                assert_eq!(r.umi(scheme), htslib.umi(scheme));
+                #[cfg(feature = "noodles")]
+                assert_eq!(r.umi(scheme), r.to_noodles().umi(scheme));
+            }
+        }
+        assert_eq!(
+            records[1].umi(UmiScheme::SequencePrefix(4)).as_deref(),
+            Some(&b"ACGG"[..])
+        );
+    }
+}
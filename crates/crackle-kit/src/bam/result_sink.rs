@@ -0,0 +1,456 @@
+//! Destinations of the outputs of
+//! [`ParallelLocusProcessorPileup::process_with_batch_streaming`], written a
+//! batch at a time as the batches are done rather than collected, e.g. to a TSV
+//! per contig by [`PerContigTsvSink`].
+//!
+//! [`ParallelLocusProcessorPileup::process_with_batch_streaming`]: crate::bam::process::ParallelLocusProcessorPileup::process_with_batch_streaming
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt::Display,
+    fs,
+    io::Write,
+    path::PathBuf,
+    sync::Mutex,
+    thread::{self, JoinHandle},
+};
+
+use anyhow::{Context, anyhow};
+use crossbeam_channel::{Receiver, Sender, bounded};
+
+use crate::{
+    bam::output::CONTIG_PLACEHOLDER, data::chrom::Chrom, err_opt_ext::HashMapExt, errors::Error,
+    table::TableWriter, utils::atomic_write::AtomicFile,
+};
+
+/// Batches buffered for the writer of a shard of [`PerContigTsvSink`].
+const SHARD_CHANNEL_CAPACITY: usize = 4;
+
+/// Where the outputs of a run go, a batch at a time.
+pub trait LocusResultSink<O>: Sync {
+    /// What the sink returns once the run is over, e.g. the files written.
+    type Summary;
+
+    /// Called once before any batch, with the contig of each batch in input
+    /// order.
+    fn start(&mut self, batch_contigs: &[Chrom<'_>]) -> Result<(), Error>;
+
+    /// Take the outputs of the batch `batch_idx`, in input order. It is called
+    /// once per batch, even without outputs, from the thread which ran it, so
+    /// the batches come in any order.
+    fn write_batch(&self, batch_idx: usize, outputs: Vec<O>) -> Result<(), Error>;
+
+    /// Called once the batches are done, `succeeded` unless one of them or a
+    /// write failed; the batches after a failed one may then be missing.
+    fn finish(self, succeeded: bool) -> Result<Self::Summary, Error>;
+}
+
+/// A file of [`PerContigTsvSink`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ShardFile {
+    /// As [`Chrom::as_str`].
+    pub contig: String,
+    pub path: PathBuf,
+    /// Rows written, without the header.
+    pub n_rows: u64,
+}
+
+/// The files written by [`PerContigTsvSink`], by the order of the first batch
+/// of their contig.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ShardManifest {
+    pub files: Vec<ShardFile>,
+}
+
+impl ShardManifest {
+    /// Rows of all the files.
+    pub fn n_rows(&self) -> u64 {
+        self.files.iter().map(|file| file.n_rows).sum()
+    }
+}
+
+/// Writes the outputs of each contig to a TSV in `dir`, named by `template`
+/// with [`CONTIG_PLACEHOLDER`] replaced by the contig name, see
+/// [`path_safe`], e.g. `depth.{contig}.tsv`. Each row is made by `to_row` from
+/// an output, and each file starts with the header line of `headers`.
+///
+/// Each contig is written by a thread of its own, from the first batch on it
+/// to the last, which writes the batches in input order. A file is only created once an
+/// output of its contig is written, so contigs without outputs have none.
+/// Files are written next to their path and renamed to it once the run
+/// succeeds, see [`AtomicFile`].
+pub struct PerContigTsvSink<F> {
+    dir: PathBuf,
+    template: String,
+    headers: Vec<String>,
+    to_row: F,
+    /// Contig of each batch, as [`Chrom::as_str`].
+    batch_contigs: Vec<String>,
+    /// Batches of each contig, in input order.
+    contig_batches: HashMap<String, Vec<usize>>,
+    /// Contigs by the order of their first batch.
+    contigs: Vec<String>,
+    shards: Mutex<HashMap<String, Shard>>,
+}
+
+impl<F> PerContigTsvSink<F> {
+    pub fn new(
+        dir: impl Into<PathBuf>,
+        template: impl Into<String>,
+        headers: &[&str],
+        to_row: F,
+    ) -> Self {
+        Self {
+            dir: dir.into(),
+            template: template.into(),
+            headers: headers.iter().map(|h| h.to_string()).collect(),
+            to_row,
+            batch_contigs: vec![],
+            contig_batches: HashMap::new(),
+            contigs: vec![],
+            shards: Mutex::default(),
+        }
+    }
+
+    fn path(&self, contig: &str) -> PathBuf {
+        self.dir.join(
+            self.template
+                .replace(CONTIG_PLACEHOLDER, &path_safe(contig)),
+        )
+    }
+
+    fn spawn_shard(&self, contig: &str) -> Result<Shard, anyhow::Error> {
+        let (tx, rx) = bounded(SHARD_CHANNEL_CAPACITY);
+        let writer = ShardWriter {
+            path: self.path(contig),
+            header: self.headers.join("\t"),
+            batches: self.contig_batches[contig].clone(),
+        };
+        let handle = thread::Builder::new()
+            .name(format!("shard-{}", contig))
+            .spawn(move || writer.run(rx))
+            .context("Failed to spawn a shard writer")?;
+        Ok(Shard { tx, handle })
+    }
+}
+
+impl<O, R, F> LocusResultSink<O> for PerContigTsvSink<F>
+where
+    F: Fn(&O) -> R + Sync,
+    R: IntoIterator,
+    R::Item: Display,
+{
+    type Summary = ShardManifest;
+
+    fn start(&mut self, batch_contigs: &[Chrom<'_>]) -> Result<(), Error> {
+        if !self.template.contains(CONTIG_PLACEHOLDER) {
+            return Err(anyhow!(
+                "Template {} of the per-contig output has no {}",
+                self.template,
+                CONTIG_PLACEHOLDER
+            )
+            .into());
+        }
+        for (batch_idx, contig) in batch_contigs.iter().enumerate() {
+            let contig = contig.as_str();
+            if !self.contig_batches.contains_key(contig) {
+                self.contigs.push(contig.to_string());
+            }
+            self.contig_batches
+                .entry(contig.to_string())
+                .or_default()
+                .push(batch_idx);
+            self.batch_contigs.push(contig.to_string());
+        }
+
+        let mut paths = HashMap::new();
+        for contig in &self.contigs {
+            if let Some(other) = paths.insert(self.path(contig), contig) {
+                return Err(anyhow!(
+                    "Contigs {} and {} have the same file name {}",
+                    other,
+                    contig,
+                    self.path(contig).display()
+                )
+                .into());
+            }
+        }
+        fs::create_dir_all(&self.dir).map_err(|err| Error::io("create", &self.dir, err))?;
+
+        Ok(())
+    }
+
+    fn write_batch(&self, batch_idx: usize, outputs: Vec<O>) -> Result<(), Error> {
+        let mut rows = TableWriter::headerless(vec![], self.headers.len());
+        for output in &outputs {
+            rows.write_row((self.to_row)(output))?;
+        }
+        let rows = ShardRows {
+            batch_idx,
+            bytes: rows.into_inner()?,
+            n_rows: outputs.len() as u64,
+        };
+
+        let contig = &self.batch_contigs[batch_idx];
+        let tx = {
+            let mut shards = self.shards.lock().unwrap();
+            let shard =
+                shards.entry_or_try_insert_with(contig.clone(), || self.spawn_shard(contig))?;
+            shard.tx.clone()
+        };
+        // the writer only stops early on an error, returned by `finish`.
+        tx.send(rows)
+            .map_err(|_| anyhow!("The writer of {} stopped", contig))?;
+
+        Ok(())
+    }
+
+    fn finish(self, succeeded: bool) -> Result<ShardManifest, Error> {
+        let mut shards = self.shards.into_inner().unwrap();
+
+        let mut written = vec![];
+        let mut first_err = None;
+        for contig in &self.contigs {
+            let Some(Shard { tx, handle }) = shards.remove(contig) else {
+                continue;
+            };
+            drop(tx);
+            let res = handle
+                .join()
+                .map_err(|_| anyhow!("The writer of {} panicked", contig));
+            match res.map_err(Error::from).and_then(|res| res) {
+                Ok(Some(file)) => written.push((contig, file)),
+                Ok(None) => {}
+                Err(err) => {
+                    first_err.get_or_insert(err);
+                }
+            }
+        }
+        if let Some(err) = first_err {
+            return Err(err);
+        }
+        // the files of a failed run are removed when dropped.
+        if !succeeded {
+            return Ok(ShardManifest::default());
+        }
+
+        let mut manifest = ShardManifest::default();
+        for (contig, file) in written {
+            let path = file.file.path().to_path_buf();
+            file.file.commit()?;
+            manifest.files.push(ShardFile {
+                contig: contig.clone(),
+                path,
+                n_rows: file.n_rows,
+            });
+        }
+
+        Ok(manifest)
+    }
+}
+
+/// `contig` usable in a file name: characters other than ASCII letters, digits,
+/// `.`, `-` and `_` become `_`, e.g. `HLA-A_01_01` for `HLA-A*01:01`.
+pub fn path_safe(contig: &str) -> String {
+    contig
+        .chars()
+        .map(|c| match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' | '.' | '-' | '_' => c,
+            _ => '_',
+        })
+        .collect()
+}
+
+struct Shard {
+    tx: Sender<ShardRows>,
+    handle: JoinHandle<Result<Option<OpenShardFile>, Error>>,
+}
+
+/// The rows of a batch, formatted by the thread which ran it.
+struct ShardRows {
+    batch_idx: usize,
+    bytes: Vec<u8>,
+    n_rows: u64,
+}
+
+struct OpenShardFile {
+    file: AtomicFile,
+    n_rows: u64,
+}
+
+/// The thread writing a contig of [`PerContigTsvSink`].
+struct ShardWriter {
+    path: PathBuf,
+    header: String,
+    /// Batches of the contig, in input order.
+    batches: Vec<usize>,
+}
+
+impl ShardWriter {
+    /// Write the batches received in input order, those which arrive before the
+    /// batches preceding them being held until these do. Returns once the last
+    /// batch is written, or once the sink is finished.
+    fn run(self, rx: Receiver<ShardRows>) -> Result<Option<OpenShardFile>, Error> {
+        let mut file = None;
+        let mut pending = BTreeMap::new();
+        let mut next = 0;
+
+        while next < self.batches.len() {
+            let Ok(rows) = rx.recv() else {
+                break;
+            };
+            pending.insert(rows.batch_idx, rows);
+
+            while let Some(rows) = self.batches.get(next).and_then(|i| pending.remove(i)) {
+                next += 1;
+                if rows.n_rows == 0 {
+                    continue;
+                }
+                let file = match &mut file {
+                    Some(file) => file,
+                    None => file.insert(self.create()?),
+                };
+                file.file
+                    .file()
+                    .write_all(&rows.bytes)
+                    .map_err(|err| Error::io("write", &self.path, err))?;
+                file.n_rows += rows.n_rows;
+            }
+        }
+
+        Ok(file)
+    }
+
+    fn create(&self) -> Result<OpenShardFile, Error> {
+        let mut file = AtomicFile::create(&self.path)?;
+        writeln!(file.file(), "{}", self.header)
+            .map_err(|err| Error::io("write", &self.path, err))?;
+        Ok(OpenShardFile { file, n_rows: 0 })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::bail;
+    use rust_htslib::bam::pileup::Pileup;
+
+    use super::*;
+    use crate::{
+        bam::process::{BamLocusWorker, ParallelLocusProcessorPileup},
+        data::locus::GenomeCoordinate,
+        test_utils::TestBam,
+    };
+
+    /// The contig, position and depth of each locus; fails at `fail_at`.
+    struct ContigDepth {
+        fail_at: Option<i64>,
+    }
+
+    impl<'a> BamLocusWorker<'a> for ContigDepth {
+        type Input = GenomeCoordinate<'a>;
+        type Output = (String, i64, u32);
+        type Error = anyhow::Error;
+
+        fn work_for_locus(
+            &self,
+            plp: Pileup,
+            inp: Self::Input,
+        ) -> Result<Self::Output, Self::Error> {
+            if self.fail_at == Some(inp.pos) {
+                bail!("failed at {}", inp.pos);
+            }
+            Ok((inp.contig.to_string(), inp.pos, plp.depth()))
+        }
+    }
+
+    fn sink(
+        dir: &std::path::Path,
+    ) -> PerContigTsvSink<impl Fn(&(String, i64, u32)) -> [String; 3]> {
+        PerContigTsvSink::new(
+            dir,
+            "depth.{contig}.tsv",
+            &["contig", "pos", "depth"],
+            |(contig, pos, depth): &(String, i64, u32)| {
+                [contig.clone(), pos.to_string(), depth.to_string()]
+            },
+        )
+    }
+
+    #[test]
+    fn test_per_contig_tsv_sink() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = tempfile::tempdir()?;
+        let bam_path = TestBam::new()
+            .add_reads("chr1", 0, 10, 200, 50)
+            .add_reads("chr2", 500, 3, 300, 40)
+            .add_reads("chrUn_KI270302v1", 0, 20, 10, 30)
+            .build(dir.path())?;
+        let inputs = || {
+            ["chr1", "chr2", "chrUn_KI270302v1"]
+                .into_iter()
+                .flat_map(|contig| {
+                    (1..=3_000)
+                        .step_by(7)
+                        .map(move |pos| GenomeCoordinate::new(contig.to_string(), pos).unwrap())
+                })
+                .collect::<Vec<_>>()
+        };
+        let plp = ParallelLocusProcessorPileup::new(ContigDepth { fail_at: None }, 4, bam_path);
+        let expected = plp.process_with_batch(inputs(), 100)?;
+
+        let out_dir = dir.path().join("shards");
+        let manifest = plp.process_with_batch_streaming(inputs(), 100, sink(&out_dir))?;
+        assert_eq!(manifest.n_rows(), expected.len() as u64);
+        let contigs = manifest
+            .files
+            .iter()
+            .map(|f| f.contig.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(contigs, ["chr1", "chr2", "chrUn_KI270302v1"]);
+
+        for file in &manifest.files {
+            assert_eq!(
+                file.path,
+                out_dir.join(format!("depth.{}.tsv", file.contig))
+            );
+            let content = fs::read_to_string(&file.path)?;
+            let mut lines = content.lines();
+            assert_eq!(lines.next(), Some("contig\tpos\tdepth"));
+            let rows = lines.collect::<Vec<_>>();
+            // the rows of the contig, in input order.
+            let expected_rows = expected
+                .iter()
+                .filter(|(contig, ..)| *contig == file.contig)
+                .map(|(contig, pos, depth)| format!("{}\t{}\t{}", contig, pos, depth))
+                .collect::<Vec<_>>();
+            assert_eq!(rows, expected_rows);
+            assert_eq!(file.n_rows, rows.len() as u64);
+            assert!(file.n_rows > 0);
+        }
+
+        // a failed run leaves no file.
+        let plp = plp.with_worker(ContigDepth {
+            fail_at: Some(1_002),
+        });
+        let failed_dir = dir.path().join("failed");
+        let err = plp
+            .process_with_batch_streaming(inputs(), 100, sink(&failed_dir))
+            .unwrap_err();
+        assert!(format!("{:#}", err).contains("failed at 1002"), "{:#}", err);
+        assert_eq!(fs::read_dir(&failed_dir)?.count(), 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_path_safe() {
+        assert_eq!(path_safe("chr1"), "chr1");
+        assert_eq!(path_safe("HLA-A*01:01"), "HLA-A_01_01");
+        assert_eq!(path_safe("../x y"), ".._x_y");
+
+        let dir = tempfile::tempdir().unwrap();
+        let mut sink = sink(dir.path());
+        let contigs = [Chrom::from("a:b"), Chrom::from("a*b")];
+        let err = LocusResultSink::<(String, i64, u32)>::start(&mut sink, &contigs).unwrap_err();
+        assert!(err.to_string().contains("the same file name"), "{}", err);
+    }
+}
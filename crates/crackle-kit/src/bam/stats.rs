@@ -0,0 +1,686 @@
+//! Ready-made summary statistics over a bam file.
+
+use std::{fmt, io::Write, path::Path};
+
+use anyhow::{Error, anyhow};
+use rust_htslib::bam::{self, FetchDefinition, IndexedReader, Read, Record};
+
+use crate::{
+    bam::{
+        cigar::RecordCigarExt,
+        fan_out::{par_map_contigs, par_map_regions},
+        filter::ReadFilter,
+    },
+    data::locus::GenomeRegion,
+    table::TableWriter,
+};
+
+/// Options for [`insert_size_distribution`].
+#[derive(Debug)]
+pub struct InsertSizeOptions<'a> {
+    /// Insert sizes above this are counted in [`InsertSizeStats::n_above_max`] only.
+    pub max_insert_size: usize,
+    pub read_filter: ReadFilter,
+    /// Restrict to pairs whose first read starts in one of these regions.
+    pub regions: Option<Vec<GenomeRegion<'a>>>,
+    /// Contigs (or regions) are processed in parallel with this many threads.
+    pub n_threads: usize,
+}
+
+impl Default for InsertSizeOptions<'_> {
+    fn default() -> Self {
+        Self {
+            max_insert_size: 2000,
+            read_filter: ReadFilter::default(),
+            regions: None,
+            n_threads: 1,
+        }
+    }
+}
+
+/// Insert size (|TLEN|) distribution of proper pairs.
+///
+/// Summary values are computed from the histogram, so pairs above the maximum
+/// insert size are not included in them. `mad` is the raw median absolute
+/// deviation, without the normal-consistency scale factor.
+#[derive(Debug, Clone, PartialEq)]
+pub struct InsertSizeStats {
+    /// Pairs in the histogram.
+    pub n_pairs: u64,
+    pub n_above_max: u64,
+    pub mean: f64,
+    pub median: f64,
+    pub mad: f64,
+    /// Sample standard deviation.
+    pub std_dev: f64,
+    /// Count per insert size, indexed by insert size.
+    pub histogram: Vec<u64>,
+}
+
+impl InsertSizeStats {
+    fn from_histogram(histogram: Vec<u64>, n_above_max: u64) -> Self {
+        let n_pairs = histogram.iter().sum::<u64>();
+
+        let (mean, std_dev) = if n_pairs == 0 {
+            (f64::NAN, f64::NAN)
+        } else {
+            let n = n_pairs as f64;
+            let mean = histogram
+                .iter()
+                .enumerate()
+                .map(|(size, &c)| size as f64 * c as f64)
+                .sum::<f64>()
+                / n;
+            let sq_sum = histogram
+                .iter()
+                .enumerate()
+                .map(|(size, &c)| (size as f64 - mean).powi(2) * c as f64)
+                .sum::<f64>();
+            let std_dev = if n_pairs > 1 {
+                (sq_sum / (n - 1.0)).sqrt()
+            } else {
+                0.0
+            };
+            (mean, std_dev)
+        };
+
+        let median = weighted_median(histogram.iter().enumerate().map(|(s, &c)| (s as f64, c)));
+
+        let mut deviations = histogram
+            .iter()
+            .enumerate()
+            .map(|(s, &c)| ((s as f64 - median).abs(), c))
+            .collect::<Vec<_>>();
+        deviations.sort_by(|a, b| a.0.total_cmp(&b.0));
+        let mad = weighted_median(deviations.into_iter());
+
+        Self {
+            n_pairs,
+            n_above_max,
+            mean,
+            median,
+            mad,
+            std_dev,
+            histogram,
+        }
+    }
+
+    /// Write summary values as a `metric\tvalue` table.
+    pub fn write_summary_tsv(&self, w: impl Write) -> Result<(), Error> {
+        let mut tw = TableWriter::new(w, &["metric", "value"])?;
+        tw.write_row(["n_pairs".to_string(), self.n_pairs.to_string()])?;
+        tw.write_row(["n_above_max".to_string(), self.n_above_max.to_string()])?;
+        tw.write_row(["mean".to_string(), self.mean.to_string()])?;
+        tw.write_row(["median".to_string(), self.median.to_string()])?;
+        tw.write_row(["mad".to_string(), self.mad.to_string()])?;
+        tw.write_row(["std_dev".to_string(), self.std_dev.to_string()])?;
+        tw.into_inner()?;
+        Ok(())
+    }
+
+    /// Write non-zero histogram bins as an `insert_size\tcount` table.
+    pub fn write_histogram_tsv(&self, w: impl Write) -> Result<(), Error> {
+        let mut tw = TableWriter::new(w, &["insert_size", "count"])?;
+        for (size, &count) in self.histogram.iter().enumerate() {
+            if count > 0 {
+                tw.write_row([size as u64, count])?;
+            }
+        }
+        tw.into_inner()?;
+        Ok(())
+    }
+}
+
+/// Median of values sorted ascending, given with their counts.
+/// The two middle values are averaged for an even total count.
+fn weighted_median(sorted: impl Iterator<Item = (f64, u64)> + Clone) -> f64 {
+    let total = sorted.clone().map(|(_, c)| c).sum::<u64>();
+    if total == 0 {
+        return f64::NAN;
+    }
+
+    // 1-based ranks of the middle values.
+    let (lo_rank, hi_rank) = (total.div_ceil(2), total / 2 + 1);
+    let (mut lo, mut hi) = (None, None);
+    let mut cum = 0;
+    for (v, c) in sorted {
+        cum += c;
+        if lo.is_none() && cum >= lo_rank {
+            lo = Some(v);
+        }
+        if cum >= hi_rank {
+            hi = Some(v);
+            break;
+        }
+    }
+
+    (lo.unwrap() + hi.unwrap()) / 2.0
+}
+
+struct InsertSizeCounter<'f> {
+    histogram: Vec<u64>,
+    n_above_max: u64,
+    read_filter: &'f ReadFilter,
+}
+
+impl<'f> InsertSizeCounter<'f> {
+    fn new(max_insert_size: usize, read_filter: &'f ReadFilter) -> Self {
+        Self {
+            histogram: vec![0; max_insert_size + 1],
+            n_above_max: 0,
+            read_filter,
+        }
+    }
+
+    /// Count proper pairs on `contig`, once per pair via the first read.
+    /// `start_range` limits counted reads to those starting in it (0-based,
+    /// half-open).
+    fn count(
+        mut self,
+        reader: &mut IndexedReader,
+        contig: &str,
+        start_range: Option<(i64, i64)>,
+    ) -> Result<Self, Error> {
+        let mut record = Record::new();
+        while let Some(r) = reader.read(&mut record) {
+            r?;
+
+            let is_first_of_proper_pair =
+                record.is_paired() && record.is_proper_pair() && record.is_first_in_template();
+            if !is_first_of_proper_pair || !self.read_filter.passes_on(contig, &record) {
+                continue;
+            }
+            if let Some((start, end)) = start_range
+                && (record.pos() < start || record.pos() >= end)
+            {
+                continue;
+            }
+
+            let size = record.insert_size().unsigned_abs() as usize;
+            if size == 0 {
+                continue;
+            }
+            match self.histogram.get_mut(size) {
+                Some(c) => *c += 1,
+                None => self.n_above_max += 1,
+            }
+        }
+        Ok(self)
+    }
+
+    fn merge(mut self, other: Self) -> Self {
+        self.histogram
+            .iter_mut()
+            .zip(other.histogram)
+            .for_each(|(a, b)| *a += b);
+        self.n_above_max += other.n_above_max;
+        self
+    }
+}
+
+/// Insert size distribution of proper pairs in an indexed bam.
+///
+/// Only the first read of each pair is looked at, so pairs are counted once.
+/// Reads rejected by `opts.read_filter` and pairs with a zero TLEN are skipped.
+pub fn insert_size_distribution(
+    bam_path: impl AsRef<Path>,
+    opts: &InsertSizeOptions<'_>,
+) -> Result<InsertSizeStats, crate::Error> {
+    let new_counter = || InsertSizeCounter::new(opts.max_insert_size, &opts.read_filter);
+
+    let counters = match opts.regions.as_ref() {
+        None => par_map_contigs(bam_path, opts.n_threads, |contig, reader| {
+            new_counter().count(reader, contig, None)
+        })?
+        .into_iter()
+        .map(|(_, c)| c)
+        .collect::<Vec<_>>(),
+        Some(regions) => par_map_regions(bam_path, regions, opts.n_threads, |region, reader| {
+            // A pair is assigned to the region where its first read starts,
+            // so adjacent regions do not count it twice.
+            new_counter().count(
+                reader,
+                region.contig.as_str(),
+                Some((region.start - 1, region.end)),
+            )
+        })?,
+    };
+
+    let total = counters
+        .into_iter()
+        .fold(new_counter(), InsertSizeCounter::merge);
+
+    Ok(InsertSizeStats::from_histogram(
+        total.histogram,
+        total.n_above_max,
+    ))
+}
+
+/// Mapped reads looked at for the mean aligned length of [`estimate_mean_coverage`].
+const N_LENGTH_SAMPLE: usize = 10_000;
+
+/// Mean depth over the contigs of the header of an indexed bam.
+///
+/// Estimated from the mapped read counts of the index and the mean aligned length
+/// of the first mapped reads, so only the index and a few reads are read. Like the
+/// counts of the index, secondary and supplementary alignments are included.
+pub fn estimate_mean_coverage(bam_path: impl AsRef<Path>) -> Result<f64, crate::Error> {
+    let mut reader = IndexedReader::from_path(bam_path)?;
+
+    let (mut n_mapped, mut genome_len) = (0, 0);
+    for (tid, len, mapped, _) in reader.index_stats()? {
+        if tid >= 0 {
+            n_mapped += mapped;
+            genome_len += len;
+        }
+    }
+    if genome_len == 0 {
+        return Err(anyhow!("The bam header has no contig with a length").into());
+    }
+
+    reader.fetch(".")?;
+    let (mut n_sampled, mut aligned_len) = (0, 0);
+    let mut record = Record::new();
+    while n_sampled < N_LENGTH_SAMPLE {
+        match reader.read(&mut record) {
+            Some(res) => res?,
+            None => break,
+        }
+        if !record.is_unmapped() {
+            n_sampled += 1;
+            aligned_len += record.aligned_reference_span();
+        }
+    }
+    if n_sampled == 0 {
+        return Ok(0.0);
+    }
+
+    let mean_len = aligned_len as f64 / n_sampled as f64;
+    Ok(n_mapped as f64 * mean_len / genome_len as f64)
+}
+
+/// Counts of [`Flagstat`], of the reads passing or failing the QC (flag 0x200).
+///
+/// Counted as by `samtools flagstat`: the pair counts are of primary reads only,
+/// and the mapped and duplicate counts of all of them.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FlagCounts {
+    pub total: u64,
+    pub primary: u64,
+    pub secondary: u64,
+    pub supplementary: u64,
+    pub duplicates: u64,
+    pub primary_duplicates: u64,
+    pub mapped: u64,
+    pub primary_mapped: u64,
+    pub paired: u64,
+    pub read1: u64,
+    pub read2: u64,
+    /// Mapped reads flagged as properly paired.
+    pub properly_paired: u64,
+    pub both_mapped: u64,
+    /// Mapped reads of an unmapped mate.
+    pub singletons: u64,
+    pub mate_on_other_contig: u64,
+    /// Of [`Self::mate_on_other_contig`], those of a mapq of 5 or more.
+    pub mate_on_other_contig_mapq5: u64,
+}
+
+impl FlagCounts {
+    fn add(&mut self, record: &Record) {
+        self.total += 1;
+        let mapped = !record.is_unmapped();
+        if record.is_secondary() {
+            self.secondary += 1;
+        } else if record.is_supplementary() {
+            self.supplementary += 1;
+        } else {
+            self.primary += 1;
+            if record.is_paired() {
+                self.paired += 1;
+                self.properly_paired += (record.is_proper_pair() && mapped) as u64;
+                self.read1 += record.is_first_in_template() as u64;
+                self.read2 += record.is_last_in_template() as u64;
+                if mapped && record.is_mate_unmapped() {
+                    self.singletons += 1;
+                }
+                if mapped && !record.is_mate_unmapped() {
+                    self.both_mapped += 1;
+                    if record.mtid() != record.tid() {
+                        self.mate_on_other_contig += 1;
+                        self.mate_on_other_contig_mapq5 += (record.mapq() >= 5) as u64;
+                    }
+                }
+            }
+            self.primary_mapped += mapped as u64;
+            self.primary_duplicates += record.is_duplicate() as u64;
+        }
+        self.mapped += mapped as u64;
+        self.duplicates += record.is_duplicate() as u64;
+    }
+
+    fn merge(self, other: Self) -> Self {
+        Self {
+            total: self.total + other.total,
+            primary: self.primary + other.primary,
+            secondary: self.secondary + other.secondary,
+            supplementary: self.supplementary + other.supplementary,
+            duplicates: self.duplicates + other.duplicates,
+            primary_duplicates: self.primary_duplicates + other.primary_duplicates,
+            mapped: self.mapped + other.mapped,
+            primary_mapped: self.primary_mapped + other.primary_mapped,
+            paired: self.paired + other.paired,
+            read1: self.read1 + other.read1,
+            read2: self.read2 + other.read2,
+            properly_paired: self.properly_paired + other.properly_paired,
+            both_mapped: self.both_mapped + other.both_mapped,
+            singletons: self.singletons + other.singletons,
+            mate_on_other_contig: self.mate_on_other_contig + other.mate_on_other_contig,
+            mate_on_other_contig_mapq5: self.mate_on_other_contig_mapq5
+                + other.mate_on_other_contig_mapq5,
+        }
+    }
+}
+
+/// Flag counts of a bam, as of `samtools flagstat`, whose layout its `Display`
+/// follows.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Flagstat {
+    pub qc_passed: FlagCounts,
+    pub qc_failed: FlagCounts,
+}
+
+impl Flagstat {
+    fn add(&mut self, record: &Record) {
+        match record.is_quality_check_failed() {
+            false => self.qc_passed.add(record),
+            true => self.qc_failed.add(record),
+        }
+    }
+
+    fn merge(self, other: Self) -> Self {
+        Self {
+            qc_passed: self.qc_passed.merge(other.qc_passed),
+            qc_failed: self.qc_failed.merge(other.qc_failed),
+        }
+    }
+
+    fn count(mut self, reader: &mut impl Read) -> Result<Self, Error> {
+        let mut record = Record::new();
+        while let Some(res) = reader.read(&mut record) {
+            res?;
+            self.add(&record);
+        }
+        Ok(self)
+    }
+}
+
+impl fmt::Display for Flagstat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (p, q) = (&self.qc_passed, &self.qc_failed);
+        let percent = |n: u64, total: u64| match total {
+            0 => "N/A".to_string(),
+            _ => format!("{:.2}%", n as f64 / total as f64 * 100.0),
+        };
+        let line = |f: &mut fmt::Formatter<'_>, count: fn(&FlagCounts) -> u64, what: &str| {
+            writeln!(f, "{} + {} {}", count(p), count(q), what)
+        };
+        let line_percent = |f: &mut fmt::Formatter<'_>,
+                            count: fn(&FlagCounts) -> u64,
+                            of: fn(&FlagCounts) -> u64,
+                            what: &str| {
+            writeln!(
+                f,
+                "{} + {} {} ({} : {})",
+                count(p),
+                count(q),
+                what,
+                percent(count(p), of(p)),
+                percent(count(q), of(q))
+            )
+        };
+
+        line(
+            f,
+            |c| c.total,
+            "in total (QC-passed reads + QC-failed reads)",
+        )?;
+        line(f, |c| c.primary, "primary")?;
+        line(f, |c| c.secondary, "secondary")?;
+        line(f, |c| c.supplementary, "supplementary")?;
+        line(f, |c| c.duplicates, "duplicates")?;
+        line(f, |c| c.primary_duplicates, "primary duplicates")?;
+        line_percent(f, |c| c.mapped, |c| c.total, "mapped")?;
+        line_percent(f, |c| c.primary_mapped, |c| c.primary, "primary mapped")?;
+        line(f, |c| c.paired, "paired in sequencing")?;
+        line(f, |c| c.read1, "read1")?;
+        line(f, |c| c.read2, "read2")?;
+        line_percent(f, |c| c.properly_paired, |c| c.paired, "properly paired")?;
+        line(f, |c| c.both_mapped, "with itself and mate mapped")?;
+        line_percent(f, |c| c.singletons, |c| c.paired, "singletons")?;
+        line(
+            f,
+            |c| c.mate_on_other_contig,
+            "with mate mapped to a different chr",
+        )?;
+        line(
+            f,
+            |c| c.mate_on_other_contig_mapq5,
+            "with mate mapped to a different chr (mapQ>=5)",
+        )
+    }
+}
+
+/// [`Flagstat`] of the bam at `bam_path`, read once from start to end with
+/// `n_threads` htslib decompression threads. No index is needed.
+pub fn flagstat(bam_path: impl AsRef<Path>, n_threads: usize) -> Result<Flagstat, crate::Error> {
+    let mut reader = bam::Reader::from_path(bam_path)?;
+    if n_threads > 1 {
+        reader.set_threads(n_threads)?;
+    }
+    Ok(Flagstat::default().count(&mut reader)?)
+}
+
+/// [`flagstat`] of an indexed bam, its contigs counted in parallel on
+/// `n_threads`, then the unmapped reads without a contig.
+pub fn par_flagstat(
+    bam_path: impl AsRef<Path>,
+    n_threads: usize,
+) -> Result<Flagstat, crate::Error> {
+    let bam_path = bam_path.as_ref();
+    let per_contig = par_map_contigs(bam_path, n_threads, |_, reader| {
+        Flagstat::default().count(reader)
+    })?;
+
+    let mut reader = IndexedReader::from_path(bam_path)?;
+    reader.fetch(FetchDefinition::Unmapped)?;
+    let unplaced = Flagstat::default().count(&mut reader)?;
+
+    Ok(per_contig
+        .into_iter()
+        .map(|(_, counts)| counts)
+        .fold(unplaced, Flagstat::merge))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        bam::filter::{FLAG_DUPLICATE, FLAG_QC_FAIL, FLAG_SECONDARY, FLAG_SUPPLEMENTARY},
+        data::interval::RegionSet,
+        test_utils::TestBam,
+    };
+
+    #[test]
+    fn test_weighted_median() {
+        let v = |xs: &[(f64, u64)]| weighted_median(xs.iter().copied());
+
+        assert_eq!(v(&[(1.0, 1), (2.0, 1), (3.0, 1)]), 2.0);
+        assert_eq!(v(&[(1.0, 1), (2.0, 1), (3.0, 1), (4.0, 1)]), 2.5);
+        assert_eq!(v(&[(1.0, 0), (2.0, 3), (9.0, 1)]), 2.0);
+        assert!(v(&[]).is_nan());
+    }
+
+    #[test]
+    fn test_estimate_mean_coverage() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = tempfile::tempdir()?;
+        // 27 kb aligned to chr1, 9 kb to chr2, over 2 x 9 kb: 2x.
+        let bam_path = TestBam::new()
+            .contig("chr1", 9_000)
+            .contig("chr2", 9_000)
+            .add_reads("chr1", 0, 30, 270, 100)
+            .add_read("chr2", 0, &[b'A'; 100], &[30; 100], 0)
+            .with_cigar("10S90M")
+            .add_reads("chr2", 100, 90, 99, 90)
+            .build(dir.path())?;
+
+        let coverage = estimate_mean_coverage(&bam_path)?;
+        assert!((coverage - 2.0).abs() < 0.1, "{}", coverage);
+
+        // not a bam.
+        let text_path = dir.path().join("reads.txt");
+        std::fs::write(&text_path, "not a bam\n")?;
+        let err = estimate_mean_coverage(&text_path).unwrap_err();
+        assert!(matches!(err, crate::Error::Htslib(_)), "{:?}", err);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_insert_size_distribution() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = tempfile::tempdir()?;
+        // read length 50: insert size = mate_pos + 50 - pos.
+        let bam_path = TestBam::new()
+            .add_pair("chr1", 100, 250, 50, 0) // 200
+            .add_pair("chr1", 1000, 1250, 50, 0) // 300
+            .add_pair("chr1", 5000, 5350, 50, 0) // 400
+            .add_pair("chr1", 9000, 9250, 50, FLAG_DUPLICATE) // excluded
+            .add_pair("chr2", 100, 450, 50, 0) // 400
+            .add_pair("chr2", 2000, 4950, 50, 0) // 3000, above max
+            .build(dir.path())?;
+
+        let stats = insert_size_distribution(
+            &bam_path,
+            &InsertSizeOptions {
+                n_threads: 2,
+                ..Default::default()
+            },
+        )?;
+
+        assert_eq!(stats.n_pairs, 4);
+        assert_eq!(stats.n_above_max, 1);
+        assert_eq!(stats.mean, 325.0);
+        assert_eq!(stats.median, 350.0);
+        // deviations from 350: 150, 50, 50, 50
+        assert_eq!(stats.mad, 50.0);
+        assert!((stats.std_dev - 95.7427).abs() < 1e-4);
+        assert_eq!(stats.histogram.len(), 2001);
+        assert_eq!(stats.histogram[400], 2);
+
+        let mut hist_tsv = vec![];
+        stats.write_histogram_tsv(&mut hist_tsv)?;
+        assert_eq!(
+            String::from_utf8(hist_tsv)?,
+            "insert_size\tcount\n200\t1\n300\t1\n400\t2\n"
+        );
+
+        let mut summary_tsv = vec![];
+        stats.write_summary_tsv(&mut summary_tsv)?;
+        assert!(String::from_utf8(summary_tsv)?.contains("median\t350\n"));
+
+        // restricted to regions
+        let stats = insert_size_distribution(
+            &bam_path,
+            &InsertSizeOptions {
+                regions: Some(vec![
+                    GenomeRegion::from(("chr1", 1, 1000)),
+                    GenomeRegion::from(("chr1", 1001, 2000)),
+                ]),
+                ..Default::default()
+            },
+        )?;
+        assert_eq!(stats.n_pairs, 2);
+        assert_eq!(stats.mean, 250.0);
+
+        // filtered by a region set, over whole contigs.
+        let targets = RegionSet::new(
+            [
+                GenomeRegion::from(("chr1", 5001, 5100)),
+                GenomeRegion::from(("chr2", 1, 200)),
+            ],
+            false,
+        );
+        let stats = insert_size_distribution(
+            &bam_path,
+            &InsertSizeOptions {
+                read_filter: ReadFilter::default().within_regions(targets),
+                ..Default::default()
+            },
+        )?;
+        assert_eq!(stats.n_pairs, 2);
+        assert_eq!(stats.histogram[400], 2);
+
+        Ok(())
+    }
+
+    /// What `samtools flagstat` prints for the bam of [`test_flagstat`].
+    const FLAGSTAT: &str = "\
+11 + 1 in total (QC-passed reads + QC-failed reads)
+9 + 1 primary
+1 + 0 secondary
+1 + 0 supplementary
+2 + 0 duplicates
+2 + 0 primary duplicates
+9 + 1 mapped (81.82% : 100.00%)
+7 + 1 primary mapped (77.78% : 100.00%)
+8 + 0 paired in sequencing
+4 + 0 read1
+4 + 0 read2
+4 + 0 properly paired (50.00% : N/A)
+6 + 0 with itself and mate mapped
+1 + 0 singletons (12.50% : N/A)
+2 + 0 with mate mapped to a different chr
+1 + 0 with mate mapped to a different chr (mapQ>=5)
+";
+
+    #[test]
+    fn test_flagstat() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = tempfile::tempdir()?;
+        let (seq, qual) = (&[b'A'; 50], &[30; 50]);
+        let bam_path = TestBam::new()
+            .contig("chr1", 10_000)
+            .contig("chr2", 10_000)
+            // proper pairs, the second of duplicates.
+            .add_pair("chr1", 100, 300, 50, 0)
+            .add_pair("chr1", 1_000, 1_200, 50, FLAG_DUPLICATE)
+            // a singleton and its unmapped mate.
+            .add_read("chr1", 2_000, seq, qual, 0x1 | 0x8 | 0x40)
+            .with_qname("single")
+            .with_mate(2_000, 0)
+            .add_read("chr1", 2_000, seq, qual, 0x1 | 0x4 | 0x80)
+            .with_qname("single")
+            .with_mate(2_000, 0)
+            // mates on different contigs, the second of mapq 3.
+            .add_read("chr1", 3_000, seq, qual, 0x1 | 0x40)
+            .with_qname("split")
+            .with_mate_on("chr2", 500)
+            .add_read("chr2", 500, seq, qual, 0x1 | 0x80)
+            .with_qname("split")
+            .with_mate_on("chr1", 3_000)
+            .with_mapq(3)
+            .add_read("chr1", 150, seq, qual, FLAG_SECONDARY)
+            .add_read("chr2", 800, seq, qual, FLAG_SUPPLEMENTARY)
+            .add_read("chr1", 4_000, seq, qual, FLAG_QC_FAIL)
+            .add_unplaced(seq, qual)
+            .build(dir.path())?;
+
+        let stats = flagstat(&bam_path, 2)?;
+        assert_eq!(stats.to_string(), FLAGSTAT);
+        assert_eq!(stats.qc_passed.mate_on_other_contig_mapq5, 1);
+        assert_eq!(par_flagstat(&bam_path, 2)?, stats);
+
+        Ok(())
+    }
+}
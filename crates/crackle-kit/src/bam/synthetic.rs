@@ -0,0 +1,207 @@
+//! Synthetic bams of any size, for benchmarks which need no files shipped.
+//!
+//! ```no_run
+//! use crackle_kit::bam::synthetic::SyntheticBam;
+//!
+//! let dir = std::env::temp_dir();
+//! let bam = SyntheticBam::new(1_000_000).with_contigs(24);
+//! let bam_path = bam.build(&dir)?;
+//! let loci = bam.loci(100_000);
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+
+use std::path::{Path, PathBuf};
+
+use rust_htslib::bam::{
+    self, Header, Writer,
+    header::HeaderRecord,
+    record::{Cigar, CigarString},
+};
+
+use crate::{
+    data::{chrom::Chrom, locus::GenomeCoordinate},
+    errors::Error,
+};
+
+/// A coordinate-sorted, indexed bam of unpaired reads tiled at a fixed step
+/// along the first contigs of [`Chrom::typical_chroms`], the records spread
+/// evenly over them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SyntheticBam {
+    n_records: usize,
+    n_contigs: usize,
+    read_len: usize,
+    step: usize,
+}
+
+impl SyntheticBam {
+    /// `n_records` reads of 100 bp on one contig, one every 10 bp.
+    pub fn new(n_records: usize) -> Self {
+        Self {
+            n_records,
+            n_contigs: 1,
+            read_len: 100,
+            step: 10,
+        }
+    }
+
+    /// Spread the reads over `n_contigs` contigs.
+    ///
+    /// # Panics
+    ///
+    /// If `n_contigs` is not within 1 to 24.
+    pub fn with_contigs(mut self, n_contigs: usize) -> Self {
+        assert!(
+            (1..=24).contains(&n_contigs),
+            "synthetic bams have 1 to 24 contigs, got {}",
+            n_contigs
+        );
+        self.n_contigs = n_contigs;
+        self
+    }
+
+    pub fn with_read_len(mut self, read_len: usize) -> Self {
+        self.read_len = read_len;
+        self
+    }
+
+    /// Start a read every `step` bp.
+    pub fn with_step(mut self, step: usize) -> Self {
+        self.step = step.max(1);
+        self
+    }
+
+    pub fn n_records(&self) -> usize {
+        self.n_records
+    }
+
+    pub fn contigs(&self) -> Vec<Chrom<'static>> {
+        Chrom::typical_chroms()[..self.n_contigs].to_vec()
+    }
+
+    /// Records on contig `i`, the first contigs having one more when they do
+    /// not divide evenly.
+    pub fn records_on(&self, i: usize) -> usize {
+        self.n_records / self.n_contigs + usize::from(i < self.n_records % self.n_contigs)
+    }
+
+    /// Length of every contig, covering the reads of the first one.
+    pub fn contig_len(&self) -> u64 {
+        (self.records_on(0) * self.step + self.read_len) as u64
+    }
+
+    /// Write the bam and its `.bai` index to `synthetic.bam` in `dir`.
+    pub fn build(&self, dir: impl AsRef<Path>) -> Result<PathBuf, Error> {
+        let path = dir.as_ref().join("synthetic.bam");
+        self.write(&path)?;
+        Ok(path)
+    }
+
+    /// Write the bam and its `.bai` index to `path`.
+    pub fn write(&self, path: impl AsRef<Path>) -> Result<(), Error> {
+        let path = path.as_ref();
+        let mut header = Header::new();
+        header.push_record(
+            HeaderRecord::new(b"HD")
+                .push_tag(b"VN", "1.6")
+                .push_tag(b"SO", "coordinate"),
+        );
+        for contig in self.contigs() {
+            header.push_record(
+                HeaderRecord::new(b"SQ")
+                    .push_tag(b"SN", contig.as_str())
+                    .push_tag(b"LN", self.contig_len()),
+            );
+        }
+
+        {
+            let mut writer = Writer::from_path(path, &header, bam::Format::Bam)?;
+            let cigar = CigarString(vec![Cigar::Match(self.read_len as u32)]);
+            let bases = b"ACGT".repeat(self.read_len / 4 + 2);
+            let qual = vec![30; self.read_len];
+            let mut record = bam::Record::new();
+
+            for tid in 0..self.n_contigs {
+                for i in 0..self.records_on(tid) {
+                    // the bases shift by one from read to read.
+                    let seq = &bases[i % 4..i % 4 + self.read_len];
+                    record.set(
+                        format!("read{}_{}", tid, i).as_bytes(),
+                        Some(&cigar),
+                        seq,
+                        &qual,
+                    );
+                    record.set_tid(tid as i32);
+                    record.set_pos((i * self.step) as i64);
+                    record.set_mapq(60);
+                    record.set_mtid(-1);
+                    record.set_mpos(-1);
+                    writer.write(&record)?;
+                }
+            }
+        }
+        bam::index::build(path, None, bam::index::Type::Bai, 1)?;
+
+        Ok(())
+    }
+
+    /// `n_loci` sorted loci spread evenly over the reads of each contig, for
+    /// pileups of the bam.
+    pub fn loci(&self, n_loci: usize) -> Vec<GenomeCoordinate<'static>> {
+        self.contigs()
+            .into_iter()
+            .enumerate()
+            .flat_map(|(tid, contig)| {
+                let n_on_contig =
+                    n_loci / self.n_contigs + usize::from(tid < n_loci % self.n_contigs);
+                let span = (self.records_on(tid) * self.step).max(1);
+                let step = (span / n_on_contig.max(1)).max(1);
+                (0..n_on_contig).map(move |i| GenomeCoordinate {
+                    contig: contig.clone(),
+                    pos: 1 + (i * step) as i64,
+                })
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_htslib::bam::{IndexedReader, Read as _};
+
+    use super::*;
+
+    #[test]
+    fn test_synthetic_bam() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = tempfile::tempdir()?;
+        let bam = SyntheticBam::new(1_001).with_contigs(3).with_read_len(50);
+        let bam_path = bam.build(dir.path())?;
+        assert_eq!(bam.contig_len(), 334 * 10 + 50);
+
+        let mut reader = IndexedReader::from_path(&bam_path)?;
+        for (tid, expected) in [334, 334, 333].into_iter().enumerate() {
+            reader.fetch(tid as u32)?;
+            let mut n_records = 0;
+            let mut last_pos = -1;
+            for record in reader.records() {
+                let record = record?;
+                assert_eq!(record.seq_len(), 50);
+                assert!(record.pos() > last_pos);
+                last_pos = record.pos();
+                n_records += 1;
+            }
+            assert_eq!(n_records, expected);
+        }
+
+        let loci = bam.loci(100);
+        assert_eq!(loci.len(), 100);
+        assert_eq!(loci.iter().filter(|l| l.contig == Chrom::Chr3).count(), 33);
+        assert!(loci.is_sorted_by_key(|l| (l.contig.clone(), l.pos)));
+        assert!(
+            loci.iter()
+                .all(|l| l.pos >= 1 && l.pos as u64 <= bam.contig_len())
+        );
+
+        Ok(())
+    }
+}
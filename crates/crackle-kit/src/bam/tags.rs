@@ -0,0 +1,92 @@
+//! Read tag helpers on [`Record`]s, or any [`RecordView`], matching their fastq
+//! counterparts.
+
+use std::borrow::Cow;
+
+#[cfg(doc)]
+use rust_htslib::bam::Record;
+
+#[cfg(doc)]
+use crate::fastq::FastqRecord;
+use crate::{
+    bam::record_view::RecordView, data::bases::comp::complement_base, utils::umi::UmiScheme,
+};
+
+/// UMI queries on a [`Record`], or any [`RecordView`], giving the same bytes as
+/// [`FastqRecord::umi`] on the read the record was aligned from.
+pub trait RecordUmiExt {
+    /// UMI of the record, if it has a valid one under `scheme`.
+    ///
+    /// UMIs of the read id are borrowed from the qname. A sequence prefix is
+    /// decoded from the 4-bit packed sequence, and for reverse strand records
+    /// taken from the end of the sequence and reverse complemented, i.e. as
+    /// sequenced.
+    fn umi(&self, scheme: UmiScheme) -> Option<Cow<'_, [u8]>>;
+}
+
+impl<R: RecordView + ?Sized> RecordUmiExt for R {
+    fn umi(&self, scheme: UmiScheme) -> Option<Cow<'_, [u8]>> {
+        let UmiScheme::SequencePrefix(len) = scheme else {
+            return scheme.extract(self.qname(), &[]).map(Cow::Borrowed);
+        };
+
+        let seq_len = self.seq_len();
+        if seq_len < len {
+            return None;
+        }
+        let prefix = if self.is_reverse() {
+            (seq_len - len..seq_len)
+                .rev()
+                .map(|i| complement_base(self.seq_base(i)))
+                .collect::<Vec<_>>()
+        } else {
+            (0..len).map(|i| self.seq_base(i)).collect()
+        };
+
+        scheme.extract(&[], &prefix)?;
+        Some(Cow::Owned(prefix))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_htslib::bam::Record;
+
+    use super::*;
+
+    fn record(qname: &[u8], seq: &[u8], reverse: bool) -> Record {
+        let mut record = Record::new();
+        record.set(qname, None, seq, &vec![30; seq.len()]);
+        if reverse {
+            record.set_reverse();
+        }
+        record
+    }
+
+    #[test]
+    fn test_record_umi() {
+        let r = record(b"A01:1:FC:1:1101:1000:2000:ACGTACGT", b"ACGTTT", false);
+        assert_eq!(
+            r.umi(UmiScheme::IlluminaReadId).as_deref(),
+            Some(&b"ACGTACGT"[..])
+        );
+        assert_eq!(r.umi(UmiScheme::ReadIdSuffix), None);
+        assert_eq!(
+            r.umi(UmiScheme::SequencePrefix(4)).as_deref(),
+            Some(&b"ACGT"[..])
+        );
+        assert_eq!(r.umi(UmiScheme::SequencePrefix(7)), None);
+
+        let r = record(b"read1_GGNA", b"AACCGT", true);
+        assert_eq!(
+            r.umi(UmiScheme::ReadIdSuffix).as_deref(),
+            Some(&b"GGNA"[..])
+        );
+        assert_eq!(r.umi(UmiScheme::IlluminaReadId), None);
+        // read as sequenced: ACGGTT.
+        assert_eq!(
+            r.umi(UmiScheme::SequencePrefix(4)).as_deref(),
+            Some(&b"ACGG"[..])
+        );
+    }
+}
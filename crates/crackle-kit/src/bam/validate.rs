@@ -0,0 +1,328 @@
+//! Quick checks of a bam before a long run over it, see [`quick_check`]: that
+//! it ends with the BGZF EOF block, that its header parses, and that its index
+//! is not older than it.
+//!
+//! The processors run them while planning, and refuse to start on an error
+//! under [`InputCheck::Strict`].
+
+use std::{
+    fmt,
+    fs::{self, File},
+    io::{self, Read as _, Seek, SeekFrom},
+    path::{Path, PathBuf},
+};
+
+use rust_htslib::bam::{self, IndexedReader, Read as _};
+
+use crate::{bam::reader::find_bam_index, errors::Error};
+
+/// The empty BGZF block ending every complete bam.
+pub const BGZF_EOF: [u8; 28] = [
+    0x1f, 0x8b, 0x08, 0x04, 0x00, 0x00, 0x00, 0x00, 0x00, 0xff, 0x06, 0x00, 0x42, 0x43, 0x02, 0x00,
+    0x1b, 0x00, 0x03, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+];
+
+/// Length of the windows fetched by the seek check, about a bin of the index.
+const SEEK_WINDOW: u64 = 16 * 1024;
+
+/// What a processor does with the findings of [`quick_check`] of its input.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum InputCheck {
+    /// Do not check the input.
+    Skip,
+    /// Log every finding as a warning.
+    Warn,
+    /// Refuse to start on an [`Severity::Error`] finding; log warnings.
+    #[default]
+    Strict,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Severity {
+    /// The bam can be read, but may not be what was meant, e.g. of a stale
+    /// index.
+    Warning,
+    /// The bam is truncated or cannot be read.
+    Error,
+}
+
+/// The check a [`Finding`] is of.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum CheckKind {
+    /// The BGZF EOF block at the end of the file.
+    Eof,
+    Header,
+    /// The age of the index against the bam.
+    Index,
+    /// Fetches through the index, see [`QuickCheckOptions::with_seek`].
+    Seek,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Finding {
+    pub severity: Severity,
+    pub check: CheckKind,
+    pub message: String,
+}
+
+/// The findings of [`quick_check`] of a bam.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct BamCheckReport {
+    pub path: PathBuf,
+    /// The index of the bam, if found. A missing index is not a finding, as
+    /// not every use of a bam needs one.
+    pub index: Option<PathBuf>,
+    pub findings: Vec<Finding>,
+}
+
+/// Options of [`quick_check_with`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct QuickCheckOptions {
+    seek: bool,
+}
+
+impl QuickCheckOptions {
+    /// Also fetch the first 16 kb of the first contig with reads and the last
+    /// 16 kb of the last one through the index, to check the bam can be seeked
+    /// into. Off by default, as it reads records.
+    pub fn with_seek(mut self, seek: bool) -> Self {
+        self.seek = seek;
+        self
+    }
+}
+
+impl BamCheckReport {
+    /// Whether any finding is an [`Severity::Error`].
+    pub fn has_errors(&self) -> bool {
+        self.errors().next().is_some()
+    }
+
+    pub fn errors(&self) -> impl Iterator<Item = &Finding> {
+        self.findings
+            .iter()
+            .filter(|finding| finding.severity == Severity::Error)
+    }
+
+    pub fn warnings(&self) -> impl Iterator<Item = &Finding> {
+        self.findings
+            .iter()
+            .filter(|finding| finding.severity == Severity::Warning)
+    }
+
+    fn push(&mut self, severity: Severity, check: CheckKind, message: String) {
+        self.findings.push(Finding {
+            severity,
+            check,
+            message,
+        });
+    }
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let severity = match self.severity {
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        };
+        write!(f, "{}: {}", severity, self.message)
+    }
+}
+
+impl fmt::Display for BamCheckReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.findings.as_slice() {
+            [] => write!(f, "{}: ok", self.path.display()),
+            findings => {
+                let findings = findings.iter().map(Finding::to_string).collect::<Vec<_>>();
+                write!(f, "{}: {}", self.path.display(), findings.join("; "))
+            }
+        }
+    }
+}
+
+/// [`quick_check_with`] of the default options, without the seek check.
+pub fn quick_check(bam_path: impl AsRef<Path>) -> Result<BamCheckReport, Error> {
+    quick_check_with(bam_path, QuickCheckOptions::default())
+}
+
+/// Check the bam at `bam_path` without reading it through: that it ends with
+/// the BGZF EOF block ([`BGZF_EOF`]), else it was likely truncated; that its
+/// header parses; and that its index, if any, is not older than it, as a
+/// warning. With [`QuickCheckOptions::with_seek`], also fetch through the index.
+///
+/// Fails with an [`Io`](Error::Io) error only if the file cannot be opened or
+/// read; what is wrong with its content are the findings of the report.
+pub fn quick_check_with(
+    bam_path: impl AsRef<Path>,
+    options: QuickCheckOptions,
+) -> Result<BamCheckReport, Error> {
+    let path = bam_path.as_ref();
+    let mut report = BamCheckReport {
+        path: path.to_path_buf(),
+        index: find_bam_index(path).ok(),
+        findings: vec![],
+    };
+
+    let mut file = File::open(path).map_err(|err| Error::io("open", path, err))?;
+    if let Some(message) = check_eof(&mut file).map_err(|err| Error::io("read", path, err))? {
+        report.push(Severity::Error, CheckKind::Eof, message);
+    }
+
+    if let Err(err) = bam::Reader::from_path(path) {
+        report.push(
+            Severity::Error,
+            CheckKind::Header,
+            format!("the header cannot be read: {}", err),
+        );
+        return Ok(report);
+    }
+
+    if let Some(index) = &report.index {
+        let modified = |path: &Path| fs::metadata(path).and_then(|meta| meta.modified());
+        let bam_modified = modified(path).map_err(|err| Error::io("stat", path, err))?;
+        let index_modified = modified(index).map_err(|err| Error::io("stat", index, err))?;
+        if index_modified < bam_modified {
+            let message = format!(
+                "the index {} is older than the bam, and may be stale",
+                index.display()
+            );
+            report.push(Severity::Warning, CheckKind::Index, message);
+        }
+        if options.seek
+            && let Err(message) = check_seek(path)
+        {
+            report.push(Severity::Error, CheckKind::Seek, message);
+        }
+    }
+
+    Ok(report)
+}
+
+/// The message of a missing or other EOF block at the end of `file`, if so.
+fn check_eof(file: &mut File) -> io::Result<Option<String>> {
+    let len = file.metadata()?.len();
+    if len < BGZF_EOF.len() as u64 {
+        return Ok(Some(format!(
+            "the file is {} bytes, too short to end with the BGZF EOF block",
+            len
+        )));
+    }
+    let mut tail = [0u8; BGZF_EOF.len()];
+    file.seek(SeekFrom::End(-(BGZF_EOF.len() as i64)))?;
+    file.read_exact(&mut tail)?;
+    Ok((tail != BGZF_EOF)
+        .then(|| "the file does not end with the BGZF EOF block, and may be truncated".to_string()))
+}
+
+/// Fetch the first window of the first contig with mapped reads, and the last
+/// window of the last one, reading their records through.
+fn check_seek(path: &Path) -> Result<(), String> {
+    let mut reader = IndexedReader::from_path(path)
+        .map_err(|err| format!("cannot open the bam with its index: {}", err))?;
+    let stats = reader
+        .index_stats()
+        .map_err(|err| format!("cannot read the index: {}", err))?;
+    let mapped = stats
+        .iter()
+        .filter(|&&(tid, _, mapped, _)| tid >= 0 && mapped > 0)
+        .map(|&(tid, len, _, _)| (tid as u32, len))
+        .collect::<Vec<_>>();
+    let (Some(&(first, first_len)), Some(&(last, last_len))) = (mapped.first(), mapped.last())
+    else {
+        return Ok(());
+    };
+
+    let windows = [
+        (first, 0, SEEK_WINDOW.min(first_len)),
+        (last, last_len.saturating_sub(SEEK_WINDOW), last_len),
+    ];
+    for (tid, start, end) in windows {
+        let window = || format!("{}:{}-{}", tid, start, end);
+        reader
+            .fetch((tid, start as i64, end as i64))
+            .map_err(|err| format!("cannot fetch {}: {}", window(), err))?;
+        for record in reader.records() {
+            record.map_err(|err| format!("cannot read the records of {}: {}", window(), err))?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, SystemTime};
+
+    use super::*;
+    use crate::test_utils::TestBam;
+
+    #[test]
+    fn test_quick_check() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = tempfile::tempdir()?;
+        let bam_path = TestBam::new()
+            .add_reads("chr1", 1_000, 1, 40, 10)
+            .build(dir.path())?;
+        let options = QuickCheckOptions::default().with_seek(true);
+        let report = quick_check_with(&bam_path, options)?;
+        assert_eq!(report.findings, []);
+        assert_eq!(report.index, Some(dir.path().join("test.bam.bai")));
+        assert!(report.to_string().ends_with(": ok"));
+
+        // a copy with its EOF block chopped off, as of an interrupted copy.
+        let bytes = fs::read(&bam_path)?;
+        let truncated = dir.path().join("truncated.bam");
+        fs::write(&truncated, &bytes[..bytes.len() - BGZF_EOF.len()])?;
+        let report = quick_check(&truncated)?;
+        assert!(report.has_errors());
+        assert_eq!(report.index, None);
+        assert!(matches!(
+            report.findings.as_slice(),
+            [Finding {
+                severity: Severity::Error,
+                check: CheckKind::Eof,
+                ..
+            }]
+        ));
+
+        fs::write(&truncated, &bytes[..10])?;
+        let report = quick_check(&truncated)?;
+        let checks = report.findings.iter().map(|f| f.check).collect::<Vec<_>>();
+        assert_eq!(checks, [CheckKind::Eof, CheckKind::Header]);
+
+        assert!(matches!(
+            quick_check(dir.path().join("missing.bam")),
+            Err(Error::Io { .. })
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_quick_check_stale_index() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = tempfile::tempdir()?;
+        let bam_path = TestBam::new()
+            .add_reads("chr1", 1_000, 1, 40, 10)
+            .build(dir.path())?;
+        let now = SystemTime::now();
+        File::options()
+            .write(true)
+            .open(dir.path().join("test.bam.bai"))?
+            .set_modified(now - Duration::from_secs(3600))?;
+        File::options()
+            .write(true)
+            .open(&bam_path)?
+            .set_modified(now)?;
+
+        let report = quick_check(&bam_path)?;
+        assert!(!report.has_errors());
+        let warnings = report.warnings().collect::<Vec<_>>();
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(warnings[0].check, CheckKind::Index);
+        assert!(report.to_string().contains("warning: the index"));
+
+        Ok(())
+    }
+}
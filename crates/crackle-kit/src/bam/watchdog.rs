@@ -0,0 +1,161 @@
+//! Batch timeouts of [`ParallelLocusProcessorPileup`], to find the batches stuck
+//! on pathological regions, e.g. collapsed repeats of extreme depth.
+
+use std::{
+    collections::HashMap,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, Ordering},
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use thiserror::Error;
+use tracing::{Level, event};
+
+#[cfg(doc)]
+use crate::bam::process::ParallelLocusProcessorPileup;
+use crate::data::locus::GenomeRegion;
+
+/// What happens to a batch running for longer than the batch timeout.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum BatchTimeoutPolicy {
+    /// Log a WARN every time the batch runs for another timeout, and let it run.
+    #[default]
+    Warn,
+    /// Log a WARN and abort the batch with a [`BatchTimedOut`] error, once the
+    /// pileup column being processed is done.
+    Abort,
+}
+
+/// Error of a batch aborted by [`BatchTimeoutPolicy::Abort`].
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+#[error("batch at {region} timed out after {elapsed:.1?}")]
+pub struct BatchTimedOut {
+    /// The span fetched by the batch, 1-based.
+    pub region: GenomeRegion<'static>,
+    pub elapsed: Duration,
+}
+
+/// The batches being processed, checked by [`Self::run`] on its own thread.
+pub(crate) struct BatchWatchdog {
+    timeout: Duration,
+    policy: BatchTimeoutPolicy,
+    running: Mutex<HashMap<usize, RunningBatch>>,
+    done: AtomicBool,
+}
+
+struct RunningBatch {
+    region: GenomeRegion<'static>,
+    started: Instant,
+    /// Timeouts elapsed when last checked.
+    n_timeouts: u32,
+    cancelled: Arc<AtomicBool>,
+}
+
+impl BatchWatchdog {
+    pub(crate) fn new(timeout: Duration, policy: BatchTimeoutPolicy) -> Self {
+        Self {
+            timeout,
+            policy,
+            running: Mutex::new(HashMap::new()),
+            done: AtomicBool::new(false),
+        }
+    }
+
+    /// Watch batch `idx` until the returned guard is dropped.
+    pub(crate) fn watch(&self, idx: usize, region: GenomeRegion<'static>) -> WatchedBatch<'_> {
+        let cancelled = Arc::new(AtomicBool::new(false));
+        let started = Instant::now();
+        self.running.lock().unwrap().insert(
+            idx,
+            RunningBatch {
+                region,
+                started,
+                n_timeouts: 0,
+                cancelled: cancelled.clone(),
+            },
+        );
+
+        WatchedBatch {
+            watchdog: self,
+            idx,
+            started,
+            cancelled,
+        }
+    }
+
+    /// Check the running batches until [`Self::stop`] is called.
+    pub(crate) fn run(&self) {
+        let interval = (self.timeout / 4).clamp(Duration::from_millis(1), Duration::from_secs(1));
+        while !self.done.load(Ordering::Acquire) {
+            thread::park_timeout(interval);
+            self.check();
+        }
+    }
+
+    /// Make [`Self::run`] return, once its thread is unparked.
+    pub(crate) fn stop(&self) {
+        self.done.store(true, Ordering::Release);
+    }
+
+    fn check(&self) {
+        let mut running = self.running.lock().unwrap();
+        for batch in running.values_mut() {
+            let elapsed = batch.started.elapsed();
+            let n_timeouts = (elapsed.as_nanos() / self.timeout.as_nanos().max(1)) as u32;
+            if n_timeouts <= batch.n_timeouts {
+                continue;
+            }
+            batch.n_timeouts = n_timeouts;
+
+            event!(
+                Level::WARN,
+                region = %batch.region,
+                elapsed_ms = elapsed.as_millis() as u64,
+                "Batch at {} has run for {:.1?}, over the batch timeout of {:.1?}{}",
+                batch.region,
+                elapsed,
+                self.timeout,
+                match self.policy {
+                    BatchTimeoutPolicy::Warn => "",
+                    BatchTimeoutPolicy::Abort => ", aborting it",
+                },
+            );
+            if self.policy == BatchTimeoutPolicy::Abort {
+                batch.cancelled.store(true, Ordering::Relaxed);
+            }
+        }
+    }
+}
+
+/// A batch watched by [`BatchWatchdog`], until dropped.
+pub(crate) struct WatchedBatch<'a> {
+    watchdog: &'a BatchWatchdog,
+    idx: usize,
+    started: Instant,
+    cancelled: Arc<AtomicBool>,
+}
+
+impl WatchedBatch<'_> {
+    /// The error to abort the batch with, if its timeout was hit under
+    /// [`BatchTimeoutPolicy::Abort`].
+    pub(crate) fn timed_out(&self) -> Option<BatchTimedOut> {
+        if !self.cancelled.load(Ordering::Relaxed) {
+            return None;
+        }
+
+        let running = self.watchdog.running.lock().unwrap();
+        Some(BatchTimedOut {
+            region: running[&self.idx].region.clone(),
+            elapsed: self.started.elapsed(),
+        })
+    }
+}
+
+impl Drop for WatchedBatch<'_> {
+    fn drop(&mut self) {
+        self.watchdog.running.lock().unwrap().remove(&self.idx);
+    }
+}
@@ -0,0 +1,670 @@
+//! Ready-made [`BamLocusWorker`]s.
+
+use std::{collections::HashMap, io::Write};
+
+use anyhow::Error;
+use rust_htslib::bam::{pileup::Pileup, record::Aux};
+
+use crate::{
+    bam::{
+        cigar::{RecordCigarExt, Strand},
+        pileup_ext::{AlignmentContext, SampledPileup},
+        process::{BamLocusWorkInput, BamLocusWorker},
+    },
+    data::{bases::Base, locus::GenomeCoordinate},
+    nuc_base_map::NucBaseMap,
+    table::TableWriter,
+};
+
+/// Count the reads with a clip boundary near the locus, the split and clipped
+/// read evidence of a breakpoint.
+///
+/// The boundary of a left clip is the first aligned base of the read, and that of
+/// a right clip the last one. Soft and hard clips both count. Only the reads of
+/// the pileup are seen, i.e. those aligned over the locus.
+#[derive(Debug, Clone, Default)]
+pub struct ClipCountWorker {
+    /// Largest distance in bp between a clip boundary and the locus.
+    pub max_distance: u32,
+}
+
+impl ClipCountWorker {
+    pub fn new(max_distance: u32) -> Self {
+        Self { max_distance }
+    }
+}
+
+/// Output of [`ClipCountWorker`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ClipCounts {
+    /// Reads in the pileup.
+    pub n_reads: u32,
+    pub n_left_clipped: u32,
+    pub n_right_clipped: u32,
+    /// Reads with a left or right clip boundary near the locus, counted once.
+    pub n_clipped: u32,
+}
+
+impl<'a> BamLocusWorker<'a> for ClipCountWorker {
+    type Input = GenomeCoordinate<'a>;
+    type Output = ClipCounts;
+    type Error = Error;
+
+    fn work_for_locus(&self, plp: Pileup, input: Self::Input) -> Result<Self::Output, Self::Error> {
+        let locus = input.pos - 1;
+        let max_distance = self.max_distance as i64;
+        let mut counts = ClipCounts::default();
+
+        for alignment in plp.alignments() {
+            let record = alignment.record();
+            counts.n_reads += 1;
+
+            let start = record.pos();
+            let end = start + record.aligned_reference_span() - 1;
+            let left = record.is_left_clipped() && (start - locus).abs() <= max_distance;
+            let right = record.is_right_clipped() && (end - locus).abs() <= max_distance;
+
+            counts.n_left_clipped += left as u32;
+            counts.n_right_clipped += right as u32;
+            counts.n_clipped += (left || right) as u32;
+        }
+
+        Ok(counts)
+    }
+}
+
+/// How [`DuplexCountWorker`] gets the strand of a read.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum CountingMode {
+    /// The reverse flag of the read.
+    #[default]
+    SimpleStrand,
+    /// The strand of the fragment, that of the first read of the pair: the
+    /// reverse flag, flipped for second reads.
+    FirstInPairStrand,
+    /// [`Self::FirstInPairStrand`], and reads grouped into families by their UMI
+    /// and fragment, see [`FamilyCounts`].
+    Duplex,
+}
+
+/// Count the bases at the locus by strand, and in [`CountingMode::Duplex`] the
+/// UMI families of each strand supporting them.
+///
+/// Families are the reads of a fragment, i.e. of the same start and end of the
+/// pair, with the same UMI. A duplex UMI is two halves joined by `-`, swapped on
+/// the reads of the bottom strand, e.g. `AAT-GCC` and `GCC-AAT`; both are read as
+/// the same UMI. The top strand of a fragment is that of its first read being
+/// forward.
+#[derive(Debug, Clone)]
+pub struct DuplexCountWorker {
+    pub mode: CountingMode,
+    /// Tag of the UMI, `RX` by default.
+    pub umi_tag: [u8; 2],
+    /// Bases of a lower quality are not counted.
+    pub min_baseq: u8,
+}
+
+impl DuplexCountWorker {
+    pub fn new(mode: CountingMode) -> Self {
+        Self {
+            mode,
+            umi_tag: *b"RX",
+            min_baseq: 0,
+        }
+    }
+
+    pub fn with_umi_tag(mut self, umi_tag: [u8; 2]) -> Self {
+        self.umi_tag = umi_tag;
+        self
+    }
+
+    pub fn with_min_baseq(mut self, min_baseq: u8) -> Self {
+        self.min_baseq = min_baseq;
+        self
+    }
+}
+
+impl Default for DuplexCountWorker {
+    fn default() -> Self {
+        Self::new(CountingMode::default())
+    }
+}
+
+/// Output of [`DuplexCountWorker`].
+#[derive(Debug, Clone, Default)]
+pub struct StrandCounts {
+    /// Reads by base, on the strand of the [`CountingMode`].
+    pub forward: NucBaseMap<u32>,
+    pub reverse: NucBaseMap<u32>,
+    /// In [`CountingMode::Duplex`] only.
+    pub families: Option<FamilyCounts>,
+}
+
+/// UMI families by base, of [`DuplexCountWorker`] in [`CountingMode::Duplex`].
+///
+/// The base of a family is the most frequent one of its reads; families
+/// without a single most frequent base are not counted.
+#[derive(Debug, Clone, Default)]
+pub struct FamilyCounts {
+    /// Families of the top strand, of two reads or more.
+    pub top: NucBaseMap<u32>,
+    /// Families of the bottom strand, of two reads or more.
+    pub bottom: NucBaseMap<u32>,
+    /// Fragments of which the top and the bottom family, of two reads or more
+    /// each, have the same base.
+    pub duplex: NucBaseMap<u32>,
+    /// Families of a single read, of either strand, and in no other count.
+    pub singletons: NucBaseMap<u32>,
+    /// Reads without the UMI tag, in no family.
+    pub untagged: NucBaseMap<u32>,
+}
+
+impl<'a> BamLocusWorker<'a> for DuplexCountWorker {
+    type Input = GenomeCoordinate<'a>;
+    type Output = StrandCounts;
+    type Error = Error;
+
+    fn work_for_locus(
+        &self,
+        plp: Pileup,
+        _input: Self::Input,
+    ) -> Result<Self::Output, Self::Error> {
+        let mut counts = StrandCounts::default();
+        // reads by base of each strand, by UMI and fragment.
+        let mut families = HashMap::<(Vec<u8>, i64, i64), [NucBaseMap<u32>; 2]>::new();
+        let mut untagged = NucBaseMap::default();
+
+        for alignment in plp.alignments() {
+            let ctx = AlignmentContext::new(&alignment);
+            let (Some(base), Some(bq)) = (ctx.base, ctx.base_qual) else {
+                continue;
+            };
+            if bq < self.min_baseq {
+                continue;
+            }
+
+            let record = alignment.record();
+            let reverse = match self.mode {
+                CountingMode::SimpleStrand => ctx.strand == Strand::Reverse,
+                CountingMode::FirstInPairStrand | CountingMode::Duplex => {
+                    (ctx.strand == Strand::Reverse) ^ record.is_last_in_template()
+                }
+            };
+            let strand_counts = match reverse {
+                false => &mut counts.forward,
+                true => &mut counts.reverse,
+            };
+            add_base(strand_counts, base.to_ascii());
+
+            if self.mode != CountingMode::Duplex {
+                continue;
+            }
+            let umi = match record.aux(&self.umi_tag) {
+                Ok(Aux::String(umi)) => canonical_umi(umi.as_bytes()),
+                _ => {
+                    add_base(&mut untagged, base.to_ascii());
+                    continue;
+                }
+            };
+            let (start, end) = match record.is_paired() {
+                true => {
+                    let start = record.pos().min(record.mpos());
+                    (start, start + record.insert_size().abs())
+                }
+                false => (record.pos(), record.pos() + record.aligned_reference_span()),
+            };
+            add_base(
+                &mut families.entry((umi, start, end)).or_default()[reverse as usize],
+                base.to_ascii(),
+            );
+        }
+
+        if self.mode == CountingMode::Duplex {
+            let mut family_counts = FamilyCounts {
+                untagged,
+                ..Default::default()
+            };
+            for [top, bottom] in families.values() {
+                let [top_base, bottom_base] = [top, bottom].map(|reads| {
+                    let base = family_base(reads)?;
+                    if reads.get_inner().iter().flatten().sum::<u32>() == 1 {
+                        add_base(&mut family_counts.singletons, base);
+                        return None;
+                    }
+                    Some(base)
+                });
+                if let Some(base) = top_base {
+                    add_base(&mut family_counts.top, base);
+                }
+                if let Some(base) = bottom_base {
+                    add_base(&mut family_counts.bottom, base);
+                }
+                if let (Some(top_base), Some(bottom_base)) = (top_base, bottom_base)
+                    && top_base == bottom_base
+                {
+                    add_base(&mut family_counts.duplex, top_base);
+                }
+            }
+            counts.families = Some(family_counts);
+        }
+
+        Ok(counts)
+    }
+}
+
+fn add_base(counts: &mut NucBaseMap<u32>, base: u8) {
+    *counts.get_or_insert_with(base, || 0).unwrap() += 1;
+}
+
+/// The UMI of both strands: the halves of a duplex UMI in sorted order.
+fn canonical_umi(umi: &[u8]) -> Vec<u8> {
+    match umi.iter().position(|&b| b == b'-') {
+        Some(i) if umi[i + 1..] < umi[..i] => [&umi[i + 1..], b"-", &umi[..i]].concat(),
+        _ => umi.to_vec(),
+    }
+}
+
+/// The most frequent base of the reads of a family, if only one is.
+fn family_base(reads: &NucBaseMap<u32>) -> Option<u8> {
+    let counts = reads.get_inner();
+    let max = counts.iter().flatten().max()?;
+    let mut bases = (b"ATCGN".iter().zip(counts)).filter(|(_, n)| *n == &Some(*max));
+    match (bases.next(), bases.next()) {
+        (Some((&base, _)), None) => Some(base),
+        _ => None,
+    }
+}
+
+/// Write the counts of each locus as a `contig\tpos\tbase\t...` table, a row
+/// per base with a count. The family columns are 0 unless counted in
+/// [`CountingMode::Duplex`].
+pub fn write_strand_counts_tsv<'a, 'b: 'a>(
+    w: impl Write,
+    loci: impl IntoIterator<Item = (&'a GenomeCoordinate<'b>, &'a StrandCounts)>,
+) -> Result<(), Error> {
+    let mut tw = TableWriter::new(
+        w,
+        &[
+            "contig",
+            "pos",
+            "base",
+            "forward",
+            "reverse",
+            "top_families",
+            "bottom_families",
+            "duplex",
+            "singletons",
+            "untagged",
+        ],
+    )?;
+    let no_families = FamilyCounts::default();
+    for (locus, counts) in loci {
+        let families = counts.families.as_ref().unwrap_or(&no_families);
+        for base in *b"ACGTN" {
+            let row = [
+                &counts.forward,
+                &counts.reverse,
+                &families.top,
+                &families.bottom,
+                &families.duplex,
+                &families.singletons,
+                &families.untagged,
+            ]
+            .map(|map| map.get(base).copied().unwrap_or(0));
+            if row.iter().all(|&n| n == 0) {
+                continue;
+            }
+
+            let mut fields = vec![
+                locus.contig.to_string(),
+                locus.pos.to_string(),
+                (base as char).to_string(),
+            ];
+            fields.extend(row.iter().map(u32::to_string));
+            tw.write_row(fields)?;
+        }
+    }
+    tw.into_inner()?;
+    Ok(())
+}
+
+/// Adaptor of a worker pairing each output with its input, e.g. to write the
+/// outputs next to the variants they were computed at without joining them
+/// back. Set it with
+/// [`with_worker`](crate::bam::process::ParallelLocusProcessorPileup::with_worker).
+///
+/// The input is cloned for the wrapped worker. Inputs without coverage have no
+/// output from [`ParallelLocusProcessorPileup::process_with_batch`], so their
+/// echo is missing too, while
+/// [`LocusQueryEngine::query`](crate::bam::query::LocusQueryEngine::query)
+/// answers `None` for them.
+///
+/// [`ParallelLocusProcessorPileup::process_with_batch`]: crate::bam::process::ParallelLocusProcessorPileup::process_with_batch
+#[derive(Debug, Clone, Default)]
+pub struct EchoInput<W>(pub W);
+
+impl<W> EchoInput<W> {
+    pub fn new(worker: W) -> Self {
+        Self(worker)
+    }
+}
+
+impl<'a, W> BamLocusWorker<'a> for EchoInput<W>
+where
+    W: BamLocusWorker<'a>,
+    W::Input: Clone,
+{
+    type Input = W::Input;
+    type Output = (W::Input, W::Output);
+    type Error = W::Error;
+
+    fn work_for_locus(&self, plp: Pileup, input: Self::Input) -> Result<Self::Output, Self::Error> {
+        let output = self.0.work_for_locus(plp, input.clone())?;
+        Ok((input, output))
+    }
+
+    fn work_for_locus_with_ref(
+        &self,
+        plp: Pileup,
+        input: Self::Input,
+        ref_base: Option<Base>,
+    ) -> Result<Self::Output, Self::Error> {
+        let output = self
+            .0
+            .work_for_locus_with_ref(plp, input.clone(), ref_base)?;
+        Ok((input, output))
+    }
+
+    fn work_for_locus_sampled(
+        &self,
+        plp: Pileup,
+        input: Self::Input,
+        ref_base: Option<Base>,
+        sample: SampledPileup,
+    ) -> Result<Self::Output, Self::Error> {
+        let output = self
+            .0
+            .work_for_locus_sampled(plp, input.clone(), ref_base, sample)?;
+        Ok((input, output))
+    }
+}
+
+/// Adaptor of a worker pairing each output with the locus of its input, for
+/// inputs which need not be cloned, or outputs outliving them.
+///
+/// Like [`EchoInput`], inputs without coverage have no output.
+#[derive(Debug, Clone, Default)]
+pub struct WithLocus<W>(pub W);
+
+impl<W> WithLocus<W> {
+    pub fn new(worker: W) -> Self {
+        Self(worker)
+    }
+}
+
+impl<'a, W: BamLocusWorker<'a>> BamLocusWorker<'a> for WithLocus<W> {
+    type Input = W::Input;
+    type Output = (GenomeCoordinate<'static>, W::Output);
+    type Error = W::Error;
+
+    fn work_for_locus(&self, plp: Pileup, input: Self::Input) -> Result<Self::Output, Self::Error> {
+        let locus = input.genome_coordinate().to_owned_coordinate();
+        Ok((locus, self.0.work_for_locus(plp, input)?))
+    }
+
+    fn work_for_locus_with_ref(
+        &self,
+        plp: Pileup,
+        input: Self::Input,
+        ref_base: Option<Base>,
+    ) -> Result<Self::Output, Self::Error> {
+        let locus = input.genome_coordinate().to_owned_coordinate();
+        Ok((locus, self.0.work_for_locus_with_ref(plp, input, ref_base)?))
+    }
+
+    fn work_for_locus_sampled(
+        &self,
+        plp: Pileup,
+        input: Self::Input,
+        ref_base: Option<Base>,
+        sample: SampledPileup,
+    ) -> Result<Self::Output, Self::Error> {
+        let locus = input.genome_coordinate().to_owned_coordinate();
+        let output = self
+            .0
+            .work_for_locus_sampled(plp, input, ref_base, sample)?;
+        Ok((locus, output))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        bam::{process::ParallelLocusProcessorPileup, query::LocusQueryEngine},
+        data::chrom::Chrom,
+        test_utils::TestBam,
+    };
+
+    #[test]
+    fn test_clip_count_worker() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = tempfile::tempdir()?;
+        let seq = b"ACGT".repeat(25);
+        let qual = vec![30; 100];
+        // 0-based aligned spans in the comments.
+        let bam_path = TestBam::new()
+            // 1_010..=1_099
+            .add_read("chr1", 1_010, &seq, &qual, 0)
+            .with_cigar("90M10S")
+            // 1_098..=1_187
+            .add_read("chr1", 1_098, &seq, &qual, 0)
+            .with_cigar("10S90M")
+            // 1_051..=1_100
+            .add_read("chr1", 1_051, &seq[..50], &qual[..50], 0)
+            .with_cigar("50M50H")
+            // 1_050..=1_149
+            .add_read("chr1", 1_050, &seq, &qual, 0)
+            // 1_080..=1_169, clipped too far away.
+            .add_read("chr1", 1_080, &seq, &qual, 0)
+            .with_cigar("10S90M")
+            // 1_099..=1_188
+            .add_read("chr1", 1_099, &seq, &qual, 0)
+            .with_cigar("5S90M5S")
+            .build(dir.path())?;
+
+        let coord = |pos| GenomeCoordinate {
+            contig: Chrom::Chr1,
+            pos,
+        };
+        let plp = ParallelLocusProcessorPileup::new(ClipCountWorker::new(2), 2, bam_path);
+        let res = plp.process_with_batch(vec![coord(1_100), coord(1_102), coord(1_189)], 1_000)?;
+
+        assert_eq!(
+            res,
+            vec![
+                ClipCounts {
+                    n_reads: 6,
+                    n_left_clipped: 2,
+                    n_right_clipped: 2,
+                    n_clipped: 4,
+                },
+                ClipCounts {
+                    n_reads: 4,
+                    n_left_clipped: 1,
+                    n_right_clipped: 0,
+                    n_clipped: 1,
+                },
+                ClipCounts {
+                    n_reads: 1,
+                    n_left_clipped: 0,
+                    n_right_clipped: 1,
+                    n_clipped: 1,
+                },
+            ]
+        );
+
+        Ok(())
+    }
+
+    /// 50 bp of C, with `base` at 1-based 1_001 for a read at 0-based 980.
+    fn seq_with(base: u8) -> Vec<u8> {
+        let mut seq = vec![b'C'; 50];
+        seq[20] = base;
+        seq
+    }
+
+    /// `(base, count)` of the bases counted.
+    fn nonzero(counts: &NucBaseMap<u32>) -> Vec<(char, u32)> {
+        b"ACGTN"
+            .iter()
+            .filter_map(|&b| counts.get(b).map(|&n| (b as char, n)))
+            .collect()
+    }
+
+    #[test]
+    fn test_duplex_count_worker() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = tempfile::tempdir()?;
+        let qual = [30; 50];
+        let mut test_bam = TestBam::new();
+        // all of the fragment 980..1_150: top strand reads are first reads
+        // forward, bottom strand ones second reads forward.
+        let (top, bottom) = (0x1 | 0x40, 0x1 | 0x80);
+        for (umi, flags, n, base) in [
+            // duplex A.
+            ("AAAA-CCCC", top, 2, b'A'),
+            ("CCCC-AAAA", bottom, 2, b'A'),
+            // a single read on the bottom strand.
+            ("GGGG-TTTT", top, 2, b'A'),
+            ("TTTT-GGGG", bottom, 1, b'A'),
+            // the strands disagree.
+            ("ACAC-GTGT", top, 2, b'T'),
+            ("GTGT-ACAC", bottom, 2, b'G'),
+            // no single most frequent base.
+            ("TTTT-AAAA", top, 1, b'A'),
+            ("TTTT-AAAA", top, 1, b'G'),
+        ] {
+            for _ in 0..n {
+                test_bam = test_bam
+                    .add_read("chr1", 980, &seq_with(base), &qual, flags)
+                    .with_mate(1_100, 170)
+                    .with_tag(b"RX", umi);
+            }
+        }
+        let bam_path = test_bam
+            .add_read("chr1", 980, &seq_with(b'C'), &qual, top)
+            .with_mate(1_100, 170)
+            // a reverse read, of the top strand as a second read.
+            .add_read("chr1", 980, &seq_with(b'T'), &qual, 0x1 | 0x80 | 0x10)
+            .with_mate(900, -130)
+            .with_tag(b"RX", "TGTG-CACA")
+            .build(dir.path())?;
+
+        let coord = GenomeCoordinate {
+            contig: Chrom::Chr1,
+            pos: 1_001,
+        };
+        let count = |worker: DuplexCountWorker| {
+            ParallelLocusProcessorPileup::new(worker, 1, bam_path.clone())
+                .process_with_batch(vec![coord.clone()], 1_000)
+                .map(|mut res| res.pop().unwrap())
+        };
+
+        let simple = count(DuplexCountWorker::new(CountingMode::SimpleStrand))?;
+        assert_eq!(
+            nonzero(&simple.forward),
+            [('A', 8), ('C', 1), ('G', 3), ('T', 2)]
+        );
+        assert_eq!(nonzero(&simple.reverse), [('T', 1)]);
+        assert!(simple.families.is_none());
+
+        let by_pair = count(DuplexCountWorker::new(CountingMode::FirstInPairStrand))?;
+        assert_eq!(
+            nonzero(&by_pair.forward),
+            [('A', 5), ('C', 1), ('G', 1), ('T', 3)]
+        );
+        assert_eq!(nonzero(&by_pair.reverse), [('A', 3), ('G', 2)]);
+
+        let duplex = count(DuplexCountWorker::new(CountingMode::Duplex))?;
+        assert_eq!(nonzero(&duplex.forward), nonzero(&by_pair.forward));
+        let families = duplex.families.as_ref().unwrap();
+        assert_eq!(nonzero(&families.top), [('A', 2), ('T', 1)]);
+        assert_eq!(nonzero(&families.bottom), [('A', 1), ('G', 1)]);
+        assert_eq!(nonzero(&families.duplex), [('A', 1)]);
+        assert_eq!(nonzero(&families.singletons), [('A', 1), ('T', 1)]);
+        assert_eq!(nonzero(&families.untagged), [('C', 1)]);
+
+        // the UMIs are not in another tag.
+        let other_tag = count(DuplexCountWorker::new(CountingMode::Duplex).with_umi_tag(*b"MI"))?;
+        let families = other_tag.families.unwrap();
+        assert_eq!(
+            nonzero(&families.untagged),
+            [('A', 8), ('C', 1), ('G', 3), ('T', 3)]
+        );
+        assert!(nonzero(&families.top).is_empty());
+
+        let mut tsv = vec![];
+        write_strand_counts_tsv(&mut tsv, [(&coord, &duplex), (&coord, &simple)])?;
+        let tsv = String::from_utf8(tsv)?;
+        let lines = tsv.lines().collect::<Vec<_>>();
+        assert_eq!(
+            lines[0],
+            "contig\tpos\tbase\tforward\treverse\ttop_families\tbottom_families\tduplex\tsingletons\tuntagged"
+        );
+        assert_eq!(lines[1], "chr1\t1001\tA\t5\t3\t2\t1\t1\t1\t0");
+        assert_eq!(lines[2], "chr1\t1001\tC\t1\t0\t0\t0\t0\t0\t1");
+        assert_eq!(lines.len(), 1 + 4 + 4);
+
+        Ok(())
+    }
+    #[test]
+    fn test_echo_adaptors() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = tempfile::tempdir()?;
+        // 1-based 1_001..=1_050, 1_011..=1_060 and 1_021..=1_070.
+        let bam_path = TestBam::new()
+            .add_reads("chr1", 1_000, 10, 3, 50)
+            .build(dir.path())?;
+
+        let coord = |pos| GenomeCoordinate {
+            contig: Chrom::Chr1,
+            pos,
+        };
+        // with loci without coverage between the others.
+        let coords = [1_001, 900, 1_021, 2_000, 1_065].map(coord);
+        let processor =
+            ParallelLocusProcessorPileup::new(ClipCountWorker::new(0), 1, bam_path.clone());
+        let plain = processor.process_with_batch(coords.to_vec(), 1_000)?;
+
+        let processor = processor.with_worker(EchoInput::new(ClipCountWorker::new(0)));
+        let echoed = processor.process_with_batch(coords.to_vec(), 1_000)?;
+        let (inputs, outputs): (Vec<_>, Vec<_>) = echoed.into_iter().unzip();
+        assert_eq!(inputs, [1_001, 1_021, 1_065].map(coord));
+        assert_eq!(outputs, plain);
+        assert_eq!(
+            outputs.iter().map(|c| c.n_reads).collect::<Vec<_>>(),
+            [1, 3, 1]
+        );
+
+        // the engine pairs the loci without coverage with `None`.
+        let engine = LocusQueryEngine::new(processor)?;
+        let queried = engine.query(&coords)?;
+        for (coord, output) in coords.iter().zip(&queried) {
+            if let Some((input, _)) = output {
+                assert_eq!(input, coord);
+            }
+        }
+        assert_eq!(
+            queried.iter().map(Option::is_some).collect::<Vec<_>>(),
+            [true, false, true, false, true]
+        );
+
+        let processor =
+            ParallelLocusProcessorPileup::new(WithLocus::new(ClipCountWorker::new(0)), 1, bam_path);
+        let with_locus = processor.process_with_batch(coords.to_vec(), 1_000)?;
+        assert_eq!(
+            with_locus,
+            inputs.into_iter().zip(outputs).collect::<Vec<_>>()
+        );
+
+        Ok(())
+    }
+}
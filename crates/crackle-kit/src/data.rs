@@ -1,6 +1,14 @@
 pub mod locus;
+pub mod interval;
 pub mod chrom;
 pub mod variant;
 pub mod bases;
 pub mod arc_string;
-pub mod data_with_index;
\ No newline at end of file
+pub mod data_with_index;
+#[cfg(feature = "liftover")]
+pub mod liftover;
+pub mod seq_dict;
+#[cfg(feature = "mmap")]
+pub mod ref_mmap;
+#[cfg(feature = "interop")]
+pub mod interop;
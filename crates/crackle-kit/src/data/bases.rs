@@ -1,10 +1,9 @@
 pub mod rev_comp;
 pub mod comp;
+pub mod iupac;
 
 use std::{
-    arch::x86_64::{
-        _mm256_loadu_si256, _mm256_permute2x128_si256, _mm256_shuffle_epi8, _mm256_storeu_si256,
-    },
+    io,
     ops::{Index, Range, RangeFrom, RangeFull, RangeTo},
 };
 
@@ -12,6 +11,19 @@ use anyhow::{Error, anyhow};
 
 const BASE_ARR_LEN: usize = 8;
 
+/// Whether the AVX2 modes of [`comp::Complementor`] and [`rev_comp::RevComplementor`]
+/// can run here. Always false off x86_64, where only the scalar modes are built.
+pub fn avx2_detected() -> bool {
+    #[cfg(target_arch = "x86_64")]
+    {
+        is_x86_feature_detected!("avx2")
+    }
+    #[cfg(not(target_arch = "x86_64"))]
+    {
+        false
+    }
+}
+
 // we use u64, so the count is 21.
 macro_rules! n_bases_in_u64_chunk {
     () => {
@@ -33,27 +45,91 @@ const C_CODE: u64 = 0b011;
 const G_CODE: u64 = 0b100;
 const N_CODE: u64 = 0b101;
 
+/// How lowercase letters, e.g. of soft-masked reference sequence, are read by
+/// [`BaseArr::from_bytes_with_case`] and [`Base::from_ascii`].
+///
+/// The default, used by [`BaseArr::from_bytes`] and `Base::try_from`, is
+/// [`Self::Insensitive`], as for [`crate::nuc_base_map::NucBaseMap`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum CaseSensitivity {
+    /// Lowercase letters are the same bases as uppercase ones.
+    #[default]
+    Insensitive,
+    /// Lowercase letters are invalid bases.
+    Strict,
+    /// Lowercase letters are all read as N.
+    MaskedAsN,
+}
+
+impl CaseSensitivity {
+    const fn lookup(self) -> &'static [u8; 256] {
+        match self {
+            Self::Insensitive => &BYTE_TO_CODE_LOOKUP,
+            Self::Strict => &STRICT_BYTE_TO_CODE_LOOKUP,
+            Self::MaskedAsN => &MASKED_BYTE_TO_CODE_LOOKUP,
+        }
+    }
+}
+
 // --- Compile-time Lookup Table for performance ---
-const fn build_lookup_table() -> [u8; 256] {
+const fn build_lookup_table(case: CaseSensitivity) -> [u8; 256] {
     let mut table = [0xFF; 256]; // 0xFF is our error sentinel
     table[b'A' as usize] = A_CODE as u8;
     table[b'T' as usize] = T_CODE as u8;
     table[b'C' as usize] = C_CODE as u8;
     table[b'G' as usize] = G_CODE as u8;
     table[b'N' as usize] = N_CODE as u8;
+
+    let mut upper = b'A';
+    while upper <= b'Z' {
+        let lower = upper.to_ascii_lowercase() as usize;
+        table[lower] = match (case, table[upper as usize]) {
+            (_, 0xFF) | (CaseSensitivity::Strict, _) => 0xFF,
+            (CaseSensitivity::Insensitive, code) => code,
+            (CaseSensitivity::MaskedAsN, _) => N_CODE as u8,
+        };
+        upper += 1;
+    }
     table
 }
 
-const BYTE_TO_CODE_LOOKUP: [u8; 256] = build_lookup_table();
+/// The codes of the bytes of a chunk by `lookup`, packed into a word with 3 bits
+/// each, the first one lowest, and whether any is invalid, i.e. `0xFF`.
+///
+/// The codes are looked up 8 at a time into the bytes of a `u64`, checked all at
+/// once by their high bits, the sentinel being the only code with its high bit
+/// set, then packed by merging neighbouring fields with a shift and a mask, from
+/// 8 3-bit fields to 4 6-bit ones, to 2 12-bit ones, to a 24-bit one.
+#[inline(always)]
+fn pack_chunk(lookup: &[u8; 256], chunk: &[u8]) -> (u64, bool) {
+    let mut packed = 0;
+    let mut any = 0;
+    for (i, bytes) in chunk.chunks(8).enumerate() {
+        let mut x = 0;
+        for (j, &byte) in bytes.iter().enumerate() {
+            x |= (lookup[byte as usize] as u64) << (j * 8);
+        }
+        any |= x;
+        x = (x | (x >> 5)) & 0x003F_003F_003F_003F;
+        x = (x | (x >> 10)) & 0x0000_0FFF_0000_0FFF;
+        x = (x | (x >> 20)) & 0x00FF_FFFF;
+        packed |= x << (i * 24);
+    }
+    (packed, any & 0x8080_8080_8080_8080 != 0)
+}
+
+const BYTE_TO_CODE_LOOKUP: [u8; 256] = build_lookup_table(CaseSensitivity::Insensitive);
+const STRICT_BYTE_TO_CODE_LOOKUP: [u8; 256] = build_lookup_table(CaseSensitivity::Strict);
+const MASKED_BYTE_TO_CODE_LOOKUP: [u8; 256] = build_lookup_table(CaseSensitivity::MaskedAsN);
 
-const CODE_TO_CHAR_LOOKUP: [char; 6] = {
-    let mut arr = [0 as char; 6];
+const CODE_TO_ASCII_LOOKUP: [u8; 6] = {
+    let mut arr = [0; 6];
 
-    arr[A_CODE as usize] = 'A';
-    arr[T_CODE as usize] = 'T';
-    arr[C_CODE as usize] = 'C';
-    arr[G_CODE as usize] = 'G';
-    arr[N_CODE as usize] = 'N';
+    arr[A_CODE as usize] = b'A';
+    arr[T_CODE as usize] = b'T';
+    arr[C_CODE as usize] = b'C';
+    arr[G_CODE as usize] = b'G';
+    arr[N_CODE as usize] = b'N';
     arr
 };
 
@@ -61,43 +137,106 @@ const CODE_TO_CHAR_LOOKUP: [char; 6] = {
 ///
 /// This stores a dna base as 3-bit to u16 or u64.
 ///
-/// Supported bases are: A, T, C, G, N. All other bases raises error when this is being initialized.
+/// Supported bases are: A, T, C, G, N, in either case. All other bases raises error when this is being initialized.
 ///
 /// This has const generic, to control the size of the array.  
 ///
 /// The default N is 8. You may need to use smaller values, if you only store very short sequences (e.g. 4-bases seqeunce)
-#[derive(Debug, PartialEq, Eq, Clone)]
+///
+/// A chunk holds 21 bases with `u64` and 5 with `u16`, see [`Self::capacity`].
+/// Arrays are ordered as their sequences, like strings, so they can key sorted
+/// maps as well as hash maps, e.g. barcodes for demultiplexing.
+#[derive(Debug, PartialEq, Eq, Clone, Hash)]
 pub struct BaseArr<C = u64, const N: usize = BASE_ARR_LEN> {
     inner: [C; N],
 }
 
-macro_rules! impl_display {
-    ($type:ty, $n_bases_in_chunk:expr) => {
-        /// A more efficient and safer Display implementation.
-        /// It iterates through each potential base position and stops
-        /// as soon as it encounters a NULL_CODE terminator.
-        impl<const N: usize> std::fmt::Display for BaseArr<$type, N> {
-            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-                'outer: for chunk in self.inner {
-                    for i in 0..$n_bases_in_chunk {
-                        let code = (chunk >> (i * 3)) & 0b111;
+/// Fits reads of up to 168 bases, e.g. 150 bp reads.
+pub type BaseArr150 = BaseArr<u64, 8>;
+
+/// Fits up to 25 bases in 10 bytes, for UMIs and barcodes.
+pub type BaseArrShort = BaseArr<u16, 5>;
 
+macro_rules! impl_ascii {
+    ($type:ty, $n_bases_in_chunk:expr) => {
+        impl<const N: usize> BaseArr<$type, N> {
+            /// Decode chunk by chunk into a stack buffer, passing the ASCII bases of
+            /// each chunk to `f`. Stops at the NULL terminator.
+            #[inline]
+            fn for_each_ascii_chunk<E>(
+                &self,
+                mut f: impl FnMut(&[u8]) -> Result<(), E>,
+            ) -> Result<(), E> {
+                let mut buf = [0_u8; $n_bases_in_chunk];
+                for chunk in self.inner {
+                    let mut n = 0;
+                    while n < $n_bases_in_chunk {
+                        let code = (chunk >> (n * 3)) & 0b111;
                         if code == NULL_CODE as $type {
-                            break 'outer;
+                            break;
                         }
+                        buf[n] = CODE_TO_ASCII_LOOKUP[code as usize];
+                        n += 1;
+                    }
 
-                        let base_char = CODE_TO_CHAR_LOOKUP[code as usize];
-                        write!(f, "{}", base_char)?;
+                    if n > 0 {
+                        f(&buf[..n])?;
+                    }
+                    if n < $n_bases_in_chunk {
+                        break;
                     }
                 }
                 Ok(())
             }
+
+            /// Returns the bases as ASCII bytes.
+            pub fn to_ascii_vec(&self) -> Vec<u8> {
+                let mut v = Vec::with_capacity(N * $n_bases_in_chunk);
+                let _ = self.for_each_ascii_chunk(|bases| {
+                    v.extend_from_slice(bases);
+                    Ok::<_, ()>(())
+                });
+                v
+            }
+
+            /// Writes the bases as ASCII and returns the number of bytes written.
+            pub fn write_ascii(&self, out: &mut impl io::Write) -> io::Result<usize> {
+                let mut n = 0;
+                self.for_each_ascii_chunk(|bases| {
+                    n += bases.len();
+                    out.write_all(bases)
+                })?;
+                Ok(n)
+            }
+
+            /// Fills `buf` with the bases as ASCII and returns the number of bytes
+            /// written. Bases which do not fit in `buf` are left out.
+            pub fn fill_ascii(&self, buf: &mut [u8]) -> usize {
+                let mut n = 0;
+                let _ = self.for_each_ascii_chunk(|bases| {
+                    let len = bases.len().min(buf.len() - n);
+                    buf[n..n + len].copy_from_slice(&bases[..len]);
+                    n += len;
+                    if n == buf.len() { Err(()) } else { Ok(()) }
+                });
+                n
+            }
+        }
+
+        /// Writes each chunk of bases at once, stopping at the NULL terminator.
+        impl<const N: usize> std::fmt::Display for BaseArr<$type, N> {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                self.for_each_ascii_chunk(|bases| {
+                    // the lookup table only holds ASCII letters.
+                    f.write_str(std::str::from_utf8(bases).map_err(|_| std::fmt::Error)?)
+                })
+            }
         }
     };
 }
 
-impl_display!(u16, n_bases_in_u16_chunk!());
-impl_display!(u64, n_bases_in_u64_chunk!());
+impl_ascii!(u16, n_bases_in_u16_chunk!());
+impl_ascii!(u64, n_bases_in_u64_chunk!());
 
 pub trait BaseArrIndex<C, const N: usize> {
     type Output;
@@ -231,7 +370,34 @@ macro_rules! impl_basearr {
 
                 arr
             };
-            /// Creates a new `BaseArr` from any iterator of bytes.
+
+            /// Number of bases the array can hold.
+            pub const fn capacity() -> usize {
+                N * $n_bases_in_chunk
+            }
+
+            /// Creates a new `BaseArr` from decoded bases.
+            pub fn from_bases(bases: &[Base]) -> Result<Self, Error> {
+                if bases.len() > Self::capacity() {
+                    return Err(anyhow!(
+                        "Input slice is too long: {} bases, max is {}",
+                        bases.len(),
+                        Self::capacity()
+                    ));
+                }
+
+                let mut inner = [0; N];
+                for (chunk_idx, chunk) in bases.chunks($n_bases_in_chunk).enumerate() {
+                    for (offset, &base) in chunk.iter().enumerate() {
+                        inner[chunk_idx] |= Self::BASE_TO_CODE_TABLE[base as usize] << (offset * 3);
+                    }
+                }
+
+                Ok(BaseArr { inner })
+            }
+
+            /// Creates a new `BaseArr` from any iterator of bytes, lowercase letters
+            /// read as their uppercase bases.
             pub fn from_iter(iter: impl IntoIterator<Item = u8>) -> Result<Self, Error> {
                 let mut inner = [0; N];
                 let mut iter = iter.into_iter().peekable();
@@ -271,7 +437,20 @@ macro_rules! impl_basearr {
             }
 
             /// Creates a new BaseArr from a slice of bytes using a fast, chunk-based approach.
+            ///
+            /// Lowercase letters are read as their uppercase bases, see
+            /// [`Self::from_bytes_with_case`] to reject or mask them.
+            ///
+            /// Inputs longer than [`Self::capacity`], e.g. the whole sequence of
+            /// a long read, fail with an "Input slice is too long" error rather
+            /// than being truncated; pack windows of them instead.
             pub fn from_bytes(s: &[u8]) -> Result<Self, Error> {
+                Self::from_bytes_with_case(s, CaseSensitivity::default())
+            }
+
+            /// [`Self::from_bytes`], reading lowercase letters as set by `case`.
+            pub fn from_bytes_with_case(s: &[u8], case: CaseSensitivity) -> Result<Self, Error> {
+                let lookup = case.lookup();
                 let max_len = N * $n_bases_in_chunk;
                 if s.len() > max_len {
                     return Err(anyhow!(
@@ -281,30 +460,50 @@ macro_rules! impl_basearr {
                     ));
                 }
 
+                // the chunks are all packed before any byte is checked, as they
+                // are valid but for bad inputs, which are searched again.
                 let mut inner = [0; N];
-
-                // Process full chunks of 21 bytes for maximum efficiency
-                for (chunk_idx, chunk) in s.chunks($n_bases_in_chunk).enumerate() {
-                    let mut current_u64 = 0;
-                    for (offset, &byte) in chunk.iter().enumerate() {
-                        // Use the fast lookup table instead of a match statement
-                        let code = BYTE_TO_CODE_LOOKUP[byte as usize];
-                        if code == 0xFF {
-                            let global_idx = chunk_idx * $n_bases_in_chunk + offset;
-                            return Err(anyhow!(
-                                "Invalid base '{}' at position {}",
-                                byte as char,
-                                global_idx
-                            ));
-                        }
-                        current_u64 |= (code as $type) << (offset * 3);
-                    }
-                    inner[chunk_idx] = current_u64;
+                let mut invalid = false;
+                for (packed, chunk) in inner.iter_mut().zip(s.chunks($n_bases_in_chunk)) {
+                    let (chunk_packed, chunk_invalid) = pack_chunk(lookup, chunk);
+                    invalid |= chunk_invalid;
+                    *packed = chunk_packed as $type;
+                }
+                if invalid {
+                    let position = s
+                        .iter()
+                        .position(|&byte| lookup[byte as usize] == 0xFF)
+                        .unwrap();
+                    return Err(anyhow!(
+                        "Invalid base '{}' at position {}",
+                        s[position] as char,
+                        position
+                    ));
                 }
 
                 Ok(BaseArr { inner })
             }
 
+            /// [`Self::from_bytes`] without checking the bytes, for inputs already
+            /// known to be bases, e.g. checked once for many arrays.
+            ///
+            /// # Safety
+            ///
+            /// `s` must fit in the array, see [`Self::capacity`], and hold only
+            /// `A`, `C`, `G`, `T` and `N`, in either case. Other bytes make an
+            /// array whose bases are unspecified, and on which [`Self::get`] and
+            /// the iterators may panic.
+            pub unsafe fn from_bytes_unchecked(s: &[u8]) -> Self {
+                debug_assert!(s.len() <= Self::capacity());
+
+                let mut inner = [0; N];
+                for (packed, chunk) in inner.iter_mut().zip(s.chunks($n_bases_in_chunk)) {
+                    *packed = pack_chunk(&BYTE_TO_CODE_LOOKUP, chunk).0 as $type;
+                }
+
+                BaseArr { inner }
+            }
+
             /// Gets the Base at a given index.
             pub fn get<I: BaseArrIndex<$type, N>>(&self, index: I) -> Option<I::Output> {
                 index.get(self)
@@ -324,6 +523,35 @@ macro_rules! impl_basearr {
                 BaseArrIter::<$type, N>::new(self, 0, N * $n_bases_in_chunk)
             }
 
+            /// Whether `self` and `other` differ at `max_mismatches` positions at
+            /// most. A base against the end of the shorter array is a mismatch.
+            ///
+            /// Compares whole chunks at once, stopping at the first chunk over the
+            /// limit, so it is cheap enough to match a read against many barcodes.
+            #[inline]
+            pub fn hamming_within(&self, other: &Self, max_mismatches: u32) -> bool {
+                // lowest bit of each 3-bit code.
+                const LOW_BITS: $type = {
+                    let mut mask = 0;
+                    let mut i = 0;
+                    while i < $n_bases_in_chunk {
+                        mask |= 1 << (i * 3);
+                        i += 1;
+                    }
+                    mask
+                };
+
+                let mut n_mismatches = 0;
+                for (a, b) in self.inner.iter().zip(other.inner.iter()) {
+                    let diff = a ^ b;
+                    n_mismatches += ((diff | diff >> 1 | diff >> 2) & LOW_BITS).count_ones();
+                    if n_mismatches > max_mismatches {
+                        return false;
+                    }
+                }
+                true
+            }
+
             /// Sets the Base at a given index to a new value.
             pub fn set(&mut self, index: usize, new_base: Base) {
                 let (idx, offset) = (index / $n_bases_in_chunk, index % $n_bases_in_chunk);
@@ -362,6 +590,58 @@ macro_rules! impl_basearr {
 impl_basearr!(u16, n_bases_in_u16_chunk!());
 impl_basearr!(u64, n_bases_in_u64_chunk!());
 
+macro_rules! impl_basearr_ord {
+    ($type:ty) => {
+        /// Lexicographic order of the bases as letters, a prefix first, as for
+        /// their strings.
+        impl<const N: usize> Ord for BaseArr<$type, N> {
+            fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+                self.iter()
+                    .map(Base::to_ascii)
+                    .cmp(other.iter().map(Base::to_ascii))
+                    // only differs for arrays with bases after a NULL, e.g. by `set`.
+                    .then_with(|| self.inner.cmp(&other.inner))
+            }
+        }
+
+        impl<const N: usize> PartialOrd for BaseArr<$type, N> {
+            fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+                Some(self.cmp(other))
+            }
+        }
+    };
+}
+
+impl_basearr_ord!(u16);
+impl_basearr_ord!(u64);
+
+macro_rules! impl_basearr_repack {
+    ($from:ty, $to:ty) => {
+        /// Re-packs the bases, failing if they do not fit in the target.
+        impl<const N1: usize, const N2: usize> TryFrom<&BaseArr<$from, N1>> for BaseArr<$to, N2> {
+            type Error = Error;
+
+            fn try_from(arr: &BaseArr<$from, N1>) -> Result<Self, Self::Error> {
+                let len = arr.iter().count();
+                if len > Self::capacity() {
+                    return Err(anyhow!(
+                        "{} bases do not fit in a BaseArr of {} bases",
+                        len,
+                        Self::capacity()
+                    ));
+                }
+
+                Self::from_iter(arr.iter().map(Base::to_ascii))
+            }
+        }
+    };
+}
+
+impl_basearr_repack!(u16, u16);
+impl_basearr_repack!(u16, u64);
+impl_basearr_repack!(u64, u16);
+impl_basearr_repack!(u64, u64);
+
 /// A trait for range types that can be used to create an iterator over a `BaseArr`.
 pub trait BaseArrRange<'a, C, const N: usize> {
     fn get_iter(self, arr: &'a BaseArr<C, N>) -> BaseArrIter<'a, C, N>;
@@ -409,17 +689,24 @@ pub enum Base {
 }
 
 impl Base {
-    const BYTE_TO_BASE_TABLE: [Option<Self>; 256] = {
-        {
-            let mut table = [None; 256]; // 0xFF is our error sentinel
-            table[b'A' as usize] = Some(Self::A);
-            table[b'T' as usize] = Some(Self::T);
-            table[b'C' as usize] = Some(Self::C);
-            table[b'G' as usize] = Some(Self::G);
-            table[b'N' as usize] = Some(Self::N);
-            table
+    /// The base of an ASCII letter, lowercase letters read as set by `case`.
+    pub fn from_ascii(value: u8, case: CaseSensitivity) -> Result<Self, Error> {
+        match case.lookup()[value as usize] {
+            0xFF => Err(anyhow!("Invalid base: {}", value as char)),
+            code => Ok(BaseArr150::CODE_TO_BASE_LOOKUP[code as usize].unwrap()),
         }
-    };
+    }
+
+    /// Uppercase letter of the base.
+    pub fn to_ascii(self) -> u8 {
+        match self {
+            Self::A => b'A',
+            Self::T => b'T',
+            Self::C => b'C',
+            Self::G => b'G',
+            Self::N => b'N',
+        }
+    }
 
     // const STRING_LOOKUP_STABLE: [std::string::String; 256] = {
     //     let mut table = [const { String::new() }; 256];
@@ -443,13 +730,9 @@ impl TryFrom<u8> for Base {
 
     //     Ok(r)
     // }
+    /// Lowercase letters are the same bases, see [`Base::from_ascii`].
     fn try_from(value: u8) -> Result<Self, Self::Error> {
-        let r = match Self::BYTE_TO_BASE_TABLE[value as usize] {
-            Some(b) => b,
-            None => Err(anyhow!("Invalid base: {}", value as char))?,
-        };
-
-        Ok(r)
+        Self::from_ascii(value, CaseSensitivity::default())
     }
 }
 
@@ -574,6 +857,51 @@ mod tests {
                         expected_msg,
                         err
                     );
+
+                    // lowercase letters of invalid bases stay invalid.
+                    let err = BaseArr::<$type>::from_bytes(b"acgtz").unwrap_err();
+                    assert_eq!(err.to_string(), "Invalid base 'z' at position 4");
+
+                    let err =
+                        BaseArr::<$type>::from_bytes_with_case(b"ACgT", CaseSensitivity::Strict)
+                            .unwrap_err();
+                    assert_eq!(err.to_string(), "Invalid base 'g' at position 2");
+                }
+
+                #[test]
+                fn test_soft_masked() -> Result<(), Error> {
+                    let arr = BaseArr::<$type>::from_bytes(b"acgtn")?;
+                    assert_eq!(arr, BaseArr::<$type>::from_bytes(b"ACGTN")?);
+
+                    let masked = BaseArr::<$type>::from_bytes_with_case(
+                        b"acgtn",
+                        CaseSensitivity::MaskedAsN,
+                    )?;
+                    assert_eq!(masked.to_string(), "NNNNN");
+                    assert!(
+                        BaseArr::<$type>::from_bytes_with_case(b"acgtn", CaseSensitivity::Strict)
+                            .is_err()
+                    );
+
+                    Ok(())
+                }
+
+                #[test]
+                fn test_mixed_case_to_string() -> Result<(), Error> {
+                    let seq = b"ACGTacgtNnTTGCAAcca";
+                    let arr = BaseArr::<$type>::from_bytes(seq)?;
+                    assert_eq!(arr.to_string(), "ACGTACGTNNTTGCAACCA");
+                    assert_eq!(
+                        BaseArr::<$type>::from_bytes(arr.to_string().as_bytes())?,
+                        arr
+                    );
+                    assert_eq!(BaseArr::<$type>::from_iter(seq.iter().copied())?, arr);
+
+                    let masked =
+                        BaseArr::<$type>::from_bytes_with_case(seq, CaseSensitivity::MaskedAsN)?;
+                    assert_eq!(masked.to_string(), "ACGTNNNNNNTTGCAANNN");
+
+                    Ok(())
                 }
 
                 #[test]
@@ -615,6 +943,49 @@ mod tests {
                     Ok(())
                 }
 
+                #[test]
+                fn test_ascii_matches_per_base_decoding() -> Result<(), Error> {
+                    // default N is 8 chunks.
+                    let capacity = 8 * (<$type>::BITS as usize / 3);
+                    let pattern = b"ACGTNGTCA".repeat(20);
+
+                    for len in [0, 1, 4, 5, 6, 20, 21, 22, 40, capacity - 1, capacity] {
+                        let seq = &pattern[..len];
+                        let arr = BaseArr::<$type>::from_bytes(seq)?;
+
+                        // what Display wrote before, one base at a time.
+                        let per_base = arr
+                            .iter()
+                            .map(|b| match b {
+                                Base::A => 'A',
+                                Base::T => 'T',
+                                Base::C => 'C',
+                                Base::G => 'G',
+                                Base::N => 'N',
+                            })
+                            .collect::<String>();
+                        assert_eq!(per_base.as_bytes(), seq);
+
+                        assert_eq!(arr.to_string(), per_base, "len {}", len);
+                        assert_eq!(arr.to_ascii_vec(), seq, "len {}", len);
+
+                        let mut out = vec![];
+                        assert_eq!(arr.write_ascii(&mut out)?, len);
+                        assert_eq!(out, seq);
+
+                        let mut buf = vec![0; capacity + 3];
+                        assert_eq!(arr.fill_ascii(&mut buf), len);
+                        assert_eq!(&buf[..len], seq);
+
+                        let mut short = [0; 3];
+                        let n = arr.fill_ascii(&mut short);
+                        assert_eq!(n, len.min(3));
+                        assert_eq!(&short[..n], &seq[..n]);
+                    }
+
+                    Ok(())
+                }
+
                 #[test]
                 fn test_get_iter() -> Result<(), Error> {
                     let seq = b"ATCGNATCGN"; // 10 bases
@@ -756,5 +1127,218 @@ mod tests {
 
     make_test_functions!(u16, u16);
     make_test_functions!(u64, u64);
+
+    #[test]
+    fn test_base_from_ascii() {
+        assert_eq!(Base::try_from(b'g').unwrap(), Base::G);
+        assert_eq!(Base::try_from(b'G').unwrap(), Base::G);
+        assert!(Base::try_from(b'r').is_err());
+        assert_eq!(
+            Base::from_ascii(b't', CaseSensitivity::MaskedAsN).unwrap(),
+            Base::N
+        );
+        assert_eq!(
+            Base::from_ascii(b'T', CaseSensitivity::MaskedAsN).unwrap(),
+            Base::T
+        );
+        assert_eq!(
+            Base::from_ascii(b'a', CaseSensitivity::Strict)
+                .unwrap_err()
+                .to_string(),
+            "Invalid base: a"
+        );
+    }
+
+    #[test]
+    fn test_capacity_and_from_bases() -> Result<(), Error> {
+        assert_eq!(BaseArr150::capacity(), 168);
+        assert_eq!(BaseArrShort::capacity(), 25);
+        assert_eq!(BaseArr::<u16, 2>::capacity(), 10);
+
+        let bases = b"GATTACA".map(|b| Base::try_from(b).unwrap());
+        assert_eq!(
+            BaseArr::<u16, 2>::from_bases(&bases)?,
+            BaseArr::<u16, 2>::from_bytes(b"GATTACA")?
+        );
+        assert_eq!(
+            BaseArr150::from_bases(&[Base::N; 168])?.to_string(),
+            "N".repeat(168)
+        );
+        assert!(BaseArr::<u16, 1>::from_bases(&bases).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_repack_between_sizes() -> Result<(), Error> {
+        let seq = b"ACGTNACGTTGCANTTGCA";
+        let long = BaseArr150::from_bytes(seq)?;
+        let short = BaseArrShort::try_from(&long)?;
+        assert_eq!(short.to_ascii_vec(), seq);
+        assert_eq!(BaseArr150::try_from(&short)?, long);
+        assert_eq!(BaseArr::<u64, 1>::try_from(&long)?.to_ascii_vec(), seq);
+
+        // 30 bases do not fit in 25.
+        let too_long = BaseArr150::from_bytes(&[b'A'; 30])?;
+        assert_eq!(
+            BaseArrShort::try_from(&too_long).unwrap_err().to_string(),
+            "30 bases do not fit in a BaseArr of 25 bases"
+        );
+        let full = BaseArr::<u16, 2>::from_bytes(b"ACGTNACGTN")?;
+        assert!(BaseArr::<u16, 1>::try_from(&full).is_err());
+        let empty = BaseArr::<u16, 2>::from_bytes(b"")?;
+        assert_eq!(BaseArr::<u16, 1>::try_from(&empty)?.to_string(), "");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_ordering_matches_strings() -> Result<(), Error> {
+        // across the chunks of 5 bases, and an empty one.
+        let mut seqs = "T,ACGT,,AC,N,GATTACA,ACGTA,CCCCCCCCCCCC,CA,ACGTNNNNNN,ACGTNNNNNNA,NA"
+            .split(',')
+            .collect::<Vec<_>>();
+        let mut arrs = seqs
+            .iter()
+            .map(|s| BaseArr::<u16, 6>::from_bytes(s.as_bytes()))
+            .collect::<Result<Vec<_>, _>>()?;
+        seqs.sort();
+        arrs.sort();
+        assert_eq!(arrs.iter().map(|a| a.to_string()).collect::<Vec<_>>(), seqs);
+
+        let a = BaseArr150::from_bytes(b"AT")?;
+        let b = BaseArr150::from_bytes(b"ATA")?;
+        assert!(a < b);
+        assert_eq!(b.cmp(&a), std::cmp::Ordering::Greater);
+        assert_eq!(a.cmp(&a.clone()), std::cmp::Ordering::Equal);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_hamming_within() -> Result<(), Error> {
+        let arr = |s: &[u8]| BaseArr::<u16, 4>::from_bytes(s).unwrap();
+        let barcode = arr(b"ACGTACGTAC");
+
+        assert!(barcode.hamming_within(&barcode, 0));
+        assert!(!barcode.hamming_within(&arr(b"ACGTACGTAA"), 0));
+        assert!(barcode.hamming_within(&arr(b"ACGTACGTAA"), 1));
+        // mismatches in 2 chunks, N counted as any other base.
+        assert!(!barcode.hamming_within(&arr(b"NCGTACGTAA"), 1));
+        assert!(barcode.hamming_within(&arr(b"NCGTACGTAA"), 2));
+        // the missing base is a mismatch.
+        assert!(!barcode.hamming_within(&arr(b"ACGTACGTA"), 0));
+        assert!(barcode.hamming_within(&arr(b"ACGTACGTA"), 1));
+
+        let long = BaseArr150::from_bytes(&[b'A'; 150])?;
+        let mut other = long.clone();
+        for i in [0, 20, 21, 149] {
+            other.set(i, Base::G);
+        }
+        assert!(long.hamming_within(&other, 4));
+        assert!(!long.hamming_within(&other, 3));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_barcodes_key_hash_map() -> Result<(), Error> {
+        let barcodes = ["ACGTACGT", "TTGCAAGC", "GGNATCCA"];
+        let samples = barcodes
+            .iter()
+            .enumerate()
+            .map(|(i, bc)| Ok((BaseArrShort::from_bytes(bc.as_bytes())?, i)))
+            .collect::<Result<std::collections::HashMap<_, _>, Error>>()?;
+
+        for (i, bc) in barcodes.iter().enumerate() {
+            assert_eq!(samples[&BaseArrShort::from_bytes(bc.as_bytes())?], i);
+        }
+        assert!(!samples.contains_key(&BaseArrShort::from_bytes(b"ACGTACG")?));
+
+        Ok(())
+    }
+
+    /// The packing of `from_bytes` compared with the previous one, a base at a
+    /// time.
+    macro_rules! make_packing_tests {
+        ($mod_name:ident, $type:ty, $n_bases_in_chunk:expr, $n:expr) => {
+            mod $mod_name {
+                use proptest::prelude::*;
+
+                use super::*;
+
+                fn from_bytes_per_base(
+                    s: &[u8],
+                    case: CaseSensitivity,
+                ) -> Result<BaseArr<$type, $n>, Error> {
+                    let mut inner = [0; $n];
+                    for (chunk_idx, chunk) in s.chunks($n_bases_in_chunk).enumerate() {
+                        for (offset, &byte) in chunk.iter().enumerate() {
+                            let code = case.lookup()[byte as usize];
+                            if code == 0xFF {
+                                return Err(anyhow!(
+                                    "Invalid base '{}' at position {}",
+                                    byte as char,
+                                    chunk_idx * $n_bases_in_chunk + offset
+                                ));
+                            }
+                            inner[chunk_idx] |= (code as $type) << (offset * 3);
+                        }
+                    }
+                    Ok(BaseArr { inner })
+                }
+
+                fn case() -> impl Strategy<Value = CaseSensitivity> {
+                    prop_oneof![
+                        Just(CaseSensitivity::Insensitive),
+                        Just(CaseSensitivity::Strict),
+                        Just(CaseSensitivity::MaskedAsN),
+                    ]
+                }
+
+                proptest! {
+                    #[test]
+                    fn test_packing_matches_per_base(
+                        seq in prop::collection::vec(
+                            prop::sample::select(b"ACGTNacgtn".to_vec()),
+                            0..=$n * $n_bases_in_chunk,
+                        ),
+                    ) {
+                        let arr = BaseArr::<$type, $n>::from_bytes(&seq).unwrap();
+                        prop_assert_eq!(
+                            &arr,
+                            &from_bytes_per_base(&seq, CaseSensitivity::Insensitive).unwrap()
+                        );
+                        // SAFETY: the bytes are bases and fit.
+                        let unchecked =
+                            unsafe { BaseArr::<$type, $n>::from_bytes_unchecked(&seq) };
+                        prop_assert_eq!(unchecked, arr);
+                    }
+
+                    #[test]
+                    fn test_errors_match_per_base(
+                        seq in prop::collection::vec(
+                            prop_oneof![
+                                20 => prop::sample::select(b"ACGTNacgtn".to_vec()),
+                                1 => any::<u8>(),
+                            ],
+                            0..=$n * $n_bases_in_chunk,
+                        ),
+                        case in case(),
+                    ) {
+                        let res = BaseArr::<$type, $n>::from_bytes_with_case(&seq, case);
+                        let expected = from_bytes_per_base(&seq, case);
+                        prop_assert_eq!(
+                            res.map_err(|err| err.to_string()),
+                            expected.map_err(|err| err.to_string())
+                        );
+                    }
+                }
+            }
+        };
+    }
+
+    make_packing_tests!(packing_u16, u16, n_bases_in_u16_chunk!(), 5);
+    make_packing_tests!(packing_u64, u64, n_bases_in_u64_chunk!(), 8);
 }
 
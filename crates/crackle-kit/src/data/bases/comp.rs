@@ -1,5 +1,8 @@
+#[cfg(target_arch = "x86_64")]
 use std::arch::x86_64::{_mm256_loadu_si256, _mm256_shuffle_epi8, _mm256_storeu_si256};
 
+use crate::data::bases::avx2_detected;
+#[cfg(target_arch = "x86_64")]
 use crate::data::bases::rev_comp::SIMD_COMPLEMENT_LUT;
 
 #[inline]
@@ -22,6 +25,7 @@ fn complement_seq_scalar(seq: &[u8], dst: &mut [u8]) {
     }
 }
 
+#[cfg(target_arch = "x86_64")]
 #[target_feature(enable = "avx2")]
 unsafe fn complement_avx2(src: &[u8], dst_ptr: *mut u8) {
     let len = src.len();
@@ -74,7 +78,7 @@ pub struct Complementor {
 impl Complementor {
     pub fn new(mode: CompMode) -> Self {
         if let CompMode::SIMD = mode {
-            if !is_x86_feature_detected!("avx2") {
+            if !avx2_detected() {
                 panic!("AVX2 not detected");
             }
         }
@@ -96,12 +100,16 @@ impl Complementor {
                 self.buf.resize(len, 0);
                 complement_seq_scalar(seq, &mut self.buf);
             }
+            #[cfg(target_arch = "x86_64")]
             CompMode::SIMD => unsafe {
                 // Pass the raw pointer to the SIMD function
                 complement_avx2(seq, self.buf.as_mut_ptr());
                 // Critical: Manually update length since we wrote via pointer
                 self.buf.set_len(len);
             },
+            // `new` refuses SIMD off x86_64
+            #[cfg(not(target_arch = "x86_64"))]
+            CompMode::SIMD => unreachable!("AVX2 is not available off x86_64"),
         }
         self.buf.as_slice()
     }
@@ -123,7 +131,7 @@ mod tests {
         let mut scalar_runner = Complementor::new(CompMode::Scalar);
 
         // Only run SIMD checks if CPU supports it
-        let mut simd_runner = if is_x86_feature_detected!("avx2") {
+        let mut simd_runner = if avx2_detected() {
             Some(Complementor::new(CompMode::SIMD))
         } else {
             println!("Skipping SIMD test: AVX2 not detected.");
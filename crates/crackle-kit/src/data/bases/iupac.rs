@@ -0,0 +1,373 @@
+//! IUPAC nucleotide codes, for reference sequences containing ambiguity codes.
+
+use std::fmt;
+
+use anyhow::{Error, anyhow};
+
+use crate::data::bases::{Base, BaseArr};
+
+const BASE_ARR_LEN: usize = 8;
+const N_CODES_IN_CHUNK: usize = 16;
+const NULL_CODE: u64 = 0;
+
+/// A nucleotide or IUPAC ambiguity code.
+///
+/// The discriminant is the set of bases the code stands for, as a bitmask of
+/// A = 1, C = 2, G = 4, T = 8. The 15 non-empty masks are exactly the 15 IUPAC
+/// codes, and 0 is left for the NULL terminator of [`IupacArr`].
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
+#[repr(u8)]
+pub enum IupacBase {
+    A = 0b0001,
+    C = 0b0010,
+    /// A or C
+    M = 0b0011,
+    G = 0b0100,
+    /// A or G
+    R = 0b0101,
+    /// C or G
+    S = 0b0110,
+    /// not T
+    V = 0b0111,
+    T = 0b1000,
+    /// A or T
+    W = 0b1001,
+    /// C or T
+    Y = 0b1010,
+    /// not G
+    H = 0b1011,
+    /// G or T
+    K = 0b1100,
+    /// not C
+    D = 0b1101,
+    /// not A
+    B = 0b1110,
+    N = 0b1111,
+}
+
+impl IupacBase {
+    const CODE_TO_BASE: [Option<Self>; 16] = [
+        None,
+        Some(Self::A),
+        Some(Self::C),
+        Some(Self::M),
+        Some(Self::G),
+        Some(Self::R),
+        Some(Self::S),
+        Some(Self::V),
+        Some(Self::T),
+        Some(Self::W),
+        Some(Self::Y),
+        Some(Self::H),
+        Some(Self::K),
+        Some(Self::D),
+        Some(Self::B),
+        Some(Self::N),
+    ];
+
+    const CODE_TO_ASCII: [u8; 16] = *b"\0ACMGRSVTWYHKDBN";
+
+    const BYTE_TO_BASE: [Option<Self>; 256] = {
+        let mut table = [None; 256];
+        let mut code = 1;
+        while code < 16 {
+            let upper = Self::CODE_TO_ASCII[code];
+            table[upper as usize] = Self::CODE_TO_BASE[code];
+            table[upper.to_ascii_lowercase() as usize] = Self::CODE_TO_BASE[code];
+            code += 1;
+        }
+        table
+    };
+
+    #[inline]
+    fn code(self) -> u64 {
+        self as u64
+    }
+
+    /// Uppercase letter of the code.
+    pub fn to_ascii(self) -> u8 {
+        Self::CODE_TO_ASCII[self as usize]
+    }
+
+    /// True for codes standing for more than one base, N included.
+    pub fn is_ambiguous(self) -> bool {
+        (self as u8).count_ones() > 1
+    }
+
+    /// The [`Base`] for A, C, G and T, and [`Base::N`] for every ambiguity code.
+    pub fn to_base_lossy(self) -> Base {
+        match self {
+            Self::A => Base::A,
+            Self::C => Base::C,
+            Self::G => Base::G,
+            Self::T => Base::T,
+            _ => Base::N,
+        }
+    }
+
+    /// IUPAC matching: whether `base` is one of the bases this code stands for.
+    ///
+    /// [`Base::N`] is any base, so it matches every code.
+    pub fn matches(self, base: Base) -> bool {
+        self as u8 & Self::from(base) as u8 != 0
+    }
+}
+
+impl From<Base> for IupacBase {
+    fn from(value: Base) -> Self {
+        match value {
+            Base::A => Self::A,
+            Base::C => Self::C,
+            Base::G => Self::G,
+            Base::T => Self::T,
+            Base::N => Self::N,
+        }
+    }
+}
+
+/// Case-insensitive.
+impl TryFrom<u8> for IupacBase {
+    type Error = Error;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        Self::BYTE_TO_BASE[value as usize]
+            .ok_or_else(|| anyhow!("Invalid IUPAC base: {}", value as char))
+    }
+}
+
+impl fmt::Display for IupacBase {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.to_ascii() as char)
+    }
+}
+
+/// [`BaseArr`] counterpart for IUPAC codes.
+///
+/// Each code takes 4 bits, so a u64 stores 16 of them and the default N of 8
+/// holds up to 128 codes. Like `BaseArr`, the sequence ends at the first NULL code.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct IupacArr<const N: usize = BASE_ARR_LEN> {
+    inner: [u64; N],
+}
+
+impl<const N: usize> IupacArr<N> {
+    pub const CAPACITY: usize = N * N_CODES_IN_CHUNK;
+
+    /// Pack IUPAC letters, in upper or lower case.
+    pub fn from_bytes(s: &[u8]) -> Result<Self, Error> {
+        if s.len() > Self::CAPACITY {
+            return Err(anyhow!(
+                "Input slice is too long: {} bases, max is {}",
+                s.len(),
+                Self::CAPACITY
+            ));
+        }
+
+        let mut inner = [0; N];
+        for (chunk_idx, chunk) in s.chunks(N_CODES_IN_CHUNK).enumerate() {
+            let mut current = 0;
+            for (offset, &byte) in chunk.iter().enumerate() {
+                let base = IupacBase::BYTE_TO_BASE[byte as usize].ok_or_else(|| {
+                    anyhow!(
+                        "Invalid base '{}' at position {}",
+                        byte as char,
+                        chunk_idx * N_CODES_IN_CHUNK + offset
+                    )
+                })?;
+                current |= base.code() << (offset * 4);
+            }
+            inner[chunk_idx] = current;
+        }
+
+        Ok(Self { inner })
+    }
+
+    #[inline]
+    fn code_at(&self, index: usize) -> Option<u64> {
+        let (idx, offset) = (index / N_CODES_IN_CHUNK, index % N_CODES_IN_CHUNK);
+        self.inner
+            .get(idx)
+            .map(|chunk| (chunk >> (offset * 4)) & 0b1111)
+    }
+
+    /// Gets the code at a given index. `None` past the end of the sequence.
+    pub fn get(&self, index: usize) -> Option<IupacBase> {
+        IupacBase::CODE_TO_BASE[self.code_at(index)? as usize]
+    }
+
+    /// Sets the code at a given index to a new value.
+    pub fn set(&mut self, index: usize, new_base: IupacBase) {
+        let (idx, offset) = (index / N_CODES_IN_CHUNK, index % N_CODES_IN_CHUNK);
+        if idx >= N {
+            panic!("Index out of bounds");
+        }
+
+        let bit_pos = offset * 4;
+        self.inner[idx] &= !(0b1111 << bit_pos);
+        self.inner[idx] |= new_base.code() << bit_pos;
+    }
+
+    /// Returns an iterator over the codes of the sequence.
+    pub fn iter(&self) -> impl Iterator<Item = IupacBase> + '_ {
+        (0..Self::CAPACITY).map_while(|i| self.get(i))
+    }
+
+    /// Convert to a [`BaseArr`], replacing ambiguity codes with N.
+    pub fn to_base_arr_lossy(&self) -> BaseArr<u64, N> {
+        // A u64 BaseArr holds 21 bases per chunk, so this always fits.
+        BaseArr::<u64, N>::from_iter(self.iter().map(|b| b.to_base_lossy().to_ascii()))
+            .expect("BaseArr has room for every IupacArr of the same N")
+    }
+}
+
+impl<const N: usize> From<&IupacArr<N>> for BaseArr<u64, N> {
+    fn from(value: &IupacArr<N>) -> Self {
+        value.to_base_arr_lossy()
+    }
+}
+
+/// Writes each chunk of codes at once, stopping at the NULL terminator.
+impl<const N: usize> fmt::Display for IupacArr<N> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut buf = [0_u8; N_CODES_IN_CHUNK];
+        for chunk in self.inner {
+            let mut n = 0;
+            while n < N_CODES_IN_CHUNK {
+                let code = (chunk >> (n * 4)) & 0b1111;
+                if code == NULL_CODE {
+                    break;
+                }
+                buf[n] = IupacBase::CODE_TO_ASCII[code as usize];
+                n += 1;
+            }
+
+            f.write_str(std::str::from_utf8(&buf[..n]).map_err(|_| fmt::Error)?)?;
+            if n < N_CODES_IN_CHUNK {
+                break;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ALL: &[u8] = b"ACGTRYSWKMBDHVN";
+
+    #[test]
+    fn test_every_letter() -> Result<(), Error> {
+        for &letter in ALL {
+            let upper = IupacBase::try_from(letter)?;
+            let lower = IupacBase::try_from(letter.to_ascii_lowercase())?;
+            assert_eq!(upper, lower);
+            assert_eq!(upper.to_ascii(), letter);
+            assert_eq!(upper.to_string(), (letter as char).to_string());
+            assert_eq!(upper.is_ambiguous(), !b"ACGT".contains(&letter));
+        }
+
+        assert!(IupacBase::try_from(b'U').is_err());
+        assert!(IupacBase::try_from(b'-').is_err());
+        assert!(IupacBase::try_from(0).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_matches() {
+        let expected: &[(IupacBase, &[Base])] = &[
+            (IupacBase::A, &[Base::A]),
+            (IupacBase::C, &[Base::C]),
+            (IupacBase::G, &[Base::G]),
+            (IupacBase::T, &[Base::T]),
+            (IupacBase::R, &[Base::A, Base::G]),
+            (IupacBase::Y, &[Base::C, Base::T]),
+            (IupacBase::S, &[Base::C, Base::G]),
+            (IupacBase::W, &[Base::A, Base::T]),
+            (IupacBase::K, &[Base::G, Base::T]),
+            (IupacBase::M, &[Base::A, Base::C]),
+            (IupacBase::B, &[Base::C, Base::G, Base::T]),
+            (IupacBase::D, &[Base::A, Base::G, Base::T]),
+            (IupacBase::H, &[Base::A, Base::C, Base::T]),
+            (IupacBase::V, &[Base::A, Base::C, Base::G]),
+            (IupacBase::N, &[Base::A, Base::C, Base::G, Base::T]),
+        ];
+
+        for (code, bases) in expected {
+            for base in [Base::A, Base::C, Base::G, Base::T] {
+                assert_eq!(
+                    code.matches(base),
+                    bases.contains(&base),
+                    "{} {:?}",
+                    code,
+                    base
+                );
+            }
+            assert!(code.matches(Base::N), "{} N", code);
+        }
+    }
+
+    #[test]
+    fn test_arr_roundtrip_and_set() -> Result<(), Error> {
+        // crosses the 16 codes chunk boundary.
+        let seq = b"ACGTRYSWKMBDHVNacgtrysw";
+        let mut arr = IupacArr::<4>::from_bytes(seq)?;
+
+        assert_eq!(
+            arr.to_string(),
+            String::from_utf8(seq.to_ascii_uppercase())?
+        );
+        assert_eq!(arr.iter().count(), seq.len());
+        assert_eq!(arr.get(4), Some(IupacBase::R));
+        assert_eq!(arr.get(16), Some(IupacBase::C));
+        assert_eq!(arr.get(seq.len()), None);
+        assert_eq!(arr.get(IupacArr::<4>::CAPACITY), None);
+
+        arr.set(15, IupacBase::A);
+        arr.set(16, IupacBase::K);
+        assert_eq!(arr.get(13), Some(IupacBase::V));
+        assert_eq!(arr.get(15), Some(IupacBase::A));
+        assert_eq!(arr.get(16), Some(IupacBase::K));
+        assert_eq!(arr.get(17), Some(IupacBase::G));
+
+        // a full array has no terminator.
+        let full = vec![b'Y'; IupacArr::<2>::CAPACITY];
+        assert_eq!(
+            IupacArr::<2>::from_bytes(&full)?.to_string().as_bytes(),
+            full
+        );
+
+        assert!(IupacArr::<2>::from_bytes(&[b'A'; 33]).is_err());
+        assert_eq!(
+            IupacArr::<2>::from_bytes(b"ACX").unwrap_err().to_string(),
+            "Invalid base 'X' at position 2"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    #[should_panic(expected = "Index out of bounds")]
+    fn test_set_out_of_bounds() {
+        let mut arr = IupacArr::<1>::from_bytes(b"A").unwrap();
+        arr.set(16, IupacBase::C);
+    }
+
+    #[test]
+    fn test_lossy_conversion() -> Result<(), Error> {
+        let arr = IupacArr::<8>::from_bytes(b"ACGTRYSWKMBDHVNacgt")?;
+        let base_arr = BaseArr::from(&arr);
+
+        assert_eq!(base_arr.to_string(), "ACGTNNNNNNNNNNNACGT");
+        assert_eq!(
+            base_arr,
+            BaseArr::<u64>::from_bytes(b"ACGTNNNNNNNNNNNACGT")?
+        );
+
+        let full = IupacArr::<1>::from_bytes(&[b'R'; 16])?;
+        assert_eq!(full.to_base_arr_lossy().to_string(), "N".repeat(16));
+
+        Ok(())
+    }
+}
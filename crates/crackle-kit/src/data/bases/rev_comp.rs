@@ -1,16 +1,18 @@
+#[cfg(target_arch = "x86_64")]
 use std::arch::x86_64::{
-    __m256i, _mm256_loadu_si256, _mm256_permute2x128_si256, _mm256_shuffle_epi8,
-    _mm256_storeu_si256,
+    _mm256_loadu_si256, _mm256_permute2x128_si256, _mm256_shuffle_epi8, _mm256_storeu_si256,
 };
 
-use crate::data::bases::comp::complement_base;
+use crate::data::bases::{avx2_detected, comp::complement_base};
 
 // Same constants as before
+#[cfg(target_arch = "x86_64")]
 pub(crate) const SIMD_COMPLEMENT_LUT: [u8; 32] = [
     0, 'T' as u8, 0, 'G' as u8, 'A' as u8, 'A' as u8, 0, 'C' as u8, 0, 0, 0, 0, 0, 0, 'N' as u8, 0,
     0, 'T' as u8, 0, 'G' as u8, 'A' as u8, 'A' as u8, 0, 'C' as u8, 0, 0, 0, 0, 0, 0, 'N' as u8, 0,
 ];
 
+#[cfg(target_arch = "x86_64")]
 pub(crate) const SIMD_REV_MASK: [u8; 32] = [
     15, 14, 13, 12, 11, 10, 9, 8, 7, 6, 5, 4, 3, 2, 1, 0, // lane 1
     15, 14, 13, 12, 11, 10, 9, 8, 7, 6, 5, 4, 3, 2, 1, 0, // lane 2
@@ -38,6 +40,7 @@ fn reverse_complement_scalar_ptr(src: &[u8], dst: *mut u8) {
     }
 }
 
+#[cfg(target_arch = "x86_64")]
 #[target_feature(enable = "avx2")]
 unsafe fn reverse_complement_avx2(src: &[u8], dst_ptr: *mut u8) {
     let len = src.len();
@@ -87,6 +90,7 @@ unsafe fn reverse_complement_avx2(src: &[u8], dst_ptr: *mut u8) {
     }
 }
 
+#[cfg(target_arch = "x86_64")]
 #[target_feature(enable = "avx2")]
 unsafe fn rc_avx2_unrolled(mut src_ptr: *const u8, dst_base: *mut u8, len: usize) {
     let mut i = 0;
@@ -180,7 +184,7 @@ pub struct RevComplementor {
 
 impl RevComplementor {
     pub fn new() -> RevComplementor {
-        let mode = if is_x86_feature_detected!("avx2") {
+        let mode = if avx2_detected() {
             RevCompMode::SIMD
         } else {
             RevCompMode::Normal
@@ -197,7 +201,7 @@ impl RevComplementor {
             RevCompMode::Normal => {}
             RevCompMode::NormalPtr => {}
             RevCompMode::SIMD | RevCompMode::SIMDUnrolled4x => {
-                if !is_x86_feature_detected!("avx2") {
+                if !avx2_detected() {
                     panic!("avx2 feature is not detected.")
                 }
             }
@@ -218,18 +222,25 @@ impl RevComplementor {
         }
 
         match self.mode {
+            #[cfg(target_arch = "x86_64")]
             RevCompMode::SIMD => {
                 unsafe {
                     reverse_complement_avx2(seq, self.buf.as_mut_ptr());
                     self.buf.set_len(seq.len());
                 };
             }
+            #[cfg(target_arch = "x86_64")]
             RevCompMode::SIMDUnrolled4x => {
                 unsafe {
                     rc_avx2_unrolled(seq.as_ptr(), self.buf.as_mut_ptr(), seq.len());
                     self.buf.set_len(seq.len());
                 };
             }
+            // `with_mode` refuses the SIMD modes off x86_64, and `new` never picks them
+            #[cfg(not(target_arch = "x86_64"))]
+            RevCompMode::SIMD | RevCompMode::SIMDUnrolled4x => {
+                unreachable!("AVX2 is not available off x86_64")
+            }
             RevCompMode::NormalPtr => {
                 unsafe {
                     reverse_complement_scalar_ptr(seq, self.buf.as_mut_ptr());
@@ -265,7 +276,7 @@ mod rc_tests {
         let mut normal_ptr_runner = RevComplementor::with_mode(RevCompMode::NormalPtr);
 
         // Only run SIMD checks if the CPU supports it
-        let mut simd_runner = if is_x86_feature_detected!("avx2") {
+        let mut simd_runner = if avx2_detected() {
             Some(RevComplementor::with_mode(RevCompMode::SIMD))
         } else {
             println!("Skipping SIMD test: AVX2 not detected on this machine.");
@@ -273,7 +284,7 @@ mod rc_tests {
         };
 
         // Only run SIMD checks if the CPU supports it
-        let mut simd_unrolled_runner = if is_x86_feature_detected!("avx2") {
+        let mut simd_unrolled_runner = if avx2_detected() {
             Some(RevComplementor::with_mode(RevCompMode::SIMDUnrolled4x))
         } else {
             // println!("Skipping SIMD test: AVX2 not detected on this machine.");
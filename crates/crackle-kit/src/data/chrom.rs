@@ -1,4 +1,5 @@
 use std::borrow::Cow;
+use std::cmp::Ordering;
 use std::fmt;
 use std::str::FromStr;
 
@@ -10,7 +11,10 @@ use self::constants::*;
 /// like VCF or BED. For the 25 standard human chromosomes (1-22, X, Y, M),
 /// no new memory is allocated. Any other chromosome name is stored in the `Other`
 /// variant as a `String`.
-#[derive(Debug, PartialEq, Eq, Hash, Clone)]
+///
+/// Chromosomes are ordered as in the karyotype, chr1 to chr22, X, Y and M, then
+/// the other contigs by name.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone)]
 pub enum Chrom<'a> {
     Chr1,
     Chr2,
@@ -52,6 +56,7 @@ impl<'a> From<&str> for Chrom<'a> {
 /// # Examples
 ///
 /// ```
+/// # use crackle_kit::data::chrom::Chrom;
 /// let chrom = Chrom::Chr1;
 /// assert_eq!(chrom.to_string(), "chr1");
 ///
@@ -75,6 +80,7 @@ impl<'a> fmt::Display for Chrom<'a> {
 /// # Examples
 ///
 /// ```
+/// # use crackle_kit::data::chrom::Chrom;
 /// let chrom: Chrom = "chrX".parse().unwrap();
 /// assert_eq!(chrom, Chrom::ChrX);
 ///
@@ -163,6 +169,76 @@ impl<'a> Chrom<'a> {
         }
     }
 
+    /// Detach from the borrowed name, if any.
+    pub fn into_owned(self) -> Chrom<'static> {
+        match self {
+            Chrom::Chr1 => Chrom::Chr1,
+            Chrom::Chr2 => Chrom::Chr2,
+            Chrom::Chr3 => Chrom::Chr3,
+            Chrom::Chr4 => Chrom::Chr4,
+            Chrom::Chr5 => Chrom::Chr5,
+            Chrom::Chr6 => Chrom::Chr6,
+            Chrom::Chr7 => Chrom::Chr7,
+            Chrom::Chr8 => Chrom::Chr8,
+            Chrom::Chr9 => Chrom::Chr9,
+            Chrom::Chr10 => Chrom::Chr10,
+            Chrom::Chr11 => Chrom::Chr11,
+            Chrom::Chr12 => Chrom::Chr12,
+            Chrom::Chr13 => Chrom::Chr13,
+            Chrom::Chr14 => Chrom::Chr14,
+            Chrom::Chr15 => Chrom::Chr15,
+            Chrom::Chr16 => Chrom::Chr16,
+            Chrom::Chr17 => Chrom::Chr17,
+            Chrom::Chr18 => Chrom::Chr18,
+            Chrom::Chr19 => Chrom::Chr19,
+            Chrom::Chr20 => Chrom::Chr20,
+            Chrom::Chr21 => Chrom::Chr21,
+            Chrom::Chr22 => Chrom::Chr22,
+            Chrom::ChrX => Chrom::ChrX,
+            Chrom::ChrY => Chrom::ChrY,
+            Chrom::ChrM => Chrom::ChrM,
+            Chrom::Other(s) => Chrom::Other(Cow::Owned(s.into_owned())),
+        }
+    }
+
+    /// Stable integer code of a standard chromosome: 1-22, then 23 for X, 24 for
+    /// Y and 25 for M. `None` for [`Chrom::Other`].
+    pub fn code(&self) -> Option<u8> {
+        match self {
+            Chrom::Chr1 => Some(1),
+            Chrom::Chr2 => Some(2),
+            Chrom::Chr3 => Some(3),
+            Chrom::Chr4 => Some(4),
+            Chrom::Chr5 => Some(5),
+            Chrom::Chr6 => Some(6),
+            Chrom::Chr7 => Some(7),
+            Chrom::Chr8 => Some(8),
+            Chrom::Chr9 => Some(9),
+            Chrom::Chr10 => Some(10),
+            Chrom::Chr11 => Some(11),
+            Chrom::Chr12 => Some(12),
+            Chrom::Chr13 => Some(13),
+            Chrom::Chr14 => Some(14),
+            Chrom::Chr15 => Some(15),
+            Chrom::Chr16 => Some(16),
+            Chrom::Chr17 => Some(17),
+            Chrom::Chr18 => Some(18),
+            Chrom::Chr19 => Some(19),
+            Chrom::Chr20 => Some(20),
+            Chrom::Chr21 => Some(21),
+            Chrom::Chr22 => Some(22),
+            Chrom::ChrX => Some(23),
+            Chrom::ChrY => Some(24),
+            Chrom::ChrM => Some(25),
+            Chrom::Other(_) => None,
+        }
+    }
+
+    /// Inverse of [`Self::code`].
+    pub fn from_code(code: u8) -> Option<Chrom<'static>> {
+        BY_CODE.get(usize::from(code).checked_sub(1)?).cloned()
+    }
+
     pub fn typical_chroms() -> [Chrom<'static>; 24] {
         const TYPICAL_CHROMS: [Chrom; 24] = [
             Chrom::Chr1,
@@ -310,8 +386,271 @@ impl<'a> Chrom<'a> {
             }
         }
     }
+
+    /// [`Ord`], with the other contigs in natural order of their names, i.e.
+    /// with runs of digits compared as numbers: `chrUn_2` before `chrUn_10`.
+    pub fn natural_cmp(&self, other: &Self) -> Ordering {
+        match (self, other) {
+            (Chrom::Other(a), Chrom::Other(b)) => natural_cmp(a, b),
+            _ => self.cmp(other),
+        }
+    }
+}
+
+/// Compares runs of digits by value, then by length so that `01` and `1` are
+/// not equal, and the rest byte by byte.
+fn natural_cmp(a: &str, b: &str) -> Ordering {
+    let (mut a, mut b) = (a.as_bytes(), b.as_bytes());
+    loop {
+        match (a.first(), b.first()) {
+            (None, None) => return Ordering::Equal,
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(x), Some(y)) if x.is_ascii_digit() && y.is_ascii_digit() => {
+                let n_a = a.iter().take_while(|c| c.is_ascii_digit()).count();
+                let n_b = b.iter().take_while(|c| c.is_ascii_digit()).count();
+                let (digits_a, digits_b) = (&a[..n_a], &b[..n_b]);
+                let (trimmed_a, trimmed_b) = (trim_zeros(digits_a), trim_zeros(digits_b));
+
+                let ord = trimmed_a
+                    .len()
+                    .cmp(&trimmed_b.len())
+                    .then_with(|| trimmed_a.cmp(trimmed_b))
+                    .then_with(|| n_a.cmp(&n_b));
+                if ord != Ordering::Equal {
+                    return ord;
+                }
+                (a, b) = (&a[n_a..], &b[n_b..]);
+            }
+            (Some(x), Some(y)) => {
+                if x != y {
+                    return x.cmp(y);
+                }
+                (a, b) = (&a[1..], &b[1..]);
+            }
+        }
+    }
+}
+
+fn trim_zeros(digits: &[u8]) -> &[u8] {
+    let n_zeros = digits.iter().take_while(|c| **c == b'0').count();
+    &digits[n_zeros..]
+}
+
+/// Names of non-standard contigs, stored once and borrowed by the [`Chrom`]s of
+/// many records, e.g. of variants on alt or decoy contigs.
+///
+/// Names are canonicalized as by [`FromStr`], so `KI270742.1` and
+/// `chrKI270742.1` are one contig. Standard chromosomes are never stored.
+#[derive(Debug, Clone, Default)]
+pub struct ContigInterner {
+    /// Canonical names, all starting with `chr`, sorted.
+    names: Vec<Box<str>>,
+}
+
+impl ContigInterner {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Interner of `names`, e.g. the contigs of a bam header.
+    pub fn from_names<S: AsRef<str>>(names: impl IntoIterator<Item = S>) -> Self {
+        let mut interner = Self::new();
+        for name in names {
+            interner.insert(name.as_ref());
+        }
+        interner
+    }
+
+    /// Store `name`, unless it is standard or stored already.
+    pub fn insert(&mut self, name: &str) {
+        let core = name.strip_prefix("chr").unwrap_or(name);
+        if Chrom::from_standard_core(core).is_some() {
+            return;
+        }
+        if let Err(i) = self.find(core) {
+            self.names.insert(i, format!("chr{core}").into_boxed_str());
+        }
+    }
+
+    /// The chromosome of `name`, borrowing the stored name if any. Standard
+    /// chromosomes and stored names are not allocated.
+    pub fn chrom(&self, name: &str) -> Chrom<'_> {
+        let core = name.strip_prefix("chr").unwrap_or(name);
+        if let Some(chrom) = Chrom::from_standard_core(core) {
+            return chrom;
+        }
+        match self.get(name) {
+            Some(stored) => Chrom::Other(Cow::Borrowed(stored)),
+            None => Chrom::Other(Cow::Owned(format!("chr{core}"))),
+        }
+    }
+
+    /// The stored canonical name of `name`.
+    pub fn get(&self, name: &str) -> Option<&str> {
+        let core = name.strip_prefix("chr").unwrap_or(name);
+        self.find(core).ok().map(|i| &*self.names[i])
+    }
+
+    /// Number of stored names.
+    pub fn len(&self) -> usize {
+        self.names.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.names.is_empty()
+    }
+
+    /// Index of the name `chr{core}`, by its core as all names have the prefix.
+    fn find(&self, core: &str) -> Result<usize, usize> {
+        self.names
+            .binary_search_by(|name| name["chr".len()..].cmp(core))
+    }
+}
+
+/// Human-readable formats (JSON, YAML, ...) get the name of the chromosome, e.g.
+/// `"chr1"`. Binary formats get an enum whose variant index is the code of a
+/// standard chromosome, or 0 followed by the name for [`Chrom::Other`].
+///
+/// Names are read back as is: a name of a standard chromosome gives its variant,
+/// any other one an `Other`, without the `chr` canonicalization of [`FromStr`].
+#[cfg(feature = "serde")]
+mod serde_impl {
+    use std::{borrow::Cow, fmt};
+
+    use serde::{
+        Deserialize, Deserializer, Serialize, Serializer,
+        de::{self, EnumAccess, VariantAccess, Visitor},
+    };
+
+    use super::{Chrom, constants::*};
+
+    /// Variants of the binary form, by index.
+    const VARIANTS: [&str; 26] = [
+        "Other", CHR1, CHR2, CHR3, CHR4, CHR5, CHR6, CHR7, CHR8, CHR9, CHR10, CHR11, CHR12, CHR13,
+        CHR14, CHR15, CHR16, CHR17, CHR18, CHR19, CHR20, CHR21, CHR22, CHRX, CHRY, CHRM,
+    ];
+
+    impl Serialize for Chrom<'_> {
+        fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            if serializer.is_human_readable() {
+                return serializer.serialize_str(self.as_str());
+            }
+
+            match self.code() {
+                Some(code) => {
+                    serializer.serialize_unit_variant("Chrom", code.into(), VARIANTS[code as usize])
+                }
+                None => {
+                    serializer.serialize_newtype_variant("Chrom", 0, VARIANTS[0], self.as_str())
+                }
+            }
+        }
+    }
+
+    impl<'de> Deserialize<'de> for Chrom<'_> {
+        fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+            if deserializer.is_human_readable() {
+                let name = Cow::<str>::deserialize(deserializer)?;
+                return Ok(from_name(name.into_owned()));
+            }
+
+            deserializer.deserialize_enum("Chrom", &VARIANTS, ChromVisitor)
+        }
+    }
+
+    fn from_name(name: String) -> Chrom<'static> {
+        match VARIANTS[1..].iter().position(|v| *v == name) {
+            Some(i) => Chrom::from_code(i as u8 + 1).unwrap(),
+            None => Chrom::Other(Cow::Owned(name)),
+        }
+    }
+
+    struct ChromVisitor;
+
+    impl<'de> Visitor<'de> for ChromVisitor {
+        type Value = Chrom<'static>;
+
+        fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            f.write_str("a chromosome code or name")
+        }
+
+        fn visit_enum<A: EnumAccess<'de>>(self, data: A) -> Result<Self::Value, A::Error> {
+            match data.variant::<VariantIndex>()? {
+                (VariantIndex(0), variant) => Ok(Chrom::Other(Cow::Owned(
+                    variant.newtype_variant::<String>()?,
+                ))),
+                (VariantIndex(code), variant) => {
+                    variant.unit_variant()?;
+                    Ok(Chrom::from_code(code).unwrap())
+                }
+            }
+        }
+    }
+
+    /// Index into [`VARIANTS`], from the index or the name of the variant.
+    struct VariantIndex(u8);
+
+    impl<'de> Deserialize<'de> for VariantIndex {
+        fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+            deserializer.deserialize_identifier(VariantIndexVisitor)
+        }
+    }
+
+    struct VariantIndexVisitor;
+
+    impl<'de> Visitor<'de> for VariantIndexVisitor {
+        type Value = VariantIndex;
+
+        fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            f.write_str("a chromosome variant index or name")
+        }
+
+        fn visit_u64<E: de::Error>(self, v: u64) -> Result<Self::Value, E> {
+            match u8::try_from(v) {
+                Ok(i) if (i as usize) < VARIANTS.len() => Ok(VariantIndex(i)),
+                _ => Err(E::invalid_value(de::Unexpected::Unsigned(v), &self)),
+            }
+        }
+
+        fn visit_str<E: de::Error>(self, v: &str) -> Result<Self::Value, E> {
+            match VARIANTS.iter().position(|name| *name == v) {
+                Some(i) => Ok(VariantIndex(i as u8)),
+                None => Err(E::unknown_variant(v, &VARIANTS)),
+            }
+        }
+    }
 }
 
+/// Standard chromosomes, by [`Chrom::code`] - 1.
+const BY_CODE: [Chrom<'static>; 25] = [
+    Chrom::Chr1,
+    Chrom::Chr2,
+    Chrom::Chr3,
+    Chrom::Chr4,
+    Chrom::Chr5,
+    Chrom::Chr6,
+    Chrom::Chr7,
+    Chrom::Chr8,
+    Chrom::Chr9,
+    Chrom::Chr10,
+    Chrom::Chr11,
+    Chrom::Chr12,
+    Chrom::Chr13,
+    Chrom::Chr14,
+    Chrom::Chr15,
+    Chrom::Chr16,
+    Chrom::Chr17,
+    Chrom::Chr18,
+    Chrom::Chr19,
+    Chrom::Chr20,
+    Chrom::Chr21,
+    Chrom::Chr22,
+    Chrom::ChrX,
+    Chrom::ChrY,
+    Chrom::ChrM,
+];
+
 /// A module to hold the string constants for standard chromosome names.
 mod constants {
     pub(crate) const CHR1: &'static str = "chr1";
@@ -434,6 +773,51 @@ mod tests {
         assert_eq!(Chrom::Chr22.as_ref(), "chr22");
     }
 
+    #[test]
+    fn test_code() {
+        for code in 1..=25 {
+            let chrom = Chrom::from_code(code).unwrap();
+            assert_eq!(chrom.code(), Some(code));
+        }
+        assert_eq!(Chrom::Chr22.code(), Some(22));
+        assert_eq!(Chrom::ChrX.code(), Some(23));
+        assert_eq!(Chrom::ChrM.code(), Some(25));
+        assert_eq!(Chrom::Other(Cow::Borrowed("chrEBV")).code(), None);
+        assert_eq!(Chrom::from_code(0), None);
+        assert_eq!(Chrom::from_code(26), None);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde_round_trip() {
+        let mut chroms = (1..=25)
+            .map(|c| Chrom::from_code(c).unwrap())
+            .collect::<Vec<_>>();
+        chroms.extend([
+            Chrom::Other(Cow::Borrowed("chrEBV")),
+            Chrom::Other(Cow::Borrowed("HLA-A*01:01")),
+            Chrom::Other(Cow::Borrowed("contig_α_染色体")),
+        ]);
+
+        for chrom in chroms {
+            let json = serde_json::to_string(&chrom).unwrap();
+            assert_eq!(json, format!("\"{}\"", chrom.as_str()));
+            assert_eq!(serde_json::from_str::<Chrom>(&json).unwrap(), chrom);
+
+            let bin = bincode::serialize(&chrom).unwrap();
+            assert_eq!(bincode::deserialize::<Chrom>(&bin).unwrap(), chrom);
+        }
+
+        // the variant index, then the name for other contigs.
+        assert_eq!(
+            bincode::serialize(&Chrom::ChrX).unwrap(),
+            23_u32.to_le_bytes()
+        );
+        let bin = bincode::serialize(&Chrom::Other(Cow::Borrowed("chrEBV"))).unwrap();
+        assert_eq!(bin[..4], 0_u32.to_le_bytes());
+        assert!(bincode::deserialize::<Chrom>(&26_u32.to_le_bytes()).is_err());
+    }
+
     #[test]
     fn test_to_prefixed_and_to_unprefixed() {
         let standard = Chrom::Chr1;
@@ -465,4 +849,55 @@ mod tests {
         ));
         assert_eq!(other_mixed_case.to_unprefixed(), "ChrEBV");
     }
+
+    #[test]
+    fn test_natural_cmp() {
+        let mut chroms = [
+            "chrUn_10", "chrX", "chrUn_2", "chr2", "chrEBV", "chr10", "chrUn_02",
+        ]
+        .map(Chrom::from)
+        .to_vec();
+        chroms.sort_by(Chrom::natural_cmp);
+        assert_eq!(
+            chroms.iter().map(|c| c.as_ref()).collect::<Vec<_>>(),
+            vec![
+                "chr2", "chr10", "chrX", "chrEBV", "chrUn_2", "chrUn_02", "chrUn_10"
+            ]
+        );
+        assert_eq!(
+            Chrom::from("chrUn_10").natural_cmp(&Chrom::from("chrUn_10")),
+            Ordering::Equal
+        );
+    }
+
+    #[test]
+    fn test_contig_interner() {
+        let interner =
+            ContigInterner::from_names(["chr1", "chrUn_KI270742v1", "EBV", "chrEBV", "X"]);
+        assert_eq!(interner.len(), 2);
+
+        assert_eq!(interner.chrom("1"), Chrom::Chr1);
+        for name in ["chrEBV", "EBV"] {
+            let chrom = interner.chrom(name);
+            assert!(
+                matches!(chrom, Chrom::Other(Cow::Borrowed("chrEBV"))),
+                "{:?}",
+                chrom
+            );
+            // as parsed.
+            assert_eq!(chrom, Chrom::from(name));
+        }
+        assert!(matches!(
+            interner.chrom("chrUn_KI270742v1"),
+            Chrom::Other(Cow::Borrowed(_))
+        ));
+
+        // not stored, so owned.
+        let chrom = interner.chrom("HLA-A");
+        assert!(
+            matches!(chrom, Chrom::Other(Cow::Owned(ref s)) if s == "chrHLA-A"),
+            "{:?}",
+            chrom
+        );
+    }
 }
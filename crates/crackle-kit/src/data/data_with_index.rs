@@ -1,9 +1,20 @@
-use std::fmt::Debug;
+use std::{
+    cmp::Ordering,
+    hash::{Hash, Hasher},
+};
 
-#[derive(Clone)]
+/// A value tagged with its original position in a stream.
+///
+/// Equality, hashing and ordering only look at `idx`, so a batch of these can be sorted
+/// back into input order with a plain `sort()`.
+///
+/// `skip` marks a slot whose data was dropped but whose index must still be
+/// accounted for, e.g. a filtered record in an ordered pipeline.
+#[derive(Debug, Clone)]
 pub struct DataWithIndex<T> {
     data: T,
     pub idx: usize,
+    pub skip: bool,
 }
 
 impl<T> DataWithIndex<T> {
@@ -11,22 +22,145 @@ impl<T> DataWithIndex<T> {
         &mut self.data
     }
 
-    pub fn data(&mut self) -> &T {
+    pub fn data(&self) -> &T {
         &self.data
     }
+
+    /// Take the inner value and its index.
+    pub fn into_inner(self) -> (T, usize) {
+        (self.data, self.idx)
+    }
+
+    /// Map the inner value, keeping the index and the skip flag.
+    pub fn map<U>(self, f: impl FnOnce(T) -> U) -> DataWithIndex<U> {
+        DataWithIndex {
+            data: f(self.data),
+            idx: self.idx,
+            skip: self.skip,
+        }
+    }
 }
 
 impl<T> DataWithIndex<T> {
     pub fn new(data: T, idx: usize) -> Self {
-        Self { data, idx }
+        Self {
+            data,
+            idx,
+            skip: false,
+        }
+    }
+}
+
+impl<T: Default> Default for DataWithIndex<T> {
+    fn default() -> Self {
+        Self::new(T::default(), 0)
+    }
+}
+
+impl<T> AsRef<T> for DataWithIndex<T> {
+    fn as_ref(&self) -> &T {
+        &self.data
+    }
+}
+
+impl<T> AsMut<T> for DataWithIndex<T> {
+    fn as_mut(&mut self) -> &mut T {
+        &mut self.data
+    }
+}
+
+impl<T> PartialEq for DataWithIndex<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.idx == other.idx
+    }
+}
+
+impl<T> Eq for DataWithIndex<T> {}
+
+impl<T> Hash for DataWithIndex<T> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.idx.hash(state);
     }
 }
 
-impl<T: Debug> Debug for DataWithIndex<T> {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        f.debug_struct("DataWithIndex")
-            .field("data", &self.data)
-            .field("idx", &self.idx)
-            .finish()
+impl<T> PartialOrd for DataWithIndex<T> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<T> Ord for DataWithIndex<T> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.idx.cmp(&other.idx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ordering_by_idx_only() {
+        let mut v = [
+            DataWithIndex::new("c", 2),
+            DataWithIndex::new("a", 0),
+            DataWithIndex::new("b", 1),
+        ];
+
+        v.sort();
+
+        assert_eq!(
+            v.iter().map(|d| *d.data()).collect::<Vec<_>>(),
+            vec!["a", "b", "c"]
+        );
+
+        // data is ignored by comparison.
+        assert_eq!(DataWithIndex::new("x", 5), DataWithIndex::new("y", 5));
+        assert!(DataWithIndex::new("z", 1) < DataWithIndex::new("a", 2));
+    }
+
+    #[test]
+    fn test_map_and_into_inner() {
+        let mut d = DataWithIndex::new(21, 7);
+        d.skip = true;
+        let mapped = d.map(|v| v * 2);
+
+        assert_eq!(mapped.idx, 7);
+        assert!(mapped.skip);
+        assert_eq!(*mapped.as_ref(), 42);
+
+        let mut mapped = mapped.map(|v| v.to_string());
+        mapped.as_mut().push('!');
+
+        assert_eq!(mapped.into_inner(), ("42!".to_string(), 7));
+    }
+
+    #[test]
+    fn test_debug_and_hash() {
+        use std::collections::HashSet;
+
+        let mut d = DataWithIndex::new("read1", 3);
+        d.skip = true;
+        assert_eq!(
+            format!("{:?}", d),
+            r#"DataWithIndex { data: "read1", idx: 3, skip: true }"#
+        );
+
+        // hashed like compared, by index.
+        let set = [
+            d,
+            DataWithIndex::new("read2", 3),
+            DataWithIndex::new("read3", 4),
+        ]
+        .into_iter()
+        .collect::<HashSet<_>>();
+        assert_eq!(set.len(), 2);
+    }
+
+    #[test]
+    fn test_default() {
+        let d: DataWithIndex<Vec<u8>> = DataWithIndex::default();
+        assert!(d.data().is_empty());
+        assert_eq!(d.idx, 0);
     }
 }
@@ -0,0 +1,215 @@
+//! Conversions of [`GenomeRegion`]s and [`Chrom`]s to and from the types of
+//! rust-bio, and of noodles with the `noodles` feature, for code written
+//! against them.
+//!
+//! Records of other libraries go through the filters and tag helpers of this
+//! crate by implementing [`RecordView`](crate::bam::record_view::RecordView).
+
+use std::borrow::Cow;
+
+use bio_types::genome::{AbstractInterval, Interval};
+
+use crate::{
+    data::{chrom::Chrom, locus::GenomeRegion},
+    errors::LocusError,
+};
+
+/// How contig names are written for another library. Names are read back in
+/// either style by [`Chrom::from`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ContigStyle {
+    /// `chr1`, as [`Chrom::as_str`], see [`Chrom::to_prefixed`].
+    #[default]
+    Prefixed,
+    /// `1`, see [`Chrom::to_unprefixed`].
+    Unprefixed,
+}
+
+impl Chrom<'_> {
+    /// The name of the contig in `style`.
+    pub fn to_style(&self, style: ContigStyle) -> Cow<'_, str> {
+        match style {
+            ContigStyle::Prefixed => self.to_prefixed(),
+            ContigStyle::Unprefixed => Cow::Borrowed(self.to_unprefixed()),
+        }
+    }
+}
+
+impl GenomeRegion<'_> {
+    /// The region as an [`Interval`], 0-based half-open, on its contig named in
+    /// `style`.
+    pub fn to_interval(&self, style: ContigStyle) -> Interval {
+        let start = (self.start - 1).max(0) as u64;
+        Interval::new(
+            self.contig.to_style(style).into_owned(),
+            start..(self.end.max(0) as u64).max(start),
+        )
+    }
+}
+
+impl From<&GenomeRegion<'_>> for Interval {
+    fn from(region: &GenomeRegion<'_>) -> Self {
+        region.to_interval(ContigStyle::Prefixed)
+    }
+}
+
+/// Fails on an empty interval, which has no 1-based inclusive equivalent.
+impl TryFrom<&Interval> for GenomeRegion<'static> {
+    type Error = LocusError;
+
+    fn try_from(interval: &Interval) -> Result<Self, Self::Error> {
+        let range = interval.range();
+        GenomeRegion::new(
+            interval.contig().to_string(),
+            range.start as i64 + 1,
+            range.end as i64,
+        )
+    }
+}
+
+#[cfg(feature = "noodles")]
+impl GenomeRegion<'_> {
+    /// The region as a [`noodles_core::Region`], 1-based inclusive like it, on
+    /// its contig named in `style`. Fails on a region starting before 1 or
+    /// ending before it starts, which the struct literal and the `From` tuple
+    /// conversions let through.
+    pub fn to_noodles_region(
+        &self,
+        style: ContigStyle,
+    ) -> Result<noodles_core::Region, LocusError> {
+        let position = |pos: i64| {
+            usize::try_from(pos)
+                .ok()
+                .and_then(noodles_core::Position::new)
+        };
+        match (position(self.start), position(self.end)) {
+            (Some(start), Some(end)) if start <= end => Ok(noodles_core::Region::new(
+                self.contig.to_style(style).into_owned(),
+                start..=end,
+            )),
+            _ => Err(LocusError::InvalidRegion(self.to_string())),
+        }
+    }
+}
+
+/// Fails as [`GenomeRegion::to_noodles_region`].
+#[cfg(feature = "noodles")]
+impl TryFrom<&GenomeRegion<'_>> for noodles_core::Region {
+    type Error = LocusError;
+
+    fn try_from(region: &GenomeRegion<'_>) -> Result<Self, Self::Error> {
+        region.to_noodles_region(ContigStyle::Prefixed)
+    }
+}
+
+/// A region without a start starts at 1; fails on one without an end, as the
+/// length of its contig is not known here.
+#[cfg(feature = "noodles")]
+impl TryFrom<&noodles_core::Region> for GenomeRegion<'static> {
+    type Error = LocusError;
+
+    fn try_from(region: &noodles_core::Region) -> Result<Self, Self::Error> {
+        use std::ops::Bound;
+
+        let end = match region.end() {
+            Bound::Included(end) => usize::from(end) as i64,
+            Bound::Excluded(end) => usize::from(end) as i64 - 1,
+            Bound::Unbounded => return Err(LocusError::InvalidRegion(region.to_string())),
+        };
+        let start = match region.start() {
+            Bound::Included(start) => usize::from(start) as i64,
+            Bound::Excluded(start) => usize::from(start) as i64 + 1,
+            Bound::Unbounded => 1,
+        };
+        GenomeRegion::new(region.name().to_string(), start, end)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_region_interval_roundtrip() {
+        let region = GenomeRegion::from(("chr1", 101, 200));
+        let interval = Interval::from(&region);
+        assert_eq!(interval.contig(), "chr1");
+        assert_eq!(interval.range(), 100..200);
+        assert_eq!(GenomeRegion::try_from(&interval).unwrap(), region);
+
+        let interval = region.to_interval(ContigStyle::Unprefixed);
+        assert_eq!(interval.contig(), "1");
+        // `1` and `chr1` are the same contig.
+        assert_eq!(GenomeRegion::try_from(&interval).unwrap(), region);
+
+        let region = GenomeRegion::from(("chrUn_KI270302v1", 1, 1));
+        let back = GenomeRegion::try_from(&Interval::from(&region)).unwrap();
+        assert_eq!(back, region);
+
+        let empty = Interval::new("chr1".to_string(), 10..10);
+        assert!(matches!(
+            GenomeRegion::try_from(&empty),
+            Err(LocusError::InvertedRegion { .. })
+        ));
+    }
+
+    #[cfg(feature = "noodles")]
+    #[test]
+    fn test_region_noodles_roundtrip() {
+        use noodles_core::{Position, Region};
+
+        let region = GenomeRegion::from(("chr1", 101, 200));
+        let noodles = Region::try_from(&region).unwrap();
+        assert_eq!(noodles.to_string(), "chr1:101-200");
+        assert_eq!(GenomeRegion::try_from(&noodles).unwrap(), region);
+
+        let noodles = region.to_noodles_region(ContigStyle::Unprefixed).unwrap();
+        assert_eq!(noodles.name(), "1");
+        assert_eq!(GenomeRegion::try_from(&noodles).unwrap(), region);
+
+        let region = GenomeRegion::from(("chrUn_KI270302v1", 1, 1));
+        let back = GenomeRegion::try_from(&Region::try_from(&region).unwrap()).unwrap();
+        assert_eq!(back, region);
+
+        // unchecked regions `to_interval` clamps are not valid noodles regions.
+        for (start, end) in [(0, 10), (-5, 10), (20, 10)] {
+            let region = GenomeRegion::from(("chr1", start, end));
+            assert!(matches!(
+                Region::try_from(&region),
+                Err(LocusError::InvalidRegion(s)) if s == format!("chr1:{}-{}", start, end)
+            ));
+        }
+
+        // the same region through rust-bio.
+        let parsed: Region = "chrX:5-8".parse().unwrap();
+        let region = GenomeRegion::try_from(&parsed).unwrap();
+        let interval = Interval::from(&region);
+        assert_eq!(interval.range(), 4..8);
+        assert_eq!(GenomeRegion::try_from(&interval).unwrap(), region);
+
+        let open_start = Region::new("chr2", ..=Position::try_from(10).unwrap());
+        assert_eq!(
+            GenomeRegion::try_from(&open_start).unwrap(),
+            GenomeRegion::from(("chr2", 1, 10))
+        );
+        let open_end = Region::new("chr2", Position::try_from(10).unwrap()..);
+        assert!(matches!(
+            GenomeRegion::try_from(&open_end),
+            Err(LocusError::InvalidRegion(_))
+        ));
+    }
+
+    #[test]
+    fn test_chrom_styles() {
+        let chrom = Chrom::from("1");
+        assert_eq!(chrom.to_style(ContigStyle::Prefixed), "chr1");
+        assert_eq!(chrom.to_style(ContigStyle::Unprefixed), "1");
+        for style in [ContigStyle::Prefixed, ContigStyle::Unprefixed] {
+            assert_eq!(Chrom::from(&*chrom.to_style(style)), chrom);
+        }
+
+        let other = Chrom::from("HLA-A*01:01");
+        assert_eq!(other.to_style(ContigStyle::Prefixed), "chrHLA-A*01:01");
+        assert_eq!(other.to_style(ContigStyle::Unprefixed), "HLA-A*01:01");
+    }
+}
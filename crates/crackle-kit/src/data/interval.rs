@@ -0,0 +1,673 @@
+//! Sets of [`GenomeRegion`]s with overlap, containment and nearest queries, e.g.
+//! the targets of a capture kit, and their unions, intersections and
+//! differences, e.g. the targets out of a blacklist.
+
+use std::collections::{BTreeSet, HashMap};
+
+use crate::data::{
+    chrom::Chrom,
+    locus::{GenomeCoordinate, GenomeRegion},
+    seq_dict::SeqDict,
+};
+
+/// Regions of a contig sorted by start, with the running maximum of their ends.
+///
+/// `max_end[i]` is the largest end of `regions[..=i]`, with the index of the region
+/// having it. It does not decrease, so the first region which may reach a position
+/// is found by binary search as well. Searches run on `starts` and `max_end`,
+/// which are denser in cache than the regions.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+struct ContigRegions {
+    regions: Vec<GenomeRegion<'static>>,
+    starts: Vec<i64>,
+    max_end: Vec<(i64, usize)>,
+}
+
+impl ContigRegions {
+    fn new(mut regions: Vec<GenomeRegion<'static>>, merge: bool) -> Self {
+        regions.sort_by_key(|r| (r.start, r.end));
+
+        if merge {
+            let mut merged: Vec<GenomeRegion<'static>> = Vec::with_capacity(regions.len());
+            for region in regions {
+                match merged.last_mut() {
+                    // overlapping or book-ended.
+                    Some(last) if region.start <= last.end + 1 => {
+                        last.end = last.end.max(region.end)
+                    }
+                    _ => merged.push(region),
+                }
+            }
+            regions = merged;
+        }
+
+        let mut max_end = Vec::with_capacity(regions.len());
+        for (i, region) in regions.iter().enumerate() {
+            match max_end.last() {
+                Some(&(end, j)) if end >= region.end => max_end.push((end, j)),
+                _ => max_end.push((region.end, i)),
+            }
+        }
+
+        let starts = regions.iter().map(|r| r.start).collect();
+
+        Self {
+            regions,
+            starts,
+            max_end,
+        }
+    }
+
+    /// Regions with `start <= end` and `end >= start`, in the order of their start.
+    fn overlapping(&self, start: i64, end: i64) -> impl Iterator<Item = &GenomeRegion<'static>> {
+        let lo = self
+            .max_end
+            .partition_point(|&(max_end, _)| max_end < start);
+        let hi = self.starts.partition_point(|&s| s <= end);
+
+        self.regions[lo..hi.max(lo)]
+            .iter()
+            .filter(move |r| r.end >= start)
+    }
+
+    /// The bases of the regions, as sorted `(start, end)`s without overlapping
+    /// or book-ended ones.
+    fn spans(&self) -> Vec<(i64, i64)> {
+        merge_spans(
+            self.regions
+                .iter()
+                .filter(|r| r.end >= r.start)
+                .map(|r| (r.start, r.end))
+                .collect(),
+        )
+    }
+
+    fn nearest(&self, pos: i64) -> Option<(&GenomeRegion<'static>, i64)> {
+        if let Some(region) = self.overlapping(pos, pos).next() {
+            return Some((region, 0));
+        }
+
+        // regions starting after pos, and the one reaching furthest among the others.
+        let idx = self.starts.partition_point(|&s| s <= pos);
+        let right = self.regions.get(idx).map(|r| (r, r.start - pos));
+        let left = idx
+            .checked_sub(1)
+            .map(|i| self.max_end[i])
+            .map(|(end, i)| (&self.regions[i], pos - end));
+
+        match (left, right) {
+            (Some(l), Some(r)) if r.1 < l.1 => Some(r),
+            (Some(l), _) => Some(l),
+            (None, r) => r,
+        }
+    }
+}
+
+/// Regions indexed per contig for fast queries.
+///
+/// Contig names are normalized as [`Chrom`] does, so `1` and `chr1` are the same
+/// contig. Positions are 1-based and inclusive, as in [`GenomeRegion`].
+///
+/// Built once, then queried in O(log n) plus the number of regions which reach past
+/// the start of the query without overlapping it, which is small for the usual,
+/// mostly disjoint, region sets.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RegionSet {
+    contigs: HashMap<String, ContigRegions>,
+    len: usize,
+}
+
+impl RegionSet {
+    /// Index `regions`. With `merge`, overlapping and book-ended regions (e.g.
+    /// `1-10` and `11-20`) are merged into one; otherwise every region is kept,
+    /// duplicates included.
+    pub fn new<'a>(regions: impl IntoIterator<Item = GenomeRegion<'a>>, merge: bool) -> Self {
+        let mut by_contig = HashMap::<String, Vec<GenomeRegion<'static>>>::new();
+        for region in regions {
+            by_contig
+                .entry(contig_key(region.contig.as_str()))
+                .or_default()
+                .push(region.into_owned());
+        }
+
+        let contigs = by_contig
+            .into_iter()
+            .map(|(contig, regions)| (contig, ContigRegions::new(regions, merge)))
+            .collect::<HashMap<_, _>>();
+        let len = contigs.values().map(|c| c.regions.len()).sum();
+
+        Self { contigs, len }
+    }
+
+    /// Number of regions, after merging.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    fn contig(&self, contig: &Chrom<'_>) -> Option<&ContigRegions> {
+        self.contigs.get(contig.as_str()).or_else(|| match contig {
+            // names not in the standard form, e.g. `Other("1")`.
+            Chrom::Other(name) => self.contigs.get(&contig_key(name)),
+            _ => None,
+        })
+    }
+
+    /// Whether a region contains `coord`.
+    pub fn contains(&self, coord: &GenomeCoordinate<'_>) -> bool {
+        self.overlapping(&GenomeRegion::from(coord))
+            .next()
+            .is_some()
+    }
+
+    /// Regions sharing at least one base with `region`, in the order of their start.
+    pub fn overlapping<'s>(
+        &'s self,
+        region: &GenomeRegion<'_>,
+    ) -> impl Iterator<Item = &'s GenomeRegion<'static>> {
+        let (start, end) = (region.start, region.end);
+        self.contig(&region.contig)
+            .into_iter()
+            .flat_map(move |c| c.overlapping(start, end))
+    }
+
+    /// The region closest to `coord`, with its distance in bp, 0 if it contains
+    /// `coord`. Of two regions at the same distance, the one before `coord` wins.
+    pub fn nearest(&self, coord: &GenomeCoordinate<'_>) -> Option<(&GenomeRegion<'static>, i64)> {
+        self.contig(&coord.contig)?.nearest(coord.pos)
+    }
+
+    /// Fraction of the bases of `region` covered by the set.
+    pub fn coverage_fraction(&self, region: &GenomeRegion<'_>) -> f64 {
+        let len = region.end - region.start + 1;
+        if len <= 0 {
+            return 0.0;
+        }
+
+        // overlapping regions come by start, so covered bases are counted once
+        // by skipping those before `covered_to`.
+        let mut covered = 0;
+        let mut covered_to = region.start - 1;
+        for r in self.overlapping(region) {
+            let start = r.start.max(covered_to + 1);
+            let end = r.end.min(region.end);
+            if end >= start {
+                covered += end - start + 1;
+                covered_to = end;
+            }
+        }
+
+        covered as f64 / len as f64
+    }
+
+    /// `region` without the bases of the set at either of its ends, `None` if the
+    /// set covers all of it. Bases of the set within the region are kept.
+    pub(crate) fn trim_ends(&self, region: &GenomeRegion<'_>) -> Option<(i64, i64)> {
+        let spans = merge_spans(self.overlapping(region).map(|r| (r.start, r.end)).collect());
+        let (mut start, mut end) = (region.start, region.end);
+        if let Some(&(first_start, first_end)) = spans.first()
+            && first_start <= start
+        {
+            start = first_end + 1;
+        }
+        if let Some(&(last_start, last_end)) = spans.last()
+            && last_end >= end
+        {
+            end = last_start - 1;
+        }
+        (start <= end).then_some((start, end))
+    }
+
+    /// The bases of the set not in `other`, e.g. the targets out of a blacklist.
+    ///
+    /// Like the other set operations, the regions returned are sorted by contig
+    /// and start, without overlapping or book-ended ones.
+    pub fn subtract(&self, other: &RegionSet) -> Vec<GenomeRegion<'static>> {
+        self.combine(other, subtract_spans)
+    }
+
+    /// The bases in both sets.
+    pub fn intersect(&self, other: &RegionSet) -> Vec<GenomeRegion<'static>> {
+        self.combine(other, intersect_spans)
+    }
+
+    /// The bases in either set.
+    pub fn union(&self, other: &RegionSet) -> Vec<GenomeRegion<'static>> {
+        self.combine(other, |a, b| {
+            let mut spans = [a, b].concat();
+            spans.sort_unstable();
+            merge_spans(spans)
+        })
+    }
+
+    /// The bases of the set, with overlapping and book-ended regions merged,
+    /// however the set was built.
+    pub fn merged(&self) -> Vec<GenomeRegion<'static>> {
+        self.combine(&RegionSet::default(), |a, _| a.to_vec())
+    }
+
+    /// The bases of the contigs of `dict` not in the set, the gaps between its
+    /// regions and the ends of the contigs. Regions on contigs not in `dict`
+    /// are ignored, and regions past the end of their contig clipped.
+    pub fn complement_within(&self, dict: &SeqDict) -> Vec<GenomeRegion<'static>> {
+        let mut regions = vec![];
+        for entry in dict.entries() {
+            let spans = self
+                .contig(&entry.contig)
+                .map(ContigRegions::spans)
+                .unwrap_or_default();
+            let contig_span = [(1, entry.len as i64)];
+            regions.extend(
+                subtract_spans(&contig_span, &spans)
+                    .into_iter()
+                    .map(|(start, end)| GenomeRegion {
+                        contig: entry.contig.clone(),
+                        start,
+                        end,
+                    }),
+            );
+        }
+        regions.sort_by(|a, b| (&a.contig, a.start).cmp(&(&b.contig, b.start)));
+        regions
+    }
+
+    /// `op` of the spans of each contig of either set.
+    fn combine(
+        &self,
+        other: &RegionSet,
+        op: impl Fn(&[(i64, i64)], &[(i64, i64)]) -> Vec<(i64, i64)>,
+    ) -> Vec<GenomeRegion<'static>> {
+        let keys = self
+            .contigs
+            .keys()
+            .chain(other.contigs.keys())
+            .collect::<BTreeSet<_>>();
+
+        let mut regions = vec![];
+        for key in keys {
+            let (a, b) = (self.contigs.get(key), other.contigs.get(key));
+            let Some(contig) = a.or(b).and_then(|c| c.regions.first()) else {
+                continue;
+            };
+            let spans = |c: Option<&ContigRegions>| c.map(ContigRegions::spans).unwrap_or_default();
+            regions.extend(
+                op(&spans(a), &spans(b))
+                    .into_iter()
+                    .map(|(start, end)| GenomeRegion {
+                        contig: contig.contig.clone(),
+                        start,
+                        end,
+                    }),
+            );
+        }
+        regions.sort_by(|a, b| (&a.contig, a.start).cmp(&(&b.contig, b.start)));
+        regions
+    }
+}
+
+/// Merge overlapping and book-ended spans sorted by start.
+fn merge_spans(spans: Vec<(i64, i64)>) -> Vec<(i64, i64)> {
+    let mut merged: Vec<(i64, i64)> = Vec::with_capacity(spans.len());
+    for (start, end) in spans {
+        match merged.last_mut() {
+            Some(last) if start <= last.1 + 1 => last.1 = last.1.max(end),
+            _ => merged.push((start, end)),
+        }
+    }
+    merged
+}
+
+/// The pieces of `spans` out of `mask`, both sorted and disjoint. A span may be
+/// clipped on either side, split by several spans of `mask`, or removed.
+fn subtract_spans(spans: &[(i64, i64)], mask: &[(i64, i64)]) -> Vec<(i64, i64)> {
+    let mut pieces = vec![];
+    let mut j = 0;
+    for &(start, end) in spans {
+        // masks ending before the span end before the next ones too.
+        while j < mask.len() && mask[j].1 < start {
+            j += 1;
+        }
+
+        let mut from = start;
+        for &(mask_start, mask_end) in mask[j..].iter().take_while(|m| m.0 <= end) {
+            if mask_start > from {
+                pieces.push((from, mask_start - 1));
+            }
+            from = from.max(mask_end + 1);
+        }
+        if from <= end {
+            pieces.push((from, end));
+        }
+    }
+    pieces
+}
+
+/// The bases of both `a` and `b`, both sorted and disjoint.
+fn intersect_spans(a: &[(i64, i64)], b: &[(i64, i64)]) -> Vec<(i64, i64)> {
+    let mut both = vec![];
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() && j < b.len() {
+        let start = a[i].0.max(b[j].0);
+        let end = a[i].1.min(b[j].1);
+        if start <= end {
+            both.push((start, end));
+        }
+        // the span ending first overlaps nothing else of the other.
+        if a[i].1 < b[j].1 {
+            i += 1;
+        } else {
+            j += 1;
+        }
+    }
+    both
+}
+
+fn contig_key(name: &str) -> String {
+    Chrom::from(name).as_str().to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+
+    use super::*;
+    use crate::data::seq_dict::SeqEntry;
+
+    fn region(contig: &str, start: i64, end: i64) -> GenomeRegion<'static> {
+        GenomeRegion::from((contig, start, end))
+    }
+
+    fn coord(contig: &str, pos: i64) -> GenomeCoordinate<'static> {
+        GenomeCoordinate {
+            contig: Chrom::from(contig),
+            pos,
+        }
+    }
+
+    #[test]
+    fn test_queries() {
+        let set = RegionSet::new(
+            [
+                region("chr1", 100, 200),
+                region("1", 150, 160),
+                region("chr1", 1_000, 5_000),
+                region("chr1", 300, 400),
+                region("chrEBV", 10, 20),
+            ],
+            false,
+        );
+
+        assert_eq!(set.len(), 5);
+        assert!(set.contains(&coord("chr1", 155)));
+        assert!(set.contains(&coord("chr1", 5_000)));
+        assert!(!set.contains(&coord("chr1", 250)));
+        assert!(!set.contains(&coord("chr2", 155)));
+        assert!(set.contains(&coord("chrEBV", 10)));
+
+        assert_eq!(
+            set.overlapping(&region("chr1", 160, 1_000))
+                .map(|r| (r.start, r.end))
+                .collect::<Vec<_>>(),
+            vec![(100, 200), (150, 160), (300, 400), (1_000, 5_000)]
+        );
+
+        assert_eq!(set.nearest(&coord("chr1", 155)).unwrap().1, 0);
+        // 50 bp after 200, 50 bp before 300: the one before wins.
+        assert_eq!(
+            set.nearest(&coord("chr1", 250)),
+            Some((&region("chr1", 100, 200), 50))
+        );
+        assert_eq!(
+            set.nearest(&coord("chr1", 10)),
+            Some((&region("chr1", 100, 200), 90))
+        );
+        assert_eq!(
+            set.nearest(&coord("chr1", 6_000)),
+            Some((&region("chr1", 1_000, 5_000), 1_000))
+        );
+        assert_eq!(set.nearest(&coord("chr2", 1)), None);
+
+        // 101-200 and 300-350 of 1-350.
+        assert_eq!(
+            set.coverage_fraction(&region("chr1", 1, 350)),
+            152.0 / 350.0
+        );
+    }
+
+    #[test]
+    fn test_merge() {
+        let regions = [
+            region("chr1", 100, 200),
+            region("chr1", 100, 200),
+            region("chr1", 150, 250),
+            region("chr1", 251, 260),
+            region("chr1", 262, 270),
+        ];
+
+        let kept = RegionSet::new(regions.clone(), false);
+        assert_eq!(kept.len(), 5);
+        assert_eq!(kept.overlapping(&region("chr1", 160, 160)).count(), 3);
+
+        let merged = RegionSet::new(regions, true);
+        assert_eq!(merged.len(), 2);
+        assert_eq!(
+            merged
+                .overlapping(&region("chr1", 1, 1_000))
+                .map(|r| (r.start, r.end))
+                .collect::<Vec<_>>(),
+            vec![(100, 260), (262, 270)]
+        );
+        assert_eq!(
+            kept.coverage_fraction(&region("chr1", 100, 270)),
+            170.0 / 171.0
+        );
+        assert_eq!(kept.merged(), merged.merged());
+        assert_eq!(
+            kept.merged(),
+            [region("chr1", 100, 260), region("chr1", 262, 270)]
+        );
+    }
+
+    fn spans(regions: &[GenomeRegion<'_>]) -> Vec<(String, i64, i64)> {
+        regions
+            .iter()
+            .map(|r| (r.contig.to_string(), r.start, r.end))
+            .collect()
+    }
+
+    fn n_bases(regions: &[GenomeRegion<'_>]) -> i64 {
+        regions.iter().map(|r| r.end - r.start + 1).sum()
+    }
+
+    #[test]
+    fn test_set_operations() {
+        let targets = RegionSet::new(
+            [
+                region("chr1", 100, 200),
+                region("chr1", 150, 300),
+                region("chr1", 1_000, 1_100),
+                region("chr1", 2_000, 2_100),
+                region("chr2", 500, 600),
+                region("chrX", 10, 20),
+            ],
+            false,
+        );
+        let blacklist = RegionSet::new(
+            [
+                // clips 100-300 on the left, and splits it.
+                region("1", 50, 120),
+                region("chr1", 180, 190),
+                region("chr1", 250, 260),
+                // swallows 1000-1100.
+                region("chr1", 900, 1_200),
+                // clips 2000-2100 on the right.
+                region("chr1", 2_050, 2_500),
+                region("chr3", 1, 100),
+            ],
+            false,
+        );
+
+        assert_eq!(
+            spans(&targets.subtract(&blacklist)),
+            [
+                ("chr1".to_string(), 121, 179),
+                ("chr1".to_string(), 191, 249),
+                ("chr1".to_string(), 261, 300),
+                ("chr1".to_string(), 2_000, 2_049),
+                ("chr2".to_string(), 500, 600),
+                ("chrX".to_string(), 10, 20),
+            ]
+        );
+        assert_eq!(
+            spans(&targets.intersect(&blacklist)),
+            [
+                ("chr1".to_string(), 100, 120),
+                ("chr1".to_string(), 180, 190),
+                ("chr1".to_string(), 250, 260),
+                ("chr1".to_string(), 1_000, 1_100),
+                ("chr1".to_string(), 2_050, 2_100),
+            ]
+        );
+        assert_eq!(
+            spans(&targets.union(&blacklist)),
+            [
+                ("chr1".to_string(), 50, 300),
+                ("chr1".to_string(), 900, 1_200),
+                ("chr1".to_string(), 2_000, 2_500),
+                ("chr2".to_string(), 500, 600),
+                ("chr3".to_string(), 1, 100),
+                ("chrX".to_string(), 10, 20),
+            ]
+        );
+
+        let dict = SeqDict::new([("chr1", 3_000), ("chr2", 600), ("chrM", 100)].map(
+            |(contig, len)| SeqEntry {
+                contig: Chrom::from(contig).into_owned(),
+                len,
+                md5: None,
+            },
+        ));
+        assert_eq!(
+            spans(&targets.complement_within(&dict)),
+            [
+                ("chr1".to_string(), 1, 99),
+                ("chr1".to_string(), 301, 999),
+                ("chr1".to_string(), 1_101, 1_999),
+                ("chr1".to_string(), 2_101, 3_000),
+                ("chr2".to_string(), 1, 499),
+                ("chrM".to_string(), 1, 100),
+            ]
+        );
+    }
+
+    fn regions_strategy() -> impl Strategy<Value = Vec<GenomeRegion<'static>>> {
+        prop::collection::vec(
+            (
+                prop::sample::select(vec!["chr1", "chr2"]),
+                1_i64..1_000,
+                0_i64..150,
+            ),
+            0..60,
+        )
+        .prop_map(|v| {
+            v.into_iter()
+                .map(|(contig, start, len)| region(contig, start, start + len))
+                .collect()
+        })
+    }
+
+    proptest! {
+        #[test]
+        fn test_queries_match_brute_force(
+            regions in regions_strategy(),
+            merge in any::<bool>(),
+            queries in prop::collection::vec((1_i64..1_200, 0_i64..100), 20),
+        ) {
+            let set = RegionSet::new(regions.clone(), merge);
+            let on_chr1 = regions.iter().filter(|r| r.contig == Chrom::Chr1).collect::<Vec<_>>();
+
+            for (start, len) in queries {
+                let (end, pos) = (start + len, start);
+
+                prop_assert_eq!(
+                    set.contains(&coord("chr1", pos)),
+                    on_chr1.iter().any(|r| r.start <= pos && pos <= r.end)
+                );
+
+                let distance = |r: &GenomeRegion| (r.start - pos).max(pos - r.end).max(0);
+                prop_assert_eq!(
+                    set.nearest(&coord("chr1", pos)).map(|(_, d)| d),
+                    on_chr1.iter().map(|r| distance(r)).min()
+                );
+
+                let covered = (start..=end)
+                    .filter(|&p| on_chr1.iter().any(|r| r.start <= p && p <= r.end))
+                    .count();
+                let query = region("chr1", start, end);
+                prop_assert_eq!(
+                    set.coverage_fraction(&query),
+                    covered as f64 / (len + 1) as f64
+                );
+
+                if !merge {
+                    let mut expected = on_chr1
+                        .iter()
+                        .filter(|r| r.start <= end && r.end >= start)
+                        .map(|r| (r.start, r.end))
+                        .collect::<Vec<_>>();
+                    expected.sort();
+                    let found = set.overlapping(&query).map(|r| (r.start, r.end)).collect::<Vec<_>>();
+                    prop_assert_eq!(found, expected);
+                }
+            }
+        }
+
+        #[test]
+        fn test_set_operations_conserve_bases(
+            regions in regions_strategy(),
+            mask in regions_strategy(),
+        ) {
+            let (set, mask) = (RegionSet::new(regions, false), RegionSet::new(mask, false));
+            let empty = RegionSet::default();
+            let merged = set.union(&empty);
+            let subtracted = set.subtract(&mask);
+            let intersected = set.intersect(&mask);
+
+            // the pieces out of the mask and those in it make up the set.
+            let pieces = RegionSet::new(subtracted.clone(), false)
+                .union(&RegionSet::new(intersected.clone(), false));
+            prop_assert_eq!(&pieces, &merged);
+            prop_assert!(RegionSet::new(subtracted.clone(), false).intersect(&mask).is_empty());
+            prop_assert_eq!(
+                n_bases(&subtracted) + n_bases(&intersected),
+                n_bases(&merged)
+            );
+            prop_assert_eq!(
+                n_bases(&set.union(&mask)),
+                n_bases(&merged) + n_bases(&mask.union(&empty)) - n_bases(&intersected)
+            );
+
+            for regions in [&merged, &subtracted, &intersected] {
+                let disjoint = regions
+                    .windows(2)
+                    .all(|w| w[0].contig < w[1].contig || w[0].end + 1 < w[1].start);
+                prop_assert!(disjoint);
+            }
+
+            // every base of the contigs is in the set or its complement.
+            let dict = SeqDict::new(["chr1", "chr2"].map(|contig| {
+                SeqEntry {
+                    contig: Chrom::from(contig).into_owned(),
+                    len: 1_200,
+                    md5: None,
+                }
+            }));
+            let complement = set.complement_within(&dict);
+            prop_assert!(RegionSet::new(complement.clone(), false).intersect(&set).is_empty());
+            prop_assert_eq!(n_bases(&complement) + n_bases(&merged), 2 * 1_200);
+        }
+    }
+}
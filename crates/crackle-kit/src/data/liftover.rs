@@ -0,0 +1,554 @@
+//! Lifting of [`GenomeCoordinate`]s and [`GenomeRegion`]s between assemblies,
+//! e.g. hg19 to hg38, by UCSC chain files.
+//!
+//! A chain aligns a region of the source assembly (`t`, the target of the
+//! chain format) to one of the destination (`q`, the query) in ungapped blocks.
+//! Positions between blocks are in a gap, and do not map.
+//!
+//! ```no_run
+//! use crackle_kit::data::{liftover::{ChainLiftover, LiftResult}, locus::GenomeCoordinate};
+//!
+//! let liftover = ChainLiftover::from_path("hg19ToHg38.over.chain.gz")?;
+//! match liftover.lift_coordinate(&GenomeCoordinate::new("chr1", 1_000_000)?) {
+//!     LiftResult::Mapped(lifted) => println!("{:?}", lifted.locus),
+//!     LiftResult::Split(lifted) => println!("{} loci", lifted.len()),
+//!     LiftResult::Unmapped => println!("unmapped"),
+//! }
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+
+use std::{collections::HashMap, path::Path};
+
+use anyhow::{anyhow, bail};
+
+use crate::{
+    data::{
+        chrom::Chrom,
+        locus::{GenomeCoordinate, GenomeRegion},
+    },
+    errors::Error,
+    utils::textio::LineReader,
+};
+
+/// A locus lifted to the destination assembly.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Lifted<T> {
+    pub locus: T,
+    /// Whether the locus maps to the reverse strand of the destination, so that
+    /// e.g. its bases are to be reverse complemented.
+    pub strand_flipped: bool,
+}
+
+/// Result of [`ChainLiftover::lift_coordinate`] and
+/// [`ChainLiftover::lift_region`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LiftResult<T> {
+    /// The locus maps whole to one place.
+    Mapped(Lifted<T>),
+    /// The locus maps in pieces, e.g. a region spanning a gap, or only partly,
+    /// or to several places, of overlapping chains. The pieces are in the order
+    /// of the source positions they map.
+    Split(Vec<Lifted<T>>),
+    Unmapped,
+}
+
+impl<T> LiftResult<T> {
+    pub fn is_mapped(&self) -> bool {
+        matches!(self, Self::Mapped(_))
+    }
+
+    /// The locus mapped to, if it maps whole to one place.
+    pub fn mapped(&self) -> Option<&Lifted<T>> {
+        match self {
+            Self::Mapped(lifted) => Some(lifted),
+            _ => None,
+        }
+    }
+}
+
+/// An ungapped block of a chain. Positions are 0-based, half-open.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Block {
+    t_start: i64,
+    t_end: i64,
+    /// Index of the destination contig in [`ChainLiftover::q_contigs`].
+    q_contig: usize,
+    /// Start on the strand of the chain, i.e. counted from the end of the
+    /// contig if `reverse`.
+    q_start: i64,
+    q_size: i64,
+    reverse: bool,
+}
+
+impl Block {
+    /// 1-based inclusive destination span of the source positions
+    /// `start..end`, 0-based half-open, within the block.
+    fn map(&self, start: i64, end: i64) -> (i64, i64) {
+        let q_start = self.q_start + start - self.t_start;
+        let q_end = q_start + end - start;
+        if self.reverse {
+            (self.q_size - q_end + 1, self.q_size - q_start)
+        } else {
+            (q_start + 1, q_end)
+        }
+    }
+}
+
+/// Blocks of a source contig sorted by start, with the running maximum of their
+/// ends, as blocks of different chains may overlap.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+struct ContigBlocks {
+    blocks: Vec<Block>,
+    max_end: Vec<i64>,
+}
+
+impl ContigBlocks {
+    fn new(mut blocks: Vec<Block>) -> Self {
+        blocks.sort_by_key(|b| (b.t_start, b.t_end));
+        let max_end = blocks
+            .iter()
+            .scan(i64::MIN, |max, b| {
+                *max = (*max).max(b.t_end);
+                Some(*max)
+            })
+            .collect();
+
+        Self { blocks, max_end }
+    }
+
+    /// Blocks overlapping `start..end`, 0-based half-open.
+    fn overlapping(&self, start: i64, end: i64) -> impl Iterator<Item = &Block> {
+        let first = self.max_end.partition_point(|&max_end| max_end <= start);
+        self.blocks[first..]
+            .iter()
+            .take_while(move |b| b.t_start < end)
+            .filter(move |b| b.t_end > start)
+    }
+}
+
+/// The blocks of a chain file, by source contig.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ChainLiftover {
+    contigs: HashMap<String, ContigBlocks>,
+    q_contigs: Vec<Chrom<'static>>,
+    n_chains: usize,
+}
+
+impl ChainLiftover {
+    /// Load the chains of `path`, plain or gzipped (see
+    /// [`open_text`](crate::utils::textio::open_text)).
+    ///
+    /// A malformed chain, e.g. whose blocks do not add up to its span, is an
+    /// [`Error::Parse`] of its line.
+    pub fn from_path(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let mut lines = LineReader::from_path(path)?;
+        let mut blocks = HashMap::<String, Vec<Block>>::new();
+        let mut q_contig_ids = HashMap::<String, usize>::new();
+        let mut q_contigs = vec![];
+        let mut n_chains = 0;
+        let mut chain: Option<ChainState> = None;
+
+        while let Some(line) = lines.next_line()? {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let Some(state) = chain.as_mut() else {
+                let header =
+                    ChainHeader::parse(line).map_err(|e| lines.parse_error("chain header", e))?;
+                let n_q_contigs = q_contigs.len();
+                let q_contig = *q_contig_ids
+                    .entry(header.q_name.as_str().to_string())
+                    .or_insert_with(|| {
+                        q_contigs.push(header.q_name.clone());
+                        n_q_contigs
+                    });
+                chain = Some(ChainState {
+                    t: header.t_start,
+                    q: header.q_start,
+                    q_contig,
+                    blocks: vec![],
+                    header,
+                });
+                n_chains += 1;
+                continue;
+            };
+
+            let done = state
+                .push_line(line)
+                .map_err(|e| lines.parse_error("chain block", e))?;
+            if done && let Some(state) = chain.take() {
+                blocks
+                    .entry(state.header.t_name.as_str().to_string())
+                    .or_default()
+                    .extend(state.blocks);
+            }
+        }
+
+        if chain.is_some() {
+            return Err(lines.parse_error("chain", anyhow!("the last chain has no final block")));
+        }
+
+        Ok(Self {
+            contigs: blocks
+                .into_iter()
+                .map(|(contig, blocks)| (contig, ContigBlocks::new(blocks)))
+                .collect(),
+            q_contigs,
+            n_chains,
+        })
+    }
+
+    pub fn n_chains(&self) -> usize {
+        self.n_chains
+    }
+
+    /// Number of ungapped blocks of all chains.
+    pub fn n_blocks(&self) -> usize {
+        self.contigs.values().map(|c| c.blocks.len()).sum()
+    }
+
+    /// Pieces of `contig:start-end`, 1-based inclusive, lifted.
+    fn lift(&self, contig: &Chrom<'_>, start: i64, end: i64) -> Vec<Lifted<GenomeRegion<'static>>> {
+        let Some(blocks) = self.contigs.get(contig.as_str()) else {
+            return vec![];
+        };
+
+        blocks
+            .overlapping(start - 1, end)
+            .map(|block| {
+                let (q_start, q_end) =
+                    block.map(block.t_start.max(start - 1), block.t_end.min(end));
+                Lifted {
+                    locus: GenomeRegion {
+                        contig: self.q_contigs[block.q_contig].clone(),
+                        start: q_start,
+                        end: q_end,
+                    },
+                    strand_flipped: block.reverse,
+                }
+            })
+            .collect()
+    }
+
+    /// Lift `coord`. It is [`LiftResult::Split`] only if mapped by overlapping
+    /// chains.
+    pub fn lift_coordinate(
+        &self,
+        coord: &GenomeCoordinate<'_>,
+    ) -> LiftResult<GenomeCoordinate<'static>> {
+        let mut lifted = self
+            .lift(&coord.contig, coord.pos, coord.pos)
+            .into_iter()
+            .map(|l| Lifted {
+                locus: GenomeCoordinate {
+                    contig: l.locus.contig,
+                    pos: l.locus.start,
+                },
+                strand_flipped: l.strand_flipped,
+            })
+            .collect::<Vec<_>>();
+
+        match lifted.len() {
+            0 => LiftResult::Unmapped,
+            1 => LiftResult::Mapped(lifted.pop().unwrap()),
+            _ => LiftResult::Split(lifted),
+        }
+    }
+
+    /// Lift `region`. It is [`LiftResult::Mapped`] if it lies within one block,
+    /// [`LiftResult::Split`] into the pieces mapped if it spans a gap or the end
+    /// of a chain. Pieces of a reverse strand block are flipped, so the start
+    /// of a piece maps its last position.
+    pub fn lift_region(&self, region: &GenomeRegion<'_>) -> LiftResult<GenomeRegion<'static>> {
+        let mut lifted = self.lift(&region.contig, region.start, region.end);
+        let len = region.end - region.start;
+
+        match lifted.as_slice() {
+            [] => LiftResult::Unmapped,
+            [l] if l.locus.end - l.locus.start == len => LiftResult::Mapped(lifted.pop().unwrap()),
+            _ => LiftResult::Split(lifted),
+        }
+    }
+}
+
+/// The `chain` line of a chain, `chain score tName tSize tStrand tStart tEnd
+/// qName qSize qStrand qStart qEnd id`.
+#[derive(Debug)]
+struct ChainHeader {
+    t_name: Chrom<'static>,
+    t_start: i64,
+    t_end: i64,
+    q_name: Chrom<'static>,
+    q_size: i64,
+    q_start: i64,
+    q_end: i64,
+    reverse: bool,
+}
+
+impl ChainHeader {
+    fn parse(line: &str) -> anyhow::Result<Self> {
+        let fields = line.split_whitespace().collect::<Vec<_>>();
+        if fields.first() != Some(&"chain") || !(12..=13).contains(&fields.len()) {
+            bail!(
+                "expected `chain score tName tSize tStrand tStart tEnd qName qSize qStrand qStart qEnd id`, got `{}`",
+                line
+            );
+        }
+        let int = |i: usize| {
+            fields[i]
+                .parse::<i64>()
+                .map_err(|_| anyhow!("invalid number `{}` in field {}", fields[i], i + 1))
+        };
+
+        if fields[4] != "+" {
+            bail!("unsupported source strand `{}`", fields[4]);
+        }
+        let reverse = match fields[9] {
+            "+" => false,
+            "-" => true,
+            strand => bail!("invalid strand `{}`", strand),
+        };
+
+        let header = Self {
+            t_name: Chrom::from(fields[2].to_string()),
+            t_start: int(5)?,
+            t_end: int(6)?,
+            q_name: Chrom::from(fields[7].to_string()),
+            q_size: int(8)?,
+            q_start: int(10)?,
+            q_end: int(11)?,
+            reverse,
+        };
+        let within = |start, end, size| 0 <= start && start <= end && end <= size;
+        if !within(header.t_start, header.t_end, int(3)?)
+            || !within(header.q_start, header.q_end, header.q_size)
+        {
+            bail!("span out of the contig in `{}`", line);
+        }
+
+        Ok(header)
+    }
+}
+
+/// A chain being read, at the start of its next block.
+struct ChainState {
+    header: ChainHeader,
+    t: i64,
+    q: i64,
+    q_contig: usize,
+    blocks: Vec<Block>,
+}
+
+impl ChainState {
+    /// Add the block of a `size dt dq` line, or of the final `size` line, which
+    /// ends the chain: `true` then.
+    fn push_line(&mut self, line: &str) -> anyhow::Result<bool> {
+        let fields = line
+            .split_whitespace()
+            .map(|f| {
+                f.parse::<i64>()
+                    .ok()
+                    .filter(|&n| n >= 0)
+                    .ok_or_else(|| anyhow!("invalid size `{}`", f))
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        let (size, gaps) = match *fields.as_slice() {
+            [size, dt, dq] => (size, Some((dt, dq))),
+            [size] => (size, None),
+            _ => bail!("expected `size dt dq` or `size`, got `{}`", line),
+        };
+
+        if size > 0 {
+            self.blocks.push(Block {
+                t_start: self.t,
+                t_end: self.t + size,
+                q_contig: self.q_contig,
+                q_start: self.q,
+                q_size: self.header.q_size,
+                reverse: self.header.reverse,
+            });
+        }
+        self.t += size;
+        self.q += size;
+
+        match gaps {
+            Some((dt, dq)) => {
+                self.t += dt;
+                self.q += dq;
+                if self.t > self.header.t_end || self.q > self.header.q_end {
+                    bail!("blocks run past the end of the chain");
+                }
+                Ok(false)
+            }
+            None => {
+                if (self.t, self.q) != (self.header.t_end, self.header.q_end) {
+                    bail!(
+                        "blocks end at {} and {}, not at the ends of the chain {} and {}",
+                        self.t,
+                        self.q,
+                        self.header.t_end,
+                        self.header.q_end
+                    );
+                }
+                Ok(true)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    /// chr1:101-200 to chr1 forward, with a gap of 10 bp in the source and 20 bp
+    /// in the destination after 50 bp; chr2:1-100 to chr5:601-700, reversed.
+    const CHAIN: &str = "\
+chain 1000 chr1 1000 + 100 200 chr1 1000 + 0 110 1
+50 10 20
+40
+
+chain 900 2 500 + 0 100 chr5 800 - 100 200 2
+100
+";
+
+    fn liftover(content: &str) -> Result<ChainLiftover, Error> {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("test.chain");
+        fs::write(&path, content).unwrap();
+        ChainLiftover::from_path(&path)
+    }
+
+    fn coord(contig: &str, pos: i64) -> GenomeCoordinate<'static> {
+        GenomeCoordinate::new(contig.to_string(), pos).unwrap()
+    }
+
+    fn region(contig: &str, start: i64, end: i64) -> GenomeRegion<'static> {
+        GenomeRegion::new(contig.to_string(), start, end).unwrap()
+    }
+
+    fn mapped<T>(locus: T, strand_flipped: bool) -> Lifted<T> {
+        Lifted {
+            locus,
+            strand_flipped,
+        }
+    }
+
+    #[test]
+    fn test_lift_coordinate() {
+        let liftover = liftover(CHAIN).unwrap();
+        assert_eq!((liftover.n_chains(), liftover.n_blocks()), (2, 3));
+
+        let lift = |contig, pos| liftover.lift_coordinate(&coord(contig, pos));
+        // forward block.
+        assert_eq!(
+            lift("chr1", 101),
+            LiftResult::Mapped(mapped(coord("chr1", 1), false))
+        );
+        assert_eq!(
+            lift("chr1", 150),
+            LiftResult::Mapped(mapped(coord("chr1", 50), false))
+        );
+        // the gap, then the next block, 20 bp on in the destination.
+        assert_eq!(lift("chr1", 151), LiftResult::Unmapped);
+        assert_eq!(lift("chr1", 160), LiftResult::Unmapped);
+        assert_eq!(
+            lift("chr1", 161),
+            LiftResult::Mapped(mapped(coord("chr1", 71), false))
+        );
+        assert_eq!(
+            lift("chr1", 200),
+            LiftResult::Mapped(mapped(coord("chr1", 110), false))
+        );
+        // out of the chain.
+        assert_eq!(lift("chr1", 100), LiftResult::Unmapped);
+        assert_eq!(lift("chr1", 201), LiftResult::Unmapped);
+        assert_eq!(lift("chr3", 150), LiftResult::Unmapped);
+
+        // reverse block, whatever the naming of the contig.
+        assert_eq!(
+            lift("chr2", 1),
+            LiftResult::Mapped(mapped(coord("chr5", 700), true))
+        );
+        assert_eq!(
+            lift("2", 100),
+            LiftResult::Mapped(mapped(coord("chr5", 601), true))
+        );
+    }
+
+    #[test]
+    fn test_lift_region() {
+        let liftover = liftover(CHAIN).unwrap();
+        let lift = |contig, start, end| liftover.lift_region(&region(contig, start, end));
+
+        assert_eq!(
+            lift("chr1", 111, 120),
+            LiftResult::Mapped(mapped(region("chr1", 11, 20), false))
+        );
+        assert_eq!(
+            lift("chr2", 1, 10),
+            LiftResult::Mapped(mapped(region("chr5", 691, 700), true))
+        );
+
+        // across the gap.
+        assert_eq!(
+            lift("chr1", 141, 170),
+            LiftResult::Split(vec![
+                mapped(region("chr1", 41, 50), false),
+                mapped(region("chr1", 71, 80), false),
+            ])
+        );
+        // partly out of the chain.
+        assert_eq!(
+            lift("chr1", 95, 105),
+            LiftResult::Split(vec![mapped(region("chr1", 1, 5), false)])
+        );
+        assert_eq!(lift("chr1", 151, 160), LiftResult::Unmapped);
+    }
+
+    #[test]
+    fn test_overlapping_chains() {
+        let content = format!("{CHAIN}\nchain 10 chr1 1000 + 120 130 chr7 100 + 0 10 3\n10\n");
+        let liftover = liftover(&content).unwrap();
+
+        assert_eq!(
+            liftover.lift_coordinate(&coord("chr1", 121)),
+            LiftResult::Split(vec![
+                mapped(coord("chr1", 21), false),
+                mapped(coord("chr7", 1), false),
+            ])
+        );
+        assert!(liftover.lift_coordinate(&coord("chr1", 131)).is_mapped());
+    }
+
+    #[test]
+    fn test_malformed_chains() {
+        let line_of = |content: &str| match liftover(content).unwrap_err() {
+            Error::Parse { line, .. } => line,
+            err => panic!("{:?}", err),
+        };
+
+        // blocks not adding up to the span.
+        assert_eq!(
+            line_of("chain 1 chr1 1000 + 0 100 chr1 1000 + 0 100 1\n90\n"),
+            2
+        );
+        assert_eq!(
+            line_of("chain 1 chr1 1000 + 0 100 chr1 1000 + 0 100 1\n90 20 20\n"),
+            2
+        );
+        // block before any chain, bad strand, missing final block.
+        assert_eq!(line_of("100\n"), 1);
+        assert_eq!(
+            line_of("chain 1 chr1 1000 + 0 100 chr1 1000 ? 0 100 1\n100\n"),
+            1
+        );
+        assert_eq!(
+            line_of("chain 1 chr1 1000 + 0 100 chr1 1000 + 0 100 1\n50 0 0\n"),
+            2
+        );
+    }
+}
@@ -1,5 +1,6 @@
-use std::str::FromStr;
+use std::fmt;
 use std::ops::Deref;
+use std::str::FromStr;
 
 use crate::data::chrom::Chrom;
 use crate::errors::{LocusError, PosError};
@@ -128,7 +129,8 @@ impl From<Pos> for i64 {
     }
 }
 
-#[derive(Debug, PartialEq, Eq, Clone)]
+#[derive(Debug, PartialEq, Eq, Clone, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct GenomeCoordinate<'a> {
     pub contig: Chrom<'a>,
 
@@ -136,7 +138,32 @@ pub struct GenomeCoordinate<'a> {
     pub pos: i64,
 }
 
-#[derive(Debug, PartialEq, Eq)]
+impl<'a> GenomeCoordinate<'a> {
+    /// A coordinate at 1-based `pos`, which must be positive.
+    ///
+    /// The struct literal skips the check, for positions known to be valid.
+    pub fn new(contig: impl Into<Chrom<'a>>, pos: i64) -> Result<Self, LocusError> {
+        Pos::from_1based(pos)?;
+        Ok(Self {
+            contig: contig.into(),
+            pos,
+        })
+    }
+
+    pub fn into_owned(self) -> GenomeCoordinate<'static> {
+        GenomeCoordinate {
+            contig: self.contig.into_owned(),
+            pos: self.pos,
+        }
+    }
+
+    pub fn to_owned_coordinate(&self) -> GenomeCoordinate<'static> {
+        self.clone().into_owned()
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct GenomeRegion<'a> {
     pub contig: Chrom<'a>,
 
@@ -156,9 +183,97 @@ impl<'a> From<(&str, i64, i64)> for GenomeRegion<'a> {
     }
 }
 
+impl<'a> From<(Chrom<'a>, i64, i64)> for GenomeRegion<'a> {
+    fn from(value: (Chrom<'a>, i64, i64)) -> Self {
+        Self {
+            contig: value.0,
+            start: value.1,
+            end: value.2,
+        }
+    }
+}
+
+/// 1 bp region at the coordinate.
+impl<'a> From<&GenomeCoordinate<'a>> for GenomeRegion<'a> {
+    fn from(value: &GenomeCoordinate<'a>) -> Self {
+        Self {
+            contig: value.contig.clone(),
+            start: value.pos,
+            end: value.pos,
+        }
+    }
+}
+
+/// Lets functions taking `Into<GenomeRegion>` accept borrowed regions, cloning them.
+impl<'a> From<&GenomeRegion<'a>> for GenomeRegion<'a> {
+    fn from(value: &GenomeRegion<'a>) -> Self {
+        value.clone()
+    }
+}
+
+/// Formats as `chr1:100-200`, which [`FromStr`] parses back.
+impl fmt::Display for GenomeRegion<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}-{}", self.contig, self.start, self.end)
+    }
+}
+
+/// Parses `contig:start-end` (1-based, inclusive). The contig is split at the
+/// last `:`, so names containing `:` are kept whole.
+impl FromStr for GenomeRegion<'static> {
+    type Err = LocusError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || LocusError::InvalidRegion(s.to_string());
+
+        let (contig, range) = s.rsplit_once(':').ok_or_else(invalid)?;
+        let (start, end) = range.split_once('-').ok_or_else(invalid)?;
+        let start = start.parse::<i64>().map_err(|_| invalid())?;
+        let end = end.parse::<i64>().map_err(|_| invalid())?;
+
+        if contig.is_empty() || end < start {
+            return Err(invalid());
+        }
+        Pos::from_1based(start)?;
+
+        Ok(Self {
+            contig: Chrom::from(contig.to_string()),
+            start,
+            end,
+        })
+    }
+}
+
 impl<'a> GenomeRegion<'a> {
-    pub(crate) fn as_fetch_tuple(&self) -> (&str, i64, i64) {
-        (self.contig.as_str(), self.start as i64, self.end as i64)
+    /// A region from `start` to `end`, 1-based and inclusive. `start` must be
+    /// positive and not after `end`.
+    ///
+    /// The struct literal and the `From` tuple conversions skip the checks,
+    /// for regions known to be valid.
+    pub fn new(contig: impl Into<Chrom<'a>>, start: i64, end: i64) -> Result<Self, LocusError> {
+        let contig = contig.into();
+        Pos::from_1based(start)?;
+        if end < start {
+            return Err(LocusError::InvertedRegion {
+                contig: contig.to_string(),
+                start,
+                end,
+            });
+        }
+
+        Ok(Self { contig, start, end })
+    }
+
+    pub fn into_owned(self) -> GenomeRegion<'static> {
+        GenomeRegion {
+            contig: self.contig.into_owned(),
+            start: self.start,
+            end: self.end,
+        }
+    }
+
+    pub fn to_owned_region(&self) -> GenomeRegion<'static> {
+        self.clone().into_owned()
     }
 }
 
@@ -230,9 +345,127 @@ mod tests {
         assert_eq!(pos.checked_sub(20), Err(PosError::NegativeZeroBased(-10)));
     }
 
+    #[test]
+    fn region_display_parse_round_trip() {
+        for s in ["chr1:100-200", "chrX:1-1", "chrEBV:5-10", "chrHLA-A*01:01:3-7"] {
+            let region: GenomeRegion = s.parse().unwrap();
+            assert_eq!(region.to_string(), s);
+        }
+
+        let region: GenomeRegion = "2:10-20".parse().unwrap();
+        assert_eq!(region, GenomeRegion::from((Chrom::Chr2, 10, 20)));
+        assert_eq!(region.to_string(), "chr2:10-20");
+
+        for s in ["chr1", "chr1:10", "chr1:a-20", "chr1:20-10", "chr1:0-10", ":1-2"] {
+            assert!(s.parse::<GenomeRegion>().is_err(), "{s}");
+        }
+    }
+
+    #[test]
+    fn region_hash_and_owned() {
+        use std::collections::HashSet;
+
+        let name = String::from("chrEBV");
+        let borrowed = GenomeRegion {
+            contig: Chrom::Other(name.as_str().into()),
+            start: 1,
+            end: 10,
+        };
+        let owned: GenomeRegion<'static> = borrowed.to_owned_region();
+        drop(name);
+
+        let set = [
+            owned.clone(),
+            owned.clone(),
+            GenomeRegion::from(("chr1", 1, 10)),
+            GenomeRegion::from(&GenomeCoordinate {
+                contig: Chrom::Chr1,
+                pos: 5,
+            }),
+        ]
+        .into_iter()
+        .collect::<HashSet<_>>();
+
+        assert_eq!(set.len(), 3);
+        assert!(set.contains(&owned));
+        assert!(set.contains(&GenomeRegion::from((Chrom::Chr1, 5, 5))));
+    }
+
+    #[test]
+    fn coordinate_and_region_validation() {
+        let coord = GenomeCoordinate::new("chr1", 100).unwrap();
+        assert_eq!(
+            coord,
+            GenomeCoordinate {
+                contig: Chrom::Chr1,
+                pos: 100
+            }
+        );
+        assert_eq!(
+            GenomeCoordinate::new(Chrom::Chr2, 0),
+            Err(LocusError::Pos(PosError::NonPositiveOneBased(0)))
+        );
+        assert_eq!(
+            GenomeCoordinate::new("chr1", -5),
+            Err(LocusError::Pos(PosError::NonPositiveOneBased(-5)))
+        );
+
+        assert_eq!(
+            GenomeRegion::new("chrX", 1, 1).unwrap(),
+            GenomeRegion::from((Chrom::ChrX, 1, 1))
+        );
+        assert_eq!(
+            GenomeRegion::new("chr1", -1, 10),
+            Err(LocusError::Pos(PosError::NonPositiveOneBased(-1)))
+        );
+        let err = GenomeRegion::new("chr1", 20, 10).unwrap_err();
+        assert_eq!(
+            err,
+            LocusError::InvertedRegion {
+                contig: "chr1".to_string(),
+                start: 20,
+                end: 10
+            }
+        );
+        assert_eq!(err.to_string(), "region chr1:20-10 ends before it starts");
+    }
+
+    #[test]
+    fn coordinate_and_region_debug() {
+        assert_eq!(
+            format!("{:?}", GenomeCoordinate::new("chr7", 55_191_822).unwrap()),
+            "GenomeCoordinate { contig: Chr7, pos: 55191822 }"
+        );
+        assert_eq!(
+            format!("{:?}", GenomeRegion::new("chrEBV", 5, 10).unwrap()),
+            r#"GenomeRegion { contig: Other("chrEBV"), start: 5, end: 10 }"#
+        );
+    }
+
     #[test]
     fn locus_error_wraps_pos_error() {
         let err: LocusError = Pos::from_1based(0).unwrap_err().into();
         assert!(matches!(err, LocusError::Pos(PosError::NonPositiveOneBased(0))));
     }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn coordinate_and_region_serde_round_trip() {
+        let coord = GenomeCoordinate {
+            contig: Chrom::Chr7,
+            pos: 55_191_822,
+        };
+        let region = GenomeRegion::from(("contig_α", 10, 20));
+
+        let json = serde_json::to_string(&coord).unwrap();
+        assert_eq!(json, r#"{"contig":"chr7","pos":55191822}"#);
+        assert_eq!(serde_json::from_str::<GenomeCoordinate>(&json).unwrap(), coord);
+        let bin = bincode::serialize(&coord).unwrap();
+        assert_eq!(bincode::deserialize::<GenomeCoordinate>(&bin).unwrap(), coord);
+
+        let json = serde_json::to_string(&region).unwrap();
+        assert_eq!(serde_json::from_str::<GenomeRegion>(&json).unwrap(), region);
+        let bin = bincode::serialize(&region).unwrap();
+        assert_eq!(bincode::deserialize::<GenomeRegion>(&bin).unwrap(), region);
+    }
 }
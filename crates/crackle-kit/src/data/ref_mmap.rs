@@ -0,0 +1,386 @@
+//! Memory-mapped indexed FASTA, read without copies or locks by any number of
+//! threads.
+//!
+//! Unlike [`RefGenome`], which lends a reader with its own buffers to each
+//! fetching thread, an [`MmapFasta`] is a read-only mapping of the whole file
+//! shared by all threads, e.g. as an `Arc<MmapFasta>` for the workers of a
+//! rayon pool. The pages read stay in the page cache, and are shared with other
+//! processes mapping the same reference.
+//!
+//! ```no_run
+//! use crackle_kit::data::{locus::GenomeRegion, ref_mmap::MmapFasta};
+//!
+//! let fasta = MmapFasta::from_path("hg38.fa")?;
+//! let seq = fasta.seq(&GenomeRegion::from(("chr1", 10_001, 10_100)))?;
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+//!
+//! [`RefGenome`]: crate::reference::RefGenome
+
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    ffi::c_void,
+    fs::File,
+    num::NonZeroUsize,
+    path::{Path, PathBuf},
+    ptr::NonNull,
+};
+
+use anyhow::{Context, anyhow, bail};
+use nix::sys::mman::{MapFlags, ProtFlags, mmap, munmap};
+
+use crate::{
+    data::{
+        chrom::Chrom,
+        locus::{GenomeCoordinate, GenomeRegion},
+        seq_dict::{SeqDict, SeqEntry},
+    },
+    err_opt_ext::{did_you_mean, suggest_keys},
+    errors::Error,
+    utils::textio::LineReader,
+};
+
+/// A contig of the `.fai`.
+#[derive(Debug, Clone, Copy)]
+struct FaiEntry {
+    len: u64,
+    /// Byte offset of the first base.
+    offset: u64,
+    line_bases: u64,
+    /// Bytes of a line, its line break included.
+    line_width: u64,
+}
+
+impl FaiEntry {
+    /// Byte offset of the 0-based base `pos`.
+    fn byte_offset(&self, pos: u64) -> u64 {
+        self.offset + pos / self.line_bases * self.line_width + pos % self.line_bases
+    }
+}
+
+/// A read-only private mapping of a whole file, unmapped when dropped.
+struct Mapping {
+    ptr: NonNull<c_void>,
+    len: usize,
+}
+
+// SAFETY: the mapping is only read, and lives until the `Mapping` is dropped.
+unsafe impl Send for Mapping {}
+unsafe impl Sync for Mapping {}
+
+impl Mapping {
+    fn new(file: &File, len: NonZeroUsize) -> nix::Result<Self> {
+        // SAFETY: a new mapping at an address chosen by the kernel, aliasing no
+        // memory of the process.
+        let ptr = unsafe {
+            mmap(
+                None,
+                len,
+                ProtFlags::PROT_READ,
+                MapFlags::MAP_PRIVATE,
+                file,
+                0,
+            )?
+        };
+        Ok(Self {
+            ptr,
+            len: len.get(),
+        })
+    }
+
+    fn as_slice(&self) -> &[u8] {
+        // SAFETY: `len` bytes are mapped at `ptr`, readable, for the lifetime of
+        // `self`.
+        unsafe { std::slice::from_raw_parts(self.ptr.as_ptr().cast(), self.len) }
+    }
+}
+
+impl Drop for Mapping {
+    fn drop(&mut self) {
+        // SAFETY: the slices of the mapping borrow `self`, so none is left.
+        let _ = unsafe { munmap(self.ptr, self.len) };
+    }
+}
+
+/// An uncompressed FASTA and its `.fai`, memory-mapped.
+///
+/// Contigs are looked up as in a [`SeqDict`], so `1` and `chr1` are the same
+/// contig. Bases are as in the file, soft-masked ones lowercase.
+///
+/// Sequences within a line of the FASTA are slices of the mapping; those across
+/// line breaks are copied by [`Self::seq`], and an error of [`Self::slice`].
+/// A FASTA written with a single line per contig is thus sliced without copies
+/// anywhere.
+///
+/// The file must not be changed while mapped: its pages would change under the
+/// slices, and reading past a truncated end kills the process.
+pub struct MmapFasta {
+    path: PathBuf,
+    map: Mapping,
+    /// By [`Chrom::as_str`] of the contigs.
+    entries: HashMap<String, FaiEntry>,
+    dict: SeqDict,
+}
+
+impl MmapFasta {
+    /// Map the FASTA at `path`. Its `.fai` must exist next to it, and every
+    /// contig of it must be within the file.
+    pub fn from_path(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let path = path.as_ref().to_path_buf();
+        let mut fai_path = path.clone().into_os_string();
+        fai_path.push(".fai");
+        let fai = read_fai(Path::new(&fai_path))?;
+
+        let file = File::open(&path).map_err(|e| Error::io("open", &path, e))?;
+        let file_len = file
+            .metadata()
+            .map_err(|e| Error::io("read the size of", &path, e))?
+            .len();
+        let Some(len) = NonZeroUsize::new(file_len as usize) else {
+            return Err(anyhow!("{} is empty", path.display()).into());
+        };
+        let map = Mapping::new(&file, len)
+            .with_context(|| format!("Failed to map {}", path.display()))?;
+        if map.as_slice().starts_with(&[0x1f, 0x8b]) {
+            return Err(anyhow!(
+                "{} is compressed, only uncompressed FASTAs are mapped",
+                path.display()
+            )
+            .into());
+        }
+
+        let mut entries = HashMap::new();
+        for (name, entry) in &fai {
+            if entry.len > 0 && entry.byte_offset(entry.len - 1) >= file_len {
+                return Err(anyhow!(
+                    "Contig {} of the .fai ends past the end of {}, of {} bytes",
+                    name,
+                    path.display(),
+                    file_len
+                )
+                .into());
+            }
+            let key = Chrom::from(name.as_str()).as_str().to_string();
+            entries.entry(key).or_insert(*entry);
+        }
+        let dict = SeqDict::new(fai.into_iter().map(|(name, entry)| SeqEntry {
+            contig: Chrom::from(name),
+            len: entry.len,
+            md5: None,
+        }));
+
+        Ok(Self {
+            path,
+            map,
+            entries,
+            dict,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Contigs of the `.fai`, in its order.
+    pub fn seq_dict(&self) -> &SeqDict {
+        &self.dict
+    }
+
+    pub fn contig_len(&self, contig: &Chrom<'_>) -> Option<u64> {
+        self.dict.contig_len(contig)
+    }
+
+    /// Sequence of a region, 1-based inclusive, borrowed from the mapping if
+    /// within a line of the FASTA, copied otherwise.
+    pub fn seq(&self, region: &GenomeRegion<'_>) -> Result<Cow<'_, [u8]>, Error> {
+        let (entry, start, end) = self.range(region)?;
+        if let Some(slice) = self.line_slice(&entry, start, end) {
+            return Ok(Cow::Borrowed(slice));
+        }
+
+        let mut seq = Vec::with_capacity((end - start) as usize);
+        let mut pos = start;
+        while pos < end {
+            let line_end = ((pos / entry.line_bases + 1) * entry.line_bases).min(end);
+            seq.extend_from_slice(self.line_slice(&entry, pos, line_end).unwrap());
+            pos = line_end;
+        }
+        Ok(Cow::Owned(seq))
+    }
+
+    /// Sequence of a region, 1-based inclusive, as a slice of the mapping.
+    /// Regions across a line break of the FASTA are an error, see [`Self::seq`].
+    pub fn slice(&self, region: &GenomeRegion<'_>) -> Result<&[u8], Error> {
+        let (entry, start, end) = self.range(region)?;
+        self.line_slice(&entry, start, end).ok_or_else(|| {
+            anyhow!(
+                "{} spans a line break of {}, of {} bases per line",
+                region,
+                self.path.display(),
+                entry.line_bases
+            )
+            .into()
+        })
+    }
+
+    /// Base at the 1-based position of `coord`, as in the file.
+    pub fn base_at(&self, coord: &GenomeCoordinate<'_>) -> Result<u8, Error> {
+        let (entry, start, _) = self.range(&GenomeRegion::from(coord))?;
+        Ok(self.map.as_slice()[entry.byte_offset(start) as usize])
+    }
+
+    /// The contig of `region`, and its 0-based half-open range.
+    fn range(&self, region: &GenomeRegion<'_>) -> Result<(FaiEntry, u64, u64), Error> {
+        let entry = self.entries.get(region.contig.as_str()).ok_or_else(|| {
+            let suggestions = suggest_keys(region.contig.as_str(), self.entries.keys().cloned());
+            anyhow!(
+                "Contig {} is not in the reference {}{}",
+                region.contig,
+                self.path.display(),
+                did_you_mean(&suggestions)
+            )
+        })?;
+        if region.start < 1 || region.end < region.start || region.end as u64 > entry.len {
+            return Err(anyhow!(
+                "Invalid range {} for contig of length {}",
+                region,
+                entry.len
+            )
+            .into());
+        }
+        Ok((*entry, region.start as u64 - 1, region.end as u64))
+    }
+
+    /// Bases `start..end` of a contig, `None` if they span a line break.
+    fn line_slice(&self, entry: &FaiEntry, start: u64, end: u64) -> Option<&[u8]> {
+        if start / entry.line_bases != (end - 1) / entry.line_bases {
+            return None;
+        }
+        let from = entry.byte_offset(start) as usize;
+        Some(&self.map.as_slice()[from..from + (end - start) as usize])
+    }
+}
+
+/// The contigs of a `.fai`, in its order.
+fn read_fai(path: &Path) -> Result<Vec<(String, FaiEntry)>, Error> {
+    let mut lines = LineReader::from_path(path)?;
+    let mut entries = vec![];
+    while let Some(line) = lines.next_line()? {
+        if line.is_empty() {
+            continue;
+        }
+        let entry = parse_fai_line(line).map_err(|e| lines.parse_error("fai line", e))?;
+        entries.push(entry);
+    }
+    Ok(entries)
+}
+
+fn parse_fai_line(line: &str) -> anyhow::Result<(String, FaiEntry)> {
+    let fields = line.split('\t').collect::<Vec<_>>();
+    let [name, len, offset, line_bases, line_width, ..] = fields[..] else {
+        bail!(
+            "expected `name\\tlength\\toffset\\tlinebases\\tlinewidth`, got `{}`",
+            line
+        );
+    };
+    let entry = FaiEntry {
+        len: len.parse()?,
+        offset: offset.parse()?,
+        line_bases: line_bases.parse()?,
+        line_width: line_width.parse()?,
+    };
+    if entry.line_bases == 0 || entry.line_width < entry.line_bases {
+        bail!(
+            "invalid line lengths of {}: {} bases in {} bytes",
+            name,
+            entry.line_bases,
+            entry.line_width
+        );
+    }
+    Ok((name.to_string(), entry))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, sync::Arc, thread};
+
+    use super::*;
+    use crate::test_utils::write_test_fasta_with_width;
+
+    #[test]
+    fn test_mmap_fasta() -> Result<(), Box<dyn std::error::Error>> {
+        let chr1 = b"ACGTacgtNNGGCCAATT".repeat(20);
+        let chr_ebv = b"GGGCCCAT".to_vec();
+        let contigs = [("1", chr1.as_slice()), ("chrEBV", chr_ebv.as_slice())];
+
+        for width in [60, 7, 1_000] {
+            let dir = tempfile::tempdir()?;
+            let path = write_test_fasta_with_width(dir.path(), &contigs, width)?;
+            let fasta = Arc::new(MmapFasta::from_path(&path)?);
+            assert_eq!(fasta.contig_len(&Chrom::Chr1), Some(360));
+            assert_eq!(fasta.seq_dict().len(), 2);
+
+            for (start, end) in [(1, 1), (3, 8), (55, 70), (1, 360), (358, 360)] {
+                let region = GenomeRegion::from(("chr1", start, end));
+                let expected = &chr1[start as usize - 1..end as usize];
+                assert_eq!(fasta.seq(&region)?.as_ref(), expected);
+
+                let in_line = (start - 1) as usize / width == (end - 1) as usize / width;
+                assert!(matches!(fasta.seq(&region)?, Cow::Borrowed(_)) == in_line);
+                match fasta.slice(&region) {
+                    Ok(slice) => assert_eq!(slice, expected),
+                    Err(err) => {
+                        assert!(!in_line);
+                        assert!(err.to_string().contains("spans a line break"), "{}", err);
+                    }
+                }
+            }
+            assert_eq!(
+                fasta.seq(&GenomeRegion::from(("chrEBV", 2, 8)))?.as_ref(),
+                b"GGCCCAT"
+            );
+
+            let coord = |contig: &str, pos| GenomeCoordinate::new(contig.to_string(), pos).unwrap();
+            assert_eq!(fasta.base_at(&coord("chr1", 5))?, b'a');
+            assert_eq!(fasta.base_at(&coord("chrEBV", 8))?, b'T');
+            assert!(fasta.base_at(&coord("chr1", 361)).is_err());
+            let err = fasta.base_at(&coord("chr2", 1)).unwrap_err();
+            assert!(err.to_string().ends_with("Did you mean chr1?"), "{}", err);
+            assert!(fasta.seq(&GenomeRegion::from(("chr1", 0, 5))).is_err());
+
+            // a single mapping shared by the threads.
+            thread::scope(|s| {
+                for i in 0..4 {
+                    let fasta = Arc::clone(&fasta);
+                    let chr1 = &chr1;
+                    s.spawn(move || {
+                        let region = GenomeRegion::from(("chr1", 1 + i * 50, 100 + i * 50));
+                        let seq = fasta.seq(&region).unwrap();
+                        assert_eq!(seq.as_ref(), &chr1[i as usize * 50..100 + i as usize * 50]);
+                    });
+                }
+            });
+
+            #[cfg(feature = "bio")]
+            {
+                let reference = crate::reference::RefGenome::from_path(&path)?;
+                for region in [("chr1", 3, 8), ("chr1", 40, 300), ("chrEBV", 1, 8)] {
+                    let region = GenomeRegion::from(region);
+                    let mut seq = fasta.seq(&region)?.into_owned();
+                    seq.make_ascii_uppercase();
+                    assert_eq!(seq, reference.fetch_seq(&region)?);
+                }
+            }
+        }
+
+        let dir = tempfile::tempdir()?;
+        let path = write_test_fasta_with_width(dir.path(), &contigs, 60)?;
+        fs::write(&path, ">1\nACGT\n")?;
+        let err = MmapFasta::from_path(&path).err().unwrap();
+        assert!(err.to_string().contains("ends past the end"), "{}", err);
+        assert!(MmapFasta::from_path(dir.path().join("missing.fa")).is_err());
+
+        Ok(())
+    }
+}
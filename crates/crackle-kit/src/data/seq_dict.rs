@@ -0,0 +1,595 @@
+//! Sequence dictionaries, the contigs of a reference, bam or variant list with
+//! their lengths, to catch inputs made against different references before
+//! they give wrong results.
+//!
+//! ```no_run
+//! use crackle_kit::data::seq_dict::SeqDict;
+//!
+//! let reference = SeqDict::from_fai("hg38.fa.fai")?;
+//! let dict = SeqDict::from_dict("hg38.dict")?;
+//! if let Err(mismatch) = reference.compatible_with(&dict) {
+//!     eprintln!("{}", mismatch);
+//! }
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+
+use std::{collections::HashMap, path::Path};
+
+#[cfg(feature = "bam")]
+use anyhow::Context;
+use anyhow::{anyhow, bail};
+#[cfg(feature = "bam")]
+use rust_htslib::bam::HeaderView;
+#[cfg(feature = "tracing")]
+use tracing::{Level, event};
+
+#[cfg(feature = "bam")]
+use crate::bam::process::{BamLocusWorkInput, CoordinateSystem};
+use crate::{
+    data::{chrom::Chrom, interval::RegionSet, locus::GenomeRegion},
+    errors::{DictMismatch, Error},
+    utils::textio::LineReader,
+};
+
+/// A contig of a [`SeqDict`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SeqEntry {
+    pub contig: Chrom<'static>,
+    pub len: u64,
+    /// MD5 of the sequence, the `M5` of `@SQ` lines, lowercase.
+    pub md5: Option<String>,
+}
+
+/// What a processor does when the sequence dictionaries of its inputs differ,
+/// see [`SeqDict::check`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DictCheck {
+    /// Log every mismatch as a warning.
+    Warn,
+    /// Fail on incompatible dictionaries and on inputs out of their contig;
+    /// only log contigs in another order as a warning.
+    #[default]
+    Strict,
+}
+
+/// Contigs in order, looked up by their usual names as [`Chrom`]s are, so that
+/// `chr1` and `1` are the same contig.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SeqDict {
+    entries: Vec<SeqEntry>,
+    /// Index of each contig in `entries`, by [`Chrom::as_str`].
+    index: HashMap<String, usize>,
+}
+
+impl SeqDict {
+    /// A dictionary of `entries` in their order. Of repeated contigs, the first
+    /// is kept.
+    pub fn new(entries: impl IntoIterator<Item = SeqEntry>) -> Self {
+        let mut dict = Self::default();
+        for entry in entries {
+            if !dict.index.contains_key(entry.contig.as_str()) {
+                dict.index
+                    .insert(entry.contig.as_str().to_string(), dict.entries.len());
+                dict.entries.push(entry);
+            }
+        }
+        dict
+    }
+
+    /// The `@SQ` lines of a bam header.
+    #[cfg(feature = "bam")]
+    pub fn from_header(header: &HeaderView) -> Result<Self, Error> {
+        let text = String::from_utf8_lossy(header.as_bytes());
+        let entries = text
+            .lines()
+            .enumerate()
+            .filter_map(|(i, line)| {
+                parse_sq_line(line)
+                    .with_context(|| format!("Invalid @SQ line {} of the bam header", i + 1))
+                    .transpose()
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        Ok(Self::new(entries))
+    }
+
+    /// The contigs of the `.fai` index of a FASTA, `name`, `length`, then the
+    /// offsets of each contig, tab-separated.
+    pub fn from_fai(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let mut lines = LineReader::from_path(path)?;
+        let mut entries = vec![];
+        while let Some(line) = lines.next_line()? {
+            if let Some(entry) =
+                parse_fai_line(line).map_err(|e| lines.parse_error("fai line", e))?
+            {
+                entries.push(entry);
+            }
+        }
+
+        Ok(Self::new(entries))
+    }
+
+    /// The `@SQ` lines of a Picard `.dict`, as of a SAM header. Other lines are
+    /// skipped.
+    pub fn from_dict(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let mut lines = LineReader::from_path(path)?;
+        let mut entries = vec![];
+        while let Some(line) = lines.next_line()? {
+            if let Some(entry) =
+                parse_sq_line(line).map_err(|e| lines.parse_error("@SQ line", e))?
+            {
+                entries.push(entry);
+            }
+        }
+
+        Ok(Self::new(entries))
+    }
+
+    pub fn entries(&self) -> &[SeqEntry] {
+        &self.entries
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn get(&self, contig: &Chrom<'_>) -> Option<&SeqEntry> {
+        self.index.get(contig.as_str()).map(|&i| &self.entries[i])
+    }
+
+    pub fn contig_len(&self, contig: &Chrom<'_>) -> Option<u64> {
+        self.get(contig).map(|entry| entry.len)
+    }
+
+    /// Whether `other` has the same contigs as `self`, of the same lengths and
+    /// MD5s where both have one, in the same order.
+    ///
+    /// Contigs missing from either, or of other lengths or MD5s, are a
+    /// [`DictMismatch::Incompatible`]. Otherwise, contigs in another order are a
+    /// [`DictMismatch::OrderDiffers`], which is only a warning: coordinates
+    /// agree, but files sorted by one do not merge with files sorted by the
+    /// other.
+    pub fn compatible_with(&self, other: &SeqDict) -> Result<(), DictMismatch> {
+        let mut missing = vec![];
+        let mut lengths = vec![];
+        let mut md5s = vec![];
+        for entry in &self.entries {
+            let Some(other_entry) = other.get(&entry.contig) else {
+                missing.push(entry.contig.to_string());
+                continue;
+            };
+            if entry.len != other_entry.len {
+                lengths.push((entry.contig.to_string(), entry.len, other_entry.len));
+            }
+            if let (Some(md5), Some(other_md5)) = (&entry.md5, &other_entry.md5)
+                && md5 != other_md5
+            {
+                md5s.push(entry.contig.to_string());
+            }
+        }
+        let extra = other
+            .entries
+            .iter()
+            .filter(|entry| self.get(&entry.contig).is_none())
+            .map(|entry| entry.contig.to_string())
+            .collect::<Vec<_>>();
+
+        if !(missing.is_empty() && extra.is_empty() && lengths.is_empty() && md5s.is_empty()) {
+            return Err(DictMismatch::Incompatible {
+                missing,
+                extra,
+                lengths,
+                md5s,
+            });
+        }
+
+        // the first contig of `other` out of the order of `self`.
+        let positions = other
+            .entries
+            .iter()
+            .map(|entry| (entry, self.index[entry.contig.as_str()]));
+        let mut last: Option<(&SeqEntry, usize)> = None;
+        for (entry, pos) in positions {
+            if let Some((prev, prev_pos)) = last
+                && pos < prev_pos
+            {
+                return Err(DictMismatch::OrderDiffers {
+                    contig: entry.contig.to_string(),
+                    after: prev.contig.to_string(),
+                });
+            }
+            last = Some((entry, pos));
+        }
+
+        Ok(())
+    }
+
+    /// [`Self::compatible_with`], failing or logging the mismatch as set by
+    /// `check`. `what` names `other` in the error, e.g. `the bam header`.
+    pub fn check(&self, other: &SeqDict, what: &str, check: DictCheck) -> Result<(), Error> {
+        match self.compatible_with(other) {
+            Ok(()) => Ok(()),
+            Err(mismatch) if mismatch.is_warning() || check == DictCheck::Warn => {
+                #[cfg(feature = "tracing")]
+                event!(Level::WARN, "{} does not match: {}", what, mismatch);
+                Ok(())
+            }
+            Err(mismatch) => Err(anyhow::Error::new(mismatch)
+                .context(format!("{} does not match", what))
+                .into()),
+        }
+    }
+
+    /// Check that the locus of every input is within its contig, its position
+    /// read as set by `coordinate_system`.
+    ///
+    /// The first input on a contig missing from the dictionary, or out of it,
+    /// is an error.
+    #[cfg(feature = "bam")]
+    pub fn validate_inputs<'a, 'b, I: BamLocusWorkInput<'a> + 'b>(
+        &self,
+        inputs: impl IntoIterator<Item = &'b I>,
+        coordinate_system: CoordinateSystem,
+    ) -> Result<(), Error> {
+        for (i, input) in inputs.into_iter().enumerate() {
+            let coord = input.genome_coordinate();
+            let pos = coordinate_system.to_0based(coord.pos) + 1;
+            let Some(len) = self.contig_len(&coord.contig) else {
+                return Err(anyhow!(
+                    "Input {} at {}:{} is on a contig not in the dictionary of {} contigs",
+                    i,
+                    coord.contig,
+                    coord.pos,
+                    self.len()
+                )
+                .into());
+            };
+            if pos < 1 || pos as u64 > len {
+                return Err(anyhow!(
+                    "Input {} at {}:{} is out of its contig, of {} bp",
+                    i,
+                    coord.contig,
+                    coord.pos,
+                    len
+                )
+                .into());
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Windows of `window` bp tiling the contigs of `dict`, in its order, each
+/// starting `window - overlap` bp after the previous one on its contig. The
+/// last window of a contig is clamped to its end, and no window starts after
+/// one reaching the end.
+///
+/// The windows are made as iterated, for scans of a whole genome, e.g. batched
+/// with [`batch_region`] or processed with [`par_map_regions`].
+///
+/// # Panics
+///
+/// If `window` is 0 or `overlap` is not less than `window`.
+///
+/// [`batch_region`]: crate::utils::batch_region::batch_region
+/// [`par_map_regions`]: crate::bam::fan_out::par_map_regions
+pub fn genome_windows(
+    dict: &SeqDict,
+    window: usize,
+    overlap: usize,
+) -> impl Iterator<Item = GenomeRegion<'static>> + Clone {
+    assert!(
+        overlap < window,
+        "windows of {} bp must overlap by less, got {}",
+        window,
+        overlap
+    );
+    let (window, step) = (window as u64, (window - overlap) as u64);
+
+    dict.entries.iter().flat_map(move |entry| {
+        let n_windows = match entry.len {
+            0 => 0,
+            len if len <= window => 1,
+            len => 1 + (len - window).div_ceil(step),
+        };
+        (0..n_windows).map(move |i| {
+            let start = 1 + i * step;
+            GenomeRegion {
+                contig: entry.contig.clone(),
+                start: start as i64,
+                end: (start + window - 1).min(entry.len) as i64,
+            }
+        })
+    })
+}
+
+/// [`genome_windows`] out of `exclude`: windows it covers are skipped, and the
+/// bases of it at either end of a window trimmed off, e.g. for scans skipping
+/// the gaps of a reference. Excluded bases within a window are kept, so that
+/// every window gives at most one region.
+pub fn genome_windows_filtered<'a>(
+    dict: &'a SeqDict,
+    window: usize,
+    overlap: usize,
+    exclude: &'a RegionSet,
+) -> impl Iterator<Item = GenomeRegion<'static>> + Clone + 'a {
+    genome_windows(dict, window, overlap).filter_map(move |region| {
+        let (start, end) = exclude.trim_ends(&region)?;
+        Some(GenomeRegion {
+            start,
+            end,
+            ..region
+        })
+    })
+}
+
+/// The contig of a `.fai` line, `None` for an empty line.
+fn parse_fai_line(line: &str) -> anyhow::Result<Option<SeqEntry>> {
+    if line.is_empty() {
+        return Ok(None);
+    }
+    let mut fields = line.split('\t');
+    let (Some(name), Some(len)) = (fields.next(), fields.next()) else {
+        bail!("expected `name\\tlength\\t...`, got `{}`", line);
+    };
+
+    Ok(Some(SeqEntry {
+        contig: Chrom::from(name.to_string()),
+        len: parse_len(len)?,
+        md5: None,
+    }))
+}
+
+/// The contig of an `@SQ` line, `None` for other lines.
+fn parse_sq_line(line: &str) -> anyhow::Result<Option<SeqEntry>> {
+    let mut fields = line.split('\t');
+    if fields.next() != Some("@SQ") {
+        return Ok(None);
+    }
+
+    let (mut name, mut len, mut md5) = (None, None, None);
+    for field in fields {
+        match field.split_once(':') {
+            Some(("SN", value)) => name = Some(value),
+            Some(("LN", value)) => len = Some(parse_len(value)?),
+            Some(("M5", value)) => md5 = Some(value.to_ascii_lowercase()),
+            _ => {}
+        }
+    }
+
+    match (name, len) {
+        (Some(name), Some(len)) => Ok(Some(SeqEntry {
+            contig: Chrom::from(name.to_string()),
+            len,
+            md5,
+        })),
+        _ => bail!("expected SN and LN in `{}`", line),
+    }
+}
+
+fn parse_len(s: &str) -> anyhow::Result<u64> {
+    s.parse::<u64>()
+        .map_err(|_| anyhow!("invalid contig length `{}`", s))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    fn entry(contig: &str, len: u64) -> SeqEntry {
+        SeqEntry {
+            contig: Chrom::from(contig.to_string()),
+            len,
+            md5: None,
+        }
+    }
+
+    fn dict(entries: &[(&str, u64)]) -> SeqDict {
+        SeqDict::new(entries.iter().map(|&(contig, len)| entry(contig, len)))
+    }
+
+    #[test]
+    fn test_from_fai_and_dict() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = tempfile::tempdir()?;
+        let fai_path = dir.path().join("ref.fa.fai");
+        fs::write(
+            &fai_path,
+            "chr1\t1000\t6\t60\t61\nchr2\t500\t1030\t60\t61\n",
+        )?;
+        let dict_path = dir.path().join("ref.dict");
+        fs::write(
+            &dict_path,
+            "@HD\tVN:1.0\tSO:unsorted\n\
+             @SQ\tSN:1\tLN:1000\tM5:0123ABCD\tUR:file:/ref.fa\n\
+             @SQ\tSN:2\tLN:500\n",
+        )?;
+
+        let fai = SeqDict::from_fai(&fai_path)?;
+        let dict = SeqDict::from_dict(&dict_path)?;
+        assert_eq!(fai.entries(), [entry("chr1", 1000), entry("chr2", 500)]);
+        assert_eq!(
+            dict.get(&Chrom::Chr1).unwrap().md5.as_deref(),
+            Some("0123abcd")
+        );
+        assert_eq!(dict.contig_len(&Chrom::Chr2), Some(500));
+        // `1` and `chr1` are the same contig.
+        assert_eq!(fai.compatible_with(&dict), Ok(()));
+
+        fs::write(&dict_path, "@SQ\tSN:chr1\tLN:1k\n")?;
+        let err = SeqDict::from_dict(&dict_path).unwrap_err();
+        assert!(matches!(err, Error::Parse { line: 1, .. }), "{:?}", err);
+        fs::write(&fai_path, "chr1\n")?;
+        assert!(SeqDict::from_fai(&fai_path).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_compatible_with() {
+        let reference = dict(&[("chr1", 1000), ("chr2", 500), ("chrM", 16)]);
+
+        assert_eq!(reference.compatible_with(&reference), Ok(()));
+        assert_eq!(
+            reference.compatible_with(&dict(&[("chr1", 1000), ("chr2", 600), ("chrEBV", 10)])),
+            Err(DictMismatch::Incompatible {
+                missing: vec!["chrM".to_string()],
+                extra: vec!["chrEBV".to_string()],
+                lengths: vec![("chr2".to_string(), 500, 600)],
+                md5s: vec![],
+            })
+        );
+
+        let other_order = dict(&[("chrM", 16), ("chr1", 1000), ("chr2", 500)]);
+        let mismatch = reference.compatible_with(&other_order).unwrap_err();
+        assert_eq!(
+            mismatch,
+            DictMismatch::OrderDiffers {
+                contig: "chr1".to_string(),
+                after: "chrM".to_string(),
+            }
+        );
+        assert!(mismatch.is_warning());
+        assert!(
+            reference
+                .check(&other_order, "the bam", DictCheck::Strict)
+                .is_ok()
+        );
+
+        let mut other_md5 = reference.clone();
+        other_md5.entries[0].md5 = Some("a".to_string());
+        // compared only if both have one.
+        assert_eq!(reference.compatible_with(&other_md5), Ok(()));
+        let mut md5 = reference.clone();
+        md5.entries[0].md5 = Some("b".to_string());
+        let mismatch = md5.compatible_with(&other_md5).unwrap_err();
+        assert!(!mismatch.is_warning());
+
+        let err = md5
+            .check(&other_md5, "the bam", DictCheck::Strict)
+            .unwrap_err();
+        assert_eq!(
+            format!("{:#}", err),
+            "the bam does not match: sequence dictionaries differ: MD5 of chr1"
+        );
+        assert!(md5.check(&other_md5, "the bam", DictCheck::Warn).is_ok());
+    }
+
+    #[test]
+    fn test_genome_windows() {
+        let dict = dict(&[("chr1", 1_000), ("chr2", 250), ("chrM", 0), ("chr3", 100)]);
+        let spans = |regions: Vec<GenomeRegion<'_>>| {
+            regions
+                .iter()
+                .map(|r| (r.contig.to_string(), r.start, r.end))
+                .collect::<Vec<_>>()
+        };
+        let span = |contig: &str, start, end| (contig.to_string(), start, end);
+
+        let windows = genome_windows(&dict, 300, 0).collect::<Vec<_>>();
+        let n_bases = windows.iter().map(|r| r.end - r.start + 1).sum::<i64>();
+        assert_eq!(n_bases, 1_000 + 250 + 100);
+        assert_eq!(
+            spans(windows),
+            [
+                span("chr1", 1, 300),
+                span("chr1", 301, 600),
+                span("chr1", 601, 900),
+                span("chr1", 901, 1_000),
+                span("chr2", 1, 250),
+                span("chr3", 1, 100),
+            ]
+        );
+
+        // none after the window reaching the end.
+        let windows = genome_windows(&dict, 400, 100);
+        assert_eq!(
+            spans(windows.clone().take(4).collect()),
+            [
+                span("chr1", 1, 400),
+                span("chr1", 301, 700),
+                span("chr1", 601, 1_000),
+                span("chr2", 1, 250),
+            ]
+        );
+        assert_eq!(windows.count(), 5);
+        assert_eq!(
+            genome_windows(&dict, 100, 0)
+                .filter(|r| r.contig == Chrom::Chr3)
+                .count(),
+            1
+        );
+
+        let exclude = RegionSet::new(
+            [
+                GenomeRegion::from(("chr1", 250, 650)),
+                GenomeRegion::from(("chr1", 950, 960)),
+                GenomeRegion::from(("2", 1, 10)),
+                GenomeRegion::from(("chr2", 240, 300)),
+                GenomeRegion::from(("chr3", 1, 100)),
+            ],
+            false,
+        );
+        assert_eq!(
+            spans(genome_windows_filtered(&dict, 300, 0, &exclude).collect()),
+            [
+                span("chr1", 1, 249),
+                // 601-900 less 601-650.
+                span("chr1", 651, 900),
+                // 950-960 is within the window.
+                span("chr1", 901, 1_000),
+                span("chr2", 11, 239),
+            ]
+        );
+    }
+
+    #[cfg(feature = "bam")]
+    #[test]
+    fn test_from_header_and_validate_inputs() -> Result<(), Box<dyn std::error::Error>> {
+        use crate::{data::locus::GenomeCoordinate, test_utils::TestBam};
+        use rust_htslib::bam::{IndexedReader, Read as _};
+
+        let dir = tempfile::tempdir()?;
+        let bam_path = TestBam::new()
+            .contig("chr1", 1_000)
+            .contig("chr2", 500)
+            .build(dir.path())?;
+        let reader = IndexedReader::from_path(&bam_path)?;
+        let dict = SeqDict::from_header(reader.header())?;
+        assert_eq!(dict.entries(), [entry("chr1", 1000), entry("chr2", 500)]);
+
+        let coord = |contig: &str, pos| GenomeCoordinate::new(contig.to_string(), pos).unwrap();
+        let one_based = CoordinateSystem::OneBased;
+        dict.validate_inputs(&[coord("chr1", 1), coord("2", 500)], one_based)?;
+        let err = dict
+            .validate_inputs(&[coord("chr1", 1), coord("chr2", 501)], one_based)
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Input 1 at chr2:501 is out of its contig, of 500 bp"
+        );
+        // 500 is past the end once 0-based.
+        let err = dict
+            .validate_inputs(&[coord("chr2", 500)], CoordinateSystem::ZeroBased)
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Input 0 at chr2:500 is out of its contig, of 500 bp"
+        );
+        let err = dict
+            .validate_inputs(&[coord("chr3", 1)], one_based)
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Input 0 at chr3:1 is on a contig not in the dictionary of 2 contigs"
+        );
+
+        Ok(())
+    }
+}
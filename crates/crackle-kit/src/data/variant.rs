@@ -1,20 +1,227 @@
-use std::str::FromStr;
+use std::{borrow::Cow, cmp::Ordering, collections::HashSet, fmt, path::Path};
 
-use anyhow::{Context, Error, anyhow};
+use anyhow::{Error, anyhow};
 
-use crate::data::{chrom::Chrom, locus::GenomeRegion};
+use crate::{
+    data::{
+        bases::BaseArrShort,
+        chrom::{Chrom, ContigInterner},
+        locus::{GenomeCoordinate, GenomeRegion},
+    },
+    utils::textio::LineReader,
+};
 
-#[derive(Debug, PartialEq, Eq, Clone)]
+/// A variant at a 1-based position.
+///
+/// Alleles of up to 25 bases of `A`, `C`, `G`, `T` or `N`, as of SNVs and most
+/// indels, are packed in place, so a variant on a standard chromosome has no
+/// heap allocation. Others are kept as strings. A contig name can be borrowed,
+/// e.g. from a [`ContigInterner`], instead of being copied into each variant.
+///
+/// [`StringVariant`] is the plain form, with the alleles as strings.
+///
+/// Variants are ordered by contig, in the order of [`Chrom`], position, ref
+/// then alt bases. Equal variants are equal as written: see [`Self::min_rep`]
+/// to compare indels written with different padding.
+#[derive(PartialEq, Eq, Clone, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(
+    feature = "serde",
+    serde(from = "StringVariant<'a>", into = "StringVariant<'a>")
+)]
 pub struct Variant<'a> {
-    chrom: Chrom<'a>,
-    pos: i64,
-    ref_b: String,
-    alt_b: String,
+    /// 1-based position.
+    coord: GenomeCoordinate<'a>,
+    alleles: Alleles,
+}
+
+/// Ref and alt bases of a [`Variant`], packed if both can be.
+///
+/// Packed whenever possible, so that equal alleles have equal representations.
+#[derive(Debug, PartialEq, Eq, Clone, Hash)]
+enum Alleles {
+    Packed {
+        ref_b: BaseArrShort,
+        alt_b: BaseArrShort,
+    },
+    Long(Box<(String, String)>),
+}
+
+impl Alleles {
+    fn new(ref_b: &str, alt_b: &str) -> Self {
+        match (pack(ref_b), pack(alt_b)) {
+            (Some(ref_b), Some(alt_b)) => Self::Packed { ref_b, alt_b },
+            _ => Self::Long(Box::new((ref_b.to_string(), alt_b.to_string()))),
+        }
+    }
+
+    fn from_strings(ref_b: String, alt_b: String) -> Self {
+        match (pack(&ref_b), pack(&alt_b)) {
+            (Some(ref_b), Some(alt_b)) => Self::Packed { ref_b, alt_b },
+            _ => Self::Long(Box::new((ref_b, alt_b))),
+        }
+    }
+}
+
+/// `bases` packed, if it fits and has only bases a [`BaseArrShort`] can hold.
+fn pack(bases: &str) -> Option<BaseArrShort> {
+    // checked first, as a failed `from_bytes` allocates its error.
+    let packable = bases.len() <= BaseArrShort::capacity()
+        && bases
+            .bytes()
+            .all(|b| matches!(b, b'A' | b'C' | b'G' | b'T' | b'N'));
+    packable.then(|| BaseArrShort::from_bytes(bases.as_bytes()).unwrap())
 }
 
 impl<'a> Variant<'a> {
-    fn from_str_key(s: &'a str) -> Result<Self, Error> {
-        fn parse_internal(s: &str) -> Result<Variant, Error> {
+    /// `pos` is 1-based.
+    pub fn new(chrom: Chrom<'a>, pos: i64, ref_b: impl AsRef<str>, alt_b: impl AsRef<str>) -> Self {
+        Self {
+            coord: GenomeCoordinate { contig: chrom, pos },
+            alleles: Alleles::new(ref_b.as_ref(), alt_b.as_ref()),
+        }
+    }
+
+    pub fn chrom(&self) -> &Chrom<'a> {
+        &self.coord.contig
+    }
+
+    /// 1-based position.
+    pub fn pos(&self) -> i64 {
+        self.coord.pos
+    }
+
+    pub fn coordinate(&self) -> &GenomeCoordinate<'a> {
+        &self.coord
+    }
+
+    pub fn ref_bases(&self) -> BaseSlice<'_> {
+        match &self.alleles {
+            Alleles::Packed { ref_b, .. } => BaseSlice::Packed(ref_b),
+            Alleles::Long(long) => BaseSlice::Str(&long.0),
+        }
+    }
+
+    pub fn alt_bases(&self) -> BaseSlice<'_> {
+        match &self.alleles {
+            Alleles::Packed { alt_b, .. } => BaseSlice::Packed(alt_b),
+            Alleles::Long(long) => BaseSlice::Str(&long.1),
+        }
+    }
+
+    /// Bytes taken by the variant, heap allocations included. A borrowed
+    /// contig name is not counted, as it is shared.
+    pub fn memory_footprint(&self) -> usize {
+        let contig = match &self.coord.contig {
+            Chrom::Other(Cow::Owned(name)) => name.capacity(),
+            _ => 0,
+        };
+        let alleles = match &self.alleles {
+            Alleles::Packed { .. } => 0,
+            Alleles::Long(long) => {
+                size_of::<(String, String)>() + long.0.capacity() + long.1.capacity()
+            }
+        };
+        size_of::<Self>() + contig + alleles
+    }
+
+    /// Contig and position, e.g. to group the alleles of a multi-allelic site.
+    pub fn site_key(&self) -> GenomeCoordinate<'a> {
+        self.coord.clone()
+    }
+
+    /// The variant in minimal representation: the bases shared by the ends of
+    /// both alleles trimmed, at the right then at the left, moving the position
+    /// past those trimmed at the left.
+    ///
+    /// Indels lose their padding base too, leaving an empty allele, so that
+    /// the same indel written by different callers has the same key, e.g.
+    /// `chr1:100 CTT>CT` and `chr1:101 TT>T` are both `chr1:101 T>`. Trimming
+    /// at the right first keeps an indel of a repeat at its leftmost written
+    /// position. Variants with a symbolic allele, e.g. `<DEL>` or `*`, are
+    /// kept as is.
+    pub fn min_rep(&self) -> Variant<'a> {
+        self.ref_bases().with_str(|ref_b| {
+            self.alt_bases().with_str(|alt_b| {
+                let is_bases = |s: &str| s.bytes().all(|b| b.is_ascii_alphabetic());
+                if !(is_bases(ref_b) && is_bases(alt_b)) {
+                    return self.clone();
+                }
+
+                let (mut ref_b, mut alt_b) = (ref_b.as_bytes(), alt_b.as_bytes());
+                while let (Some((r, ref_rest)), Some((a, alt_rest))) =
+                    (ref_b.split_last(), alt_b.split_last())
+                    && r == a
+                {
+                    (ref_b, alt_b) = (ref_rest, alt_rest);
+                }
+                let mut pos = self.coord.pos;
+                while let (Some((r, ref_rest)), Some((a, alt_rest))) =
+                    (ref_b.split_first(), alt_b.split_first())
+                    && r == a
+                {
+                    (ref_b, alt_b) = (ref_rest, alt_rest);
+                    pos += 1;
+                }
+
+                // the trimmed ends are ASCII, so the rest is still UTF-8.
+                Variant::new(
+                    self.coord.contig.clone(),
+                    pos,
+                    std::str::from_utf8(ref_b).unwrap(),
+                    std::str::from_utf8(alt_b).unwrap(),
+                )
+            })
+        })
+    }
+
+    /// A copy detached from the borrowed contig name, if any, e.g. to keep as
+    /// the key of a long-lived map.
+    pub fn to_static(&self) -> Variant<'static> {
+        self.clone().into_owned()
+    }
+
+    /// [`Self::min_rep`] detached from the borrowed contig name, if any: the
+    /// key under which equivalent variants are stored, see [`VariantSet`].
+    pub fn to_static_min_rep(&self) -> Variant<'static> {
+        self.min_rep().into_owned()
+    }
+
+    /// Detach from the borrowed contig name, if any.
+    pub fn into_owned(self) -> Variant<'static> {
+        Variant {
+            coord: GenomeCoordinate {
+                contig: self.coord.contig.into_owned(),
+                pos: self.coord.pos,
+            },
+            alleles: self.alleles,
+        }
+    }
+
+    /// The variant with its contig name borrowed from `interner`, if stored
+    /// there.
+    pub fn intern(self, interner: &ContigInterner) -> Variant<'_> {
+        let contig = match self.coord.contig {
+            Chrom::Other(name) => match interner.get(&name) {
+                Some(stored) => Chrom::Other(Cow::Borrowed(stored)),
+                None => Chrom::Other(Cow::Owned(name.into_owned())),
+            },
+            standard => standard.into_owned(),
+        };
+        Variant {
+            coord: GenomeCoordinate {
+                contig,
+                pos: self.coord.pos,
+            },
+            alleles: self.alleles,
+        }
+    }
+
+    /// Parse a `{chrom}_{pos}_{ref}_{alt}` key, e.g. `chrX_12341_AA_GG`.
+    ///
+    /// A non-standard contig name is borrowed from `s` if it starts with `chr`.
+    pub fn from_str_key(s: &'a str) -> Result<Self, Error> {
+        fn parse_internal(s: &str) -> Result<Variant<'_>, Error> {
             let mut elem_iter = s.split("_");
 
             macro_rules! parse_next {
@@ -26,21 +233,16 @@ impl<'a> Variant<'a> {
             }
 
             // first elem: chrom
-            let chrom = Chrom::from_str(parse_next!()?)?;
+            let chrom = Chrom::from(Cow::Borrowed(parse_next!()?));
             let pos = parse_next!()?.parse::<i64>()?;
-            let ref_b = parse_next!()?.to_string();
-            let alt_b = parse_next!()?.to_string();
+            let ref_b = parse_next!()?;
+            let alt_b = parse_next!()?;
 
-            if parse_next!().is_ok() {
+            if elem_iter.next().is_some() {
                 Err(anyhow!("Invalid variant key, it has 5-th element: {}", s))?
             }
 
-            Ok(Variant {
-                chrom,
-                pos,
-                ref_b,
-                alt_b,
-            })
+            Ok(Variant::new(chrom, pos, ref_b, alt_b))
         }
 
         match parse_internal(s) {
@@ -49,20 +251,370 @@ impl<'a> Variant<'a> {
         }
     }
 
+    /// Load the variant keys of `path`, one [`Self::from_str_key`] key per line,
+    /// in the order of the file. Gzipped files are read as such, see
+    /// [`open_text`](crate::utils::textio::open_text).
+    ///
+    /// A malformed key is an [`Error::Parse`](crate::Error::Parse) of its line.
+    pub fn load_keys_from_path(
+        path: impl AsRef<Path>,
+    ) -> Result<Vec<Variant<'static>>, crate::Error> {
+        Self::load_keys_from_path_with(path, &LoadKeysOptions::new())
+    }
+
+    /// [`Self::load_keys_from_path`], deduplicated or sorted as set by `opts`.
+    pub fn load_keys_from_path_with(
+        path: impl AsRef<Path>,
+        opts: &LoadKeysOptions,
+    ) -> Result<Vec<Variant<'static>>, crate::Error> {
+        load_keys(path.as_ref(), opts, |variant| variant.into_owned())
+    }
+
+    /// [`Self::load_keys_from_path_with`], with the names of non-standard
+    /// contigs borrowed from `interner` instead of copied into each variant, if
+    /// stored there.
+    pub fn load_keys_from_path_interned<'b>(
+        path: impl AsRef<Path>,
+        opts: &LoadKeysOptions,
+        interner: &'b ContigInterner,
+    ) -> Result<Vec<Variant<'b>>, crate::Error> {
+        load_keys(path.as_ref(), opts, |variant| variant.intern(interner))
+    }
+
+    /// Iterate over the variant keys of `path` without loading them all, e.g.
+    /// for very large files. See [`Self::load_keys_from_path`].
+    pub fn read_keys_from_path(path: impl AsRef<Path>) -> Result<VariantKeyReader, crate::Error> {
+        Ok(VariantKeyReader {
+            lines: LineReader::from_path(path)?,
+        })
+    }
+
     fn get_1bp_region(&self) -> GenomeRegion<'_> {
         GenomeRegion {
-            contig: self.chrom.clone(),
-            start: self.pos,
-            end: self.pos + 1,
+            contig: self.coord.contig.clone(),
+            start: self.coord.pos,
+            end: self.coord.pos + 1,
+        }
+    }
+}
+
+fn load_keys<'b>(
+    path: &Path,
+    opts: &LoadKeysOptions,
+    mut detach: impl FnMut(Variant<'_>) -> Variant<'b>,
+) -> Result<Vec<Variant<'b>>, crate::Error> {
+    let mut reader = Variant::read_keys_from_path(path)?;
+
+    let mut variants = vec![];
+    while let Some(variant) = reader.next_with(&mut detach) {
+        if opts.max_keys.is_some_and(|max| variants.len() >= max) {
+            return Err(anyhow!(
+                "{} has more than {} variant keys, read it with `Variant::read_keys_from_path` instead",
+                path.display(),
+                variants.len()
+            )
+            .into());
+        }
+        variants.push(variant?);
+    }
+
+    if opts.dedup {
+        let mut seen = HashSet::with_capacity(variants.len());
+        variants.retain(|v| seen.insert(v.clone()));
+    }
+    if opts.sort {
+        // stable, so variants at one position keep their order.
+        variants
+            .sort_by(|a, b| (&a.coord.contig, a.coord.pos).cmp(&(&b.coord.contig, b.coord.pos)));
+    }
+
+    Ok(variants)
+}
+
+/// Options of [`Variant::load_keys_from_path_with`]. By default, the variants
+/// are kept as in the file.
+#[derive(Debug, Clone, Default)]
+pub struct LoadKeysOptions {
+    dedup: bool,
+    sort: bool,
+    max_keys: Option<usize>,
+}
+
+impl LoadKeysOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Keep only the first of identical variants.
+    pub fn with_dedup(mut self, dedup: bool) -> Self {
+        self.dedup = dedup;
+        self
+    }
+
+    /// Sort by contig, in the order of [`Chrom`], then position, ready for
+    /// `batch_input_by_coordinate`.
+    pub fn with_sort(mut self, sort: bool) -> Self {
+        self.sort = sort;
+        self
+    }
+
+    /// Fail if the file has more than `max_keys` keys, duplicates included,
+    /// instead of loading them all.
+    pub fn with_max_keys(mut self, max_keys: usize) -> Self {
+        self.max_keys = Some(max_keys);
+        self
+    }
+}
+
+/// Variants of a key file, from [`Variant::read_keys_from_path`].
+///
+/// Blank lines are skipped. A malformed key is an error naming its line, and
+/// the keys after it can still be read.
+pub struct VariantKeyReader {
+    lines: LineReader,
+}
+
+impl VariantKeyReader {
+    /// The next variant, passed to `detach` while it borrows the line.
+    fn next_with<'b>(
+        &mut self,
+        detach: impl FnOnce(Variant<'_>) -> Variant<'b>,
+    ) -> Option<Result<Variant<'b>, crate::Error>> {
+        loop {
+            let key = match self.lines.next_line() {
+                Ok(Some(line)) => line.trim(),
+                Ok(None) => return None,
+                Err(err) => return Some(Err(err)),
+            };
+            if key.is_empty() {
+                continue;
+            }
+
+            let res = Variant::from_str_key(key).map(detach);
+            return Some(res.map_err(|err| self.lines.parse_error("variant key", err)));
+        }
+    }
+}
+
+impl Iterator for VariantKeyReader {
+    type Item = Result<Variant<'static>, crate::Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_with(|variant| variant.into_owned())
+    }
+}
+
+impl fmt::Debug for Variant<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Variant")
+            .field("coord", &self.coord)
+            .field("ref_b", &self.ref_bases())
+            .field("alt_b", &self.alt_bases())
+            .finish()
+    }
+}
+
+impl PartialOrd for Variant<'_> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Variant<'_> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (&self.coord.contig, self.coord.pos)
+            .cmp(&(&other.coord.contig, other.coord.pos))
+            .then_with(|| self.ref_bases().cmp(&other.ref_bases()))
+            .then_with(|| self.alt_bases().cmp(&other.alt_bases()))
+    }
+}
+
+/// Variants stored in minimal representation, see [`Variant::min_rep`], so
+/// that an indel is found however its padding is written.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct VariantSet {
+    variants: HashSet<Variant<'static>>,
+}
+
+impl VariantSet {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Store `variant` in minimal representation. Returns whether no
+    /// equivalent variant was stored.
+    pub fn insert(&mut self, variant: &Variant) -> bool {
+        self.variants.insert(variant.to_static_min_rep())
+    }
+
+    /// Whether a variant equivalent to `variant` is stored, i.e. one with the
+    /// same minimal representation.
+    pub fn contains_equivalent(&self, variant: &Variant) -> bool {
+        // looked up without detaching the contig name of the key.
+        let variants: &HashSet<Variant> = &self.variants;
+        variants.contains(&variant.min_rep())
+    }
+
+    /// Remove the variant equivalent to `variant`, if stored.
+    pub fn remove_equivalent(&mut self, variant: &Variant) -> bool {
+        self.variants.remove(&variant.to_static_min_rep())
+    }
+
+    pub fn len(&self) -> usize {
+        self.variants.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.variants.is_empty()
+    }
+
+    /// The stored variants, in minimal representation and arbitrary order.
+    pub fn iter(&self) -> impl Iterator<Item = &Variant<'static>> {
+        self.variants.iter()
+    }
+}
+
+impl<'a> FromIterator<Variant<'a>> for VariantSet {
+    fn from_iter<I: IntoIterator<Item = Variant<'a>>>(iter: I) -> Self {
+        let mut set = Self::new();
+        set.extend(iter);
+        set
+    }
+}
+
+impl<'a> Extend<Variant<'a>> for VariantSet {
+    fn extend<I: IntoIterator<Item = Variant<'a>>>(&mut self, iter: I) {
+        for variant in iter {
+            self.insert(&variant);
+        }
+    }
+}
+
+/// A [`Variant`] with its alleles as strings, e.g. to build or take apart
+/// one field by field.
+///
+/// It is also the serialized form of a variant, with the fields of the
+/// coordinate at the top level.
+#[derive(Debug, PartialEq, Eq, Clone, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct StringVariant<'a> {
+    pub chrom: Chrom<'a>,
+    /// 1-based position.
+    pub pos: i64,
+    pub ref_b: String,
+    pub alt_b: String,
+}
+
+impl<'a> From<StringVariant<'a>> for Variant<'a> {
+    /// Packs the alleles if they fit, reusing their strings otherwise.
+    fn from(value: StringVariant<'a>) -> Self {
+        Variant {
+            coord: GenomeCoordinate {
+                contig: value.chrom,
+                pos: value.pos,
+            },
+            alleles: Alleles::from_strings(value.ref_b, value.alt_b),
+        }
+    }
+}
+
+impl<'a> From<Variant<'a>> for StringVariant<'a> {
+    fn from(value: Variant<'a>) -> Self {
+        let (ref_b, alt_b) = match value.alleles {
+            Alleles::Packed { ref_b, alt_b } => (ref_b.to_string(), alt_b.to_string()),
+            Alleles::Long(long) => *long,
+        };
+        StringVariant {
+            chrom: value.coord.contig,
+            pos: value.coord.pos,
+            ref_b,
+            alt_b,
         }
     }
 }
 
+/// Bases of an allele of a [`Variant`], displayed and compared as a string.
+///
+/// Converted with `to_string` if a `String` is needed; [`Self::with_str`]
+/// lends a `&str` without allocating.
+#[derive(Clone, Copy)]
+pub enum BaseSlice<'a> {
+    Packed(&'a BaseArrShort),
+    Str(&'a str),
+}
+
+impl BaseSlice<'_> {
+    /// Call `f` with the bases as a `&str`, decoded on the stack if packed.
+    pub fn with_str<R>(&self, f: impl FnOnce(&str) -> R) -> R {
+        match self {
+            BaseSlice::Packed(arr) => {
+                let mut buf = [0_u8; BaseArrShort::capacity()];
+                let n = arr.fill_ascii(&mut buf);
+                // the bases of a `BaseArr` are ASCII.
+                f(std::str::from_utf8(&buf[..n]).unwrap())
+            }
+            BaseSlice::Str(s) => f(s),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.with_str(str::len)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl fmt::Display for BaseSlice<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.with_str(|s| f.pad(s))
+    }
+}
+
+impl fmt::Debug for BaseSlice<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.with_str(|s| fmt::Debug::fmt(s, f))
+    }
+}
+
+impl PartialEq for BaseSlice<'_> {
+    fn eq(&self, other: &Self) -> bool {
+        self.with_str(|a| other.with_str(|b| a == b))
+    }
+}
+
+impl Eq for BaseSlice<'_> {}
+
+impl PartialOrd for BaseSlice<'_> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for BaseSlice<'_> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.with_str(|a| other.with_str(|b| a.cmp(b)))
+    }
+}
+
+impl PartialEq<str> for BaseSlice<'_> {
+    fn eq(&self, other: &str) -> bool {
+        self.with_str(|s| s == other)
+    }
+}
+
+impl PartialEq<&str> for BaseSlice<'_> {
+    fn eq(&self, other: &&str) -> bool {
+        self == *other
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use std::str::FromStr;
+    use std::path::PathBuf;
 
-    use crate::data::{chrom::Chrom, variant::Variant};
+    use super::*;
 
     #[test]
     fn test_parse_string() -> Result<(), Box<dyn std::error::Error>> {
@@ -70,24 +622,20 @@ mod tests {
 
         assert_eq!(
             Variant::from_str_key(a)?,
-            Variant {
-                chrom: Chrom::ChrX,
-                pos: 12341,
-                ref_b: "AA".to_string(),
-                alt_b: "GG".to_string()
-            }
+            Variant::new(Chrom::ChrX, 12341, "AA", "GG")
         );
 
+        let v = Variant::from_str_key(a)?;
+        assert_eq!(v, Variant::new(Chrom::ChrX, 12341, "AA", "GG"));
+        assert_eq!((v.chrom(), v.pos()), (&Chrom::ChrX, 12341));
+        assert_eq!(v.ref_bases(), "AA");
+        assert_eq!(v.alt_bases(), "GG");
+
         let a = "chr1_1234111_ACA_TGG";
 
         assert_eq!(
             Variant::from_str_key(a)?,
-            Variant {
-                chrom: Chrom::Chr1,
-                pos: 1234111,
-                ref_b: "ACA".to_string(),
-                alt_b: "TGG".to_string()
-            }
+            Variant::new(Chrom::Chr1, 1234111, "ACA", "TGG")
         );
 
         Ok(())
@@ -116,4 +664,297 @@ mod tests {
 
         Variant::from_str_key(a).unwrap();
     }
+
+    fn fixture(name: &str) -> PathBuf {
+        PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/data")
+            .join(name)
+    }
+
+    fn keys(variants: &[Variant]) -> Vec<String> {
+        variants
+            .iter()
+            .map(|v| {
+                format!(
+                    "{}_{}_{}_{}",
+                    v.chrom(),
+                    v.pos(),
+                    v.ref_bases(),
+                    v.alt_bases()
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_load_keys() -> Result<(), Error> {
+        let path = fixture("variant_keys.txt");
+
+        let variants = Variant::load_keys_from_path(&path)?;
+        assert_eq!(variants.len(), 9);
+        assert_eq!(variants[6], Variant::new(Chrom::Chr1, 1000, "A", "C"));
+
+        let opts = LoadKeysOptions::new().with_dedup(true).with_sort(true);
+        assert_eq!(
+            keys(&Variant::load_keys_from_path_with(&path, &opts)?),
+            [
+                "chr1_1000_A_C",
+                "chr1_1000_A_G",
+                "chr1_2000_C_T",
+                "chr2_3000_A_G",
+                "chr10_10_T_TA",
+                "chrX_500_G_A",
+                "chrEBV_15_C_A",
+            ]
+        );
+
+        let opts = LoadKeysOptions::new().with_dedup(true);
+        assert_eq!(
+            keys(&Variant::load_keys_from_path_with(&path, &opts)?)[..3],
+            ["chr2_3000_A_G", "chr1_2000_C_T", "chrX_500_G_A"]
+        );
+
+        let opts = LoadKeysOptions::new().with_max_keys(9);
+        assert_eq!(Variant::load_keys_from_path_with(&path, &opts)?.len(), 9);
+        let opts = LoadKeysOptions::new().with_max_keys(8);
+        assert!(Variant::load_keys_from_path_with(&path, &opts).is_err());
+
+        #[cfg(feature = "gzip")]
+        {
+            use std::{fs::File, io::Write};
+
+            use flate2::{Compression, write::GzEncoder};
+
+            let dir = tempfile::tempdir()?;
+            let gz_path = dir.path().join("variant_keys.txt.gz");
+            let mut gz = GzEncoder::new(File::create(&gz_path)?, Compression::default());
+            gz.write_all(&std::fs::read(&path)?)?;
+            gz.finish()?;
+            assert_eq!(Variant::load_keys_from_path(&gz_path)?, variants);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_load_invalid_keys() -> Result<(), Error> {
+        let path = fixture("variant_keys_invalid.txt");
+
+        let err = Variant::load_keys_from_path(&path).unwrap_err();
+        assert!(
+            matches!(&err, crate::Error::Parse { line: 2, what, .. } if what == "variant key"),
+            "{:?}",
+            err
+        );
+        assert!(err.to_string().contains("line 2 of"), "{:#}", err);
+        assert!(format!("{:#}", err).contains("chr1_1001_A"), "{:#}", err);
+
+        // the keys after a malformed one are still read.
+        let results = Variant::read_keys_from_path(&path)?.collect::<Vec<_>>();
+        assert_eq!(results.len(), 3);
+        assert!(results[1].is_err());
+        assert_eq!(
+            results[2].as_ref().unwrap(),
+            &Variant::new(Chrom::Chr1, 1002, "A", "C")
+        );
+
+        let err = Variant::load_keys_from_path(fixture("missing.txt")).unwrap_err();
+        assert!(
+            matches!(&err, crate::Error::Io { source, .. } if source.kind() == std::io::ErrorKind::NotFound),
+            "{:?}",
+            err
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_packed_alleles() {
+        // 56 bytes, against 80 and two allocations with string alleles.
+        assert!(size_of::<Variant>() <= 56, "{}", size_of::<Variant>());
+
+        let snv = Variant::new(Chrom::Chr1, 100, "A", "G");
+        assert!(matches!(snv.alleles, Alleles::Packed { .. }));
+        assert_eq!(snv.memory_footprint(), size_of::<Variant>());
+        assert_eq!(snv.ref_bases(), "A");
+        assert_eq!(snv.alt_bases(), BaseSlice::Str("G"));
+        assert_eq!(
+            format!("{}>{:?}", snv.ref_bases(), snv.alt_bases()),
+            "A>\"G\""
+        );
+
+        // deletion of 25 bases, the most that is packed.
+        let del = "ACGTN".repeat(5);
+        let v = Variant::new(Chrom::Chr1, 100, &del, "A");
+        assert!(matches!(v.alleles, Alleles::Packed { .. }));
+        assert_eq!(v.ref_bases(), del.as_str());
+        assert_eq!(v.ref_bases().len(), 25);
+
+        // too long, lowercase, symbolic or empty.
+        for (ref_b, alt_b) in [
+            (format!("{del}A"), "A"),
+            ("a".to_string(), "G"),
+            ("A".to_string(), "<DEL>"),
+            ("A".to_string(), "*"),
+        ] {
+            let v = Variant::new(Chrom::Chr1, 100, &ref_b, alt_b);
+            assert!(matches!(v.alleles, Alleles::Long(_)), "{:?}", v);
+            assert_eq!(v.ref_bases(), ref_b.as_str());
+            assert_eq!(v.alt_bases(), alt_b);
+            assert!(v.memory_footprint() > size_of::<Variant>());
+        }
+        let empty = Variant::new(Chrom::Chr1, 100, "A", "");
+        assert!(empty.alt_bases().is_empty());
+
+        // the plain form converts back to the same, packed, variant.
+        for v in [snv, Variant::new(Chrom::from("chrEBV"), 7, "a", "T")] {
+            let plain = StringVariant::from(v.clone());
+            assert_eq!(plain.ref_b, v.ref_bases().to_string());
+            assert_eq!(Variant::from(plain), v);
+        }
+    }
+
+    #[test]
+    fn test_debug() {
+        // the same for packed and string alleles.
+        for alt_b in ["G", "<DEL>"] {
+            let v = Variant::new(Chrom::Chr1, 100, "A", alt_b);
+            assert_eq!(
+                format!("{:?}", v),
+                format!(
+                    r#"Variant {{ coord: GenomeCoordinate {{ contig: Chr1, pos: 100 }}, ref_b: "A", alt_b: "{}" }}"#,
+                    alt_b
+                )
+            );
+        }
+    }
+
+    #[test]
+    fn test_load_keys_interned() -> Result<(), Error> {
+        let path = fixture("variant_keys.txt");
+        let interner = ContigInterner::from_names(["chr1", "chrEBV"]);
+
+        let opts = LoadKeysOptions::new();
+        let variants = Variant::load_keys_from_path_interned(&path, &opts, &interner)?;
+        assert_eq!(variants, Variant::load_keys_from_path(&path)?);
+        let ebv = variants.last().unwrap();
+        assert!(
+            matches!(ebv.chrom(), Chrom::Other(Cow::Borrowed("chrEBV"))),
+            "{:?}",
+            ebv
+        );
+        assert_eq!(ebv.memory_footprint(), size_of::<Variant>());
+
+        // a key borrows its contig name too.
+        let v = Variant::from_str_key("chrKI270742.1_10_A_C")?;
+        assert!(matches!(v.chrom(), Chrom::Other(Cow::Borrowed(_))));
+        let owned = v.into_owned();
+        assert!(owned.memory_footprint() > size_of::<Variant>());
+        let interned = owned.clone().intern(&interner);
+        assert_eq!(interned, owned);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_min_rep() {
+        use std::hash::BuildHasher;
+
+        let v = |pos, ref_b, alt_b| Variant::new(Chrom::Chr1, pos, ref_b, alt_b);
+
+        // the same deletion, with different padding.
+        let (a, b) = (v(100, "CTT", "CT"), v(101, "TT", "T"));
+        assert_ne!(a, b);
+        assert_eq!(a.min_rep(), v(101, "T", ""));
+        assert_eq!(b.min_rep(), a.min_rep());
+        let state = std::hash::RandomState::new();
+        assert_eq!(
+            state.hash_one(a.to_static_min_rep()),
+            state.hash_one(b.to_static_min_rep())
+        );
+
+        for (variant, min_rep) in [
+            (v(100, "A", "G"), v(100, "A", "G")),
+            (v(100, "ACG", "ATG"), v(101, "C", "T")),
+            (v(100, "C", "CTT"), v(101, "", "TT")),
+            // a deletion in a repeat stays at its leftmost written position.
+            (v(100, "CTTT", "CTT"), v(101, "T", "")),
+            (v(100, "A", "A"), v(100, "", "")),
+            (v(100, "A", "<DEL>"), v(100, "A", "<DEL>")),
+            (v(100, "AT", "*"), v(100, "AT", "*")),
+        ] {
+            assert_eq!(variant.min_rep(), min_rep, "{:?}", variant);
+        }
+
+        let mut set = [v(100, "CTT", "CT"), v(100, "A", "G")]
+            .into_iter()
+            .collect::<VariantSet>();
+        assert_eq!(set.len(), 2);
+        assert!(set.contains_equivalent(&b));
+        assert!(!set.contains_equivalent(&v(101, "TT", "TTT")));
+        assert!(!set.insert(&b));
+        let ebv = Variant::from_str_key("chrEBV_7_AC_A").unwrap();
+        assert!(set.insert(&ebv));
+        assert!(set.contains_equivalent(&Variant::from_str_key("chrEBV_8_C_").unwrap()));
+        assert!(set.remove_equivalent(&a));
+        assert!(!set.contains_equivalent(&b));
+        assert_eq!(set.len(), 2);
+    }
+
+    #[test]
+    fn test_ordering_and_sites() {
+        let mut variants = vec![
+            Variant::new(Chrom::from("chrEBV"), 1, "A", "C"),
+            Variant::new(Chrom::Chr10, 5, "A", "C"),
+            Variant::new(Chrom::Chr2, 5, "AT", "A"),
+            Variant::new(Chrom::Chr2, 5, "A", "T"),
+            Variant::new(Chrom::Chr2, 5, "A", "<DEL>"),
+            Variant::new(Chrom::Chr2, 5, "A", "G"),
+            Variant::new(Chrom::Chr2, 4, "G", "A"),
+        ];
+        variants.sort();
+        assert_eq!(
+            keys(&variants),
+            [
+                "chr2_4_G_A",
+                "chr2_5_A_<DEL>",
+                "chr2_5_A_G",
+                "chr2_5_A_T",
+                "chr2_5_AT_A",
+                "chr10_5_A_C",
+                "chrEBV_1_A_C",
+            ]
+        );
+
+        // the alleles of a multi-allelic site grouped by its key, in a map
+        // shared with another thread.
+        let mut sites = std::collections::HashMap::<_, Vec<_>>::new();
+        for v in &variants {
+            sites
+                .entry(v.site_key().into_owned())
+                .or_default()
+                .push(v.to_static());
+        }
+        let sites = std::thread::spawn(move || sites).join().unwrap();
+        assert_eq!(sites.len(), 4);
+        let site = GenomeCoordinate {
+            contig: Chrom::Chr2,
+            pos: 5,
+        };
+        assert_eq!(sites[&site].len(), 4);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde_round_trip() {
+        for v in [
+            Variant::new(Chrom::ChrY, 2_781_480, "G", "A"),
+            Variant::new(Chrom::Other("chrUn_KI270742v1".into()), 1, "ACGT", "A"),
+        ] {
+            let json = serde_json::to_string(&v).unwrap();
+            assert_eq!(serde_json::from_str::<Variant>(&json).unwrap(), v);
+            let bin = bincode::serialize(&v).unwrap();
+            assert_eq!(bincode::deserialize::<Variant>(&bin).unwrap(), v);
+        }
+    }
 }
@@ -1,5 +1,19 @@
+//! Errors for missing values: traits turning an `Option` into an error, and
+//! [`HashMapExt`] for lookups of maps, e.g. by contig, failing with the key.
+//!
+//! # Example
+//! ```
+//! use std::collections::HashMap;
+//!
+//! use crackle_kit::err_opt_ext::HashMapExt;
+//!
+//! let lens = HashMap::from([("chr20".to_string(), 64_444_167)]);
+//! let err = lens.get_or_keyerr_suggest("20").unwrap_err();
+//! assert_eq!(err.to_string(), "Key 20 not found. Did you mean chr20?");
+//! ```
+
 /// # Example
-/// ```rust
+/// ```ignore
 /// impl_option_handle_trait!(SliceOption, ok_or_slice_err, anyhow!("slice failed"));
 ///
 /// // will expand to (default visibility: pub(crate)):
@@ -41,10 +55,16 @@ macro_rules! impl_option_handle_trait {
 }
 pub(crate) use impl_option_handle_trait;
 
-use std::slice::SliceIndex;
+use std::{collections::hash_map::Entry, slice::SliceIndex};
+
+/// Keys listed in the error of [`HashMapExt::get_or_keyerr_suggest`].
+const N_KEY_SUGGESTIONS: usize = 3;
 
+/// Keys longer than this are not compared by edit distance.
+const MAX_EDIT_DISTANCE_KEY_LEN: usize = 32;
 
-pub(crate) trait HashMapExt<K, V> {
+/// Lookups and inserts of a `HashMap` failing with an error naming the key.
+pub trait HashMapExt<K, V> {
     fn get_or_keyerr<Q>(&self, k: &Q) -> Result<&V, anyhow::Error>
     where
         K: std::borrow::Borrow<Q>,
@@ -54,6 +74,22 @@ pub(crate) trait HashMapExt<K, V> {
     where
         K: std::borrow::Borrow<Q>,
         Q: std::fmt::Display + std::hash::Hash + Eq + ?Sized;
+
+    /// Like [`Self::get_or_keyerr`], the error listing up to 3 keys close to
+    /// `k`, e.g. `chr20` for `20`: equal to it but for case or a `chr` prefix,
+    /// or within an edit distance of 2.
+    fn get_or_keyerr_suggest<Q>(&self, k: &Q) -> Result<&V, anyhow::Error>
+    where
+        K: std::borrow::Borrow<Q>,
+        Q: std::fmt::Display + std::hash::Hash + Eq + ?Sized;
+
+    /// The value of `k`, inserting the one made by `f` if it is missing. The
+    /// map is left as it was if `f` fails.
+    fn entry_or_try_insert_with(
+        &mut self,
+        k: K,
+        f: impl FnOnce() -> Result<V, anyhow::Error>,
+    ) -> Result<&mut V, anyhow::Error>;
 }
 
 impl<K, V, S> HashMapExt<K, V> for std::collections::HashMap<K, V, S>
@@ -82,6 +118,100 @@ where
             None => Err(anyhow::anyhow!("Key {} not found", k)),
         }
     }
+
+    fn get_or_keyerr_suggest<Q>(&self, k: &Q) -> Result<&V, anyhow::Error>
+    where
+        K: std::borrow::Borrow<Q>,
+        Q: std::fmt::Display + std::hash::Hash + Eq + ?Sized,
+    {
+        if let Some(v) = self.get(k) {
+            return Ok(v);
+        }
+        let query = k.to_string();
+        let suggestions = suggest_keys(&query, self.keys().map(|key| key.borrow().to_string()));
+        Err(anyhow::anyhow!(
+            "Key {} not found{}",
+            k,
+            did_you_mean(&suggestions)
+        ))
+    }
+
+    fn entry_or_try_insert_with(
+        &mut self,
+        k: K,
+        f: impl FnOnce() -> Result<V, anyhow::Error>,
+    ) -> Result<&mut V, anyhow::Error> {
+        match self.entry(k) {
+            Entry::Occupied(entry) => Ok(entry.into_mut()),
+            Entry::Vacant(entry) => Ok(entry.insert(f()?)),
+        }
+    }
+}
+
+/// The keys closest to the missing `query`, closest first and at most
+/// [`N_KEY_SUGGESTIONS`]: those equal to it but for case, then those equal but
+/// for a `chr` prefix, then those within an edit distance of 2 of it, ties by
+/// key.
+pub(crate) fn suggest_keys(query: &str, keys: impl Iterator<Item = String>) -> Vec<String> {
+    let mut close = keys
+        .filter_map(|key| key_distance(query, &key).map(|dist| (dist, key)))
+        .collect::<Vec<_>>();
+    close.sort_unstable();
+    close
+        .into_iter()
+        .take(N_KEY_SUGGESTIONS)
+        .map(|(_, key)| key)
+        .collect()
+}
+
+/// `. Did you mean a, b?` for the `suggestions` of [`suggest_keys`], to end an
+/// error message with; empty if there are none.
+pub(crate) fn did_you_mean(suggestions: &[String]) -> String {
+    match suggestions {
+        [] => String::new(),
+        _ => format!(". Did you mean {}?", suggestions.join(", ")),
+    }
+}
+
+/// How far `key` is from `query`, `None` if too far to suggest.
+fn key_distance(query: &str, key: &str) -> Option<usize> {
+    if key.eq_ignore_ascii_case(query) {
+        return Some(0);
+    }
+
+    let unprefixed = |s: &str| {
+        let s = s.to_ascii_lowercase();
+        match s.strip_prefix("chr") {
+            Some(rest) => rest.to_string(),
+            None => s,
+        }
+    };
+    let (query, key) = (unprefixed(query), unprefixed(key));
+    if key == query {
+        return Some(1);
+    }
+    if query.len().max(key.len()) > MAX_EDIT_DISTANCE_KEY_LEN {
+        return None;
+    }
+    match edit_distance(query.as_bytes(), key.as_bytes()) {
+        dist @ 1..=2 => Some(1 + dist),
+        _ => None,
+    }
+}
+
+/// Levenshtein distance.
+pub(crate) fn edit_distance(a: &[u8], b: &[u8]) -> usize {
+    let mut prev = (0..=b.len()).collect::<Vec<_>>();
+    let mut cur = vec![0; b.len() + 1];
+    for (i, ca) in a.iter().enumerate() {
+        cur[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = prev[j] + usize::from(ca != cb);
+            cur[j + 1] = substitution.min(prev[j + 1] + 1).min(cur[j] + 1);
+        }
+        std::mem::swap(&mut prev, &mut cur);
+    }
+    prev[b.len()]
 }
 
 pub(crate) trait SliceIndexExt {
@@ -116,3 +246,79 @@ impl<T> SliceIndexExt for [T] {
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use anyhow::bail;
+
+    use super::*;
+    use crate::data::{arc_string::ArcString, chrom::Chrom};
+
+    #[test]
+    fn test_edit_distance() {
+        assert_eq!(edit_distance(b"", b"abc"), 3);
+        assert_eq!(edit_distance(b"12", b"11"), 1);
+        assert_eq!(edit_distance(b"kitten", b"sitting"), 3);
+    }
+
+    #[test]
+    fn test_get_or_keyerr_suggest() {
+        let map = ["chr2", "chr20", "CHR21", "chr22", "chrX", "HLA-A*01:01"]
+            .into_iter()
+            .map(|k| (ArcString::from(k.to_string()), ()))
+            .collect::<HashMap<_, _>>();
+        assert!(map.get_or_keyerr_suggest("chr2").is_ok());
+
+        // the case, then the prefix, then the edit distance, ties by key.
+        let err = map.get_or_keyerr_suggest("chr21").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Key chr21 not found. Did you mean CHR21, chr2, chr20?"
+        );
+        let err = map.get_or_keyerr_suggest("20").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Key 20 not found. Did you mean chr20, CHR21, chr2?"
+        );
+        let err = map.get_or_keyerr_suggest("x").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Key x not found. Did you mean chrX, chr2, CHR21?"
+        );
+        let err = map.get_or_keyerr_suggest("chrEBV").unwrap_err();
+        assert_eq!(err.to_string(), "Key chrEBV not found");
+        let err = map.get_or_keyerr("20").unwrap_err();
+        assert_eq!(err.to_string(), "Key 20 not found");
+        let err = map.get_or_keyerr_suggest("HLA-A*01:02").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Key HLA-A*01:02 not found. Did you mean HLA-A*01:01?"
+        );
+
+        let map = HashMap::from([(Chrom::Chr20, 20), (Chrom::Chr1, 1)]);
+        let err = map.get_or_keyerr_suggest(&Chrom::Chr2).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Key chr2 not found. Did you mean chr1, chr20?"
+        );
+    }
+
+    #[test]
+    fn test_entry_or_try_insert_with() -> Result<(), anyhow::Error> {
+        let mut map = HashMap::from([(Chrom::Chr1, 1)]);
+
+        *map.entry_or_try_insert_with(Chrom::Chr1, || bail!("not called"))? += 1;
+        assert_eq!(*map.get_mut_or_keyerr(&Chrom::Chr1)?, 2);
+        assert_eq!(*map.entry_or_try_insert_with(Chrom::Chr2, || Ok(2))?, 2);
+        assert_eq!(map, HashMap::from([(Chrom::Chr1, 2), (Chrom::Chr2, 2)]));
+
+        let err = map
+            .entry_or_try_insert_with(Chrom::ChrX, || bail!("cannot open"))
+            .unwrap_err();
+        assert_eq!(err.to_string(), "cannot open");
+        assert_eq!(map, HashMap::from([(Chrom::Chr1, 2), (Chrom::Chr2, 2)]));
+
+        Ok(())
+    }
+}
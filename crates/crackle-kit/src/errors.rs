@@ -1,5 +1,214 @@
+use std::{error::Error as StdError, fmt, io, path::PathBuf};
+
 use thiserror::Error;
 
+/// Inputs of an [`Error::OutOfContig`] displayed, the others counted.
+const N_COORDINATES_SHOWN: usize = 5;
+
+/// Error of the public functions of the crate, by kind of failure.
+///
+/// Converts to [`anyhow::Error`] with `?`. An `anyhow::Error` made from one of
+/// these, or from an htslib error, converts back to it unless context was added
+/// to it; any other is [`Error::Other`].
+///
+/// Like an `anyhow::Error`, it is displayed with its causes by `{:#}`.
+///
+/// Functions failing only on invalid arguments, or on errors of a writer given
+/// by the caller, return an `anyhow::Error`, as do the traits implemented by
+/// users, e.g. of workers.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum Error {
+    /// A file could not be opened, created, read or written, e.g. as it does
+    /// not exist.
+    Io {
+        action: &'static str,
+        path: PathBuf,
+        source: io::Error,
+    },
+    /// Malformed content of a file.
+    Parse {
+        what: String,
+        path: PathBuf,
+        /// 1-based.
+        line: usize,
+        source: anyhow::Error,
+    },
+    #[cfg(feature = "bam")]
+    Htslib(rust_htslib::errors::Error),
+    ContigNotFound {
+        contig: String,
+        n_contigs: usize,
+        /// The contigs of the header closest to `contig`, closest first.
+        suggestions: Vec<String>,
+    },
+    /// Fetching a region of a bam failed.
+    Fetch {
+        /// 1-based inclusive, e.g. `chr1:101-200`.
+        region: String,
+        path: Option<PathBuf>,
+        source: anyhow::Error,
+    },
+    Cancelled,
+    /// The inputs of a batch all lie past the end of their contig, so that
+    /// there is nothing to fetch for them.
+    OutOfContig {
+        contig: String,
+        /// Length of the contig in the bam header.
+        len: u64,
+        /// The inputs of the batch, 1-based, e.g. `chr1:1001`.
+        coordinates: Vec<String>,
+    },
+    /// A worker failed on an input, e.g. at a locus or on a read.
+    WorkerFailed {
+        /// The input, e.g. `locus chr1:100` or `read r1 at chr1:100`.
+        item: String,
+        source: anyhow::Error,
+    },
+    /// The plan of a run has problems, all of those found, see
+    /// [`RunPlan`](crate::bam::plan::RunPlan).
+    InvalidPlan {
+        problems: Vec<PlanProblem>,
+    },
+    Other(anyhow::Error),
+}
+
+impl Error {
+    pub(crate) fn io(action: &'static str, path: impl Into<PathBuf>, source: io::Error) -> Self {
+        Self::Io {
+            action,
+            path: path.into(),
+            source,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let source: &dyn fmt::Display = match self {
+            Self::Io {
+                action,
+                path,
+                source,
+            } => {
+                write!(f, "Failed to {} {}", action, path.display())?;
+                source
+            }
+            Self::Parse {
+                what,
+                path,
+                line,
+                source,
+            } => {
+                write!(f, "Invalid {} at line {} of {}", what, line, path.display())?;
+                source
+            }
+            #[cfg(feature = "bam")]
+            Self::Htslib(err) => return fmt::Display::fmt(err, f),
+            Self::ContigNotFound {
+                contig,
+                n_contigs,
+                suggestions,
+            } => {
+                return write!(
+                    f,
+                    "Contig {} is not in the bam header. Closest of its {} contigs: {}",
+                    contig,
+                    n_contigs,
+                    suggestions.join(", ")
+                );
+            }
+            Self::Fetch {
+                region,
+                path,
+                source,
+            } => {
+                write!(f, "Failed to fetch {}", region)?;
+                if let Some(path) = path {
+                    write!(f, " from {}", path.display())?;
+                }
+                source
+            }
+            Self::Cancelled => return write!(f, "Processing was cancelled"),
+            Self::OutOfContig {
+                contig,
+                len,
+                coordinates,
+            } => {
+                write!(f, "Inputs ")?;
+                for (i, coordinate) in coordinates.iter().take(N_COORDINATES_SHOWN).enumerate() {
+                    write!(f, "{}{}", if i == 0 { "" } else { ", " }, coordinate)?;
+                }
+                if coordinates.len() > N_COORDINATES_SHOWN {
+                    write!(f, " and {} more", coordinates.len() - N_COORDINATES_SHOWN)?;
+                }
+                return write!(f, " lie past the end of {}, of {} bp", contig, len);
+            }
+            Self::WorkerFailed { item, source } => {
+                write!(f, "Failed to process {}", item)?;
+                source
+            }
+            Self::InvalidPlan { problems } => {
+                write!(f, "The run plan has {} problem", problems.len())?;
+                if problems.len() > 1 {
+                    write!(f, "s")?;
+                }
+                for (i, problem) in problems.iter().enumerate() {
+                    write!(f, "{}{}", if i == 0 { ": " } else { "; " }, problem)?;
+                }
+                return Ok(());
+            }
+            Self::Other(err) => return fmt::Display::fmt(err, f),
+        };
+
+        if f.alternate() {
+            write!(f, ": {:#}", source)?;
+        }
+        Ok(())
+    }
+}
+
+impl StdError for Error {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        match self {
+            Self::Io { source, .. } => Some(source),
+            Self::Parse { source, .. }
+            | Self::Fetch { source, .. }
+            | Self::WorkerFailed { source, .. } => Some(source.as_ref()),
+            // displayed as the error itself, so its causes follow.
+            #[cfg(feature = "bam")]
+            Self::Htslib(err) => err.source(),
+            Self::Other(err) => err.source(),
+            Self::ContigNotFound { .. }
+            | Self::Cancelled
+            | Self::OutOfContig { .. }
+            | Self::InvalidPlan { .. } => None,
+        }
+    }
+}
+
+#[cfg(feature = "bam")]
+impl From<rust_htslib::errors::Error> for Error {
+    fn from(err: rust_htslib::errors::Error) -> Self {
+        Self::Htslib(err)
+    }
+}
+
+impl From<anyhow::Error> for Error {
+    fn from(err: anyhow::Error) -> Self {
+        // only the error itself: downcasting one with context drops the context.
+        let inner = &*err as &(dyn StdError + 'static);
+        if inner.is::<Error>() {
+            return err.downcast().unwrap();
+        }
+        #[cfg(feature = "bam")]
+        if inner.is::<rust_htslib::errors::Error>() {
+            return Self::Htslib(err.downcast().unwrap());
+        }
+        Self::Other(err)
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Error)]
 pub enum PosError {
     #[error("0-based position must be >= 0, got {0}")]
@@ -16,4 +225,168 @@ pub enum PosError {
 pub enum LocusError {
     #[error(transparent)]
     Pos(#[from] PosError),
+    #[error("invalid region `{0}`, expected `contig:start-end`")]
+    InvalidRegion(String),
+    #[error("region {contig}:{start}-{end} ends before it starts")]
+    InvertedRegion {
+        contig: String,
+        start: i64,
+        end: i64,
+    },
+}
+
+/// Error of [`ThreadBudget::reserve`](crate::utils::thread_budget::ThreadBudget::reserve).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Error)]
+pub enum ThreadBudgetError {
+    #[error("{requested} threads requested of a budget of {total}")]
+    OverTotal { requested: usize, total: usize },
+    #[error("{requested} threads requested, {available} of {total} available")]
+    Exhausted {
+        requested: usize,
+        available: usize,
+        total: usize,
+    },
+}
+
+/// What would fail a run, found by the checks of its
+/// [`RunPlan`](crate::bam::plan::RunPlan).
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum PlanProblem {
+    #[error("input {} does not exist", .path.display())]
+    MissingInput { path: PathBuf },
+    /// The input has no index, which the run needs.
+    #[error("{message}")]
+    MissingIndex { path: PathBuf, message: String },
+    /// The input or its header could not be read.
+    #[error("failed to read {}: {message}", .path.display())]
+    UnreadableInput { path: PathBuf, message: String },
+    /// The input failed its [`quick_check`](crate::bam::validate::quick_check),
+    /// e.g. of a missing EOF block.
+    #[error("input {} looks corrupt: {message}", .path.display())]
+    CorruptInput { path: PathBuf, message: String },
+    /// Contigs of the inputs not in the header under any of their names.
+    #[error("contigs not in the header of {n_contigs} contigs: {}", .contigs.join(", "))]
+    UnknownContigs {
+        contigs: Vec<String>,
+        n_contigs: usize,
+    },
+    /// The header does not match the dictionary the run checks it against.
+    #[error("{message}")]
+    DictMismatch { message: String },
+    /// An output, or the directory it goes to, cannot be written.
+    #[error("cannot write {}: {message}", .path.display())]
+    UnwritableOutput { path: PathBuf, message: String },
+    /// The threads asked for cannot be had, e.g. of a thread budget too small.
+    #[error("{message}")]
+    Threads { message: String },
+    /// An option of the run is invalid, e.g. a batch size of 0.
+    #[error("{message}")]
+    InvalidOption { message: String },
+}
+
+/// Difference of two sequence dictionaries, see
+/// [`SeqDict::compatible_with`](crate::data::seq_dict::SeqDict::compatible_with).
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum DictMismatch {
+    /// Contigs missing from either dictionary, or of other lengths or MD5s.
+    /// Lengths are of the first dictionary, then of the second.
+    #[error("sequence dictionaries differ: {}", describe_incompatible(.missing, .extra, .lengths, .md5s))]
+    Incompatible {
+        missing: Vec<String>,
+        extra: Vec<String>,
+        lengths: Vec<(String, u64, u64)>,
+        md5s: Vec<String>,
+    },
+    #[error("contigs are in another order: {contig} comes after {after}")]
+    OrderDiffers { contig: String, after: String },
+}
+
+impl DictMismatch {
+    /// Whether the dictionaries agree on every coordinate, and only differ in
+    /// the order of their contigs.
+    pub fn is_warning(&self) -> bool {
+        matches!(self, Self::OrderDiffers { .. })
+    }
+}
+
+fn describe_incompatible(
+    missing: &[String],
+    extra: &[String],
+    lengths: &[(String, u64, u64)],
+    md5s: &[String],
+) -> String {
+    let mut parts = vec![];
+    if !missing.is_empty() {
+        parts.push(format!("missing {}", missing.join(", ")));
+    }
+    if !extra.is_empty() {
+        parts.push(format!("extra {}", extra.join(", ")));
+    }
+    if !lengths.is_empty() {
+        let lengths = lengths
+            .iter()
+            .map(|(contig, len, other_len)| format!("{} ({} vs {})", contig, len, other_len))
+            .collect::<Vec<_>>();
+        parts.push(format!("lengths of {}", lengths.join(", ")));
+    }
+    if !md5s.is_empty() {
+        parts.push(format!("MD5 of {}", md5s.join(", ")));
+    }
+    parts.join("; ")
+}
+
+#[cfg(all(feature = "memfd", target_os = "linux"))]
+#[derive(Debug, Error)]
+pub enum MemFdError {
+    #[error("source is {size} bytes, over the limit of {limit} bytes")]
+    TooLarge { size: u64, limit: u64 },
+    #[error("memfd_create failed: {0}")]
+    Create(#[from] nix::Error),
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::anyhow;
+
+    use super::*;
+
+    #[test]
+    fn test_error_round_trip() {
+        let err = Error::WorkerFailed {
+            item: "locus chr1:100".to_string(),
+            source: anyhow!("no reads").context("Failed to count"),
+        };
+        assert_eq!(err.to_string(), "Failed to process locus chr1:100");
+        assert_eq!(
+            format!("{:#}", err),
+            "Failed to process locus chr1:100: Failed to count: no reads"
+        );
+
+        // through anyhow and back, the causes printed once.
+        let any = anyhow::Error::from(err);
+        assert_eq!(
+            format!("{:#}", any),
+            "Failed to process locus chr1:100: Failed to count: no reads"
+        );
+        assert!(matches!(Error::from(any), Error::WorkerFailed { .. }));
+
+        // with context, the context is kept.
+        let any = anyhow::Error::from(Error::Cancelled).context("Failed to run");
+        let err = Error::from(any);
+        assert!(matches!(err, Error::Other(_)), "{:?}", err);
+        assert_eq!(
+            format!("{:#}", err),
+            "Failed to run: Processing was cancelled"
+        );
+
+        let err = Error::io(
+            "open",
+            "a.bed",
+            io::Error::new(io::ErrorKind::NotFound, "missing"),
+        );
+        assert_eq!(format!("{:#}", err), "Failed to open a.bed: missing");
+    }
 }
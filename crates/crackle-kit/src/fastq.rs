@@ -1,27 +1,81 @@
 use anyhow::{Error, anyhow};
 use crossbeam_channel::{Receiver, Sender, bounded, select};
+use flate2::Compression;
 use flate2::bufread::MultiGzDecoder;
+use flate2::write::GzEncoder;
 use std::fs::File;
-use std::io::{self, BufRead, BufReader};
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
 use std::path::{Path, PathBuf};
+use std::sync::{
+    Arc,
+    atomic::{self, AtomicU64},
+};
 use std::thread::{self, JoinHandle, sleep};
 use std::time::Duration;
 
+#[cfg(feature = "pbar")]
+use indicatif::ProgressBar;
+
+#[cfg(feature = "tracing")]
+use crate::utils::throughput::ThroughputLogger;
+
+use crate::utils::{
+    pool::ObjectPool,
+    textio::{CompressionKind, detect_compression},
+    umi::UmiScheme,
+};
+
+#[cfg(feature = "async")]
+pub mod async_reader;
+pub mod demux;
+pub mod extract;
+pub mod stats;
+
+/// Counts the bytes read from `inner`.
+struct CountingReader<R> {
+    inner: R,
+    n_read: u64,
+}
+
+impl<R: io::Read> io::Read for CountingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.n_read += n as u64;
+        Ok(n)
+    }
+}
+
 enum FastqReader {
-    Plain(BufReader<File>),
-    Gz(BufReader<MultiGzDecoder<BufReader<File>>>),
+    Plain(BufReader<CountingReader<File>>),
+    Gz(Box<BufReader<MultiGzDecoder<BufReader<CountingReader<File>>>>>),
 }
 
 impl FastqReader {
+    /// Gzipped by the content of the file, see [`detect_compression`].
     fn from_path(path: impl AsRef<Path>) -> io::Result<Self> {
-        let file = BufReader::new(File::open(path.as_ref())?);
-        if let Some(true) = path.as_ref().extension().map(|s| s == "gz") {
-            let decoder = MultiGzDecoder::new(file);
-            Ok(FastqReader::Gz(BufReader::new(decoder)))
-        } else {
-            Ok(FastqReader::Plain(file))
+        let file = CountingReader {
+            inner: File::open(path.as_ref())?,
+            n_read: 0,
+        };
+        let mut file = BufReader::new(file);
+        match detect_compression(&mut file)? {
+            CompressionKind::Plain => Ok(FastqReader::Plain(file)),
+            CompressionKind::Gzip | CompressionKind::Bgzf => {
+                let decoder = MultiGzDecoder::new(file);
+                Ok(FastqReader::Gz(Box::new(BufReader::new(decoder))))
+            }
         }
     }
+
+    /// Bytes read from the file, compressed if gzipped, buffered ones
+    /// included: the size of the file once all records are read.
+    fn bytes_read(&self) -> u64 {
+        let file = match self {
+            FastqReader::Plain(r) => r.get_ref(),
+            FastqReader::Gz(r) => r.get_ref().get_ref().get_ref(),
+        };
+        file.n_read
+    }
 }
 
 impl io::Read for FastqReader {
@@ -112,17 +166,41 @@ impl FastqRecord {
         Ok(true)
     }
 
+    /// Line `i` of the record, empty if not loaded yet, e.g. once cleared.
+    fn line(&self, i: usize) -> &[u8] {
+        if i >= self.idx_offset {
+            return &[];
+        }
+        let start = match i {
+            0 => 0,
+            _ => self.indices[i - 1],
+        };
+        &self.buf[start..self.indices[i]]
+    }
+
+    fn line_str(&self, i: usize, what: &str) -> Result<&str, Error> {
+        std::str::from_utf8(self.line(i)).map_err(|e| anyhow!("Invalid UTF-8 in {}: {}", what, e))
+    }
+
     /// Returns the header as a &str.
+    ///
+    /// # Panics
+    /// If the header is not valid UTF-8, see [`Self::try_header`].
     pub fn header(&self) -> &str {
-        std::str::from_utf8(&self.buf[0..self.indices[0]]).expect("Invalid UTF-8 in header")
+        self.try_header().unwrap()
+    }
+
+    /// The header as a &str, failing if it is not valid UTF-8.
+    pub fn try_header(&self) -> Result<&str, Error> {
+        self.line_str(0, "header")
     }
 
     pub fn header_bytes(&self) -> &[u8] {
-        &self.buf[0..self.indices[0]]
+        self.line(0)
     }
 
     pub fn header_id_bytes(&self) -> &[u8] {
-        let header = &self.buf[0..self.indices[0]];
+        let header = self.header_bytes();
         // Find the position of the first whitespace
         match header.iter().position(|b| b.is_ascii_whitespace()) {
             Some(pos) => &header[0..pos],
@@ -130,43 +208,191 @@ impl FastqRecord {
         }
     }
 
+    /// Read id without the leading `@` and the comment, as the bam qname of the
+    /// read.
+    pub fn read_id_bytes(&self) -> &[u8] {
+        let id = self.header_id_bytes();
+        id.strip_prefix(b"@").unwrap_or(id)
+    }
+
+    /// UMI of the read, if it has a valid one under `scheme`.
+    pub fn umi(&self, scheme: UmiScheme) -> Option<&[u8]> {
+        scheme.extract(self.read_id_bytes(), self.sequence_bytes())
+    }
+
+    /// Remove the first `len` bases and their qualities, e.g. a UMI of
+    /// [`UmiScheme::SequencePrefix`] once extracted.
+    ///
+    /// # Panics
+    /// If the sequence is shorter than `len`.
+    pub fn trim_umi_prefix(&mut self, len: usize) {
+        assert!(
+            self.indices[0] + len <= self.indices[1],
+            "cannot trim {} bases from a sequence of {}",
+            len,
+            self.indices[1] - self.indices[0]
+        );
+
+        let [header_end, seq_end, plus_end, qual_end] = &mut self.indices;
+        self.buf.drain(*plus_end..*plus_end + len);
+        self.buf.drain(*header_end..*header_end + len);
+        *seq_end -= len;
+        *plus_end -= len;
+        *qual_end -= 2 * len;
+    }
+
     /// Returns the sequence as a &str.
+    ///
+    /// # Panics
+    /// If the sequence is not valid UTF-8, see [`Self::try_sequence`].
     pub fn sequence(&self) -> &str {
-        std::str::from_utf8(&self.buf[self.indices[0]..self.indices[1]])
-            .expect("Invalid UTF-8 in sequence")
+        self.try_sequence().unwrap()
+    }
+
+    pub fn try_sequence(&self) -> Result<&str, Error> {
+        self.line_str(1, "sequence")
+    }
+
+    /// The bases, as read, for the code which needs no `str`.
+    pub fn sequence_bytes(&self) -> &[u8] {
+        self.line(1)
     }
 
     /// Returns the plus line as a &str.
+    ///
+    /// # Panics
+    /// If the line is not valid UTF-8, see [`Self::try_plus`].
     pub fn plus(&self) -> &str {
-        std::str::from_utf8(&self.buf[self.indices[1]..self.indices[2]])
-            .expect("Invalid UTF-8 in plus line")
+        self.try_plus().unwrap()
+    }
+
+    pub fn try_plus(&self) -> Result<&str, Error> {
+        self.line_str(2, "plus line")
+    }
+
+    pub fn plus_bytes(&self) -> &[u8] {
+        self.line(2)
     }
 
     /// Returns the quality line as a &str.
+    ///
+    /// # Panics
+    /// If the line is not valid UTF-8, see [`Self::try_quality`].
     pub fn quality(&self) -> &str {
-        std::str::from_utf8(&self.buf[self.indices[2]..]).expect("Invalid UTF-8 in quality")
+        self.try_quality().unwrap()
+    }
+
+    pub fn try_quality(&self) -> Result<&str, Error> {
+        self.line_str(3, "quality")
+    }
+
+    /// The qualities, phred+33 as read.
+    pub fn quality_bytes(&self) -> &[u8] {
+        self.line(3)
+    }
+
+    /// Mean base quality, decoding the quality line as phred+33.
+    /// Returns `None` for a record without bases.
+    pub fn mean_quality(&self) -> Option<f64> {
+        let qual = self.quality_bytes();
+        if qual.is_empty() {
+            return None;
+        }
+
+        let sum = qual
+            .iter()
+            .map(|q| q.saturating_sub(33) as u64)
+            .sum::<u64>();
+        Some(sum as f64 / qual.len() as f64)
+    }
+
+    /// Write the record as four fastq lines.
+    pub fn write_to(&self, mut w: impl Write) -> io::Result<()> {
+        for i in 0..self.indices.len() {
+            w.write_all(self.line(i))?;
+            w.write_all(b"\n")?;
+        }
+        Ok(())
+    }
+}
+
+impl Default for FastqRecord {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
+/// Writes fastq records to a file, gzipped if its name ends with `.gz`.
+pub struct FastqWriter {
+    inner: FastqWriterInner,
+}
+
+enum FastqWriterInner {
+    Plain(BufWriter<File>),
+    Gz(Box<GzEncoder<BufWriter<File>>>),
+}
+
+impl FastqWriter {
+    pub fn from_path(path: impl AsRef<Path>) -> io::Result<Self> {
+        let file = BufWriter::new(File::create(path.as_ref())?);
+        let inner = if let Some(true) = path.as_ref().extension().map(|s| s == "gz") {
+            FastqWriterInner::Gz(Box::new(GzEncoder::new(file, Compression::default())))
+        } else {
+            FastqWriterInner::Plain(file)
+        };
+        Ok(Self { inner })
+    }
+
+    pub fn write(&mut self, record: &FastqRecord) -> io::Result<()> {
+        match &mut self.inner {
+            FastqWriterInner::Plain(w) => record.write_to(w),
+            FastqWriterInner::Gz(w) => record.write_to(w),
+        }
+    }
+
+    /// Flush the records, and write the end of the gzip stream.
+    pub fn finish(self) -> io::Result<()> {
+        match self.inner {
+            FastqWriterInner::Plain(mut w) => w.flush(),
+            FastqWriterInner::Gz(w) => (*w).finish()?.flush(),
+        }
+    }
+}
+
+/// Batches of records handed back by the consumer to a reader thread, so that
+/// the records keep their buffers.
+type BatchPool = Arc<ObjectPool<Vec<FastqRecord>>>;
+
 /// Spawns a thread that continuously loads FASTQ records from the file at `filename`
 /// and sends them on a bounded crossbeam channel.
+///
+/// Batches are taken from `pool`, and only allocated, `batch_size` records each,
+/// while it is empty. The bounded channel limits how many are out at once.
+///
+/// `bytes_read` is set to the bytes read from the file, compressed if gzipped,
+/// before each batch is sent.
 fn spawn_reader_thread(
     filename: impl AsRef<Path>,
     sender: Sender<Result<Vec<FastqRecord>, Error>>,
-    buf_receiver: Receiver<Vec<FastqRecord>>,
-) -> Result<thread::JoinHandle<Result<(), Error>>, Error> {
-    let filename = filename.as_ref().to_path_buf();
-    let mut reader = FastqReader::from_path(filename)?;
+    pool: BatchPool,
+    batch_size: usize,
+    bytes_read: Arc<AtomicU64>,
+) -> Result<thread::JoinHandle<Result<(), Error>>, crate::errors::Error> {
+    let filename = filename.as_ref();
+    let mut reader = FastqReader::from_path(filename)
+        .map_err(|e| crate::errors::Error::io("open", filename, e))?;
 
     let r = thread::spawn(move || {
         'w: loop {
-            let mut record_buf = buf_receiver.recv()?;
+            let mut record_buf =
+                pool.get_or_else(|| (0..batch_size).map(|_| FastqRecord::new()).collect());
 
             for record in record_buf.iter_mut() {
                 match record.load_record(&mut reader) {
                     Ok(true) => {}
                     Ok(false) => {
                         // EOF reached.
+                        bytes_read.store(reader.bytes_read(), atomic::Ordering::Relaxed);
                         sender.send(Ok(record_buf))?;
                         break 'w;
                     }
@@ -177,6 +403,7 @@ fn spawn_reader_thread(
                 }
             }
 
+            bytes_read.store(reader.bytes_read(), atomic::Ordering::Relaxed);
             sender.send(Ok(record_buf))?;
         }
 
@@ -210,6 +437,10 @@ pub struct PairedFastqReaderConfig {
     r2_filename: PathBuf,
     batch_size: usize,
     pool_capacity: usize,
+    #[cfg(feature = "pbar")]
+    pbar: Option<ProgressBar>,
+    #[cfg(feature = "tracing")]
+    throughput: Option<Arc<ThroughputLogger>>,
 }
 
 impl PairedFastqReaderConfig {
@@ -220,38 +451,74 @@ impl PairedFastqReaderConfig {
             r2_filename: r2_filename.as_ref().to_path_buf(),
             batch_size: 1024,    // Fixed records per batch.
             pool_capacity: 512, // Fixed number of batches.
+            #[cfg(feature = "pbar")]
+            pbar: None,
+            #[cfg(feature = "tracing")]
+            throughput: None,
         }
     }
 
+    /// Show the bytes read of both files on `pbar`, its length set to their
+    /// total size by [`Self::run`]. Its position is updated as batches of R1
+    /// are read, and at the end of the files.
+    #[cfg(feature = "pbar")]
+    pub fn with_progress_bar(mut self, pbar: ProgressBar) -> Self {
+        self.pbar = Some(pbar);
+        self
+    }
+
+    /// Count the pairs read on `logger`, as batches of R1 are taken.
+    #[cfg(feature = "tracing")]
+    pub fn with_throughput(mut self, logger: Arc<ThroughputLogger>) -> Self {
+        self.throughput = Some(logger);
+        self
+    }
+
     /// Spawns the worker threads based on the configuration and returns the runtime reader.
-    pub fn run(self) -> Result<PairedFastqReader, Error> {
+    pub fn run(self) -> Result<PairedFastqReader, crate::errors::Error> {
         // Create output channels from the worker threads.
         let (tx_r1, rx_r1) = bounded::<Result<Vec<FastqRecord>, Error>>(self.pool_capacity);
         let (tx_r2, rx_r2) = bounded::<Result<Vec<FastqRecord>, Error>>(self.pool_capacity);
 
-        // Create pool channels for recycling empty batch buffers.
-        let (pool_tx_r1, pool_rx_r1) = bounded::<Vec<FastqRecord>>(self.pool_capacity);
-        let (pool_tx_r2, pool_rx_r2) = bounded::<Vec<FastqRecord>>(self.pool_capacity);
-
-        // Preinitialize the batch pools.
-        for _ in 0..self.pool_capacity {
-            let batch: Vec<FastqRecord> =
-                (0..self.batch_size).map(|_| FastqRecord::new()).collect();
-            pool_tx_r1.send(batch.clone())?; // Clone one for r1.
-            pool_tx_r2.send(batch)?; // r2 gets its own copy.
+        // Pools for recycling empty batch buffers, filled as batches are read.
+        let pool_r1 = Arc::new(ObjectPool::new(self.pool_capacity));
+        let pool_r2 = Arc::new(ObjectPool::new(self.pool_capacity));
+
+        #[cfg(feature = "pbar")]
+        if let Some(pbar) = &self.pbar {
+            let mut total = 0;
+            for path in [&self.r1_filename, &self.r2_filename] {
+                let metadata = std::fs::metadata(path)
+                    .map_err(|e| crate::errors::Error::io("read the metadata of", path, e))?;
+                total += metadata.len();
+            }
+            pbar.set_length(total);
         }
 
         // Spawn worker threads (using your spawn_reader_thread function).
-        let handle_r1 = spawn_reader_thread(&self.r1_filename, tx_r1, pool_rx_r1)?;
-        let handle_r2 = spawn_reader_thread(&self.r2_filename, tx_r2, pool_rx_r2)?;
+        let bytes_read: [Arc<AtomicU64>; 2] = Default::default();
+        let handle_r1 = spawn_reader_thread(
+            &self.r1_filename,
+            tx_r1,
+            pool_r1.clone(),
+            self.batch_size,
+            bytes_read[0].clone(),
+        )?;
+        let handle_r2 = spawn_reader_thread(
+            &self.r2_filename,
+            tx_r2,
+            pool_r2.clone(),
+            self.batch_size,
+            bytes_read[1].clone(),
+        )?;
 
         Ok(PairedFastqReader {
             // Initialize channels.
             r1_out: rx_r1,
             r2_out: rx_r2,
-            // Store pool senders.
-            r1_pool: pool_tx_r1,
-            r2_pool: pool_tx_r2,
+            // Store pools.
+            r1_pool: pool_r1,
+            r2_pool: pool_r2,
             // Start with no current batch and indices at 0.
             current_batch_r1: None,
             current_batch_r2: None,
@@ -259,6 +526,13 @@ impl PairedFastqReaderConfig {
             current_index_r2: 0,
             // Save join handles for later shutdown.
             handles: vec![handle_r1, handle_r2],
+            bytes_read,
+            #[cfg(feature = "pbar")]
+            pbar: self.pbar,
+            #[cfg(feature = "tracing")]
+            throughput: self.throughput,
+            #[cfg(feature = "tracing")]
+            n_unlogged: 0,
         })
     }
 }
@@ -277,9 +551,9 @@ pub struct PairedFastqReader {
     // Channels for receiving filled batches.
     r1_out: Receiver<Result<Vec<FastqRecord>, Error>>,
     r2_out: Receiver<Result<Vec<FastqRecord>, Error>>,
-    // Pool channels for recycling empty batch buffers.
-    r1_pool: Sender<Vec<FastqRecord>>,
-    r2_pool: Sender<Vec<FastqRecord>>,
+    // Pools for recycling empty batch buffers.
+    r1_pool: BatchPool,
+    r2_pool: BatchPool,
     // Current batch state and independent indices for each stream.
     current_batch_r1: Option<Vec<FastqRecord>>,
     current_batch_r2: Option<Vec<FastqRecord>>,
@@ -287,6 +561,15 @@ pub struct PairedFastqReader {
     current_index_r2: usize,
     // Join handles for background threads.
     handles: Vec<JoinHandle<Result<(), Error>>>,
+    // Bytes read from the R1 and R2 files by the threads.
+    bytes_read: [Arc<AtomicU64>; 2],
+    #[cfg(feature = "pbar")]
+    pbar: Option<ProgressBar>,
+    #[cfg(feature = "tracing")]
+    throughput: Option<Arc<ThroughputLogger>>,
+    /// Pairs read and not yet added to `throughput`.
+    #[cfg(feature = "tracing")]
+    n_unlogged: u64,
 }
 
 impl PairedFastqReader {
@@ -294,14 +577,14 @@ impl PairedFastqReader {
         out_r: &mut FastqRecord,
         current_batch: &mut Option<Vec<FastqRecord>>,
         current_index: &mut usize,
-        pool: &Sender<Vec<FastqRecord>>,
+        pool: &ObjectPool<Vec<FastqRecord>>,
         r_out: &Receiver<Result<Vec<FastqRecord>, Error>>,
     ) -> ProcessResult {
         // If no current batch or the current batch is exhausted…
         if current_batch.is_none() || *current_index >= current_batch.as_ref().unwrap().len() {
             // Recycle an old batch, if available.
             if let Some(batch) = current_batch.take() {
-                let _ = pool.send(batch);
+                pool.put(batch);
             }
             // Try to receive a new batch nonblocking.
             match r_out.try_recv() {
@@ -396,15 +679,49 @@ impl PairedFastqReader {
             thread::sleep(Duration::from_millis(1));
         }
 
-        match (proc_res1, proc_res2) {
+        let res = match (proc_res1, proc_res2) {
             (ProcessResult::Done(r1_res), ProcessResult::Done(r2_res)) => (r1_res, r2_res),
             _ => panic!("Unexpected state in read()."),
+        };
+
+        // on the first record of a batch of R1, or at the end.
+        #[cfg(feature = "pbar")]
+        if let Some(pbar) = &self.pbar
+            && (self.current_index_r1 == 1 || res.0.is_none())
+        {
+            let (r1, r2) = self.progress();
+            pbar.set_position(r1 + r2);
+        }
+        // counted in a batch, on its first record or at the end.
+        #[cfg(feature = "tracing")]
+        if let Some(throughput) = &self.throughput {
+            if self.current_index_r1 == 1 || res.0.is_none() {
+                throughput.add(std::mem::take(&mut self.n_unlogged));
+            }
+            if res.0.is_some() {
+                self.n_unlogged += 1;
+            }
         }
+
+        res
+    }
+
+    /// Bytes read so far from the R1 and R2 files, compressed if gzipped, as of
+    /// the last batches sent by the reader threads.
+    ///
+    /// The threads read ahead of the records returned by [`Self::read`], so
+    /// these are the sizes of the files before the last records are read.
+    pub fn progress(&self) -> (u64, u64) {
+        let [r1, r2] = &self.bytes_read;
+        (
+            r1.load(atomic::Ordering::Relaxed),
+            r2.load(atomic::Ordering::Relaxed),
+        )
     }
 
     /// Shuts down the background worker threads by joining them.
     /// Returns an error if any thread panicked or returned an error.
-    pub fn join(self) -> Result<(), Error> {
+    pub fn join(self) -> Result<(), crate::errors::Error> {
         for handle in self.handles {
             handle
                 .join()
@@ -416,65 +733,102 @@ impl PairedFastqReader {
 
 #[cfg(test)]
 mod tests {
-    use std::env::current_dir;
-
     use super::*;
-
-    const R1: &str = "/home/eck/workspace/crackle-kit/NA12891_R1.fastq.gz";
-    const R2: &str = "/home/eck/workspace/crackle-kit/NA12891_R2.fastq.gz";
-
-    const GF_R1: &str = "/home/eck/workspace/crackle-kit/gf_R1.fastq.gz";
+    use crate::test_utils::TestFastqPair;
 
     #[test]
     fn test_reader() -> Result<(), Box<dyn std::error::Error>> {
-        let mut handle = PairedFastqReaderConfig::new(R1, R2);
+        let dir = tempfile::tempdir()?;
+        // more reads than a batch, so batches are recycled.
+        let n_pairs = 3000;
+        let (r1, r2) = TestFastqPair::new()
+            .add_pairs(n_pairs, 100)
+            .build(dir.path(), true)?;
+
+        let handle = PairedFastqReaderConfig::new(r1, r2);
 
         // handle.pool_capacity = 512;
 
         let mut handle = handle.run()?;
-        
+
+
         let mut record1 = FastqRecord::new();
         let mut record2 = FastqRecord::new();
 
         let mut r1_bases = 0;
         let mut r2_bases = 0;
-        for _ in 0..10000000 {
+        let mut n_read = 0;
+        loop {
             match handle.read(&mut record1, &mut record2) {
                 (Some(r1), Some(r2)) => {
                     r1?;
                     r2?;
                 }
-                _ => {}
+                (None, None) => break,
+                _ => panic!("R1 and R2 ended at different reads"),
             };
+            assert_eq!(record1.header(), format!("@pair{} 1:N:0:1", n_read));
+            assert_eq!(record2.header(), format!("@pair{} 2:N:0:1", n_read));
             r1_bases += record1.sequence().len();
             r2_bases += record2.sequence().len();
+            n_read += 1;
         }
 
-        eprintln!("r1_bases={r1_bases}");
-        eprintln!("r2_bases={r2_bases}");
+        assert_eq!(n_read, n_pairs);
+        assert_eq!(r1_bases, n_pairs * 100);
+        assert_eq!(r2_bases, n_pairs * 100);
 
-        // handle.join()
+        handle.join()?;
+
+        Ok(())
+    }
+
+    #[cfg(feature = "tracing")]
+    #[test]
+    fn test_reader_throughput() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = tempfile::tempdir()?;
+        let (r1, r2) = TestFastqPair::new()
+            .add_pairs(2500, 20)
+            .build(dir.path(), false)?;
+        let logger = Arc::new(ThroughputLogger::new("pairs", tracing::Level::DEBUG));
+
+        let mut handle = PairedFastqReaderConfig::new(r1, r2)
+            .with_throughput(Arc::clone(&logger))
+            .run()?;
+        let (mut record1, mut record2) = (FastqRecord::new(), FastqRecord::new());
+        let mut n_read = 0;
+        while let (Some(r1), Some(r2)) = handle.read(&mut record1, &mut record2) {
+            r1?;
+            r2?;
+            // added per batch, as the next one is taken.
+            assert_eq!(logger.total(), n_read / 1024 * 1024);
+            n_read += 1;
+        }
+        handle.join()?;
+        assert_eq!(logger.total(), 2500);
 
         Ok(())
     }
 
     #[test]
     fn test_spawn_reader_thread() -> Result<(), Error> {
+        let dir = tempfile::tempdir()?;
+        // fewer reads than the batch, so the reader reaches EOF with a single buffer.
+        let (r1, _) = TestFastqPair::new().add_pairs(5, 20).build(dir.path(), false)?;
+
         // Create the sender/receiver pair for batches from the reader thread.
         let (sender, receiver) = bounded::<Result<Vec<FastqRecord>, Error>>(10);
 
-        // Create the pool channels for recycling batch buffers.
-        // We intentionally keep pool_sender in scope so that the pool channel
-        // remains connected.
-        let (pool_sender, pool_receiver) = bounded::<Vec<FastqRecord>>(10);
-
-        // Prepopulate the pool with one batch.
+        // Prepopulate the pool for recycling batch buffers with one batch.
+        let pool = Arc::new(ObjectPool::new(10));
         let batch_size = 10; // adjust if necessary
         let initial_batch: Vec<FastqRecord> = (0..batch_size).map(|_| FastqRecord::new()).collect();
-        pool_sender.send(initial_batch)?;
+        pool.put(initial_batch);
 
         // Spawn the reader thread.
-        let handle = spawn_reader_thread(R1, sender, pool_receiver)?;
+        let bytes_read = Arc::new(AtomicU64::new(0));
+        let handle =
+            spawn_reader_thread(&r1, sender, pool.clone(), batch_size, bytes_read.clone())?;
 
         // Attempt to receive a batch from the reader thread.
         // This call will block until the reader thread sends a batch or errors.
@@ -484,6 +838,9 @@ mod tests {
             Ok(Ok(batch)) => {
                 // If a batch was received, assert that it is nonempty (if the file has data).
                 assert!(!batch.is_empty(), "Received an empty batch");
+                assert!(batch[..5].iter().all(|r| r.sequence().len() == 20));
+                // the slot where EOF was hit is left empty.
+                assert!(batch[5].is_empty());
                 println!("Received batch with {} records", batch.len());
             }
             Ok(Err(e)) => {
@@ -508,18 +865,120 @@ mod tests {
             }
         }
 
-        // Keep pool_sender alive until after join.
-        // (It will be dropped here at the end of the test.)
+        assert_eq!(
+            bytes_read.load(atomic::Ordering::Relaxed),
+            std::fs::metadata(&r1)?.len()
+        );
+
+        // the prepopulated batch was used, none was allocated.
+        assert_eq!(pool.stats().n_reused, 1);
+        assert_eq!(pool.stats().n_created, 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_mean_quality_and_write_to() -> Result<(), Error> {
+        let text = b"@r1 1:N\nACGT\n+\nI5+#\n";
+        let mut record = FastqRecord::new();
+        record.load_record(&text[..])?;
+
+        // 40, 20, 10, 2
+        assert_eq!(record.mean_quality(), Some(18.0));
+
+        let mut out = vec![];
+        record.write_to(&mut out)?;
+        assert_eq!(out, text);
+
+        record.clear();
+        record.load_record(&b"@empty\n\n+\n\n"[..])?;
+        assert_eq!(record.mean_quality(), None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_long_reads() -> Result<(), Error> {
+        // reads of well over the 8 KiB the buffer of a record starts with,
+        // between short ones which reuse the grown buffer.
+        let dir = tempfile::tempdir()?;
+        let (r1, r2) = TestFastqPair::new()
+            .add_pairs(2, 150)
+            .add_pairs(5, 120_000)
+            .add_pairs(2, 150)
+            .build(dir.path(), true)?;
+
+        let mut reader = PairedFastqReaderConfig::new(r1, r2).run()?;
+        let (mut rec1, mut rec2) = (FastqRecord::new(), FastqRecord::new());
+        let mut lens = vec![];
+        while let (Some(res1), Some(res2)) = reader.read(&mut rec1, &mut rec2) {
+            res1?;
+            res2?;
+            assert_eq!(rec1.sequence_bytes().len(), rec1.quality_bytes().len());
+            assert_eq!(rec2.sequence_bytes(), rec1.sequence_bytes());
+            assert_eq!(rec1.mean_quality(), Some(30.0));
+            lens.push(rec1.sequence_bytes().len());
+        }
+        reader.join()?;
+        let long = [120_000; 5];
+        assert_eq!(lens, [&[150, 150][..], &long, &[150, 150]].concat());
+
+        let mut out = vec![];
+        rec1.clear();
+        let text = format!(
+            "@long\n{}\n+\n{}\n",
+            "ACGT".repeat(25_000),
+            "I".repeat(100_000)
+        );
+        rec1.load_record(text.as_bytes())?;
+        assert_eq!(rec1.read_id_bytes(), b"long");
+        rec1.write_to(&mut out)?;
+        assert_eq!(out, text.as_bytes());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_fallible_views() -> Result<(), Error> {
+        let mut record = FastqRecord::new();
+        record.load_record(&b"@r1 \xff\xfe\nACGT\n+\nIIII\n"[..])?;
+
+        let err = record.try_header().unwrap_err();
+        assert!(
+            err.to_string().contains("Invalid UTF-8 in header"),
+            "{}",
+            err
+        );
+        assert_eq!(record.header_bytes(), b"@r1 \xff\xfe");
+        assert_eq!(record.read_id_bytes(), b"r1");
+        assert_eq!(record.try_sequence()?, "ACGT");
+        assert_eq!(record.plus_bytes(), b"+");
+        assert_eq!(record.quality(), "IIII");
+
+        // a cleared or partly loaded record has no bogus views.
+        record.clear();
+        assert!(record.is_empty());
+        assert_eq!(record.header(), "");
+        assert_eq!(record.sequence(), "");
+        assert_eq!(record.plus(), "");
+        assert_eq!(record.quality(), "");
+        assert_eq!(record.mean_quality(), None);
+        record.push_line_from(&b"@r2\nAC\n"[..])?;
+        assert_eq!(record.header(), "@r2");
+        assert!(record.sequence_bytes().is_empty() && record.quality_bytes().is_empty());
+
         Ok(())
     }
 
     #[test]
     fn test_fastq_reader_gz() -> Result<(), Error> {
-        // Create a FastqReader from the path.
-        // eprintln!("{}", current_dir()?.display());
-        // eprintln!("{}", env!("CARGO_MANIFEST_DIR"));
+        let dir = tempfile::tempdir()?;
+        let (gz_r1, _) = TestFastqPair::new()
+            .add_pair("r1", (b"ACGTN", &[40, 30, 20, 10, 2]), (b"TTTTT", &[30; 5]))
+            .build(dir.path(), true)?;
 
-        let mut reader = FastqReader::from_path(GF_R1)?;
+        // Create a FastqReader from the path.
+        let mut reader = FastqReader::from_path(gz_r1)?;
 
         let mut record = FastqRecord::new();
 
@@ -528,12 +987,126 @@ mod tests {
         // eprintln!("{}", String::from_utf8_lossy(&record.buf));
         // eprintln!("{}", String::from_utf8(record.buf.to_vec())?);
 
-        eprintln!("{}", record.header());
-        eprintln!("{}", record.sequence());
-        eprintln!("{}", record.plus());
-        eprintln!("{}", record.quality());
+        assert_eq!(record.header(), "@r1 1:N:0:1");
+        assert_eq!(record.sequence(), "ACGTN");
+        assert_eq!(record.plus(), "+");
+        assert_eq!(record.quality(), "I?5+#");
+
+        assert!(!record.load_record(&mut reader)?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_bytes_read() -> Result<(), Error> {
+        let dir = tempfile::tempdir()?;
+        for gz in [false, true] {
+            let (r1, r2) = TestFastqPair::new()
+                .add_pairs(2000, 100)
+                .build(dir.path(), gz)?;
+            let file_size = std::fs::metadata(&r1)?.len();
+
+            let mut reader = FastqReader::from_path(&r1)?;
+            let mut record = FastqRecord::new();
+            assert!(record.load_record(&mut reader)?);
+            assert!(reader.bytes_read() > 0);
+            assert!(reader.bytes_read() < file_size);
+            while record.load_record(&mut reader)? {}
+            assert_eq!(reader.bytes_read(), file_size);
+
+            let mut reader = PairedFastqReaderConfig::new(&r1, &r2).run()?;
+            let (mut rec1, mut rec2) = (FastqRecord::new(), FastqRecord::new());
+            while let (Some(res1), Some(res2)) = reader.read(&mut rec1, &mut rec2) {
+                res1?;
+                res2?;
+            }
+            assert_eq!(
+                reader.progress(),
+                (file_size, std::fs::metadata(&r2)?.len())
+            );
+            reader.join()?;
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    #[cfg(feature = "pbar")]
+    fn test_progress_bar() -> Result<(), Error> {
+        let dir = tempfile::tempdir()?;
+        let (r1, r2) = TestFastqPair::new()
+            .add_pairs(3000, 100)
+            .build(dir.path(), true)?;
+        let total = std::fs::metadata(&r1)?.len() + std::fs::metadata(&r2)?.len();
+
+        let pbar = ProgressBar::hidden();
+        let mut reader = PairedFastqReaderConfig::new(&r1, &r2)
+            .with_progress_bar(pbar.clone())
+            .run()?;
+        assert_eq!(pbar.length(), Some(total));
+
+        let (mut rec1, mut rec2) = (FastqRecord::new(), FastqRecord::new());
+        while let (Some(res1), Some(res2)) = reader.read(&mut rec1, &mut rec2) {
+            res1?;
+            res2?;
+        }
+        assert_eq!(pbar.position(), total);
+        reader.join()?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_umi() -> Result<(), Error> {
+        let text = b"@A01:1:FC:1:1101:1000:2000:ACGTACGT 1:N:0:1\nGGNATTTACG\n+\nIIII5555##\n";
+        let mut record = FastqRecord::new();
+        record.load_record(&text[..])?;
+
+        assert_eq!(
+            record.read_id_bytes(),
+            b"A01:1:FC:1:1101:1000:2000:ACGTACGT"
+        );
+        assert_eq!(
+            record.umi(UmiScheme::IlluminaReadId),
+            Some(&b"ACGTACGT"[..])
+        );
+        assert_eq!(record.umi(UmiScheme::ReadIdSuffix), None);
+        assert_eq!(record.umi(UmiScheme::SequencePrefix(4)), Some(&b"GGNA"[..]));
+        assert_eq!(record.umi(UmiScheme::SequencePrefix(11)), None);
+
+        #[cfg(feature = "bam")]
+        {
+            use crate::bam::tags::RecordUmiExt;
+
+            // the same read, aligned to the reverse strand.
+            let mut bam_record = rust_htslib::bam::Record::new();
+            bam_record.set(record.read_id_bytes(), None, b"CGTAAATNCC", &[2; 10]);
+            bam_record.set_reverse();
+            for scheme in [
+                UmiScheme::IlluminaReadId,
+                UmiScheme::ReadIdSuffix,
+                UmiScheme::SequencePrefix(4),
+                UmiScheme::SequencePrefix(11),
+            ] {
+                assert_eq!(bam_record.umi(scheme).as_deref(), record.umi(scheme));
+            }
+        }
 
-        // eprintln!("{record:?}");
+        record.trim_umi_prefix(4);
+        assert_eq!(record.sequence(), "TTTACG");
+        assert_eq!(record.quality(), "5555##");
+        let mut out = vec![];
+        record.write_to(&mut out)?;
+        assert_eq!(
+            out,
+            b"@A01:1:FC:1:1101:1000:2000:ACGTACGT 1:N:0:1\nTTTACG\n+\n5555##\n"
+        );
+
+        record.load_record(&b"@read1_AAGGT\nAC\n+\nII\n"[..])?;
+        assert_eq!(record.umi(UmiScheme::ReadIdSuffix), Some(&b"AAGGT"[..]));
+        assert_eq!(record.umi(UmiScheme::IlluminaReadId), None);
+        record.trim_umi_prefix(2);
+        assert!(record.sequence().is_empty() && record.quality().is_empty());
 
         Ok(())
     }
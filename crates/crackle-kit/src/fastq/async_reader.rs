@@ -0,0 +1,133 @@
+//! [`PairedFastqReader`] for tokio tasks.
+
+use anyhow::{Error, bail};
+use tokio::{sync::mpsc, task::JoinHandle};
+
+use crate::fastq::{FastqRecord, PairedFastqReader, PairedFastqReaderConfig};
+
+/// Pairs per message sent to the async side.
+const BATCH_SIZE: usize = 1024;
+/// Messages the channel can hold before the reader waits for the consumer.
+const CHANNEL_CAPACITY: usize = 16;
+
+type PairBatch = Vec<(FastqRecord, FastqRecord)>;
+
+/// Async handle of a [`PairedFastqReader`].
+///
+/// The reader runs on tokio's blocking pool and forwards batches of pairs over a
+/// bounded channel, so it only reads ahead by [`CHANNEL_CAPACITY`] batches of the
+/// consumer. Dropping the handle stops the reader at its next batch.
+pub struct AsyncPairedFastqReader {
+    rx: mpsc::Receiver<Result<PairBatch, Error>>,
+    current: std::vec::IntoIter<(FastqRecord, FastqRecord)>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl AsyncPairedFastqReader {
+    /// Start the reader threads of `config`.
+    ///
+    /// # Panics
+    /// Outside of a tokio runtime.
+    pub fn new(config: PairedFastqReaderConfig) -> Result<Self, crate::errors::Error> {
+        let reader = config.run()?;
+        let (tx, rx) = mpsc::channel(CHANNEL_CAPACITY);
+        let handle = tokio::task::spawn_blocking(move || forward_pairs(reader, tx));
+
+        Ok(Self {
+            rx,
+            current: Vec::new().into_iter(),
+            handle: Some(handle),
+        })
+    }
+
+    /// The next pair, `None` at the end of both files.
+    ///
+    /// Files ending at different reads are an error.
+    pub async fn read_pair(
+        &mut self,
+    ) -> Result<Option<(FastqRecord, FastqRecord)>, crate::errors::Error> {
+        loop {
+            if let Some(pair) = self.current.next() {
+                return Ok(Some(pair));
+            }
+
+            match self.rx.recv().await {
+                Some(batch) => self.current = batch?.into_iter(),
+                None => {
+                    // the reader is done; surface a panic of its task.
+                    if let Some(handle) = self.handle.take() {
+                        handle.await.map_err(Error::from)?;
+                    }
+                    return Ok(None);
+                }
+            }
+        }
+    }
+}
+
+/// Read `reader` to the end, sending batches of pairs until `tx` is closed.
+fn forward_pairs(mut reader: PairedFastqReader, tx: mpsc::Sender<Result<PairBatch, Error>>) {
+    let res = (|| {
+        let (mut r1, mut r2) = (FastqRecord::new(), FastqRecord::new());
+        let mut batch = Vec::with_capacity(BATCH_SIZE);
+
+        loop {
+            match reader.read(&mut r1, &mut r2) {
+                (Some(res1), Some(res2)) => {
+                    res1?;
+                    res2?;
+                    batch.push((std::mem::take(&mut r1), std::mem::take(&mut r2)));
+                }
+                (None, None) => break,
+                (Some(Err(e)), _) | (_, Some(Err(e))) => return Err(e),
+                _ => bail!("R1 and R2 ended at different reads"),
+            }
+
+            if batch.len() == BATCH_SIZE {
+                let full = std::mem::replace(&mut batch, Vec::with_capacity(BATCH_SIZE));
+                if tx.blocking_send(Ok(full)).is_err() {
+                    // the handle was dropped.
+                    return Ok(());
+                }
+            }
+        }
+
+        if !batch.is_empty() && tx.blocking_send(Ok(batch)).is_err() {
+            return Ok(());
+        }
+        Ok(reader.join()?)
+    })();
+
+    if let Err(e) = res {
+        let _ = tx.blocking_send(Err(e));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::TestFastqPair;
+
+    #[tokio::test]
+    async fn test_read_pair() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = tempfile::tempdir()?;
+        // more pairs than a batch.
+        let n_pairs = 2_500;
+        let (r1, r2) = TestFastqPair::new()
+            .add_pairs(n_pairs, 50)
+            .build(dir.path(), true)?;
+
+        let mut reader = AsyncPairedFastqReader::new(PairedFastqReaderConfig::new(r1, r2))?;
+        let mut n_read = 0;
+        while let Some((r1, r2)) = reader.read_pair().await? {
+            assert_eq!(r1.header(), format!("@pair{} 1:N:0:1", n_read));
+            assert_eq!(r2.header(), format!("@pair{} 2:N:0:1", n_read));
+            n_read += 1;
+        }
+
+        assert_eq!(n_read, n_pairs);
+        assert!(reader.read_pair().await?.is_none());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,582 @@
+//! Demultiplexing of paired fastq files by sample index, i.e. the i7 and, if dual
+//! indexed, the i5 barcode of each pair.
+
+use std::{
+    collections::HashMap,
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Error, anyhow, bail};
+
+use crate::{
+    data::bases::BaseArrShort,
+    errors::Error as CrateError,
+    fastq::{FastqRecord, FastqWriter, PairedFastqReader},
+    table::TableWriter,
+    utils::textio::read_non_comment_lines,
+};
+
+/// Undetermined barcodes kept by [`Demultiplexer::new`].
+pub const DEFAULT_TOP_UNDETERMINED: usize = 20;
+
+/// Name of the outputs of the pairs matching no sample.
+pub const UNDETERMINED: &str = "Undetermined";
+
+/// A sample of a sample sheet.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Sample {
+    pub name: String,
+    pub i7: String,
+    pub i5: Option<String>,
+}
+
+impl Sample {
+    pub fn new(name: impl Into<String>, i7: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            i7: i7.into(),
+            i5: None,
+        }
+    }
+
+    pub fn with_i5(mut self, i5: impl Into<String>) -> Self {
+        self.i5 = Some(i5.into());
+        self
+    }
+}
+
+/// Read a sample sheet of `name\ti7` or `name\ti7\ti5` lines.
+///
+/// Blank lines and lines starting with `#` are skipped.
+pub fn read_sample_sheet(path: impl AsRef<Path>) -> Result<Vec<Sample>, CrateError> {
+    let mut lines = read_non_comment_lines(path, &["#"])?;
+
+    let mut samples = vec![];
+    while let Some(line) = lines.next() {
+        let (_, line) = line?;
+        let fields = line.trim_end().split('\t').collect::<Vec<_>>();
+        let sample = match fields[..] {
+            [name, i7] => Sample::new(name, i7),
+            [name, i7, i5] => Sample::new(name, i7).with_i5(i5),
+            _ => {
+                return Err(lines.line_reader().parse_error(
+                    "sample sheet line",
+                    anyhow!("expected 2 or 3 fields, got {}", fields.len()),
+                ));
+            }
+        };
+        samples.push(sample);
+    }
+
+    Ok(samples)
+}
+
+/// Where the index of a pair is read.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IndexSource {
+    /// Last `:`-delimited field of the R1 header, `i7` or `i7+i5`, as written by
+    /// bcl2fastq, e.g. `@A01:1:FC:1:1101:1000:2000 1:N:0:ACGTACGT+GGTTCCAA`.
+    Header,
+    /// The first bases of R1 for the i7, and of R2 for the i5, trimmed from the
+    /// written reads.
+    SequencePrefix(usize),
+}
+
+/// The sample of a pair.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Assignment {
+    /// Index of the sample in the sample sheet.
+    Sample(usize),
+    /// No sample, or more than one, is within the mismatches of the index.
+    Undetermined,
+}
+
+/// Pairs written by [`Demultiplexer::run`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DemuxReport {
+    /// Pairs per sample, in sample sheet order.
+    pub sample_counts: Vec<(String, u64)>,
+    pub n_undetermined: u64,
+    /// Most frequent indexes of the undetermined pairs, as `i7` or `i7+i5`, most
+    /// frequent first.
+    pub top_undetermined: Vec<(String, u64)>,
+}
+
+impl DemuxReport {
+    /// Write the pairs of each sample, then of the undetermined pairs, as a
+    /// `sample\tn_pairs` table.
+    pub fn write_tsv(&self, w: impl Write) -> Result<(), Error> {
+        let mut tw = TableWriter::new(w, &["sample", "n_pairs"])?;
+        for (name, count) in &self.sample_counts {
+            tw.write_row([name.clone(), count.to_string()])?;
+        }
+        tw.write_row([UNDETERMINED.to_string(), self.n_undetermined.to_string()])?;
+        tw.into_inner()?;
+        Ok(())
+    }
+}
+
+/// Routes pairs to their sample by index, allowing mismatches.
+pub struct Demultiplexer {
+    samples: Vec<Sample>,
+    barcodes: Vec<(BaseArrShort, Option<BaseArrShort>)>,
+    source: IndexSource,
+    max_mismatches: u32,
+    n_top_undetermined: usize,
+}
+
+impl Demultiplexer {
+    /// Fails if the samples mix single and dual indexes, if their indexes differ
+    /// in length, or if two samples are within `max_mismatches` of each other on
+    /// all their indexes, as a pair could then match both.
+    pub fn new(
+        samples: Vec<Sample>,
+        source: IndexSource,
+        max_mismatches: u32,
+    ) -> Result<Self, Error> {
+        let Some(first) = samples.first() else {
+            bail!("No samples to demultiplex");
+        };
+
+        let mut barcodes = vec![];
+        for sample in &samples {
+            if sample.name == UNDETERMINED {
+                bail!("Sample name {} is reserved", UNDETERMINED);
+            }
+            if sample.i7.len() != first.i7.len()
+                || sample.i5.as_ref().map(|s| s.len()) != first.i5.as_ref().map(|s| s.len())
+            {
+                bail!(
+                    "Indexes of samples {} and {} differ in length",
+                    first.name,
+                    sample.name
+                );
+            }
+
+            let parse = |index: &str| {
+                BaseArrShort::from_bytes(index.as_bytes()).map_err(|e| {
+                    anyhow!("Invalid index {} of sample {}: {}", index, sample.name, e)
+                })
+            };
+            barcodes.push((
+                parse(&sample.i7)?,
+                sample.i5.as_deref().map(parse).transpose()?,
+            ));
+        }
+
+        for (i, (a, a_bc)) in samples.iter().zip(&barcodes).enumerate() {
+            for (b, b_bc) in samples[i + 1..].iter().zip(&barcodes[i + 1..]) {
+                if a.name == b.name {
+                    bail!("Sample {} is in the sample sheet twice", a.name);
+                }
+
+                let i5_within = match (&a_bc.1, &b_bc.1) {
+                    (Some(x), Some(y)) => x.hamming_within(y, max_mismatches),
+                    _ => true,
+                };
+                if a_bc.0.hamming_within(&b_bc.0, max_mismatches) && i5_within {
+                    bail!(
+                        "Indexes of samples {} and {} are within {} mismatches",
+                        a.name,
+                        b.name,
+                        max_mismatches
+                    );
+                }
+            }
+        }
+
+        Ok(Self {
+            samples,
+            barcodes,
+            source,
+            max_mismatches,
+            n_top_undetermined: DEFAULT_TOP_UNDETERMINED,
+        })
+    }
+
+    /// Keep the `n` most frequent undetermined indexes in the report.
+    pub fn with_top_undetermined(mut self, n: usize) -> Self {
+        self.n_top_undetermined = n;
+        self
+    }
+
+    pub fn samples(&self) -> &[Sample] {
+        &self.samples
+    }
+
+    fn is_dual(&self) -> bool {
+        self.barcodes[0].1.is_some()
+    }
+
+    /// Sample of a pair.
+    pub fn assign(&self, r1: &FastqRecord, r2: &FastqRecord) -> Assignment {
+        let mut index = vec![];
+        self.read_index(r1, r2, &mut index);
+        self.assign_index(&index)
+    }
+
+    /// Write the index of a pair to `index`, as `i7` or `i7+i5`.
+    fn read_index(&self, r1: &FastqRecord, r2: &FastqRecord, index: &mut Vec<u8>) {
+        index.clear();
+        match self.source {
+            IndexSource::Header => {
+                let header = r1.header_bytes();
+                let start = header.iter().rposition(|b| *b == b':').map_or(0, |i| i + 1);
+                index.extend_from_slice(&header[start..]);
+            }
+            IndexSource::SequencePrefix(len) => {
+                let seq = r1.sequence_bytes();
+                index.extend_from_slice(&seq[..len.min(seq.len())]);
+                if self.is_dual() {
+                    let seq = r2.sequence_bytes();
+                    index.push(b'+');
+                    index.extend_from_slice(&seq[..len.min(seq.len())]);
+                }
+            }
+        }
+    }
+
+    /// Sample of an index of [`Self::read_index`]. An i5 is ignored if the
+    /// samples are single indexed.
+    fn assign_index(&self, index: &[u8]) -> Assignment {
+        let mut parts = index.splitn(2, |b| *b == b'+');
+        let Ok(i7) = BaseArrShort::from_bytes(parts.next().unwrap()) else {
+            return Assignment::Undetermined;
+        };
+        let i5 = if self.is_dual() {
+            match parts.next().map(BaseArrShort::from_bytes) {
+                Some(Ok(i5)) => Some(i5),
+                _ => return Assignment::Undetermined,
+            }
+        } else {
+            None
+        };
+
+        let mut hit = None;
+        for (i, (s_i7, s_i5)) in self.barcodes.iter().enumerate() {
+            let i5_within = match (s_i5, &i5) {
+                (Some(x), Some(y)) => x.hamming_within(y, self.max_mismatches),
+                _ => true,
+            };
+            if i5_within && s_i7.hamming_within(&i7, self.max_mismatches) {
+                if hit.is_some() {
+                    return Assignment::Undetermined;
+                }
+                hit = Some(i);
+            }
+        }
+
+        hit.map_or(Assignment::Undetermined, Assignment::Sample)
+    }
+
+    /// Write the pairs of `reader` to gzipped `{sample}_R1.fastq.gz` and
+    /// `{sample}_R2.fastq.gz` files in `out_dir`, and the pairs of no sample to
+    /// `Undetermined_R1.fastq.gz` and `Undetermined_R2.fastq.gz`.
+    pub fn run(
+        &self,
+        reader: &mut PairedFastqReader,
+        out_dir: impl AsRef<Path>,
+    ) -> Result<DemuxReport, CrateError> {
+        let out_path = |name: &str, read: u8| -> PathBuf {
+            out_dir
+                .as_ref()
+                .join(format!("{}_R{}.fastq.gz", name, read))
+        };
+        let names = self
+            .samples
+            .iter()
+            .map(|s| s.name.as_str())
+            .chain([UNDETERMINED])
+            .collect::<Vec<_>>();
+        let write_error = |out: usize, read: u8| {
+            let path = out_path(names[out], read);
+            move |e| CrateError::io("write", path, e)
+        };
+        let mut writers = names
+            .iter()
+            .map(|&name| {
+                let create = |path: PathBuf| {
+                    FastqWriter::from_path(&path).map_err(|e| CrateError::io("create", path, e))
+                };
+                Ok([create(out_path(name, 1))?, create(out_path(name, 2))?])
+            })
+            .collect::<Result<Vec<_>, CrateError>>()?;
+
+        let mut counts = vec![0; writers.len()];
+        let mut undetermined = HashMap::<Vec<u8>, u64>::new();
+        let mut index = vec![];
+        let mut r1 = FastqRecord::new();
+        let mut r2 = FastqRecord::new();
+        loop {
+            match reader.read(&mut r1, &mut r2) {
+                (Some(res1), Some(res2)) => {
+                    res1?;
+                    res2?;
+                }
+                (None, None) => break,
+                _ => return Err(anyhow!("R1 and R2 have different numbers of reads").into()),
+            }
+
+            self.read_index(&r1, &r2, &mut index);
+            let out = match self.assign_index(&index) {
+                Assignment::Sample(i) => i,
+                Assignment::Undetermined => {
+                    match undetermined.get_mut(index.as_slice()) {
+                        Some(count) => *count += 1,
+                        None => {
+                            undetermined.insert(index.clone(), 1);
+                        }
+                    }
+                    self.samples.len()
+                }
+            };
+
+            if let IndexSource::SequencePrefix(len) = self.source {
+                r1.trim_umi_prefix(len.min(r1.sequence_bytes().len()));
+                if self.is_dual() {
+                    r2.trim_umi_prefix(len.min(r2.sequence_bytes().len()));
+                }
+            }
+            let [w1, w2] = &mut writers[out];
+            w1.write(&r1).map_err(write_error(out, 1))?;
+            w2.write(&r2).map_err(write_error(out, 2))?;
+            counts[out] += 1;
+        }
+
+        for (out, [w1, w2]) in writers.into_iter().enumerate() {
+            w1.finish().map_err(write_error(out, 1))?;
+            w2.finish().map_err(write_error(out, 2))?;
+        }
+
+        let mut top_undetermined = undetermined
+            .into_iter()
+            .map(|(index, count)| (String::from_utf8_lossy(&index).into_owned(), count))
+            .collect::<Vec<_>>();
+        top_undetermined.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        top_undetermined.truncate(self.n_top_undetermined);
+
+        Ok(DemuxReport {
+            sample_counts: self
+                .samples
+                .iter()
+                .zip(&counts)
+                .map(|(s, c)| (s.name.clone(), *c))
+                .collect(),
+            n_undetermined: counts[self.samples.len()],
+            top_undetermined,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::{
+        fastq::{FastqReader, PairedFastqReaderConfig},
+        test_utils::TestFastqPair,
+    };
+
+    fn read_names(path: impl AsRef<Path>) -> Result<Vec<String>, Error> {
+        let mut reader = FastqReader::from_path(path)?;
+        let mut record = FastqRecord::new();
+        let mut names = vec![];
+        while record.load_record(&mut reader)? {
+            names.push(String::from_utf8(record.read_id_bytes().to_vec())?);
+        }
+        Ok(names)
+    }
+
+    #[test]
+    fn test_demux_header() -> Result<(), Error> {
+        let dir = tempfile::tempdir()?;
+        let read = (&b"ACGTACGTAC"[..], &[30; 10][..]);
+        let (r1, r2) = TestFastqPair::new()
+            .add_indexed_pair("exact_a", "AAAACCCC+GGGGTTTT", read, read)
+            .add_indexed_pair("one_mismatch_a", "AAAACCCA+GGGGTTTT", read, read)
+            .add_indexed_pair("exact_b", "CCCCGGGG+TTTTAAAA", read, read)
+            .add_indexed_pair("one_mismatch_b", "CCCCGGGG+TTTTAAAT", read, read)
+            .add_indexed_pair("two_mismatches", "CCCCGGGG+TTTTAATT", read, read)
+            .add_indexed_pair("other", "TTTTTTTT+AAAAAAAA", read, read)
+            .add_indexed_pair("other2", "TTTTTTTT+AAAAAAAA", read, read)
+            .add_indexed_pair("no_index", "1", read, read)
+            .build(dir.path(), true)?;
+
+        let samples = vec![
+            Sample::new("a", "AAAACCCC").with_i5("GGGGTTTT"),
+            Sample::new("b", "CCCCGGGG").with_i5("TTTTAAAA"),
+        ];
+        let demux = Demultiplexer::new(samples, IndexSource::Header, 1)?.with_top_undetermined(2);
+        let mut reader = PairedFastqReaderConfig::new(r1, r2).run()?;
+        let report = demux.run(&mut reader, dir.path())?;
+        reader.join()?;
+
+        assert_eq!(
+            report,
+            DemuxReport {
+                sample_counts: vec![("a".to_string(), 2), ("b".to_string(), 2)],
+                n_undetermined: 4,
+                top_undetermined: vec![("TTTTTTTT+AAAAAAAA".to_string(), 2), ("1".to_string(), 1)],
+            }
+        );
+        for read in [1, 2] {
+            let names =
+                |name: &str| read_names(dir.path().join(format!("{name}_R{read}.fastq.gz")));
+            assert_eq!(names("a")?, ["exact_a", "one_mismatch_a"]);
+            assert_eq!(names("b")?, ["exact_b", "one_mismatch_b"]);
+            assert_eq!(
+                names(UNDETERMINED)?,
+                ["two_mismatches", "other", "other2", "no_index"]
+            );
+        }
+
+        let mut tsv = vec![];
+        report.write_tsv(&mut tsv)?;
+        assert_eq!(
+            String::from_utf8(tsv)?,
+            "sample\tn_pairs\na\t2\nb\t2\nUndetermined\t4\n"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_demux_sequence_prefix() -> Result<(), Error> {
+        let dir = tempfile::tempdir()?;
+        let qual = [30; 10];
+        let read = |seq: &'static [u8]| (seq, &qual[..seq.len()]);
+        let (r1, r2) = TestFastqPair::new()
+            .add_pair("a", read(b"AAAAAAGGGG"), read(b"TTTT"))
+            .add_pair("b", read(b"AAAACCCCGG"), read(b"TTTT"))
+            // one mismatch from both samples.
+            .add_pair("ambiguous", read(b"AAAAACGGGG"), read(b"TTTT"))
+            .add_pair("b_one_mismatch", read(b"AAATCCGGGG"), read(b"TTTT"))
+            .build(dir.path(), false)?;
+
+        // single indexed, so R2 is not trimmed.
+        let samples = vec![Sample::new("a", "AAAAAA"), Sample::new("b", "AAAACC")];
+        let demux = Demultiplexer::new(samples, IndexSource::SequencePrefix(6), 1)?;
+        let mut reader = PairedFastqReaderConfig::new(r1, r2).run()?;
+        let report = demux.run(&mut reader, dir.path())?;
+        reader.join()?;
+
+        assert_eq!(
+            report.sample_counts,
+            vec![("a".to_string(), 1), ("b".to_string(), 2)]
+        );
+        assert_eq!(report.n_undetermined, 1);
+        assert_eq!(report.top_undetermined, vec![("AAAAAC".to_string(), 1)]);
+
+        let mut reader = FastqReader::from_path(dir.path().join("b_R1.fastq.gz"))?;
+        let mut record = FastqRecord::new();
+        let mut seqs = vec![];
+        while record.load_record(&mut reader)? {
+            seqs.push(record.sequence().to_string());
+        }
+        assert_eq!(seqs, ["CCGG", "GGGG"]);
+        assert_eq!(read_names(dir.path().join("b_R2.fastq.gz"))?.len(), 2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_assign() -> Result<(), Error> {
+        let samples = vec![Sample::new("a", "ACGTACGT"), Sample::new("b", "ACGTACCA")];
+        let demux = Demultiplexer::new(samples, IndexSource::Header, 1)?;
+        assert_eq!(demux.assign_index(b"ACGTACGT"), Assignment::Sample(0));
+        // i5 ignored for single indexed samples.
+        assert_eq!(demux.assign_index(b"ACGTACGT+GGGG"), Assignment::Sample(0));
+        assert_eq!(demux.assign_index(b"ACGTACCT"), Assignment::Undetermined);
+        assert_eq!(demux.assign_index(b"ACGTACCAA"), Assignment::Sample(1));
+        assert_eq!(demux.assign_index(b"ACGTAC-A"), Assignment::Undetermined);
+        assert_eq!(demux.assign_index(b""), Assignment::Undetermined);
+
+        let samples = vec![Sample::new("a", "ACGT").with_i5("GGCC")];
+        let demux = Demultiplexer::new(samples, IndexSource::Header, 0)?;
+        assert_eq!(demux.assign_index(b"ACGT+GGCC"), Assignment::Sample(0));
+        assert_eq!(demux.assign_index(b"ACGT"), Assignment::Undetermined);
+        assert_eq!(demux.assign_index(b"ACGT+GGCA"), Assignment::Undetermined);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_barcode_collision() -> Result<(), Error> {
+        let err = |samples: Vec<Sample>, max_mismatches| {
+            Demultiplexer::new(samples, IndexSource::Header, max_mismatches)
+                .err()
+                .map(|e| e.to_string())
+        };
+
+        let samples = vec![Sample::new("a", "ACGTACGT"), Sample::new("b", "ACGTACCA")];
+        assert_eq!(err(samples.clone(), 1), None);
+        assert_eq!(
+            err(samples, 2).as_deref(),
+            Some("Indexes of samples a and b are within 2 mismatches")
+        );
+
+        // the i5 tells the samples apart.
+        let samples = vec![
+            Sample::new("a", "ACGTACGT").with_i5("AAAAAAAA"),
+            Sample::new("b", "ACGTACGT").with_i5("CCCCCCCC"),
+        ];
+        assert_eq!(err(samples, 2), None);
+
+        let samples = vec![
+            Sample::new("a", "ACGTACGT").with_i5("AAAAAAAA"),
+            Sample::new("b", "CCCCCCCC"),
+        ];
+        assert_eq!(
+            err(samples, 1).as_deref(),
+            Some("Indexes of samples a and b differ in length")
+        );
+        let samples = vec![Sample::new("a", "ACGT"), Sample::new("a", "CCCC")];
+        assert_eq!(
+            err(samples, 1).as_deref(),
+            Some("Sample a is in the sample sheet twice")
+        );
+        assert!(err(vec![Sample::new("a", "ACGU")], 1).is_some());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_sample_sheet() -> Result<(), Error> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("samples.tsv");
+        fs::write(&path, "# name\ti7\ti5\na\tACGT\tGGCC\n\nb\tTTAA\tCCGG\n")?;
+        assert_eq!(
+            read_sample_sheet(&path)?,
+            vec![
+                Sample::new("a", "ACGT").with_i5("GGCC"),
+                Sample::new("b", "TTAA").with_i5("CCGG"),
+            ]
+        );
+
+        fs::write(&path, "a\tACGT\n#b\n\nc\tACGT\tGGCC\tX\n")?;
+        let err = read_sample_sheet(&path).unwrap_err();
+        assert!(
+            matches!(err, CrateError::Parse { line: 4, .. }),
+            "{:?}",
+            err
+        );
+        assert_eq!(
+            format!("{:#}", err),
+            format!(
+                "Invalid sample sheet line at line 4 of {}: expected 2 or 3 fields, got 4",
+                path.display()
+            )
+        );
+
+        let err = read_sample_sheet(dir.path().join("missing.tsv")).unwrap_err();
+        assert!(
+            matches!(err, CrateError::Io { action: "open", .. }),
+            "{:?}",
+            err
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,232 @@
+//! Extraction of the pairs of given reads from paired fastq files, e.g. of the
+//! reads aligned to a region, collected by
+//! [`collect_qnames`](crate::bam::qnames::collect_qnames).
+
+use std::{collections::HashSet, path::Path};
+
+use anyhow::anyhow;
+
+use crate::{
+    errors::Error,
+    fastq::{FastqRecord, FastqWriter, PairedFastqReaderConfig},
+    utils::bloom::BloomFilter,
+};
+
+/// Read names to extract.
+#[derive(Debug, Clone)]
+pub enum QnameSet {
+    Exact(HashSet<Vec<u8>>),
+    /// Names in a [`BloomFilter`], for sets too large to hold, at the cost of
+    /// pairs extracted by false positives.
+    Bloom(BloomFilter),
+}
+
+impl Default for QnameSet {
+    fn default() -> Self {
+        Self::Exact(HashSet::new())
+    }
+}
+
+impl From<HashSet<Vec<u8>>> for QnameSet {
+    fn from(qnames: HashSet<Vec<u8>>) -> Self {
+        Self::Exact(qnames)
+    }
+}
+
+impl QnameSet {
+    /// An empty [`Self::Bloom`] of `expected_items` at a false positive rate of
+    /// `fp_rate`, see [`BloomFilter::new`].
+    pub fn bloom(expected_items: usize, fp_rate: f64) -> Self {
+        Self::Bloom(BloomFilter::new(expected_items, fp_rate))
+    }
+
+    pub fn insert(&mut self, qname: &[u8]) {
+        match self {
+            Self::Exact(qnames) => {
+                if !qnames.contains(qname) {
+                    qnames.insert(qname.to_vec());
+                }
+            }
+            Self::Bloom(filter) => filter.insert(qname),
+        }
+    }
+
+    pub fn contains(&self, qname: &[u8]) -> bool {
+        match self {
+            Self::Exact(qnames) => qnames.contains(qname),
+            Self::Bloom(filter) => filter.contains(qname),
+        }
+    }
+}
+
+/// Counts of [`extract_by_qname`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ExtractStats {
+    pub pairs_read: u64,
+    pub pairs_written: u64,
+}
+
+/// The read id of `record` as the bam qname of the read, without the leading
+/// `@`, the comment, and a `/1` or `/2` mate suffix.
+pub fn qname_of(record: &FastqRecord) -> &[u8] {
+    let id = record.read_id_bytes();
+    match id {
+        [name @ .., b'/', b'1' | b'2'] => name,
+        _ => id,
+    }
+}
+
+/// Write the pairs of `paired_config` whose name, see [`qname_of`], is in
+/// `qnames`, to `out_r1` and `out_r2`, gzipped if their names end with `.gz`.
+///
+/// Pairs are matched by the name of R1, in the order they are read.
+pub fn extract_by_qname(
+    paired_config: PairedFastqReaderConfig,
+    qnames: &QnameSet,
+    out_r1: impl AsRef<Path>,
+    out_r2: impl AsRef<Path>,
+) -> Result<ExtractStats, Error> {
+    let out_paths = [out_r1.as_ref(), out_r2.as_ref()];
+    let [writer_r1, writer_r2] = out_paths
+        .map(|path| FastqWriter::from_path(path).map_err(|e| Error::io("create", path, e)));
+    let (mut writer_r1, mut writer_r2) = (writer_r1?, writer_r2?);
+    let write_error = |read: usize| move |e| Error::io("write", out_paths[read], e);
+
+    let mut reader = paired_config.run()?;
+    let mut stats = ExtractStats::default();
+    let mut r1 = FastqRecord::new();
+    let mut r2 = FastqRecord::new();
+    loop {
+        match reader.read(&mut r1, &mut r2) {
+            (Some(res1), Some(res2)) => {
+                res1?;
+                res2?;
+            }
+            (None, None) => break,
+            _ => return Err(anyhow!("R1 and R2 have different numbers of reads").into()),
+        }
+        stats.pairs_read += 1;
+
+        if qnames.contains(qname_of(&r1)) {
+            writer_r1.write(&r1).map_err(write_error(0))?;
+            writer_r2.write(&r2).map_err(write_error(1))?;
+            stats.pairs_written += 1;
+        }
+    }
+    reader.join()?;
+
+    writer_r1.finish().map_err(write_error(0))?;
+    writer_r2.finish().map_err(write_error(1))?;
+
+    Ok(stats)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::test_utils::TestFastqPair;
+
+    #[test]
+    fn test_qname_of() -> Result<(), Box<dyn std::error::Error>> {
+        let mut record = FastqRecord::new();
+        for (text, qname) in [
+            ("@read1/1 comment\nA\n+\nI\n", "read1"),
+            ("@read1/2\nA\n+\nI\n", "read1"),
+            ("@read1/3 1:N:0:1\nA\n+\nI\n", "read1/3"),
+        ] {
+            record.clear();
+            record.load_record(text.as_bytes())?;
+            assert_eq!(qname_of(&record), qname.as_bytes());
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_extract_by_qname_bloom() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = tempfile::tempdir()?;
+        let (r1, r2) = TestFastqPair::new()
+            .add_pairs(2_000, 20)
+            .build(dir.path(), true)?;
+        let mut qnames = QnameSet::bloom(100, 0.001);
+        for i in (0..2_000).step_by(20) {
+            qnames.insert(format!("pair{}", i).as_bytes());
+        }
+
+        let out = [1, 2].map(|i| dir.path().join(format!("out_R{}.fastq.gz", i)));
+        let stats = extract_by_qname(
+            PairedFastqReaderConfig::new(r1, r2),
+            &qnames,
+            &out[0],
+            &out[1],
+        )?;
+        // at most a few false positives.
+        assert_eq!(stats.pairs_read, 2_000);
+        assert!((100..105).contains(&stats.pairs_written), "{:?}", stats);
+        assert!(fs::metadata(&out[1])?.len() > 0);
+
+        Ok(())
+    }
+
+    #[cfg(feature = "bam")]
+    #[test]
+    fn test_extract_aligned_to_region() -> Result<(), Box<dyn std::error::Error>> {
+        use crate::{bam::qnames::collect_qnames, data::locus::GenomeRegion, test_utils::TestBam};
+
+        let dir = tempfile::tempdir()?;
+        // of pair0, pair2, pair4 and pair6, the first and the third in
+        // chr1:1001-2000.
+        let bam_path = TestBam::new()
+            .add_pair("chr1", 1_000, 1_200, 50, 0)
+            .add_pair("chr1", 5_000, 5_200, 50, 0)
+            .add_pair("chr1", 1_500, 1_700, 50, 0)
+            .add_pair("chr2", 1_000, 1_200, 50, 0)
+            .build(dir.path())?;
+        let qnames = collect_qnames(
+            &bam_path,
+            &[GenomeRegion::from(("chr1", 1_001, 2_000))],
+            false,
+        )?;
+        assert_eq!(
+            qnames,
+            HashSet::from([b"pair0".to_vec(), b"pair4".to_vec()])
+        );
+
+        let (r1, r2) = TestFastqPair::new()
+            .add_pairs(8, 20)
+            .build(dir.path(), false)?;
+        let out = [1, 2].map(|i| dir.path().join(format!("out_R{}.fastq", i)));
+        let stats = extract_by_qname(
+            PairedFastqReaderConfig::new(r1, r2),
+            &QnameSet::from(qnames),
+            &out[0],
+            &out[1],
+        )?;
+        assert_eq!(
+            stats,
+            ExtractStats {
+                pairs_read: 8,
+                pairs_written: 2,
+            }
+        );
+
+        for (i, path) in out.iter().enumerate() {
+            let headers = fs::read_to_string(path)?
+                .lines()
+                .step_by(4)
+                .map(str::to_string)
+                .collect::<Vec<_>>();
+            assert_eq!(
+                headers,
+                [
+                    format!("@pair0 {}:N:0:1", i + 1),
+                    format!("@pair4 {}:N:0:1", i + 1)
+                ]
+            );
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,338 @@
+//! Per-cycle quality profile and read composition of fastq streams, the core of
+//! a FastQC report.
+
+use std::io::Write;
+
+use anyhow::Error;
+
+use crate::{fastq::FastqRecord, table::TableWriter};
+
+/// Cycles tracked by [`QualityProfile::new`].
+pub const DEFAULT_MAX_CYCLES: usize = 500;
+
+/// Phred scores 0 to 93, the range of phred+33 printable characters.
+const N_QUALS: usize = 94;
+const PHRED_OFFSET: u8 = 33;
+
+/// Quality and composition statistics accumulated over reads.
+///
+/// Qualities are decoded as phred+33, and scores above 93 are counted as 93.
+/// Per-cycle statistics cover the first `max_cycles` bases of each read, so
+/// memory does not depend on the read length; the other statistics cover whole
+/// reads.
+///
+/// Profiles can be computed on separate threads and combined with [`Self::merge`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct QualityProfile {
+    max_cycles: usize,
+    n_reads: u64,
+    /// Quality histogram per cycle, up to the longest read seen.
+    cycle_quals: Vec<[u64; N_QUALS]>,
+    cycle_n_counts: Vec<u64>,
+    qual_histogram: [u64; N_QUALS],
+    /// Reads per GC percent, rounded; reads with only N are not counted.
+    gc_histogram: [u64; 101],
+    /// Reads per length, the last bin counting reads of `max_cycles` bases or more.
+    length_histogram: Vec<u64>,
+}
+
+impl Default for QualityProfile {
+    fn default() -> Self {
+        Self::with_max_cycles(DEFAULT_MAX_CYCLES)
+    }
+}
+
+impl QualityProfile {
+    /// Track the first [`DEFAULT_MAX_CYCLES`] cycles.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_max_cycles(max_cycles: usize) -> Self {
+        Self {
+            max_cycles,
+            n_reads: 0,
+            cycle_quals: vec![],
+            cycle_n_counts: vec![],
+            qual_histogram: [0; N_QUALS],
+            gc_histogram: [0; 101],
+            length_histogram: vec![0; max_cycles + 1],
+        }
+    }
+
+    pub fn max_cycles(&self) -> usize {
+        self.max_cycles
+    }
+
+    pub fn n_reads(&self) -> u64 {
+        self.n_reads
+    }
+
+    pub fn update(&mut self, record: &FastqRecord) {
+        let seq = record.sequence_bytes();
+        let qual = record.quality_bytes();
+
+        self.n_reads += 1;
+        self.length_histogram[seq.len().min(self.max_cycles)] += 1;
+
+        let n_cycles = seq.len().min(self.max_cycles);
+        if self.cycle_quals.len() < n_cycles {
+            self.cycle_quals.resize(n_cycles, [0; N_QUALS]);
+            self.cycle_n_counts.resize(n_cycles, 0);
+        }
+
+        for (cycle, &q) in qual.iter().enumerate() {
+            let q = (q.saturating_sub(PHRED_OFFSET) as usize).min(N_QUALS - 1);
+            self.qual_histogram[q] += 1;
+            if cycle < n_cycles {
+                self.cycle_quals[cycle][q] += 1;
+            }
+        }
+
+        let (mut n_gc, mut n_n) = (0, 0);
+        for (cycle, &b) in seq.iter().enumerate() {
+            match b.to_ascii_uppercase() {
+                b'G' | b'C' => n_gc += 1,
+                b'N' => {
+                    n_n += 1;
+                    if cycle < n_cycles {
+                        self.cycle_n_counts[cycle] += 1;
+                    }
+                }
+                _ => {}
+            }
+        }
+        if seq.len() > n_n {
+            let gc_percent = (n_gc as f64 * 100.0 / (seq.len() - n_n) as f64).round();
+            self.gc_histogram[gc_percent as usize] += 1;
+        }
+    }
+
+    /// Update with both mates of a pair.
+    pub fn update_pair(&mut self, r1: &FastqRecord, r2: &FastqRecord) {
+        self.update(r1);
+        self.update(r2);
+    }
+
+    /// Add the counts of `other`.
+    ///
+    /// # Panics
+    /// If the profiles track a different number of cycles.
+    pub fn merge(&mut self, other: Self) {
+        assert_eq!(
+            self.max_cycles, other.max_cycles,
+            "Cannot merge quality profiles with different max_cycles"
+        );
+
+        self.n_reads += other.n_reads;
+        if self.cycle_quals.len() < other.cycle_quals.len() {
+            self.cycle_quals
+                .resize(other.cycle_quals.len(), [0; N_QUALS]);
+            self.cycle_n_counts.resize(other.cycle_n_counts.len(), 0);
+        }
+        for (mine, theirs) in self.cycle_quals.iter_mut().zip(other.cycle_quals) {
+            add_counts(mine, &theirs);
+        }
+        add_counts(&mut self.cycle_n_counts, &other.cycle_n_counts);
+        add_counts(&mut self.qual_histogram, &other.qual_histogram);
+        add_counts(&mut self.gc_histogram, &other.gc_histogram);
+        add_counts(&mut self.length_histogram, &other.length_histogram);
+    }
+
+    /// Number of tracked cycles, the length of the longest read up to `max_cycles`.
+    pub fn n_cycles(&self) -> usize {
+        self.cycle_quals.len()
+    }
+
+    /// Mean quality of each cycle (0-based).
+    pub fn cycle_means(&self) -> Vec<f64> {
+        self.cycle_quals
+            .iter()
+            .map(|hist| {
+                let n = hist.iter().sum::<u64>();
+                let sum = hist
+                    .iter()
+                    .enumerate()
+                    .map(|(q, &c)| q as u64 * c)
+                    .sum::<u64>();
+                sum as f64 / n as f64
+            })
+            .collect()
+    }
+
+    /// First quartile, median and third quartile of the qualities at `cycle`
+    /// (0-based), by the nearest-rank method. `None` past the tracked cycles.
+    pub fn cycle_quartiles(&self, cycle: usize) -> Option<[u8; 3]> {
+        let hist = self.cycle_quals.get(cycle)?;
+        Some([
+            nearest_rank(hist, 0.25),
+            nearest_rank(hist, 0.5),
+            nearest_rank(hist, 0.75),
+        ])
+    }
+
+    /// Number of N bases at each cycle (0-based).
+    pub fn cycle_n_counts(&self) -> &[u64] {
+        &self.cycle_n_counts
+    }
+
+    /// Bases per quality score, over whole reads.
+    pub fn quality_histogram(&self) -> &[u64] {
+        &self.qual_histogram
+    }
+
+    /// Reads per GC percent (0 to 100) of their non-N bases.
+    pub fn gc_histogram(&self) -> &[u64] {
+        &self.gc_histogram
+    }
+
+    /// Reads per length. The last bin, at `max_cycles`, counts reads of
+    /// `max_cycles` bases or more.
+    pub fn length_histogram(&self) -> &[u64] {
+        &self.length_histogram
+    }
+
+    /// Write the per-cycle profile as a `cycle\tmean\tq1\tmedian\tq3\tn_count`
+    /// table, with 1-based cycles.
+    pub fn write_tsv(&self, w: impl Write) -> Result<(), Error> {
+        let mut tw = TableWriter::new(w, &["cycle", "mean", "q1", "median", "q3", "n_count"])?;
+        for (cycle, mean) in self.cycle_means().into_iter().enumerate() {
+            let [q1, median, q3] = self.cycle_quartiles(cycle).unwrap();
+            tw.write_row([
+                (cycle + 1).to_string(),
+                format!("{:.2}", mean),
+                q1.to_string(),
+                median.to_string(),
+                q3.to_string(),
+                self.cycle_n_counts[cycle].to_string(),
+            ])?;
+        }
+        tw.into_inner()?;
+        Ok(())
+    }
+
+    /// Write the non-zero bins of the quality, GC and length histograms as a
+    /// `histogram\tvalue\tcount` table.
+    pub fn write_histograms_tsv(&self, w: impl Write) -> Result<(), Error> {
+        let mut tw = TableWriter::new(w, &["histogram", "value", "count"])?;
+        for (name, hist) in [
+            ("quality", self.quality_histogram()),
+            ("gc_percent", self.gc_histogram()),
+            ("length", self.length_histogram()),
+        ] {
+            for (value, &count) in hist.iter().enumerate() {
+                if count > 0 {
+                    tw.write_row([name.to_string(), value.to_string(), count.to_string()])?;
+                }
+            }
+        }
+        tw.into_inner()?;
+        Ok(())
+    }
+}
+
+fn add_counts(dst: &mut [u64], src: &[u64]) {
+    dst.iter_mut().zip(src).for_each(|(d, s)| *d += s);
+}
+
+/// Smallest quality whose cumulative count reaches `p` of the total.
+fn nearest_rank(hist: &[u64; N_QUALS], p: f64) -> u8 {
+    let n = hist.iter().sum::<u64>();
+    let rank = ((p * n as f64).ceil() as u64).max(1);
+
+    let mut cum = 0;
+    for (q, &c) in hist.iter().enumerate() {
+        cum += c;
+        if cum >= rank {
+            return q as u8;
+        }
+    }
+    0
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    fn record(seq: &str, qual: &[u8]) -> FastqRecord {
+        let qual = qual.iter().map(|q| q + PHRED_OFFSET).collect::<Vec<_>>();
+        let text = format!("@r\n{}\n+\n{}\n", seq, String::from_utf8(qual).unwrap());
+
+        let mut record = FastqRecord::new();
+        record.load_record(Cursor::new(text)).unwrap();
+        record
+    }
+
+    #[test]
+    fn test_cycle_means_and_quartiles() {
+        let mut profile = QualityProfile::new();
+        profile.update(&record("ACGT", &[10, 20, 30, 40]));
+        profile.update(&record("ACGN", &[20, 20, 10, 2]));
+        profile.update(&record("GGCC", &[30, 20, 20, 40]));
+        profile.update(&record("AC", &[40, 20]));
+
+        assert_eq!(profile.n_reads(), 4);
+        assert_eq!(profile.n_cycles(), 4);
+        assert_eq!(profile.cycle_means(), vec![25.0, 20.0, 20.0, 82.0 / 3.0]);
+        assert_eq!(profile.cycle_quartiles(0), Some([10, 20, 30]));
+        assert_eq!(profile.cycle_quartiles(3), Some([2, 40, 40]));
+        assert_eq!(profile.cycle_quartiles(4), None);
+        assert_eq!(profile.cycle_n_counts(), &[0, 0, 0, 1]);
+
+        assert_eq!(profile.quality_histogram()[20], 6);
+        // 50%, 67% (N excluded), 100% and 50% GC.
+        assert_eq!(profile.gc_histogram()[50], 2);
+        assert_eq!(profile.gc_histogram()[67], 1);
+        assert_eq!(profile.gc_histogram()[100], 1);
+        assert_eq!(profile.length_histogram()[2], 1);
+        assert_eq!(profile.length_histogram()[4], 3);
+    }
+
+    #[test]
+    fn test_max_cycles() {
+        let mut profile = QualityProfile::with_max_cycles(3);
+        profile.update(&record("ACGTNN", &[30, 30, 30, 10, 10, 10]));
+
+        assert_eq!(profile.n_cycles(), 3);
+        assert_eq!(profile.cycle_means(), vec![30.0; 3]);
+        assert_eq!(profile.cycle_n_counts(), &[0, 0, 0]);
+        // the whole read is still in the other statistics.
+        assert_eq!(profile.quality_histogram()[10], 3);
+        assert_eq!(profile.length_histogram(), &[0, 0, 0, 1]);
+    }
+
+    #[test]
+    fn test_merge() -> Result<(), Error> {
+        let reads = [
+            record("ACGT", &[10, 20, 30, 40]),
+            record("NNNN", &[2, 2, 2, 2]),
+            record("ACGTACGT", &[35; 8]),
+            record("GG", &[12, 13]),
+        ];
+
+        let mut whole = QualityProfile::new();
+        reads.iter().for_each(|r| whole.update(r));
+
+        // the longer read goes to the part merged into the other.
+        let mut part1 = QualityProfile::new();
+        let mut part2 = QualityProfile::new();
+        part1.update_pair(&reads[0], &reads[1]);
+        part2.update_pair(&reads[2], &reads[3]);
+        part1.merge(part2);
+
+        assert_eq!(part1, whole);
+        assert_eq!(part1.n_cycles(), 8);
+
+        let mut tsv = vec![];
+        part1.write_tsv(&mut tsv)?;
+        let tsv = String::from_utf8(tsv)?;
+        let mut lines = tsv.lines();
+        assert_eq!(lines.next(), Some("cycle\tmean\tq1\tmedian\tq3\tn_count"));
+        assert_eq!(lines.next(), Some("1\t14.75\t2\t10\t12\t1"));
+        assert_eq!(lines.count(), 7);
+
+        Ok(())
+    }
+}
@@ -1,13 +1,16 @@
 
-mod err_opt_ext;
+pub mod err_opt_ext;
 
 
 
 pub mod nuc_base_map;
-mod utils;
+pub mod utils;
 pub mod errors;
+pub use errors::Error;
 
 pub mod data;
+pub mod prelude;
+pub mod table;
 pub mod zerocopy;
 
 #[cfg(feature="macros")]
@@ -23,15 +26,24 @@ pub mod pbar;
 #[cfg(feature="pbar")]
 pub use indicatif;
 
-#[cfg(feature="memfd")]
-mod memfd_file;
+// memfd_create is Linux only; elsewhere `temp_store` falls back to disk.
+#[cfg(all(feature="memfd", target_os="linux"))]
+pub mod memfd_file;
+
+pub mod temp_store;
 
 #[cfg(feature="fastq")]
-mod fastq;
+pub mod fastq;
 
 #[cfg(feature="bam")]
 pub mod bam;
 
+#[cfg(feature="bio")]
+pub mod reference;
+
+#[cfg(test)]
+mod test_utils;
+
 #[cfg(feature="bam")]
 // re-export
 pub use rust_htslib;
\ No newline at end of file
@@ -1,7 +1,7 @@
 use std::{
     ffi::CString,
     fs::File,
-    io::{BufReader, Read, Seek, SeekFrom, Write},
+    io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write},
     os::fd::{AsRawFd, FromRawFd, IntoRawFd},
     path::{Path, PathBuf},
 };
@@ -9,6 +9,8 @@ use std::{
 use anyhow::Error;
 use nix::sys::memfd::{MFdFlags, memfd_create};
 
+use crate::errors::MemFdError;
+
 pub struct MemFdFile {
     file: File,
     path: PathBuf,
@@ -20,6 +22,10 @@ impl MemFdFile {
     /// This function creates an anonymous in‑memory file. It returns a `MemFdFile`
     /// which wraps the underlying file descriptor.
     pub fn new(name: &str, flags: MFdFlags) -> Result<Self, Error> {
+        Ok(Self::create(name, flags)?)
+    }
+
+    fn create(name: &str, flags: MFdFlags) -> Result<Self, MemFdError> {
         // Create the memfd file descriptor.
         let fd = memfd_create(name, flags)?;
 
@@ -48,6 +54,30 @@ impl MemFdFile {
         Ok(memfd_file)
     }
 
+    /// Same as [`Self::from_path`], but fails with [`MemFdError::TooLarge`] before
+    /// copying anything if the source is larger than `max_bytes`.
+    pub fn from_path_with_limit(
+        source_path: impl AsRef<Path>,
+        memfd_name: &str,
+        flags: MFdFlags,
+        max_bytes: u64,
+    ) -> Result<Self, MemFdError> {
+        let mut source_file = File::open(source_path)?;
+
+        let size = source_file.metadata()?.len();
+        if size > max_bytes {
+            return Err(MemFdError::TooLarge {
+                size,
+                limit: max_bytes,
+            });
+        }
+
+        let mut memfd_file = Self::create(memfd_name, flags)?;
+        std::io::copy(&mut source_file, &mut memfd_file.file)?;
+        memfd_file.file.seek(SeekFrom::Start(0))?;
+        Ok(memfd_file)
+    }
+
     /// Return the file path (/proc/self/fd/<fd>) for this memfd.
     pub fn path(&self) -> &Path {
         &self.path
@@ -59,6 +89,20 @@ impl MemFdFile {
         Ok(())
     }
 
+    /// Write several chunks through one buffer, to avoid a syscall per small chunk.
+    pub fn write_batch<I, D>(&mut self, chunks: I) -> Result<(), Error>
+    where
+        I: IntoIterator<Item = D>,
+        D: AsRef<[u8]>,
+    {
+        let mut writer = BufWriter::with_capacity(1 << 16, &mut self.file);
+        for chunk in chunks {
+            writer.write_all(chunk.as_ref())?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+
     pub fn truncate(&mut self) -> Result<(), Error> {
         self.file.set_len(0)?;
         self.file.seek(SeekFrom::Start(0))?;
@@ -74,3 +118,61 @@ impl MemFdFile {
         Ok(buffer)
     }
 }
+
+impl Read for MemFdFile {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.file.read(buf)
+    }
+}
+
+impl Write for MemFdFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.file.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+impl Seek for MemFdFile {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.file.seek(pos)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_write_batch_and_read() -> Result<(), Error> {
+        let mut f = MemFdFile::new("test_write_batch", MFdFlags::MFD_CLOEXEC)?;
+        f.write_batch([b"ab".as_slice(), b"", b"cde"])?;
+        f.write_data(b"f")?;
+
+        assert_eq!(f.read_data()?, b"abcdef");
+        assert_eq!(std::fs::read(f.path())?, b"abcdef");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_from_path_with_limit() -> Result<(), Error> {
+        let dir = tempfile::tempdir()?;
+        let src = dir.path().join("src.txt");
+        std::fs::write(&src, b"0123456789")?;
+
+        let mut f = MemFdFile::from_path_with_limit(&src, "test_limit", MFdFlags::MFD_CLOEXEC, 10)?;
+        assert_eq!(f.read_data()?, b"0123456789");
+
+        match MemFdFile::from_path_with_limit(&src, "test_limit", MFdFlags::MFD_CLOEXEC, 9) {
+            Err(MemFdError::TooLarge { size, limit }) => {
+                assert_eq!((size, limit), (10, 9));
+            }
+            _ => panic!("expected TooLarge"),
+        }
+
+        Ok(())
+    }
+}
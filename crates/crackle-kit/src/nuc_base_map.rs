@@ -131,6 +131,12 @@ mod tests {
     fn test_get_and_get_mut_valid_uppercase() {
         let mut map: NucBaseMap<u32> = NucBaseMap::default();
 
+        // A default map has no values to get.
+        assert_eq!(map.get_mut(b'A'), None);
+        for nuc_base in *b"ATCGN" {
+            map.get_or_insert_with(nuc_base, u32::default);
+        }
+
         // Test get_mut
         *map.get_mut(b'A').unwrap() = 10;
         *map.get_mut(b'T').unwrap() = 20;
@@ -149,6 +155,9 @@ mod tests {
     #[test]
     fn test_get_and_get_mut_valid_lowercase() {
         let mut map: NucBaseMap<u32> = NucBaseMap::default();
+        for nuc_base in *b"atcgn" {
+            map.get_or_insert_with(nuc_base, u32::default);
+        }
 
         // Test get_mut with lowercase
         *map.get_mut(b'a').unwrap() = 10;
@@ -2,9 +2,12 @@ use std::fmt::Write;
 
 use indicatif::{ProgressBar, ProgressDrawTarget, ProgressState, ProgressStyle};
 
+use crate::utils::fmt::format_rate;
+
 /// Make `indicatif::ProgressBar` instance.
 /// 
 /// You can give `0` to `len` argument, then a question mark will be on progress bar.
+/// The rate is the average since the start, see [`format_rate`].
 pub fn prepare_pbar(len: u64) -> ProgressBar {
     let pb = ProgressBar::new(len);
 
@@ -27,6 +30,11 @@ pub fn prepare_pbar(len: u64) -> ProgressBar {
             |state: &ProgressState, w: &mut dyn Write| {
                 write!(w, "{:.1}s", state.eta().as_secs_f64()).unwrap()
             },
+        ).with_key(
+            "per_sec",
+            |state: &ProgressState, w: &mut dyn Write| {
+                write!(w, "{}", format_rate(state.pos(), state.elapsed(), "")).unwrap()
+            },
         ), // .with_key("bases", |state: &ProgressState, w: &mut dyn Write| {
            //     write!(
            //         w,
@@ -0,0 +1,48 @@
+//! Commonly used items, for `use crackle_kit::prelude::*;`.
+
+pub use crate::{
+    data::{
+        bases::{Base, BaseArr},
+        chrom::Chrom,
+        locus::{GenomeCoordinate, GenomeRegion},
+        variant::Variant,
+    },
+    err_opt_ext::HashMapExt,
+    nuc_base_map::NucBaseMap,
+    utils::{binning::make_bins, thread_budget::ThreadBudget, umi::UmiScheme},
+};
+
+#[cfg(feature = "batch-work")]
+pub use crate::utils::{batch_region::batch_region, batched_data::BatchedData};
+
+#[cfg(feature = "bam")]
+pub use crate::bam::{
+    cigar::RecordCigarExt,
+    context::ProcessContext,
+    headerless::HeaderlessRecord,
+    output::OutputTarget,
+    paired::{PairDecision, PairedRecordModifier},
+    pooled::{CombinedWorker, PooledBamSource, PooledLocusProcessor, PooledLocusWorker},
+    process::{
+        BamLocusWorkInput, BamLocusWorker, CoordinateSystem, MultiBamLocusProcessor,
+        MultiBamLocusWorker, OnModifyError, ParallelBamProcessor, ParallelLocusProcessorPileup,
+        ProcessBamOptions, ProcessStats, RecordModifier,
+    },
+    reader::RetryPolicy,
+    tags::RecordUmiExt,
+    watchdog::{BatchTimedOut, BatchTimeoutPolicy},
+};
+
+#[cfg(feature = "fastq")]
+pub use crate::fastq::{
+    FastqRecord, FastqWriter, PairedFastqReader, PairedFastqReaderConfig, demux::Demultiplexer,
+};
+
+#[cfg(all(feature = "fastq", feature = "async"))]
+pub use crate::fastq::async_reader::AsyncPairedFastqReader;
+
+#[cfg(feature = "tracing")]
+pub use crate::tracing_kit::{
+    setup_logging_stderr_only, setup_logging_stderr_only_verbose, setup_logging_to_stderr_and_file,
+    setup_logging_to_stderr_and_rolling_file,
+};
@@ -0,0 +1,357 @@
+//! Reference genome access for locus workers, and the assembly gaps and
+//! soft-masked stretches of a reference, e.g. to mask them out of bins.
+
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    fs::File,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use anyhow::{Error, anyhow, bail};
+use bio::io::fasta::IndexedReader;
+#[cfg(feature = "tracing")]
+use tracing::{Level, event};
+
+use crate::data::{
+    bases::Base,
+    chrom::Chrom,
+    locus::GenomeRegion,
+    seq_dict::{SeqDict, SeqEntry},
+};
+
+/// Indexed FASTA reader which can be shared between threads.
+///
+/// `bio`'s reader is not `Sync`, so this keeps a pool of reader handles and lends
+/// one to each fetching thread; a thread opens a new handle only when the pool is
+/// empty. Contigs are looked up by their name as is, then unprefixed and prefixed,
+/// so `Chrom::Chr1` is found in both `chr1` and `1` style references.
+///
+/// Returned sequences are uppercased, so soft-masked (lowercase) bases map to
+/// [`Base`] like any other.
+pub struct RefGenome {
+    path: PathBuf,
+    contig_lens: HashMap<String, u64>,
+    dict: SeqDict,
+    readers: Mutex<Vec<IndexedReader<File>>>,
+}
+
+impl RefGenome {
+    /// Open an indexed FASTA. The `.fai` must exist next to it.
+    pub fn from_path(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let path = path.as_ref().to_path_buf();
+        let reader = IndexedReader::from_file(&path)?;
+
+        let sequences = reader.index.sequences();
+        let dict = SeqDict::new(sequences.iter().map(|seq| SeqEntry {
+            contig: Chrom::from(seq.name.clone()),
+            len: seq.len,
+            md5: None,
+        }));
+        let contig_lens = sequences
+            .into_iter()
+            .map(|seq| (seq.name, seq.len))
+            .collect();
+
+        Ok(Self {
+            path,
+            contig_lens,
+            dict,
+            readers: Mutex::new(vec![reader]),
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Contigs of the `.fai`, in its order.
+    pub fn seq_dict(&self) -> &SeqDict {
+        &self.dict
+    }
+
+    pub fn contig_len(&self, contig: &Chrom<'_>) -> Option<u64> {
+        let name = self.contig_name(contig).ok()?;
+        self.contig_lens.get(name.as_ref()).copied()
+    }
+
+    /// Base at 1-based `pos`, soft-masked bases as uppercase. Bases other than A,
+    /// C, G, T and N are an error.
+    pub fn fetch_base(&self, contig: &Chrom<'_>, pos: i64) -> Result<Base, Error> {
+        let seq = self.fetch(contig, pos, pos)?;
+        Base::try_from(seq[0])
+    }
+
+    /// Sequence of a region, 1-based inclusive.
+    pub fn fetch_seq(&self, region: &GenomeRegion<'_>) -> Result<Vec<u8>, Error> {
+        self.fetch(&region.contig, region.start, region.end)
+    }
+
+    fn fetch(&self, contig: &Chrom<'_>, start: i64, end: i64) -> Result<Vec<u8>, Error> {
+        let name = self.contig_name(contig)?;
+        let len = self.contig_lens[name.as_ref()];
+
+        if start < 1 || end < start || end as u64 > len {
+            bail!(
+                "Invalid range {}:{}-{} for contig of length {}",
+                name,
+                start,
+                end,
+                len
+            );
+        }
+
+        let mut seq = Vec::with_capacity((end - start + 1) as usize);
+        self.with_reader(|reader| {
+            reader.fetch(&name, (start - 1) as u64, end as u64)?;
+            reader.read(&mut seq)?;
+            Ok(())
+        })?;
+        seq.make_ascii_uppercase();
+
+        Ok(seq)
+    }
+
+    fn contig_name<'c>(&self, contig: &'c Chrom<'_>) -> Result<Cow<'c, str>, Error> {
+        [
+            Cow::Borrowed(contig.as_str()),
+            Cow::Borrowed(contig.to_unprefixed()),
+            contig.to_prefixed(),
+        ]
+        .into_iter()
+        .find(|name| self.contig_lens.contains_key(name.as_ref()))
+        .ok_or_else(|| {
+            anyhow!(
+                "Contig {} is not in the reference {}",
+                contig,
+                self.path.display()
+            )
+        })
+    }
+
+    fn with_reader<T>(
+        &self,
+        f: impl FnOnce(&mut IndexedReader<File>) -> Result<T, Error>,
+    ) -> Result<T, Error> {
+        let pooled = self.readers.lock().unwrap().pop();
+        let mut reader = match pooled {
+            Some(r) => r,
+            None => IndexedReader::from_file(&self.path)?,
+        };
+
+        let res = f(&mut reader);
+        self.readers.lock().unwrap().push(reader);
+
+        res
+    }
+}
+
+/// Bases fetched at once by [`ref_gaps`] and [`soft_masked_regions`].
+const SCAN_CHUNK_LEN: u64 = 1 << 20;
+
+/// Runs of at least `min_gap_len` N bases of the indexed FASTA at `fasta_path`,
+/// e.g. its assembly gaps, to mask out with
+/// [`RegionSet::subtract`](crate::data::interval::RegionSet::subtract).
+///
+/// Contigs are read a chunk at a time, so memory stays bounded whatever their
+/// length. The runs and bases found on each contig are logged at DEBUG.
+pub fn ref_gaps(
+    fasta_path: impl AsRef<Path>,
+    min_gap_len: u64,
+) -> Result<Vec<GenomeRegion<'static>>, Error> {
+    scan_runs(
+        fasta_path.as_ref(),
+        min_gap_len,
+        SCAN_CHUNK_LEN,
+        "N gaps",
+        |base| base.eq_ignore_ascii_case(&b'N'),
+    )
+}
+
+/// Runs of at least `min_len` lowercase bases of the indexed FASTA at
+/// `fasta_path`, soft-masked repeats and low complexity regions. See
+/// [`ref_gaps`].
+pub fn soft_masked_regions(
+    fasta_path: impl AsRef<Path>,
+    min_len: u64,
+) -> Result<Vec<GenomeRegion<'static>>, Error> {
+    scan_runs(
+        fasta_path.as_ref(),
+        min_len,
+        SCAN_CHUNK_LEN,
+        "soft-masked runs",
+        |base| base.is_ascii_lowercase(),
+    )
+}
+
+/// Runs of at least `min_len` bases matching `in_run`, in the order of the
+/// contigs, read `chunk_len` bases at a time.
+fn scan_runs(
+    fasta_path: &Path,
+    min_len: u64,
+    chunk_len: u64,
+    what: &str,
+    in_run: impl Fn(u8) -> bool,
+) -> Result<Vec<GenomeRegion<'static>>, Error> {
+    let mut reader = IndexedReader::from_file(&fasta_path)?;
+    let mut regions = vec![];
+    let mut chunk = Vec::with_capacity(chunk_len as usize);
+
+    for seq in reader.index.sequences() {
+        let contig = Chrom::from(seq.name.clone());
+        let n_before = regions.len();
+        let mut n_bases = 0;
+        // 1-based start of the run going on.
+        let mut run_start = None;
+        let mut end_run = |start: u64, end: u64| {
+            if end + 1 - start >= min_len.max(1) {
+                regions.push(GenomeRegion {
+                    contig: contig.clone(),
+                    start: start as i64,
+                    end: end as i64,
+                });
+                n_bases += end + 1 - start;
+            }
+        };
+
+        for chunk_start in (0..seq.len).step_by(chunk_len as usize) {
+            let chunk_end = (chunk_start + chunk_len).min(seq.len);
+            reader.fetch(&seq.name, chunk_start, chunk_end)?;
+            chunk.clear();
+            reader.read(&mut chunk)?;
+
+            for (pos, &base) in (chunk_start + 1..).zip(chunk.iter()) {
+                match (in_run(base), run_start) {
+                    (true, None) => run_start = Some(pos),
+                    (false, Some(start)) => {
+                        end_run(start, pos - 1);
+                        run_start = None;
+                    }
+                    _ => {}
+                }
+            }
+        }
+        if let Some(start) = run_start {
+            end_run(start, seq.len);
+        }
+
+        #[cfg(feature = "tracing")]
+        event!(
+            Level::DEBUG,
+            contig = %contig,
+            runs = regions.len() - n_before,
+            bases = n_bases,
+            "{} of {}: {} bp in {} runs",
+            what,
+            contig,
+            n_bases,
+            regions.len() - n_before
+        );
+        #[cfg(not(feature = "tracing"))]
+        let _ = (what, n_before, n_bases);
+    }
+
+    Ok(regions)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use super::*;
+    use crate::test_utils::write_test_fasta;
+
+    #[test]
+    fn test_fetch_base_and_seq() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = tempfile::tempdir()?;
+        let fasta = write_test_fasta(
+            dir.path(),
+            &[("1", b"ACGTacgtNN".repeat(10).as_slice()), ("chrEBV", &b"GGGCCC"[..])],
+        )?;
+        let reference = RefGenome::from_path(&fasta)?;
+
+        assert_eq!(reference.contig_len(&Chrom::Chr1), Some(100));
+        assert_eq!(reference.fetch_base(&Chrom::Chr1, 1)?, Base::A);
+        assert_eq!(reference.fetch_base(&Chrom::Chr1, 4)?, Base::T);
+        // soft-masked bases
+        assert_eq!(reference.fetch_base(&Chrom::Chr1, 6)?, Base::C);
+        assert_eq!(reference.fetch_base(&Chrom::Chr1, 9)?, Base::N);
+        // across line breaks
+        assert_eq!(reference.fetch_base(&Chrom::Chr1, 62)?, Base::C);
+
+        assert_eq!(
+            reference.fetch_seq(&GenomeRegion::from(("chr1", 3, 8)))?,
+            b"GTACGT"
+        );
+        assert_eq!(
+            reference.fetch_seq(&GenomeRegion::from(("chrEBV", 2, 4)))?,
+            b"GGC"
+        );
+
+        assert!(reference.fetch_base(&Chrom::Chr1, 0).is_err());
+        assert!(reference.fetch_base(&Chrom::Chr1, 101).is_err());
+        assert!(reference.fetch_base(&Chrom::Chr2, 1).is_err());
+
+        // shared between threads
+        thread::scope(|s| {
+            for _ in 0..4 {
+                s.spawn(|| assert_eq!(reference.fetch_base(&Chrom::Chr1, 2).unwrap(), Base::C));
+            }
+        });
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_gaps_and_soft_masked_runs() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = tempfile::tempdir()?;
+        // chr1: N at 5-16 and 29-30, lowercase at 21-26, both at 35-39.
+        let fasta = write_test_fasta(
+            dir.path(),
+            &[
+                ("chr1", &b"ACGTNNNNNNNNNNNNACGTacgtacGGNNCCCCnnnnn"[..]),
+                ("chr2", &b"NNNNNNNNNNACGT"[..]),
+            ],
+        )?;
+        let spans = |regions: Vec<GenomeRegion<'static>>| {
+            regions
+                .into_iter()
+                .map(|r| (r.contig.to_string(), r.start, r.end))
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            spans(ref_gaps(&fasta, 5)?),
+            [
+                ("chr1".to_string(), 5, 16),
+                ("chr1".to_string(), 35, 39),
+                ("chr2".to_string(), 1, 10),
+            ]
+        );
+        // runs across chunks.
+        let gaps = scan_runs(&fasta, 1, 7, "N gaps", |base| {
+            base.eq_ignore_ascii_case(&b'N')
+        })?;
+        assert_eq!(
+            spans(gaps),
+            [
+                ("chr1".to_string(), 5, 16),
+                ("chr1".to_string(), 29, 30),
+                ("chr1".to_string(), 35, 39),
+                ("chr2".to_string(), 1, 10),
+            ]
+        );
+
+        assert_eq!(
+            spans(soft_masked_regions(&fasta, 5)?),
+            [("chr1".to_string(), 21, 26), ("chr1".to_string(), 35, 39)]
+        );
+        assert_eq!(
+            spans(soft_masked_regions(&fasta, 6)?),
+            [("chr1".to_string(), 21, 26)]
+        );
+
+        Ok(())
+    }
+}
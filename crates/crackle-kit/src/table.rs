@@ -0,0 +1,91 @@
+//! Minimal TSV table writer for analysis outputs.
+
+use std::{fmt::Display, io::Write};
+
+use anyhow::{Error, bail};
+
+/// Writes a header line and rows of tab-separated fields.
+///
+/// Every row must have as many fields as the header, and fields must not contain
+/// tabs or newlines.
+pub struct TableWriter<W: Write> {
+    inner: W,
+    n_cols: usize,
+    buf: String,
+}
+
+impl<W: Write> TableWriter<W> {
+    pub fn new(mut inner: W, header: &[&str]) -> Result<Self, Error> {
+        writeln!(inner, "{}", header.join("\t"))?;
+
+        Ok(Self {
+            inner,
+            n_cols: header.len(),
+            buf: String::new(),
+        })
+    }
+
+    /// A writer of rows of `n_cols` fields without the header line, e.g. to
+    /// append to a table whose header is written.
+    #[cfg(feature = "bam")]
+    pub(crate) fn headerless(inner: W, n_cols: usize) -> Self {
+        Self {
+            inner,
+            n_cols,
+            buf: String::new(),
+        }
+    }
+
+    pub fn write_row<D: Display>(&mut self, row: impl IntoIterator<Item = D>) -> Result<(), Error> {
+        use std::fmt::Write as _;
+
+        self.buf.clear();
+        let mut n = 0;
+        for field in row {
+            if n > 0 {
+                self.buf.push('\t');
+            }
+            let start = self.buf.len();
+            write!(self.buf, "{}", field)?;
+            if self.buf[start..].contains(['\t', '\n', '\r']) {
+                bail!("Table field contains a tab or newline: {:?}", &self.buf[start..]);
+            }
+            n += 1;
+        }
+
+        if n != self.n_cols {
+            bail!("Expected {} fields in a row, got {}", self.n_cols, n);
+        }
+
+        self.buf.push('\n');
+        self.inner.write_all(self.buf.as_bytes())?;
+
+        Ok(())
+    }
+
+    /// Flush and return the underlying writer.
+    pub fn into_inner(mut self) -> Result<W, Error> {
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_table_writer() -> Result<(), Error> {
+        let mut tw = TableWriter::new(vec![], &["name", "value"])?;
+        tw.write_row(["a", "1"])?;
+        tw.write_row([&"b" as &dyn Display, &2.5])?;
+
+        assert!(tw.write_row(["only one"]).is_err());
+        assert!(tw.write_row(["tab\there", "1"]).is_err());
+
+        let out = String::from_utf8(tw.into_inner()?)?;
+        assert_eq!(out, "name\tvalue\na\t1\nb\t2.5\n");
+
+        Ok(())
+    }
+}
@@ -0,0 +1,246 @@
+//! Scratch file kept in memory when possible, on disk otherwise.
+
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use anyhow::Error;
+
+#[cfg(all(feature = "memfd", target_os = "linux"))]
+use crate::{errors::MemFdError, memfd_file::MemFdFile};
+
+/// Scratch file with a real path, backed by a memfd or by a file in the temp dir.
+///
+/// Use the path to hand the content to readers which need one (e.g. htslib, or the
+/// fastq readers). Memory is used only with the `memfd` feature on Linux, and when
+/// `memfd_create` succeeds; everywhere else the disk variant is used, so calling
+/// code does not need to care about the platform.
+pub enum TempStore {
+    #[cfg(all(feature = "memfd", target_os = "linux"))]
+    Memory(MemFdFile),
+    Disk(DiskTempFile),
+}
+
+impl TempStore {
+    /// Create an empty store, in memory if possible.
+    pub fn new(name: &str) -> Result<Self, Error> {
+        #[cfg(all(feature = "memfd", target_os = "linux"))]
+        if let Ok(f) = MemFdFile::new(name, memfd_flags()) {
+            return Ok(Self::Memory(f));
+        }
+
+        Ok(Self::Disk(DiskTempFile::new(name)?))
+    }
+
+    /// Copy `source_path` into a new store. It is kept in memory if it is at most
+    /// `max_bytes` long and memfd is available, and written to disk otherwise.
+    pub fn from_path_with_limit(
+        source_path: impl AsRef<Path>,
+        name: &str,
+        max_bytes: u64,
+    ) -> Result<Self, Error> {
+        let source_path = source_path.as_ref();
+
+        #[cfg(all(feature = "memfd", target_os = "linux"))]
+        match MemFdFile::from_path_with_limit(source_path, name, memfd_flags(), max_bytes) {
+            Ok(f) => return Ok(Self::Memory(f)),
+            Err(MemFdError::TooLarge { .. } | MemFdError::Create(_)) => {}
+            Err(err) => return Err(err.into()),
+        }
+        #[cfg(not(all(feature = "memfd", target_os = "linux")))]
+        let _ = max_bytes;
+
+        let mut disk = DiskTempFile::new(name)?;
+        io::copy(&mut File::open(source_path)?, &mut disk)?;
+        disk.seek(SeekFrom::Start(0))?;
+
+        Ok(Self::Disk(disk))
+    }
+
+    pub fn path(&self) -> &Path {
+        match self {
+            #[cfg(all(feature = "memfd", target_os = "linux"))]
+            Self::Memory(f) => f.path(),
+            Self::Disk(f) => f.path(),
+        }
+    }
+
+    pub fn is_memory(&self) -> bool {
+        match self {
+            #[cfg(all(feature = "memfd", target_os = "linux"))]
+            Self::Memory(_) => true,
+            Self::Disk(_) => false,
+        }
+    }
+
+    fn inner(&mut self) -> &mut dyn ReadWriteSeek {
+        match self {
+            #[cfg(all(feature = "memfd", target_os = "linux"))]
+            Self::Memory(f) => f,
+            Self::Disk(f) => f,
+        }
+    }
+}
+
+#[cfg(all(feature = "memfd", target_os = "linux"))]
+fn memfd_flags() -> nix::sys::memfd::MFdFlags {
+    nix::sys::memfd::MFdFlags::MFD_CLOEXEC
+}
+
+trait ReadWriteSeek: Read + Write + Seek {}
+
+impl<T: Read + Write + Seek> ReadWriteSeek for T {}
+
+impl AsRef<Path> for TempStore {
+    fn as_ref(&self) -> &Path {
+        self.path()
+    }
+}
+
+impl Read for TempStore {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.inner().read(buf)
+    }
+}
+
+impl Write for TempStore {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.inner().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner().flush()
+    }
+}
+
+impl Seek for TempStore {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.inner().seek(pos)
+    }
+}
+
+/// File in [`std::env::temp_dir`] (`TMPDIR`), removed on drop.
+pub struct DiskTempFile {
+    file: File,
+    path: PathBuf,
+}
+
+impl DiskTempFile {
+    pub fn new(name: &str) -> Result<Self, Error> {
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+        let dir = std::env::temp_dir();
+        loop {
+            let path = dir.join(format!(
+                "{}.{}.{}",
+                name,
+                std::process::id(),
+                COUNTER.fetch_add(1, Ordering::Relaxed)
+            ));
+
+            match OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create_new(true)
+                .open(&path)
+            {
+                Ok(file) => return Ok(Self { file, path }),
+                // left over by a previous process with the same pid.
+                Err(err) if err.kind() == io::ErrorKind::AlreadyExists => continue,
+                Err(err) => return Err(err.into()),
+            }
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for DiskTempFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+impl Read for DiskTempFile {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.file.read(buf)
+    }
+}
+
+impl Write for DiskTempFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.file.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+impl Seek for DiskTempFile {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.file.seek(pos)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read_all(store: &mut TempStore) -> Result<Vec<u8>, Error> {
+        store.seek(SeekFrom::Start(0))?;
+        let mut buf = vec![];
+        store.read_to_end(&mut buf)?;
+        Ok(buf)
+    }
+
+    #[test]
+    fn test_from_path_with_limit() -> Result<(), Error> {
+        let dir = tempfile::tempdir()?;
+        let src = dir.path().join("src.txt");
+        fs::write(&src, b"0123456789")?;
+
+        let mut small = TempStore::from_path_with_limit(&src, "test_small", 100)?;
+        assert_eq!(
+            small.is_memory(),
+            cfg!(all(feature = "memfd", target_os = "linux"))
+        );
+        assert_eq!(read_all(&mut small)?, b"0123456789");
+        assert_eq!(fs::read(small.path())?, b"0123456789");
+
+        let mut large = TempStore::from_path_with_limit(&src, "test_large", 5)?;
+        assert!(!large.is_memory());
+        assert_eq!(read_all(&mut large)?, b"0123456789");
+
+        let disk_path = large.path().to_path_buf();
+        assert!(disk_path.starts_with(std::env::temp_dir()));
+        drop(large);
+        assert!(!disk_path.exists());
+
+        Ok(())
+    }
+
+    #[cfg(not(all(feature = "memfd", target_os = "linux")))]
+    #[test]
+    fn test_disk_only_without_memfd() -> Result<(), Error> {
+        assert!(!TempStore::new("test_no_memfd")?.is_memory());
+        Ok(())
+    }
+
+    #[test]
+    fn test_write_through_path() -> Result<(), Error> {
+        for mut store in [TempStore::new("test_new")?, TempStore::Disk(DiskTempFile::new("test_disk")?)] {
+            store.write_all(b"hello")?;
+            store.flush()?;
+            assert_eq!(fs::read(&store)?, b"hello");
+            assert_eq!(read_all(&mut store)?, b"hello");
+        }
+
+        Ok(())
+    }
+}
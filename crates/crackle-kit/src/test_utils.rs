@@ -0,0 +1,493 @@
+//! Fixture builders for tests.
+//!
+//! Tests should not depend on files outside the repository, so fixtures are
+//! synthesized into a temporary directory instead.
+
+#[cfg(feature = "bam")]
+pub(crate) use bam_fixture::TestBam;
+#[cfg(feature = "bio")]
+pub(crate) use fasta_fixture::write_test_fasta;
+#[cfg(feature = "mmap")]
+pub(crate) use fasta_fixture::write_test_fasta_with_width;
+#[cfg(feature = "fastq")]
+pub(crate) use fastq_fixture::TestFastqPair;
+
+#[cfg(any(feature = "bio", feature = "mmap"))]
+mod fasta_fixture {
+    use std::{
+        fs::File,
+        io::{BufWriter, Write},
+        path::{Path, PathBuf},
+    };
+
+    use anyhow::Error;
+
+    #[cfg(feature = "bio")]
+    const LINE_WIDTH: usize = 60;
+
+    /// Write `test.fa` and its `.fai` into `dir` and return the fasta path.
+    #[cfg(feature = "bio")]
+    pub(crate) fn write_test_fasta(
+        dir: impl AsRef<Path>,
+        contigs: &[(&str, &[u8])],
+    ) -> Result<PathBuf, Error> {
+        write_test_fasta_with_width(dir, contigs, LINE_WIDTH)
+    }
+
+    /// [`write_test_fasta`] with `line_width` bases per line.
+    pub(crate) fn write_test_fasta_with_width(
+        dir: impl AsRef<Path>,
+        contigs: &[(&str, &[u8])],
+        line_width: usize,
+    ) -> Result<PathBuf, Error> {
+        let path = dir.as_ref().join("test.fa");
+        let mut fa = BufWriter::new(File::create(&path)?);
+        let mut fai = BufWriter::new(File::create(dir.as_ref().join("test.fa.fai"))?);
+
+        let mut offset = 0;
+        for (name, seq) in contigs {
+            let header = format!(">{}\n", name);
+            fa.write_all(header.as_bytes())?;
+            offset += header.len();
+
+            writeln!(
+                fai,
+                "{}\t{}\t{}\t{}\t{}",
+                name,
+                seq.len(),
+                offset,
+                line_width,
+                line_width + 1
+            )?;
+
+            for line in seq.chunks(line_width) {
+                fa.write_all(line)?;
+                fa.write_all(b"\n")?;
+                offset += line.len() + 1;
+            }
+        }
+
+        fa.flush()?;
+        fai.flush()?;
+
+        Ok(path)
+    }
+}
+
+#[cfg(feature = "fastq")]
+mod fastq_fixture {
+    use std::{
+        fs::File,
+        io::{BufWriter, Write},
+        path::{Path, PathBuf},
+    };
+
+    use anyhow::Error;
+    use flate2::{Compression, write::GzEncoder};
+
+    struct TestPair {
+        name: String,
+        /// Last field of the header comment, the sample number or index.
+        index: String,
+        reads: [(Vec<u8>, Vec<u8>); 2],
+    }
+
+    /// Builder for a pair of R1/R2 fastq files with reads in the same order.
+    #[derive(Default)]
+    pub(crate) struct TestFastqPair {
+        pairs: Vec<TestPair>,
+    }
+
+    impl TestFastqPair {
+        pub(crate) fn new() -> Self {
+            Self::default()
+        }
+
+        /// Add a pair. Qualities are raw phred, not phred+33.
+        pub(crate) fn add_pair(
+            mut self,
+            name: &str,
+            r1: (&[u8], &[u8]),
+            r2: (&[u8], &[u8]),
+        ) -> Self {
+            for (seq, qual) in [r1, r2] {
+                assert_eq!(seq.len(), qual.len(), "seq and qual length differ");
+            }
+
+            self.pairs.push(TestPair {
+                name: name.to_string(),
+                index: "1".to_string(),
+                reads: [r1, r2].map(|(seq, qual)| {
+                    (seq.to_vec(), qual.iter().map(|q| q + 33).collect())
+                }),
+            });
+            self
+        }
+
+        /// Add a pair with `index`, e.g. `ACGTACGT+GGTTCCAA`, ending its
+        /// headers.
+        pub(crate) fn add_indexed_pair(
+            self,
+            name: &str,
+            index: &str,
+            r1: (&[u8], &[u8]),
+            r2: (&[u8], &[u8]),
+        ) -> Self {
+            let mut this = self.add_pair(name, r1, r2);
+            this.pairs.last_mut().unwrap().index = index.to_string();
+            this
+        }
+
+        /// Add `n` pairs named `pair{i}` with `read_len` bases of quality 30.
+        pub(crate) fn add_pairs(mut self, n: usize, read_len: usize) -> Self {
+            let seq = b"ACGT".repeat(read_len.div_ceil(4));
+            let seq = &seq[..read_len];
+            let qual = vec![30; read_len];
+
+            for _ in 0..n {
+                let name = format!("pair{}", self.pairs.len());
+                self = self.add_pair(&name, (seq, &qual), (seq, &qual));
+            }
+            self
+        }
+
+        /// Write `test_R1.fastq` and `test_R2.fastq` (with a `.gz` suffix and
+        /// gzipped if `gz`) into `dir` and return their paths.
+        pub(crate) fn build(
+            self,
+            dir: impl AsRef<Path>,
+            gz: bool,
+        ) -> Result<(PathBuf, PathBuf), Error> {
+            let ext = if gz { "fastq.gz" } else { "fastq" };
+            let paths = [1, 2].map(|i| dir.as_ref().join(format!("test_R{}.{}", i, ext)));
+
+            for (i, path) in paths.iter().enumerate() {
+                let mut text = vec![];
+                for pair in self.pairs.iter() {
+                    let (seq, qual) = &pair.reads[i];
+                    writeln!(text, "@{} {}:N:0:{}", pair.name, i + 1, pair.index)?;
+                    text.extend_from_slice(seq);
+                    text.extend_from_slice(b"\n+\n");
+                    text.extend_from_slice(qual);
+                    text.push(b'\n');
+                }
+
+                let mut file = BufWriter::new(File::create(path)?);
+                if gz {
+                    let mut encoder = GzEncoder::new(file, Compression::fast());
+                    encoder.write_all(&text)?;
+                    encoder.finish()?.flush()?;
+                } else {
+                    file.write_all(&text)?;
+                    file.flush()?;
+                }
+            }
+
+            let [r1, r2] = paths;
+            Ok((r1, r2))
+        }
+    }
+}
+
+#[cfg(feature = "bam")]
+mod bam_fixture {
+    use std::path::{Path, PathBuf};
+
+    use anyhow::Error;
+    use rust_htslib::bam::{
+        self, Header, Writer,
+        header::HeaderRecord,
+        record::{Aux, Cigar, CigarString},
+    };
+
+    const DEFAULT_CONTIG_LEN: u64 = 1_000_000;
+    /// Contig of the reads of [`TestBam::add_unplaced`].
+    const UNPLACED: &str = "*";
+
+    struct TestRead {
+        qname: Vec<u8>,
+        contig: String,
+        pos: i64,
+        seq: Vec<u8>,
+        qual: Vec<u8>,
+        flags: u16,
+        /// Mate position and template length, for paired reads.
+        mate: Option<(i64, i64)>,
+        /// Contig of the mate, that of the read if not set.
+        mate_contig: Option<String>,
+        /// Full-length match if not set.
+        cigar: Option<CigarString>,
+        /// `RG` tag.
+        read_group: Option<String>,
+        mapq: u8,
+        /// String tags other than `RG`.
+        tags: Vec<([u8; 2], String)>,
+    }
+
+    /// Builder for a small coordinate-sorted, indexed BAM.
+    ///
+    /// Contigs are registered in the order they are first seen, unless declared
+    /// beforehand with [`TestBam::contig`]. Reads are written with a full-length
+    /// match CIGAR unless set with [`TestBam::with_cigar`], and sorted on
+    /// [`TestBam::build`].
+    #[derive(Default)]
+    pub(crate) struct TestBam {
+        contigs: Vec<(String, u64)>,
+        /// `ID` and `SM` of the `@RG` lines.
+        read_groups: Vec<(String, String)>,
+        reads: Vec<TestRead>,
+    }
+
+    impl TestBam {
+        pub(crate) fn new() -> Self {
+            Self::default()
+        }
+
+        /// Declare a contig with the given length.
+        pub(crate) fn contig(mut self, name: &str, len: u64) -> Self {
+            match self.contigs.iter_mut().find(|(n, _)| n == name) {
+                Some((_, l)) => *l = len,
+                None => self.contigs.push((name.to_string(), len)),
+            }
+            self
+        }
+
+        /// Declare a read group of `sample`, set on the reads added after this.
+        pub(crate) fn read_group(mut self, id: &str, sample: &str) -> Self {
+            self.read_groups.push((id.to_string(), sample.to_string()));
+            self
+        }
+
+        /// Add a read at 0-based `pos`. `qual` is raw phred, not phred+33.
+        pub(crate) fn add_read(
+            mut self,
+            contig: &str,
+            pos: i64,
+            seq: &[u8],
+            qual: &[u8],
+            flags: u16,
+        ) -> Self {
+            assert_eq!(seq.len(), qual.len(), "seq and qual length differ");
+
+            if !self.contigs.iter().any(|(n, _)| n == contig) {
+                self.contigs.push((contig.to_string(), DEFAULT_CONTIG_LEN));
+            }
+
+            let qname = format!("read{}", self.reads.len()).into_bytes();
+            self.reads.push(TestRead {
+                qname,
+                contig: contig.to_string(),
+                pos,
+                seq: seq.to_vec(),
+                qual: qual.to_vec(),
+                flags,
+                mate: None,
+                mate_contig: None,
+                cigar: None,
+                read_group: self.read_groups.last().map(|(id, _)| id.clone()),
+                mapq: 60,
+                tags: vec![],
+            });
+            self
+        }
+
+        /// Set the CIGAR of the last added read, e.g. `"10S90M"`.
+        pub(crate) fn with_cigar(mut self, cigar: &str) -> Self {
+            let cigar = CigarString::try_from(cigar).expect("invalid CIGAR");
+            let read = self.reads.last_mut().expect("no read to set the CIGAR of");
+            read.cigar = Some(cigar);
+            self
+        }
+
+        /// Add an unmapped read without a contig, written after the others.
+        pub(crate) fn add_unplaced(mut self, seq: &[u8], qual: &[u8]) -> Self {
+            let contigs = std::mem::take(&mut self.contigs);
+            self = self.add_read(UNPLACED, -1, seq, qual, 0x4);
+            self.contigs = contigs;
+            self.reads.last_mut().unwrap().cigar = Some(CigarString(vec![]));
+            self
+        }
+
+        /// Set the mapping quality of the last added read, 60 by default.
+        pub(crate) fn with_mapq(mut self, mapq: u8) -> Self {
+            let read = self.reads.last_mut().expect("no read to set the mapq of");
+            read.mapq = mapq;
+            self
+        }
+
+        /// Set the mate position and the template length of the last added read.
+        pub(crate) fn with_mate(mut self, mate_pos: i64, tlen: i64) -> Self {
+            let read = self.reads.last_mut().expect("no read to set the mate of");
+            read.mate = Some((mate_pos, tlen));
+            self
+        }
+
+        /// Set the mate of the last added read on another contig, at 0-based
+        /// `mate_pos`, with a template length of 0.
+        pub(crate) fn with_mate_on(mut self, contig: &str, mate_pos: i64) -> Self {
+            let read = self.reads.last_mut().expect("no read to set the mate of");
+            read.mate = Some((mate_pos, 0));
+            read.mate_contig = Some(contig.to_string());
+            self
+        }
+
+        /// Set the name of the last added read, e.g. that of a pair for its
+        /// supplementary alignment.
+        pub(crate) fn with_qname(mut self, qname: &str) -> Self {
+            let read = self.reads.last_mut().expect("no read to set the name of");
+            read.qname = qname.as_bytes().to_vec();
+            self
+        }
+
+        /// Add a string tag to the last added read, e.g. `RX`.
+        pub(crate) fn with_tag(mut self, tag: &[u8; 2], value: &str) -> Self {
+            let read = self.reads.last_mut().expect("no read to set a tag of");
+            read.tags.push((*tag, value.to_string()));
+            self
+        }
+
+        /// Add a proper pair: a forward first read at `pos` and a reverse second read
+        /// at `mate_pos`, both `read_len` long. `extra_flags` are set on both reads.
+        pub(crate) fn add_pair(
+            mut self,
+            contig: &str,
+            pos: i64,
+            mate_pos: i64,
+            read_len: usize,
+            extra_flags: u16,
+        ) -> Self {
+            let seq = b"ACGT".repeat(read_len.div_ceil(4));
+            let seq = &seq[..read_len];
+            let qual = vec![30; read_len];
+            let tlen = mate_pos + read_len as i64 - pos;
+            let qname = format!("pair{}", self.reads.len());
+
+            self = self.add_read(contig, pos, seq, &qual, 0x1 | 0x2 | 0x20 | 0x40 | extra_flags);
+            self = self.add_read(contig, mate_pos, seq, &qual, 0x1 | 0x2 | 0x10 | 0x80 | extra_flags);
+
+            let n = self.reads.len();
+            for (read, (mpos, tlen)) in self.reads[n - 2..]
+                .iter_mut()
+                .zip([(mate_pos, tlen), (pos, -tlen)])
+            {
+                read.qname = qname.clone().into_bytes();
+                read.mate = Some((mpos, tlen));
+            }
+            self
+        }
+
+        /// Add `n` reads of `read_len` bases, spaced `step` bases apart from `start`.
+        pub(crate) fn add_reads(
+            mut self,
+            contig: &str,
+            start: i64,
+            step: i64,
+            n: usize,
+            read_len: usize,
+        ) -> Self {
+            let seq = b"ACGT".repeat(read_len.div_ceil(4));
+            let seq = &seq[..read_len];
+            let qual = vec![30; read_len];
+
+            for i in 0..n {
+                self = self.add_read(contig, start + i as i64 * step, seq, &qual, 0);
+            }
+            self
+        }
+
+        /// Write `test.bam` and its index into `dir` and return the bam path.
+        pub(crate) fn build(self, dir: impl AsRef<Path>) -> Result<PathBuf, Error> {
+            let path = dir.as_ref().join("test.bam");
+            self.write(&path, false)?;
+            bam::index::build(&path, None, bam::index::Type::Bai, 1)?;
+
+            Ok(path)
+        }
+
+        /// Write the reads sorted by name, mates in the order they were added, to
+        /// `test.qname.bam` in `dir`, without index, and return its path.
+        pub(crate) fn build_name_sorted(self, dir: impl AsRef<Path>) -> Result<PathBuf, Error> {
+            let path = dir.as_ref().join("test.qname.bam");
+            self.write(&path, true)?;
+
+            Ok(path)
+        }
+
+        fn write(&self, path: &Path, by_name: bool) -> Result<(), Error> {
+            let mut header = Header::new();
+            header.push_record(
+                HeaderRecord::new(b"HD")
+                    .push_tag(b"VN", "1.6")
+                    .push_tag(b"SO", if by_name { "queryname" } else { "coordinate" }),
+            );
+            for (name, len) in self.contigs.iter() {
+                header.push_record(
+                    HeaderRecord::new(b"SQ")
+                        .push_tag(b"SN", name)
+                        .push_tag(b"LN", len),
+                );
+            }
+            for (id, sample) in self.read_groups.iter() {
+                header.push_record(
+                    HeaderRecord::new(b"RG")
+                        .push_tag(b"ID", id)
+                        .push_tag(b"SM", sample),
+                );
+            }
+
+            let tid_of = |contig: &str| match contig {
+                UNPLACED => -1,
+                _ => self.contigs.iter().position(|(n, _)| n == contig).unwrap() as i32,
+            };
+
+            let mut reads = self.reads.iter().collect::<Vec<_>>();
+            if by_name {
+                reads.sort_by_key(|r| &r.qname);
+            } else {
+                // unplaced reads, of tid -1, last.
+                reads.sort_by_key(|r| (tid_of(&r.contig) as u32, r.pos));
+            }
+
+            {
+                let mut writer = Writer::from_path(path, &header, bam::Format::Bam)?;
+
+                for read in reads {
+                    // new for each read, as `set` keeps the aux fields.
+                    let mut record = bam::Record::new();
+                    let cigar = match &read.cigar {
+                        Some(cigar) => cigar.clone(),
+                        None => CigarString(vec![Cigar::Match(read.seq.len() as u32)]),
+                    };
+                    record.set(&read.qname, Some(&cigar), &read.seq, &read.qual);
+                    record.set_tid(tid_of(&read.contig));
+                    record.set_pos(read.pos);
+                    record.set_mapq(read.mapq);
+                    record.set_flags(read.flags);
+                    match read.mate {
+                        Some((mpos, tlen)) => {
+                            let mate_contig = read.mate_contig.as_ref().unwrap_or(&read.contig);
+                            record.set_mtid(tid_of(mate_contig));
+                            record.set_mpos(mpos);
+                            record.set_insert_size(tlen);
+                        }
+                        None => {
+                            record.set_mtid(-1);
+                            record.set_mpos(-1);
+                            record.set_insert_size(0);
+                        }
+                    }
+                    if let Some(rg) = &read.read_group {
+                        record.push_aux(b"RG", Aux::String(rg))?;
+                    }
+                    for (tag, value) in &read.tags {
+                        record.push_aux(tag, Aux::String(value))?;
+                    }
+
+                    writer.write(&record)?;
+                }
+            }
+
+            Ok(())
+        }
+    }
+}
@@ -55,10 +55,15 @@ pub fn setup_logging_to_stderr_and_file(
 }
 
 fn get_tmp_dir() -> String {
-    match env::var("TMPDIR").or_else(|_| env::var("TEMP")) {
-        Ok(v) => v,
-        Err(_) => "log".into(),
-    }
+    tmp_dir_from(|key| env::var(key).ok())
+}
+
+/// `TMPDIR`, `TEMP` or `TMP` (Windows), falling back to the platform temp dir.
+fn tmp_dir_from(lookup: impl Fn(&str) -> Option<String>) -> String {
+    ["TMPDIR", "TEMP", "TMP"]
+        .into_iter()
+        .find_map(|key| lookup(key).filter(|v| !v.is_empty()))
+        .unwrap_or_else(|| env::temp_dir().to_string_lossy().into_owned())
 }
 
 fn get_env_filter(level: filter::LevelFilter) -> Result<filter::EnvFilter, Error> {
@@ -86,7 +91,7 @@ fn get_env_filter(level: filter::LevelFilter) -> Result<filter::EnvFilter, Error
 }
 
 /// # Example
-/// ```rust
+/// ```ignore
 /// let stderr_layer = tracing_subscriber::fmt::layer()
 ///    .pretty()
 /// .with_writer(io::stderr);
@@ -537,12 +542,44 @@ pub fn global_tracing_control_tower() -> &'static TracingControlTower {
 
 #[cfg(test)]
 mod test {
+    use std::process::Command;
+
     use tracing::{Level, event};
 
     use super::*;
-    
+
+    /// Set in the child process running a test alone.
+    const ALONE_VAR: &str = "CRACKLE_KIT_TEST_ALONE";
+
+    /// Whether this is the process to run `test` in. The global subscriber set
+    /// by the `setup_logging_*` functions can only be set once in a process, so
+    /// unless it is the child running `test` alone, run that child, with `envs`,
+    /// and check that it passed.
+    fn run_alone(test: &str, envs: &[(&str, &Path)]) -> bool {
+        if env::var_os(ALONE_VAR).is_some_and(|alone| alone == test) {
+            return true;
+        }
+        let output = Command::new(env::current_exe().unwrap())
+            .args(["--exact", &format!("tracing_kit::test::{}", test)])
+            .env(ALONE_VAR, test)
+            .envs(envs.iter().copied())
+            .output()
+            .unwrap();
+        assert!(
+            output.status.success(),
+            "{} failed alone:\n{}{}",
+            test,
+            String::from_utf8_lossy(&output.stdout),
+            String::from_utf8_lossy(&output.stderr)
+        );
+        false
+    }
+
     #[test]
     fn test_cc() -> Result<(), Box<dyn std::error::Error>> {
+        if !run_alone("test_cc", &[]) {
+            return Ok(());
+        }
         let cc = TracingControlTower::default();
 
         let sh = setup_logging_stderr_only(LevelFilter::DEBUG)?;
@@ -586,7 +623,11 @@ mod test {
 
     #[test]
     fn test_rolling() {
-        // setup_logging_to_stderr_and_file("test.log").unwrap();
+        // the logs go to `TMPDIR`.
+        let dir = tempfile::tempdir().unwrap();
+        if !run_alone("test_rolling", &[("TMPDIR", dir.path())]) {
+            return;
+        }
         setup_logging_to_stderr_and_rolling_file(env!("CARGO_PKG_NAME")).unwrap();
 
         event!(Level::TRACE, "trace!");
@@ -594,17 +635,57 @@ mod test {
         event!(Level::INFO, "info!");
         event!(Level::WARN, "trace!");
         event!(Level::ERROR, "error!");
+
+        let logs = fs::read_dir(get_tmp_dir())
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| {
+                let name = path.file_name().unwrap().to_string_lossy();
+                name.starts_with(env!("CARGO_PKG_NAME")) && name.ends_with(".log")
+            })
+            .map(|path| fs::read_to_string(path).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(logs.len(), 1);
+        assert!(logs[0].contains("debug!") && logs[0].contains("error!"));
     }
 
     #[test]
     fn test_append() {
-        setup_logging_to_stderr_and_file("test.log").unwrap();
-        // setup_logging_to_stderr_and_rolling_file("crackle-kit").unwrap();
+        if !run_alone("test_append", &[]) {
+            return;
+        }
+        let dir = tempfile::tempdir().unwrap();
+        let log_path = dir.path().join("test.log");
+        fs::write(&log_path, "earlier run\n").unwrap();
+        setup_logging_to_stderr_and_file(&log_path).unwrap();
 
         event!(Level::TRACE, "trace!");
         event!(Level::DEBUG, "debug!");
         event!(Level::INFO, "info!");
         event!(Level::WARN, "trace!");
         event!(Level::ERROR, "error!");
+
+        let log = fs::read_to_string(&log_path).unwrap();
+        assert!(log.starts_with("earlier run\n"), "{}", log);
+        assert!(log.contains("debug!") && log.contains("error!"), "{}", log);
+    }
+
+    #[test]
+    fn test_tmp_dir_lookup_order() {
+        let env = |vars: &'static [(&'static str, &'static str)]| {
+            move |key: &str| {
+                vars.iter()
+                    .find(|(k, _)| *k == key)
+                    .map(|(_, v)| v.to_string())
+            }
+        };
+
+        assert_eq!(tmp_dir_from(env(&[("TEMP", "/b"), ("TMPDIR", "/a")])), "/a");
+        assert_eq!(tmp_dir_from(env(&[("TMP", "/c"), ("TEMP", "/b")])), "/b");
+        assert_eq!(tmp_dir_from(env(&[("TMPDIR", ""), ("TMP", "/c")])), "/c");
+        assert_eq!(
+            tmp_dir_from(env(&[])),
+            env::temp_dir().to_string_lossy()
+        );
     }
 }
@@ -1,10 +1,38 @@
+pub mod atomic_write;
 pub mod binning;
+pub mod bloom;
+pub mod columnar;
+pub mod fmt;
+pub mod merge;
+pub mod pool;
+pub mod retry;
 pub mod rounding;
+pub mod seq_stats;
+pub mod stable_hash;
+pub mod textio;
+pub mod thread_budget;
+pub mod umi;
 
 #[cfg(feature = "batch-work")]
 pub mod batch_region;
+#[cfg(feature = "bam")]
+pub mod batch_tuning;
 #[cfg(feature = "batch-work")]
 pub mod batched_channel;
 #[cfg(feature = "batch-work")]
 pub mod batched_data;
+#[cfg(feature = "batch-work")]
+pub mod channel_metrics;
+#[cfg(feature = "batch-work")]
+pub(crate) mod instrument;
+#[cfg(feature = "metrics")]
+pub mod metrics;
+#[cfg(feature = "batch-work")]
+pub mod panic;
+#[cfg(feature = "batch-work")]
+pub mod pipeline;
+#[cfg(all(test, feature = "batch-work"))]
+pub(crate) mod pipeline_test_kit;
+#[cfg(feature = "tracing")]
+pub mod throughput;
 pub mod traits;
\ No newline at end of file
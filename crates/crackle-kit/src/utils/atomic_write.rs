@@ -0,0 +1,173 @@
+//! Files which appear at their final path complete or not at all.
+
+use std::{
+    ffi::OsString,
+    fs::{self, File},
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+use crate::errors::Error;
+
+/// File written at `{path}.tmp.{pid}` and renamed to `path` by [`Self::commit`].
+///
+/// The temp file is in the directory of `path`, so the rename is atomic: a crash
+/// or an error leaves either the previous content of `path` or the new one, never
+/// a truncated file. It is removed by [`Self::abort`], or when dropped uncommitted.
+///
+/// Writers which open files by path themselves, like htslib's, can write to
+/// [`Self::tmp_path`] instead of through [`Self::file`].
+#[derive(Debug)]
+pub struct AtomicFile {
+    path: PathBuf,
+    tmp_path: PathBuf,
+    file: Option<File>,
+    fsync: bool,
+}
+
+impl AtomicFile {
+    /// Create the temp file of `path`, truncating a leftover one of this process.
+    pub fn create(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let path = path.as_ref().to_path_buf();
+        let tmp_path = tmp_path_of(&path);
+        let file = File::create(&tmp_path).map_err(|e| Error::io("create", &tmp_path, e))?;
+
+        Ok(Self {
+            path,
+            tmp_path,
+            file: Some(file),
+            fsync: false,
+        })
+    }
+
+    /// Flush the file and its directory to the disk on [`Self::commit`], so the
+    /// output survives a power loss as well. Off by default.
+    pub fn with_fsync(mut self, fsync: bool) -> Self {
+        self.fsync = fsync;
+        self
+    }
+
+    /// The final path.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The path written to until [`Self::commit`].
+    pub fn tmp_path(&self) -> &Path {
+        &self.tmp_path
+    }
+
+    pub fn file(&mut self) -> &mut File {
+        self.file
+            .as_mut()
+            .expect("file is taken on commit or abort only")
+    }
+
+    /// Rename the temp file to the final path, replacing any file there.
+    pub fn commit(mut self) -> Result<(), Error> {
+        let mut file = self
+            .file
+            .take()
+            .expect("file is taken on commit or abort only");
+        file.flush()
+            .map_err(|e| Error::io("write", &self.tmp_path, e))?;
+        if self.fsync {
+            file.sync_all()
+                .map_err(|e| Error::io("sync", &self.tmp_path, e))?;
+        }
+        drop(file);
+
+        if let Err(err) = fs::rename(&self.tmp_path, &self.path) {
+            let _ = fs::remove_file(&self.tmp_path);
+            return Err(Error::io("rename the temp file to", &self.path, err));
+        }
+
+        if self.fsync {
+            // the rename itself is durable once the directory is synced.
+            let dir = match self.path.parent() {
+                Some(dir) if !dir.as_os_str().is_empty() => dir,
+                _ => Path::new("."),
+            };
+            File::open(dir)
+                .and_then(|dir| dir.sync_all())
+                .map_err(|e| Error::io("sync", dir, e))?;
+        }
+
+        Ok(())
+    }
+
+    /// Remove the temp file, leaving the final path untouched.
+    pub fn abort(mut self) -> Result<(), Error> {
+        let tmp_path = self.tmp_path.clone();
+        self.remove_tmp()
+            .map_err(|e| Error::io("remove", tmp_path, e))
+    }
+
+    fn remove_tmp(&mut self) -> io::Result<()> {
+        match self.file.take() {
+            Some(file) => {
+                drop(file);
+                fs::remove_file(&self.tmp_path)
+            }
+            None => Ok(()),
+        }
+    }
+}
+
+impl Write for AtomicFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.file().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file().flush()
+    }
+}
+
+impl Drop for AtomicFile {
+    fn drop(&mut self) {
+        let _ = self.remove_tmp();
+    }
+}
+
+fn tmp_path_of(path: &Path) -> PathBuf {
+    let mut name = OsString::from(path.as_os_str());
+    name.push(format!(".tmp.{}", std::process::id()));
+    PathBuf::from(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_commit_and_abort() -> Result<(), anyhow::Error> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("out.tsv");
+        fs::write(&path, "old\n")?;
+
+        let mut file = AtomicFile::create(&path)?.with_fsync(true);
+        writeln!(file, "new")?;
+        let tmp_path = file.tmp_path().to_path_buf();
+        assert!(tmp_path.exists());
+        assert_eq!(fs::read_to_string(&path)?, "old\n");
+
+        file.commit()?;
+        assert_eq!(fs::read_to_string(&path)?, "new\n");
+        assert!(!tmp_path.exists());
+
+        let mut file = AtomicFile::create(&path)?;
+        writeln!(file.file(), "aborted")?;
+        file.abort()?;
+        assert_eq!(fs::read_to_string(&path)?, "new\n");
+        assert!(!tmp_path.exists());
+
+        // dropped without commit.
+        let mut file = AtomicFile::create(dir.path().join("dropped.tsv"))?;
+        writeln!(file, "dropped")?;
+        drop(file);
+        assert_eq!(fs::read_dir(dir.path())?.count(), 1);
+
+        Ok(())
+    }
+}
@@ -7,7 +7,8 @@ use crate::data::locus::GenomeRegion;
 /// of the current region does not exceed `window_size`.
 ///
 /// # Arguments
-/// * `input` - An iterator yielding items convertible into `GenomeRegion`.
+/// * `input` - An iterator yielding items convertible into `GenomeRegion`. Borrowed
+///   regions (e.g. `regions.iter()`) are accepted and cloned as they are batched.
 /// * `window_size` - The maximum allowed span (in base pairs) for a single batch.
 ///
 /// # Returns
@@ -65,6 +66,20 @@ mod tests {
         assert_eq!(batches, Vec::<Vec<GenomeRegion>>::new());
     }
 
+    #[test]
+    fn test_batch_region_borrowed_input() {
+        let regions = [
+            GenomeRegion::from(("chr1", 10, 20)),
+            GenomeRegion::from(("chr1", 25, 35)),
+            GenomeRegion::from(("chr2", 40, 50)),
+        ];
+        let batches = batch_region(regions.iter(), 100);
+
+        assert_eq!(batches, batch_region(regions.clone().into_iter(), 100));
+        assert_eq!(batches.len(), 2);
+        assert_eq!(batches[1], vec![regions[2].clone()]);
+    }
+
     #[test]
     fn test_batch_region_single_region_fits_window() {
         let regions = vec![
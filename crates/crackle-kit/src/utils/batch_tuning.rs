@@ -0,0 +1,209 @@
+//! Choice of the `batch_window_size` of
+//! [`ParallelLocusProcessorPileup::process_with_batch`] from the density of
+//! the inputs.
+//!
+//! A small window makes many batches, each paying for a fetch; a large one
+//! makes batches with huge pileups, run by a single thread.
+//!
+//! [`ParallelLocusProcessorPileup::process_with_batch`]: crate::bam::process::ParallelLocusProcessorPileup::process_with_batch
+
+use std::collections::HashMap;
+
+use crate::bam::process::{BamLocusWorkInput, batch_indices_by_coordinate};
+
+/// Largest window suggested by [`ParallelLocusProcessorPileup::process_with_auto_batch`].
+///
+/// [`ParallelLocusProcessorPileup::process_with_auto_batch`]: crate::bam::process::ParallelLocusProcessorPileup::process_with_auto_batch
+pub const DEFAULT_MAX_WINDOW: usize = 1_000_000;
+
+/// A window expected to make batches of about `target_loci_per_batch` inputs.
+///
+/// The window of a contig is its median spacing between consecutive inputs
+/// times the target; the windows of contigs are combined by their median,
+/// weighted by the number of spacings. Inputs at the same position, and inputs
+/// going back, add no spacing. The result is clamped to `1..=max_window`, and
+/// is `max_window` if no contig has two inputs.
+pub fn suggest_window<'a, I: BamLocusWorkInput<'a>>(
+    inputs: &[I],
+    target_loci_per_batch: usize,
+    max_window: usize,
+) -> usize {
+    let max_window = max_window.max(1);
+
+    let mut last_pos = HashMap::new();
+    let mut spacings = HashMap::<_, Vec<i64>>::new();
+    for inp in inputs {
+        let gc = inp.genome_coordinate();
+        if let Some(prev) = last_pos.insert(&gc.contig, gc.pos)
+            && gc.pos > prev
+        {
+            spacings.entry(&gc.contig).or_default().push(gc.pos - prev);
+        }
+    }
+
+    // (window, weight) per contig.
+    let mut windows = spacings
+        .into_values()
+        .map(|mut spacings| {
+            let spacing = lower_median(&mut spacings) as usize;
+            (
+                spacing.saturating_mul(target_loci_per_batch),
+                spacings.len(),
+            )
+        })
+        .collect::<Vec<_>>();
+    if windows.is_empty() {
+        return max_window;
+    }
+
+    windows.sort_unstable();
+    let half = windows.iter().map(|(_, n)| n).sum::<usize>().div_ceil(2);
+    let mut n = 0;
+    let window = windows
+        .iter()
+        .find(|(_, weight)| {
+            n += weight;
+            n >= half
+        })
+        .map(|(window, _)| *window)
+        .unwrap();
+
+    window.clamp(1, max_window)
+}
+
+/// Sizes of the batches made from inputs by a window, see [`describe_batching`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BatchingReport {
+    pub n_batches: usize,
+    pub min_loci: usize,
+    pub median_loci: usize,
+    pub max_loci: usize,
+    /// Span in bp, from the first to the last position of a batch.
+    pub min_span: usize,
+    pub median_span: usize,
+    pub max_span: usize,
+}
+
+/// Sizes of the batches `inputs` would be split into with `window`, to be
+/// checked or logged before a run. Medians are the lower ones; all fields are
+/// 0 if there are no inputs.
+pub fn describe_batching<'a, I: BamLocusWorkInput<'a>>(
+    inputs: &[I],
+    window: usize,
+) -> BatchingReport {
+    let batches = batch_indices_by_coordinate(inputs, window);
+    if batches.is_empty() {
+        return BatchingReport::default();
+    }
+
+    let mut loci = batches.iter().map(|b| b.len()).collect::<Vec<_>>();
+    let mut spans = batches
+        .iter()
+        .map(|b| {
+            let first = inputs[b.start].genome_coordinate().pos;
+            let last = inputs[b.end - 1].genome_coordinate().pos;
+            (last - first + 1) as usize
+        })
+        .collect::<Vec<_>>();
+
+    BatchingReport {
+        n_batches: batches.len(),
+        min_loci: *loci.iter().min().unwrap(),
+        median_loci: lower_median(&mut loci),
+        max_loci: *loci.iter().max().unwrap(),
+        min_span: *spans.iter().min().unwrap(),
+        median_span: lower_median(&mut spans),
+        max_span: *spans.iter().max().unwrap(),
+    }
+}
+
+fn lower_median<T: Ord + Copy>(values: &mut [T]) -> T {
+    let mid = (values.len() - 1) / 2;
+    *values.select_nth_unstable(mid).1
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::{chrom::Chrom, locus::GenomeCoordinate};
+
+    fn coords(
+        contig: &'static str,
+        positions: impl IntoIterator<Item = i64>,
+    ) -> Vec<GenomeCoordinate<'static>> {
+        positions
+            .into_iter()
+            .map(|pos| GenomeCoordinate {
+                contig: Chrom::from(contig),
+                pos,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_suggest_window_uniform() {
+        let inputs = coords("chr1", (1..=100_000).step_by(100));
+
+        let window = suggest_window(&inputs, 50, DEFAULT_MAX_WINDOW);
+        assert_eq!(window, 5_000);
+        let report = describe_batching(&inputs, window);
+        assert_eq!(
+            report,
+            BatchingReport {
+                n_batches: 20,
+                min_loci: 50,
+                median_loci: 50,
+                max_loci: 50,
+                min_span: 4_901,
+                median_span: 4_901,
+                max_span: 4_901,
+            }
+        );
+
+        // more loci per batch, a larger window; clamped to the max.
+        assert_eq!(suggest_window(&inputs, 100, DEFAULT_MAX_WINDOW), 10_000);
+        assert_eq!(suggest_window(&inputs, 100, 8_000), 8_000);
+    }
+
+    #[test]
+    fn test_suggest_window_clustered() {
+        // clusters of 20 loci 10 bp apart, 100 kb apart.
+        let clustered = coords(
+            "chr1",
+            (0..50).flat_map(|c| (0..20).map(move |i| 1 + c * 100_000 + i * 10)),
+        );
+        // as many loci spread over the same span.
+        let uniform = coords("chr1", (0..1_000).map(|i| 1 + i * 5_000));
+
+        let clustered_window = suggest_window(&clustered, 20, DEFAULT_MAX_WINDOW);
+        let uniform_window = suggest_window(&uniform, 20, DEFAULT_MAX_WINDOW);
+        assert_eq!(clustered_window, 200);
+        assert_eq!(uniform_window, 100_000);
+
+        // a cluster per batch.
+        let report = describe_batching(&clustered, clustered_window);
+        assert_eq!(
+            (report.n_batches, report.median_loci, report.max_span),
+            (50, 20, 191)
+        );
+    }
+
+    #[test]
+    fn test_suggest_window_contigs() {
+        // the contig with more inputs weighs more.
+        let mut inputs = coords("chr1", (1..=10_000).step_by(10));
+        inputs.extend(coords("chr2", (1..=10_000).step_by(1_000)));
+        assert_eq!(suggest_window(&inputs, 10, DEFAULT_MAX_WINDOW), 100);
+
+        // spacings are not taken across contigs.
+        let report = describe_batching(&inputs, 100);
+        assert_eq!((report.n_batches, report.min_loci), (110, 1));
+
+        // no spacing at all.
+        assert_eq!(suggest_window(&coords("chr1", [5]), 10, 1_000), 1_000);
+        assert_eq!(suggest_window(&coords("chr1", [5, 5]), 10, 1_000), 1_000);
+        let empty = coords("chr1", []);
+        assert_eq!(suggest_window(&empty, 10, 1_000), 1_000);
+        assert_eq!(describe_batching(&empty, 1_000), BatchingReport::default());
+    }
+}
@@ -3,6 +3,7 @@ use crossbeam_channel::{Receiver, SendError, Sender, bounded};
 
 use crate::utils::batched_data::BatchedData;
 
+#[derive(Debug)]
 pub struct ChannelPair<T> {
     pub tx: crossbeam_channel::Sender<T>,
     pub rx: crossbeam_channel::Receiver<T>,
@@ -35,19 +36,20 @@ Needed
 */
 
 ///
-/// ```
+/// ```ignore
 /// let bc = BatchedChannel::default();
 ///
-/// if Ok(data) = bc.data.rx.try_recv() {
+/// if let Ok(data) = bc.data.rx.try_recv() {
 ///     // check data is filled.
 ///     
 ///
 /// }
 ///
-/// if Ok(buffer) = bc.buffer.rx.try_recv() {
+/// if let Ok(buffer) = bc.buffer.rx.try_recv() {
 ///
 /// }
 /// ```
+#[derive(Debug)]
 pub struct BatchedChannel<T> {
     data: ChannelPair<BatchedData<T>>,
     buffer: ChannelPair<BatchedData<T>>,
@@ -1,3 +1,4 @@
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct BatchedData<T> {
     inner: Vec<T>,
     next_item_idx: usize,
@@ -55,6 +56,12 @@ impl<T> BatchedData<T> {
         }
     }
 
+    /// Give back the slot returned by the last `next_mut()` call,
+    /// e.g. when the data source turned out to be exhausted.
+    pub fn unfill_last(&mut self) {
+        self.next_item_idx = self.next_item_idx.saturating_sub(1);
+    }
+
     #[inline]
     pub fn increment_idx(&mut self) {
         self.next_item_idx += 1;
@@ -188,6 +195,11 @@ mod tests {
 
         // Try to get another item, should be None
         assert!(batch.next_mut().is_none());
+
+        // Give back the last slot
+        batch.unfill_last();
+        assert!(!batch.is_full());
+        assert_eq!(batch.filled(), &[100, 200]);
     }
 
     #[test]
@@ -272,4 +284,20 @@ mod tests {
         let res3 = batch.modify_next(|_| "third".to_string());
         assert_eq!(res3, None);
     }
+
+    #[test]
+    fn test_debug_clone_eq() {
+        let mut batch = BatchedData::from_vec(vec![0; 3]);
+        *batch.next_mut().unwrap() = 7;
+        assert_eq!(
+            format!("{:?}", batch),
+            "BatchedData { inner: [7, 0, 0], next_item_idx: 1 }"
+        );
+
+        // the fill index is compared as well.
+        let mut cloned = batch.clone();
+        assert_eq!(cloned, batch);
+        cloned.reset_index();
+        assert_ne!(cloned, batch);
+    }
 }
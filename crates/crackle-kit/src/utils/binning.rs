@@ -24,28 +24,55 @@ pub fn make_bins(start: usize, end: usize, bin_size: usize) -> Vec<(usize, usize
 mod fasta {
     use std::{collections::HashMap, str::FromStr};
 
-    use crate::data::chrom::Chrom;
+    use crate::{
+        data::{chrom::Chrom, interval::RegionSet, locus::GenomeRegion},
+        reference::ref_gaps,
+    };
 
     use super::*;
     use anyhow::Error;
     use bio::io::fasta::IndexedReader;
 
+    /// 0-based, half-open bins of `bin_size` of each contig of the fasta. With
+    /// `min_gap_len`, the bins are of the bases between the assembly gaps, the
+    /// runs of at least `min_gap_len` N, see [`ref_gaps`].
     pub fn make_bins_from_fasta<'a>(
         fasta_file: impl AsRef<Path>,
         bin_size: usize,
+        min_gap_len: Option<u64>,
     ) -> Result<HashMap<Chrom<'a>, Vec<(usize, usize)>>, Error> {
         let ir = IndexedReader::from_file(&fasta_file.as_ref())?;
+        let gaps = match min_gap_len {
+            Some(min_gap_len) => RegionSet::new(ref_gaps(&fasta_file, min_gap_len)?, true),
+            None => RegionSet::default(),
+        };
 
         let mut res = HashMap::with_capacity(ir.index.sequences().len());
         for seq in ir.index.sequences() {
-            let bins = make_bins(0, seq.len as usize, bin_size);
-            res.insert(Chrom::from_str(&seq.name).unwrap(), bins);
+            let chrom = Chrom::from_str(&seq.name).unwrap();
+            let contig = GenomeRegion {
+                contig: chrom.clone(),
+                start: 1,
+                end: seq.len as i64,
+            };
+            let bins = RegionSet::new([contig], false)
+                .subtract(&gaps)
+                .into_iter()
+                .flat_map(|piece| {
+                    let start = piece.start as usize - 1;
+                    make_bins(start, piece.end as usize, bin_size)
+                })
+                .collect();
+            res.insert(chrom, bins);
         }
 
         Ok(res)
     }
 }
 
+#[cfg(feature = "bio")]
+pub use fasta::*;
+
 // --- Test Functions ---
 #[cfg(test)] // This attribute tells Cargo to compile this module only when running tests
 mod tests {
@@ -151,61 +178,84 @@ mod tests {
 #[cfg(test)]
 #[cfg(feature = "bio")]
 mod fasta_tests {
-    use crate::{data::chrom::Chrom, utils::binning::fasta::make_bins_from_fasta};
+    use std::{collections::HashMap, str::FromStr};
 
-    use super::*;
+    use crate::{
+        data::chrom::Chrom, test_utils::write_test_fasta, utils::binning::make_bins_from_fasta,
+    };
 
     #[test]
-    fn test_make_bins_from_fasta() {
-        use std::collections::HashMap;
-        use std::fs::File;
-        use std::io::Write;
-
-        use crate::data::chrom::Chrom;
-
-        // 1. Create a dummy FASTA file
-        let fasta_path = "test_bins.fa";
-        let mut fasta_file = File::create(fasta_path).unwrap();
-        writeln!(fasta_file, ">chr1").unwrap();
-        writeln!(fasta_file, "ACGTACGTACGT").unwrap(); // 12 bases
-        writeln!(fasta_file, ">chrM").unwrap();
-        writeln!(fasta_file, "NNNNN").unwrap(); // 5 bases
-
-        // 2. Create a corresponding FASTA index file (.fai)
-        let fai_path = "test_bins.fa.fai";
-        let mut fai_file = File::create(fai_path).unwrap();
-        // Format: name, length, offset, line_bases, line_width
-        writeln!(fai_file, "chr1\t12\t6\t12\t13").unwrap();
-        writeln!(fai_file, "chrM\t5\t25\t5\t6").unwrap();
-
-        // 3. Call the function to be tested
+    fn test_make_bins_from_fasta() -> Result<(), Box<dyn std::error::Error>> {
+        // 1. Create a dummy FASTA file and its index
+        let dir = tempfile::tempdir()?;
+        let fasta_path = write_test_fasta(
+            dir.path(),
+            &[("chr1", &b"ACGTACGTACGT"[..]), ("chrM", &b"NNNNN"[..])],
+        )?;
+
+        // 2. Call the function to be tested
         let bin_size = 10;
-        let result = fasta::make_bins_from_fasta(fasta_path, bin_size).unwrap();
+        let result = make_bins_from_fasta(&fasta_path, bin_size, None)?;
 
-        // 4. Define the expected output
+        // 3. Define the expected output
         let mut expected = HashMap::new();
         expected.insert(Chrom::Chr1, vec![(0, 10), (10, 12)]);
         expected.insert(Chrom::ChrM, vec![(0, 5)]);
 
-        // 5. Assert correctness
+        // 4. Assert correctness
         assert_eq!(result.len(), 2);
         assert_eq!(result.get(&Chrom::Chr1), Some(&vec![(0, 10), (10, 12)]));
         assert_eq!(result.get(&Chrom::ChrM), Some(&vec![(0, 5)]));
         assert_eq!(result, expected);
 
-        // 6. Clean up the dummy files
-        std::fs::remove_file(fasta_path).unwrap();
-        std::fs::remove_file(fai_path).unwrap();
+        Ok(())
+    }
+
+    #[test]
+    fn make_bins_with_refseq_contig_names() -> Result<(), Box<dyn std::error::Error>> {
+        // GRCh38 RefSeq style names, which are not standard chromosomes.
+        let dir = tempfile::tempdir()?;
+        let fasta_file = write_test_fasta(
+            dir.path(),
+            &[
+                ("NC_000001.11", b"A".repeat(250).as_slice()),
+                ("NC_000002.12", b"C".repeat(130).as_slice()),
+                ("NT_187361.1", b"G".repeat(10).as_slice()),
+            ],
+        )?;
+        let mut bin_map = make_bins_from_fasta(&fasta_file, 100, None)?;
+
+        let keep = ["NC_000001.11", "NC_000002.12"].map(|n| Chrom::from_str(n).unwrap());
+        bin_map.retain(|k, _| keep.contains(k));
+
+        assert_eq!(bin_map.len(), 2);
+        assert_eq!(bin_map[&keep[0]], vec![(0, 100), (100, 200), (200, 250)]);
+        assert_eq!(bin_map[&keep[1]], vec![(0, 100), (100, 130)]);
+
+        Ok(())
     }
 
     #[test]
-    fn make_bins_using_grch38_fasta() -> Result<(), Box<dyn std::error::Error>> {
-        let fasta_file =
-            "/home/eck/workspace/common_resources/GCF_000001405.40_GRCh38.p14_genomic.fna.gz";
-        let mut bin_map = make_bins_from_fasta(fasta_file, 100_000_000)?;
-        bin_map.retain(|k, v| ["NC_000002.12", "NC_000001.11"].contains(&k.as_str()));
+    fn make_bins_without_gaps() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = tempfile::tempdir()?;
+        // gaps at 5-16 and 1-10; the 5 N at 35-39 are too few to be one.
+        let fasta_file = write_test_fasta(
+            dir.path(),
+            &[
+                ("chr1", &b"ACGTNNNNNNNNNNNNACGTacgtacGGNNCCCCnnnnn"[..]),
+                ("chr2", &b"NNNNNNNNNNACGT"[..]),
+            ],
+        )?;
+
+        let bin_map = make_bins_from_fasta(&fasta_file, 10, Some(10))?;
+        assert_eq!(
+            bin_map[&Chrom::Chr1],
+            vec![(0, 4), (16, 26), (26, 36), (36, 39)]
+        );
+        assert_eq!(bin_map[&Chrom::Chr2], vec![(10, 14)]);
 
-        println!("{:?}", bin_map);
+        let bin_map = make_bins_from_fasta(&fasta_file, 10, None)?;
+        assert_eq!(bin_map[&Chrom::Chr2], vec![(0, 10), (10, 14)]);
 
         Ok(())
     }
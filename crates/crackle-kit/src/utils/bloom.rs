@@ -0,0 +1,101 @@
+//! Bloom filters, sets of byte strings in a fixed number of bits which may
+//! report an item absent from them as present, at a rate set when made.
+
+use std::{
+    f64::consts::LN_2,
+    hash::{DefaultHasher, Hash, Hasher},
+};
+
+/// Bloom filter of byte strings.
+///
+/// Items are hashed by SipHash with fixed keys, the same in every run, and
+/// indexed by double hashing.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BloomFilter {
+    bits: Vec<u64>,
+    n_bits: u64,
+    n_hashes: u32,
+}
+
+impl BloomFilter {
+    /// A filter sized for `expected_items` at a false positive rate of
+    /// `fp_rate`, or more once it holds more items.
+    ///
+    /// # Panics
+    ///
+    /// If `fp_rate` is not within (0, 1).
+    pub fn new(expected_items: usize, fp_rate: f64) -> Self {
+        assert!(
+            fp_rate > 0.0 && fp_rate < 1.0,
+            "false positive rate must be within (0, 1), got {}",
+            fp_rate
+        );
+        let n = expected_items.max(1) as f64;
+        let n_bits = (-n * fp_rate.ln() / (LN_2 * LN_2)).ceil().max(64.0) as u64;
+        let n_hashes = (n_bits as f64 / n * LN_2).round().clamp(1.0, 32.0) as u32;
+
+        Self {
+            bits: vec![0; n_bits.div_ceil(64) as usize],
+            n_bits,
+            n_hashes,
+        }
+    }
+
+    pub fn n_bits(&self) -> u64 {
+        self.n_bits
+    }
+
+    pub fn n_hashes(&self) -> u32 {
+        self.n_hashes
+    }
+
+    pub fn insert(&mut self, item: &[u8]) {
+        for i in self.indices(item) {
+            self.bits[(i / 64) as usize] |= 1 << (i % 64);
+        }
+    }
+
+    /// Whether `item` may have been inserted. `false` is certain.
+    pub fn contains(&self, item: &[u8]) -> bool {
+        self.indices(item)
+            .all(|i| self.bits[(i / 64) as usize] & (1 << (i % 64)) != 0)
+    }
+
+    fn indices(&self, item: &[u8]) -> impl Iterator<Item = u64> + use<> {
+        let hash = |seed: u64| {
+            let mut hasher = DefaultHasher::new();
+            seed.hash(&mut hasher);
+            item.hash(&mut hasher);
+            hasher.finish()
+        };
+        let (h1, h2) = (hash(0), hash(1) | 1);
+        let n_bits = self.n_bits;
+        (0..self.n_hashes as u64).map(move |i| h1.wrapping_add(i.wrapping_mul(h2)) % n_bits)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bloom_filter() {
+        let mut filter = BloomFilter::new(10_000, 0.01);
+        // 9.6 bits and 7 hashes per item.
+        assert_eq!((filter.n_bits(), filter.n_hashes()), (95_851, 7));
+
+        for i in 0..10_000 {
+            filter.insert(format!("read{}", i).as_bytes());
+        }
+        assert!((0..10_000).all(|i| filter.contains(format!("read{}", i).as_bytes())));
+
+        let n_false = (0..100_000)
+            .filter(|i| filter.contains(format!("other{}", i).as_bytes()))
+            .count();
+        assert!(
+            (500..1_500).contains(&n_false),
+            "{} false positives",
+            n_false
+        );
+    }
+}
@@ -0,0 +1,363 @@
+//! Crossbeam channels which count what goes through them, to tell which stage
+//! of a pipeline holds the others back.
+//!
+//! A [`ChannelMeter`] counts the items sent and received, and is sampled by
+//! [`ChannelMeter::sample`] for the fraction of the time the channel was full,
+//! its producers held back, or empty, its consumers starved.
+//!
+//! ```
+//! use crackle_kit::utils::channel_metrics::metered;
+//!
+//! let (tx, mut rx) = metered::<u32>("numbers", 2);
+//! tx.send(1)?;
+//! tx.send(2)?;
+//! rx.meter().sample();
+//! assert_eq!(rx.recv()?, 1);
+//!
+//! let stats = rx.meter().stats();
+//! assert_eq!((stats.n_sent, stats.n_received, stats.high_water), (2, 1, 2));
+//! assert_eq!(stats.full_fraction(), 1.0);
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+
+use std::{
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::Duration,
+};
+
+use crossbeam_channel::{Receiver, RecvError, RecvTimeoutError, SendError, Sender, bounded};
+
+#[cfg(feature = "metrics")]
+use crate::utils::metrics::Gauge;
+
+/// Counts of a metered channel, from [`ChannelMeter::stats`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ChannelStats {
+    pub name: &'static str,
+    pub capacity: usize,
+    pub n_sent: u64,
+    pub n_received: u64,
+    /// Most items queued at once, see [`ChannelMeter::len`].
+    pub high_water: u64,
+    /// Times [`ChannelMeter::sample`] was called.
+    pub n_samples: u64,
+    /// Samples of a full channel.
+    pub n_full: u64,
+    /// Samples of an empty channel.
+    pub n_empty: u64,
+}
+
+impl ChannelStats {
+    /// Fraction of the samples the channel was full, i.e. its producers had to
+    /// wait for the consumers. 0 without samples.
+    pub fn full_fraction(&self) -> f64 {
+        fraction(self.n_full, self.n_samples)
+    }
+
+    /// Fraction of the samples the channel was empty, i.e. its consumers had
+    /// to wait for the producers. 0 without samples.
+    pub fn empty_fraction(&self) -> f64 {
+        fraction(self.n_empty, self.n_samples)
+    }
+}
+
+fn fraction(n: u64, total: u64) -> f64 {
+    match total {
+        0 => 0.0,
+        _ => n as f64 / total as f64,
+    }
+}
+
+/// Counts shared by the ends of a metered channel.
+#[derive(Debug)]
+pub struct ChannelMeter {
+    name: &'static str,
+    capacity: usize,
+    n_sent: AtomicU64,
+    n_received: AtomicU64,
+    /// Items done with by their receiver, see [`Self::len`].
+    n_done: AtomicU64,
+    high_water: AtomicU64,
+    /// High-water mark since the last [`Self::take_window_high_water`].
+    window_high_water: AtomicU64,
+    n_samples: AtomicU64,
+    n_full: AtomicU64,
+    n_empty: AtomicU64,
+}
+
+impl ChannelMeter {
+    fn new(name: &'static str, capacity: usize) -> Self {
+        Self {
+            name,
+            capacity,
+            n_sent: AtomicU64::new(0),
+            n_received: AtomicU64::new(0),
+            n_done: AtomicU64::new(0),
+            high_water: AtomicU64::new(0),
+            window_high_water: AtomicU64::new(0),
+            n_samples: AtomicU64::new(0),
+            n_full: AtomicU64::new(0),
+            n_empty: AtomicU64::new(0),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    /// Items sent and not yet done with by a receiver.
+    ///
+    /// A receiver is done with an item when it asks for the next one, or is
+    /// dropped, so that the item a consumer is working on counts as queued: a
+    /// slow consumer then shows as a full channel, even if the producers cannot
+    /// fill it past what the consumers hold, as when they recycle a fixed set of
+    /// batches.
+    pub fn len(&self) -> u64 {
+        let n_done = self.n_done.load(Ordering::Relaxed);
+        self.n_sent.load(Ordering::Relaxed).saturating_sub(n_done)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Count whether the channel is full or empty now.
+    pub fn sample(&self) {
+        let len = self.len();
+        self.n_samples.fetch_add(1, Ordering::Relaxed);
+        if len >= self.capacity as u64 {
+            self.n_full.fetch_add(1, Ordering::Relaxed);
+        } else if len == 0 {
+            self.n_empty.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// The `channel_queue_depth` gauge of the channel in `registry`, labelled
+    /// by its name, set to the items queued now. It is not kept up to date: the
+    /// owner of the channel sets it as it samples it. Fails if
+    /// `channel_queue_depth` is a counter of `registry`.
+    #[cfg(feature = "metrics")]
+    pub fn publish_depth(
+        &self,
+        registry: &crate::utils::metrics::MetricsRegistry,
+    ) -> Result<Gauge, crate::Error> {
+        let gauge = registry.gauge("channel_queue_depth", &[("channel", self.name)])?;
+        gauge.set(self.len() as f64);
+        Ok(gauge)
+    }
+
+    /// The high-water mark since the last call, for periodic reports.
+    pub fn take_window_high_water(&self) -> u64 {
+        self.window_high_water.swap(self.len(), Ordering::Relaxed)
+    }
+
+    pub fn stats(&self) -> ChannelStats {
+        ChannelStats {
+            name: self.name,
+            capacity: self.capacity,
+            n_sent: self.n_sent.load(Ordering::Relaxed),
+            n_received: self.n_received.load(Ordering::Relaxed),
+            high_water: self.high_water.load(Ordering::Relaxed),
+            n_samples: self.n_samples.load(Ordering::Relaxed),
+            n_full: self.n_full.load(Ordering::Relaxed),
+            n_empty: self.n_empty.load(Ordering::Relaxed),
+        }
+    }
+
+    fn sent(&self) {
+        self.n_sent.fetch_add(1, Ordering::Relaxed);
+        let len = self.len();
+        self.high_water.fetch_max(len, Ordering::Relaxed);
+        self.window_high_water.fetch_max(len, Ordering::Relaxed);
+    }
+}
+
+/// A bounded channel of `capacity` items, metered under `name`.
+pub fn metered<T>(name: &'static str, capacity: usize) -> (MeteredSender<T>, MeteredReceiver<T>) {
+    let (tx, rx) = bounded(capacity);
+    let meter = Arc::new(ChannelMeter::new(name, capacity));
+    (
+        MeteredSender {
+            inner: tx,
+            meter: Arc::clone(&meter),
+        },
+        MeteredReceiver {
+            inner: rx,
+            meter,
+            holding: false,
+        },
+    )
+}
+
+/// [`Sender`] counting the items sent.
+#[derive(Debug)]
+pub struct MeteredSender<T> {
+    inner: Sender<T>,
+    meter: Arc<ChannelMeter>,
+}
+
+impl<T> Clone for MeteredSender<T> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            meter: Arc::clone(&self.meter),
+        }
+    }
+}
+
+impl<T> MeteredSender<T> {
+    /// Send `msg`, blocking while the channel is full, as [`Sender::send`].
+    pub fn send(&self, msg: T) -> Result<(), SendError<T>> {
+        self.inner.send(msg)?;
+        self.meter.sent();
+        Ok(())
+    }
+
+    pub fn meter(&self) -> &Arc<ChannelMeter> {
+        &self.meter
+    }
+}
+
+/// [`Receiver`] counting the items received, and done with, see
+/// [`ChannelMeter::len`].
+///
+/// Each clone holds its own item.
+#[derive(Debug)]
+pub struct MeteredReceiver<T> {
+    inner: Receiver<T>,
+    meter: Arc<ChannelMeter>,
+    /// Whether the last item received is not done with yet.
+    holding: bool,
+}
+
+impl<T> Clone for MeteredReceiver<T> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            meter: Arc::clone(&self.meter),
+            holding: false,
+        }
+    }
+}
+
+impl<T> MeteredReceiver<T> {
+    /// Receive an item, blocking while the channel is empty, as
+    /// [`Receiver::recv`]. The item received before is done with.
+    pub fn recv(&mut self) -> Result<T, RecvError> {
+        self.release();
+        let msg = self.inner.recv()?;
+        self.meter.n_received.fetch_add(1, Ordering::Relaxed);
+        self.holding = true;
+        Ok(msg)
+    }
+
+    /// [`Self::recv`], waiting at most `timeout`, as [`Receiver::recv_timeout`].
+    pub fn recv_timeout(&mut self, timeout: Duration) -> Result<T, RecvTimeoutError> {
+        self.release();
+        let msg = self.inner.recv_timeout(timeout)?;
+        self.meter.n_received.fetch_add(1, Ordering::Relaxed);
+        self.holding = true;
+        Ok(msg)
+    }
+
+    /// Mark the last item received as done with, before the next
+    /// [`Self::recv`].
+    pub fn release(&mut self) {
+        if std::mem::take(&mut self.holding) {
+            self.meter.n_done.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub fn meter(&self) -> &Arc<ChannelMeter> {
+        &self.meter
+    }
+}
+
+impl<T> Drop for MeteredReceiver<T> {
+    fn drop(&mut self) {
+        self.release();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{thread, time::Duration};
+
+    use super::*;
+
+    #[test]
+    fn test_counts() {
+        let (tx, mut rx) = metered::<u32>("test", 3);
+        rx.meter().sample();
+        for i in 0..3 {
+            tx.send(i).unwrap();
+        }
+        rx.meter().sample();
+
+        // the item received is queued until the next one is asked for.
+        assert_eq!(rx.recv().unwrap(), 0);
+        assert_eq!(rx.meter().len(), 3);
+        rx.meter().sample();
+        rx.release();
+        assert_eq!(rx.meter().len(), 2);
+        rx.meter().sample();
+
+        // clones hold their own item.
+        let mut rx2 = rx.clone();
+        assert_eq!(rx2.recv().unwrap(), 1);
+        assert_eq!(rx.recv().unwrap(), 2);
+        assert_eq!(rx.meter().len(), 2);
+        drop(rx2);
+        assert_eq!(rx.meter().len(), 1);
+        assert_eq!(rx.meter().take_window_high_water(), 3);
+        assert_eq!(rx.meter().take_window_high_water(), 1);
+
+        assert_eq!(
+            tx.meter().stats(),
+            ChannelStats {
+                name: "test",
+                capacity: 3,
+                n_sent: 3,
+                n_received: 3,
+                high_water: 3,
+                n_samples: 4,
+                n_full: 2,
+                n_empty: 1,
+            }
+        );
+
+        drop(tx);
+        assert!(rx.recv().is_err());
+        assert!(rx.meter().is_empty());
+    }
+
+    #[test]
+    fn test_slow_consumer_fills_channel() {
+        let (tx, mut rx) = metered::<u32>("test", 4);
+        let meter = Arc::clone(tx.meter());
+
+        thread::scope(|s| {
+            s.spawn(move || {
+                for i in 0..50 {
+                    tx.send(i).unwrap();
+                }
+            });
+            s.spawn(move || {
+                while rx.recv().is_ok() {
+                    thread::sleep(Duration::from_millis(2));
+                }
+            });
+            while meter.n_received.load(Ordering::Relaxed) < 50 {
+                meter.sample();
+                thread::sleep(Duration::from_micros(200));
+            }
+        });
+
+        let stats = meter.stats();
+        assert!(stats.full_fraction() > 0.8, "{:?}", stats);
+        assert_eq!(stats.high_water, 5);
+    }
+}
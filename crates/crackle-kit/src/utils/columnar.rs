@@ -0,0 +1,273 @@
+//! Outputs kept by field, one `Vec` per field, rather than as a `Vec` of
+//! structs, see [`ColumnarSink`].
+//!
+//! A `Vec` of small structs pays for the padding of each of them, e.g. 4 bytes
+//! of every `{ pos: i64, depth: u32, mean_bq: f32, alt_frac: f32 }`, which adds
+//! up over the hundreds of millions of loci of a genome-wide scan. Columns of
+//! the fields have none, and are read as slices.
+//!
+//! ```
+//! use crackle_kit::{columnar_sink, utils::columnar::ColumnarSink};
+//!
+//! struct LocusStats {
+//!     pos: i64,
+//!     depth: u32,
+//!     mean_bq: f32,
+//! }
+//!
+//! columnar_sink! {
+//!     /// [`LocusStats`] by field.
+//!     pub struct StatsColumns for LocusStats {
+//!         pos: i64,
+//!         depth: u32,
+//!         mean_bq: f32,
+//!     }
+//! }
+//!
+//! let mut columns = StatsColumns::default();
+//! columns.push(LocusStats { pos: 100, depth: 30, mean_bq: 35.5 });
+//! columns.push(LocusStats { pos: 101, depth: 32, mean_bq: 36.0 });
+//! assert_eq!(columns.depth(), &[30, 32]);
+//! assert_eq!(columns.len(), 2);
+//! ```
+
+use std::{collections::BTreeMap, marker::PhantomData};
+
+/// Storage the outputs of a run are appended to, e.g. by
+/// [`ParallelLocusProcessorPileup::process_into`], typically a `Vec` per field
+/// of the output made by [`columnar_sink!`](crate::columnar_sink).
+///
+/// [`ParallelLocusProcessorPileup::process_into`]: crate::bam::process::ParallelLocusProcessorPileup::process_into
+pub trait ColumnarSink<T> {
+    /// Number of outputs pushed.
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Make room for `n` more outputs.
+    fn reserve(&mut self, n: usize);
+
+    fn push(&mut self, output: T);
+
+    /// Append the outputs of `other` after those of `self`.
+    fn merge(&mut self, other: Self);
+}
+
+/// The outputs as they are.
+impl<T> ColumnarSink<T> for Vec<T> {
+    fn len(&self) -> usize {
+        Vec::len(self)
+    }
+
+    fn reserve(&mut self, n: usize) {
+        Vec::reserve(self, n);
+    }
+
+    fn push(&mut self, output: T) {
+        Vec::push(self, output);
+    }
+
+    fn merge(&mut self, mut other: Self) {
+        self.append(&mut other);
+    }
+}
+
+/// Merges the sinks of batches, given in any order, into a sink in the order
+/// of the batches, as soon as those before them are, so that only the batches
+/// which finished early are kept apart.
+#[derive(Debug)]
+pub struct OrderedMerge<'s, T, S> {
+    sink: &'s mut S,
+    next: usize,
+    /// Batches given before those preceding them, by index.
+    pending: BTreeMap<usize, S>,
+    _output: PhantomData<fn(T)>,
+}
+
+impl<'s, T, S: ColumnarSink<T>> OrderedMerge<'s, T, S> {
+    /// A merge into `sink`, after its outputs, from the batch of index 0.
+    pub fn new(sink: &'s mut S) -> Self {
+        Self {
+            sink,
+            next: 0,
+            pending: BTreeMap::new(),
+            _output: PhantomData,
+        }
+    }
+
+    /// Add the batch of index `batch_idx`, merged into the sink along with
+    /// those after it already given, once all those before it are.
+    pub fn add(&mut self, batch_idx: usize, batch: S) {
+        self.pending.insert(batch_idx, batch);
+        while let Some(batch) = self.pending.remove(&self.next) {
+            self.sink.merge(batch);
+            self.next += 1;
+        }
+    }
+
+    /// Number of batches merged into the sink.
+    pub fn n_merged(&self) -> usize {
+        self.next
+    }
+
+    /// Merge the batches still pending, in their order, over the indices of
+    /// the batches never given, e.g. of batches without outputs.
+    pub fn finish(self) {
+        for (_, batch) in self.pending {
+            self.sink.merge(batch);
+        }
+    }
+}
+
+/// Define a [`ColumnarSink`] of the outputs of type `$output`, with a `Vec` of
+/// each of the fields listed, and accessors of their slices of the same names.
+///
+/// The fields are moved out of the outputs pushed, so other fields of
+/// `$output` are dropped. Attributes and docs given before `struct` are those
+/// of the sink, which also derives `Debug`, `Clone`, `Default` and
+/// `PartialEq`.
+///
+/// ```
+/// # use crackle_kit::columnar_sink;
+/// # struct Output { depth: u32, mean_bq: f32 }
+/// columnar_sink! {
+///     pub struct MySink for Output { depth: u32, mean_bq: f32 }
+/// }
+/// ```
+#[macro_export]
+macro_rules! columnar_sink {
+    (
+        $(#[$meta:meta])*
+        $vis:vis struct $name:ident for $output:ty {
+            $first:ident : $first_ty:ty
+            $(, $field:ident : $ty:ty)* $(,)?
+        }
+    ) => {
+        $(#[$meta])*
+        #[derive(Debug, Clone, Default, PartialEq)]
+        $vis struct $name {
+            $first: ::std::vec::Vec<$first_ty>,
+            $($field: ::std::vec::Vec<$ty>,)*
+        }
+
+        impl $name {
+            #[allow(dead_code)]
+            $vis fn $first(&self) -> &[$first_ty] {
+                &self.$first
+            }
+
+            $(
+                #[allow(dead_code)]
+                $vis fn $field(&self) -> &[$ty] {
+                    &self.$field
+                }
+            )*
+
+            #[allow(dead_code)]
+            $vis fn len(&self) -> usize {
+                self.$first.len()
+            }
+
+            #[allow(dead_code)]
+            $vis fn is_empty(&self) -> bool {
+                self.$first.is_empty()
+            }
+        }
+
+        impl $crate::utils::columnar::ColumnarSink<$output> for $name {
+            fn len(&self) -> usize {
+                self.$first.len()
+            }
+
+            fn reserve(&mut self, n: usize) {
+                self.$first.reserve(n);
+                $(self.$field.reserve(n);)*
+            }
+
+            fn push(&mut self, output: $output) {
+                self.$first.push(output.$first);
+                $(self.$field.push(output.$field);)*
+            }
+
+            fn merge(&mut self, mut other: Self) {
+                self.$first.append(&mut other.$first);
+                $(self.$field.append(&mut other.$field);)*
+            }
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, Clone, PartialEq)]
+    struct Stats {
+        pos: i64,
+        depth: u32,
+        alt_frac: f32,
+        label: String,
+    }
+
+    columnar_sink! {
+        struct StatsSink for Stats {
+            pos: i64,
+            depth: u32,
+            alt_frac: f32,
+        }
+    }
+
+    #[test]
+    fn test_columnar_sink() {
+        let stats = (0..10)
+            .map(|i| Stats {
+                pos: 100 + i,
+                depth: i as u32 * 3,
+                alt_frac: i as f32 / 10.0,
+                label: format!("locus{}", i),
+            })
+            .collect::<Vec<_>>();
+
+        let batches = stats
+            .chunks(3)
+            .map(|chunk| {
+                let mut sink = StatsSink::default();
+                sink.reserve(chunk.len());
+                for s in chunk {
+                    sink.push(s.clone());
+                }
+                sink
+            })
+            .collect::<Vec<_>>();
+        let mut sink = StatsSink::default();
+        let mut merge = OrderedMerge::new(&mut sink);
+        merge.add(1, batches[1].clone());
+        merge.add(3, batches[3].clone());
+        assert_eq!(merge.n_merged(), 0);
+        merge.add(0, batches[0].clone());
+        assert_eq!(merge.n_merged(), 2);
+        merge.add(2, batches[2].clone());
+        assert_eq!(merge.n_merged(), 4);
+
+        assert_eq!(sink.len(), 10);
+        assert_eq!(sink.pos(), stats.iter().map(|s| s.pos).collect::<Vec<_>>());
+        assert_eq!(
+            sink.depth(),
+            stats.iter().map(|s| s.depth).collect::<Vec<_>>()
+        );
+        assert_eq!(
+            sink.alt_frac(),
+            stats.iter().map(|s| s.alt_frac).collect::<Vec<_>>()
+        );
+
+        // batches never given are skipped by finish.
+        let mut vec = vec![];
+        let mut merge = OrderedMerge::new(&mut vec);
+        merge.add(3, stats[6..].to_vec());
+        merge.add(0, stats[..6].to_vec());
+        merge.finish();
+        assert_eq!(vec, stats);
+    }
+}
@@ -0,0 +1,219 @@
+//! Sizes, counts, durations and rates for people to read, e.g. `1.2 Gb`,
+//! `3m 41s` or `12.3 Mreads/s`, the same in the stats, logs and progress bars.
+//!
+//! The precision is fixed so that the strings can be compared:
+//! - counts below the base of their scale are written whole, e.g. `999 b`,
+//!   and the others with one decimal, rounded half away from zero, under the
+//!   smallest prefix keeping them below the base, e.g. `1.0 kb` for 1000 and
+//!   `1.0 Mb` for 999 950;
+//! - sizes in bytes are binary, e.g. `1.0 GiB` for 2^30 and `953.7 MiB` for
+//!   10^9;
+//! - rates always have one decimal, e.g. `0.5 reads/s`;
+//! - durations are truncated, see [`format_duration`].
+
+use std::{fmt, time::Duration};
+
+const SI_PREFIXES: [&str; 6] = ["k", "M", "G", "T", "P", "E"];
+const BINARY_PREFIXES: [&str; 6] = ["Ki", "Mi", "Gi", "Ti", "Pi", "Ei"];
+
+/// A size in bytes, displayed as [`format_bytes`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Bytes(pub u64);
+
+impl fmt::Display for Bytes {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        Scaled::binary(self.0 as f64, "B", true).fmt(f)
+    }
+}
+
+/// A count without a unit, displayed as [`format_si`], e.g. `12.3 M`, or `999`
+/// below 1000.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct SiCount(pub u64);
+
+impl fmt::Display for SiCount {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        Scaled::si(self.0 as f64, "", true).fmt(f)
+    }
+}
+
+/// `bytes` with a binary prefix, e.g. `512 B`, `1.5 KiB` or `1.0 GiB`.
+pub fn format_bytes(bytes: u64) -> String {
+    Bytes(bytes).to_string()
+}
+
+/// `n` of `unit` with a prefix of powers of 1000, e.g. `999 b`, `1.0 kb` or
+/// `1.2 Gb`.
+pub fn format_si(n: u64, unit: &str) -> String {
+    Scaled::si(n as f64, unit, true).to_string()
+}
+
+/// `duration` truncated to:
+/// - `0s` for zero;
+/// - whole microseconds below 1 ms, e.g. `250µs`;
+/// - whole milliseconds below 1 s, e.g. `999ms`;
+/// - tenths of a second below 10 s, e.g. `1.5s`;
+/// - whole seconds below a minute, e.g. `59s`;
+/// - minutes and seconds below an hour, e.g. `3m 41s`;
+/// - hours and minutes, e.g. `26h 5m`.
+pub fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    match secs {
+        _ if duration.is_zero() => "0s".to_string(),
+        0 if duration.as_millis() == 0 => format!("{}µs", duration.as_micros()),
+        0 => format!("{}ms", duration.as_millis()),
+        1..10 => format!("{}.{}s", secs, duration.subsec_millis() / 100),
+        10..60 => format!("{}s", secs),
+        60..3600 => format!("{}m {}s", secs / 60, secs % 60),
+        _ => format!("{}h {}m", secs / 3600, secs % 3600 / 60),
+    }
+}
+
+/// `count` of `unit` per second over `elapsed`, scaled as [`format_si`] but
+/// always with one decimal, e.g. `12.3 Mreads/s` or `0.5 reads/s`. Over no
+/// time, the rate is 0.
+pub fn format_rate(count: u64, elapsed: Duration, unit: &str) -> String {
+    let rate = match elapsed.as_secs_f64() {
+        0.0 => 0.0,
+        secs => count as f64 / secs,
+    };
+    format!("{}/s", Scaled::si(rate, unit, false))
+}
+
+/// Lowercase hex of `bytes`, e.g. of a digest.
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// `value` divided by `base` until it is below it once rounded, with the
+/// prefix of the number of divisions, then `unit`. Values not divided are
+/// written whole if `whole`.
+struct Scaled<'a> {
+    value: f64,
+    base: f64,
+    prefixes: &'static [&'static str],
+    unit: &'a str,
+    whole: bool,
+}
+
+impl<'a> Scaled<'a> {
+    fn si(value: f64, unit: &'a str, whole: bool) -> Self {
+        Self {
+            value,
+            base: 1000.0,
+            prefixes: &SI_PREFIXES,
+            unit,
+            whole,
+        }
+    }
+
+    fn binary(value: f64, unit: &'a str, whole: bool) -> Self {
+        Self {
+            value,
+            base: 1024.0,
+            prefixes: &BINARY_PREFIXES,
+            unit,
+            whole,
+        }
+    }
+}
+
+impl fmt::Display for Scaled<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut scaled = self.value;
+        let mut prefix = None;
+        for next in self.prefixes {
+            if (scaled * 10.0).round() < self.base * 10.0 {
+                break;
+            }
+            scaled /= self.base;
+            prefix = Some(*next);
+        }
+
+        match prefix {
+            None if self.whole => write!(f, "{}", scaled as u64)?,
+            _ => {
+                let tenths = (scaled * 10.0).round() as u64;
+                write!(f, "{}.{}", tenths / 10, tenths % 10)?
+            }
+        }
+        match (prefix.unwrap_or(""), self.unit) {
+            ("", "") => Ok(()),
+            (prefix, unit) => write!(f, " {}{}", prefix, unit),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_si() {
+        assert_eq!(format_si(0, "b"), "0 b");
+        assert_eq!(format_si(999, "b"), "999 b");
+        assert_eq!(format_si(1_000, "b"), "1.0 kb");
+        assert_eq!(format_si(1_049, "b"), "1.0 kb");
+        assert_eq!(format_si(1_050, "b"), "1.1 kb");
+        assert_eq!(format_si(999_949, "b"), "999.9 kb");
+        // rounded up to the next prefix rather than to 1000.0 kb.
+        assert_eq!(format_si(999_950, "b"), "1.0 Mb");
+        assert_eq!(format_si(1_234_567_890, "b"), "1.2 Gb");
+        assert_eq!(format_si(u64::MAX, "b"), "18.4 Eb");
+
+        assert_eq!(SiCount(999).to_string(), "999");
+        assert_eq!(SiCount(12_345_678).to_string(), "12.3 M");
+        assert_eq!(format!("{} reads", SiCount(0)), "0 reads");
+    }
+
+    #[test]
+    fn test_format_bytes() {
+        assert_eq!(format_bytes(0), "0 B");
+        assert_eq!(format_bytes(1_000), "1000 B");
+        assert_eq!(format_bytes(1_023), "1023 B");
+        assert_eq!(format_bytes(1_024), "1.0 KiB");
+        assert_eq!(format_bytes(1_536), "1.5 KiB");
+        // 10^9 bytes are less than a GiB.
+        assert_eq!(format_bytes(1_000_000_000), "953.7 MiB");
+        assert_eq!(format_bytes(1 << 30), "1.0 GiB");
+        assert_eq!(format!("{}", Bytes((1 << 30) - 1)), "1.0 GiB");
+    }
+
+    #[test]
+    fn test_format_duration() {
+        let ms = Duration::from_millis;
+        assert_eq!(format_duration(Duration::ZERO), "0s");
+        assert_eq!(format_duration(Duration::from_nanos(999)), "0µs");
+        assert_eq!(format_duration(Duration::from_micros(250)), "250µs");
+        assert_eq!(format_duration(ms(1)), "1ms");
+        assert_eq!(format_duration(ms(999)), "999ms");
+        assert_eq!(format_duration(ms(1_000)), "1.0s");
+        assert_eq!(format_duration(ms(1_599)), "1.5s");
+        assert_eq!(format_duration(ms(9_999)), "9.9s");
+        assert_eq!(format_duration(ms(10_000)), "10s");
+        assert_eq!(format_duration(ms(59_999)), "59s");
+        assert_eq!(format_duration(ms(60_000)), "1m 0s");
+        assert_eq!(format_duration(ms(221_500)), "3m 41s");
+        assert_eq!(format_duration(ms(3_600_000)), "1h 0m");
+        assert_eq!(format_duration(ms(94_000_000)), "26h 6m");
+    }
+
+    #[test]
+    fn test_format_rate() {
+        let secs = Duration::from_secs;
+        assert_eq!(format_rate(0, secs(1), "reads"), "0.0 reads/s");
+        assert_eq!(format_rate(10, Duration::ZERO, "reads"), "0.0 reads/s");
+        assert_eq!(format_rate(1, secs(2), "reads"), "0.5 reads/s");
+        assert_eq!(format_rate(999, secs(1), "reads"), "999.0 reads/s");
+        assert_eq!(format_rate(1_000, secs(1), "reads"), "1.0 kreads/s");
+        assert_eq!(format_rate(123_000_000, secs(10), "reads"), "12.3 Mreads/s");
+        assert_eq!(format_rate(5, secs(1), ""), "5.0/s");
+        assert_eq!(format_rate(5_000, secs(1), ""), "5.0 k/s");
+    }
+
+    #[test]
+    fn test_to_hex() {
+        assert_eq!(to_hex(&[]), "");
+        assert_eq!(to_hex(&[0x00, 0x0f, 0xa0, 0xff]), "000fa0ff");
+    }
+}
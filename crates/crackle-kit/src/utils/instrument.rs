@@ -0,0 +1,77 @@
+//! Tracing spans and busy time of the processor and pipeline stages.
+//!
+//! Stage threads (scoped threads of `OrderedPipeline`, rayon workers of
+//! `process_with_batch`) do not inherit the caller's subscriber, so spans are
+//! entered through [`enter_on_thread`] with the caller's [`Dispatch`]. When the
+//! subscriber filters the spans out, this costs a clone of the dispatcher per
+//! stage or batch.
+
+use std::{
+    sync::atomic::{AtomicU64, Ordering},
+    time::{Duration, Instant},
+};
+
+use tracing::{
+    Dispatch, Span,
+    dispatcher::{self, DefaultGuard},
+    span::EnteredSpan,
+};
+
+/// The subscriber of the current thread, to pass to stage threads.
+pub(crate) fn current_dispatch() -> Dispatch {
+    dispatcher::get_default(|d| d.clone())
+}
+
+/// An entered span, with `dispatch` as the thread's subscriber while it lives.
+pub(crate) struct ThreadSpan {
+    // dropped first: the span exits before the subscriber is unset.
+    _span: EnteredSpan,
+    _dispatch: DefaultGuard,
+}
+
+/// Make `dispatch` the thread's subscriber and enter the span made by `make_span`,
+/// until the returned guard is dropped.
+pub(crate) fn enter_on_thread(dispatch: &Dispatch, make_span: impl FnOnce() -> Span) -> ThreadSpan {
+    let dispatch = dispatcher::set_default(dispatch);
+    ThreadSpan {
+        _span: make_span().entered(),
+        _dispatch: dispatch,
+    }
+}
+
+/// Time spent working, summed over threads.
+#[derive(Debug, Default)]
+pub(crate) struct BusyTime(AtomicU64);
+
+impl BusyTime {
+    /// Count the time until the returned guard is dropped.
+    pub(crate) fn start(&self) -> BusyGuard<'_> {
+        BusyGuard {
+            busy: self,
+            started: Instant::now(),
+        }
+    }
+
+    pub(crate) fn get(&self) -> Duration {
+        Duration::from_nanos(self.0.load(Ordering::Relaxed))
+    }
+
+    /// In milliseconds, for span and event fields.
+    #[cfg(feature = "bam")]
+    pub(crate) fn millis(&self) -> u64 {
+        self.get().as_millis() as u64
+    }
+}
+
+pub(crate) struct BusyGuard<'a> {
+    busy: &'a BusyTime,
+    started: Instant,
+}
+
+impl Drop for BusyGuard<'_> {
+    fn drop(&mut self) {
+        self.busy
+            .0
+            .fetch_add(self.started.elapsed().as_nanos() as u64, Ordering::Relaxed);
+    }
+}
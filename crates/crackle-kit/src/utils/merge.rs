@@ -0,0 +1,293 @@
+//! Merge of results sorted by locus, e.g. the per-chunk outputs of batched or
+//! per-contig runs, back into genome order.
+//!
+//! Loci are ordered by [`Chrom::natural_cmp`], then by position.
+
+use std::{cmp::Ordering, collections::BinaryHeap};
+
+use thiserror::Error;
+
+use crate::data::{
+    chrom::Chrom,
+    locus::{GenomeCoordinate, GenomeRegion},
+    variant::Variant,
+};
+
+/// Items with a locus to be sorted on.
+pub trait LocusKeyed {
+    fn chrom(&self) -> &Chrom<'_>;
+
+    /// 1-based position, the start of regions.
+    fn pos(&self) -> i64;
+}
+
+impl LocusKeyed for GenomeCoordinate<'_> {
+    fn chrom(&self) -> &Chrom<'_> {
+        &self.contig
+    }
+
+    fn pos(&self) -> i64 {
+        self.pos
+    }
+}
+
+impl LocusKeyed for GenomeRegion<'_> {
+    fn chrom(&self) -> &Chrom<'_> {
+        &self.contig
+    }
+
+    fn pos(&self) -> i64 {
+        self.start
+    }
+}
+
+impl LocusKeyed for Variant<'_> {
+    fn chrom(&self) -> &Chrom<'_> {
+        &self.coordinate().contig
+    }
+
+    fn pos(&self) -> i64 {
+        self.coordinate().pos
+    }
+}
+
+impl<L: LocusKeyed> LocusKeyed for &L {
+    fn chrom(&self) -> &Chrom<'_> {
+        (*self).chrom()
+    }
+
+    fn pos(&self) -> i64 {
+        (*self).pos()
+    }
+}
+
+impl<T> LocusKeyed for (GenomeCoordinate<'_>, T) {
+    fn chrom(&self) -> &Chrom<'_> {
+        &self.0.contig
+    }
+
+    fn pos(&self) -> i64 {
+        self.0.pos
+    }
+}
+
+/// Order of the loci of `a` and `b`.
+pub fn locus_cmp(a: &impl LocusKeyed, b: &impl LocusKeyed) -> Ordering {
+    a.chrom()
+        .natural_cmp(b.chrom())
+        .then_with(|| a.pos().cmp(&b.pos()))
+}
+
+/// Merge of inputs each sorted by locus into one sorted stream.
+///
+/// The merge is stable: items at the same locus come in the order of their
+/// inputs, then in their order within an input. Inputs which are not sorted
+/// give an unsorted stream; check them with [`is_sorted_by_locus`] if unsure.
+pub fn merge_sorted_by_locus<T, I>(inputs: Vec<I>) -> LocusMerge<T, I>
+where
+    T: LocusKeyed,
+    I: Iterator<Item = T>,
+{
+    let mut inputs = inputs;
+    let heap = inputs
+        .iter_mut()
+        .enumerate()
+        .filter_map(|(input_idx, input)| Some(HeapItem::new(input.next()?, input_idx)))
+        .collect();
+
+    LocusMerge { inputs, heap }
+}
+
+/// Iterator of [`merge_sorted_by_locus`].
+pub struct LocusMerge<T: LocusKeyed, I> {
+    inputs: Vec<I>,
+    heap: BinaryHeap<HeapItem<T>>,
+}
+
+impl<T, I> Iterator for LocusMerge<T, I>
+where
+    T: LocusKeyed,
+    I: Iterator<Item = T>,
+{
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        let HeapItem { item, input_idx } = self.heap.pop()?;
+        if let Some(next) = self.inputs[input_idx].next() {
+            self.heap.push(HeapItem::new(next, input_idx));
+        }
+
+        Some(item)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inputs.iter().map(|input| input.size_hint()).fold(
+            (self.heap.len(), Some(self.heap.len())),
+            |(lo, hi), (l, h)| {
+                (
+                    lo.saturating_add(l),
+                    hi.zip(h).and_then(|(a, b)| a.checked_add(b)),
+                )
+            },
+        )
+    }
+}
+
+/// Item of the min-heap of [`LocusMerge`], ordered by locus then input.
+struct HeapItem<T> {
+    item: T,
+    input_idx: usize,
+}
+
+impl<T> HeapItem<T> {
+    fn new(item: T, input_idx: usize) -> Self {
+        Self { item, input_idx }
+    }
+}
+
+impl<T: LocusKeyed> Ord for HeapItem<T> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        // reversed, as BinaryHeap pops the greatest.
+        locus_cmp(&other.item, &self.item).then_with(|| other.input_idx.cmp(&self.input_idx))
+    }
+}
+
+impl<T: LocusKeyed> PartialOrd for HeapItem<T> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<T: LocusKeyed> PartialEq for HeapItem<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl<T: LocusKeyed> Eq for HeapItem<T> {}
+
+/// The first item of an iterator before the item preceding it.
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+#[error("item {index} at {}:{} is before the previous item at {}:{}", next.0, next.1, prev.0, prev.1)]
+pub struct FirstViolation {
+    /// 0-based index of the item in the iterator.
+    pub index: usize,
+    /// Contig and position of the previous item.
+    pub prev: (Chrom<'static>, i64),
+    /// Contig and position of the item.
+    pub next: (Chrom<'static>, i64),
+}
+
+/// Check that `iter` is sorted by locus, items at the same locus allowed.
+pub fn is_sorted_by_locus<T: LocusKeyed>(
+    iter: impl IntoIterator<Item = T>,
+) -> Result<(), FirstViolation> {
+    let mut iter = iter.into_iter();
+    let Some(mut prev) = iter.next() else {
+        return Ok(());
+    };
+
+    for (index, next) in (1..).zip(iter) {
+        if locus_cmp(&prev, &next) == Ordering::Greater {
+            let key = |item: &T| (item.chrom().clone().into_owned(), item.pos());
+            return Err(FirstViolation {
+                index,
+                prev: key(&prev),
+                next: key(&next),
+            });
+        }
+        prev = next;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn coord(contig: &str, pos: i64) -> GenomeCoordinate<'static> {
+        GenomeCoordinate {
+            contig: Chrom::from(contig).into_owned(),
+            pos,
+        }
+    }
+
+    #[test]
+    fn test_merge_sorted_by_locus() {
+        let mut loci = vec![];
+        for contig in ["chr1", "chr2", "chr10", "chrX", "chrUn_2", "chrUn_10"] {
+            for pos in [1, 5, 5, 90, 1_000] {
+                loci.push(coord(contig, pos));
+            }
+        }
+
+        // chunks taking every third locus, so each chunk spans every contig.
+        let chunks = (0..3)
+            .map(|chunk| {
+                loci.iter()
+                    .enumerate()
+                    .filter(|(i, _)| i % 3 == chunk)
+                    .map(|(i, locus)| (locus.clone(), i))
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+        for chunk in &chunks {
+            assert!(is_sorted_by_locus(chunk).is_ok());
+        }
+
+        let merge = merge_sorted_by_locus(chunks.into_iter().map(|c| c.into_iter()).collect());
+        assert_eq!(merge.size_hint(), (loci.len(), Some(loci.len())));
+        let merged = merge.collect::<Vec<_>>();
+        assert_eq!(
+            merged.iter().map(|(locus, _)| locus).collect::<Vec<_>>(),
+            loci.iter().collect::<Vec<_>>()
+        );
+        assert!(is_sorted_by_locus(&merged).is_ok());
+        // ties in input order: chr2:5 at 11 of chunk 2 and 12 of chunk 0.
+        assert_eq!(
+            merged[10..13].iter().map(|(_, i)| *i).collect::<Vec<_>>(),
+            vec![10, 12, 11]
+        );
+
+        let empty = merge_sorted_by_locus(Vec::<std::vec::IntoIter<GenomeRegion>>::new());
+        assert_eq!(empty.count(), 0);
+    }
+
+    #[test]
+    fn test_is_sorted_by_locus() {
+        let regions = [
+            ("chr1", 10),
+            ("chr2", 5),
+            ("chr2", 5),
+            ("chrUn_10", 1),
+            ("chrUn_9", 100),
+        ]
+        .map(|(contig, start)| GenomeRegion {
+            contig: Chrom::from(contig),
+            start,
+            end: start + 10,
+        });
+        let violation = is_sorted_by_locus(&regions).unwrap_err();
+        assert_eq!(
+            violation,
+            FirstViolation {
+                index: 4,
+                prev: (Chrom::from("chrUn_10"), 1),
+                next: (Chrom::from("chrUn_9"), 100),
+            }
+        );
+        assert_eq!(
+            violation.to_string(),
+            "item 4 at chrUn_9:100 is before the previous item at chrUn_10:1"
+        );
+        assert!(is_sorted_by_locus(&regions[..4]).is_ok());
+
+        let variants = [
+            Variant::new(Chrom::Chr2, 10, "A", "G"),
+            Variant::new(Chrom::Chr1, 20, "C", "T"),
+        ];
+        assert_eq!(is_sorted_by_locus(&variants).unwrap_err().index, 1);
+        assert!(is_sorted_by_locus(Vec::<Variant>::new()).is_ok());
+    }
+}
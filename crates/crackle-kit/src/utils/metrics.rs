@@ -0,0 +1,506 @@
+//! Counters and gauges of a long-running process, e.g. of the records a bam
+//! processor has read, served in the Prometheus text format for an orchestrator
+//! to scrape.
+//!
+//! Metrics are keyed by name and labels in a [`MetricsRegistry`], usually the
+//! [`global`] one, into which the bam processors and pipelines publish when the
+//! `metrics` feature is on. [`serve_text`] serves it on `GET /metrics`.
+//!
+//! ```
+//! use crackle_kit::utils::metrics::global;
+//!
+//! let reads = global().counter("doc_reads_total", &[("sample", "s1")])?;
+//! reads.add(10);
+//! global().gauge("doc_queue_depth", &[])?.set(3.0);
+//!
+//! let text = global().render();
+//! assert!(text.contains("# TYPE doc_reads_total counter\n"));
+//! assert!(text.contains("doc_reads_total{sample=\"s1\"} 10\n"));
+//! assert!(text.contains("doc_queue_depth 3\n"));
+//! # Ok::<(), crackle_kit::Error>(())
+//! ```
+
+use std::{
+    collections::BTreeMap,
+    fmt::Write as _,
+    io::{self, BufRead, BufReader, Write as _},
+    net::{
+        IpAddr, Ipv4Addr, Ipv6Addr, Shutdown, SocketAddr, TcpListener, TcpStream, ToSocketAddrs,
+    },
+    sync::{
+        Arc, Mutex, OnceLock,
+        atomic::{AtomicBool, AtomicU64, Ordering},
+    },
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use anyhow::anyhow;
+
+/// Time a scraper has to send its request before it is dropped, so that a
+/// stalled one does not block the others.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Kind of a metric, as in the `# TYPE` line of the text format.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MetricKind {
+    /// Only goes up, e.g. records read.
+    Counter,
+    /// Goes up and down, e.g. the items queued in a channel.
+    Gauge,
+}
+
+impl MetricKind {
+    fn as_str(&self) -> &'static str {
+        match self {
+            MetricKind::Counter => "counter",
+            MetricKind::Gauge => "gauge",
+        }
+    }
+}
+
+/// A counter of a [`MetricsRegistry`]. Clones count into the same metric.
+#[derive(Debug, Clone)]
+pub struct Counter(Arc<AtomicU64>);
+
+impl Counter {
+    pub fn inc(&self) {
+        self.add(1);
+    }
+
+    pub fn add(&self, n: u64) {
+        self.0.fetch_add(n, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// A gauge of a [`MetricsRegistry`]. Clones set the same metric.
+#[derive(Debug, Clone)]
+pub struct Gauge(Arc<AtomicU64>);
+
+impl Gauge {
+    pub fn set(&self, value: f64) {
+        self.0.store(value.to_bits(), Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> f64 {
+        f64::from_bits(self.0.load(Ordering::Relaxed))
+    }
+}
+
+/// Metrics of a name, by their labels.
+#[derive(Debug)]
+struct Family {
+    kind: MetricKind,
+    series: BTreeMap<Vec<(String, String)>, Arc<AtomicU64>>,
+}
+
+/// Counters and gauges by name and labels.
+///
+/// Asking for a metric already registered returns it, so that each run of a
+/// processor counts into the same one. Names and label names have the
+/// characters not allowed by Prometheus replaced by `_`.
+#[derive(Debug, Default)]
+pub struct MetricsRegistry {
+    families: Mutex<BTreeMap<String, Family>>,
+}
+
+/// The registry the processors of the crate publish into.
+pub fn global() -> &'static MetricsRegistry {
+    static GLOBAL: OnceLock<MetricsRegistry> = OnceLock::new();
+    GLOBAL.get_or_init(MetricsRegistry::new)
+}
+
+/// Serve the [`global`] registry on `addr`, see [`MetricsRegistry::serve_text`].
+pub fn serve_text(addr: impl ToSocketAddrs) -> Result<MetricsServerHandle, crate::Error> {
+    global().serve_text(addr)
+}
+
+impl MetricsRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The counter `name` with `labels`, registered at 0 if new. Fails with an
+    /// [`Error::Other`](crate::Error::Other) if `name` is a gauge.
+    pub fn counter(&self, name: &str, labels: &[(&str, &str)]) -> Result<Counter, crate::Error> {
+        Ok(Counter(self.get_or_register(
+            name,
+            MetricKind::Counter,
+            labels,
+            0,
+        )?))
+    }
+
+    /// The gauge `name` with `labels`, registered at 0 if new. Fails with an
+    /// [`Error::Other`](crate::Error::Other) if `name` is a counter.
+    pub fn gauge(&self, name: &str, labels: &[(&str, &str)]) -> Result<Gauge, crate::Error> {
+        Ok(Gauge(self.get_or_register(
+            name,
+            MetricKind::Gauge,
+            labels,
+            0.0_f64.to_bits(),
+        )?))
+    }
+
+    fn get_or_register(
+        &self,
+        name: &str,
+        kind: MetricKind,
+        labels: &[(&str, &str)],
+        initial: u64,
+    ) -> Result<Arc<AtomicU64>, crate::Error> {
+        let mut labels = labels
+            .iter()
+            .map(|(k, v)| (sanitize_name(k), v.to_string()))
+            .collect::<Vec<_>>();
+        labels.sort();
+
+        let mut families = self.families.lock().unwrap();
+        let family = families
+            .entry(sanitize_name(name))
+            .or_insert_with(|| Family {
+                kind,
+                series: BTreeMap::new(),
+            });
+        if family.kind != kind {
+            return Err(anyhow!(
+                "Metric {} is a {}, not a {}",
+                name,
+                family.kind.as_str(),
+                kind.as_str()
+            )
+            .into());
+        }
+        Ok(Arc::clone(
+            family
+                .series
+                .entry(labels)
+                .or_insert_with(|| Arc::new(AtomicU64::new(initial))),
+        ))
+    }
+
+    /// The metrics in the Prometheus text format, by name then labels, each
+    /// name under its `# TYPE` line.
+    pub fn render(&self) -> String {
+        let families = self.families.lock().unwrap();
+        let mut text = String::new();
+        for (name, family) in families.iter() {
+            // writing to a `String` does not fail.
+            let _ = writeln!(text, "# TYPE {} {}", name, family.kind.as_str());
+            for (labels, value) in &family.series {
+                text.push_str(name);
+                if !labels.is_empty() {
+                    let labels = labels
+                        .iter()
+                        .map(|(k, v)| format!("{}=\"{}\"", k, escape_label_value(v)))
+                        .collect::<Vec<_>>();
+                    let _ = write!(text, "{{{}}}", labels.join(","));
+                }
+                let value = value.load(Ordering::Relaxed);
+                let _ = match family.kind {
+                    MetricKind::Counter => writeln!(text, " {}", value),
+                    MetricKind::Gauge => writeln!(text, " {}", format_gauge(f64::from_bits(value))),
+                };
+            }
+        }
+        text
+    }
+
+    /// Serve [`Self::render`] on `GET /metrics` at `addr`, e.g. `0.0.0.0:9100`,
+    /// from a thread of its own, until the handle is shut down or dropped.
+    ///
+    /// Requests are answered one at a time, each on a connection of its own.
+    /// Other paths are answered 404 and other methods 405. Binding `addr`
+    /// fails with an [`Error::Other`](crate::Error::Other).
+    pub fn serve_text(
+        &'static self,
+        addr: impl ToSocketAddrs,
+    ) -> Result<MetricsServerHandle, crate::Error> {
+        let listener = TcpListener::bind(addr)
+            .and_then(|listener| Ok((listener.local_addr()?, listener)))
+            .map_err(|err| anyhow!(err).context("Failed to listen for metrics scrapes"));
+        let (local_addr, listener) = listener?;
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread = {
+            let stop = Arc::clone(&stop);
+            thread::Builder::new()
+                .name("metrics".to_string())
+                .spawn(move || {
+                    for stream in listener.incoming() {
+                        if stop.load(Ordering::Acquire) {
+                            break;
+                        }
+                        // a failed connection is the scraper's to retry.
+                        if let Ok(stream) = stream {
+                            let _ = self.respond(stream);
+                        }
+                    }
+                })
+                .map_err(|err| anyhow!(err).context("Failed to spawn the metrics thread"))?
+        };
+
+        Ok(MetricsServerHandle {
+            local_addr,
+            stop,
+            thread: Some(thread),
+        })
+    }
+
+    fn respond(&self, mut stream: TcpStream) -> io::Result<()> {
+        stream.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+        let mut reader = BufReader::new(&stream);
+        let mut request_line = String::new();
+        reader.read_line(&mut request_line)?;
+        // the headers are not used, but read so that the client sees its whole
+        // request taken before the connection is closed.
+        let mut header = String::new();
+        while reader.read_line(&mut header)? > 0 && header.trim_end() != "" {
+            header.clear();
+        }
+
+        let mut parts = request_line.split_whitespace();
+        let (status, body) = match (parts.next(), parts.next()) {
+            (Some("GET"), Some("/metrics")) => ("200 OK", self.render()),
+            (Some("GET"), _) => (
+                "404 Not Found",
+                "Not found. Metrics are at /metrics\n".into(),
+            ),
+            _ => ("405 Method Not Allowed", "Only GET is allowed\n".into()),
+        };
+        write!(
+            stream,
+            "HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4; charset=utf-8\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            status,
+            body.len(),
+            body
+        )?;
+        stream.flush()?;
+        stream.shutdown(Shutdown::Both)
+    }
+}
+
+/// The server of [`MetricsRegistry::serve_text`]. Dropping it shuts the server
+/// down too.
+#[derive(Debug)]
+pub struct MetricsServerHandle {
+    local_addr: SocketAddr,
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl MetricsServerHandle {
+    /// The address served, e.g. to find the port picked for port 0.
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    /// Stop serving, after the request being answered if any, and wait for the
+    /// server thread to end.
+    pub fn shutdown(mut self) {
+        self.stop_thread();
+    }
+
+    fn stop_thread(&mut self) {
+        let Some(thread) = self.thread.take() else {
+            return;
+        };
+        self.stop.store(true, Ordering::Release);
+        // wakes up the thread blocked on accepting, which then sees `stop`.
+        let _ = TcpStream::connect(wake_addr(self.local_addr));
+        let _ = thread.join();
+    }
+}
+
+impl Drop for MetricsServerHandle {
+    fn drop(&mut self) {
+        self.stop_thread();
+    }
+}
+
+/// Where to connect to reach a server listening on `addr`: the loopback
+/// address of its family if `addr` is unspecified, e.g. `0.0.0.0`, which some
+/// platforms do not connect to.
+fn wake_addr(addr: SocketAddr) -> SocketAddr {
+    let ip = match addr.ip() {
+        IpAddr::V4(ip) if ip.is_unspecified() => IpAddr::V4(Ipv4Addr::LOCALHOST),
+        IpAddr::V6(ip) if ip.is_unspecified() => IpAddr::V6(Ipv6Addr::LOCALHOST),
+        ip => ip,
+    };
+    SocketAddr::new(ip, addr.port())
+}
+
+/// `name` with the characters not allowed in metric names replaced by `_`.
+fn sanitize_name(name: &str) -> String {
+    name.char_indices()
+        .map(|(i, c)| match c {
+            'a'..='z' | 'A'..='Z' | '_' | ':' => c,
+            '0'..='9' if i > 0 => c,
+            _ => '_',
+        })
+        .collect()
+}
+
+fn escape_label_value(value: &str) -> String {
+    value
+        .replace('\\', r"\\")
+        .replace('"', "\\\"")
+        .replace('\n', r"\n")
+}
+
+/// `value` as in the text format: whole numbers without a fraction, and
+/// `+Inf`, `-Inf` and `NaN`.
+fn format_gauge(value: f64) -> String {
+    match value {
+        v if v.is_nan() => "NaN".to_string(),
+        f64::INFINITY => "+Inf".to_string(),
+        f64::NEG_INFINITY => "-Inf".to_string(),
+        v => v.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read as _;
+
+    use super::*;
+
+    /// Send `request` to `addr`, returning the status line and the body.
+    fn scrape(addr: SocketAddr, request: &str) -> (String, String) {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream.write_all(request.as_bytes()).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        let (head, body) = response.split_once("\r\n\r\n").unwrap();
+        (head.lines().next().unwrap().to_string(), body.to_string())
+    }
+
+    fn get(addr: SocketAddr, path: &str) -> (String, String) {
+        scrape(
+            addr,
+            &format!("GET {} HTTP/1.1\r\nHost: test\r\n\r\n", path),
+        )
+    }
+
+    #[test]
+    fn test_render() {
+        let registry = MetricsRegistry::new();
+        let read = registry.counter("records_read_total", &[]).unwrap();
+        read.add(5);
+        registry.counter("records_read_total", &[]).unwrap().inc();
+        assert_eq!(read.get(), 6);
+
+        // labels are sorted, and their values escaped.
+        registry
+            .gauge("queue depth", &[("stage", "b"), ("bam", "a\"1\"\n")])
+            .unwrap()
+            .set(2.5);
+        registry
+            .gauge("queue depth", &[("stage", "a")])
+            .unwrap()
+            .set(f64::NAN);
+        registry.gauge("9lives", &[]).unwrap().set(-1.0);
+
+        assert_eq!(
+            registry.render(),
+            "# TYPE _lives gauge\n\
+             _lives -1\n\
+             # TYPE queue_depth gauge\n\
+             queue_depth{bam=\"a\\\"1\\\"\\n\",stage=\"b\"} 2.5\n\
+             queue_depth{stage=\"a\"} NaN\n\
+             # TYPE records_read_total counter\n\
+             records_read_total 6\n"
+        );
+    }
+
+    #[test]
+    fn test_kind_mismatch() {
+        let registry = MetricsRegistry::new();
+        registry.gauge("queue_depth", &[]).unwrap().set(1.0);
+        let err = registry.counter("queue_depth", &[]).unwrap_err();
+        assert!(matches!(err, crate::Error::Other(_)), "{:?}", err);
+        assert_eq!(
+            err.to_string(),
+            "Metric queue_depth is a gauge, not a counter"
+        );
+        // left as it was.
+        assert_eq!(
+            registry.render(),
+            "# TYPE queue_depth gauge\nqueue_depth 1\n"
+        );
+    }
+
+    #[test]
+    fn test_wake_addr() {
+        let wake = |addr: &str| wake_addr(addr.parse().unwrap()).to_string();
+        assert_eq!(wake("0.0.0.0:9100"), "127.0.0.1:9100");
+        assert_eq!(wake("[::]:9100"), "[::1]:9100");
+        assert_eq!(wake("10.0.0.1:9100"), "10.0.0.1:9100");
+
+        // shuts down without blocking.
+        let server = serve_text("0.0.0.0:0").unwrap();
+        let port = server.local_addr().port();
+        assert_eq!(
+            get(SocketAddr::from((Ipv4Addr::LOCALHOST, port)), "/metrics").0,
+            "HTTP/1.1 200 OK"
+        );
+        server.shutdown();
+    }
+
+    #[test]
+    fn test_serve_text() {
+        let server = serve_text("127.0.0.1:0").unwrap();
+        let addr = server.local_addr();
+        let counter = global()
+            .counter("test_serve_records_total", &[("run", "1")])
+            .unwrap();
+        let gauge = global().gauge("test_serve_queue_depth", &[]).unwrap();
+        counter.add(3);
+        gauge.set(7.0);
+
+        let (status, body) = get(addr, "/metrics");
+        assert_eq!(status, "HTTP/1.1 200 OK");
+        assert!(
+            body.contains("# TYPE test_serve_records_total counter\n"),
+            "{}",
+            body
+        );
+        assert!(
+            body.contains("test_serve_records_total{run=\"1\"} 3\n"),
+            "{}",
+            body
+        );
+        assert!(body.contains("test_serve_queue_depth 7\n"), "{}", body);
+
+        // updated on the next scrape.
+        counter.add(2);
+        gauge.set(0.0);
+        let (_, body) = get(addr, "/metrics");
+        assert!(
+            body.contains("test_serve_records_total{run=\"1\"} 5\n"),
+            "{}",
+            body
+        );
+        assert!(body.contains("test_serve_queue_depth 0\n"), "{}", body);
+
+        assert_eq!(get(addr, "/").0, "HTTP/1.1 404 Not Found");
+        let (status, _) = scrape(addr, "POST /metrics HTTP/1.1\r\n\r\n");
+        assert_eq!(status, "HTTP/1.1 405 Method Not Allowed");
+
+        // the port is free again once shut down.
+        server.shutdown();
+        assert!(TcpStream::connect(addr).is_err());
+        let server = serve_text(addr).unwrap();
+        assert_eq!(get(server.local_addr(), "/metrics").0, "HTTP/1.1 200 OK");
+        drop(server);
+
+        // in use.
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let err = serve_text(listener.local_addr().unwrap()).unwrap_err();
+        assert!(matches!(err, crate::Error::Other(_)), "{:?}", err);
+    }
+}
@@ -0,0 +1,60 @@
+//! Panics of user code, e.g. of record modifiers and locus workers, caught as
+//! errors, so that they are reported with the item they panicked on and stop a
+//! run like any other error rather than taking down its threads.
+
+use std::{
+    any::Any,
+    panic::{self, AssertUnwindSafe},
+};
+
+use thiserror::Error;
+
+/// A panic caught by the processors, as the source of the
+/// [`WorkerFailed`](crate::Error::WorkerFailed) naming the item it panicked on.
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+#[error("panicked: {message}")]
+pub struct Panicked {
+    /// The message of the panic, if it was a string.
+    pub message: String,
+}
+
+impl Panicked {
+    pub(crate) fn from_payload(payload: Box<dyn Any + Send>) -> Self {
+        let message = match payload.downcast::<String>() {
+            Ok(message) => *message,
+            Err(payload) => match payload.downcast::<&'static str>() {
+                Ok(message) => message.to_string(),
+                Err(_) => "Box<dyn Any>".to_string(),
+            },
+        };
+        Self { message }
+    }
+}
+
+/// Run `f`, catching its panic.
+///
+/// What `f` borrows mutably may be left half-updated by the panic, so it must
+/// be treated as lost, e.g. a record not written.
+pub(crate) fn catch_panic<T>(f: impl FnOnce() -> T) -> Result<T, Panicked> {
+    panic::catch_unwind(AssertUnwindSafe(f)).map_err(Panicked::from_payload)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_catch_panic() {
+        assert_eq!(catch_panic(|| 1), Ok(1));
+        assert_eq!(
+            catch_panic(|| panic!("bad {}", "read")),
+            Err::<(), _>(Panicked {
+                message: "bad read".to_string()
+            })
+        );
+        let err = catch_panic(|| panic!("static")).unwrap_err();
+        assert_eq!(err.to_string(), "panicked: static");
+        let err = catch_panic(|| std::panic::panic_any(7)).unwrap_err();
+        assert_eq!(err.message, "Box<dyn Any>");
+    }
+}
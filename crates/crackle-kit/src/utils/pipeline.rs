@@ -0,0 +1,993 @@
+//! Reader -> workers -> writer pipelines which keep the input order.
+//!
+//! [`OrderedPipeline`] runs a producer on one thread, a closure on each item on
+//! `worker_threads` threads, and a consumer on one thread which gets the batches
+//! back in input order. Items are moved between the stages in [`Batch`]es, which
+//! the consumer hands back to the producer once done, so the items (e.g. bam
+//! records) are allocated once per run rather than once per item.
+//!
+//! ```
+//! use crackle_kit::utils::pipeline::OrderedPipeline;
+//!
+//! let mut inputs = 0..10_000_u64;
+//! let mut squares = vec![];
+//! OrderedPipeline::new(
+//!     |x: &mut u64| match inputs.next() {
+//!         Some(v) => {
+//!             *x = v;
+//!             Ok(true)
+//!         }
+//!         None => Ok(false),
+//!     },
+//!     |x: &mut u64| Ok((*x % 2 == 0).then(|| *x * *x)),
+//!     |batch| {
+//!         squares.extend(batch.kept().map(|(_, square)| *square));
+//!         Ok(())
+//!     },
+//! )
+//! .with_worker_threads(4)
+//! .with_batch_size(128)
+//! .run()?;
+//!
+//! assert_eq!(squares.len(), 5_000);
+//! assert_eq!(squares[..3], [0, 4, 16]);
+//! # Ok::<(), anyhow::Error>(())
+//! ```
+
+use std::{
+    collections::HashMap,
+    sync::{
+        Arc, OnceLock,
+        atomic::{self, AtomicBool},
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use anyhow::{Error, bail};
+use crossbeam_channel::{Receiver, RecvTimeoutError, bounded};
+use tracing::{Level, Span, event, field, span};
+
+use crate::{
+    data::data_with_index::DataWithIndex,
+    utils::{
+        batched_data::BatchedData,
+        channel_metrics::{ChannelMeter, ChannelStats, metered},
+        instrument::{BusyTime, current_dispatch, enter_on_thread},
+        panic::{Panicked, catch_panic},
+        pool::{ObjectPool, PoolStats},
+    },
+};
+
+/// Samples of the channels of a run for [`ChannelStats`] per metrics report,
+/// see [`OrderedPipeline::with_metrics_interval`].
+const CHANNEL_SAMPLES_PER_REPORT: u32 = 20;
+
+/// Least time between two samples of the channels, however short the metrics
+/// interval.
+const MIN_CHANNEL_SAMPLE_INTERVAL: Duration = Duration::from_millis(1);
+
+/// How often a stage waiting on a channel checks whether another one failed.
+const FAILURE_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+type Producer<'a, I> = Box<dyn FnMut(&mut I) -> Result<bool, Error> + Send + 'a>;
+type Work<'a, I, O> = Box<dyn FnMut(&mut I) -> Result<Option<O>, Error> + 'a>;
+type MakeWorker<'a, I, O> = Box<dyn Fn(usize) -> Result<Work<'a, I, O>, Error> + Sync + 'a>;
+type Consumer<'a, I, O> = Box<dyn FnMut(&mut Batch<I, O>) -> Result<(), Error> + Send + 'a>;
+
+/// Items moved together between the stages of an [`OrderedPipeline`], with the
+/// outputs of the worker.
+pub struct Batch<I, O> {
+    seq: usize,
+    items: BatchedData<DataWithIndex<I>>,
+    outputs: Vec<Option<O>>,
+}
+
+impl<I: Default, O> Batch<I, O> {
+    fn new(batch_size: usize) -> Self {
+        Self {
+            seq: 0,
+            items: BatchedData::from_vec(
+                (0..batch_size).map(|_| DataWithIndex::default()).collect(),
+            ),
+            outputs: Vec::with_capacity(batch_size),
+        }
+    }
+}
+
+impl<I, O> Batch<I, O> {
+    pub fn len(&self) -> usize {
+        self.items.filled().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    /// Index in the input of the first item.
+    pub fn first_idx(&self) -> Option<usize> {
+        self.items.filled().first().map(|item| item.idx)
+    }
+
+    /// Every item with its output, `None` where the worker dropped it.
+    pub fn iter(&self) -> impl Iterator<Item = (&I, Option<&O>)> {
+        self.items
+            .filled()
+            .iter()
+            .zip(self.outputs.iter())
+            .map(|(item, output)| (item.data(), output.as_ref()))
+    }
+
+    /// Items the worker kept, with their output.
+    pub fn kept(&self) -> impl Iterator<Item = (&I, &O)> {
+        self.iter()
+            .filter_map(|(item, output)| output.map(|output| (item, output)))
+    }
+
+    /// Make the batch empty, keeping the items for the next fill.
+    fn recycle(&mut self) {
+        self.items.reset_index();
+        self.outputs.clear();
+    }
+}
+
+/// Counts and busy times of a finished [`OrderedPipeline`] run. Busy times are
+/// summed over the threads of a stage.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PipelineReport {
+    pub n_items: u64,
+    pub reader_busy: Duration,
+    pub worker_busy: Duration,
+    pub writer_busy: Duration,
+    /// Reuse of the batches, which are only allocated while none is idle.
+    pub batch_pool: PoolStats,
+    /// Batches from the reader to the workers: full when the workers are
+    /// behind, empty when the reader is.
+    pub read_channel: ChannelStats,
+    /// Batches from the workers to the writer: full when the writer is behind,
+    /// empty when the workers are.
+    pub write_channel: ChannelStats,
+}
+
+/// Busy time of each stage, summed over the workers.
+#[derive(Debug, Default)]
+struct StageBusyTime {
+    reader: BusyTime,
+    worker: BusyTime,
+    writer: BusyTime,
+}
+
+/// Stage of a pipeline, used to report which one failed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum PipelineStage {
+    Reader,
+    Worker(usize),
+    Writer,
+}
+
+impl std::fmt::Display for PipelineStage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PipelineStage::Reader => write!(f, "reader"),
+            PipelineStage::Worker(i) => write!(f, "worker {}", i),
+            PipelineStage::Writer => write!(f, "writer"),
+        }
+    }
+}
+
+/// Failure flag shared by the pipeline threads.
+///
+/// A stage that returns an error sets it before releasing its channel ends, so the
+/// other stages can tell a shutdown caused by a failure from a normal end of input.
+/// A cancellation of the run counts as a failure.
+#[derive(Debug)]
+pub(crate) struct PipelineFailure<'a> {
+    failed: AtomicBool,
+    first_stage: OnceLock<PipelineStage>,
+    cancelled: &'a AtomicBool,
+}
+
+impl<'a> PipelineFailure<'a> {
+    pub(crate) fn new(cancelled: &'a AtomicBool) -> Self {
+        Self {
+            failed: AtomicBool::new(false),
+            first_stage: OnceLock::new(),
+            cancelled,
+        }
+    }
+
+    pub(crate) fn is_set(&self) -> bool {
+        self.failed.load(atomic::Ordering::Acquire)
+            || self.cancelled.load(atomic::Ordering::Acquire)
+    }
+
+    /// Receive through `recv_timeout` until an item comes, or `None` once the
+    /// channel is disconnected or the flag is set.
+    ///
+    /// A blocking receive cannot be used: the stages wait on each other in a
+    /// cycle through the recycled batches, and the channel ends held by the
+    /// stages still running keep a failed stage from disconnecting them.
+    pub(crate) fn recv<T>(
+        &self,
+        mut recv_timeout: impl FnMut(Duration) -> Result<T, RecvTimeoutError>,
+    ) -> Option<T> {
+        loop {
+            match recv_timeout(FAILURE_POLL_INTERVAL) {
+                Ok(msg) => return Some(msg),
+                Err(RecvTimeoutError::Timeout) if !self.is_set() => {}
+                Err(_) => return None,
+            }
+        }
+    }
+
+    pub(crate) fn mark_if_err<T>(
+        &self,
+        stage: PipelineStage,
+        res: Result<T, Error>,
+    ) -> Result<T, Error> {
+        if res.is_err() {
+            let _ = self.first_stage.set(stage);
+            self.failed.store(true, atomic::Ordering::Release);
+        }
+        res
+    }
+
+    /// Pick the error of the stage which failed first, logging the others.
+    pub(crate) fn into_result(
+        self,
+        results: Vec<(PipelineStage, Result<(), Error>)>,
+    ) -> Result<(), Error> {
+        let first_stage = self.first_stage.into_inner();
+        let mut first_err = None;
+
+        for (stage, res) in results {
+            let Err(err) = res else { continue };
+
+            if first_err.is_none() && (first_stage.is_none() || first_stage == Some(stage)) {
+                first_err = Some(err.context(format!("{} thread failed", stage)));
+            } else {
+                event!(Level::DEBUG, "{} thread also failed: {:#}", stage, err);
+            }
+        }
+
+        match first_err {
+            Some(err) => Err(err),
+            None if self.cancelled.load(atomic::Ordering::Acquire) => {
+                Err(crate::errors::Error::Cancelled.into())
+            }
+            None => Ok(()),
+        }
+    }
+}
+
+/// Producer -> workers -> consumer pipeline keeping the input order.
+///
+/// - The producer fills one item at a time and returns `false` once the input
+///   is exhausted, leaving the item untouched.
+/// - The worker modifies each item in place and returns its output, or `None`
+///   to drop it. Its error stops the run.
+/// - The consumer gets the batches in input order, dropped items included.
+///
+/// If a stage fails or the run is cancelled (see [`Self::with_cancel_flag`]),
+/// the others stop at their next batch and [`Self::run`] returns the error of
+/// the stage which failed first, as `"{stage} thread failed"`.
+///
+/// Stages run in `reader`, `worker` and `writer` spans, children of the span
+/// current when [`Self::run`] is called, with a `batch` span for each batch.
+/// While DEBUG events are enabled, or with the `metrics` feature, the channels
+/// between them are sampled, see [`PipelineReport::read_channel`], and
+/// reported every [`Self::with_metrics_interval`]. Otherwise no thread samples
+/// them and their stats have no samples.
+pub struct OrderedPipeline<'a, I, O> {
+    producer: Producer<'a, I>,
+    make_worker: MakeWorker<'a, I, O>,
+    consumer: Consumer<'a, I, O>,
+    worker_threads: usize,
+    batch_size: usize,
+    channel_capacity: usize,
+    cancelled: Option<&'a AtomicBool>,
+    metrics_interval: Duration,
+}
+
+impl<'a, I: Default + Send, O: Send> OrderedPipeline<'a, I, O> {
+    /// With 4 worker threads, batches of 1024 items and 128 batches per channel.
+    pub fn new(
+        producer: impl FnMut(&mut I) -> Result<bool, Error> + Send + 'a,
+        worker: impl Fn(&mut I) -> Result<Option<O>, Error> + Send + Sync + 'a,
+        consumer: impl FnMut(&mut Batch<I, O>) -> Result<(), Error> + Send + 'a,
+    ) -> Self {
+        let worker = Arc::new(worker);
+        Self::with_worker_init(
+            producer,
+            move |_| {
+                let worker = Arc::clone(&worker);
+                Ok(move |item: &mut I| worker(item))
+            },
+            consumer,
+        )
+    }
+
+    /// [`Self::new`] with a worker made on each worker thread by `make_worker`,
+    /// called with the index of the thread. The worker can then own state which
+    /// is not `Send`, like an `Rc`, or reuse buffers across items.
+    pub fn with_worker_init<W>(
+        producer: impl FnMut(&mut I) -> Result<bool, Error> + Send + 'a,
+        make_worker: impl Fn(usize) -> Result<W, Error> + Sync + 'a,
+        consumer: impl FnMut(&mut Batch<I, O>) -> Result<(), Error> + Send + 'a,
+    ) -> Self
+    where
+        W: FnMut(&mut I) -> Result<Option<O>, Error> + 'a,
+    {
+        Self {
+            producer: Box::new(producer),
+            make_worker: Box::new(move |worker_i| {
+                make_worker(worker_i).map(|w| Box::new(w) as Work<'a, I, O>)
+            }),
+            consumer: Box::new(consumer),
+            worker_threads: 4,
+            batch_size: 1024,
+            channel_capacity: 128,
+            cancelled: None,
+            metrics_interval: DEFAULT_METRICS_INTERVAL,
+        }
+    }
+
+    pub fn with_worker_threads(mut self, worker_threads: usize) -> Self {
+        self.worker_threads = worker_threads;
+        self
+    }
+
+    /// Items per batch.
+    pub fn with_batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size;
+        self
+    }
+
+    /// Batches each channel can hold. As many batches are allocated up front,
+    /// and no more during the run.
+    pub fn with_channel_capacity(mut self, channel_capacity: usize) -> Self {
+        self.channel_capacity = channel_capacity;
+        self
+    }
+
+    /// Stop the run when `cancelled` is set, failing with
+    /// [`Error::Cancelled`](crate::errors::Error::Cancelled).
+    pub fn with_cancel_flag(mut self, cancelled: &'a AtomicBool) -> Self {
+        self.cancelled = Some(cancelled);
+        self
+    }
+
+    /// Report the channel metrics at DEBUG this often, 10 s by default. The
+    /// channels are sampled 20 times per report.
+    pub fn with_metrics_interval(mut self, metrics_interval: Duration) -> Self {
+        self.metrics_interval = metrics_interval;
+        self
+    }
+
+    pub fn run(self) -> Result<PipelineReport, Error> {
+        let Self {
+            mut producer,
+            make_worker,
+            mut consumer,
+            worker_threads,
+            batch_size,
+            channel_capacity,
+            cancelled,
+            metrics_interval,
+        } = self;
+        if worker_threads == 0 || batch_size == 0 || channel_capacity == 0 {
+            bail!(
+                "Worker threads, batch size and channel capacity must be at least 1, got {}, {} and {}",
+                worker_threads,
+                batch_size,
+                channel_capacity
+            );
+        }
+
+        let (tx_read, rx_read) = metered::<Batch<I, O>>("reader->workers", channel_capacity);
+        let (tx_worker, mut rx_worker) =
+            metered::<Batch<I, O>>("workers->writer", channel_capacity);
+        let meters = [Arc::clone(tx_read.meter()), Arc::clone(tx_worker.meter())];
+        let (tx_buf, rx_buf) = bounded::<Batch<I, O>>(channel_capacity);
+        // batches the reader's channel had no room for.
+        let batch_pool = ObjectPool::new(channel_capacity);
+        for _ in 0..channel_capacity {
+            // can not fail: the channel is open and has room for them.
+            let _ = tx_buf.send(batch_pool.get_or_else(|| Batch::new(batch_size)));
+        }
+
+        let not_cancelled = AtomicBool::new(false);
+        let failure = PipelineFailure::new(cancelled.unwrap_or(&not_cancelled));
+        let busy = StageBusyTime::default();
+        let parent = Span::current();
+        let dispatch = current_dispatch();
+        // dropped once the stages are done, stopping the sampler.
+        let (stages_done, done_rx) = bounded::<()>(0);
+        let report_metrics = cfg!(feature = "metrics") || tracing::enabled!(Level::DEBUG);
+
+        let (results, n_items) = thread::scope(|s| {
+            let (failure, busy, batch_pool) = (&failure, &busy, &batch_pool);
+            let (parent, dispatch) = (&parent, &dispatch);
+            let make_worker = &make_worker;
+            let meters = &meters;
+
+            if report_metrics {
+                s.spawn(move || {
+                    let _stage = enter_on_thread(
+                        dispatch,
+                        || span!(parent: parent, Level::DEBUG, "metrics"),
+                    );
+                    sample_channels(meters, &done_rx, metrics_interval);
+                });
+            }
+
+            let reader_handle = s.spawn(move || {
+                let _stage =
+                    enter_on_thread(dispatch, || span!(parent: parent, Level::DEBUG, "reader"));
+                let res = catch_panic(|| {
+                    let (mut i, mut seq) = (0, 0);
+
+                    loop {
+                        if failure.is_set() {
+                            event!(Level::DEBUG, "Reader stops: downstream failed.");
+                            break;
+                        }
+
+                        let Some(mut batch) = failure.recv(|t| rx_buf.recv_timeout(t)) else {
+                            break;
+                        };
+
+                        let batch_span =
+                            span!(Level::TRACE, "batch", first_record = i, size = field::Empty)
+                                .entered();
+                        let batch_busy = busy.reader.start();
+                        let mut exhausted = false;
+                        while let Some(item) = batch.items.next_mut() {
+                            if producer(item.data_mut())? {
+                                item.idx = i;
+                                item.skip = false;
+                                i += 1;
+                            } else {
+                                // the slot taken for this item is not filled.
+                                batch.items.unfill_last();
+                                exhausted = true;
+                                break;
+                            }
+                        }
+                        batch_span.record("size", batch.len());
+                        drop((batch_busy, batch_span));
+
+                        if !batch.is_empty() {
+                            batch.seq = seq;
+                            seq += 1;
+                            // a send error means the workers are gone, which only
+                            // happens after a failure.
+                            if tx_read.send(batch).is_err() {
+                                break;
+                            }
+                        }
+                        if exhausted {
+                            break;
+                        }
+                    }
+
+                    event!(Level::DEBUG, "Reader thread ended.");
+                    Ok::<_, Error>(())
+                })
+                .unwrap_or_else(|panicked| Err(panicked.into()));
+
+                failure.mark_if_err(PipelineStage::Reader, res)
+            });
+
+            let mut worker_handles = Vec::with_capacity(worker_threads);
+            for worker_i in 0..worker_threads {
+                let mut rx_read = rx_read.clone();
+                let tx_worker = tx_worker.clone();
+
+                worker_handles.push(s.spawn(move || {
+                    let _stage = enter_on_thread(
+                        dispatch,
+                        || span!(parent: parent, Level::DEBUG, "worker", worker = worker_i),
+                    );
+                    let res = catch_panic(|| {
+                        let mut work = make_worker(worker_i)?;
+
+                        loop {
+                            if failure.is_set() {
+                                break;
+                            }
+
+                            let Some(mut batch) = failure.recv(|t| rx_read.recv_timeout(t)) else {
+                                break;
+                            };
+
+                            let batch_span = span!(
+                                Level::TRACE,
+                                "batch",
+                                first_record = batch.first_idx().unwrap_or(0),
+                                size = batch.len(),
+                            )
+                            .entered();
+                            let batch_busy = busy.worker.start();
+                            for item in batch.items.filled_mut() {
+                                let output = work(item.data_mut())?;
+                                item.skip = output.is_none();
+                                batch.outputs.push(output);
+                            }
+                            drop((batch_busy, batch_span));
+
+                            if tx_worker.send(batch).is_err() {
+                                // the writer is gone, which only happens after a failure.
+                                break;
+                            }
+                        }
+
+                        event!(Level::DEBUG, "Worker thread {} ended.", worker_i);
+                        Ok::<_, Error>(())
+                    })
+                    .unwrap_or_else(|panicked| Err(panicked.into()));
+
+                    failure.mark_if_err(PipelineStage::Worker(worker_i), res)
+                }));
+            }
+
+            // Only the threads hold channel ends from here, so a stage exiting early
+            // disconnects its neighbours instead of leaving them blocked.
+            drop(rx_read);
+            drop(tx_worker);
+
+            let writer_handle = s.spawn(move || {
+                let _stage =
+                    enter_on_thread(dispatch, || span!(parent: parent, Level::DEBUG, "writer"));
+                let res = catch_panic(|| {
+                    let mut n_items = 0;
+                    let mut next_seq = 0;
+                    // batches which arrived before those preceding them.
+                    let mut pending = HashMap::<usize, Batch<I, O>>::new();
+
+                    let mut consume = |mut batch: Batch<I, O>, recycle: bool| {
+                        let _batch_span = span!(
+                            Level::TRACE,
+                            "batch",
+                            first_record = batch.first_idx().unwrap_or(0),
+                            size = batch.len(),
+                        )
+                        .entered();
+                        let _busy = busy.writer.start();
+
+                        consumer(&mut batch)?;
+                        n_items += batch.len() as u64;
+
+                        if recycle {
+                            batch.recycle();
+                            // the reader may have finished already.
+                            if let Err(err) = tx_buf.try_send(batch) {
+                                batch_pool.put(err.into_inner());
+                            }
+                        }
+                        Ok::<_, Error>(())
+                    };
+
+                    loop {
+                        if failure.is_set() {
+                            event!(Level::DEBUG, "Writer stops: another stage failed.");
+                            return Ok(0);
+                        }
+
+                        let Some(batch) = failure.recv(|t| rx_worker.recv_timeout(t)) else {
+                            // stop as on a failure seen at the top of the loop.
+                            if failure.is_set() {
+                                continue;
+                            }
+                            break;
+                        };
+
+                        if batch.seq != next_seq {
+                            pending.insert(batch.seq, batch);
+                            // an idle batch for the reader, in place of the one kept here.
+                            if let Some(spare) = batch_pool.get()
+                                && let Err(err) = tx_buf.try_send(spare)
+                            {
+                                batch_pool.put(err.into_inner());
+                            }
+                            continue;
+                        }
+
+                        consume(batch, true)?;
+                        next_seq += 1;
+                        while let Some(batch) = pending.remove(&next_seq) {
+                            consume(batch, true)?;
+                            next_seq += 1;
+                        }
+                    }
+
+                    debug_assert!(pending.is_empty() || failure.is_set());
+                    event!(Level::DEBUG, "Writer thread ended.");
+
+                    Ok::<_, Error>(n_items)
+                })
+                .unwrap_or_else(|panicked| Err(panicked.into()));
+
+                failure.mark_if_err(PipelineStage::Writer, res)
+            });
+
+            // the stages catch their panics, but not those of their spans.
+            let mut results = Vec::with_capacity(worker_threads + 2);
+            results.push((PipelineStage::Reader, joined(reader_handle.join())));
+            for (worker_i, handle) in worker_handles.into_iter().enumerate() {
+                results.push((PipelineStage::Worker(worker_i), joined(handle.join())));
+            }
+            let (writer_res, n_items) = match joined(writer_handle.join()) {
+                Ok(n_items) => (Ok(()), n_items),
+                Err(err) => (Err(err), 0),
+            };
+            results.push((PipelineStage::Writer, writer_res));
+            drop(stages_done);
+
+            (results, n_items)
+        });
+
+        failure.into_result(results)?;
+
+        Ok(PipelineReport {
+            n_items,
+            reader_busy: busy.reader.get(),
+            worker_busy: busy.worker.get(),
+            writer_busy: busy.writer.get(),
+            batch_pool: batch_pool.stats(),
+            read_channel: meters[0].stats(),
+            write_channel: meters[1].stats(),
+        })
+    }
+}
+
+/// The result of a stage thread, its panic as a [`Panicked`] error.
+pub(crate) fn joined<T>(res: thread::Result<Result<T, Error>>) -> Result<T, Error> {
+    res.unwrap_or_else(|payload| Err(Panicked::from_payload(payload).into()))
+}
+
+/// Default of [`OrderedPipeline::with_metrics_interval`].
+pub const DEFAULT_METRICS_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Sample `meters` [`CHANNEL_SAMPLES_PER_REPORT`] times per `report_interval`
+/// until `done` is disconnected, logging them every `report_interval`.
+///
+/// With the `metrics` feature, the number of items queued in each channel is
+/// published as it is sampled, see [`ChannelMeter::publish_depth`].
+fn sample_channels(meters: &[Arc<ChannelMeter>], done: &Receiver<()>, report_interval: Duration) {
+    #[cfg(feature = "metrics")]
+    let depths = meters
+        .iter()
+        .filter_map(
+            |meter| match meter.publish_depth(crate::utils::metrics::global()) {
+                Ok(depth) => Some((meter, depth)),
+                // the run goes on without the gauge.
+                Err(err) => {
+                    event!(Level::WARN, "{:#}", err);
+                    None
+                }
+            },
+        )
+        .collect::<Vec<_>>();
+
+    let sample_interval =
+        (report_interval / CHANNEL_SAMPLES_PER_REPORT).max(MIN_CHANNEL_SAMPLE_INTERVAL);
+    let mut last_report = Instant::now();
+    loop {
+        for meter in meters {
+            meter.sample();
+        }
+        #[cfg(feature = "metrics")]
+        for (meter, depth) in &depths {
+            depth.set(meter.len() as f64);
+        }
+
+        if last_report.elapsed() >= report_interval {
+            for meter in meters {
+                let stats = meter.stats();
+                event!(
+                    Level::DEBUG,
+                    channel = stats.name,
+                    len = meter.len(),
+                    high_water = meter.take_window_high_water(),
+                    full = format!("{:.3}", stats.full_fraction()),
+                    empty = format!("{:.3}", stats.empty_fraction()),
+                    "Channel metrics"
+                );
+            }
+            last_report = Instant::now();
+        }
+        if done.recv_timeout(sample_interval) != Err(RecvTimeoutError::Timeout) {
+            break;
+        }
+    }
+
+    // the channels of a failed run may be left with items.
+    #[cfg(feature = "metrics")]
+    for (meter, depth) in &depths {
+        depth.set(meter.len() as f64);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::atomic::{AtomicU64, AtomicUsize},
+        time::Instant,
+    };
+
+    use rand::{Rng, SeedableRng};
+    use tracing_subscriber::filter::LevelFilter;
+
+    use super::*;
+    use crate::utils::pipeline_test_kit::{with_debug_tracing, with_tracing_level};
+
+    /// Yields `0..n`.
+    fn count_to(n: u64) -> impl FnMut(&mut u64) -> Result<bool, Error> + Send {
+        let mut next = 0;
+        move |x| {
+            if next == n {
+                return Ok(false);
+            }
+            *x = next;
+            next += 1;
+            Ok(true)
+        }
+    }
+
+    #[test]
+    fn test_order_is_kept_under_contention() -> Result<(), Error> {
+        let n = 20_000;
+        let mut squares = Vec::with_capacity(n as usize);
+        let mut n_batches = 0;
+
+        let report = OrderedPipeline::new(
+            count_to(n),
+            |x: &mut u64| {
+                // uneven work so that batches finish out of order.
+                let mut rng = rand::rngs::StdRng::seed_from_u64(*x);
+                if rng.random_range(0..100) == 0 {
+                    thread::sleep(Duration::from_micros(rng.random_range(0..500)));
+                }
+                let square = *x * *x;
+                *x += 1;
+                // drop multiples of 7.
+                Ok((!square.is_multiple_of(7)).then_some(square))
+            },
+            |batch| {
+                n_batches += 1;
+                let first = batch.first_idx().unwrap() as u64;
+                for (i, (x, square)) in batch.iter().enumerate() {
+                    // the worker modified the item in place.
+                    assert_eq!(*x, first + i as u64 + 1);
+                    squares.push((first + i as u64, square.copied()));
+                }
+                Ok(())
+            },
+        )
+        .with_worker_threads(8)
+        .with_batch_size(16)
+        .with_channel_capacity(4)
+        .run()?;
+
+        assert_eq!(report.n_items, n);
+        assert_eq!(n_batches, n.div_ceil(16));
+        assert_eq!(report.batch_pool.n_created, 4);
+        assert_eq!(squares.len(), n as usize);
+        for (i, (x, square)) in squares.into_iter().enumerate() {
+            assert_eq!(x, i as u64);
+            assert_eq!(square, (x % 7 != 0).then_some(x * x));
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_empty_input_and_worker_state() -> Result<(), Error> {
+        let n_consumed = AtomicUsize::new(0);
+        let report = OrderedPipeline::<u64, ()>::new(
+            count_to(0),
+            |_| Ok(Some(())),
+            |_| {
+                n_consumed.fetch_add(1, atomic::Ordering::Relaxed);
+                Ok(())
+            },
+        )
+        .run()?;
+        assert_eq!(report.n_items, 0);
+        assert_eq!(n_consumed.load(atomic::Ordering::Relaxed), 0);
+
+        // each worker counts its items in its own `Rc`.
+        let total = AtomicU64::new(0);
+        OrderedPipeline::with_worker_init(
+            count_to(1_000),
+            |_| {
+                let seen = std::rc::Rc::new(std::cell::Cell::new(0));
+                let total = &total;
+                Ok(move |_: &mut u64| {
+                    seen.set(seen.get() + 1);
+                    total.fetch_add(1, atomic::Ordering::Relaxed);
+                    Ok(Some(seen.get()))
+                })
+            },
+            |_| Ok(()),
+        )
+        .with_worker_threads(3)
+        .run()?;
+        assert_eq!(total.load(atomic::Ordering::Relaxed), 1_000);
+
+        assert!(
+            OrderedPipeline::<u64, ()>::new(count_to(1), |_| Ok(None), |_| Ok(()))
+                .with_batch_size(0)
+                .run()
+                .is_err()
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_errors_and_cancellation() -> Result<(), Error> {
+        // a failing worker stops an endless input.
+        let timer = Instant::now();
+        let err = OrderedPipeline::new(
+            count_to(u64::MAX),
+            |x: &mut u64| match *x {
+                10_000 => bail!("failed at {}", x),
+                _ => Ok(Some(())),
+            },
+            |_| Ok(()),
+        )
+        .with_batch_size(64)
+        .with_channel_capacity(4)
+        .run()
+        .unwrap_err();
+        assert!(timer.elapsed() < Duration::from_secs(30));
+        let msg = format!("{:#}", err);
+        assert!(msg.starts_with("worker ") && msg.ends_with("thread failed: failed at 10000"));
+
+        let err = OrderedPipeline::<u64, ()>::new(
+            count_to(1_000),
+            |_| Ok(Some(())),
+            |_| bail!("disk full"),
+        )
+        .run()
+        .unwrap_err();
+        assert_eq!(format!("{:#}", err), "writer thread failed: disk full");
+
+        let err =
+            OrderedPipeline::<u64, ()>::new(|_| bail!("no input"), |_| Ok(Some(())), |_| Ok(()))
+                .run()
+                .unwrap_err();
+        assert_eq!(format!("{:#}", err), "reader thread failed: no input");
+
+        let cancelled = AtomicBool::new(false);
+        let mut n_consumed = 0;
+        let err = OrderedPipeline::<u64, ()>::new(
+            count_to(u64::MAX),
+            |_| Ok(Some(())),
+            |_| {
+                n_consumed += 1;
+                if n_consumed == 10 {
+                    cancelled.store(true, atomic::Ordering::Release);
+                }
+                Ok(())
+            },
+        )
+        .with_cancel_flag(&cancelled)
+        .run()
+        .unwrap_err();
+        assert_eq!(err.to_string(), "Processing was cancelled");
+        assert!(matches!(
+            crate::errors::Error::from(err),
+            crate::errors::Error::Cancelled
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_panicking_stages() {
+        // a panicking writer stops an endless input.
+        let timer = Instant::now();
+        let err = OrderedPipeline::<u64, ()>::new(
+            count_to(u64::MAX),
+            |_| Ok(Some(())),
+            |_| panic!("disk on fire"),
+        )
+        .with_channel_capacity(4)
+        .run()
+        .unwrap_err();
+        assert!(timer.elapsed() < Duration::from_secs(30));
+        assert_eq!(
+            format!("{:#}", err),
+            "writer thread failed: panicked: disk on fire"
+        );
+
+        let err = OrderedPipeline::new(
+            count_to(1_000),
+            |x: &mut u64| match *x {
+                500 => panic!("bad item"),
+                _ => Ok(Some(())),
+            },
+            |_| Ok(()),
+        )
+        .run()
+        .unwrap_err();
+        assert!(format!("{:#}", err).ends_with("thread failed: panicked: bad item"));
+
+        let err =
+            OrderedPipeline::<u64, ()>::new(|_| panic!("no input"), |_| Ok(Some(())), |_| Ok(()))
+                .run()
+                .unwrap_err();
+        assert_eq!(
+            format!("{:#}", err),
+            "reader thread failed: panicked: no input"
+        );
+    }
+
+    #[test]
+    fn test_channel_metrics() -> Result<(), Error> {
+        // a slow consumer: the batches pile up before the writer.
+        let slow_consumer = || {
+            OrderedPipeline::new(
+                count_to(2_000),
+                |x: &mut u64| Ok(Some(*x)),
+                |_| {
+                    thread::sleep(Duration::from_millis(2));
+                    Ok(())
+                },
+            )
+            .with_worker_threads(2)
+            .with_batch_size(20)
+            .with_channel_capacity(4)
+            .with_metrics_interval(Duration::from_millis(20))
+        };
+        let report = with_debug_tracing(|| slow_consumer().run())?;
+
+        let (read, write) = (report.read_channel, report.write_channel);
+        assert_eq!(
+            (read.name, write.name),
+            ("reader->workers", "workers->writer")
+        );
+        assert_eq!((read.n_sent, write.n_received), (100, 100));
+        assert!(write.full_fraction() > 0.5, "{:?}", write);
+        assert!(read.full_fraction() < 0.2, "{:?}", read);
+
+        // nothing samples the channels of a run no one reports on.
+        if !cfg!(feature = "metrics") {
+            let report = with_tracing_level(LevelFilter::INFO, || slow_consumer().run())?;
+            assert_eq!(report.write_channel.n_sent, 100);
+            assert_eq!(report.write_channel.n_samples, 0);
+        }
+
+        // slow workers: the reader is held back instead.
+        let report = with_debug_tracing(|| {
+            OrderedPipeline::new(
+                count_to(200),
+                |x: &mut u64| {
+                    thread::sleep(Duration::from_micros(500));
+                    Ok(Some(*x))
+                },
+                |_| Ok(()),
+            )
+            .with_worker_threads(2)
+            .with_batch_size(10)
+            .with_channel_capacity(4)
+            .with_metrics_interval(Duration::from_millis(20))
+            .run()
+        })?;
+        assert!(
+            report.read_channel.full_fraction() > 0.5,
+            "{:?}",
+            report.read_channel
+        );
+        assert!(
+            report.write_channel.full_fraction() < 0.2,
+            "{:?}",
+            report.write_channel
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,447 @@
+//! Fake stages with scripted failures, for tests of how an [`OrderedPipeline`]
+//! and the processors built on it shut down.
+//!
+//! A [`Script`] makes a stage fail, panic or hang at its n-th step. The stages
+//! made by a [`PipelineKit`] then tell, once the run returned:
+//! - whether a stage is still running, see [`PipelineKit::assert_shut_down`];
+//! - whether an item is still held anywhere, by the token each item gets when
+//!   produced;
+//! - which items were written, checked to be in input order by
+//!   [`PipelineKit::assert_written_in_order`].
+
+use std::{
+    panic,
+    sync::{
+        Arc, Mutex,
+        atomic::{self, AtomicUsize},
+    },
+    thread,
+    time::Duration,
+};
+
+use anyhow::{Error, bail};
+use tracing_subscriber::filter::LevelFilter;
+
+use crate::utils::pipeline::{Batch, OrderedPipeline, PipelineReport};
+
+/// What a [`Script`] does at a step.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Fault {
+    Fail,
+    Panic,
+    /// Sleep, then go on.
+    Hang(Duration),
+}
+
+/// Faults of a stage by step, e.g. by item for a producer, by batch for a
+/// consumer.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Script {
+    faults: Vec<(u64, Fault)>,
+}
+
+impl Script {
+    pub(crate) fn new() -> Self {
+        Self::default()
+    }
+
+    pub(crate) fn fail_at(self, step: u64) -> Self {
+        self.at(step, Fault::Fail)
+    }
+
+    pub(crate) fn panic_at(self, step: u64) -> Self {
+        self.at(step, Fault::Panic)
+    }
+
+    pub(crate) fn hang_at(self, step: u64, duration: Duration) -> Self {
+        self.at(step, Fault::Hang(duration))
+    }
+
+    fn at(mut self, step: u64, fault: Fault) -> Self {
+        self.faults.push((step, fault));
+        self
+    }
+
+    /// Run the fault of `step` of `stage`, if any. Errors and panics are
+    /// `"{stage} failed at {step}"` and `"{stage} panicked at {step}"`.
+    pub(crate) fn step(&self, stage: &str, step: u64) -> Result<(), Error> {
+        for &(at, fault) in &self.faults {
+            if at != step {
+                continue;
+            }
+            match fault {
+                Fault::Fail => bail!("{} failed at {}", stage, step),
+                Fault::Panic => panic!("{} panicked at {}", stage, step),
+                Fault::Hang(duration) => thread::sleep(duration),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// An item of the fake stages: its index in the input, and a token held until
+/// it is dropped.
+#[derive(Debug, Default)]
+pub(crate) struct KitItem {
+    pub(crate) idx: u64,
+    token: Option<Arc<()>>,
+}
+
+/// Held by a fake stage, counting it as running until dropped along with it.
+#[derive(Debug)]
+struct Running(Arc<AtomicUsize>);
+
+impl Running {
+    fn new(running: &Arc<AtomicUsize>) -> Self {
+        running.fetch_add(1, atomic::Ordering::AcqRel);
+        Self(Arc::clone(running))
+    }
+}
+
+impl Drop for Running {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, atomic::Ordering::AcqRel);
+    }
+}
+
+/// Makes the fake stages of a run, and checks it once returned.
+///
+/// The producer yields items `0..n`, the workers output twice the index of
+/// each item, and the consumer writes the outputs of each batch to a list.
+#[derive(Debug, Default)]
+pub(crate) struct PipelineKit {
+    running: Arc<AtomicUsize>,
+    token: Arc<()>,
+    written: Arc<Mutex<Vec<(u64, u64)>>>,
+    /// Index of the worker which met a fault of its script.
+    faulty_worker: Arc<Mutex<Option<usize>>>,
+}
+
+impl PipelineKit {
+    pub(crate) fn new() -> Self {
+        Self::default()
+    }
+
+    /// Yields `0..n_items`, with `script` stepped at each item.
+    pub(crate) fn producer(
+        &self,
+        n_items: u64,
+        script: Script,
+    ) -> impl FnMut(&mut KitItem) -> Result<bool, Error> + Send + use<> {
+        let running = Running::new(&self.running);
+        let token = Arc::clone(&self.token);
+        let mut next = 0;
+        move |item| {
+            let _running = &running;
+            if next == n_items {
+                return Ok(false);
+            }
+            script.step("reader", next)?;
+            item.idx = next;
+            item.token = Some(Arc::clone(&token));
+            next += 1;
+            Ok(true)
+        }
+    }
+
+    /// Yields clones of `items` in order, with `script` stepped at each, e.g. to
+    /// feed records made in memory to a processor.
+    pub(crate) fn items_from<I: Clone + Send>(
+        &self,
+        items: Vec<I>,
+        script: Script,
+    ) -> impl FnMut(&mut I) -> Result<bool, Error> + Send + use<I> {
+        let running = Running::new(&self.running);
+        let mut items = items.into_iter().enumerate();
+        move |item| {
+            let _running = &running;
+            let Some((i, next)) = items.next() else {
+                return Ok(false);
+            };
+            script.step("reader", i as u64)?;
+            *item = next;
+            Ok(true)
+        }
+    }
+
+    /// Workers outputting twice the index of each item, with `script` stepped
+    /// at the index of each item, whichever worker gets it.
+    #[allow(clippy::type_complexity)]
+    pub(crate) fn make_worker(
+        &self,
+        script: Script,
+    ) -> impl Fn(usize) -> Result<Box<dyn FnMut(&mut KitItem) -> Result<Option<u64>, Error>>, Error>
+    + Sync
+    + use<> {
+        let running = Arc::clone(&self.running);
+        let faulty_worker = Arc::clone(&self.faulty_worker);
+        move |worker_i| {
+            let running = Running::new(&running);
+            let script = script.clone();
+            let faulty_worker = Arc::clone(&faulty_worker);
+            Ok(Box::new(move |item: &mut KitItem| {
+                let _running = &running;
+                let idx = item.idx;
+                let res = panic::catch_unwind(|| script.step("worker", idx));
+                if !matches!(res, Ok(Ok(()))) {
+                    *faulty_worker.lock().unwrap() = Some(worker_i);
+                }
+                match res {
+                    Ok(res) => res.map(|()| Some(idx * 2)),
+                    Err(payload) => panic::resume_unwind(payload),
+                }
+            }))
+        }
+    }
+
+    /// Writes the outputs of each batch after sleeping `delay`, with `script`
+    /// stepped at each batch before it is written.
+    pub(crate) fn consumer(
+        &self,
+        script: Script,
+        delay: Duration,
+    ) -> impl FnMut(&mut Batch<KitItem, u64>) -> Result<(), Error> + Send + use<> {
+        let running = Running::new(&self.running);
+        let written = Arc::clone(&self.written);
+        let mut n_batches = 0;
+        move |batch| {
+            let _running = &running;
+            thread::sleep(delay);
+            script.step("writer", n_batches)?;
+            n_batches += 1;
+            let mut written = written.lock().unwrap();
+            written.extend(batch.kept().map(|(item, output)| (item.idx, *output)));
+            Ok(())
+        }
+    }
+
+    /// A pipeline of the fake stages on `n_items` items, with the scripts of
+    /// the reader, the workers and the writer.
+    pub(crate) fn pipeline(
+        &self,
+        n_items: u64,
+        [reader, worker, writer]: [Script; 3],
+    ) -> OrderedPipeline<'static, KitItem, u64> {
+        OrderedPipeline::with_worker_init(
+            self.producer(n_items, reader),
+            self.make_worker(worker),
+            self.consumer(writer, Duration::ZERO),
+        )
+    }
+
+    /// The worker which met a fault of its script, if any.
+    pub(crate) fn faulty_worker(&self) -> Option<usize> {
+        *self.faulty_worker.lock().unwrap()
+    }
+
+    /// Check that no fake stage is still running, and that every item produced
+    /// was dropped, i.e. no batch was kept past the run.
+    pub(crate) fn assert_shut_down(&self) {
+        let running = self.running.load(atomic::Ordering::Acquire);
+        assert_eq!(running, 0, "{} stages still running", running);
+        let held = Arc::strong_count(&self.token) - 1;
+        assert_eq!(held, 0, "{} items still held", held);
+    }
+
+    /// Check that the items written are the first ones of the input, in order,
+    /// with their outputs, and return how many they are.
+    pub(crate) fn assert_written_in_order(&self) -> u64 {
+        let written = self.written.lock().unwrap();
+        for (i, &(idx, output)) in written.iter().enumerate() {
+            assert_eq!(idx, i as u64, "item {} written at {}", idx, i);
+            assert_eq!(output, idx * 2);
+        }
+        written.len() as u64
+    }
+}
+
+/// Check that `err` is the first error of the run, that of `stage`, e.g.
+/// `worker 3`, ending with `message`.
+pub(crate) fn assert_failed_in(err: &Error, stage: &str, message: &str) {
+    let msg = format!("{:#}", err);
+    assert!(
+        msg.starts_with(&format!("{} thread failed: ", stage)) && msg.ends_with(message),
+        "expected {} to fail with {:?}, got {:?}",
+        stage,
+        message,
+        msg
+    );
+}
+
+/// `f` with DEBUG events enabled, so that the channels of the pipelines it runs
+/// are sampled.
+pub(crate) fn with_debug_tracing<T>(f: impl FnOnce() -> T) -> T {
+    with_tracing_level(LevelFilter::DEBUG, f)
+}
+
+/// `f` under a subscriber of its own at `level`, whatever other tests installed
+/// globally.
+pub(crate) fn with_tracing_level<T>(level: LevelFilter, f: impl FnOnce() -> T) -> T {
+    use tracing_subscriber::layer::SubscriberExt as _;
+
+    let subscriber = tracing_subscriber::registry().with(level);
+    tracing::subscriber::with_default(subscriber, f)
+}
+
+/// Check that no batch was allocated past the `channel_capacity` made up front.
+pub(crate) fn assert_pool_bounded(report: &PipelineReport, channel_capacity: usize) {
+    assert_eq!(
+        report.batch_pool.n_created, channel_capacity as u64,
+        "{:?}",
+        report.batch_pool
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use super::*;
+
+    const N_ITEMS: u64 = 10_000;
+    const BATCH_SIZE: usize = 64;
+    const CAPACITY: usize = 4;
+
+    fn run(kit: &PipelineKit, n_items: u64, scripts: [Script; 3]) -> Result<PipelineReport, Error> {
+        kit.pipeline(n_items, scripts)
+            .with_worker_threads(4)
+            .with_batch_size(BATCH_SIZE)
+            .with_channel_capacity(CAPACITY)
+            .run()
+    }
+
+    #[test]
+    fn test_reader_fails_mid_input() {
+        let kit = PipelineKit::new();
+        let scripts = [Script::new().fail_at(5_000), Script::new(), Script::new()];
+        let err = run(&kit, N_ITEMS, scripts).unwrap_err();
+        assert_failed_in(&err, "reader", "reader failed at 5000");
+        kit.assert_shut_down();
+        assert!(kit.assert_written_in_order() < 5_000);
+    }
+
+    #[test]
+    fn test_worker_fails_on_batch() {
+        let kit = PipelineKit::new();
+        // the first item of the 10th batch.
+        let scripts = [Script::new(), Script::new().fail_at(640), Script::new()];
+        let err = run(&kit, N_ITEMS, scripts).unwrap_err();
+        let worker = kit.faulty_worker().unwrap();
+        assert_failed_in(&err, &format!("worker {}", worker), "worker failed at 640");
+        kit.assert_shut_down();
+        assert!(kit.assert_written_in_order() <= 640);
+    }
+
+    #[test]
+    fn test_worker_fails_after_hang() {
+        let kit = PipelineKit::new();
+        // while the worker hangs, the others take every batch of the pool to the
+        // writer, and all stages wait on a channel when it fails.
+        let worker = Script::new()
+            .hang_at(640, Duration::from_millis(50))
+            .fail_at(640);
+        let timer = Instant::now();
+        let err = run(&kit, N_ITEMS, [Script::new(), worker, Script::new()]).unwrap_err();
+        assert!(timer.elapsed() < Duration::from_secs(10));
+        let worker = kit.faulty_worker().unwrap();
+        assert_failed_in(&err, &format!("worker {}", worker), "worker failed at 640");
+        kit.assert_shut_down();
+        assert!(kit.assert_written_in_order() <= 640);
+    }
+
+    #[test]
+    fn test_writer_fails_on_flush() {
+        let kit = PipelineKit::new();
+        let scripts = [Script::new(), Script::new(), Script::new().fail_at(3)];
+        let err = run(&kit, N_ITEMS, scripts).unwrap_err();
+        assert_failed_in(&err, "writer", "writer failed at 3");
+        kit.assert_shut_down();
+        // the batches before the failing one, and none after.
+        assert_eq!(kit.assert_written_in_order(), 3 * BATCH_SIZE as u64);
+    }
+
+    #[test]
+    fn test_slow_consumer_with_full_channels() -> Result<(), Error> {
+        let kit = PipelineKit::new();
+        let report = with_debug_tracing(|| {
+            OrderedPipeline::with_worker_init(
+                kit.producer(2_000, Script::new()),
+                kit.make_worker(Script::new()),
+                kit.consumer(Script::new(), Duration::from_millis(1)),
+            )
+            .with_worker_threads(2)
+            .with_batch_size(10)
+            .with_channel_capacity(CAPACITY)
+            .with_metrics_interval(Duration::from_millis(20))
+            .run()
+        })?;
+        assert!(report.write_channel.full_fraction() > 0.5, "{:?}", report);
+        assert_pool_bounded(&report, CAPACITY);
+        kit.assert_shut_down();
+        assert_eq!(kit.assert_written_in_order(), 2_000);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_first_error_wins() {
+        // both the worker and the writer fail, the writer after the worker as
+        // it hangs longer first.
+        let kit = PipelineKit::new();
+        let timer = Instant::now();
+        let scripts = [
+            Script::new(),
+            Script::new()
+                .hang_at(200, Duration::from_millis(100))
+                .fail_at(200),
+            Script::new()
+                .hang_at(0, Duration::from_millis(300))
+                .fail_at(0),
+        ];
+        let err = run(&kit, N_ITEMS, scripts).unwrap_err();
+        // the run waits for the hanging writer.
+        assert!(timer.elapsed() >= Duration::from_millis(300));
+        let worker = kit.faulty_worker().unwrap();
+        assert_failed_in(&err, &format!("worker {}", worker), "worker failed at 200");
+        kit.assert_shut_down();
+        assert_eq!(kit.assert_written_in_order(), 0);
+    }
+
+    #[test]
+    fn test_panics_are_attributed() {
+        let kit = PipelineKit::new();
+        let scripts = [Script::new().panic_at(100), Script::new(), Script::new()];
+        let err = run(&kit, N_ITEMS, scripts).unwrap_err();
+        assert_failed_in(&err, "reader", "panicked: reader panicked at 100");
+        kit.assert_shut_down();
+
+        let kit = PipelineKit::new();
+        let scripts = [Script::new(), Script::new().panic_at(4_321), Script::new()];
+        let err = run(&kit, N_ITEMS, scripts).unwrap_err();
+        let worker = kit.faulty_worker().unwrap();
+        assert_failed_in(
+            &err,
+            &format!("worker {}", worker),
+            "panicked: worker panicked at 4321",
+        );
+        kit.assert_shut_down();
+        assert!(kit.assert_written_in_order() <= 4_321);
+    }
+
+    #[test]
+    fn test_hangs_without_failure() -> Result<(), Error> {
+        let kit = PipelineKit::new();
+        let scripts = [
+            Script::new().hang_at(10, Duration::from_millis(50)),
+            Script::new().hang_at(3_000, Duration::from_millis(50)),
+            Script::new().hang_at(0, Duration::from_millis(50)),
+        ];
+        let report = run(&kit, N_ITEMS, scripts)?;
+        assert_eq!(report.n_items, N_ITEMS);
+        assert_pool_bounded(&report, CAPACITY);
+        kit.assert_shut_down();
+        assert_eq!(kit.assert_written_in_order(), N_ITEMS);
+
+        Ok(())
+    }
+}
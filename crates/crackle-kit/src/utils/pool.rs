@@ -0,0 +1,216 @@
+//! A pool of reusable objects, e.g. batches of records, so that buffers are
+//! allocated once per run rather than once per use.
+
+use std::{
+    ops::{Deref, DerefMut},
+    sync::Mutex,
+};
+
+/// Counts of an [`ObjectPool`], for sizing it.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PoolStats {
+    /// Objects made by [`ObjectPool::get_or_else`] as the pool was empty.
+    pub n_created: u64,
+    /// Objects taken from the pool.
+    pub n_reused: u64,
+    /// Objects put back while the pool was full, and so dropped.
+    pub n_dropped: u64,
+    /// Most objects idle in the pool at once.
+    pub max_idle: usize,
+}
+
+/// Idle objects, up to a capacity, shared by threads.
+///
+/// Objects are handed out as they were put back, so the caller resets them
+/// (e.g. [`Vec::clear`]) as needed, keeping their allocations.
+#[derive(Debug)]
+pub struct ObjectPool<T> {
+    capacity: usize,
+    inner: Mutex<PoolInner<T>>,
+}
+
+#[derive(Debug)]
+struct PoolInner<T> {
+    idle: Vec<T>,
+    stats: PoolStats,
+}
+
+impl<T> ObjectPool<T> {
+    /// A pool holding at most `capacity` idle objects.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            inner: Mutex::new(PoolInner {
+                idle: Vec::with_capacity(capacity),
+                stats: PoolStats::default(),
+            }),
+        }
+    }
+
+    /// An idle object, if any.
+    pub fn get(&self) -> Option<T> {
+        let mut inner = self.inner.lock().unwrap();
+        let obj = inner.idle.pop()?;
+        inner.stats.n_reused += 1;
+        Some(obj)
+    }
+
+    /// An idle object, or a new one made by `make` if there is none.
+    pub fn get_or_else(&self, make: impl FnOnce() -> T) -> T {
+        {
+            let mut inner = self.inner.lock().unwrap();
+            if let Some(obj) = inner.idle.pop() {
+                inner.stats.n_reused += 1;
+                return obj;
+            }
+            inner.stats.n_created += 1;
+        }
+
+        make()
+    }
+
+    /// [`Self::get_or_else`], put back when the guard is dropped.
+    pub fn guard(&self, make: impl FnOnce() -> T) -> PoolGuard<'_, T> {
+        PoolGuard {
+            pool: self,
+            obj: Some(self.get_or_else(make)),
+        }
+    }
+
+    /// Put `obj` back for reuse, or drop it if the pool is full.
+    pub fn put(&self, obj: T) {
+        let mut inner = self.inner.lock().unwrap();
+        if inner.idle.len() >= self.capacity {
+            inner.stats.n_dropped += 1;
+            return;
+        }
+
+        inner.idle.push(obj);
+        inner.stats.max_idle = inner.stats.max_idle.max(inner.idle.len());
+    }
+
+    /// Number of idle objects.
+    pub fn len(&self) -> usize {
+        self.inner.lock().unwrap().idle.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn stats(&self) -> PoolStats {
+        self.inner.lock().unwrap().stats
+    }
+}
+
+/// An object of an [`ObjectPool`], put back when dropped.
+pub struct PoolGuard<'a, T> {
+    pool: &'a ObjectPool<T>,
+    obj: Option<T>,
+}
+
+impl<T> PoolGuard<'_, T> {
+    /// Keep the object instead of putting it back.
+    pub fn into_inner(mut self) -> T {
+        self.obj.take().unwrap()
+    }
+}
+
+impl<T> Deref for PoolGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        self.obj.as_ref().unwrap()
+    }
+}
+
+impl<T> DerefMut for PoolGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        self.obj.as_mut().unwrap()
+    }
+}
+
+impl<T> Drop for PoolGuard<'_, T> {
+    fn drop(&mut self) {
+        if let Some(obj) = self.obj.take() {
+            self.pool.put(obj);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use super::*;
+
+    #[test]
+    fn test_pool_reuse() {
+        let pool = ObjectPool::new(2);
+        assert!(pool.get().is_none());
+
+        let mut buf = pool.get_or_else(|| Vec::<u8>::with_capacity(64));
+        buf.extend(b"ACGT");
+        pool.put(buf);
+        assert_eq!(pool.len(), 1);
+
+        // the allocation is kept, the content too.
+        let buf = pool.get_or_else(Vec::new);
+        assert_eq!((buf.as_slice(), buf.capacity()), (&b"ACGT"[..], 64));
+
+        {
+            let mut guard = pool.guard(Vec::new);
+            guard.push(1);
+        }
+        assert_eq!(pool.get().unwrap(), vec![1]);
+        let kept = pool.guard(Vec::new).into_inner();
+        assert!(pool.is_empty());
+
+        pool.put(buf);
+        pool.put(kept);
+        pool.put(vec![]);
+        assert_eq!(pool.len(), 2);
+        assert_eq!(
+            pool.stats(),
+            PoolStats {
+                n_created: 3,
+                n_reused: 2,
+                n_dropped: 1,
+                max_idle: 2,
+            }
+        );
+    }
+
+    #[test]
+    fn test_pool_concurrent() {
+        let pool = ObjectPool::new(8);
+        thread::scope(|s| {
+            for _ in 0..8 {
+                s.spawn(|| {
+                    for i in 0..1_000 {
+                        let mut guard = pool.guard(|| Vec::<usize>::with_capacity(16));
+                        guard.clear();
+                        guard.push(i);
+                        if i % 10 == 0 {
+                            thread::yield_now();
+                        }
+                    }
+                });
+            }
+        });
+
+        // at most one object per thread was ever out at once.
+        let stats = pool.stats();
+        assert!(stats.n_created <= 8, "{stats:?}");
+        assert_eq!(stats.n_created + stats.n_reused, 8_000);
+        assert_eq!(stats.n_dropped, 0);
+        assert_eq!(pool.len() as u64, stats.n_created);
+        while let Some(obj) = pool.get() {
+            assert_eq!(obj.capacity(), 16);
+        }
+    }
+}
@@ -0,0 +1,149 @@
+//! Retry with exponential backoff, for I/O that fails transiently on network
+//! filesystems (Lustre, NFS) under heavy parallelism.
+
+use std::{
+    fmt,
+    hash::{BuildHasher, RandomState},
+    thread::sleep,
+    time::Duration,
+};
+
+const DEFAULT_MAX_ATTEMPTS: u32 = 3;
+const DEFAULT_BASE_DELAY: Duration = Duration::from_millis(100);
+
+/// How many times to try an operation and how long to wait in between.
+///
+/// The wait before retry `n` (1-based) is `base_delay * 2^(n - 1)`, with a random
+/// jitter taking it down to as low as half of that, so that threads failing
+/// together do not retry together.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// Total attempts, the first one included. 0 is treated as 1.
+    pub max_attempts: u32,
+    pub base_delay: Duration,
+}
+
+impl Default for RetryPolicy {
+    /// 3 attempts, 100ms base delay.
+    fn default() -> Self {
+        Self {
+            max_attempts: DEFAULT_MAX_ATTEMPTS,
+            base_delay: DEFAULT_BASE_DELAY,
+        }
+    }
+}
+
+impl RetryPolicy {
+    pub fn new(max_attempts: u32, base_delay: Duration) -> Self {
+        Self {
+            max_attempts,
+            base_delay,
+        }
+    }
+
+    /// Try once, never retry.
+    pub fn no_retry() -> Self {
+        Self::new(1, Duration::ZERO)
+    }
+
+    /// Wait before retry `retry` (1-based), jitter included.
+    pub fn backoff(&self, retry: u32) -> Duration {
+        let max = self
+            .base_delay
+            .saturating_mul(2_u32.saturating_pow(retry.saturating_sub(1)));
+        let half = max / 2;
+
+        // std has no rng; a fresh RandomState is randomly seeded, which is enough here.
+        let nanos = (max - half).as_nanos() as u64;
+        let jitter = match nanos {
+            0 => 0,
+            n => RandomState::new().hash_one(retry) % (n + 1),
+        };
+
+        half + Duration::from_nanos(jitter)
+    }
+
+    /// Run `op` until it succeeds or the attempts run out, returning the last error.
+    ///
+    /// Each retry is logged at WARN with `what`, the attempt count and the error.
+    pub fn run<T, E: fmt::Display>(
+        &self,
+        what: impl fmt::Display,
+        mut op: impl FnMut() -> Result<T, E>,
+    ) -> Result<T, E> {
+        let max_attempts = self.max_attempts.max(1);
+        let mut attempt = 1;
+        loop {
+            match op() {
+                Ok(v) => return Ok(v),
+                Err(err) if attempt < max_attempts => {
+                    #[cfg(feature = "tracing")]
+                    tracing::event!(
+                        tracing::Level::WARN,
+                        "{} failed (attempt {}/{}), retrying: {}",
+                        what,
+                        attempt,
+                        max_attempts,
+                        err
+                    );
+                    #[cfg(not(feature = "tracing"))]
+                    let _ = (&what, err);
+
+                    sleep(self.backoff(attempt));
+                    attempt += 1;
+                }
+                Err(err) => return Err(err),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_run_retries_until_success() {
+        let policy = RetryPolicy::new(3, Duration::from_millis(1));
+
+        let mut calls = 0;
+        let res = policy.run("op", || {
+            calls += 1;
+            if calls < 3 {
+                Err("transient")
+            } else {
+                Ok(calls)
+            }
+        });
+        assert_eq!(res, Ok(3));
+
+        let mut calls = 0;
+        let res: Result<(), _> = policy.run("op", || {
+            calls += 1;
+            Err(format!("failure {}", calls))
+        });
+        assert_eq!(res, Err("failure 3".to_string()));
+
+        let mut calls = 0;
+        let res: Result<(), _> = RetryPolicy::no_retry().run("op", || {
+            calls += 1;
+            Err("failure")
+        });
+        assert!(res.is_err());
+        assert_eq!(calls, 1);
+    }
+
+    #[test]
+    fn test_backoff_bounds() {
+        let policy = RetryPolicy::default();
+        for retry in 1..=4 {
+            let max = Duration::from_millis(100 * 2_u64.pow(retry - 1));
+            for _ in 0..20 {
+                let d = policy.backoff(retry);
+                assert!(max / 2 <= d && d <= max, "{:?} for retry {}", d, retry);
+            }
+        }
+
+        assert_eq!(RetryPolicy::no_retry().backoff(1), Duration::ZERO);
+    }
+}
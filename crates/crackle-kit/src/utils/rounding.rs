@@ -1,3 +1,5 @@
+use std::cmp::Ordering;
+
 /// Rounds like Python 3 `round(value, ndigits)` using banker's rounding
 /// (ties to nearest even).
 ///
@@ -46,12 +48,32 @@ pub(crate) fn py_round(value: f64, ndigits: i32) -> f64 {
         return value;
     }
 
-    let shifted = value / scale;
-    if !shifted.is_finite() {
-        return value;
+    // as Python, round the exact decimal digits of `value` rather than
+    // `value / scale`, which is off by some ulps for large values.
+    let n_zeros = -ndigits as usize;
+    let mut digits = format!("{:0>width$.0}", value.abs().trunc(), width = n_zeros + 1);
+    let rest = digits.split_off(digits.len() - n_zeros);
+    let half = format!("5{:0<width$}", "", width = n_zeros - 1);
+    let round_up = match rest.cmp(&half) {
+        Ordering::Greater => true,
+        Ordering::Less => false,
+        Ordering::Equal => value.fract() != 0.0 || digits.ends_with(['1', '3', '5', '7', '9']),
+    };
+    if round_up {
+        // add 1, carried through the trailing 9s.
+        let n_nines = digits.len() - digits.trim_end_matches('9').len();
+        digits.truncate(digits.len() - n_nines);
+        match digits.pop() {
+            Some(d) => digits.push((d as u8 + 1) as char),
+            None => digits.push('1'),
+        }
+        digits.push_str(&"0".repeat(n_nines));
     }
 
-    shifted.round_ties_even() * scale
+    let sign = if value.is_sign_negative() { "-" } else { "" };
+    format!("{}{}{:0<width$}", sign, digits, "", width = n_zeros)
+        .parse()
+        .unwrap_or(value)
 }
 
 /// Convenience wrapper for `py_round(value, 0)`.
@@ -294,6 +316,20 @@ for raw in sys.stdin:
         assert_eq!(py_round(f64::NEG_INFINITY, -3), f64::NEG_INFINITY);
     }
 
+    #[test]
+    fn large_values_to_negative_ndigits() {
+        let cases = [
+            (1.9100016147390446e21, -6),
+            (2.5e22, -22),
+            (-15.0, -1),
+            (-4.0, -1),
+            (999_999.5, -6),
+            (9_950.0, -2),
+        ];
+        assert_matches_python(&cases);
+        assert!(py_round(-4.0, -1).is_sign_negative());
+    }
+
     #[test]
     fn python_oracle_many_values_deterministic() {
         let cases = deterministic_cases();
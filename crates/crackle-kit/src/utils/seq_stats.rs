@@ -0,0 +1,310 @@
+//! GC content and complexity of read sequences, e.g. to filter low-complexity
+//! reads.
+//!
+//! Bases are matched case-insensitively. `N`, and any other byte than A, C, G
+//! and T, is not a called base: it is excluded from both the numerator and the
+//! denominator of GC fractions, breaks homopolymer runs without starting one,
+//! and drops the triplets containing it from [`dust_score`].
+
+use crate::data::bases::Base;
+
+#[cfg(feature = "fastq")]
+use crate::fastq::FastqRecord;
+
+/// Score under which [`LowComplexityFilter::new`] keeps a read.
+///
+/// Random 150 bp reads score about 1.2, a dinucleotide repeat about 37 and a
+/// 150 bp homopolymer 74.
+pub const DEFAULT_MAX_DUST_SCORE: f64 = 7.0;
+
+/// Fraction of G and C among the called bases of `seq`, NaN if it has none,
+/// e.g. for an empty or all-`N` sequence.
+pub fn gc_content(seq: &[u8]) -> f64 {
+    let (n_gc, n_called) = seq.iter().fold((0_usize, 0_usize), |(n_gc, n_called), b| {
+        match b.to_ascii_uppercase() {
+            b'G' | b'C' => (n_gc + 1, n_called + 1),
+            b'A' | b'T' => (n_gc, n_called + 1),
+            _ => (n_gc, n_called),
+        }
+    });
+
+    gc_fraction(n_gc, n_called)
+}
+
+/// [`gc_content`] of decoded bases, e.g. of [`BaseArr::iter`](crate::data::bases::BaseArr::iter).
+pub fn gc_content_bases(bases: impl IntoIterator<Item = Base>) -> f64 {
+    let (n_gc, n_called) = bases
+        .into_iter()
+        .fold((0_usize, 0_usize), |(n_gc, n_called), base| match base {
+            Base::G | Base::C => (n_gc + 1, n_called + 1),
+            Base::A | Base::T => (n_gc, n_called + 1),
+            Base::N => (n_gc, n_called),
+        });
+
+    gc_fraction(n_gc, n_called)
+}
+
+fn gc_fraction(n_gc: usize, n_called: usize) -> f64 {
+    if n_called == 0 {
+        f64::NAN
+    } else {
+        n_gc as f64 / n_called as f64
+    }
+}
+
+/// [`gc_content`] of the windows of `window` bases starting every `step` bases.
+///
+/// Only whole windows are reported, so a sequence shorter than `window` has none.
+///
+/// # Panics
+/// If `window` or `step` is 0.
+pub fn sliding_gc(seq: &[u8], window: usize, step: usize) -> Vec<f64> {
+    assert!(window > 0 && step > 0, "window and step must be positive");
+    if seq.len() < window {
+        return vec![];
+    }
+
+    // running counts, so each window costs O(1) whatever its size.
+    let mut prefix = Vec::with_capacity(seq.len() + 1);
+    prefix.push((0_usize, 0_usize));
+    for b in seq {
+        let &(n_gc, n_called) = prefix.last().unwrap();
+        prefix.push(match b.to_ascii_uppercase() {
+            b'G' | b'C' => (n_gc + 1, n_called + 1),
+            b'A' | b'T' => (n_gc, n_called + 1),
+            _ => (n_gc, n_called),
+        });
+    }
+
+    (0..=seq.len() - window)
+        .step_by(step)
+        .map(|start| {
+            let (gc0, called0) = prefix[start];
+            let (gc1, called1) = prefix[start + window];
+            gc_fraction(gc1 - gc0, called1 - called0)
+        })
+        .collect()
+}
+
+/// DUST score of `seq`, as in SDUST, over the whole sequence.
+///
+/// With `c_t` the count of triplet `t` and `l` the number of triplets, the score
+/// is `sum(c_t * (c_t - 1) / 2) / (l - 1)`: 0 when no triplet repeats, and high
+/// for repeats of short motifs. Sequences with less than 2 triplets score 0.
+/// Unlike SDUST, the score is not computed over windows, so it grows with the
+/// length of a repeat.
+pub fn dust_score(seq: &[u8]) -> f64 {
+    let mut counts = [0_u32; 64];
+    let mut n_triplets = 0_u32;
+
+    // 2-bit codes of the last bases, and how many of them are called in a row.
+    let (mut code, mut n_run) = (0_usize, 0);
+    for b in seq {
+        let base = match b.to_ascii_uppercase() {
+            b'A' => 0,
+            b'C' => 1,
+            b'G' => 2,
+            b'T' => 3,
+            _ => {
+                n_run = 0;
+                continue;
+            }
+        };
+
+        code = ((code << 2) | base) & 0b111111;
+        n_run += 1;
+        if n_run >= 3 {
+            counts[code] += 1;
+            n_triplets += 1;
+        }
+    }
+
+    if n_triplets < 2 {
+        return 0.0;
+    }
+
+    let sum = counts
+        .iter()
+        .map(|&c| (c as u64 * c.saturating_sub(1) as u64) / 2)
+        .sum::<u64>();
+    sum as f64 / (n_triplets - 1) as f64
+}
+
+/// Length of the longest run of one called base, 0 if `seq` has none.
+pub fn homopolymer_max_run(seq: &[u8]) -> usize {
+    let (mut max_run, mut run, mut prev) = (0, 0, 0_u8);
+    for b in seq {
+        let b = b.to_ascii_uppercase();
+        if !matches!(b, b'A' | b'C' | b'G' | b'T') {
+            (run, prev) = (0, 0);
+            continue;
+        }
+
+        run = if b == prev { run + 1 } else { 1 };
+        prev = b;
+        max_run = max_run.max(run);
+    }
+
+    max_run
+}
+
+/// Drop reads of low complexity or extreme GC content.
+///
+/// A read is kept if its [`dust_score`] is at most the max score,
+/// [`DEFAULT_MAX_DUST_SCORE`] by default, and it passes the optional limits on
+/// [`homopolymer_max_run`] and [`gc_content`]. Reads without called bases have no
+/// GC content and pass the GC limit; filter them on their `N`s if needed.
+///
+/// It is also a [`RecordModifier`](crate::bam::process::RecordModifier) for bams.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LowComplexityFilter {
+    max_dust_score: f64,
+    max_homopolymer_run: Option<usize>,
+    gc_range: Option<(f64, f64)>,
+}
+
+impl LowComplexityFilter {
+    pub fn new() -> Self {
+        Self {
+            max_dust_score: DEFAULT_MAX_DUST_SCORE,
+            max_homopolymer_run: None,
+            gc_range: None,
+        }
+    }
+
+    pub fn with_max_dust_score(mut self, max_dust_score: f64) -> Self {
+        self.max_dust_score = max_dust_score;
+        self
+    }
+
+    /// Drop reads with a homopolymer longer than `max_run`.
+    pub fn with_max_homopolymer_run(mut self, max_run: usize) -> Self {
+        self.max_homopolymer_run = Some(max_run);
+        self
+    }
+
+    /// Drop reads whose GC content is out of `min..=max`, fractions in 0..=1.
+    pub fn with_gc_range(mut self, min: f64, max: f64) -> Self {
+        self.gc_range = Some((min, max));
+        self
+    }
+
+    /// Whether a read of sequence `seq` is kept.
+    pub fn keeps(&self, seq: &[u8]) -> bool {
+        if dust_score(seq) > self.max_dust_score {
+            return false;
+        }
+
+        if let Some(max_run) = self.max_homopolymer_run
+            && homopolymer_max_run(seq) > max_run
+        {
+            return false;
+        }
+
+        if let Some((min, max)) = self.gc_range {
+            let gc = gc_content(seq);
+            if !gc.is_nan() && (gc < min || gc > max) {
+                return false;
+            }
+        }
+
+        true
+    }
+
+    /// [`Self::keeps`] on the sequence of a fastq record.
+    #[cfg(feature = "fastq")]
+    pub fn keeps_fastq(&self, record: &FastqRecord) -> bool {
+        self.keeps(record.sequence_bytes())
+    }
+}
+
+impl Default for LowComplexityFilter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::bases::BaseArr;
+
+    #[test]
+    fn test_gc_content() {
+        assert_eq!(gc_content(b"ACGT"), 0.5);
+        assert_eq!(gc_content(b"gcgA"), 0.75);
+        // N is neither GC nor a called base.
+        assert_eq!(gc_content(b"GNNNA"), 0.5);
+        assert!(gc_content(b"NNNN").is_nan());
+        assert!(gc_content(b"").is_nan());
+
+        let arr = BaseArr::<u64>::from_bytes(b"GGCNAT").unwrap();
+        assert_eq!(gc_content_bases(arr.iter()), 0.6);
+        assert!(gc_content_bases([Base::N]).is_nan());
+    }
+
+    #[test]
+    fn test_sliding_gc() {
+        assert_eq!(sliding_gc(b"GGAANNCC", 4, 2), vec![0.5, 0.0, 1.0]);
+        // the last base is not a whole window.
+        assert_eq!(sliding_gc(b"GCATG", 2, 2), vec![1.0, 0.0]);
+        assert!(sliding_gc(b"NNNN", 2, 2).iter().all(|gc| gc.is_nan()));
+        assert!(sliding_gc(b"GC", 3, 1).is_empty());
+        assert!(sliding_gc(b"", 1, 1).is_empty());
+    }
+
+    #[test]
+    fn test_dust_score() {
+        // AAA 4 times: 4 * 3 / 2 / (4 - 1).
+        assert_eq!(dust_score(b"AAAAAA"), 2.0);
+        assert_eq!(dust_score(b"ACGTAC"), 0.0);
+        // ACA and CAC 3 times each out of 6 triplets.
+        assert_eq!(dust_score(b"acacacac"), 6.0 / 5.0);
+        // the triplets across N are skipped: AAA twice on each side.
+        assert_eq!(dust_score(b"AAAANAAAA"), 6.0 / 3.0);
+        assert_eq!(dust_score(b"AAA"), 0.0);
+        assert_eq!(dust_score(b"NNNNNN"), 0.0);
+        assert_eq!(dust_score(b""), 0.0);
+    }
+
+    #[test]
+    fn test_homopolymer_max_run() {
+        assert_eq!(homopolymer_max_run(b"ACGGGTTa"), 3);
+        assert_eq!(homopolymer_max_run(b"AAaA"), 4);
+        assert_eq!(homopolymer_max_run(b"AANAA"), 2);
+        assert_eq!(homopolymer_max_run(b"NNNN"), 0);
+        assert_eq!(homopolymer_max_run(b""), 0);
+    }
+
+    #[test]
+    fn test_low_complexity_filter() {
+        let random = b"ACGTTGCAAGCTTCGAGGATCCATGCAGTCAAGTCCTAGGTACTGATCGTA";
+        let poly_a = [b'A'; 50];
+
+        let filter = LowComplexityFilter::new();
+        assert!(filter.keeps(random));
+        assert!(!filter.keeps(&poly_a));
+        assert!(filter.keeps(b""));
+        assert!(filter.keeps(b"NNNNNNNN"));
+
+        #[cfg(feature = "fastq")]
+        {
+            let mut record = FastqRecord::new();
+            record
+                .load_record(&b"@read1\nAAAAAAAAAAAAAAAAAAAA\n+\nIIIIIIIIIIIIIIIIIIII\n"[..])
+                .unwrap();
+            assert!(!filter.keeps_fastq(&record));
+        }
+
+        let filter = LowComplexityFilter::new()
+            .with_max_dust_score(f64::INFINITY)
+            .with_max_homopolymer_run(2);
+        assert!(filter.keeps(b"AACCGGTT"));
+        assert!(!filter.keeps(b"AACCCGGTT"));
+
+        let filter = LowComplexityFilter::new().with_gc_range(0.4, 0.6);
+        assert!(filter.keeps(random));
+        assert!(!filter.keeps(b"GCGCGCAT"));
+        assert!(filter.keeps(b"NNNN"));
+    }
+}
@@ -0,0 +1,87 @@
+//! Hashes which are the same across runs, Rust versions and platforms, unlike
+//! the hashers of std, for values written to disk or drawn from a seed.
+
+const FNV_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0100_0000_01b3;
+
+/// 64-bit FNV-1a.
+///
+/// # Examples
+///
+/// ```
+/// use crackle_kit::utils::stable_hash::Fnv1a;
+///
+/// let mut hasher = Fnv1a::new();
+/// hasher.write(b"foo");
+/// hasher.write(b"bar");
+/// assert_eq!(hasher.finish(), 0x8594_4171_f739_67e8);
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Fnv1a(u64);
+
+impl Fnv1a {
+    pub const fn new() -> Self {
+        Self(FNV_OFFSET_BASIS)
+    }
+
+    /// Start from the offset basis mixed with `seed`, for a family of hashes;
+    /// seed 0 is [`Fnv1a::new`].
+    pub const fn with_seed(seed: u64) -> Self {
+        Self(FNV_OFFSET_BASIS ^ seed.wrapping_mul(0x9e37_79b9_7f4a_7c15))
+    }
+
+    pub fn write(&mut self, bytes: &[u8]) {
+        for b in bytes {
+            self.0 ^= u64::from(*b);
+            self.0 = self.0.wrapping_mul(FNV_PRIME);
+        }
+    }
+
+    pub const fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+impl Default for Fnv1a {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The finalizer of MurmurHash3, spreading the bits of `h`, as FNV-1a leaves
+/// the high bits of short inputs poorly mixed.
+pub const fn fmix64(mut h: u64) -> u64 {
+    h ^= h >> 33;
+    h = h.wrapping_mul(0xff51_afd7_ed55_8ccd);
+    h ^= h >> 33;
+    h = h.wrapping_mul(0xc4ce_b9fe_1a85_ec53);
+    h ^ (h >> 33)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fnv1a(bytes: &[u8]) -> u64 {
+        let mut hasher = Fnv1a::new();
+        hasher.write(bytes);
+        hasher.finish()
+    }
+
+    #[test]
+    fn test_fnv1a() {
+        // the test vectors of the FNV reference.
+        assert_eq!(fnv1a(b""), 0xcbf2_9ce4_8422_2325);
+        assert_eq!(fnv1a(b"a"), 0xaf63_dc4c_8601_ec8c);
+        assert_eq!(fnv1a(b"foobar"), 0x8594_4171_f739_67e8);
+
+        assert_eq!(Fnv1a::with_seed(0), Fnv1a::new());
+        assert_ne!(Fnv1a::with_seed(1), Fnv1a::new());
+    }
+
+    #[test]
+    fn test_fmix64() {
+        assert_eq!(fmix64(0), 0);
+        assert_ne!(fmix64(1), fmix64(2));
+    }
+}
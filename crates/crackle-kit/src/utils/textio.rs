@@ -0,0 +1,350 @@
+//! Reading and writing of text files, e.g. BED, TSV or key files, plain or
+//! gzipped.
+//!
+//! Files are read as gzip by their magic bytes, whatever their name, so a plain
+//! file named `.gz` is read as plain text. Files are written gzipped if named
+//! `.gz`. Gzip needs the `gzip` feature.
+
+use std::{
+    fs::File,
+    io::{self, BufRead, BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+use anyhow::anyhow;
+
+use crate::errors::Error;
+#[cfg(feature = "gzip")]
+use flate2::{Compression, bufread::MultiGzDecoder, write::GzEncoder};
+
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+/// Compression of a file, see [`detect_compression`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompressionKind {
+    Plain,
+    Gzip,
+    /// Blocked gzip, as of BAM or tabix-indexed files; read as [`Self::Gzip`].
+    Bgzf,
+}
+
+/// Compression of the data of `reader` by its first bytes, which are not
+/// consumed.
+pub fn detect_compression(reader: &mut impl BufRead) -> io::Result<CompressionKind> {
+    let buf = reader.fill_buf()?;
+    if !buf.starts_with(&GZIP_MAGIC) {
+        return Ok(CompressionKind::Plain);
+    }
+
+    // FEXTRA flag, then a first extra subfield of id "BC".
+    let is_bgzf = buf.len() >= 14 && buf[3] & 0x04 != 0 && &buf[12..14] == b"BC";
+    Ok(if is_bgzf {
+        CompressionKind::Bgzf
+    } else {
+        CompressionKind::Gzip
+    })
+}
+
+/// Open `path` for reading lines, decompressed if gzipped.
+pub fn open_text(path: impl AsRef<Path>) -> Result<Box<dyn BufRead + Send>, Error> {
+    let path = path.as_ref();
+    let mut file = BufReader::new(File::open(path).map_err(|e| Error::io("open", path, e))?);
+
+    match detect_compression(&mut file).map_err(|e| Error::io("read", path, e))? {
+        CompressionKind::Plain => Ok(Box::new(file)),
+        #[cfg(feature = "gzip")]
+        CompressionKind::Gzip | CompressionKind::Bgzf => {
+            Ok(Box::new(BufReader::new(MultiGzDecoder::new(file))))
+        }
+        #[cfg(not(feature = "gzip"))]
+        CompressionKind::Gzip | CompressionKind::Bgzf => Err(anyhow!(
+            "Reading gzipped {} needs the `gzip` feature",
+            path.display()
+        )
+        .into()),
+    }
+}
+
+/// Create `path` for writing, gzipped at `level` (0-9) if named `.gz`.
+///
+/// The gzip stream is finished when the writer is dropped, ignoring errors;
+/// flush it first to get them.
+pub fn create_text(path: impl AsRef<Path>, level: u32) -> Result<Box<dyn Write + Send>, Error> {
+    let path = path.as_ref();
+    if level > 9 {
+        return Err(anyhow!("Invalid gzip level {} for {}", level, path.display()).into());
+    }
+    let file = BufWriter::new(File::create(path).map_err(|e| Error::io("create", path, e))?);
+
+    if path.extension().is_some_and(|ext| ext == "gz") {
+        #[cfg(feature = "gzip")]
+        {
+            Ok(Box::new(GzEncoder::new(file, Compression::new(level))))
+        }
+        #[cfg(not(feature = "gzip"))]
+        Err(anyhow!(
+            "Writing gzipped {} needs the `gzip` feature",
+            path.display()
+        )
+        .into())
+    } else {
+        Ok(Box::new(file))
+    }
+}
+
+/// Lines of a text file, counted for error messages.
+pub struct LineReader {
+    reader: Box<dyn BufRead + Send>,
+    path: PathBuf,
+    line: String,
+    line_no: usize,
+}
+
+impl LineReader {
+    /// Lines of `path`, opened by [`open_text`].
+    pub fn from_path(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let path = path.as_ref();
+        Ok(Self::new(open_text(path)?, path))
+    }
+
+    /// Lines of `reader`, named `path` in errors.
+    pub fn new(reader: Box<dyn BufRead + Send>, path: impl Into<PathBuf>) -> Self {
+        Self {
+            reader,
+            path: path.into(),
+            line: String::new(),
+            line_no: 0,
+        }
+    }
+
+    /// The next line without its line ending, or `None` at the end of the file.
+    ///
+    /// A line which is not UTF-8, or corrupt gzipped data, is an
+    /// [`Error::Parse`] of the line.
+    pub fn next_line(&mut self) -> Result<Option<&str>, Error> {
+        self.line.clear();
+        let n = match self.reader.read_line(&mut self.line) {
+            Ok(n) => n,
+            Err(err) => {
+                return Err(match err.kind() {
+                    io::ErrorKind::InvalidData | io::ErrorKind::UnexpectedEof => Error::Parse {
+                        what: "text".to_string(),
+                        path: self.path.clone(),
+                        line: self.line_no + 1,
+                        source: err.into(),
+                    },
+                    _ => Error::io("read", &self.path, err),
+                });
+            }
+        };
+        if n == 0 {
+            return Ok(None);
+        }
+        self.line_no += 1;
+
+        let line = self.line.strip_suffix('\n').unwrap_or(&self.line);
+        Ok(Some(line.strip_suffix('\r').unwrap_or(line)))
+    }
+
+    /// 1-based number of the last line read, 0 before the first.
+    pub fn line_no(&self) -> usize {
+        self.line_no
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The last line read, e.g. `line 3 of regions.bed`, for error messages.
+    pub fn location(&self) -> String {
+        format!("line {} of {}", self.line_no, self.path.display())
+    }
+
+    /// An [`Error::Parse`] of `what`, e.g. `BED record`, at the last line read.
+    pub fn parse_error(&self, what: impl Into<String>, source: impl Into<anyhow::Error>) -> Error {
+        Error::Parse {
+            what: what.into(),
+            path: self.path.clone(),
+            line: self.line_no,
+            source: source.into(),
+        }
+    }
+}
+
+/// Lines of `path` with their 1-based numbers, skipping blank lines and lines
+/// starting with any of `comment_prefixes`, e.g. `#` or `track`.
+pub fn read_non_comment_lines(
+    path: impl AsRef<Path>,
+    comment_prefixes: &[&str],
+) -> Result<NonCommentLines, Error> {
+    Ok(NonCommentLines {
+        lines: LineReader::from_path(path)?,
+        comment_prefixes: comment_prefixes.iter().map(|p| p.to_string()).collect(),
+    })
+}
+
+/// Iterator of [`read_non_comment_lines`].
+pub struct NonCommentLines {
+    lines: LineReader,
+    comment_prefixes: Vec<String>,
+}
+
+impl NonCommentLines {
+    pub fn line_reader(&self) -> &LineReader {
+        &self.lines
+    }
+}
+
+impl Iterator for NonCommentLines {
+    type Item = Result<(usize, String), Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let line = match self.lines.next_line() {
+                Ok(Some(line)) => line,
+                Ok(None) => return None,
+                Err(err) => return Some(Err(err)),
+            };
+
+            if line.trim().is_empty()
+                || self
+                    .comment_prefixes
+                    .iter()
+                    .any(|prefix| line.starts_with(prefix.as_str()))
+            {
+                continue;
+            }
+
+            let line = line.to_string();
+            return Some(Ok((self.lines.line_no(), line)));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use anyhow::Context;
+
+    use super::*;
+
+    #[test]
+    #[cfg(feature = "gzip")]
+    fn test_open_text_detects_gzip() -> Result<(), anyhow::Error> {
+        let dir = tempfile::tempdir()?;
+        let text = "chr1\t100\t200\nchr2\t5\t10\n";
+
+        // gzipped, named without .gz.
+        let path = dir.path().join("regions.bed");
+        let mut gz = GzEncoder::new(File::create(&path)?, Compression::default());
+        gz.write_all(text.as_bytes())?;
+        gz.finish()?;
+        let mut reader = BufReader::new(File::open(&path)?);
+        assert_eq!(detect_compression(&mut reader)?, CompressionKind::Gzip);
+        // nothing consumed.
+        assert_eq!(reader.fill_buf()?[..2], GZIP_MAGIC);
+        let mut read = String::new();
+        open_text(&path)?.read_to_string(&mut read)?;
+        assert_eq!(read, text);
+
+        // a bgzf header, which is gzip with a BC extra field.
+        let path = dir.path().join("regions.bed.bgz");
+        let mut gz = flate2::GzBuilder::new()
+            .extra(b"BC\x02\x00\x00\x00".to_vec())
+            .write(File::create(&path)?, Compression::default());
+        gz.write_all(text.as_bytes())?;
+        gz.finish()?;
+        let mut reader = BufReader::new(File::open(&path)?);
+        assert_eq!(detect_compression(&mut reader)?, CompressionKind::Bgzf);
+        let mut read = String::new();
+        open_text(&path)?.read_to_string(&mut read)?;
+        assert_eq!(read, text);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_plain_text_named_gz() -> Result<(), anyhow::Error> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("keys.txt.gz");
+        std::fs::write(&path, "chr1_100_A_G\n")?;
+
+        // read as plain text, by its content.
+        let mut read = String::new();
+        open_text(&path)?.read_to_string(&mut read)?;
+        assert_eq!(read, "chr1_100_A_G\n");
+
+        let mut reader = BufReader::new(File::open(&path)?);
+        assert_eq!(detect_compression(&mut reader)?, CompressionKind::Plain);
+        let mut empty = BufReader::new(&b""[..]);
+        assert_eq!(detect_compression(&mut empty)?, CompressionKind::Plain);
+
+        assert!(open_text(dir.path().join("missing.txt")).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    #[cfg(feature = "gzip")]
+    fn test_create_text() -> Result<(), anyhow::Error> {
+        let dir = tempfile::tempdir()?;
+        for (name, gzipped) in [("out.tsv", false), ("out.tsv.gz", true)] {
+            let path = dir.path().join(name);
+            {
+                let mut writer = create_text(&path, 6)?;
+                writeln!(writer, "a\tb")?;
+                writer.flush()?;
+            }
+            assert_eq!(std::fs::read(&path)?.starts_with(&GZIP_MAGIC), gzipped);
+
+            let mut read = String::new();
+            open_text(&path)?.read_to_string(&mut read)?;
+            assert_eq!(read, "a\tb\n");
+        }
+
+        assert!(create_text(dir.path().join("out.gz"), 10).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_line_numbers() -> Result<(), anyhow::Error> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("regions.bed");
+        std::fs::write(
+            &path,
+            "# header\ntrack name=x\nchr1\t1\t2\r\n\nchr1\tx\t5\n#chr2\t1\t2\nchr2\t3\t4",
+        )?;
+
+        let mut lines = read_non_comment_lines(&path, &["#", "track"])?;
+        assert_eq!(lines.next().unwrap()?, (3, "chr1\t1\t2".to_string()));
+        let (line_no, line) = lines.next().unwrap()?;
+        assert_eq!((line_no, line.as_str()), (5, "chr1\tx\t5"));
+
+        let err = line
+            .split('\t')
+            .nth(1)
+            .unwrap()
+            .parse::<i64>()
+            .with_context(|| format!("Invalid start at {}", lines.line_reader().location()))
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            format!("Invalid start at line 5 of {}", path.display())
+        );
+
+        assert_eq!(lines.next().unwrap()?, (7, "chr2\t3\t4".to_string()));
+        assert!(lines.next().is_none());
+
+        // invalid UTF-8 names the line it is on.
+        std::fs::write(&path, b"chr1\t1\t2\nchr1\t\xff\t2\n")?;
+        let mut reader = LineReader::from_path(&path)?;
+        assert_eq!(reader.next_line()?, Some("chr1\t1\t2"));
+        let err = reader.next_line().unwrap_err();
+        assert!(err.to_string().contains("line 2 of"), "{:#}", err);
+        assert_eq!(reader.line_no(), 1);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,213 @@
+//! A number of cores shared by processors running at once, so that together
+//! they do not start more threads than the node has.
+//!
+//! ```
+//! use crackle_kit::utils::thread_budget::ThreadBudget;
+//!
+//! let budget = ThreadBudget::new(4);
+//! {
+//!     let guard = budget.reserve(3)?;
+//!     assert_eq!((guard.n_threads(), budget.available()), (3, 1));
+//! }
+//! assert_eq!(budget.available(), 4);
+//! # Ok::<(), anyhow::Error>(())
+//! ```
+
+use std::{
+    sync::{Arc, Condvar, Mutex, OnceLock},
+    thread,
+};
+
+use crate::errors::ThreadBudgetError;
+
+/// What [`ThreadBudget::reserve`] does when not enough threads are available.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ExhaustedPolicy {
+    /// Wait for other guards to be dropped.
+    #[default]
+    Block,
+    /// Fail with [`ThreadBudgetError::Exhausted`].
+    Error,
+}
+
+/// Cores handed out to processors by [`Self::reserve`], and given back when the
+/// [`BudgetGuard`] is dropped.
+///
+/// Clones share the same budget.
+#[derive(Debug, Clone)]
+pub struct ThreadBudget {
+    inner: Arc<BudgetInner>,
+    policy: ExhaustedPolicy,
+}
+
+#[derive(Debug)]
+struct BudgetInner {
+    total: usize,
+    available: Mutex<usize>,
+    released: Condvar,
+}
+
+impl ThreadBudget {
+    /// A budget of `total` threads, blocking when exhausted.
+    pub fn new(total: usize) -> Self {
+        Self {
+            inner: Arc::new(BudgetInner {
+                total,
+                available: Mutex::new(total),
+                released: Condvar::new(),
+            }),
+            policy: ExhaustedPolicy::default(),
+        }
+    }
+
+    /// The same budget, reserved from with `policy`.
+    pub fn with_policy(mut self, policy: ExhaustedPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    pub fn policy(&self) -> ExhaustedPolicy {
+        self.policy
+    }
+
+    pub fn total(&self) -> usize {
+        self.inner.total
+    }
+
+    /// Threads not reserved at the moment.
+    pub fn available(&self) -> usize {
+        *self.inner.available.lock().unwrap()
+    }
+
+    /// Reserve `n_threads` threads until the guard is dropped, waiting for them
+    /// or failing as set by the [`ExhaustedPolicy`].
+    ///
+    /// Fails whatever the policy if `n_threads` is over the total.
+    pub fn reserve(&self, n_threads: usize) -> Result<BudgetGuard, ThreadBudgetError> {
+        let total = self.inner.total;
+        if n_threads > total {
+            return Err(ThreadBudgetError::OverTotal {
+                requested: n_threads,
+                total,
+            });
+        }
+
+        let mut available = self.inner.available.lock().unwrap();
+        while *available < n_threads {
+            match self.policy {
+                ExhaustedPolicy::Block => {
+                    available = self.inner.released.wait(available).unwrap();
+                }
+                ExhaustedPolicy::Error => {
+                    return Err(ThreadBudgetError::Exhausted {
+                        requested: n_threads,
+                        available: *available,
+                        total,
+                    });
+                }
+            }
+        }
+        *available -= n_threads;
+
+        Ok(BudgetGuard {
+            inner: Arc::clone(&self.inner),
+            n_threads,
+        })
+    }
+}
+
+/// The budget of all processors not given another, of the available
+/// parallelism of the node.
+pub fn default_budget() -> &'static ThreadBudget {
+    static DEFAULT: OnceLock<ThreadBudget> = OnceLock::new();
+    DEFAULT
+        .get_or_init(|| ThreadBudget::new(thread::available_parallelism().map_or(1, |n| n.get())))
+}
+
+/// Threads reserved of a [`ThreadBudget`], given back when dropped.
+#[derive(Debug)]
+pub struct BudgetGuard {
+    inner: Arc<BudgetInner>,
+    n_threads: usize,
+}
+
+impl BudgetGuard {
+    pub fn n_threads(&self) -> usize {
+        self.n_threads
+    }
+}
+
+impl Drop for BudgetGuard {
+    fn drop(&mut self) {
+        *self.inner.available.lock().unwrap() += self.n_threads;
+        self.inner.released.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::atomic::{AtomicUsize, Ordering},
+        time::Duration,
+    };
+
+    use super::*;
+
+    #[test]
+    fn test_reserve_and_release() {
+        let budget = ThreadBudget::new(4).with_policy(ExhaustedPolicy::Error);
+        let a = budget.reserve(3).unwrap();
+        assert_eq!((a.n_threads(), budget.available()), (3, 1));
+
+        assert_eq!(
+            budget.reserve(2).unwrap_err(),
+            ThreadBudgetError::Exhausted {
+                requested: 2,
+                available: 1,
+                total: 4,
+            }
+        );
+        assert_eq!(
+            budget.reserve(5).unwrap_err(),
+            ThreadBudgetError::OverTotal {
+                requested: 5,
+                total: 4,
+            }
+        );
+
+        // clones share the budget.
+        let b = budget.clone().reserve(1).unwrap();
+        assert_eq!(budget.available(), 0);
+        drop(a);
+        assert_eq!(budget.available(), 3);
+        drop(b);
+        assert_eq!(budget.available(), 4);
+
+        assert!(default_budget().total() >= 1);
+    }
+
+    #[test]
+    fn test_blocking_reserve() {
+        let budget = ThreadBudget::new(4);
+        let (in_use, max_in_use) = (AtomicUsize::new(0), AtomicUsize::new(0));
+
+        // two processors of 3 threads each: one waits for the other.
+        thread::scope(|s| {
+            for _ in 0..2 {
+                s.spawn(|| {
+                    for _ in 0..5 {
+                        let guard = budget.reserve(3).unwrap();
+                        let n = in_use.fetch_add(guard.n_threads(), Ordering::SeqCst)
+                            + guard.n_threads();
+                        max_in_use.fetch_max(n, Ordering::SeqCst);
+                        thread::sleep(Duration::from_millis(5));
+                        in_use.fetch_sub(guard.n_threads(), Ordering::SeqCst);
+                    }
+                });
+            }
+        });
+
+        assert_eq!(max_in_use.load(Ordering::SeqCst), 3);
+        assert_eq!(budget.available(), 4);
+    }
+}
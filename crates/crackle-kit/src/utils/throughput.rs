@@ -0,0 +1,320 @@
+//! Throughput lines in the logs, for runs without a progress bar to watch.
+//!
+//! A [`ThroughputLogger`] is fed counts from any thread, and logs at most one
+//! line per interval however often it is fed, so that it can be fed per batch.
+//!
+//! ```
+//! use std::time::Duration;
+//!
+//! use crackle_kit::utils::throughput::ThroughputLogger;
+//! use tracing::Level;
+//!
+//! let logger = ThroughputLogger::new("records", Level::INFO)
+//!     .with_interval(Duration::from_secs(10));
+//! logger.add(1024);
+//! logger.add(1024);
+//! assert_eq!(logger.total(), 2048);
+//! let report = logger.finish();
+//! assert_eq!(report.total, 2048);
+//! ```
+
+use std::{
+    sync::atomic::{AtomicU64, Ordering},
+    time::{Duration, Instant},
+};
+
+use tracing::{Level, event};
+
+#[cfg(feature = "metrics")]
+use crate::utils::metrics::Counter;
+
+/// Default of [`ThroughputLogger::with_interval`].
+pub const DEFAULT_THROUGHPUT_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Time source of a [`ThroughputLogger`], the time since an origin of its own.
+pub trait Clock: Send + Sync {
+    fn elapsed(&self) -> Duration;
+}
+
+/// [`Clock`] of the time since it was made.
+#[derive(Debug, Clone, Copy)]
+pub struct MonotonicClock(Instant);
+
+impl Default for MonotonicClock {
+    fn default() -> Self {
+        Self(Instant::now())
+    }
+}
+
+impl Clock for MonotonicClock {
+    fn elapsed(&self) -> Duration {
+        self.0.elapsed()
+    }
+}
+
+/// A line logged by [`ThroughputLogger`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ThroughputReport {
+    pub total: u64,
+    /// Time since the logger was made.
+    pub elapsed: Duration,
+    /// Items per second since the last report.
+    pub rate: f64,
+    /// Items per second since the logger was made.
+    pub average_rate: f64,
+}
+
+/// Counts of items processed, logged as a [`ThroughputReport`] at most once per
+/// interval, 30 s by default.
+///
+/// Counts are atomics, and the clock is read once per [`Self::add`], so it is
+/// cheap enough to be fed per batch by each thread of a pipeline.
+pub struct ThroughputLogger {
+    name: &'static str,
+    level: Level,
+    interval: Duration,
+    clock: Box<dyn Clock>,
+    /// Time of the clock when the logger was made, in nanoseconds.
+    start_nanos: u64,
+    total: AtomicU64,
+    /// Time of the clock at the last report, in nanoseconds.
+    last_nanos: AtomicU64,
+    /// `total` at the last report.
+    last_total: AtomicU64,
+    #[cfg(feature = "metrics")]
+    counter: Option<Counter>,
+}
+
+impl ThroughputLogger {
+    /// A logger of the items of `name`, logged at `level`.
+    pub fn new(name: &'static str, level: Level) -> Self {
+        Self {
+            name,
+            level,
+            interval: DEFAULT_THROUGHPUT_INTERVAL,
+            clock: Box::new(MonotonicClock::default()),
+            start_nanos: 0,
+            total: AtomicU64::new(0),
+            last_nanos: AtomicU64::new(0),
+            last_total: AtomicU64::new(0),
+            #[cfg(feature = "metrics")]
+            counter: None,
+        }
+    }
+
+    /// Log at most one report per `interval`.
+    pub fn with_interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    /// Read the time from `clock`, from now on.
+    pub fn with_clock(mut self, clock: impl Clock + 'static) -> Self {
+        self.clock = Box::new(clock);
+        self.start_nanos = self.now_nanos();
+        self.last_nanos = AtomicU64::new(self.start_nanos);
+        self
+    }
+
+    /// Count the items into `counter` too, e.g. of the
+    /// [`global`](crate::utils::metrics::global) metrics registry.
+    #[cfg(feature = "metrics")]
+    pub fn with_counter(mut self, counter: Counter) -> Self {
+        self.counter = Some(counter);
+        self
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    /// Items counted so far.
+    pub fn total(&self) -> u64 {
+        self.total.load(Ordering::Relaxed)
+    }
+
+    /// Count `n` more items, and log a report if the interval has passed since
+    /// the last one. Of the threads adding at once, only one logs it.
+    ///
+    /// Returns the report logged, if any.
+    pub fn add(&self, n: u64) -> Option<ThroughputReport> {
+        let total = self.total.fetch_add(n, Ordering::Relaxed) + n;
+        #[cfg(feature = "metrics")]
+        if let Some(counter) = &self.counter {
+            counter.add(n);
+        }
+        let now = self.now_nanos();
+        let last = self.last_nanos.load(Ordering::Relaxed);
+        if now.saturating_sub(last) < self.interval.as_nanos() as u64 {
+            return None;
+        }
+        self.last_nanos
+            .compare_exchange(last, now, Ordering::Relaxed, Ordering::Relaxed)
+            .ok()?;
+
+        Some(self.report(last, now, total))
+    }
+
+    /// Log a last report, of the items since the last one.
+    pub fn finish(&self) -> ThroughputReport {
+        let now = self.now_nanos();
+        let last = self.last_nanos.swap(now, Ordering::Relaxed);
+        self.report(last, now, self.total())
+    }
+
+    fn now_nanos(&self) -> u64 {
+        self.clock.elapsed().as_nanos() as u64
+    }
+
+    fn report(&self, last_nanos: u64, now_nanos: u64, total: u64) -> ThroughputReport {
+        let last_total = self.last_total.swap(total, Ordering::Relaxed);
+        let report = ThroughputReport {
+            total,
+            elapsed: Duration::from_nanos(now_nanos.saturating_sub(self.start_nanos)),
+            rate: per_second(
+                total.saturating_sub(last_total),
+                now_nanos.saturating_sub(last_nanos),
+            ),
+            average_rate: per_second(total, now_nanos.saturating_sub(self.start_nanos)),
+        };
+        self.log(&report);
+        report
+    }
+
+    fn log(&self, report: &ThroughputReport) {
+        macro_rules! log_at {
+            ($level:expr) => {
+                event!(
+                    $level,
+                    name = self.name,
+                    total = report.total,
+                    rate = report.rate,
+                    average_rate = report.average_rate,
+                    "{}: {} in {:.0?}, {:.1}/s, {:.1}/s on average",
+                    self.name,
+                    report.total,
+                    report.elapsed,
+                    report.rate,
+                    report.average_rate
+                )
+            };
+        }
+        match self.level {
+            Level::ERROR => log_at!(Level::ERROR),
+            Level::WARN => log_at!(Level::WARN),
+            Level::INFO => log_at!(Level::INFO),
+            Level::DEBUG => log_at!(Level::DEBUG),
+            _ => log_at!(Level::TRACE),
+        }
+    }
+}
+
+/// Items per second, 0 over no time.
+fn per_second(n: u64, nanos: u64) -> f64 {
+    match nanos {
+        0 => 0.0,
+        _ => n as f64 / (nanos as f64 / 1e9),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Arc, thread};
+
+    use super::*;
+
+    /// Time set by the test.
+    #[derive(Clone, Default)]
+    struct FakeClock(Arc<AtomicU64>);
+
+    impl FakeClock {
+        fn set_secs(&self, secs: u64) {
+            self.0.store(secs * 1_000_000_000, Ordering::Relaxed);
+        }
+    }
+
+    impl Clock for FakeClock {
+        fn elapsed(&self) -> Duration {
+            Duration::from_nanos(self.0.load(Ordering::Relaxed))
+        }
+    }
+
+    #[test]
+    fn test_cadence_and_rates() {
+        let clock = FakeClock::default();
+        clock.set_secs(100);
+        let logger = ThroughputLogger::new("records", Level::INFO).with_clock(clock.clone());
+
+        clock.set_secs(110);
+        assert_eq!(logger.add(1_000), None);
+        clock.set_secs(129);
+        assert_eq!(logger.add(2_000), None);
+        clock.set_secs(130);
+        assert_eq!(
+            logger.add(3_000),
+            Some(ThroughputReport {
+                total: 6_000,
+                elapsed: Duration::from_secs(30),
+                rate: 200.0,
+                average_rate: 200.0,
+            })
+        );
+
+        // the next interval starts at the last report.
+        clock.set_secs(159);
+        assert_eq!(logger.add(1_000), None);
+        clock.set_secs(190);
+        assert_eq!(
+            logger.add(1_000),
+            Some(ThroughputReport {
+                total: 8_000,
+                elapsed: Duration::from_secs(90),
+                rate: 2_000.0 / 60.0,
+                average_rate: 8_000.0 / 90.0,
+            })
+        );
+
+        clock.set_secs(200);
+        let report = logger.finish();
+        assert_eq!((report.total, report.rate), (8_000, 0.0));
+    }
+
+    #[test]
+    fn test_one_report_per_interval_across_threads() {
+        let clock = FakeClock::default();
+        let logger = ThroughputLogger::new("records", Level::DEBUG)
+            .with_interval(Duration::from_secs(10))
+            .with_clock(clock.clone());
+
+        let n_reports = AtomicU64::new(0);
+        for secs in [5, 10, 15, 20] {
+            clock.set_secs(secs);
+            thread::scope(|s| {
+                for _ in 0..4 {
+                    s.spawn(|| {
+                        for _ in 0..100 {
+                            if logger.add(1).is_some() {
+                                n_reports.fetch_add(1, Ordering::Relaxed);
+                            }
+                        }
+                    });
+                }
+            });
+        }
+
+        // at 10 and 20 s only.
+        assert_eq!(n_reports.load(Ordering::Relaxed), 2);
+        assert_eq!(logger.total(), 1_600);
+    }
+
+    #[cfg(feature = "metrics")]
+    #[test]
+    fn test_counter() {
+        let registry = crate::utils::metrics::MetricsRegistry::new();
+        let logger = ThroughputLogger::new("records", Level::DEBUG)
+            .with_counter(registry.counter("records_total", &[]).unwrap());
+        logger.add(3);
+        logger.add(4);
+        assert_eq!(registry.counter("records_total", &[]).unwrap().get(), 7);
+    }
+}
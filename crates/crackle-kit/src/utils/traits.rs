@@ -191,7 +191,7 @@ mod tests {
     fn test_empty_slice() {
         let data: [i32; 0] = [];
         // 0..0 is valid for empty slice
-        assert_eq!(data.get_with_int(0..0i32).unwrap(), &[]);
+        assert_eq!(data.get_with_int(0..0i32).unwrap(), &[] as &[i32]);
 
         // 0..1 is OOB
         assert!(data.get_with_int(0..1i32).is_err());
@@ -208,7 +208,7 @@ mod tests {
         // Wait, -0 is just 0. So end=0.
         // 0..0 returns empty slice.
         // Note: In Python list[0:-0] is empty. Correct.
-        assert_eq!(data.get_with_int(0..0i32).unwrap(), &[]);
+        assert_eq!(data.get_with_int(0..0i32).unwrap(), &[] as &[i32]);
     }
 
     #[test]
@@ -0,0 +1,118 @@
+//! Where the UMI of a read is found, shared by fastq records and bam records so
+//! both extract the same bytes.
+//!
+//! The read id is the name of the read without the fastq `@` and comment, i.e.
+//! the bam qname.
+
+use std::ops::RangeInclusive;
+
+/// Lengths accepted by [`validate_umi`].
+pub const UMI_LEN_RANGE: RangeInclusive<usize> = 4..=32;
+
+/// Where the UMI of a read is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UmiScheme {
+    /// Last `_`-delimited token of the read id, e.g. `read1_ACGTACGT` as
+    /// written by umi_tools.
+    ReadIdSuffix,
+    /// 8th `:`-delimited field of an Illumina read id of 8 fields, e.g.
+    /// `A01:1:FC:1:1101:1000:2000:ACGTACGT`.
+    IlluminaReadId,
+    /// The first bases of the read sequence.
+    SequencePrefix(usize),
+}
+
+impl UmiScheme {
+    /// UMI of a read, if it has a valid one under this scheme.
+    pub fn extract<'a>(self, read_id: &'a [u8], seq: &'a [u8]) -> Option<&'a [u8]> {
+        let umi = match self {
+            UmiScheme::ReadIdSuffix => {
+                let sep = read_id.iter().rposition(|b| *b == b'_')?;
+                &read_id[sep + 1..]
+            }
+            UmiScheme::IlluminaReadId => {
+                let mut fields = read_id.split(|b| *b == b':');
+                let umi = fields.nth(7)?;
+                if fields.next().is_some() {
+                    return None;
+                }
+                umi
+            }
+            UmiScheme::SequencePrefix(len) => seq.get(..len)?,
+        };
+
+        validate_umi(umi).then_some(umi)
+    }
+
+    /// Whether the UMI is in the read id, so no sequence is needed.
+    pub fn in_read_id(self) -> bool {
+        !matches!(self, UmiScheme::SequencePrefix(_))
+    }
+}
+
+/// Whether `umi` is only of A, C, G, T and N, with a length in [`UMI_LEN_RANGE`].
+pub fn validate_umi(umi: &[u8]) -> bool {
+    UMI_LEN_RANGE.contains(&umi.len())
+        && umi
+            .iter()
+            .all(|b| matches!(b, b'A' | b'C' | b'G' | b'T' | b'N'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_id_suffix() {
+        let scheme = UmiScheme::ReadIdSuffix;
+        assert_eq!(scheme.extract(b"read1_ACGTN", b""), Some(&b"ACGTN"[..]));
+        assert_eq!(scheme.extract(b"a_b_ACGTACGT", b""), Some(&b"ACGTACGT"[..]));
+        assert_eq!(scheme.extract(b"read1", b""), None);
+        assert_eq!(scheme.extract(b"read1_", b""), None);
+        // malformed tokens.
+        assert_eq!(scheme.extract(b"read1_ACGU", b""), None);
+        assert_eq!(scheme.extract(b"read1_acgt", b""), None);
+        assert_eq!(scheme.extract(b"read1_ACG", b""), None);
+    }
+
+    #[test]
+    fn test_illumina_read_id() {
+        let scheme = UmiScheme::IlluminaReadId;
+        assert_eq!(
+            scheme.extract(b"A01:1:FC:1:1101:1000:2000:ACGTACGT", b""),
+            Some(&b"ACGTACGT"[..])
+        );
+        // plain Illumina id, without UMI.
+        assert_eq!(scheme.extract(b"A01:1:FC:1:1101:1000:2000", b""), None);
+        assert_eq!(
+            scheme.extract(b"A01:1:FC:1:1101:1000:2000:ACGT:X", b""),
+            None
+        );
+        // dual UMIs are not a single UMI.
+        assert_eq!(
+            scheme.extract(b"A01:1:FC:1:1101:1000:2000:ACGT+GGTT", b""),
+            None
+        );
+        assert_eq!(scheme.extract(b"read1_ACGTACGT", b""), None);
+    }
+
+    #[test]
+    fn test_sequence_prefix() {
+        let scheme = UmiScheme::SequencePrefix(6);
+        assert_eq!(scheme.extract(b"read1", b"ACGTNAGGG"), Some(&b"ACGTNA"[..]));
+        assert_eq!(scheme.extract(b"read1", b"ACGTNA"), Some(&b"ACGTNA"[..]));
+        assert_eq!(scheme.extract(b"read1", b"ACGTN"), None);
+        assert_eq!(scheme.extract(b"read1", b"ACG.NAGGG"), None);
+        assert!(!scheme.in_read_id());
+    }
+
+    #[test]
+    fn test_validate_umi() {
+        assert!(validate_umi(b"ACGTN"));
+        assert!(validate_umi(&[b'N'; 32]));
+        assert!(!validate_umi(&[b'A'; 33]));
+        assert!(!validate_umi(b"ACG"));
+        assert!(!validate_umi(b"ACGT-ACGT"));
+        assert!(!validate_umi(b""));
+    }
+}
@@ -0,0 +1,126 @@
+//! Runs the async wrappers of the pipelines on the fixtures in `tests/data`.
+
+use std::{
+    path::PathBuf,
+    sync::{
+        Arc,
+        atomic::{AtomicUsize, Ordering},
+    },
+    time::Duration,
+};
+
+use anyhow::Error;
+use crackle_kit::{prelude::*, rust_htslib::bam::Record};
+use tokio::sync::watch;
+
+fn data(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/data")
+        .join(name)
+}
+
+#[tokio::test]
+async fn test_async_paired_fastq_reader() -> Result<(), Error> {
+    let config = PairedFastqReaderConfig::new(data("sample_R1.fastq"), data("sample_R2.fastq"));
+    let mut reader = AsyncPairedFastqReader::new(config)?;
+
+    let mut names = vec![];
+    while let Some((r1, r2)) = reader.read_pair().await? {
+        assert_eq!(r1.header_id_bytes(), r2.header_id_bytes());
+        names.push(String::from_utf8(r1.header_id_bytes().to_vec())?);
+    }
+
+    assert_eq!(names, ["@p1", "@p2", "@p3", "@p4", "@p5"]);
+
+    Ok(())
+}
+
+/// Keeps every record, slowly, counting the calls.
+struct SlowModifier {
+    n_calls: Arc<AtomicUsize>,
+    delay: Duration,
+}
+
+impl RecordModifier for SlowModifier {
+    type Error = Error;
+
+    fn modify_record(&self, _record: &mut Record) -> Result<Option<()>, Self::Error> {
+        self.n_calls.fetch_add(1, Ordering::Relaxed);
+        std::thread::sleep(self.delay);
+        Ok(Some(()))
+    }
+}
+
+/// One record per batch, so the pipeline checks for cancellation between records.
+fn one_by_one() -> ProcessBamOptions {
+    ProcessBamOptions {
+        worker_threads: 1,
+        batch_size: 1,
+        channel_capacity: 1,
+        ..Default::default()
+    }
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_process_bam_async_progress() -> Result<(), Error> {
+    let dir = tempfile::tempdir()?;
+    let n_calls = Arc::new(AtomicUsize::new(0));
+    let processor = Arc::new(ParallelBamProcessor::new(SlowModifier {
+        n_calls: Arc::clone(&n_calls),
+        delay: Duration::from_millis(200),
+    }));
+
+    let (tx, mut rx) = watch::channel(ProcessStats::default());
+    let run = tokio::spawn(processor.process_bam_async(
+        data("sample.bam"),
+        dir.path().join("out.bam"),
+        one_by_one(),
+        Some(tx),
+    ));
+
+    // an update arrives while the run goes on.
+    rx.changed().await?;
+    assert!(!run.is_finished());
+
+    let stats = run.await??;
+    assert_eq!(stats.records_read, 6);
+    assert_eq!(stats.records_written, 6);
+    assert_eq!(*rx.borrow(), stats);
+    assert_eq!(n_calls.load(Ordering::Relaxed), 6);
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_process_bam_async_cancel() -> Result<(), Error> {
+    let dir = tempfile::tempdir()?;
+    let n_calls = Arc::new(AtomicUsize::new(0));
+    let processor = Arc::new(ParallelBamProcessor::new(SlowModifier {
+        n_calls: Arc::clone(&n_calls),
+        delay: Duration::from_millis(300),
+    }));
+
+    let run = processor.process_bam_async(
+        data("sample.bam"),
+        dir.path().join("out.bam"),
+        one_by_one(),
+        None,
+    );
+    // dropping the future at the timeout cancels the run.
+    assert!(
+        tokio::time::timeout(Duration::from_millis(400), run)
+            .await
+            .is_err()
+    );
+
+    // without the cancellation, all 6 records would be modified by then.
+    tokio::time::sleep(Duration::from_millis(2_500)).await;
+    let n_modified = n_calls.load(Ordering::Relaxed);
+    assert!(
+        (1..6).contains(&n_modified),
+        "{} records modified",
+        n_modified
+    );
+
+    Ok(())
+}
@@ -0,0 +1,146 @@
+//! Peak heap of the outputs of a scan kept in columns by `process_into`,
+//! against the `Vec` of them collected by `process_with_batch`, counted by a
+//! global allocator.
+
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use crackle_kit::{
+    columnar_sink,
+    utils::columnar::{ColumnarSink, OrderedMerge},
+};
+
+/// Counts the bytes allocated, and the most of them at once. Unlike those of
+/// `variant_alloc`, the counts are global, so this file has a single test.
+struct PeakAlloc;
+
+static LIVE: AtomicUsize = AtomicUsize::new(0);
+static PEAK: AtomicUsize = AtomicUsize::new(0);
+
+fn grow(n: usize) {
+    let live = LIVE.fetch_add(n, Ordering::Relaxed) + n;
+    PEAK.fetch_max(live, Ordering::Relaxed);
+}
+
+unsafe impl GlobalAlloc for PeakAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        grow(layout.size());
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        LIVE.fetch_sub(layout.size(), Ordering::Relaxed);
+        unsafe { System.dealloc(ptr, layout) }
+    }
+
+    // counted as growing in place, as large blocks are remapped.
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        match new_size.checked_sub(layout.size()) {
+            Some(n) => grow(n),
+            None => {
+                LIVE.fetch_sub(layout.size() - new_size, Ordering::Relaxed);
+            }
+        }
+        unsafe { System.realloc(ptr, layout, new_size) }
+    }
+}
+
+#[global_allocator]
+static ALLOC: PeakAlloc = PeakAlloc;
+
+/// The result of `f`, and the most bytes it had allocated at once.
+fn peak_bytes<T>(f: impl FnOnce() -> T) -> (T, usize) {
+    let before = LIVE.load(Ordering::Relaxed);
+    PEAK.store(before, Ordering::Relaxed);
+    let res = f();
+    (res, PEAK.load(Ordering::Relaxed) - before)
+}
+
+/// 24 bytes in a `Vec`, of which 4 are padding.
+#[derive(Debug, Clone, PartialEq)]
+struct LocusStats {
+    pos: i64,
+    depth: u32,
+    mean_bq: f32,
+    alt_frac: f32,
+}
+
+columnar_sink! {
+    struct StatsColumns for LocusStats {
+        pos: i64,
+        depth: u32,
+        mean_bq: f32,
+        alt_frac: f32,
+    }
+}
+
+const N_OUTPUTS: usize = 10_000_000;
+const BATCH_LEN: usize = 10_000;
+
+fn stats(i: usize) -> LocusStats {
+    LocusStats {
+        pos: i as i64 + 1,
+        depth: (i % 500) as u32,
+        mean_bq: (i % 41) as f32,
+        alt_frac: (i % 101) as f32 / 100.0,
+    }
+}
+
+/// The outputs of batch `b` in a sink of its own, as filled by a worker.
+fn batch<S: ColumnarSink<LocusStats> + Default>(b: usize) -> S {
+    let mut sink = S::default();
+    sink.reserve(BATCH_LEN);
+    for i in b * BATCH_LEN..(b + 1) * BATCH_LEN {
+        sink.push(stats(i));
+    }
+    sink
+}
+
+#[test]
+fn test_columns_take_less_memory_than_vec() {
+    let n_batches = N_OUTPUTS / BATCH_LEN;
+
+    // as by `process_with_batch`: the batches are all done before they are
+    // collected, in order.
+    let (outputs, vec_peak) = peak_bytes(|| {
+        let batches = (0..n_batches).map(batch::<Vec<_>>).collect::<Vec<_>>();
+        let mut outputs = vec![];
+        for batch in batches {
+            outputs.extend(batch);
+        }
+        outputs
+    });
+
+    // as by `process_into`: each batch is merged once those before it are,
+    // here finishing by pairs out of order.
+    let (columns, columns_peak) = peak_bytes(|| {
+        let mut columns = StatsColumns::default();
+        let mut merge = OrderedMerge::new(&mut columns);
+        for pair in (0..n_batches).step_by(2) {
+            merge.add(pair + 1, batch(pair + 1));
+            merge.add(pair, batch(pair));
+        }
+        assert_eq!(merge.n_merged(), n_batches);
+        columns
+    });
+
+    // about 205 MB for the columns, 363 MB for the vec: 20 bytes an output in
+    // the columns against 24 in the vec, and the batches of the vec all held
+    // until they are collected.
+    assert!(
+        columns_peak * 10 <= vec_peak * 7,
+        "{} bytes for the columns, {} for the vec",
+        columns_peak,
+        vec_peak
+    );
+
+    assert_eq!(columns.len(), N_OUTPUTS);
+    for (i, output) in outputs.iter().enumerate() {
+        assert_eq!(columns.pos()[i], output.pos);
+        assert_eq!(columns.depth()[i], output.depth);
+        assert_eq!(columns.mean_bq()[i], output.mean_bq);
+        assert_eq!(columns.alt_frac()[i], output.alt_frac);
+    }
+}
@@ -0,0 +1,120 @@
+//! Runs the examples on the fixtures in `tests/data`.
+//!
+//! The example sources are compiled in as modules, so their `run` functions can
+//! be called directly instead of spawning `cargo run --example`.
+
+use std::{fs, path::PathBuf};
+
+use anyhow::Error;
+use crackle_kit::rust_htslib::bam::{self, Read, record::Aux};
+
+#[path = "../examples/bam_tag_umi.rs"]
+#[allow(dead_code)]
+mod bam_tag_umi;
+#[path = "../examples/fastq_filter.rs"]
+#[allow(dead_code)]
+mod fastq_filter;
+#[path = "../examples/pileup_depth.rs"]
+#[allow(dead_code)]
+mod pileup_depth;
+
+fn data(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/data")
+        .join(name)
+}
+
+fn read_names(path: impl AsRef<std::path::Path>) -> Result<Vec<String>, Error> {
+    Ok(fs::read_to_string(path)?
+        .lines()
+        .step_by(4)
+        .map(|l| l.split(' ').next().unwrap().to_string())
+        .collect())
+}
+
+#[test]
+fn test_fastq_filter() -> Result<(), Error> {
+    let dir = tempfile::tempdir()?;
+    let (out_r1, out_r2) = (dir.path().join("R1.fastq"), dir.path().join("R2.fastq"));
+
+    let summary = fastq_filter::run(
+        data("sample_R1.fastq"),
+        data("sample_R2.fastq"),
+        &out_r1,
+        &out_r2,
+        20.0,
+    )?;
+
+    assert_eq!(
+        summary,
+        fastq_filter::FilterSummary {
+            n_pairs: 5,
+            n_kept: 3
+        }
+    );
+    // p3 and p4 have a mate with mean quality below 20; p5 is exactly 20.
+    assert_eq!(read_names(&out_r1)?, ["@p1", "@p2", "@p5"]);
+    assert_eq!(read_names(&out_r2)?, ["@p1", "@p2", "@p5"]);
+    assert!(fs::read_to_string(&out_r2)?.contains("@p1 2:N:0:1\nTTGGCCAAGG\n+\nIIIIIIII55\n"));
+
+    Ok(())
+}
+
+#[test]
+fn test_bam_tag_umi() -> Result<(), Error> {
+    let dir = tempfile::tempdir()?;
+    let out = dir.path().join("out.bam");
+
+    let stats = bam_tag_umi::run(data("sample.bam"), &out, 2)?;
+    assert_eq!(stats.records_read, 6);
+    assert_eq!(stats.records_written, 6);
+
+    let mut tagged = bam::Reader::from_path(&out)?
+        .records()
+        .map(|r| {
+            let r = r?;
+            let rx = match r.aux(b"RX") {
+                Ok(Aux::String(s)) => Some(s.to_string()),
+                _ => None,
+            };
+            Ok((String::from_utf8(r.qname().to_vec())?, rx))
+        })
+        .collect::<Result<Vec<_>, Error>>()?;
+    tagged.sort();
+
+    let expected = [
+        ("r0:ACGTACGT", Some("ACGTACGT")),
+        ("r1:AAC+GGT", Some("AAC-GGT")),
+        ("r2:TTGGCCAA", Some("TTGGCCAA")),
+        ("r3", None),
+        ("r4:CCCCAAAA", Some("CCCCAAAA")),
+        ("r5", None),
+    ]
+    .map(|(q, rx)| (q.to_string(), rx.map(str::to_string)));
+    assert_eq!(tagged, expected);
+
+    Ok(())
+}
+
+#[test]
+fn test_pileup_depth() -> Result<(), Error> {
+    let dir = tempfile::tempdir()?;
+    let out = dir.path().join("depth.tsv");
+
+    let n = pileup_depth::run(data("sample.bam"), data("positions.bed"), &out, 2)?;
+    assert_eq!(n, 7);
+
+    assert_eq!(
+        fs::read_to_string(&out)?,
+        "contig\tpos\tdepth\n\
+         chr1\t111\t3\n\
+         chr1\t120\t3\n\
+         chr1\t121\t2\n\
+         chr1\t200\t0\n\
+         chr1\t301\t1\n\
+         chr2\t55\t1\n\
+         chr2\t65\t2\n"
+    );
+
+    Ok(())
+}
@@ -0,0 +1,74 @@
+//! Uses nothing but the prelude, to keep everything in it reachable and constructible.
+
+use crackle_kit::prelude::*;
+
+#[test]
+fn test_data_types() -> Result<(), anyhow::Error> {
+    let variant = Variant::from_str_key("chr1_101_A_G")?;
+    let gc = GenomeCoordinate {
+        contig: variant.chrom().clone(),
+        pos: variant.pos(),
+    };
+    let region = GenomeRegion::from(&gc);
+    assert_eq!(region, "chr1:101-101".parse::<GenomeRegion>()?);
+    assert_eq!(gc.contig, Chrom::Chr1);
+
+    let seq = BaseArr::<u64, 2>::from_bytes(b"ACGTN")?;
+    let mut counts = NucBaseMap::<usize>::default();
+    for b in seq.to_ascii_vec() {
+        *counts.get_checked_or_insert_with(b, || 0)? += 1;
+    }
+    assert_eq!(counts.get(b'A'), Some(&1));
+    assert_eq!(Base::try_from(b'G')?, Base::G);
+
+    assert_eq!(make_bins(0, 8, 5), vec![(0, 5), (5, 8)]);
+
+    Ok(())
+}
+
+#[cfg(feature = "bam")]
+mod bam {
+    use std::path::PathBuf;
+
+    use crackle_kit::{prelude::*, rust_htslib::bam::pileup::Pileup};
+
+    struct DepthWorker;
+
+    impl<'a> BamLocusWorker<'a> for DepthWorker {
+        type Input = GenomeCoordinate<'a>;
+        type Output = (i64, u32);
+        type Error = anyhow::Error;
+
+        fn work_for_locus(
+            &self,
+            plp: Pileup,
+            input: Self::Input,
+        ) -> Result<Self::Output, Self::Error> {
+            Ok((input.pos, plp.depth()))
+        }
+    }
+
+    #[test]
+    fn test_locus_processor() -> Result<(), anyhow::Error> {
+        let bam = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/data/sample.bam");
+        let inputs = [111, 120, 121, 200]
+            .into_iter()
+            .map(|pos| GenomeCoordinate {
+                contig: Chrom::Chr1,
+                pos,
+            })
+            .collect::<Vec<_>>();
+
+        let batches = batch_region(inputs.iter().map(GenomeRegion::from), 50);
+        assert_eq!(batches.len(), 2);
+
+        let processor = ParallelLocusProcessorPileup::new(DepthWorker, 2, bam)
+            .with_retry_policy(RetryPolicy::no_retry());
+        assert_eq!(
+            processor.process_with_batch(inputs, 50)?,
+            vec![(111, 3), (120, 3), (121, 2)]
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,119 @@
+//! Heap allocations of variants, counted by a global allocator.
+
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    cell::Cell,
+};
+
+use crackle_kit::data::{
+    chrom::{Chrom, ContigInterner},
+    variant::{StringVariant, Variant},
+};
+
+/// Counts the allocations of each thread, so that tests running in parallel do
+/// not see those of the others.
+struct CountingAlloc;
+
+thread_local! {
+    static N_ALLOCS: Cell<usize> = const { Cell::new(0) };
+}
+
+unsafe impl GlobalAlloc for CountingAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let _ = N_ALLOCS.try_with(|n| n.set(n.get() + 1));
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(ptr, layout) }
+    }
+}
+
+#[global_allocator]
+static ALLOC: CountingAlloc = CountingAlloc;
+
+/// The result of `f`, and the allocations it made.
+fn count_allocs<T>(f: impl FnOnce() -> T) -> (T, usize) {
+    let before = N_ALLOCS.with(Cell::get);
+    let res = f();
+    (res, N_ALLOCS.with(Cell::get) - before)
+}
+
+const N_VARIANTS: usize = 100_000;
+
+/// SNVs and short indels, as keys.
+fn keys() -> Vec<String> {
+    let alleles = [("A", "G"), ("C", "T"), ("AT", "A"), ("G", "GCA")];
+    (0..N_VARIANTS)
+        .map(|i| {
+            let (ref_b, alt_b) = alleles[i % alleles.len()];
+            format!("chr{}_{}_{}_{}", i % 22 + 1, 1_000 + i * 10, ref_b, alt_b)
+        })
+        .collect()
+}
+
+#[test]
+fn test_packed_variants_do_not_allocate() -> Result<(), anyhow::Error> {
+    let keys = keys();
+
+    let mut variants = Vec::with_capacity(N_VARIANTS);
+    let (res, n_allocs) = count_allocs(|| {
+        for key in &keys {
+            variants.push(Variant::from_str_key(key)?);
+        }
+        Ok::<_, anyhow::Error>(())
+    });
+    res?;
+    assert_eq!(n_allocs, 0);
+
+    let heap = variants
+        .iter()
+        .map(|v| v.memory_footprint() - size_of::<Variant>())
+        .sum::<usize>();
+    assert_eq!(heap, 0);
+
+    // the same variants with string alleles: two allocations each.
+    let mut plain = Vec::with_capacity(N_VARIANTS);
+    let ((), n_allocs) = count_allocs(|| {
+        for v in &variants {
+            plain.push(StringVariant {
+                chrom: v.chrom().clone(),
+                pos: v.pos(),
+                ref_b: v.ref_bases().to_string(),
+                alt_b: v.alt_bases().to_string(),
+            });
+        }
+    });
+    assert_eq!(n_allocs, 2 * N_VARIANTS);
+
+    // and back, freeing the strings.
+    let (packed, n_allocs) = count_allocs(|| {
+        let mut packed = Vec::with_capacity(N_VARIANTS);
+        packed.extend(plain.into_iter().map(Variant::from));
+        packed
+    });
+    assert_eq!(n_allocs, 1);
+    assert_eq!(packed, variants);
+
+    Ok(())
+}
+
+#[test]
+fn test_long_alleles_and_contigs() {
+    let long = "ACGT".repeat(10);
+
+    // a box for both alleles, and their strings.
+    let (v, n_allocs) = count_allocs(|| Variant::new(Chrom::Chr1, 100, &long, "A"));
+    assert_eq!(n_allocs, 3);
+    assert_eq!(v.ref_bases(), long.as_str());
+
+    // a contig name is copied, unless borrowed from an interner.
+    let interner = ContigInterner::from_names(["chrEBV"]);
+    let (v, n_allocs) = count_allocs(|| Variant::new(Chrom::from("chrEBV"), 100, "A", "C"));
+    assert_eq!(n_allocs, 1);
+    let (interned, n_allocs) = count_allocs(|| v.clone().intern(&interner));
+    // the clone only.
+    assert_eq!(n_allocs, 1);
+    assert_eq!(interned.memory_footprint(), size_of::<Variant>());
+    assert_eq!(interned, v);
+}